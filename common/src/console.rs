@@ -0,0 +1,481 @@
+//! Line input for debug console connections, on ftms-daemon's port 8826
+//! and hrm-daemon's 8827.
+//!
+//! Three modes, switched per connection with the `mode` command:
+//!
+//! - `plain` (the default): the client edits lines (`nc`, telnet in line
+//!   mode, the integration tests) and the prompt follows each response.
+//! - `raw`: no prompt, and a blank line ends each response. For
+//!   `rlwrap nc rpi 8826` (nothing the server prints lands on rlwrap's
+//!   prompt line) and for scripts, which can read up to the blank line
+//!   instead of waiting for a timeout.
+//! - `edit`: for `telnet rpi 8826`. The server negotiates echo and
+//!   character-at-a-time input, then edits the line itself: arrows,
+//!   Home/End, Backspace/Delete, ctrl-A/E/U/W, ctrl-C to drop the line,
+//!   ctrl-D on an empty line to disconnect, and Up/Down through the
+//!   connection's last 100 lines.
+//!
+//! Telnet commands from clients are stripped from the input in every mode;
+//! option requests are refused unless edit mode asked for them.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+/// Interrupt Process, what telnet sends for ctrl-C.
+const IP: u8 = 244;
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+/// Lines kept for Up/Down recall in edit mode.
+const HISTORY_LINES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Plain,
+    Raw,
+    Edit,
+}
+
+impl Mode {
+    pub fn parse(s: &str) -> Option<Mode> {
+        match s {
+            "plain" => Some(Mode::Plain),
+            "raw" => Some(Mode::Raw),
+            "edit" => Some(Mode::Edit),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Plain => "plain",
+            Mode::Raw => "raw",
+            Mode::Edit => "edit",
+        }
+    }
+}
+
+/// Input decoded from the byte stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    KillLine,
+    KillWord,
+    Interrupt,
+    Eof,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Verb(u8),
+    Sub,
+    SubIac,
+    Esc,
+    /// CSI/SS3 sequence, with the parameter bytes so far.
+    Seq(Vec<u8>),
+}
+
+/// Splits the byte stream into keys, consuming telnet commands and
+/// terminal escape sequences.
+#[derive(Default)]
+struct Decoder {
+    state: State,
+    utf8: Vec<u8>,
+    after_cr: bool,
+}
+
+impl Decoder {
+    /// Feed one byte. Telnet option refusals go to `reply`.
+    fn feed(&mut self, b: u8, edit: bool, reply: &mut Vec<u8>) -> Option<Key> {
+        let after_cr = std::mem::take(&mut self.after_cr);
+        match std::mem::take(&mut self.state) {
+            State::Iac => match b {
+                WILL | WONT | DO | DONT => self.state = State::Verb(b),
+                SB => self.state = State::Sub,
+                IP => return Some(Key::Interrupt),
+                IAC => return self.byte(b),
+                _ => {}
+            },
+            State::Verb(verb) => match verb {
+                // Our own WILLs coming back acknowledged
+                DO if edit && (b == OPT_ECHO || b == OPT_SGA) => {}
+                DO => reply.extend([IAC, WONT, b]),
+                WILL if b == OPT_SGA => {}
+                WILL => reply.extend([IAC, DONT, b]),
+                _ => {}
+            },
+            State::Sub => self.state = if b == IAC { State::SubIac } else { State::Sub },
+            State::SubIac => self.state = if b == SE { State::Data } else { State::Sub },
+            State::Esc => match b {
+                b'[' | b'O' => self.state = State::Seq(Vec::new()),
+                _ => {}
+            },
+            State::Seq(mut params) => match b {
+                0x40..=0x7e => {
+                    return match (params.as_slice(), b) {
+                        (_, b'A') => Some(Key::Up),
+                        (_, b'B') => Some(Key::Down),
+                        (_, b'C') => Some(Key::Right),
+                        (_, b'D') => Some(Key::Left),
+                        (_, b'H') | (b"1" | b"7", b'~') => Some(Key::Home),
+                        (_, b'F') | (b"4" | b"8", b'~') => Some(Key::End),
+                        (b"3", b'~') => Some(Key::Delete),
+                        _ => None,
+                    }
+                }
+                _ if params.len() < 16 => {
+                    params.push(b);
+                    self.state = State::Seq(params);
+                }
+                _ => {}
+            },
+            State::Data => match b {
+                IAC => self.state = State::Iac,
+                0x1b => self.state = State::Esc,
+                // CR LF and CR NUL are one Enter
+                b'\n' | 0 if after_cr => {}
+                b'\r' => {
+                    self.after_cr = true;
+                    return Some(Key::Enter);
+                }
+                b'\n' => return Some(Key::Enter),
+                0x7f | 0x08 => return Some(Key::Backspace),
+                0x01 => return Some(Key::Home),
+                0x05 => return Some(Key::End),
+                0x02 => return Some(Key::Left),
+                0x06 => return Some(Key::Right),
+                0x10 => return Some(Key::Up),
+                0x0e => return Some(Key::Down),
+                0x15 => return Some(Key::KillLine),
+                0x17 => return Some(Key::KillWord),
+                0x03 => return Some(Key::Interrupt),
+                0x04 => return Some(Key::Eof),
+                0x00..=0x1f => {}
+                _ => return self.byte(b),
+            },
+        }
+        None
+    }
+
+    /// Collect a (possibly multi-byte) UTF-8 character.
+    fn byte(&mut self, b: u8) -> Option<Key> {
+        self.utf8.push(b);
+        match std::str::from_utf8(&self.utf8) {
+            Ok(s) => {
+                let c = s.chars().next();
+                self.utf8.clear();
+                c.map(Key::Char)
+            }
+            Err(e) if e.error_len().is_some() || self.utf8.len() >= 4 => {
+                self.utf8.clear();
+                Some(Key::Char(char::REPLACEMENT_CHARACTER))
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// What finished a line.
+#[derive(Debug, PartialEq)]
+enum Submit {
+    Line(String),
+    Eof,
+}
+
+/// The line being typed, plus history for recall.
+#[derive(Default)]
+struct Editor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// Index into `history` while recalling, and the line typed before.
+    recall: Option<(usize, Vec<char>)>,
+}
+
+impl Editor {
+    /// Apply `key`, appending the echo/redraw for the client to `out`.
+    fn key(&mut self, key: Key, prompt: &str, out: &mut Vec<u8>) -> Option<Submit> {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+                if self.cursor == self.line.len() {
+                    out.extend(c.to_string().as_bytes());
+                    return None;
+                }
+            }
+            Key::Enter => {
+                out.extend(b"\r\n");
+                let line: String = self.line.drain(..).collect();
+                self.cursor = 0;
+                self.recall = None;
+                if !line.trim().is_empty() && self.history.last() != Some(&line) {
+                    if self.history.len() == HISTORY_LINES {
+                        self.history.remove(0);
+                    }
+                    self.history.push(line.clone());
+                }
+                return Some(Submit::Line(line));
+            }
+            Key::Eof if self.line.is_empty() => {
+                out.extend(b"\r\n");
+                return Some(Submit::Eof);
+            }
+            Key::Eof => return None,
+            Key::Interrupt => {
+                self.line.clear();
+                self.cursor = 0;
+                self.recall = None;
+                out.extend(b"^C\r\n");
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Left if self.cursor > 0 => self.cursor -= 1,
+            Key::Right if self.cursor < self.line.len() => self.cursor += 1,
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::KillLine => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillWord => {
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.line.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Up => {
+                let i = match &self.recall {
+                    Some((0, _)) => return None,
+                    Some((i, _)) => i - 1,
+                    None if self.history.is_empty() => return None,
+                    None => self.history.len() - 1,
+                };
+                let typed = self.recall.take().map_or_else(|| self.line.clone(), |(_, typed)| typed);
+                self.line = self.history[i].chars().collect();
+                self.recall = Some((i, typed));
+                self.cursor = self.line.len();
+            }
+            Key::Down => {
+                let (i, typed) = self.recall.take()?;
+                if i + 1 < self.history.len() {
+                    self.line = self.history[i + 1].chars().collect();
+                    self.recall = Some((i + 1, typed));
+                } else {
+                    self.line = typed;
+                }
+                self.cursor = self.line.len();
+            }
+            _ => return None,
+        }
+        self.redraw(prompt, out);
+        None
+    }
+
+    fn redraw(&self, prompt: &str, out: &mut Vec<u8>) {
+        out.extend(b"\r");
+        out.extend(prompt.as_bytes());
+        out.extend(self.line.iter().collect::<String>().as_bytes());
+        out.extend(b"\x1b[K");
+        let back = self.line.len() - self.cursor;
+        if back > 0 {
+            out.extend(format!("\x1b[{}D", back).as_bytes());
+        }
+    }
+}
+
+/// Reads command lines from one debug client.
+pub struct Console<R> {
+    reader: BufReader<R>,
+    prompt: &'static str,
+    mode: Mode,
+    decoder: Decoder,
+    editor: Editor,
+}
+
+impl<R: AsyncRead + Unpin> Console<R> {
+    pub fn new(reader: R, prompt: &'static str) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            prompt,
+            mode: Mode::Plain,
+            decoder: Decoder::default(),
+            editor: Editor::default(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switch modes, negotiating server-side echo with the client when
+    /// edit mode starts or ends.
+    pub async fn set_mode<W: AsyncWrite + Unpin>(&mut self, mode: Mode, writer: &mut W) -> std::io::Result<()> {
+        match (self.mode == Mode::Edit, mode == Mode::Edit) {
+            (false, true) => writer.write_all(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA]).await?,
+            (true, false) => writer.write_all(&[IAC, WONT, OPT_ECHO, IAC, WONT, OPT_SGA]).await?,
+            _ => {}
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Prompt (except in raw mode) and read the next line. None at EOF.
+    pub async fn read_line<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> std::io::Result<Option<String>> {
+        if self.mode != Mode::Raw {
+            writer.write_all(self.prompt.as_bytes()).await?;
+        }
+        let mut out = Vec::new();
+        loop {
+            let b = match self.reader.read_u8().await {
+                Ok(b) => b,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    // A last line without a newline still counts
+                    let line: String = self.editor.line.drain(..).collect();
+                    return Ok((!line.is_empty()).then_some(line));
+                }
+                Err(e) => return Err(e),
+            };
+            let edit = self.mode == Mode::Edit;
+            let submit = match self.decoder.feed(b, edit, &mut out) {
+                Some(key) if edit => self.editor.key(key, self.prompt, &mut out),
+                // The client edits the line itself: only text and Enter matter
+                Some(Key::Char(c)) => {
+                    self.editor.line.push(c);
+                    None
+                }
+                Some(Key::Enter) => Some(Submit::Line(self.editor.line.drain(..).collect())),
+                _ => None,
+            };
+            if !out.is_empty() && (submit.is_some() || self.reader.buffer().is_empty()) {
+                writer.write_all(&out).await?;
+                out.clear();
+            }
+            match submit {
+                Some(Submit::Line(line)) => return Ok(Some(line)),
+                Some(Submit::Eof) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+
+    /// Write one response: a blank line follows it in raw mode, and edit
+    /// mode uses telnet CR LF line ends.
+    pub async fn respond<W: AsyncWrite + Unpin>(&self, writer: &mut W, msg: &str) -> std::io::Result<()> {
+        let text = match self.mode {
+            Mode::Plain => format!("{}\n", msg),
+            Mode::Raw => format!("{}\n\n", msg),
+            Mode::Edit => format!("{}\r\n", msg.replace('\n', "\r\n")),
+        };
+        writer.write_all(text.as_bytes()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `input` in edit mode, returning the submitted lines.
+    fn type_keys(editor: &mut Editor, input: &[u8]) -> Vec<Submit> {
+        let mut decoder = Decoder::default();
+        let (mut out, mut reply) = (Vec::new(), Vec::new());
+        input
+            .iter()
+            .filter_map(|&b| decoder.feed(b, true, &mut reply))
+            .filter_map(|key| editor.key(key, "> ", &mut out))
+            .collect()
+    }
+
+    #[test]
+    fn test_decoder_strips_telnet() {
+        let mut decoder = Decoder::default();
+        let mut reply = Vec::new();
+        // WILL NAWS, DO ECHO, a subnegotiation, then "a", CR NUL, "b", CR LF
+        let input = [&[IAC, WILL, 31, IAC, DO, OPT_ECHO, IAC, SB, 24, 1, IAC, SE][..], b"a\r\0b\r\n"].concat();
+        let keys: Vec<Key> = input.iter().filter_map(|&b| decoder.feed(b, false, &mut reply)).collect();
+        assert_eq!(keys, vec![Key::Char('a'), Key::Enter, Key::Char('b'), Key::Enter]);
+        assert_eq!(reply, vec![IAC, DONT, 31, IAC, WONT, OPT_ECHO]);
+
+        // Our own options acknowledged in edit mode need no reply
+        reply.clear();
+        for b in [IAC, DO, OPT_ECHO, IAC, DO, OPT_SGA] {
+            decoder.feed(b, true, &mut reply);
+        }
+        assert!(reply.is_empty());
+
+        let keys: Vec<Key> = "é\x1b[A\x1b[3~\x1bOH".bytes().filter_map(|b| decoder.feed(b, true, &mut reply)).collect();
+        assert_eq!(keys, vec![Key::Char('é'), Key::Up, Key::Delete, Key::Home]);
+    }
+
+    #[tokio::test]
+    async fn test_read_line_and_respond() {
+        let input: &[u8] = b"state\r\nsub 2\nlast";
+        let mut console = Console::new(input, "p> ");
+        let mut out = Vec::new();
+        assert_eq!(console.read_line(&mut out).await.unwrap().as_deref(), Some("state"));
+        console.respond(&mut out, "a\nb").await.unwrap();
+        console.set_mode(Mode::Raw, &mut out).await.unwrap();
+        assert_eq!(console.read_line(&mut out).await.unwrap().as_deref(), Some("sub 2"));
+        console.respond(&mut out, "a\nb").await.unwrap();
+        console.set_mode(Mode::Edit, &mut out).await.unwrap();
+        console.respond(&mut out, "a\nb").await.unwrap();
+        assert_eq!(console.read_line(&mut out).await.unwrap().as_deref(), Some("last"), "unterminated last line");
+        assert_eq!(console.read_line(&mut out).await.unwrap(), None);
+        let expected = [&b"p> a\nb\na\nb\n\n"[..], &[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA], b"a\r\nb\r\np> lastp> "].concat();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_editing_and_history() {
+        let mut editor = Editor::default();
+        // Left three times, insert, ctrl-E, backspace
+        assert_eq!(type_keys(&mut editor, b"sate\x1b[D\x1b[D\x1b[Dt\x05x\x7f\r"), vec![Submit::Line("state".into())]);
+        // ctrl-W drops the last word, ctrl-U the rest
+        assert_eq!(type_keys(&mut editor, b"cp 02 f4\x17\x15td\r"), vec![Submit::Line("td".into())]);
+        // ctrl-C drops the line
+        assert_eq!(type_keys(&mut editor, b"oops\x03\r"), vec![Submit::Line(String::new())]);
+
+        // Up recalls newest first; Down returns to what was being typed
+        assert_eq!(type_keys(&mut editor, b"\x1b[A\x1b[A\r"), vec![Submit::Line("state".into())]);
+        assert_eq!(type_keys(&mut editor, b"fe\x1b[A\x1b[B\x1b[Bat\r"), vec![Submit::Line("feat".into())]);
+        assert_eq!(editor.history, vec!["state", "td", "state", "feat"]);
+
+        assert_eq!(type_keys(&mut editor, b"x\x04"), vec![], "ctrl-D only ends an empty line");
+        assert_eq!(type_keys(&mut editor, b"\x15\x04"), vec![Submit::Eof]);
+
+        let mut out = Vec::new();
+        let mut editor = Editor::default();
+        editor.key(Key::Char('a'), "> ", &mut out);
+        editor.key(Key::Char('c'), "> ", &mut out);
+        editor.key(Key::Left, "> ", &mut out);
+        assert_eq!(out, b"ac\r> ac\x1b[K\x1b[1D");
+    }
+}
@@ -0,0 +1,7 @@
+//! Modules ftms-daemon and hrm-daemon share: debug console line input,
+//! connection limits, debug-port TLS and dropping root after startup.
+
+pub mod console;
+pub mod privileges;
+pub mod throttle;
+pub mod tls;
@@ -0,0 +1,130 @@
+//! Dropping root after startup.
+//!
+//! `--user <name>` (and optionally `--group <name>`) let the daemon start
+//! as root, bind its sockets and ports, and switch to that account before
+//! serving anything, so a compromised debug port doesn't hand out root.
+//! The account keeps its supplementary groups from /etc/group and needs
+//! whatever the daemon still does after binding: D-Bus access to BlueZ
+//! (the `bluetooth` group on Raspberry Pi OS), reading the config, and
+//! writing wherever logs, checkpoints and workout files go. `--group`
+//! alone only changes the group.
+
+use std::ffi::CString;
+use std::io;
+
+use log::info;
+
+/// The account to switch to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    /// User name, for supplementary groups; None with `--group` alone.
+    user: Option<CString>,
+    uid: Option<libc::uid_t>,
+    gid: libc::gid_t,
+}
+
+/// Look up `--user`/`--group`. None when neither was given.
+pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Option<Account>, String> {
+    let group_gid = group.map(lookup_group).transpose()?;
+    let account = match user {
+        Some(name) => {
+            let (uid, primary_gid) = lookup_user(name)?;
+            Account {
+                user: Some(CString::new(name).map_err(|_| format!("bad user name {:?}", name))?),
+                uid: Some(uid),
+                gid: group_gid.unwrap_or(primary_gid),
+            }
+        }
+        None => match group_gid {
+            Some(gid) => Account { user: None, uid: None, gid },
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(account))
+}
+
+/// Switch to `account`: groups first, then the user, then make sure root
+/// can't be taken back.
+pub fn drop_to(account: &Account) -> Result<(), String> {
+    let err = |what: &str| format!("{}: {}", what, io::Error::last_os_error());
+    // SAFETY: plain syscalls on integers and a valid C string.
+    unsafe {
+        if libc::geteuid() != 0 {
+            let already = account.uid.is_none_or(|uid| uid == libc::getuid()) && account.gid == libc::getgid();
+            return if already {
+                Ok(())
+            } else {
+                Err("--user/--group need the daemon to start as root".to_string())
+            };
+        }
+        let groups = match &account.user {
+            Some(user) => libc::initgroups(user.as_ptr(), account.gid),
+            None => libc::setgroups(1, &account.gid),
+        };
+        if groups != 0 {
+            return Err(err("setting supplementary groups"));
+        }
+        if libc::setgid(account.gid) != 0 {
+            return Err(err("setgid"));
+        }
+        if let Some(uid) = account.uid {
+            if libc::setuid(uid) != 0 {
+                return Err(err("setuid"));
+            }
+            if uid != 0 && libc::setuid(0) == 0 {
+                return Err("still able to regain root after setuid".to_string());
+            }
+        }
+        info!("Dropped privileges to uid {} gid {}", libc::getuid(), libc::getgid());
+    }
+    Ok(())
+}
+
+/// `(uid, primary gid)` for user `name`.
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let cname = CString::new(name).map_err(|_| format!("bad user name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: passwd is plain data; getpwnam_r only writes within buf.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(cname.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 {
+        return Err(format!("looking up user {:?}: {}", name, io::Error::from_raw_os_error(rc)));
+    }
+    if found.is_null() {
+        return Err(format!("no user {:?}", name));
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+/// Group id for `name`; hrm-daemon also uses it for `socket_group`.
+pub fn lookup_group(name: &str) -> Result<libc::gid_t, String> {
+    let cname = CString::new(name).map_err(|_| format!("bad group name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: group is plain data; getgrnam_r only writes within buf.
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(cname.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 {
+        return Err(format!("looking up group {:?}: {}", name, io::Error::from_raw_os_error(rc)));
+    }
+    if found.is_null() {
+        return Err(format!("no group {:?}", name));
+    }
+    Ok(grp.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(None, None), Ok(None));
+        let root = resolve(Some("root"), None).unwrap().unwrap();
+        assert_eq!((root.uid, root.gid), (Some(0), 0));
+        assert_eq!(resolve(None, Some("root")).unwrap().unwrap().uid, None, "--group alone keeps the user");
+        assert!(resolve(Some("no-such-user"), None).unwrap_err().contains("no user"));
+        assert!(resolve(Some("root"), Some("no-such-group")).unwrap_err().contains("no group"));
+    }
+}
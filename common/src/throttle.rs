@@ -0,0 +1,166 @@
+//! Connection limits, so a runaway script, a port scanner or a flood of
+//! connections can't tie up the Pi.
+//!
+//! Every server caps its open connections: the debug port, gRPC
+//! (`grpc_max_connections`) and, in hrm-daemon, the Unix socket
+//! (`max_clients`). Each connection's task holds a `Slot` for as long as
+//! it runs, so the tasks are bounded too; one past the cap is told so
+//! where the protocol allows, and closed.
+//!
+//! `debug_max_connections` caps how many debug connections are open at
+//! once; one more is told so and closed. `debug_commands_per_sec` paces
+//! each connection, with up to a second's worth in a burst: commands
+//! beyond that wait their turn rather than fail, so a script that sends
+//! too fast just slows down. `debug_idle_timeout_secs` closes connections
+//! that haven't sent a line for that long, unless they're subscribed to a
+//! stream, so clients that vanished without a FIN don't pile up. 0 turns
+//! any of these off.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// One server's limits, shared by every connection. Only the debug port
+/// paces commands and times out idle connections.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// None when connections are unlimited.
+    slots: Option<Arc<Semaphore>>,
+    max_connections: usize,
+    commands_per_sec: u32,
+    /// None when idle connections stay open.
+    idle_timeout: Option<Duration>,
+}
+
+/// Held by an open connection; frees its slot when dropped.
+pub struct Slot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Limits {
+    pub fn new(max_connections: usize, commands_per_sec: u32, idle_timeout: Duration) -> Self {
+        Self {
+            slots: (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections))),
+            max_connections,
+            commands_per_sec,
+            idle_timeout: (!idle_timeout.is_zero()).then_some(idle_timeout),
+        }
+    }
+
+    /// A slot for one more connection, or None at the cap.
+    pub fn open(&self) -> Option<Slot> {
+        match &self.slots {
+            Some(slots) => slots.clone().try_acquire_owned().ok().map(|permit| Slot { _permit: Some(permit) }),
+            None => Some(Slot { _permit: None }),
+        }
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// A fresh pacer for one connection's commands.
+    pub fn command_rate(&self) -> CommandRate {
+        CommandRate::new(self.commands_per_sec)
+    }
+
+    /// Wait for `read`, a client's next line, until the idle timeout; None
+    /// if it passed first.
+    pub async fn until_idle<T>(&self, read: impl Future<Output = T>) -> Option<T> {
+        match self.idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, read).await.ok(),
+            None => Some(read.await),
+        }
+    }
+}
+
+/// Token bucket for one connection's commands.
+#[derive(Debug)]
+pub struct CommandRate {
+    per_sec: u32,
+    tokens: f64,
+    last: Instant,
+}
+
+impl CommandRate {
+    fn new(per_sec: u32) -> Self {
+        Self { per_sec, tokens: per_sec as f64, last: Instant::now() }
+    }
+
+    /// Take a token for a command at `now`; how long it has to wait.
+    fn take(&mut self, now: Instant) -> Duration {
+        if self.per_sec == 0 {
+            return Duration::ZERO;
+        }
+        let rate = self.per_sec as f64;
+        let refill = now.saturating_duration_since(self.last).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate) - 1.0;
+        self.last = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+
+    /// Wait until the next command may run.
+    pub async fn wait(&mut self) {
+        let delay = self.take(Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_cap() {
+        let limits = Limits::new(2, 0, Duration::ZERO);
+        let a = limits.open().expect("first");
+        let _b = limits.open().expect("second");
+        assert!(limits.open().is_none(), "third is over the cap");
+        drop(a);
+        assert!(limits.open().is_some(), "a closed connection frees its slot");
+
+        let unlimited = Limits::new(0, 0, Duration::ZERO);
+        let held: Vec<_> = (0..100).map(|_| unlimited.open()).collect();
+        assert!(held.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_command_rate() {
+        let mut rate = CommandRate::new(4);
+        let t0 = rate.last;
+        // A second's worth goes straight through
+        for _ in 0..4 {
+            assert_eq!(rate.take(t0), Duration::ZERO);
+        }
+        // Then each command waits for its share of the second
+        assert_eq!(rate.take(t0), Duration::from_millis(250));
+        assert_eq!(rate.take(t0 + Duration::from_millis(250)), Duration::from_millis(250));
+        // An idle spell refills the burst, but no further
+        let later = t0 + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert_eq!(rate.take(later), Duration::ZERO);
+        }
+        assert!(rate.take(later) > Duration::ZERO);
+
+        let mut unlimited = CommandRate::new(0);
+        assert!((0..1000).all(|_| unlimited.take(t0).is_zero()));
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let limits = Limits::new(0, 0, Duration::from_millis(20));
+        assert_eq!(limits.until_idle(async { 7 }).await, Some(7));
+        assert_eq!(limits.until_idle(std::future::pending::<()>()).await, None);
+        let patient = Limits::new(0, 0, Duration::ZERO);
+        let slow = tokio::time::sleep(Duration::from_millis(40));
+        assert_eq!(patient.until_idle(slow).await, Some(()), "0 never times out");
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional TLS for the debug port (ftms-daemon 8826, hrm-daemon 8827).
+//!
+//! `debug_tls` in `ftms_config.json` or `hrm_config.json` names a PEM
+//! certificate chain and private key; with it set, every debug connection
+//! must start with a TLS handshake (`openssl s_client -quiet -connect pi:8826`, or
+//! `socat - OPENSSL:pi:8826,verify=0`). If neither file exists yet, a
+//! self-signed certificate for `localhost` and this host's name is
+//! generated into them on startup, the key readable only by its owner.
+//! Clients then either skip verification or trust that certificate file.
+//! Both files are read before privileges are dropped, so the key can stay
+//! root-only. `--check` connects with the certificate as its only trust
+//! root, as `localhost`.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{crypto, ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Where the debug port's certificate and key live.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsFiles {
+    /// PEM certificate chain, leaf first.
+    pub cert: String,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: String,
+}
+
+/// Load `files`, generating a self-signed pair first if both are missing.
+pub fn acceptor(files: &TlsFiles) -> Result<TlsAcceptor, String> {
+    if !Path::new(&files.cert).exists() && !Path::new(&files.key).exists() {
+        self_signed(files)?;
+    }
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("reading certificate {}: {}", files.cert, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", files.cert));
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key).map_err(|e| format!("reading key {}: {}", files.key, e))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| format!("TLS setup with {}: {}", files.cert, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A client that trusts only the certificates in `files`, for connecting
+/// to our own debug port.
+pub fn connector(files: &TlsFiles) -> Result<TlsConnector, String> {
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("reading certificate {}: {}", files.cert, e))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(cert).map_err(|e| format!("trusting {}: {}", files.cert, e))?;
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS setup with {}: {}", files.cert, e))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Write a self-signed certificate and its key to `files`.
+fn self_signed(files: &TlsFiles) -> Result<(), String> {
+    let mut names = vec!["localhost".to_string()];
+    if let Some(host) = std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()) {
+        if !host.is_empty() {
+            names.push(format!("{}.local", host));
+            names.push(host);
+        }
+    }
+    let generated = rcgen::generate_simple_self_signed(names.clone()).map_err(|e| format!("generating certificate: {}", e))?;
+    let write = |path: &str, mode: u32, pem: String| {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(path)
+            .and_then(|mut f| f.write_all(pem.as_bytes()))
+            .map_err(|e| format!("writing {}: {}", path, e))
+    };
+    write(&files.key, 0o600, generated.key_pair.serialize_pem())?;
+    write(&files.cert, 0o644, generated.cert.pem())?;
+    info!("Generated a self-signed debug certificate for {} at {}", names.join(", "), files.cert);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_round_trip() {
+        let dir = std::env::temp_dir().join(format!("{}_tls_test_{}", env!("CARGO_PKG_NAME"), std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = TlsFiles {
+            cert: dir.join("debug.crt").to_string_lossy().into_owned(),
+            key: dir.join("debug.key").to_string_lossy().into_owned(),
+        };
+        let _ = std::fs::remove_file(&files.cert);
+        let _ = std::fs::remove_file(&files.key);
+
+        assert!(acceptor(&files).is_ok(), "generated on first use");
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&files.key).unwrap().permissions().mode() & 0o777, 0o600);
+        let cert = std::fs::read_to_string(&files.cert).unwrap();
+        assert!(acceptor(&files).is_ok(), "reused afterwards");
+        assert_eq!(std::fs::read_to_string(&files.cert).unwrap(), cert);
+
+        // The check client trusts the generated certificate as localhost
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let (server, client) = tokio::io::duplex(16 * 1024);
+        let (accepted, connected) = rt.block_on(async {
+            let name = tokio_rustls::rustls::pki_types::ServerName::try_from("localhost").unwrap();
+            tokio::join!(acceptor(&files).unwrap().accept(server), connector(&files).unwrap().connect(name, client))
+        });
+        assert!(accepted.is_ok() && connected.is_ok());
+
+        // Only one of the pair present is a mistake, not a reason to overwrite
+        std::fs::remove_file(&files.key).unwrap();
+        assert!(acceptor(&files).is_err_and(|e| e.contains("reading key")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
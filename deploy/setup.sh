@@ -26,7 +26,7 @@ sudo systemctl disable --now treadmill_io 2>/dev/null || true
 sudo rm -f /etc/systemd/system/treadmill_io.service
 
 # Install services
-for svc in services/*.service; do
+for svc in services/*.service services/*.socket; do
     sudo cp "$svc" /etc/systemd/system/
 done
 sudo systemctl daemon-reload
@@ -37,9 +37,10 @@ if [ -f ftms-daemon ]; then
     sudo systemctl enable ftms
 fi
 
-# HRM only if binary was deployed
+# HRM only if binary was deployed. Its sockets are systemd's, so a
+# client connecting before the daemon is up starts it
 if [ -f hrm-daemon ]; then
-    sudo systemctl enable hrm
+    sudo systemctl enable hrm hrm.socket hrm-debug.socket
 fi
 
 # TLS certs (Tailscale — auto-renewed on each deploy)
@@ -66,11 +67,17 @@ fi
 # Restart services
 echo "Restarting services..."
 sudo systemctl restart treadmill-io treadmill-server
+# The daemons re-exec in place, keeping their listening sockets
 if [ -f ftms-daemon ]; then
-    sudo systemctl restart ftms
+    sudo systemctl reload-or-restart ftms
 fi
 if [ -f hrm-daemon ]; then
-    sudo systemctl restart hrm
+    if ! systemctl is-active --quiet hrm.socket; then
+        # First deploy with socket units: free the ports for systemd
+        sudo systemctl stop hrm
+        sudo systemctl start hrm.socket hrm-debug.socket
+    fi
+    sudo systemctl reload-or-restart hrm
 fi
 
 echo "Done! Services restarted."
@@ -0,0 +1,73 @@
+//! Benchmarks for the per-notification and per-status-line hot paths.
+//!
+//! These run on every Treadmill Data notify (up to 4 Hz per subscriber) and
+//! every treadmill_io line, so regressions matter on Pi Zero-class hardware.
+//! Run on the Pi itself for meaningful numbers:
+//!   cargo bench --bench hot_paths
+//!
+//! The daemon is a binary crate, so the self-contained modules are pulled in
+//! by path.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/protocol.rs"]
+mod protocol;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/status.rs"]
+mod status;
+
+const STATUS_LINE: &str = r#"{"type":"status","proxy":false,"emulate":true,"emu_speed":35,"emu_incline":4,"bus_speed":34,"bus_incline":4,"speed":3.5,"incline":2.0}"#;
+
+fn bench_encode(c: &mut Criterion) {
+    c.bench_function("encode_treadmill_data", |b| {
+        b.iter(|| {
+            protocol::encode_treadmill_data(
+                black_box(563),
+                black_box(45),
+                black_box(4_321),
+                black_box(Some(142)),
+                black_box(1_800),
+                black_box(Some(600)),
+            )
+        })
+    });
+}
+
+fn bench_control_point(c: &mut Criterion) {
+    let writes: [&[u8]; 4] = [&[0x00], &[0x02, 0xf4, 0x01], &[0x03, 0x32, 0x00], &[0x08, 0x01]];
+    c.bench_function("parse_control_point", |b| {
+        b.iter(|| {
+            for w in &writes {
+                black_box(protocol::parse_control_point(black_box(w)));
+            }
+        })
+    });
+}
+
+fn bench_conversions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("conversions");
+    group.bench_function("mph_tenths_to_kmh_hundredths", |b| {
+        b.iter(|| protocol::mph_tenths_to_kmh_hundredths(black_box(123)))
+    });
+    group.bench_function("mph_hundredths_to_kmh_hundredths", |b| {
+        b.iter(|| protocol::mph_hundredths_to_kmh_hundredths(black_box(1_234)))
+    });
+    group.bench_function("kmh_hundredths_to_mph_tenths", |b| {
+        b.iter(|| protocol::kmh_hundredths_to_mph_tenths(black_box(1_980)))
+    });
+    group.finish();
+}
+
+fn bench_status(c: &mut Criterion) {
+    c.bench_function("parse_status_line", |b| {
+        b.iter(|| match status::parse_line(black_box(STATUS_LINE)).unwrap() {
+            status::Message::Status(msg) => msg.reading(),
+            _ => unreachable!(),
+        })
+    });
+}
+
+criterion_group!(benches, bench_encode, bench_control_point, bench_conversions, bench_status);
+criterion_main!(benches);
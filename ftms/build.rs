@@ -0,0 +1,29 @@
+//! Generates the gRPC service plumbing for the `Treadmill` service in
+//! `../proto/precor.proto`. The messages are hand-written in `src/grpc.rs`,
+//! so no protoc is needed, here or when cross-compiling.
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> tonic_build::manual::MethodBuilder {
+    Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::grpc::{}", input))
+        .output_type(format!("crate::grpc::{}", output))
+        .codec_path("tonic_prost::ProstCodec")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let treadmill = Service::builder()
+        .name("Treadmill")
+        .package("precor.v1")
+        .method(method("get_state", "GetState", "Empty", "TreadmillState").build())
+        .method(method("watch_state", "WatchState", "WatchRequest", "TreadmillState").server_streaming().build())
+        .method(method("set_speed", "SetSpeed", "SetSpeedRequest", "ControlReply").build())
+        .method(method("set_incline", "SetIncline", "SetInclineRequest", "ControlReply").build())
+        .method(method("start", "Start", "Empty", "ControlReply").build())
+        .method(method("stop", "Stop", "StopRequest", "ControlReply").build())
+        .build();
+    Builder::new().compile(&[treadmill]);
+}
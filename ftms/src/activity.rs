@@ -0,0 +1,49 @@
+//! Adapter activity published for the HRM daemon.
+//!
+//! hrm-daemon usually shares our hci adapter, and BLE discovery on the same
+//! radio steals airtime from advertising and from connected apps. While
+//! apps are subscribed to FTMS notifications we keep `activity_file` up to
+//! date with how many there are, so the HRM scanner can throttle or pause
+//! discovery. The file is rewritten on every change and at least every
+//! `REFRESH_INTERVAL`; readers treat one that stops being refreshed (we
+//! crashed or lost BlueZ) as idle.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How often the file is rewritten even when nothing changed.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Contents of the activity file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Activity {
+    /// Apps currently subscribed to FTMS notifications.
+    pub centrals: usize,
+    /// Unix epoch ms when this was written.
+    pub wall_ms: u64,
+}
+
+/// Write `activity` to `path` atomically.
+pub async fn save(path: &str, activity: &Activity) -> std::io::Result<()> {
+    let json = serde_json::to_string(activity)?;
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_format() {
+        let path = "/tmp/ftms_test_activity.json";
+        save(path, &Activity { centrals: 2, wall_ms: 1_700_000_000_000 }).await.unwrap();
+        // hrm-daemon reads these field names; keep them stable
+        let json = std::fs::read_to_string(path).unwrap();
+        assert_eq!(json, r#"{"centrals":2,"wall_ms":1700000000000}"#);
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,160 @@
+//! BLE advertisement layout.
+//!
+//! A legacy advertisement carries at most 31 bytes. The FTMS service UUID and
+//! service data (machine available + treadmill type) always go in the primary
+//! advertisement, since apps filter on them. The local name goes wherever the
+//! config says: in the advertisement itself, or in the scan response (BlueZ
+//! puts an included adapter alias there), so a long name is never truncated.
+//!
+//! Optionally the advertisement goes out as a BLE 5 extended advertisement
+//! on a 2M (or 1M) secondary channel. Extended connectable advertisements
+//! can't have a scan response, but they have room for the name.
+
+use bluer::adv::{Advertisement, Feature, SecondaryChannel};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::FTMS_SERVICE_UUID;
+
+/// Legacy advertising payload limit, in bytes.
+pub const LEGACY_ADV_MAX: usize = 31;
+
+/// FTMS spec Section 3.1: Service Data must include Flags (available) + Machine Type (treadmill)
+const FTMS_SERVICE_DATA: [u8; 2] = [
+    0x01, // Flags: bit 0 = Fitness Machine Available
+    0x01, // Fitness Machine Type: bit 0 = Treadmill Supported
+];
+
+/// Where the local name is carried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamePlacement {
+    /// In the advertisement if it fits, otherwise the scan response.
+    #[default]
+    Auto,
+    /// Always in the advertisement (passive scanners see it, may be truncated).
+    Advertisement,
+    /// Always in the scan response (full name, needs an active scan).
+    ScanResponse,
+}
+
+/// Bytes the primary advertisement needs with a `name_len`-byte name in it:
+/// Flags (3) + 16-bit UUID list (2 + 2) + 16-bit service data (2 + 2 + data)
+/// + Complete Local Name (2 + name).
+pub fn adv_len_with_name(name_len: usize) -> usize {
+    3 + (2 + 2) + (2 + 2 + FTMS_SERVICE_DATA.len()) + (2 + name_len)
+}
+
+/// Resolve `Auto` against the payload budget. Extended advertising has no
+/// scan response, so the name always goes in the advertisement.
+pub fn resolve_placement(placement: NamePlacement, name: &str, extended: bool) -> NamePlacement {
+    match placement {
+        _ if extended => NamePlacement::Advertisement,
+        NamePlacement::Auto if adv_len_with_name(name.len()) <= LEGACY_ADV_MAX => NamePlacement::Advertisement,
+        NamePlacement::Auto => NamePlacement::ScanResponse,
+        other => other,
+    }
+}
+
+/// Pick the secondary channel for extended advertising from what the
+/// adapter supports, preferring 2M. None means legacy advertising only.
+pub fn pick_secondary_channel(supported: &[SecondaryChannel]) -> Option<SecondaryChannel> {
+    [SecondaryChannel::TwoM, SecondaryChannel::OneM]
+        .into_iter()
+        .find(|c| supported.contains(c))
+}
+
+/// Build the FTMS advertisement, as an extended advertisement on
+/// `secondary` when given. With the name in the scan response the returned
+/// advertisement has no `local_name`; the caller must set the adapter alias
+/// to `name` so BlueZ can include it.
+pub fn build(
+    name: &str,
+    placement: NamePlacement,
+    secondary: Option<SecondaryChannel>,
+) -> (Advertisement, NamePlacement) {
+    let placement = resolve_placement(placement, name, secondary.is_some());
+    let mut adv = Advertisement {
+        advertisement_type: bluer::adv::Type::Peripheral,
+        service_uuids: [FTMS_SERVICE_UUID].into_iter().collect(),
+        service_data: [(FTMS_SERVICE_UUID, FTMS_SERVICE_DATA.to_vec())].into_iter().collect(),
+        discoverable: Some(true),
+        secondary_channel: secondary,
+        ..Default::default()
+    };
+    match placement {
+        NamePlacement::ScanResponse => {
+            adv.system_includes.insert(Feature::LocalName);
+        }
+        _ => adv.local_name = Some(name.to_string()),
+    }
+    info!(
+        "Advertisement: {} bytes primary, name '{}' in {:?}, {}",
+        match placement {
+            NamePlacement::ScanResponse => adv_len_with_name(0) - 2,
+            _ => adv_len_with_name(name.len()),
+        },
+        name,
+        placement,
+        match secondary {
+            Some(channel) => format!("extended on {} PHY", channel),
+            None => "legacy".to_string(),
+        }
+    );
+    (adv, placement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_name_fits_in_advertisement() {
+        assert_eq!(adv_len_with_name("Precor 9.31".len()), 26);
+        assert_eq!(resolve_placement(NamePlacement::Auto, "Precor 9.31", false), NamePlacement::Advertisement);
+    }
+
+    #[test]
+    fn test_long_name_moves_to_scan_response() {
+        let name = "Precor 9.31 Basement Treadmill";
+        assert_eq!(resolve_placement(NamePlacement::Auto, name, false), NamePlacement::ScanResponse);
+        assert_eq!(
+            resolve_placement(NamePlacement::Advertisement, name, false),
+            NamePlacement::Advertisement,
+            "explicit placement is honored"
+        );
+
+        let (adv, placement) = build(name, NamePlacement::Auto, None);
+        assert_eq!(placement, NamePlacement::ScanResponse);
+        assert!(adv.local_name.is_none());
+        assert!(adv.system_includes.contains(&Feature::LocalName));
+        assert!(adv.service_data.contains_key(&FTMS_SERVICE_UUID), "service data stays primary");
+    }
+
+    #[test]
+    fn test_build_with_name_in_advertisement() {
+        let (adv, placement) = build("Precor 9.31", NamePlacement::Auto, None);
+        assert_eq!(placement, NamePlacement::Advertisement);
+        assert_eq!(adv.local_name.as_deref(), Some("Precor 9.31"));
+        assert!(adv.system_includes.is_empty());
+        assert!(adv.secondary_channel.is_none());
+    }
+
+    #[test]
+    fn test_pick_secondary_channel() {
+        use SecondaryChannel::*;
+        assert_eq!(pick_secondary_channel(&[OneM, TwoM, Coded]), Some(TwoM));
+        assert_eq!(pick_secondary_channel(&[OneM]), Some(OneM));
+        assert_eq!(pick_secondary_channel(&[Coded]), None);
+        assert_eq!(pick_secondary_channel(&[]), None);
+    }
+
+    #[test]
+    fn test_extended_keeps_long_name_in_advertisement() {
+        let name = "Precor 9.31 Basement Treadmill";
+        let (adv, placement) = build(name, NamePlacement::ScanResponse, Some(SecondaryChannel::TwoM));
+        assert_eq!(placement, NamePlacement::Advertisement, "no scan response when extended");
+        assert_eq!(adv.local_name.as_deref(), Some(name));
+        assert_eq!(adv.secondary_channel, Some(SecondaryChannel::TwoM));
+    }
+}
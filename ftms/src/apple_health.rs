@@ -0,0 +1,254 @@
+//! Apple Health export.
+//!
+//! The Health app can't import files itself, but import apps (Health
+//! Importer, Simple Health Export/Import, ...) take the same
+//! `apple_health_export/export.xml` zip that Health's own "Export All
+//! Health Data" produces. This writes one of those per workout: a
+//! `Workout` element plus per-minute distance, heart rate and (when the
+//! user's weight is known) active energy records.
+
+use std::fmt::Write;
+
+use crate::clock;
+use crate::export::{utc_parts, Metadata, Sport};
+use crate::workout::{Sample, Workout};
+
+const SOURCE_NAME: &str = "Precor FTMS";
+/// Path of the document inside the zip, as Health writes it.
+const XML_PATH: &str = "apple_health_export/export.xml";
+/// Samples per distance/HR record.
+const RECORD_SECS: usize = 60;
+
+/// Encode `workout` as an Apple Health export zip.
+pub fn encode(workout: &Workout, meta: &Metadata, weight_kg: Option<f64>) -> Vec<u8> {
+    let end = clock::local_ms(workout.end_wall_ms(), workout.utc_offset_secs);
+    zip_stored(XML_PATH, export_xml(workout, meta, weight_kg).as_bytes(), end)
+}
+
+/// The `export.xml` document.
+pub fn export_xml(workout: &Workout, meta: &Metadata, weight_kg: Option<f64>) -> String {
+    let source = xml_escape(meta.device.as_deref().unwrap_or(SOURCE_NAME));
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE HealthData>\n\
+         <HealthData locale=\"en_US\">\n",
+    );
+    let _ = writeln!(xml, " <ExportDate value=\"{}\"/>", health_date(workout.end_wall_ms(), workout.utc_offset_secs));
+
+    // Each record spans its samples, starting where the previous one ended
+    let mut prev_wall_ms = workout.start_wall_ms;
+    let mut prev_distance = 0.0;
+    for chunk in workout.samples.chunks(RECORD_SECS) {
+        let last = chunk.last().expect("chunks are non-empty");
+        let span = (prev_wall_ms, last.wall_ms);
+        let km = (last.distance_m - prev_distance) / 1000.0;
+        if km > 0.0 {
+            record(&mut xml, &source, "HKQuantityTypeIdentifierDistanceWalkingRunning", "km", &format!("{:.4}", km), span, workout.utc_offset_secs);
+        }
+        if let Some(bpm) = mean_heart_rate(chunk) {
+            record(&mut xml, &source, "HKQuantityTypeIdentifierHeartRate", "count/min", &bpm.to_string(), span, workout.utc_offset_secs);
+        }
+        prev_wall_ms = last.wall_ms;
+        prev_distance = last.distance_m;
+    }
+    let span = (workout.start_wall_ms, workout.end_wall_ms());
+    let kcal = weight_kg.map(|kg| workout.active_kcal(kg));
+    if let Some(kcal) = kcal {
+        record(&mut xml, &source, "HKQuantityTypeIdentifierActiveEnergyBurned", "kcal", &format!("{:.1}", kcal), span, workout.utc_offset_secs);
+    }
+
+    let (start, end) = (health_date(span.0, workout.utc_offset_secs), health_date(span.1, workout.utc_offset_secs));
+    let activity_type = match meta.sport {
+        Sport::Running => "HKWorkoutActivityTypeRunning",
+        Sport::Walking => "HKWorkoutActivityTypeWalking",
+    };
+    let _ = write!(
+        xml,
+        " <Workout workoutActivityType=\"{}\" duration=\"{:.2}\" durationUnit=\"min\" \
+         sourceName=\"{}\" creationDate=\"{}\" startDate=\"{}\" endDate=\"{}\">\n  \
+         <MetadataEntry key=\"HKIndoorWorkout\" value=\"1\"/>\n  \
+         <MetadataEntry key=\"HKElevationAscended\" value=\"{:.0} cm\"/>\n  \
+         {}\
+         <WorkoutStatistics type=\"HKQuantityTypeIdentifierDistanceWalkingRunning\" startDate=\"{}\" endDate=\"{}\" sum=\"{:.4}\" unit=\"km\"/>\n",
+        activity_type,
+        workout.elapsed_secs() as f64 / 60.0,
+        source,
+        end,
+        start,
+        end,
+        workout.elevation_gain_m() * 100.0,
+        meta.title.as_deref().map(|l| format!("<MetadataEntry key=\"HKWorkoutTitle\" value=\"{}\"/>\n  ", xml_escape(l))).unwrap_or_default(),
+        start,
+        end,
+        workout.distance_m() / 1000.0,
+    );
+    if let (Some(avg), Some(max)) = (workout.avg_heart_rate(), workout.max_heart_rate()) {
+        let _ = writeln!(
+            xml,
+            "  <WorkoutStatistics type=\"HKQuantityTypeIdentifierHeartRate\" startDate=\"{}\" endDate=\"{}\" average=\"{}\" maximum=\"{}\" unit=\"count/min\"/>",
+            start, end, avg, max
+        );
+    }
+    if let Some(kcal) = kcal {
+        let _ = writeln!(
+            xml,
+            "  <WorkoutStatistics type=\"HKQuantityTypeIdentifierActiveEnergyBurned\" startDate=\"{}\" endDate=\"{}\" sum=\"{:.1}\" unit=\"kcal\"/>",
+            start, end, kcal
+        );
+    }
+    xml.push_str(" </Workout>\n</HealthData>\n");
+    xml
+}
+
+fn record(xml: &mut String, source: &str, kind: &str, unit: &str, value: &str, (start_ms, end_ms): (u64, u64), offset: i32) {
+    let (start, end) = (health_date(start_ms, offset), health_date(end_ms, offset));
+    let _ = writeln!(
+        xml,
+        " <Record type=\"{}\" sourceName=\"{}\" unit=\"{}\" creationDate=\"{}\" startDate=\"{}\" endDate=\"{}\" value=\"{}\"/>",
+        kind, source, unit, end, start, end, value
+    );
+}
+
+fn mean_heart_rate(samples: &[Sample]) -> Option<u32> {
+    let hrs: Vec<u32> = samples.iter().filter_map(|s| s.heart_rate).map(u32::from).collect();
+    (!hrs.is_empty()).then(|| hrs.iter().sum::<u32>() / hrs.len() as u32)
+}
+
+/// Health's date format: local time with the offset, e.g.
+/// `2024-03-15 02:15:02 -0500`.
+fn health_date(wall_ms: u64, utc_offset_secs: i32) -> String {
+    let (y, m, d, hh, mm, ss) = utc_parts(clock::local_ms(wall_ms, utc_offset_secs));
+    let offset = clock::format_offset(utc_offset_secs).replace(':', "");
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}", y, m, d, hh, mm, ss, offset)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A zip archive holding `data` uncompressed as `name`, modified at local
+/// time `local_ms` (zip times have no zone).
+fn zip_stored(name: &str, data: &[u8], local_ms: u64) -> Vec<u8> {
+    let (y, m, d, hh, mm, ss) = utc_parts(local_ms);
+    let dos_time = ((hh << 11) | (mm << 5) | (ss / 2)) as u16;
+    let dos_date = ((((y - 1980).max(0) as u32) << 9) | (m << 5) | d) as u16;
+    let crc = crc32(data);
+
+    // Fields shared by the local and central headers, from "version needed"
+    let mut common = Vec::new();
+    common.extend_from_slice(&20u16.to_le_bytes()); // version needed (2.0)
+    common.extend_from_slice(&0u16.to_le_bytes()); // flags
+    common.extend_from_slice(&0u16.to_le_bytes()); // method: stored
+    common.extend_from_slice(&dos_time.to_le_bytes());
+    common.extend_from_slice(&dos_date.to_le_bytes());
+    common.extend_from_slice(&crc.to_le_bytes());
+    common.extend_from_slice(&(data.len() as u32).to_le_bytes()); // compressed
+    common.extend_from_slice(&(data.len() as u32).to_le_bytes()); // uncompressed
+    common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+    let mut out = Vec::with_capacity(data.len() + 128);
+    out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+    out.extend_from_slice(&common);
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(data);
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+    out.extend_from_slice(&20u16.to_le_bytes()); // version made by
+    out.extend_from_slice(&common);
+    out.extend_from_slice(&[0; 12]); // comment len, disk, internal + external attrs
+    out.extend_from_slice(&0u32.to_le_bytes()); // local header offset
+    out.extend_from_slice(name.as_bytes());
+    let central_size = out.len() as u32 - central_offset;
+
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&1u16.to_le_bytes()); // entries on this disk
+    out.extend_from_slice(&1u16.to_le_bytes()); // entries total
+    out.extend_from_slice(&central_size.to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    out
+}
+
+/// CRC-32 (IEEE), as zip uses.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    fn u16_at(b: &[u8], i: usize) -> usize {
+        u16::from_le_bytes([b[i], b[i + 1]]) as usize
+    }
+
+    fn u32_at(b: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_zip_layout() {
+        let w = sample_workout();
+        let zip = encode(&w, &Metadata::default(), None);
+        assert_eq!(u32_at(&zip, 0), 0x0403_4b50);
+        let (size, name_len) = (u32_at(&zip, 18) as usize, u16_at(&zip, 26));
+        assert_eq!(&zip[30..30 + name_len], XML_PATH.as_bytes());
+        let data = &zip[30 + name_len..30 + name_len + size];
+        assert_eq!(u32_at(&zip, 14), crc32(data));
+        assert_eq!(data, export_xml(&w, &Metadata::default(), None).as_bytes());
+
+        // End of central directory points back at the one central entry
+        let eocd = zip.len() - 22;
+        assert_eq!(u32_at(&zip, eocd), 0x0605_4b50);
+        let central = u32_at(&zip, eocd + 16) as usize;
+        assert_eq!(u32_at(&zip, central), 0x0201_4b50);
+        assert_eq!(central + u32_at(&zip, eocd + 12) as usize, eocd);
+    }
+
+    #[test]
+    fn test_export_xml() {
+        let w = sample_workout();
+        let xml = export_xml(&w, &Metadata::default(), Some(70.0));
+        assert!(xml.contains(
+            "startDate=\"2023-11-14 17:13:20 -0500\" endDate=\"2023-11-14 17:24:20 -0500\">"
+        ));
+        assert!(xml.contains("duration=\"11.00\" durationUnit=\"min\""));
+        assert!(xml.contains("sum=\"1.6898\" unit=\"km\""));
+        assert!(xml.contains("average=\"149\" maximum=\"180\""));
+        assert!(xml.contains("<MetadataEntry key=\"HKElevationAscended\" value=\"3380 cm\"/>"));
+        // 11 minutes of distance, HR only for the first 10
+        assert_eq!(xml.matches("DistanceWalkingRunning\" sourceName").count(), 11);
+        assert_eq!(xml.matches("\"HKQuantityTypeIdentifierHeartRate\" sourceName").count(), 10);
+        assert!(xml.contains("ActiveEnergyBurned"));
+        assert!(!export_xml(&w, &Metadata::default(), None).contains("ActiveEnergyBurned"));
+        assert!(!xml.contains("HKWorkoutTitle"));
+        assert!(xml.contains("workoutActivityType=\"HKWorkoutActivityTypeRunning\""));
+        assert!(xml.contains("sourceName=\"Precor FTMS\""));
+        let meta = Metadata {
+            title: Some("Hills & <stuff>".to_string()),
+            sport: Sport::Walking,
+            device: Some("Precor \"9.31\"".to_string()),
+        };
+        let named = export_xml(&w, &meta, None);
+        assert!(named.contains("<MetadataEntry key=\"HKWorkoutTitle\" value=\"Hills &amp; &lt;stuff&gt;\"/>"));
+        assert!(named.contains("workoutActivityType=\"HKWorkoutActivityTypeWalking\""));
+        assert_eq!(named.matches("sourceName=\"Precor &quot;9.31&quot;\"").count(), 22);
+    }
+}
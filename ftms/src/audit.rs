@@ -0,0 +1,174 @@
+//! Audit trail of control commands.
+//!
+//! Every control point command, over BLE, `cp` on the debug port or gRPC,
+//! is recorded with its result and who sent it: the central's Bluetooth
+//! address, or the debug or gRPC client's address and port. The most recent ones
+//! stay in memory for the debug `history` command. With `audit_log` set
+//! they're also appended to that file as JSON lines, and its tail seeds
+//! the in-memory list on startup, so a restart doesn't hide who started
+//! the belt at 2 am. Debug `sub control-events` clients get each one live.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::protocol::{self, ControlCommand};
+use crate::telemetry;
+
+/// Commands kept in memory for `history`.
+const KEEP: usize = 200;
+
+/// Who sent a control command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// A BLE central, by address.
+    Ble(String),
+    /// A debug port client, by `ip:port`.
+    Debug(String),
+    /// A gRPC client, by `ip:port`.
+    Grpc(String),
+    /// The daemon itself, e.g. stopping the belt at a workout target.
+    Daemon,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Ble(addr) => write!(f, "ble {}", addr),
+            Origin::Debug(peer) => write!(f, "debug {}", peer),
+            Origin::Grpc(peer) => write!(f, "grpc {}", peer),
+            Origin::Daemon => write!(f, "daemon"),
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch.
+    pub wall_ms: u64,
+    pub origin: Origin,
+    pub opcode: u8,
+    /// The command with its parameters, e.g. `SetTargetSpeed(500)`, or
+    /// `Unsupported(<hex>)` for bytes that didn't parse.
+    pub command: String,
+    /// FTMS result code sent back.
+    pub result: u8,
+}
+
+/// Cheap, cloneable handle to the audit trail. The default keeps
+/// commands in memory only.
+#[derive(Clone)]
+pub struct Audit {
+    recent: Arc<Mutex<VecDeque<AuditRecord>>>,
+    tx: Option<mpsc::Sender<AuditRecord>>,
+    live: broadcast::Sender<AuditRecord>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self { recent: Arc::default(), tx: None, live: broadcast::channel(16).0 }
+    }
+}
+
+impl Audit {
+    /// Record a command and the result code it produced.
+    pub fn control(&self, origin: &Origin, opcode: u8, cmd: &ControlCommand, result: u8) {
+        self.record(origin, opcode, format!("{:?}", cmd), result);
+    }
+
+    /// Record a write whose opcode we don't support.
+    pub fn unsupported(&self, origin: &Origin, bytes: &[u8]) {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let opcode = bytes.first().copied().unwrap_or(0);
+        self.record(origin, opcode, format!("Unsupported({})", hex), protocol::RESULT_NOT_SUPPORTED);
+    }
+
+    fn record(&self, origin: &Origin, opcode: u8, command: String, result: u8) {
+        let record = AuditRecord { wall_ms: telemetry::wall_ms(), origin: origin.clone(), opcode, command, result };
+        if let Some(tx) = &self.tx {
+            if tx.try_send(record.clone()).is_err() {
+                warn!("Audit log backlogged, dropping record");
+            }
+        }
+        let _ = self.live.send(record.clone());
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == KEEP {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+    }
+
+    /// Commands as they're recorded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<AuditRecord> {
+        self.live.subscribe()
+    }
+
+    /// The last `n` commands, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<AuditRecord> {
+        let recent = self.recent.lock().unwrap();
+        recent.iter().skip(recent.len().saturating_sub(n)).cloned().collect()
+    }
+}
+
+/// Start auditing to `path` (append mode), picking up where its last
+/// lines left off. In memory only when `path` is `None`.
+pub fn start(path: Option<&str>) -> Audit {
+    let Some(path) = path else {
+        return Audit::default();
+    };
+    let recent = tail(path);
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(telemetry::write_jsonl(path.to_string(), rx, "audit log"));
+    info!("Auditing control commands to {} ({} earlier ones loaded)", path, recent.len());
+    Audit { recent: Arc::new(Mutex::new(recent)), tx: Some(tx), ..Default::default() }
+}
+
+/// The last `KEEP` records in `path`, skipping lines that don't parse.
+fn tail(path: &str) -> VecDeque<AuditRecord> {
+    let Ok(data) = std::fs::read_to_string(path) else {
+        return VecDeque::new();
+    };
+    let mut recent: VecDeque<AuditRecord> = data.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    while recent.len() > KEEP {
+        recent.pop_front();
+    }
+    recent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_log_round_trip() {
+        let path = std::env::temp_dir().join(format!("ftms_audit_test_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let ble = Origin::Ble("F0:12:34:56:78:9A".into());
+        let debug = Origin::Debug("10.0.0.5:51234".into());
+
+        let audit = start(Some(path));
+        audit.control(&ble, 0x07, &ControlCommand::StartOrResume, protocol::RESULT_SUCCESS);
+        audit.unsupported(&debug, &[0x11, 0x02]);
+        let recent = audit.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!((recent[0].origin.to_string(), recent[0].command.as_str()), ("ble F0:12:34:56:78:9A".into(), "StartOrResume"));
+        assert_eq!((recent[1].opcode, recent[1].command.as_str()), (0x11, "Unsupported(1102)"));
+        assert_eq!(audit.recent(1), recent[1..]);
+
+        // The writer task appends in the background
+        drop(audit);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let line = std::fs::read_to_string(path).unwrap().lines().next().unwrap().to_string();
+        assert!(line.contains(r#""origin":{"ble":"F0:12:34:56:78:9A"}"#), "{}", line);
+
+        std::fs::write(path, std::fs::read_to_string(path).unwrap() + "garbage\n").unwrap();
+        assert_eq!(start(Some(path)).recent(10), recent, "reloaded after a restart");
+        let _ = std::fs::remove_file(path);
+    }
+}
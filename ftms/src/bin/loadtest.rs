@@ -0,0 +1,342 @@
+//! Load generator for the FTMS and HRM debug servers.
+//!
+//! Opens many concurrent debug connections, sends a mix of commands as fast
+//! as each connection allows, and reports latency percentiles and error
+//! rates per command. A response is complete when the server's prompt
+//! (`ftms-debug> ` / `hrm-debug> `) comes back.
+//!
+//! Usage:
+//!   loadtest [--host rpi] [--port 8826] [--clients 20] [--duration 10]
+//!            [--commands state,td,feat,cp 00] [--speed]
+//!
+//! `--speed` adds `cp 02` speed changes (0.5-2.0 mph) to the mix and stops
+//! the belt when done. Only use it with nobody on the treadmill.
+//!
+//! The servers cap concurrent connections and pace each one's commands
+//! (`debug_max_connections`, `debug_commands_per_sec`); raise or zero both
+//! in the daemon's config first, or the numbers measure the limits.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const DEFAULT_COMMANDS: &str = "state,td,feat,cp 00";
+/// Any response slower than this counts as an error and ends the connection.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Options {
+    host: String,
+    port: u16,
+    clients: usize,
+    duration: Duration,
+    commands: Vec<String>,
+    speed: bool,
+}
+
+/// Per-command results from one or more connections.
+#[derive(Default)]
+struct Stats {
+    latencies: BTreeMap<String, Vec<Duration>>,
+    errors: BTreeMap<String, usize>,
+    connect_failures: usize,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        for (cmd, mut l) in other.latencies {
+            self.latencies.entry(cmd).or_default().append(&mut l);
+        }
+        for (cmd, n) in other.errors {
+            *self.errors.entry(cmd).or_default() += n;
+        }
+        self.connect_failures += other.connect_failures;
+    }
+}
+
+/// Nearest-rank percentile of a sorted slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// The prompt a debug server uses, taken from its welcome line
+/// (`"ftms-debug> connected. ..."` → `"ftms-debug> "`).
+fn prompt_from_welcome(welcome: &str) -> Option<String> {
+    welcome.find("> ").map(|i| welcome[..i + 2].to_string())
+}
+
+/// Server-side errors come back as text, not a dropped connection.
+fn is_error_response(response: &str) -> bool {
+    let r = response.trim_start();
+    r.starts_with("error") || r.starts_with("unknown command") || r.starts_with("usage:")
+}
+
+/// `cp 02` with a little-endian speed in km/h hundredths.
+fn speed_command(kmh_hundredths: u16) -> String {
+    let [lo, hi] = kmh_hundredths.to_le_bytes();
+    format!("cp 02 {:02x}{:02x}", lo, hi)
+}
+
+struct Connection {
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: tokio::net::tcp::OwnedWriteHalf,
+    prompt: String,
+}
+
+impl Connection {
+    async fn open(host: &str, port: u16) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let stream = TcpStream::connect((host, port)).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut welcome = String::new();
+        tokio::time::timeout(RESPONSE_TIMEOUT, reader.read_line(&mut welcome)).await??;
+        let prompt = prompt_from_welcome(&welcome)
+            .ok_or_else(|| format!("unexpected welcome: {:?}", welcome.trim()))?;
+        let mut conn = Self { reader, writer, prompt };
+        // The first prompt follows the welcome line
+        conn.read_response().await?;
+        Ok(conn)
+    }
+
+    /// Read until the prompt comes back; returns everything before it.
+    async fn read_response(&mut self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut buf = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            if self.reader.read(&mut byte).await? == 0 {
+                return Err("connection closed".into());
+            }
+            buf.push(byte[0]);
+            if buf.ends_with(self.prompt.as_bytes())
+                && (buf.len() == self.prompt.len() || buf[buf.len() - self.prompt.len() - 1] == b'\n')
+            {
+                buf.truncate(buf.len() - self.prompt.len());
+                return Ok(String::from_utf8_lossy(&buf).into_owned());
+            }
+        }
+    }
+
+    async fn send(&mut self, cmd: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        self.writer.write_all(format!("{}\n", cmd).as_bytes()).await?;
+        tokio::time::timeout(RESPONSE_TIMEOUT, self.read_response()).await?
+    }
+}
+
+async fn run_client(id: usize, opts: &Options, deadline: Instant) -> Stats {
+    let mut stats = Stats::default();
+    let mut conn = match Connection::open(&opts.host, opts.port).await {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("client {}: connect failed: {}", id, e);
+            stats.connect_failures += 1;
+            return stats;
+        }
+    };
+
+    let mut i = id; // stagger clients through the command mix
+    while Instant::now() < deadline {
+        let cmd = if opts.speed && i % (opts.commands.len() + 1) == opts.commands.len() {
+            // 0.5-2.0 mph, stepping with the request count
+            speed_command(80 + (i % 16) as u16 * 16)
+        } else {
+            opts.commands[i % opts.commands.len()].clone()
+        };
+        let label = if cmd.starts_with("cp 02") { "cp 02 <speed>".to_string() } else { cmd.clone() };
+        i += 1;
+
+        let start = Instant::now();
+        match conn.send(&cmd).await {
+            Ok(resp) if !is_error_response(&resp) => {
+                stats.latencies.entry(label).or_default().push(start.elapsed());
+            }
+            Ok(resp) => {
+                eprintln!("client {}: '{}' -> {}", id, cmd, resp.trim());
+                *stats.errors.entry(label).or_default() += 1;
+            }
+            Err(e) => {
+                eprintln!("client {}: '{}' failed: {}", id, cmd, e);
+                *stats.errors.entry(label).or_default() += 1;
+                break;
+            }
+        }
+    }
+    let _ = conn.writer.write_all(b"quit\n").await;
+    stats
+}
+
+fn report(stats: &Stats, elapsed: Duration) {
+    let total_ok: usize = stats.latencies.values().map(Vec::len).sum();
+    let total_err: usize = stats.errors.values().sum();
+    println!(
+        "{:<16} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "command", "ok", "errors", "p50", "p90", "p99", "max"
+    );
+    let mut all = Vec::new();
+    let commands: std::collections::BTreeSet<_> =
+        stats.latencies.keys().chain(stats.errors.keys()).collect();
+    for cmd in commands {
+        let mut l = stats.latencies.get(cmd).cloned().unwrap_or_default();
+        l.sort();
+        all.extend_from_slice(&l);
+        print_row(cmd, &l, stats.errors.get(cmd).copied().unwrap_or(0));
+    }
+    all.sort();
+    print_row("all", &all, total_err);
+
+    let total = total_ok + total_err;
+    println!(
+        "\n{} requests in {:.1}s ({:.0} req/s), error rate {:.2}%, {} connect failures",
+        total,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64(),
+        if total == 0 { 0.0 } else { total_err as f64 * 100.0 / total as f64 },
+        stats.connect_failures
+    );
+}
+
+fn print_row(cmd: &str, sorted: &[Duration], errors: usize) {
+    let ms = |d: Duration| format!("{:.2}ms", d.as_secs_f64() * 1000.0);
+    println!(
+        "{:<16} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        cmd,
+        sorted.len(),
+        errors,
+        ms(percentile(sorted, 50.0)),
+        ms(percentile(sorted, 90.0)),
+        ms(percentile(sorted, 99.0)),
+        ms(sorted.last().copied().unwrap_or_default()),
+    );
+}
+
+fn parse_args() -> Options {
+    let args: Vec<String> = std::env::args().collect();
+    let mut opts = Options {
+        host: "rpi".to_string(),
+        port: 8826,
+        clients: 20,
+        duration: Duration::from_secs(10),
+        commands: Vec::new(),
+        speed: false,
+    };
+    let mut commands = DEFAULT_COMMANDS.to_string();
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match (args[i].as_str(), value) {
+            ("--host", Some(v)) => opts.host = v.clone(),
+            ("--port", Some(v)) => opts.port = v.parse().unwrap_or(opts.port),
+            ("--clients", Some(v)) => opts.clients = v.parse().unwrap_or(opts.clients),
+            ("--duration", Some(v)) => {
+                opts.duration = v.parse().map(Duration::from_secs).unwrap_or(opts.duration)
+            }
+            ("--commands", Some(v)) => commands = v.clone(),
+            ("--speed", _) => {
+                opts.speed = true;
+                i += 1;
+                continue;
+            }
+            (other, _) => {
+                eprintln!("unknown or incomplete argument: {}", other);
+                std::process::exit(2);
+            }
+        }
+        i += 2;
+    }
+
+    opts.commands = commands
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect();
+    if opts.commands.is_empty() || opts.clients == 0 {
+        eprintln!("need at least one command and one client");
+        std::process::exit(2);
+    }
+    opts
+}
+
+#[tokio::main]
+async fn main() {
+    let opts = std::sync::Arc::new(parse_args());
+    println!(
+        "loadtest: {} clients against {}:{} for {:?}, commands: {:?}{}",
+        opts.clients,
+        opts.host,
+        opts.port,
+        opts.duration,
+        opts.commands,
+        if opts.speed { " + speed changes" } else { "" }
+    );
+
+    let start = Instant::now();
+    let deadline = start + opts.duration;
+    let handles: Vec<_> = (0..opts.clients)
+        .map(|id| {
+            let opts = opts.clone();
+            tokio::spawn(async move { run_client(id, &opts, deadline).await })
+        })
+        .collect();
+
+    let mut stats = Stats::default();
+    for handle in handles {
+        match handle.await {
+            Ok(s) => stats.merge(s),
+            Err(e) => eprintln!("client task panicked: {}", e),
+        }
+    }
+    let elapsed = start.elapsed();
+
+    if opts.speed {
+        // Don't leave the belt running
+        if let Ok(mut conn) = Connection::open(&opts.host, opts.port).await {
+            let _ = conn.send("cp 08 01").await;
+        }
+    }
+
+    report(&stats, elapsed);
+    let errors: usize = stats.errors.values().sum();
+    if errors > 0 || stats.connect_failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&sorted[..1], 90.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_prompt_from_welcome() {
+        assert_eq!(
+            prompt_from_welcome("ftms-debug> connected. type 'help' for commands.\n").as_deref(),
+            Some("ftms-debug> ")
+        );
+        assert_eq!(prompt_from_welcome("hrm-debug> connected.").as_deref(), Some("hrm-debug> "));
+        assert_eq!(prompt_from_welcome("hello"), None);
+    }
+
+    #[test]
+    fn test_speed_command() {
+        // 5.00 km/h = 500 = 0x01f4
+        assert_eq!(speed_command(500), "cp 02 f401");
+        assert!(is_error_response("error: unknown opcode"));
+        assert!(is_error_response("unknown command: 'x'. type 'help'."));
+        assert!(!is_error_response("resp 800001"));
+    }
+}
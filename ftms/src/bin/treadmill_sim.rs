@@ -0,0 +1,415 @@
+//! Stand-in for treadmill_io, for running ftms-daemon without the treadmill.
+//!
+//! Serves the simulated treadmill_io from `machine.rs` (the same one
+//! `--mock-treadmill` runs in-process) on a Unix socket, with up to 4
+//! clients and every line broadcast to all of them; the last client
+//! leaving drops back to proxy at 0, as treadmill_io's watchdog does. The
+//! belt speeds up at `--accel` and slows at `--decel` mph/s, and the
+//! incline motor moves at `--incline-rate` %/s.
+//!
+//! A script plays what happens at the machine itself, one action per line
+//! at seconds since start:
+//!
+//!   # someone at the console takes over (leaves emulate)
+//!   5    console speed 3.0
+//!   20   console incline 2.5
+//!   30   console stop
+//!   40   disconnect        close every client's socket
+//!   50   mute 8            send nothing for 8 s, the socket stays open
+//!   60   quit
+//!
+//! Usage:
+//!   treadmill-sim [--socket /tmp/treadmill_io.sock] [--script FILE]
+//!                 [--accel 1.0] [--decel 1.5] [--incline-rate 1.0]
+//!
+//! Then `ftms-daemon --socket PATH` as with treadmill_io;
+//! `tests/sim_integration.sh` runs the debug port tests that way.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, Mutex, Notify};
+
+#[path = "../machine.rs"]
+mod machine;
+
+use machine::{Machine, Mode, Physics, TICK};
+
+const DEFAULT_SOCKET: &str = "/tmp/treadmill_io.sock";
+const MAX_CLIENTS: usize = 4;
+
+struct Options {
+    socket: String,
+    script: Option<String>,
+    physics: Physics,
+}
+
+/// The simulated treadmill_io with its clients.
+struct Sim {
+    machine: Machine,
+    clients: usize,
+    muted_until: Option<Instant>,
+}
+
+impl Sim {
+    fn new(physics: Physics, now: Instant) -> Self {
+        Self { machine: Machine::new(physics, now), clients: 0, muted_until: None }
+    }
+
+    /// The last client leaving drops emulate, as treadmill_io's watchdog does.
+    fn client_left(&mut self) -> Vec<String> {
+        self.clients = self.clients.saturating_sub(1);
+        if self.clients == 0 && self.machine.mode == Mode::Emulating {
+            warn!("All clients disconnected, leaving emulate");
+            return self.machine.watchdog_reset();
+        }
+        Vec::new()
+    }
+
+    /// Whether the script has the simulator gone quiet.
+    fn muted(&self) -> bool {
+        self.muted_until.is_some_and(|t| Instant::now() < t)
+    }
+}
+
+/// Something the script makes happen at the machine.
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    /// Speed set at the console, tenths of mph.
+    ConsoleSpeed(u16),
+    /// Incline set at the console, half-percent units.
+    ConsoleIncline(u16),
+    /// The console's stop button: the belt winds down, incline stays.
+    ConsoleStop,
+    /// Close every client's socket.
+    Disconnect,
+    /// Send nothing for this long; commands are still taken.
+    Mute(Duration),
+    Quit,
+}
+
+/// Parse a script into (seconds since start, action), in time order.
+fn parse_script(text: &str) -> Result<Vec<(Duration, Action)>, String> {
+    let mut steps = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| words.get(i).and_then(|w| w.parse::<f64>().ok()).filter(|v| v.is_finite() && *v >= 0.0);
+        let at = number(0).ok_or_else(|| format!("line {}: expected seconds, got {:?}", n + 1, words[0]))?;
+        let action = match (words.get(1).copied(), words.get(2).copied()) {
+            (Some("console"), Some("speed")) => number(3).map(|mph| Action::ConsoleSpeed((mph * 10.0).round() as u16)),
+            (Some("console"), Some("incline")) => number(3).map(|pct| Action::ConsoleIncline((pct * 2.0).round() as u16)),
+            (Some("console"), Some("stop")) => Some(Action::ConsoleStop),
+            (Some("disconnect"), _) => Some(Action::Disconnect),
+            (Some("mute"), _) => number(2).map(|secs| Action::Mute(Duration::from_secs_f64(secs))),
+            (Some("quit"), _) => Some(Action::Quit),
+            _ => None,
+        }
+        .ok_or_else(|| format!("line {}: unknown action {:?}", n + 1, words[1..].join(" ")))?;
+        steps.push((Duration::from_secs_f64(at), action));
+    }
+    steps.sort_by_key(|(at, _)| *at);
+    Ok(steps)
+}
+
+/// What goes out to every client.
+#[derive(Debug, Clone)]
+enum Event {
+    Line(String),
+    Disconnect,
+}
+
+struct Shared {
+    sim: Mutex<Sim>,
+    events: broadcast::Sender<Event>,
+    quit: Notify,
+}
+
+impl Shared {
+    /// Broadcast `lines` unless the script has muted the simulator.
+    fn emit(&self, sim: &Sim, lines: Vec<String>) {
+        if sim.muted() {
+            return;
+        }
+        for line in lines {
+            let _ = self.events.send(Event::Line(line));
+        }
+    }
+}
+
+async fn run_physics(shared: Arc<Shared>) {
+    let mut ticks = tokio::time::interval(TICK);
+    let mut last = Instant::now();
+    loop {
+        ticks.tick().await;
+        let now = Instant::now();
+        let mut sim = shared.sim.lock().await;
+        let out = sim.machine.tick(now, now - last);
+        shared.emit(&sim, out);
+        last = now;
+    }
+}
+
+async fn run_script(shared: Arc<Shared>, steps: Vec<(Duration, Action)>) {
+    let start = tokio::time::Instant::now();
+    for (at, action) in steps {
+        tokio::time::sleep_until(start + at).await;
+        info!("Script at {:?}: {:?}", at, action);
+        let mut sim = shared.sim.lock().await;
+        let out = match action {
+            Action::ConsoleSpeed(t) => sim.machine.console(Some(t), None),
+            Action::ConsoleIncline(h) => sim.machine.console(None, Some(h)),
+            Action::ConsoleStop => sim.machine.console(Some(0), None),
+            Action::Disconnect => {
+                let _ = shared.events.send(Event::Disconnect);
+                Vec::new()
+            }
+            Action::Mute(secs) => {
+                sim.muted_until = Some(Instant::now() + secs);
+                Vec::new()
+            }
+            Action::Quit => {
+                shared.quit.notify_one();
+                return;
+            }
+        };
+        shared.emit(&sim, out);
+    }
+}
+
+/// Accept clients until told to quit.
+async fn serve(listener: UnixListener, shared: Arc<Shared>) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((mut stream, _)) => {
+                    let mut sim = shared.sim.lock().await;
+                    if sim.clients >= MAX_CLIENTS {
+                        warn!("Refusing client: already {} connected", MAX_CLIENTS);
+                        let _ = stream.write_all(b"{\"type\":\"error\",\"msg\":\"too many clients\"}\n").await;
+                        continue;
+                    }
+                    sim.clients += 1;
+                    info!("Client connected ({} total)", sim.clients);
+                    // Subscribe before the first command so its answer isn't missed
+                    let events = shared.events.subscribe();
+                    tokio::spawn(serve_client(shared.clone(), stream, events));
+                }
+                Err(e) => warn!("Accept failed: {}", e),
+            },
+            _ = shared.quit.notified() => return,
+        }
+    }
+}
+
+async fn serve_client(shared: Arc<Shared>, stream: UnixStream, mut events: broadcast::Receiver<Event>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else { break };
+                let mut sim = shared.sim.lock().await;
+                match sim.machine.command(&line, Instant::now()) {
+                    Some(reply) => {
+                        // The answer is written before this client's next
+                        // broadcast line, so it goes ahead of the status
+                        let answer = reply.sender.filter(|_| !sim.muted());
+                        shared.emit(&sim, reply.lines);
+                        drop(sim);
+                        if let Some(answer) = answer {
+                            if writer.write_all(format!("{}\n", answer).as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    None => {
+                        info!("Quit requested by a client");
+                        shared.quit.notify_one();
+                        break;
+                    }
+                }
+            }
+            event = events.recv() => match event {
+                Ok(Event::Line(line)) => {
+                    if writer.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Event::Disconnect) => break,
+                Err(broadcast::error::RecvError::Lagged(n)) => warn!("Client fell {} lines behind", n),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    let mut sim = shared.sim.lock().await;
+    let out = sim.client_left();
+    info!("Client disconnected ({} left)", sim.clients);
+    shared.emit(&sim, out);
+}
+
+fn parse_args() -> Options {
+    let args: Vec<String> = std::env::args().collect();
+    let mut opts = Options { socket: DEFAULT_SOCKET.to_string(), script: None, physics: Physics::default() };
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        let rate = |current: f64| value.and_then(|v| v.parse::<f64>().ok()).filter(|r| *r > 0.0).unwrap_or(current);
+        match (args[i].as_str(), value) {
+            ("--socket", Some(v)) => opts.socket = v.clone(),
+            ("--script", Some(v)) => opts.script = Some(v.clone()),
+            ("--accel", Some(_)) => opts.physics.accel_mph_per_s = rate(opts.physics.accel_mph_per_s),
+            ("--decel", Some(_)) => opts.physics.decel_mph_per_s = rate(opts.physics.decel_mph_per_s),
+            ("--incline-rate", Some(_)) => opts.physics.incline_pct_per_s = rate(opts.physics.incline_pct_per_s),
+            (other, _) => {
+                eprintln!("unknown or incomplete argument: {}", other);
+                eprintln!(
+                    "usage: treadmill-sim [--socket PATH] [--script FILE] [--accel MPH/S] [--decel MPH/S] [--incline-rate %/S]"
+                );
+                std::process::exit(2);
+            }
+        }
+        i += 2;
+    }
+    opts
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opts = parse_args();
+
+    let steps = match &opts.script {
+        Some(path) => match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_script(&text)) {
+            Ok(steps) => steps,
+            Err(e) => {
+                eprintln!("script {}: {}", path, e);
+                std::process::exit(2);
+            }
+        },
+        None => Vec::new(),
+    };
+
+    // A socket left behind by an earlier run would fail the bind
+    let _ = std::fs::remove_file(&opts.socket);
+    let listener = match UnixListener::bind(&opts.socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot listen on {}: {}", opts.socket, e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Simulating treadmill_io on {} (belt +{}/-{} mph/s, incline {}%/s)",
+        opts.socket, opts.physics.accel_mph_per_s, opts.physics.decel_mph_per_s, opts.physics.incline_pct_per_s
+    );
+
+    let shared = Arc::new(Shared {
+        sim: Mutex::new(Sim::new(opts.physics, Instant::now())),
+        events: broadcast::channel(256).0,
+        quit: Notify::new(),
+    });
+    tokio::spawn(run_physics(shared.clone()));
+    tokio::spawn(run_script(shared.clone(), steps));
+    tokio::select! {
+        _ = serve(listener, shared) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    let _ = std::fs::remove_file(&opts.socket);
+    info!("Simulator stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(line: &str) -> serde_json::Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_last_client_leaving() {
+        let t0 = Instant::now();
+        let mut sim = Sim::new(Physics::default(), t0);
+        sim.clients = 2;
+        sim.machine.command(r#"{"cmd":"speed","value":3.0}"#, t0);
+        assert!(sim.client_left().is_empty());
+        assert_eq!(sim.client_left().len(), 1);
+        assert_eq!((sim.machine.mode, sim.machine.speed_tenths), (Mode::Proxy, 0));
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let steps = parse_script(
+            "# warm-up at the console\n\
+             5 console speed 3.0\n\
+             2.5 console incline 2.5  # steps are sorted\n\
+             \n\
+             30 console stop\n\
+             40 disconnect\n\
+             50 mute 8\n\
+             60 quit\n",
+        )
+        .unwrap();
+        assert_eq!(
+            steps,
+            [
+                (Duration::from_millis(2500), Action::ConsoleIncline(5)),
+                (Duration::from_secs(5), Action::ConsoleSpeed(30)),
+                (Duration::from_secs(30), Action::ConsoleStop),
+                (Duration::from_secs(40), Action::Disconnect),
+                (Duration::from_secs(50), Action::Mute(Duration::from_secs(8))),
+                (Duration::from_secs(60), Action::Quit),
+            ]
+        );
+        assert_eq!(parse_script("soon quit").unwrap_err(), "line 1: expected seconds, got \"soon\"");
+        assert_eq!(parse_script("1 console speed\n").unwrap_err(), "line 1: unknown action \"console speed\"");
+        assert!(parse_script("1 jump").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serves_clients() {
+        let path = std::env::temp_dir().join(format!("treadmill-sim-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let shared = Arc::new(Shared {
+            sim: Mutex::new(Sim::new(Physics::default(), Instant::now())),
+            events: broadcast::channel(256).0,
+            quit: Notify::new(),
+        });
+        let server = tokio::spawn(serve(listener, shared.clone()));
+
+        // Every client sees every line, its own answers included
+        let mut clients = Vec::new();
+        for _ in 0..MAX_CLIENTS {
+            let (reader, writer) = UnixStream::connect(&path).await.unwrap().into_split();
+            clients.push((BufReader::new(reader).lines(), writer));
+        }
+        clients[0].1.write_all(b"{\"cmd\":\"incline\",\"value\":2}\n").await.unwrap();
+        for (lines, _) in clients.iter_mut() {
+            let status = json(&lines.next_line().await.unwrap().unwrap());
+            assert_eq!((status["type"].as_str(), status["emu_incline"].as_u64()), (Some("status"), Some(4)));
+        }
+
+        // A fifth is turned away
+        let extra = UnixStream::connect(&path).await.unwrap();
+        let line = BufReader::new(extra).lines().next_line().await.unwrap().unwrap();
+        assert_eq!(json(&line)["msg"], "too many clients");
+
+        // Scripted disconnects close them all; quit stops the server
+        let _ = shared.events.send(Event::Disconnect);
+        for (lines, _) in clients.iter_mut() {
+            while lines.next_line().await.unwrap().is_some() {}
+        }
+        run_script(shared.clone(), vec![(Duration::ZERO, Action::Quit)]).await;
+        server.await.unwrap();
+        assert_eq!(shared.sim.lock().await.machine.mode, Mode::Proxy);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,132 @@
+//! Per-treadmill speed correction.
+//!
+//! A belt rarely runs at exactly the speed the console thinks it does. The
+//! correction factor is the actual belt speed over what treadmill_io
+//! reports (1.03 = the belt runs 3% fast). Every speed we hand out — BLE,
+//! gRPC, exports, distance — is scaled by it, while speeds sent to
+//! treadmill_io are scaled back, so an app asking for 9.0 mph gets a belt
+//! really doing 9.0 mph.
+//!
+//! The factor comes from `speed_correction` in the config, or from
+//! `calibration_file` once the debug `calibrate` command has measured one
+//! (that file wins, so a calibration survives restarts without editing the
+//! config).
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::FtmsConfig;
+
+/// Factors outside this range are a typo or a bad measurement, not a belt.
+pub const MIN_FACTOR: f64 = 0.8;
+pub const MAX_FACTOR: f64 = 1.2;
+
+/// A saved calibration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Actual belt speed over treadmill_io's.
+    pub factor: f64,
+    /// Unix epoch ms when it was measured.
+    pub wall_ms: u64,
+}
+
+/// `factor` if it's one to apply: within range and not 1.0.
+pub fn usable(factor: f64) -> Option<f64> {
+    if !(MIN_FACTOR..=MAX_FACTOR).contains(&factor) {
+        warn!("Ignoring speed correction {} (must be {}-{})", factor, MIN_FACTOR, MAX_FACTOR);
+        return None;
+    }
+    (factor != 1.0).then_some(factor)
+}
+
+/// The factor to start with: a saved calibration, else the configured one.
+pub fn initial(config: &FtmsConfig) -> Option<f64> {
+    if let Some(cal) = config.calibration_file.as_deref().and_then(load) {
+        info!("Speed correction x{:.3} from calibration", cal.factor);
+        return usable(cal.factor);
+    }
+    usable(config.speed_correction)
+}
+
+/// A treadmill_io speed in hundredths of mph, as the belt really runs.
+pub fn correct(mph_hundredths: u32, factor: Option<f64>) -> u32 {
+    match factor {
+        Some(f) => (mph_hundredths as f64 * f).round() as u32,
+        None => mph_hundredths,
+    }
+}
+
+/// The treadmill_io speed (tenths of mph) that makes the belt really run
+/// at `mph_tenths`.
+pub fn uncorrect_tenths(mph_tenths: u16, factor: Option<f64>) -> u16 {
+    match factor {
+        Some(f) => (mph_tenths as f64 / f).round() as u16,
+        None => mph_tenths,
+    }
+}
+
+/// The factor for a belt measured at `measured_mph` while treadmill_io
+/// reports `reported_tenths`. None while the belt is stopped.
+pub fn measure(measured_mph: f64, reported_tenths: u16) -> Option<f64> {
+    if reported_tenths == 0 {
+        return None;
+    }
+    Some(measured_mph * 10.0 / reported_tenths as f64)
+}
+
+/// Write `cal` to `path` atomically.
+pub async fn save(path: &str, cal: &Calibration) -> std::io::Result<()> {
+    let json = serde_json::to_string(cal)?;
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+/// Read the calibration at `path`. A missing or unreadable file means the
+/// treadmill hasn't been calibrated.
+pub fn load(path: &str) -> Option<Calibration> {
+    let data = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&data) {
+        Ok(cal) => Some(cal),
+        Err(e) => {
+            warn!("Ignoring invalid calibration {}: {}", path, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correct_roundtrip() {
+        assert_eq!(correct(900, None), 900);
+        assert_eq!(correct(900, Some(1.03)), 927);
+        assert_eq!(uncorrect_tenths(90, Some(1.03)), 87);
+        assert_eq!(correct(870, Some(1.03)), 896, "nearest the treadmill can do");
+        assert_eq!(uncorrect_tenths(90, None), 90);
+    }
+
+    #[test]
+    fn test_usable_and_measure() {
+        assert_eq!(usable(1.0), None);
+        assert_eq!(usable(1.5), None);
+        assert_eq!(usable(0.97), Some(0.97));
+        assert_eq!(measure(6.18, 60), Some(1.03));
+        assert_eq!(measure(6.0, 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_save_load_roundtrip() {
+        let path = "/tmp/ftms_test_calibration.json";
+        let cal = Calibration { factor: 1.03, wall_ms: 1_000_000 };
+        save(path, &cal).await.unwrap();
+        assert_eq!(load(path), Some(cal));
+
+        std::fs::write(path, "not json").unwrap();
+        assert_eq!(load(path), None);
+        let _ = std::fs::remove_file(path);
+        assert_eq!(load(path), None);
+    }
+}
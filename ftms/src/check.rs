@@ -0,0 +1,142 @@
+//! `ftms-daemon --check`: one-shot health probe for cron, systemd and
+//! shell scripts.
+//!
+//! Connects to the running daemon's debug port on localhost (over TLS,
+//! trusting `debug_tls`'s certificate, when that's set), asks for
+//! `state json` and prints one line, e.g.
+//!
+//!   ok: treadmill_io connected (protocol v2), 3.5 mph, 2.0%, 12:34, 1.20 mi
+//!
+//! Exit status: 0 when treadmill_io is connected, 1 when the daemon is up
+//! but treadmill_io isn't, 2 when the daemon can't be reached or doesn't
+//! answer in time.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::config::{FtmsConfig, Units};
+use crate::tls;
+
+/// How long the whole exchange may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The debug port's `state json` reply.
+#[derive(Debug, Deserialize)]
+struct State {
+    speed_mph: f64,
+    incline_pct: f64,
+    elapsed_secs: u32,
+    distance_meters: u32,
+    connected: bool,
+    protocol_version: Option<u32>,
+    console_paused: bool,
+}
+
+/// Probe the daemon on `port`, print the summary, and return the exit status.
+pub async fn run(config: &FtmsConfig, port: u16) -> i32 {
+    match tokio::time::timeout(TIMEOUT, query(config, port)).await {
+        Ok(Ok(state)) => {
+            println!("{}", summary(&state, config.units));
+            if state.connected {
+                0
+            } else {
+                1
+            }
+        }
+        Ok(Err(e)) => {
+            println!("unreachable: debug port {}: {}", port, e);
+            2
+        }
+        Err(_) => {
+            println!("unreachable: debug port {}: no answer in {}s", port, TIMEOUT.as_secs());
+            2
+        }
+    }
+}
+
+async fn query(config: &FtmsConfig, port: u16) -> Result<State, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    match &config.debug_tls {
+        Some(files) => {
+            let name = ServerName::try_from("localhost")?;
+            let stream = tls::connector(files)?.connect(name, stream).await?;
+            exchange(stream).await
+        }
+        None => exchange(stream).await,
+    }
+}
+
+/// Ask for `state json` and pick the JSON line out of the replies.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(stream: S) -> Result<State, Box<dyn std::error::Error + Send + Sync>> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(b"mode raw\nstate json\nquit\n").await?;
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.starts_with('{') {
+            return Ok(serde_json::from_str(&line)?);
+        }
+        if line.starts_with("too many debug connections") {
+            return Err(line.into());
+        }
+    }
+    Err("closed without a state".into())
+}
+
+fn summary(s: &State, units: Units) -> String {
+    let link = match (s.connected, s.protocol_version) {
+        (true, Some(v)) => format!("ok: treadmill_io connected (protocol v{})", v),
+        (true, None) => "ok: treadmill_io connected".to_string(),
+        (false, _) => "degraded: treadmill_io disconnected".to_string(),
+    };
+    format!(
+        "{}, {}, {:.1}%, {}:{:02}, {}{}",
+        link,
+        units.speed((s.speed_mph * 100.0).round() as u32),
+        s.incline_pct,
+        s.elapsed_secs / 60,
+        s.elapsed_secs % 60,
+        units.distance(s.distance_meters),
+        if s.console_paused { ", paused at console" } else { "" },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exchange_and_summary() {
+        // What the debug port sends in raw mode, after the banner
+        let (client, mut server) = tokio::io::duplex(4096);
+        server
+            .write_all(
+                b"ftms-debug> connected. type 'help' for commands.\n\
+                  mode: raw\n\n\
+                  {\"speed_mph\":3.5,\"incline_pct\":2.0,\"elapsed_secs\":754,\"distance_meters\":1931,\
+                  \"connected\":true,\"protocol_version\":2,\"console_paused\":false,\"ramp\":null}\n\n",
+            )
+            .await
+            .unwrap();
+        let state = exchange(client).await.unwrap();
+        assert_eq!(
+            summary(&state, Units::Imperial),
+            "ok: treadmill_io connected (protocol v2), 3.5 mph, 2.0%, 12:34, 1.20 mi"
+        );
+
+        let down = State { connected: false, protocol_version: None, console_paused: true, ..state };
+        assert_eq!(
+            summary(&down, Units::Metric),
+            "degraded: treadmill_io disconnected, 5.6 km/h, 2.0%, 12:34, 1.93 km, paused at console"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // Port 1 on localhost: nothing listens there
+        assert_eq!(run(&FtmsConfig::default(), 1).await, 2);
+    }
+}
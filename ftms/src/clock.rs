@@ -0,0 +1,66 @@
+//! Wall-clock time zone handling.
+//!
+//! Workouts are timed on the monotonic clock and stamped in UTC; the UTC
+//! offset of the configured `timezone` (an IANA name such as
+//! `America/Los_Angeles`, or the system zone from `/etc/localtime` when
+//! unset) is looked up when a workout starts so exports and summaries can
+//! show local times.
+
+use log::warn;
+use tz::TimeZone;
+
+/// UTC offset in seconds at `wall_ms` in `timezone` (None = system zone).
+/// Unknown zones fall back to UTC.
+pub fn utc_offset_secs(timezone: Option<&str>, wall_ms: u64) -> i32 {
+    let zone = match timezone {
+        Some(name) => TimeZone::from_posix_tz(name),
+        None => TimeZone::local(),
+    };
+    let offset = zone.map_err(|e| e.to_string()).and_then(|zone| {
+        zone.find_local_time_type((wall_ms / 1000) as i64)
+            .map(|t| t.ut_offset())
+            .map_err(|e| e.to_string())
+    });
+    match offset {
+        Ok(offset) => offset,
+        Err(e) => {
+            warn!("Can't resolve time zone {}: {}; using UTC", timezone.unwrap_or("(system)"), e);
+            0
+        }
+    }
+}
+
+/// `+HH:MM` / `-HH:MM` for an offset in seconds.
+pub fn format_offset(offset_secs: i32) -> String {
+    let sign = if offset_secs < 0 { '-' } else { '+' };
+    let abs = offset_secs.unsigned_abs();
+    format!("{}{:02}:{:02}", sign, abs / 3600, abs % 3600 / 60)
+}
+
+/// Shift a UTC epoch-ms time into local "epoch" ms for calendar math.
+pub fn local_ms(wall_ms: u64, offset_secs: i32) -> u64 {
+    wall_ms.saturating_add_signed(offset_secs as i64 * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_zone_offsets() {
+        // 2023-11-14 is standard time, 2024-07-01 daylight time
+        assert_eq!(utc_offset_secs(Some("America/New_York"), 1_700_000_000_000), -5 * 3600);
+        assert_eq!(utc_offset_secs(Some("America/New_York"), 1_719_792_000_000), -4 * 3600);
+        assert_eq!(utc_offset_secs(Some("Asia/Kolkata"), 0), 5 * 3600 + 1800);
+        assert_eq!(utc_offset_secs(Some("UTC"), 0), 0);
+        assert_eq!(utc_offset_secs(Some("Not/AZone"), 0), 0);
+    }
+
+    #[test]
+    fn test_format_offset() {
+        assert_eq!(format_offset(0), "+00:00");
+        assert_eq!(format_offset(-5 * 3600), "-05:00");
+        assert_eq!(format_offset(5 * 3600 + 1800), "+05:30");
+        assert_eq!(local_ms(10_000, -5), 5_000);
+    }
+}
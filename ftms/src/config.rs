@@ -0,0 +1,446 @@
+//! FTMS daemon configuration.
+//!
+//! Reads `ftms_config.json` at startup. Every field has a default, so a
+//! missing file or a file with only a few keys behaves like the built-in
+//! settings.
+
+use std::collections::BTreeMap;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::advertising::NamePlacement;
+use crate::archive::ArchiveTarget;
+use crate::export::{ExportFormat, Sport};
+use crate::fit::FitDevice;
+use crate::notify::NotifyTarget;
+use crate::profile::SpeedProfile;
+use crate::quiet::QuietHours;
+use crate::tls::TlsFiles;
+use crate::protocol::{self, Capabilities, METERS_PER_FOOT, METERS_PER_MILE};
+
+/// Daemon tunables loaded from disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FtmsConfig {
+    /// How long to wait for treadmill_io status to reflect a commanded
+    /// speed/incline before re-sending it.
+    pub target_verify_timeout_ms: u64,
+    /// Re-sends attempted after the first command before giving up and
+    /// emitting a target failure event.
+    pub target_retries: u32,
+    /// After treadmill_io reconnects, re-send the last commanded speed/incline
+    /// instead of telling clients their targets were lost. Off by default so a
+    /// restarted treadmill_io never starts the belt without the app asking.
+    pub reapply_targets_on_reconnect: bool,
+    /// How long to wait for the first status dump after a reconnect before
+    /// deciding which targets were lost.
+    pub reconnect_settle_ms: u64,
+    /// Drop and reconnect the treadmill_io socket when no message has arrived
+    /// for this long, even though the socket is still open. 0 disables.
+    pub status_timeout_ms: u64,
+    /// The incline motor counts as moving until its position has held for
+    /// this long. Incline targets arriving meanwhile are queued, latest
+    /// wins, and sent once it settles. 0 sends them straight away.
+    pub incline_settle_ms: u64,
+    /// Append every state change and control command to this JSONL file.
+    /// Unset (the default) disables recording.
+    pub telemetry_log: Option<String>,
+    /// Treadmill Data notification period. 1000 (1 Hz) by default; 500 and
+    /// 250 give apps 2 Hz / 4 Hz updates with interpolated speed.
+    pub treadmill_data_interval_ms: u64,
+    /// Fastest the reported speed may ramp toward a new treadmill_io value,
+    /// in mph per second. Smooths ~1 Hz status steps for every consumer.
+    /// 0 reports status values as-is.
+    pub speed_smoothing_mph_per_s: f64,
+    /// Which speed distance is integrated from: `smoothed` (the default,
+    /// what BLE apps are shown) or `reported` (treadmill_io's status values
+    /// as they arrive). The other is tracked alongside for comparison, in
+    /// debug `state` and the log at the end of each workout.
+    pub distance_source: DistanceSource,
+    /// Actual belt speed over what treadmill_io reports, e.g. 1.03 for a
+    /// belt that runs 3% fast. Applied to every speed we report; targets
+    /// are scaled back before they go to treadmill_io. See `calibration.rs`.
+    pub speed_correction: f64,
+    /// Where the debug `calibrate` command saves a measured correction,
+    /// which then takes the place of `speed_correction`. Unset (the
+    /// default) keeps calibrations until restart only.
+    pub calibration_file: Option<String>,
+    /// BLE local name apps see in their scanners.
+    pub advertised_name: String,
+    /// Put the name in the advertisement, the scan response, or `auto`
+    /// (advertisement when it fits in 31 bytes).
+    pub name_placement: NamePlacement,
+    /// Advertise with BLE 5 extended advertising on the 2M PHY (1M if the
+    /// adapter lacks 2M). Falls back to legacy advertising on adapters
+    /// without extended advertising support.
+    pub extended_advertising: bool,
+    /// Advertise from a rotating resolvable private address. BlueZ does the
+    /// rotation (needs `Privacy = device` in main.conf); we keep the adapter
+    /// pairable so clients can bond and resolve our identity.
+    pub privacy: bool,
+    /// Publish how many apps are connected here so hrm-daemon can back off
+    /// BLE discovery while they are. Unset disables. See `activity.rs`.
+    pub activity_file: Option<String>,
+    /// hrm-daemon's socket, to put its heart rate in Treadmill Data and
+    /// workouts. Unset disables. See `heart_rate.rs`.
+    pub hrm_socket: Option<String>,
+    /// Units for human-readable output (debug console, logs). BLE data is
+    /// always metric per the FTMS spec.
+    pub units: Units,
+    /// Checkpoint the workout counters and targets to this file so a
+    /// restart mid-workout resumes them. Unset (the default) disables.
+    pub session_checkpoint: Option<String>,
+    /// Only resume a checkpoint written within this many seconds; older
+    /// ones are a finished workout.
+    pub session_resume_max_age_secs: u64,
+    /// A workout starts once the belt goes faster than this (mph), app or
+    /// no app, so console-only runs are recorded too. 0 (the default)
+    /// starts on any movement; a little more keeps a belt nudged for a
+    /// moment out of the history.
+    pub workout_start_mph: f64,
+    /// A workout ends once the belt has been stopped this long: counters
+    /// reset and the workout is exported. 0 never ends it.
+    pub workout_end_idle_secs: u64,
+    /// Reach the first speed target after a Start gradually over this many
+    /// seconds. 0 (the default) goes straight there.
+    pub warmup_secs: u64,
+    /// Step the belt down to a stop over this many seconds on Stop. 0 (the
+    /// default) stops at once. A second Stop while cooling down, or a
+    /// Pause, always stops at once.
+    pub cooldown_secs: u64,
+    /// IANA time zone (e.g. `America/Los_Angeles`) for local times in
+    /// exports, file names and summaries. Unset uses the system zone.
+    pub timezone: Option<String>,
+    /// Write finished workouts here. Unset (the default) disables export.
+    pub export_dir: Option<String>,
+    /// File formats written for each workout: `fit`, `health_connect`,
+    /// `apple_health`, `csv`.
+    pub export_formats: Vec<ExportFormat>,
+    /// Exported file name before the format's extension. `{date}`
+    /// (YYYYMMDD), `{time}` (HHMMSS), `{year}`, `{month}`, `{day}`,
+    /// `{profile}`, `{label}` and `{distance}` are filled in from the
+    /// workout; `/` makes subdirectories of `export_dir`.
+    pub export_name: String,
+    /// Activity name written into exports, with the same placeholders.
+    /// Empty after filling in (the default with no label) leaves each
+    /// format's own default.
+    pub export_title: String,
+    /// Sport written into exports: `running` (the default) or `walking`.
+    pub export_sport: Sport,
+    /// Recording device name written into exports, with the same
+    /// placeholders. Unset keeps each format's default.
+    pub export_device: Option<String>,
+    /// Copy each exported file to these destinations (network share,
+    /// rsync, sftp, WebDAV, S3). See `archive.rs`.
+    pub archive: Vec<ArchiveTarget>,
+    /// Push a summary of each finished workout to these providers
+    /// (Pushover, Telegram, ntfy). See `notify.rs`.
+    pub notify: Vec<NotifyTarget>,
+    /// Device IDs written into FIT files.
+    pub fit_device: FitDevice,
+    /// Runner's weight, for calorie and power estimates in exports. Unset
+    /// leaves them out.
+    pub body_weight_kg: Option<f64>,
+    /// Runner's maximum HR, for the HR zones behind TRIMP training load.
+    /// Unset leaves TRIMP out.
+    pub max_heart_rate: Option<u16>,
+    /// Append a summary of each finished workout to this JSONL file, for
+    /// training load. Unset (the default) disables. See `history.rs`.
+    pub history_file: Option<String>,
+    /// Named speed/incline caps, e.g. a guest profile limited to 6 mph.
+    /// See `profile.rs`.
+    pub profiles: BTreeMap<String, SpeedProfile>,
+    /// Profile active at startup. Unset applies no caps beyond the
+    /// built-in safety max.
+    pub profile: Option<String>,
+    /// PIN the debug `profile` command needs to switch profiles. Unset
+    /// disables switching at runtime.
+    pub profile_pin: Option<String>,
+    /// Local time windows that refuse Start or cap the speed, e.g. late
+    /// evenings over a sleeping household. See `quiet.rs`.
+    pub quiet_hours: Vec<QuietHours>,
+    /// Append every control command, with who sent it, to this JSONL file.
+    /// Unset keeps them in memory only. See `audit.rs`.
+    pub audit_log: Option<String>,
+    /// Certificate and key for a TLS-only debug port. See `tls.rs`.
+    pub debug_tls: Option<TlsFiles>,
+    /// Debug connections allowed at once. 0 is unlimited. See `throttle.rs`.
+    pub debug_max_connections: usize,
+    /// Commands per second each debug connection may send before it's
+    /// slowed down. 0 is unlimited.
+    pub debug_commands_per_sec: u32,
+    /// Close debug connections that send nothing for this many seconds,
+    /// unless subscribed. 0 keeps them open.
+    pub debug_idle_timeout_secs: u64,
+    /// Serve the gRPC API on this address, e.g. `0.0.0.0:8836`. Unset (the
+    /// default) disables. See `grpc.rs`.
+    pub grpc_addr: Option<String>,
+    /// gRPC connections allowed at once. 0 is unlimited. See `throttle.rs`.
+    pub grpc_max_connections: usize,
+}
+
+/// Speed that distance is integrated from. treadmill_io reports no
+/// odometer and there's no footpod client, so both are dead reckoning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceSource {
+    /// The smoothed speed BLE apps see, so their distance and ours agree.
+    #[default]
+    Smoothed,
+    /// treadmill_io's status speed, held until the next status.
+    Reported,
+}
+
+impl DistanceSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            DistanceSource::Smoothed => "smoothed",
+            DistanceSource::Reported => "reported",
+        }
+    }
+
+    /// The source not in use.
+    pub fn other(self) -> Self {
+        match self {
+            DistanceSource::Smoothed => DistanceSource::Reported,
+            DistanceSource::Reported => DistanceSource::Smoothed,
+        }
+    }
+}
+
+/// Display unit system.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    /// mph and miles.
+    #[default]
+    Imperial,
+    /// km/h and kilometers.
+    Metric,
+}
+
+impl Units {
+    /// A speed given in hundredths of mph, in this unit system.
+    pub fn speed_value(self, mph_hundredths: u32) -> f64 {
+        match self {
+            Units::Imperial => mph_hundredths as f64 / 100.0,
+            Units::Metric => protocol::mph_hundredths_to_kmh_hundredths(mph_hundredths) as f64 / 100.0,
+        }
+    }
+
+    pub fn speed_unit(self) -> &'static str {
+        match self {
+            Units::Imperial => "mph",
+            Units::Metric => "km/h",
+        }
+    }
+
+    /// Format a speed given in hundredths of mph, e.g. "3.5 mph" / "5.6 km/h".
+    pub fn speed(self, mph_hundredths: u32) -> String {
+        format!("{:.1} {}", self.speed_value(mph_hundredths), self.speed_unit())
+    }
+
+    /// Format a speed given in treadmill-native tenths of mph.
+    pub fn speed_tenths(self, mph_tenths: u16) -> String {
+        self.speed(mph_tenths as u32 * 10)
+    }
+
+    /// Format a distance given in meters, e.g. "1.25 mi" / "2.01 km".
+    pub fn distance(self, meters: u32) -> String {
+        match self {
+            Units::Imperial => format!("{:.2} mi", meters as f64 / METERS_PER_MILE),
+            Units::Metric => format!("{:.2} km", meters as f64 / 1000.0),
+        }
+    }
+
+    /// Format a climb given in meters, e.g. "111 ft" / "34 m".
+    pub fn climb(self, meters: f64) -> String {
+        match self {
+            Units::Imperial => format!("{:.0} ft", meters / METERS_PER_FOOT),
+            Units::Metric => format!("{:.0} m", meters),
+        }
+    }
+}
+
+impl Default for FtmsConfig {
+    fn default() -> Self {
+        Self {
+            target_verify_timeout_ms: 3000,
+            target_retries: 2,
+            reapply_targets_on_reconnect: false,
+            reconnect_settle_ms: 1500,
+            status_timeout_ms: 5000,
+            incline_settle_ms: 2000,
+            telemetry_log: None,
+            treadmill_data_interval_ms: 1000,
+            speed_smoothing_mph_per_s: 1.0,
+            distance_source: DistanceSource::Smoothed,
+            speed_correction: 1.0,
+            calibration_file: None,
+            advertised_name: "Precor 9.31".to_string(),
+            name_placement: NamePlacement::Auto,
+            extended_advertising: false,
+            privacy: false,
+            activity_file: Some("/tmp/ftms_activity.json".to_string()),
+            hrm_socket: Some("/tmp/hrm.sock".to_string()),
+            units: Units::Imperial,
+            session_checkpoint: None,
+            session_resume_max_age_secs: 300,
+            workout_start_mph: 0.0,
+            workout_end_idle_secs: 300,
+            warmup_secs: 0,
+            cooldown_secs: 0,
+            timezone: None,
+            export_dir: None,
+            export_formats: vec![ExportFormat::Fit],
+            export_name: "treadmill-{date}-{time}".to_string(),
+            export_title: "{label}".to_string(),
+            export_sport: Sport::Running,
+            export_device: None,
+            archive: Vec::new(),
+            notify: Vec::new(),
+            fit_device: FitDevice::default(),
+            body_weight_kg: None,
+            max_heart_rate: None,
+            history_file: None,
+            profiles: BTreeMap::new(),
+            profile: None,
+            profile_pin: None,
+            quiet_hours: Vec::new(),
+            audit_log: None,
+            debug_tls: None,
+            debug_max_connections: 8,
+            debug_commands_per_sec: 20,
+            debug_idle_timeout_secs: 600,
+            grpc_addr: None,
+            grpc_max_connections: 8,
+        }
+    }
+}
+
+/// Fastest Treadmill Data notification rate we allow (4 Hz).
+pub const MIN_NOTIFY_INTERVAL_MS: u64 = 250;
+
+impl FtmsConfig {
+    /// What the Feature characteristic advertises: heart rate only while
+    /// an hrm-daemon socket is configured to bridge it in.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities { heart_rate: self.hrm_socket.is_some() }
+    }
+
+    /// Speed smoothing rate in the state's units (hundredths of mph per second).
+    pub fn speed_slew_per_s(&self) -> u32 {
+        (self.speed_smoothing_mph_per_s.max(0.0) * 100.0).round() as u32
+    }
+
+    /// `workout_start_mph` in treadmill-native tenths of mph.
+    pub fn workout_start_tenths_mph(&self) -> u16 {
+        (self.workout_start_mph.max(0.0) * 10.0).round() as u16
+    }
+
+    /// Treadmill Data notification period, clamped to 250 ms..=1 s.
+    pub fn treadmill_data_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(
+            self.treadmill_data_interval_ms.clamp(MIN_NOTIFY_INTERVAL_MS, 1000),
+        )
+    }
+}
+
+/// Load config from disk. Falls back to defaults if the file is missing or invalid.
+pub fn load(path: &str) -> FtmsConfig {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(_) => {
+            info!("No config at {}, using defaults", path);
+            return FtmsConfig::default();
+        }
+    };
+    match serde_json::from_str::<FtmsConfig>(&data) {
+        Ok(cfg) => {
+            let mut shown = cfg.clone();
+            shown.archive = shown.archive.iter().map(ArchiveTarget::redacted).collect();
+            shown.notify = shown.notify.iter().map(NotifyTarget::redacted).collect();
+            if shown.profile_pin.is_some() {
+                shown.profile_pin = Some("<redacted>".to_string());
+            }
+            info!("Loaded config from {}: {:?}", path, shown);
+            cfg
+        }
+        Err(e) => {
+            warn!("Failed to parse config {}: {}, using defaults", path, e);
+            FtmsConfig::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units_formatting() {
+        assert_eq!(Units::default(), Units::Imperial);
+        assert_eq!(Units::Imperial.speed(350), "3.5 mph");
+        assert_eq!(Units::Metric.speed(350), "5.6 km/h");
+        assert_eq!(Units::Metric.speed_tenths(10), "1.6 km/h");
+        assert_eq!(Units::Imperial.distance(1609), "1.00 mi");
+        assert_eq!(Units::Metric.distance(1609), "1.61 km");
+        assert_eq!(Units::Imperial.climb(33.8), "111 ft");
+        assert_eq!(Units::Metric.climb(33.8), "34 m");
+
+        let cfg: FtmsConfig = serde_json::from_str(r#"{"units": "metric"}"#).unwrap();
+        assert_eq!(cfg.units, Units::Metric);
+        assert_eq!(cfg.distance_source, DistanceSource::Smoothed);
+        let cfg: FtmsConfig = serde_json::from_str(r#"{"distance_source": "reported"}"#).unwrap();
+        assert_eq!((cfg.distance_source.name(), cfg.distance_source.other()), ("reported", DistanceSource::Smoothed));
+    }
+
+    #[test]
+    fn test_load_missing_uses_defaults() {
+        let cfg = load("/tmp/ftms_nonexistent_config.json");
+        assert_eq!(cfg.target_verify_timeout_ms, 3000);
+        assert_eq!(cfg.target_retries, 2);
+    }
+
+    #[test]
+    fn test_load_partial_keeps_other_defaults() {
+        let path = "/tmp/ftms_partial_config.json";
+        std::fs::write(path, r#"{"target_retries": 5}"#).unwrap();
+        let cfg = load(path);
+        assert_eq!(cfg.target_retries, 5);
+        assert_eq!(cfg.target_verify_timeout_ms, 3000);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_treadmill_data_interval_clamped() {
+        let mut cfg = FtmsConfig::default();
+        assert_eq!(cfg.treadmill_data_interval().as_millis(), 1000);
+        cfg.treadmill_data_interval_ms = 250;
+        assert_eq!(cfg.treadmill_data_interval().as_millis(), 250);
+        cfg.treadmill_data_interval_ms = 10;
+        assert_eq!(cfg.treadmill_data_interval().as_millis(), 250);
+        cfg.treadmill_data_interval_ms = 5000;
+        assert_eq!(cfg.treadmill_data_interval().as_millis(), 1000);
+    }
+
+    #[test]
+    fn test_workout_start_threshold() {
+        let mut cfg = FtmsConfig::default();
+        assert_eq!(cfg.workout_start_tenths_mph(), 0);
+        cfg.workout_start_mph = 1.04;
+        assert_eq!(cfg.workout_start_tenths_mph(), 10);
+        cfg.workout_start_mph = -2.0;
+        assert_eq!(cfg.workout_start_tenths_mph(), 0);
+    }
+
+    #[test]
+    fn test_load_invalid_uses_defaults() {
+        let path = "/tmp/ftms_invalid_config.json";
+        std::fs::write(path, "not json").unwrap();
+        let cfg = load(path);
+        assert_eq!(cfg.target_retries, 2);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,53 @@
+//! Per-second CSV export.
+//!
+//! One row per recorded sample (timestamps in local time with their UTC
+//! offset), for spreadsheets and custom analysis scripts. Heart rate is blank while no strap is connected, and power is
+//! blank unless `body_weight_kg` is set.
+
+use std::fmt::Write;
+
+use crate::export::iso8601_local;
+use crate::workout::Workout;
+
+const HEADER: &str = "timestamp,elapsed_s,speed_mph,speed_kmh,incline_pct,distance_m,heart_rate_bpm,power_w\n";
+
+/// Encode `workout` as CSV.
+pub fn encode(workout: &Workout, weight_kg: Option<f64>) -> Vec<u8> {
+    let mut out = String::from(HEADER);
+    for s in &workout.samples {
+        let _ = writeln!(
+            out,
+            "{},{},{:.1},{:.2},{:.1},{:.1},{},{}",
+            iso8601_local(s.wall_ms, workout.utc_offset_secs),
+            s.elapsed_secs,
+            s.speed_tenths_mph as f64 / 10.0,
+            s.speed_mps() * 3.6,
+            s.grade_pct(),
+            s.distance_m,
+            s.heart_rate.map(|hr| hr.to_string()).unwrap_or_default(),
+            weight_kg.map(|kg| format!("{:.0}", s.power_watts(kg))).unwrap_or_default(),
+        );
+    }
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    #[test]
+    fn test_encode_rows() {
+        let w = sample_workout();
+        let csv = String::from_utf8(encode(&w, Some(70.0))).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 661);
+        assert_eq!(lines[0], HEADER.trim_end());
+        assert_eq!(lines[1], "2023-11-14T17:13:21-05:00,1,6.0,9.66,2.0,2.7,120,232");
+        // Cool-down: no HR
+        assert_eq!(lines[660], "2023-11-14T17:24:20-05:00,660,3.0,4.83,2.0,1689.8,,116");
+
+        let csv = String::from_utf8(encode(&w, None)).unwrap();
+        assert!(csv.lines().nth(1).unwrap().ends_with(",120,"));
+    }
+}
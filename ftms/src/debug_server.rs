@@ -8,82 +8,218 @@
 //!   nc rpi 8826
 //!
 //! Commands:
-//!   state           → human-readable treadmill state
+//!   state [json]    → human-readable treadmill state, or one JSON line
 //!   td              → treadmill data (0x2ACD) as hex
 //!   feat            → feature (0x2ACC) as hex
 //!   sr              → speed range (0x2AD4) as hex
 //!   ir              → incline range (0x2AD5) as hex
 //!   cp <hex>        → write to control point (0x2AD9), returns response hex
-//!   sub             → subscribe to 1 Hz treadmill data stream (hex lines)
+//!                     (and the Machine Status hex, with the applied target)
+//!   gatt            → registered service/characteristics: handles, properties,
+//!                     subscribers
+//!   clients         → per-subscriber notification counts (sent, delivered,
+//!                     failed, skipped) and sessions dropped
+//!   history [n]     → last n control commands, who sent them, and the result
+//!   sub [hz]        → subscribe to treadmill data stream at 1/2/4 Hz (hex lines + events)
+//!   sub <topic>[:hz]... → only the given topics, each at its own rate: state,
+//!                     telemetry, control-events, session-events
+//!   replay <file>   → play a telemetry log back into the state (no treadmill_io)
+//!   sync            → retry failed archive pushes of exported workouts
+//!   latency         → command → treadmill_io → status latency percentiles
+//!   metrics         → the same histograms, plus actual vs target speed/incline
+//!                     and notification counters, in Prometheus text format
+//!   stats           → uptime, per-task last activity, counters
+//!   load            → 7/28-day TRIMP training load + recent workouts
+//!   label [text|-]  → show/set/clear the current workout's label
+//!   step [text|-]   → show/set/clear the interval program's step line
+//!   calibrate [mph|-] → show the speed correction, or measure it from the
+//!                     belt's actual speed; '-' goes back to the config's
+//!   pause [secs]    → stop the belt, keeping the workout; resume by itself
+//!                     after secs (3-2-1 countdown) if given
+//!   resume          → ramp back to the speed paused at
+//!   relabel <n> [text] → relabel the nth most recent workout in the history
+//!   profile [name pin] → show the speed-limit profile, or switch with the PIN
+//!   quiet           → configured quiet hours and the one in effect
+//!   totals [week|month] [n] [json]
+//!                   → per-week/month sessions, time, distance, climb, kcal
+//!   records [json]  → personal records: fastest 1k and mile, longest run
+//!   mode [plain|raw|edit] → input mode: raw drops the prompt (rlwrap,
+//!                     scripts), edit does telnet line editing + history
 //!   help            → list commands
 
+use std::io::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use log::info;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use log::{info, warn};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
+use tokio_rustls::TlsAcceptor;
 
+use crate::audit::Origin;
+use crate::calibration::{self, Calibration};
+use crate::config::{DistanceSource, Units};
+use crate::console::{Console, Mode};
+use crate::export;
+use crate::ftms_service::ControlContext;
+use crate::history::{self, Period, TrainingLoad};
+use crate::pause;
 use crate::protocol;
-use crate::treadmill::TreadmillState;
+use crate::quiet;
+use crate::ramp::RampKind;
+use crate::replay;
+use crate::throttle::Limits;
+use crate::treadmill::{Target, TreadmillEvent, TreadmillState};
 
-/// Run the TCP debug server.
-pub async fn run(
-    state: Arc<Mutex<TreadmillState>>,
-    socket_path: String,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Bind the debug port. Done before `run` so privileges can be dropped
+/// in between.
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
     let listener = TcpListener::bind(("0.0.0.0", port)).await?;
     info!("Debug server listening on port {}", port);
+    Ok(listener)
+}
 
+/// Run the TCP debug server, over TLS when `tls` is set.
+pub async fn run(
+    ctx: ControlContext,
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let limits = Limits::new(
+        ctx.config.debug_max_connections,
+        ctx.config.debug_commands_per_sec,
+        std::time::Duration::from_secs(ctx.config.debug_idle_timeout_secs),
+    );
     loop {
         let (stream, addr) = listener.accept().await?;
+        let Some(slot) = limits.open() else {
+            warn!("Debug client {} refused: {} connections already open", addr, limits.max_connections());
+            // A fresh socket's send buffer is empty, so this can't block
+            if let (None, Ok(mut stream)) = (&tls, stream.into_std()) {
+                let _ = stream.write_all(b"too many debug connections, try again later\n");
+            }
+            continue;
+        };
         info!("Debug client connected from {}", addr);
 
-        let state = state.clone();
-        let socket_path = socket_path.clone();
+        let ctx = ctx.clone();
+        let tls = tls.clone();
+        let limits = limits.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, state, socket_path).await {
+            let _slot = slot;
+            let result = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => handle_client(stream, addr, ctx, limits).await,
+                    Err(e) => Err(format!("TLS handshake failed: {}", e).into()),
+                },
+                None => handle_client(stream, addr, ctx, limits).await,
+            };
+            if let Err(e) = result {
                 info!("Debug client {} disconnected: {}", addr, e);
             }
         });
     }
 }
 
-async fn handle_client(
-    stream: tokio::net::TcpStream,
-    state: Arc<Mutex<TreadmillState>>,
-    socket_path: String,
+async fn handle_client<S: AsyncRead + AsyncWrite + Send>(
+    stream: S,
+    peer: SocketAddr,
+    ctx: ControlContext,
+    limits: Limits,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (reader, mut writer) = stream.into_split();
-    let mut lines = BufReader::new(reader).lines();
+    let state = &ctx.state;
+    let origin = Origin::Debug(peer.to_string());
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut console = Console::new(reader, "ftms-debug> ");
+    let mut rate = limits.command_rate();
 
     writer
         .write_all(b"ftms-debug> connected. type 'help' for commands.\n")
         .await?;
 
     loop {
-        writer.write_all(b"ftms-debug> ").await?;
-
-        match lines.next_line().await? {
-            Some(line) => {
-                let line = line.trim().to_lowercase();
+        let Some(read) = limits.until_idle(console.read_line(&mut writer)).await else {
+            let _ = writer.write_all(b"\nidle too long, closing\n").await;
+            return Err("idle timeout".into());
+        };
+        match read? {
+            Some(raw) => {
+                let raw = raw.trim();
+                let line = raw.to_lowercase();
                 if line.is_empty() {
                     continue;
                 }
 
+                rate.wait().await;
+                ctx.health.touch("debug");
                 let response = match line.split_once(' ') {
-                    Some(("cp", hex)) => handle_cp(hex.trim(), &socket_path).await,
+                    Some(("cp", hex)) => handle_cp(hex.trim(), &ctx, &origin).await,
+                    Some(("history", n)) => Ok(handle_history(n.trim(), &ctx)),
+                    Some(("state", "json")) => handle_state_json(state, ctx.config.distance_source, ctx.ramp.running()).await,
+                    Some(("sub", topics)) => match Subscription::parse(topics) {
+                        Ok(sub) => {
+                            handle_subscribe(&ctx, &mut writer, sub).await?;
+                            continue;
+                        }
+                        Err(e) => Ok(e),
+                    },
+                    Some(("mode", mode)) => match Mode::parse(mode.trim()) {
+                        Some(mode) => {
+                            console.set_mode(mode, &mut writer).await?;
+                            Ok(format!("mode: {}", mode.name()))
+                        }
+                        None => Ok("usage: mode [plain|raw|edit]".to_string()),
+                    },
+                    Some(("totals", args)) => handle_totals(args, &ctx).await,
+                    Some(("records", "json")) => handle_records(true, &ctx).await,
+                    Some(("calibrate", arg)) => Ok(handle_calibrate(arg.trim(), &ctx).await),
+                    Some(("pause", secs)) => match secs.trim().parse::<u64>() {
+                        Ok(secs) => handle_pause(Some(secs), &ctx, &origin).await,
+                        Err(_) => Ok("usage: pause [secs]".to_string()),
+                    },
+                    // File paths and labels are case-sensitive, so take args from the raw line
+                    Some(("label", _)) => handle_label(raw["label".len()..].trim(), state).await,
+                    Some(("step", _)) => Ok(handle_step(raw["step".len()..].trim(), &ctx).await),
+                    Some(("relabel", _)) => handle_relabel(raw["relabel".len()..].trim(), &ctx).await,
+                    Some(("profile", _)) => Ok(handle_profile(raw["profile".len()..].trim(), &ctx)),
+                    Some(("replay", _)) => handle_replay(raw["replay".len()..].trim(), state).await,
                     _ => match line.as_str() {
                         "help" => Ok(HELP_TEXT.to_string()),
-                        "state" => handle_state(&state).await,
-                        "td" => handle_td(&state).await,
-                        "feat" => Ok(format!("feat {}", hex_encode(&protocol::encode_feature()))),
-                        "sr" => Ok(format!("range {}", hex_encode(&protocol::encode_speed_range()))),
-                        "ir" => Ok(format!("range {}", hex_encode(&protocol::encode_incline_range()))),
+                        "mode" => Ok(format!("mode: {}", console.mode().name())),
+                        "state" => handle_state(state, ctx.config.units, ctx.config.distance_source, ctx.ramp.running()).await,
+                        "td" => handle_td(state).await,
+                        "feat" => Ok(format!(
+                            "feat {}",
+                            hex_encode(&protocol::encode_feature(&ctx.config.capabilities()))
+                        )),
+                        "sync" => {
+                            let report = ctx.archive.sync().await;
+                            Ok(if report.is_empty() { "nothing pending".to_string() } else { report.join("\n") })
+                        }
+                        "latency" => Ok(ctx.latency.report()),
+                        "gatt" => Ok(ctx.gatt.report()),
+                        "clients" => Ok(ctx.gatt.clients_report()),
+                        "history" => Ok(handle_history("", &ctx)),
+                        "stats" => Ok(ctx.health.report()),
+                        "load" => handle_load(&ctx).await,
+                        "label" => handle_label("", state).await,
+                        "step" => Ok(handle_step("", &ctx).await),
+                        "profile" => Ok(handle_profile("", &ctx)),
+                        "quiet" => Ok(handle_quiet(&ctx)),
+                        "totals" => handle_totals("", &ctx).await,
+                        "records" => handle_records(false, &ctx).await,
+                        "calibrate" => Ok(handle_calibrate("", &ctx).await),
+                        "pause" => handle_pause(None, &ctx, &origin).await,
+                        "resume" => handle_resume(&ctx, &origin).await,
+                        "metrics" => Ok(handle_metrics(&ctx).await),
+                        "sr" => Ok(format!("range {}", hex_encode(&state.lock().await.limits().speed_range()))),
+                        "ir" => Ok(format!("range {}", hex_encode(&state.lock().await.limits().incline_range()))),
                         "sub" => {
-                            handle_subscribe(&state, &mut writer).await?;
+                            handle_subscribe(&ctx, &mut writer, Subscription::everything(ONE_HZ)).await?;
                             continue; // subscribe handles its own output
                         }
                         "quit" | "exit" => return Ok(()),
@@ -92,15 +228,8 @@ async fn handle_client(
                 };
 
                 match response {
-                    Ok(msg) => {
-                        writer.write_all(msg.as_bytes()).await?;
-                        writer.write_all(b"\n").await?;
-                    }
-                    Err(e) => {
-                        writer
-                            .write_all(format!("error: {}\n", e).as_bytes())
-                            .await?;
-                    }
+                    Ok(msg) => console.respond(&mut writer, &msg).await?,
+                    Err(e) => console.respond(&mut writer, &format!("error: {}", e)).await?,
                 }
             }
             None => return Ok(()), // EOF
@@ -108,39 +237,450 @@ async fn handle_client(
     }
 }
 
+/// `state json`: the same state as one JSON line, in fixed units.
+async fn handle_state_json(
+    state: &Arc<Mutex<TreadmillState>>,
+    distance_source: DistanceSource,
+    ramp: Option<RampKind>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let s = state.lock().await;
+    let msg = serde_json::json!({
+        "speed_mph": s.displayed_speed_at(std::time::Instant::now()) as f64 / 100.0,
+        "raw_speed_mph": s.speed_tenths_mph as f64 / 10.0,
+        "speed_correction": s.speed_correction,
+        "incline_pct": s.incline_half_pct as f64 / 2.0,
+        "elapsed_secs": s.elapsed_secs,
+        "distance_meters": s.distance_meters,
+        "elevation_gain_m": (s.elevation_gain_m * 10.0).round() / 10.0,
+        "distance_source": distance_source.name(),
+        "compare_distance_meters": s.compare_distance_m as u32,
+        "connected": s.connected,
+        "protocol_version": s.protocol_version,
+        "console_paused": s.console_paused,
+        "target_speed_mph": s.last_speed_target.map(|t| s.corrected_speed(t as u32 * 10) as f64 / 100.0),
+        "target_incline_pct": s.last_incline_target.map(|h| h as f64 / 2.0),
+        "incline_moving": s.incline_moving(),
+        "queued_incline_pct": s.queued_incline.map(|h| h as f64 / 2.0),
+        "target_distance_m": s.distance_target,
+        "target_training_time_secs": s.training_time_target,
+        "remaining_secs": s.remaining_secs(),
+        "ramp": match ramp {
+            Some(RampKind::WarmUp) => Some("warm_up"),
+            Some(RampKind::CoolDown) => Some("cool_down"),
+            Some(RampKind::Resume) => Some("resume"),
+            None => None,
+        },
+        "paused": s.app_paused.is_some(),
+        "heart_rate_bpm": s.heart_rate,
+        "step": s.workout_step,
+    });
+    Ok(msg.to_string())
+}
+
 async fn handle_state(
     state: &Arc<Mutex<TreadmillState>>,
+    units: Units,
+    distance_source: DistanceSource,
+    ramp: Option<RampKind>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let s = state.lock().await;
-    let speed_mph = s.speed_tenths_mph as f64 / 10.0;
-    let speed_kmh = protocol::mph_tenths_to_kmh_hundredths(s.speed_tenths_mph) as f64 / 100.0;
+    // Smoothed and corrected speed, as BLE clients see it; raw is the
+    // latest status value. The other unit system goes in parens.
+    let displayed = s.displayed_speed_at(std::time::Instant::now());
+    let other = match units {
+        Units::Imperial => Units::Metric,
+        Units::Metric => Units::Imperial,
+    };
     Ok(format!(
-        "speed:    {:.1} mph ({:.2} km/h)  [raw: {} tenths]\n\
-         incline:  {:.1}%  [raw: {} half-pct]\n\
-         elapsed:  {}s ({}:{:02})\n\
-         distance: {}m ({:.2} mi)\n\
-         connected: {}",
-        speed_mph,
-        speed_kmh,
+        "speed:    {} ({:.2} {})  [raw: {} tenths = {} km/h*100{}]{}\n\
+         incline:  {:.1}%  [raw: {} half-pct]{}\n\
+         target:   {}\n\
+         elapsed:  {}s ({}:{:02}){}\n\
+         distance: {}m ({}), climb {}  [{} speed: {:.0}m]\n\
+         connected: {}{}{}{}",
+        units.speed(displayed),
+        other.speed_value(displayed),
+        other.speed_unit(),
         s.speed_tenths_mph,
+        protocol::mph_tenths_to_kmh_hundredths(s.speed_tenths_mph),
+        s.speed_correction.map(|f| format!(", corrected x{:.3}", f)).unwrap_or_default(),
+        match ramp {
+            Some(RampKind::WarmUp) => "  [warming up]",
+            Some(RampKind::CoolDown) => "  [cooling down]",
+            Some(RampKind::Resume) => "  [resuming]",
+            None => "",
+        },
         s.incline_half_pct as f64 / 2.0,
         s.incline_half_pct,
+        match (s.incline_moving(), s.queued_incline) {
+            (true, Some(h)) => format!("  [motor moving, {:.1}% queued]", h as f64 / 2.0),
+            (true, None) => "  [motor moving]".to_string(),
+            (false, _) => String::new(),
+        },
+        describe_targets(&s, units),
         s.elapsed_secs,
         s.elapsed_secs / 60,
         s.elapsed_secs % 60,
+        if s.console_paused {
+            "  [paused at console]"
+        } else if s.app_paused.is_some() {
+            "  [paused]"
+        } else {
+            ""
+        },
         s.distance_meters,
-        s.distance_meters as f64 / 1609.34,
+        units.distance(s.distance_meters),
+        units.climb(s.elevation_gain_m),
+        distance_source.other().name(),
+        s.compare_distance_m,
         s.connected,
+        s.protocol_version.map(|v| format!(" (protocol v{})", v)).unwrap_or_default(),
+        s.heart_rate.map(|bpm| format!("\nheart rate: {} bpm", bpm)).unwrap_or_default(),
+        s.workout_step.as_ref().map(|step| format!("\nstep:     {}", step)).unwrap_or_default(),
     ))
 }
 
+/// The last commanded speed and incline, and which of them the belt
+/// hasn't reached yet.
+fn describe_targets(s: &TreadmillState, units: Units) -> String {
+    let speed = s.last_speed_target.map_or("-".to_string(), |t| units.speed(s.corrected_speed(t as u32 * 10)));
+    let incline = s.last_incline_target.map_or("-".to_string(), |h| format!("{:.1}%", h as f64 / 2.0));
+    let distance = s.distance_target.map(|m| format!(", stop at {}", units.distance(m))).unwrap_or_default();
+    let time = s.remaining_secs().map(|r| format!(", {}:{:02} left", r / 60, r % 60)).unwrap_or_default();
+    let pending: Vec<String> = s.unapplied_targets().iter().map(Target::to_string).collect();
+    if pending.is_empty() {
+        format!("speed {}, incline {}{}{}", speed, incline, distance, time)
+    } else {
+        format!("speed {}, incline {}{}{}  [not reached: {}]", speed, incline, distance, time, pending.join(", "))
+    }
+}
+
+/// Actual and commanded speed and incline as Prometheus gauges, after the
+/// latency histograms, then the GATT notification counters. Targets never
+/// commanded are left out.
+async fn handle_metrics(ctx: &ControlContext) -> String {
+    let mut out = ctx.latency.prometheus();
+    let s = ctx.state.lock().await;
+    let gauges = [
+        ("ftms_speed_mph", "Belt speed treadmill_io reports.", Some(s.speed_tenths_mph as f64 / 10.0)),
+        ("ftms_target_speed_mph", "Last commanded speed.", s.last_speed_target.map(|t| t as f64 / 10.0)),
+        ("ftms_incline_percent", "Incline treadmill_io reports.", Some(s.incline_half_pct as f64 / 2.0)),
+        ("ftms_target_incline_percent", "Last commanded incline.", s.last_incline_target.map(|h| h as f64 / 2.0)),
+    ];
+    for (name, help, value) in gauges {
+        out.push_str(&format!("\n# HELP {} {}\n# TYPE {} gauge", name, help, name));
+        if let Some(value) = value {
+            out.push_str(&format!("\n{} {}", name, value));
+        }
+    }
+    drop(s);
+    let notifications = ctx.gatt.prometheus();
+    if !notifications.is_empty() {
+        out.push('\n');
+        out.push_str(&notifications);
+    }
+    out
+}
+
+/// `label [text]`: show, set or (with `label -`) clear the label of the
+/// workout under way, or of the next one when the belt hasn't started.
+async fn handle_label(text: &str, state: &Arc<Mutex<TreadmillState>>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut s = state.lock().await;
+    match text {
+        "" => {}
+        "-" => s.workout_label = None,
+        _ => s.workout_label = Some(text.to_string()),
+    }
+    Ok(match &s.workout_label {
+        Some(label) => format!("label: \"{}\"", label),
+        None => "no label".to_string(),
+    })
+}
+
+/// `step [text|-]`: show, set or clear the interval program's step line.
+/// A change goes out to BLE apps (Training Status) and `sub` clients.
+async fn handle_step(text: &str, ctx: &ControlContext) -> String {
+    let mut s = ctx.state.lock().await;
+    let step = match text {
+        "" => s.workout_step.clone(),
+        "-" => None,
+        _ => Some(text.to_string()),
+    };
+    if !text.is_empty() && step != s.workout_step {
+        s.workout_step = step.clone();
+        drop(s);
+        let _ = ctx.events.send(TreadmillEvent::Step(step.clone()));
+    }
+    match step {
+        Some(step) => format!("step: \"{}\"", step),
+        None => "no step".to_string(),
+    }
+}
+
+/// `calibrate [<mph>|-]`: show the speed correction, set it from the
+/// belt's measured speed against the latest status, or go back to the
+/// configured one.
+async fn handle_calibrate(arg: &str, ctx: &ControlContext) -> String {
+    let path = ctx.config.calibration_file.as_deref();
+    let factor = match arg {
+        "" => {
+            let s = ctx.state.lock().await;
+            return match s.speed_correction {
+                Some(f) => format!("speed correction x{:.3}", f),
+                None => "no speed correction".to_string(),
+            };
+        }
+        "-" => {
+            if let Some(path) = path {
+                if let Err(e) = tokio::fs::remove_file(path).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return format!("error: can't remove {}: {}", path, e);
+                    }
+                }
+            }
+            calibration::usable(ctx.config.speed_correction)
+        }
+        _ => {
+            let Ok(measured) = arg.parse::<f64>() else {
+                return "usage: calibrate [<mph>|-]".to_string();
+            };
+            let reported = ctx.state.lock().await.speed_tenths_mph;
+            let Some(factor) = calibration::measure(measured, reported) else {
+                return "error: start the belt first".to_string();
+            };
+            if !(calibration::MIN_FACTOR..=calibration::MAX_FACTOR).contains(&factor) {
+                return format!(
+                    "error: {:.1} mph against {} reported is x{:.3}, outside {}-{}",
+                    measured,
+                    ctx.config.units.speed_tenths(reported),
+                    factor,
+                    calibration::MIN_FACTOR,
+                    calibration::MAX_FACTOR
+                );
+            }
+            if let Some(path) = path {
+                let cal = Calibration { factor, wall_ms: crate::telemetry::wall_ms() };
+                if let Err(e) = calibration::save(path, &cal).await {
+                    return format!("error: can't save {}: {}", path, e);
+                }
+            }
+            calibration::usable(factor)
+        }
+    };
+    ctx.state.lock().await.speed_correction = factor;
+    info!("Speed correction now {}", factor.map_or("none".to_string(), |f| format!("x{:.3}", f)));
+    let until_restart = path.is_none() && arg != "-";
+    match factor {
+        Some(f) if until_restart => format!("speed correction x{:.3} (until restart, no calibration_file)", f),
+        Some(f) => format!("speed correction x{:.3}", f),
+        None => "no speed correction".to_string(),
+    }
+}
+
+/// `profile [<name> <pin>]`: show the active profile and the configured
+/// ones, or switch to `name`.
+fn handle_profile(args: &str, ctx: &ControlContext) -> String {
+    if !args.is_empty() {
+        let Some((name, pin)) = args.split_once(' ') else {
+            return "usage: profile <name> <pin>".to_string();
+        };
+        if let Err(e) = ctx.profiles.switch(&ctx.config, name, pin.trim()) {
+            return format!("error: {}", e);
+        }
+    }
+    let mut out = match ctx.profiles.active(&ctx.config) {
+        Some((name, caps)) => format!("profile: {} ({})", name, caps.describe(&ctx.config)),
+        None => "profile: none (safety max only)".to_string(),
+    };
+    for (name, caps) in &ctx.config.profiles {
+        out.push_str(&format!("\n  {}: {}", name, caps.describe(&ctx.config)));
+    }
+    out
+}
+
+/// `history [n]`: the last n control commands (default 20), oldest
+/// first, with who sent them and how they went.
+fn handle_history(args: &str, ctx: &ControlContext) -> String {
+    let n = match args {
+        "" => 20,
+        n => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return "usage: history [n]".to_string(),
+        },
+    };
+    let records = ctx.audit.recent(n);
+    if records.is_empty() {
+        return "no control commands yet".to_string();
+    }
+    let lines: Vec<String> = records
+        .iter()
+        .map(|r| {
+            let offset = crate::clock::utc_offset_secs(ctx.config.timezone.as_deref(), r.wall_ms);
+            let (y, m, d, hh, mm, ss) = export::utc_parts(crate::clock::local_ms(r.wall_ms, offset));
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}  {:<27}  {:<28}  {}",
+                y,
+                m,
+                d,
+                hh,
+                mm,
+                ss,
+                r.origin.to_string(),
+                r.command,
+                protocol::result_name(r.result)
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// `quiet`: the configured quiet hours and the window in effect now.
+fn handle_quiet(ctx: &ControlContext) -> String {
+    if ctx.config.quiet_hours.is_empty() {
+        return "no quiet_hours configured".to_string();
+    }
+    let mut out = match quiet::now(&ctx.config) {
+        Some(q) => format!("in effect: {}", q.describe(&ctx.config)),
+        None => "not in quiet hours".to_string(),
+    };
+    for q in &ctx.config.quiet_hours {
+        out.push_str(&format!("\n  {}", q.describe(&ctx.config)));
+    }
+    out
+}
+
+/// `relabel <n> [text]`: change the label of the nth most recent workout
+/// in the history (as numbered by `load`); no text clears it.
+async fn handle_relabel(args: &str, ctx: &ControlContext) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(path) = ctx.config.history_file.as_deref() else {
+        return Ok("no history_file configured".to_string());
+    };
+    let (n, text) = args.split_once(' ').unwrap_or((args, ""));
+    let Ok(n) = n.trim_start_matches('#').parse::<usize>() else {
+        return Ok("usage: relabel <n> [text]".to_string());
+    };
+    let label = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    Ok(match history::relabel(path, n, label).await? {
+        Some(r) => format!("#{} label: {}", n, r.label.as_deref().map_or("(none)".to_string(), |l| format!("\"{}\"", l))),
+        None => format!("no workout #{} in history", n),
+    })
+}
+
+async fn handle_load(ctx: &ControlContext) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(path) = ctx.config.history_file.as_deref() else {
+        return Ok("no history_file configured".to_string());
+    };
+    let records = history::load(path).await;
+    let mut out = TrainingLoad::at(&records, crate::telemetry::wall_ms()).report();
+    if ctx.config.max_heart_rate.is_none() {
+        out.push_str("\n(set max_heart_rate to compute TRIMP)");
+    }
+    for (n, r) in records.iter().rev().take(5).enumerate() {
+        let (y, m, d, hh, mm, _) = export::utc_parts(crate::clock::local_ms(r.start_wall_ms, r.utc_offset_secs));
+        out.push_str(&format!(
+            "\n  #{} {:04}-{:02}-{:02} {:02}:{:02}  {:>3} min  {}  TRIMP {}{}",
+            n + 1,
+            y,
+            m,
+            d,
+            hh,
+            mm,
+            r.elapsed_secs / 60,
+            ctx.config.units.distance(r.distance_m.round() as u32),
+            r.trimp.map(|t| format!("{:.0}", t)).unwrap_or_else(|| "-".to_string()),
+            r.label.as_ref().map(|l| format!("  \"{}\"", l)).unwrap_or_default()
+        ));
+    }
+    Ok(out)
+}
+
+/// `totals [week|month] [n] [json]`: the last n (default 4 weeks / 6
+/// months) periods of workout history, as a table or a JSON array.
+async fn handle_totals(args: &str, ctx: &ControlContext) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(path) = ctx.config.history_file.as_deref() else {
+        return Ok("no history_file configured".to_string());
+    };
+    let mut period = Period::Week;
+    let mut count = None;
+    let mut json = false;
+    for arg in args.split_whitespace() {
+        if let Some(p) = Period::parse(arg) {
+            period = p;
+        } else if let Ok(n) = arg.parse::<usize>() {
+            count = Some(n.clamp(1, 120));
+        } else if arg == "json" {
+            json = true;
+        } else {
+            return Ok("usage: totals [week|month] [n] [json]".to_string());
+        }
+    }
+    let count = count.unwrap_or(if period == Period::Week { 4 } else { 6 });
+    let now = crate::telemetry::wall_ms();
+    let offset = crate::clock::utc_offset_secs(ctx.config.timezone.as_deref(), now);
+    let totals = history::totals(&history::load(path).await, period, count, now, offset);
+    if json {
+        return Ok(serde_json::to_string(&totals)?);
+    }
+    let mut out = format!(
+        "{:<11} sessions      time  {:>9}  climb   kcal",
+        if period == Period::Week { "week of" } else { "month" },
+        "distance"
+    );
+    for t in &totals {
+        out.push_str(&format!(
+            "\n{:<11} {:>8} {:>6}:{:02}  {:>9}  {:>4} m {:>6.0}",
+            t.period,
+            t.sessions,
+            t.elapsed_secs / 3600,
+            t.elapsed_secs % 3600 / 60,
+            ctx.config.units.distance(t.distance_m.round() as u32),
+            t.elevation_gain_m.round(),
+            t.kcal
+        ));
+    }
+    Ok(out)
+}
+
+/// `records [json]`: the personal records in the workout history, one
+/// line each or as a JSON array.
+async fn handle_records(json: bool, ctx: &ControlContext) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(path) = ctx.config.history_file.as_deref() else {
+        return Ok("no history_file configured".to_string());
+    };
+    let bests = history::personal_records(&history::load(path).await);
+    if json {
+        return Ok(serde_json::to_string(&bests)?);
+    }
+    if bests.is_empty() {
+        return Ok("no workouts in history".to_string());
+    }
+    let lines: Vec<String> = bests
+        .iter()
+        .map(|b| {
+            let (y, m, d, hh, mm, _) = export::utc_parts(crate::clock::local_ms(b.start_wall_ms, b.utc_offset_secs));
+            format!(
+                "{:<24}  {:04}-{:02}-{:02} {:02}:{:02}{}",
+                b.describe(ctx.config.units),
+                y,
+                m,
+                d,
+                hh,
+                mm,
+                b.label.as_ref().map(|l| format!("  \"{}\"", l)).unwrap_or_default()
+            )
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
 async fn handle_td(
     state: &Arc<Mutex<TreadmillState>>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let s = state.lock().await;
-    let data = s.encode_ftms_data();
-    let speed_kmh = protocol::mph_tenths_to_kmh_hundredths(s.speed_tenths_mph);
-    let incline_tenths = (s.incline_half_pct as i16) * 5;
+    let now = std::time::Instant::now();
+    let data = s.encode_ftms_data_at(now);
+    let speed_kmh = protocol::mph_hundredths_to_kmh_hundredths(s.displayed_speed_at(now));
+    let incline_tenths = s.incline_half_pct * 5;
 
     Ok(format!(
         "data {} (speed={} incline={} dist={}m elapsed={}s)",
@@ -154,7 +694,8 @@ async fn handle_td(
 
 async fn handle_cp(
     hex: &str,
-    socket_path: &str,
+    ctx: &ControlContext,
+    origin: &Origin,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let bytes = hex_decode(hex)?;
     if bytes.is_empty() {
@@ -167,8 +708,8 @@ async fn handle_cp(
             let description = match &cmd {
                 protocol::ControlCommand::RequestControl => "Request Control".to_string(),
                 protocol::ControlCommand::SetTargetSpeed(v) => {
-                    let mph = protocol::kmh_hundredths_to_mph_tenths(*v) as f64 / 10.0;
-                    format!("Set Target Speed: {} km/h*100 ({:.1} mph)", v, mph)
+                    let mph_tenths = protocol::kmh_hundredths_to_mph_tenths(*v);
+                    format!("Set Target Speed: {} km/h*100 ({})", v, ctx.config.units.speed_tenths(mph_tenths))
                 }
                 protocol::ControlCommand::SetTargetInclination(v) => {
                     format!("Set Target Incline: {} ({:.1}%)", v, *v as f64 / 10.0)
@@ -177,21 +718,37 @@ async fn handle_cp(
                 protocol::ControlCommand::StopOrPause(p) => {
                     format!("Stop/Pause (param={})", p)
                 }
+                protocol::ControlCommand::SetTargetedDistance(m) => {
+                    format!("Set Targeted Distance: {} m ({})", m, ctx.config.units.distance(*m))
+                }
+                protocol::ControlCommand::SetTargetedTrainingTime(t) => {
+                    format!("Set Targeted Training Time: {} s ({}:{:02})", t, t / 60, t % 60)
+                }
             };
 
             // Execute via the same handler the BLE GATT server uses
             let (resp_opcode, result_code) =
-                crate::ftms_service::handle_control_command(&cmd, socket_path).await;
+                crate::ftms_service::handle_control_command(&cmd, ctx, origin).await;
             let response = protocol::encode_control_response(resp_opcode, result_code);
 
             let mut output = format!("parsed: {}\nresp {}", description, hex_encode(&response));
-            if result_code != protocol::RESULT_SUCCESS {
-                output.push_str("\nwarning: command failed (see daemon log)");
+            if result_code == protocol::RESULT_SUCCESS {
+                // The Machine Status a BLE client would get, with the applied target
+                let applied = crate::ftms_service::applied_command(&cmd, ctx.state.lock().await.limits());
+                if let Some(status) = crate::ftms_service::encode_status_notification(&applied) {
+                    output.push_str(&format!("\nstatus {}", hex_encode(&status)));
+                }
+            } else {
+                output.push_str(&format!(
+                    "\nwarning: command failed: {} (see daemon log)",
+                    protocol::result_name(result_code)
+                ));
             }
 
             Ok(output)
         }
         None => {
+            ctx.audit.unsupported(origin, &bytes);
             let response = protocol::encode_control_response(opcode, protocol::RESULT_NOT_SUPPORTED);
             Ok(format!(
                 "parsed: unknown opcode 0x{:02x}\nresp {}",
@@ -202,31 +759,205 @@ async fn handle_cp(
     }
 }
 
-async fn handle_subscribe(
+/// `pause [secs]`: audited like a control point Pause.
+async fn handle_pause(
+    secs: Option<u64>,
+    ctx: &ControlContext,
+    origin: &Origin,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let result = pause::pause(ctx, secs.map(std::time::Duration::from_secs)).await;
+    audit_pause(ctx, origin, 0x08, &protocol::ControlCommand::StopOrPause(2), result.is_ok());
+    let speed = ctx.config.units.speed_tenths(result?);
+    Ok(match secs {
+        Some(secs) => format!("paused at {}, resuming in {}s", speed, secs),
+        None => format!("paused at {}; 'resume' to carry on", speed),
+    })
+}
+
+/// `resume`: audited like a control point Resume.
+async fn handle_resume(ctx: &ControlContext, origin: &Origin) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let result = pause::resume(ctx).await;
+    audit_pause(ctx, origin, 0x07, &protocol::ControlCommand::StartOrResume, result.is_ok());
+    Ok(format!("resuming to {}", ctx.config.units.speed_tenths(result?)))
+}
+
+fn audit_pause(ctx: &ControlContext, origin: &Origin, opcode: u8, cmd: &protocol::ControlCommand, ok: bool) {
+    let result = if ok { protocol::RESULT_SUCCESS } else { protocol::RESULT_FAILED };
+    ctx.audit.control(origin, opcode, cmd, result);
+}
+
+async fn handle_replay(
+    args: &str,
     state: &Arc<Mutex<TreadmillState>>,
-    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if args == "stop" {
+        let mut s = state.lock().await;
+        if !s.replaying {
+            return Ok("no replay running".to_string());
+        }
+        s.replaying = false;
+        return Ok("replay stopping".to_string());
+    }
+
+    let mut parts = args.split_whitespace();
+    let path = parts.next().ok_or("usage: replay <file> [speed] | replay stop")?;
+    let speed = match parts.next() {
+        Some(v) => v.parse::<f64>().ok().filter(|s| *s > 0.0).ok_or("speed must be a positive number")?,
+        None => 1.0,
+    };
+
+    let samples = replay::load(path)?;
+    let count = samples.len();
+    let restore = replay::begin(state).await?;
+    tokio::spawn(replay::run(state.clone(), samples, speed, restore));
+    Ok(format!("replaying {} samples from {} at {}x ('replay stop' to end)", count, path, speed))
+}
+
+const ONE_HZ: Duration = Duration::from_secs(1);
+
+const SUB_USAGE: &str =
+    "usage: sub [1|2|4] | sub <topic>[:1|2|4]... (topics: state, telemetry, control-events, session-events)";
+
+/// Notification period for `sub <hz>`; only the rates BLE clients can get.
+fn parse_sub_rate(hz: &str) -> Option<Duration> {
+    match hz {
+        "1" => Some(Duration::from_millis(1000)),
+        "2" => Some(Duration::from_millis(500)),
+        "4" => Some(Duration::from_millis(250)),
+        _ => None,
+    }
+}
+
+/// What a `sub` client is sent: the periodic topics at their own rates,
+/// the event topics as things happen.
+#[derive(Debug, Default, PartialEq)]
+struct Subscription {
+    /// `state json` lines
+    state: Option<Duration>,
+    /// Treadmill Data hex lines, as BLE clients get them
+    telemetry: Option<Duration>,
+    /// Control commands from any client, and targets that failed or were lost
+    control_events: bool,
+    /// Console and app pauses, resumes, the resume countdown, program steps
+    /// and targets reached
+    session_events: bool,
+}
+
+impl Subscription {
+    /// Plain `sub [hz]`: Treadmill Data at `period` and every event.
+    fn everything(period: Duration) -> Self {
+        Self { telemetry: Some(period), control_events: true, session_events: true, ..Default::default() }
+    }
+
+    /// `sub` arguments: a rate alone, or topics with optional rates
+    /// (`state:1 session-events`). The error is the reply.
+    fn parse(args: &str) -> Result<Self, String> {
+        if let Some(period) = parse_sub_rate(args.trim()) {
+            return Ok(Self::everything(period));
+        }
+        let mut sub = Self::default();
+        for word in args.split_whitespace() {
+            let (topic, hz) = match word.split_once(':') {
+                Some((topic, hz)) => (topic, Some(hz)),
+                None => (word, None),
+            };
+            let period = match hz {
+                Some(hz) => parse_sub_rate(hz).ok_or_else(|| format!("bad rate '{}' for {}: 1, 2 or 4 Hz", hz, topic))?,
+                None => ONE_HZ,
+            };
+            match (topic, hz) {
+                ("state", _) => sub.state = Some(period),
+                ("telemetry", _) => sub.telemetry = Some(period),
+                ("control-events", None) => sub.control_events = true,
+                ("session-events", None) => sub.session_events = true,
+                ("control-events" | "session-events", Some(_)) => {
+                    return Err(format!("{} has no rate: events are sent as they happen", topic))
+                }
+                _ => return Err(format!("unknown topic '{}'. {}", topic, SUB_USAGE)),
+            }
+        }
+        if sub == Self::default() {
+            return Err(SUB_USAGE.to_string());
+        }
+        Ok(sub)
+    }
+
+    fn wants(&self, event: &TreadmillEvent) -> bool {
+        match event {
+            TreadmillEvent::TargetFailed { .. } | TreadmillEvent::TargetsLost => self.control_events,
+            TreadmillEvent::ConsolePaused
+            | TreadmillEvent::ConsoleResumed
+            | TreadmillEvent::Paused
+            | TreadmillEvent::ResumeCountdown(_)
+            | TreadmillEvent::Resumed
+            | TreadmillEvent::Step(_)
+            | TreadmillEvent::DistanceReached(_)
+            | TreadmillEvent::TrainingTimeReached(_) => self.session_events,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let rate = |name: &str, period: Option<Duration>| period.map(|p| format!("{} at {} Hz", name, 1000 / p.as_millis()));
+        [
+            rate("state", self.state),
+            rate("telemetry", self.telemetry),
+            self.control_events.then(|| "control-events".to_string()),
+            self.session_events.then(|| "session-events".to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// The next tick of `interval`, or never for a topic not subscribed to.
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_subscribe(
+    ctx: &ControlContext,
+    writer: &mut (impl AsyncWrite + Unpin),
+    sub: Subscription,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     writer
-        .write_all(b"subscribed to treadmill data at 1 Hz. ctrl-c to stop.\n")
+        .write_all(format!("subscribed to {}. ctrl-c to stop.\n", sub.describe()).as_bytes())
         .await?;
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    let mut events = ctx.events.subscribe();
+    let mut commands = ctx.audit.subscribe();
+    let mut state_tick = sub.state.map(tokio::time::interval);
+    let mut telemetry_tick = sub.telemetry.map(tokio::time::interval);
+    let any_events = sub.control_events || sub.session_events;
     loop {
-        interval.tick().await;
-
-        let s = state.lock().await;
-        let data = s.encode_ftms_data();
-        let speed_mph = s.speed_tenths_mph as f64 / 10.0;
-        let incline_half_pct = s.incline_half_pct;
-        drop(s);
-
-        let line = format!(
-            "data {} | {:.1}mph {:.1}%\n",
-            hex_encode(&data),
-            speed_mph,
-            incline_half_pct as f64 / 2.0,
-        );
-
+        let line = tokio::select! {
+            _ = tick(&mut state_tick) => {
+                let json = handle_state_json(&ctx.state, ctx.config.distance_source, ctx.ramp.running()).await?;
+                format!("state {}\n", json)
+            }
+            _ = tick(&mut telemetry_tick) => telemetry_line(ctx).await,
+            event = events.recv() => match event {
+                Ok(event) if sub.wants(&event) => format!("event {}\n", describe_event(&event)),
+                Err(RecvError::Lagged(n)) if any_events => format!("event lagged ({} dropped)\n", n),
+                _ => continue,
+            },
+            record = commands.recv() => match record {
+                Ok(record) if sub.control_events => format!(
+                    "control {} {} -> {}\n",
+                    record.origin,
+                    record.command,
+                    protocol::result_name(record.result)
+                ),
+                Err(RecvError::Lagged(n)) if sub.control_events => format!("control lagged ({} dropped)\n", n),
+                _ => continue,
+            },
+        };
         if writer.write_all(line.as_bytes()).await.is_err() {
             break;
         }
@@ -235,13 +966,49 @@ async fn handle_subscribe(
     Ok(())
 }
 
+/// A `telemetry` line: Treadmill Data hex, then speed and incline.
+async fn telemetry_line(ctx: &ControlContext) -> String {
+    let s = ctx.state.lock().await;
+    let now = std::time::Instant::now();
+    let data = s.encode_ftms_data_at(now);
+    let speed = ctx.config.units.speed_value(s.displayed_speed_at(now));
+    let incline_half_pct = s.incline_half_pct;
+    drop(s);
+
+    format!(
+        "data {} | {:.2}{} {:.1}%\n",
+        hex_encode(&data),
+        speed,
+        ctx.config.units.speed_unit(),
+        incline_half_pct as f64 / 2.0,
+    )
+}
+
+fn describe_event(event: &TreadmillEvent) -> String {
+    match event {
+        TreadmillEvent::TargetFailed { target, attempts } => {
+            format!("target_failed {} after {} attempts", target, attempts)
+        }
+        TreadmillEvent::TargetsLost => "targets_lost (treadmill_io reconnected)".to_string(),
+        TreadmillEvent::ConsolePaused => "console_paused".to_string(),
+        TreadmillEvent::ConsoleResumed => "console_resumed".to_string(),
+        TreadmillEvent::Paused => "paused".to_string(),
+        TreadmillEvent::ResumeCountdown(left) => format!("resume_countdown {}", left),
+        TreadmillEvent::Resumed => "resumed".to_string(),
+        TreadmillEvent::Step(Some(step)) => format!("step {}", step),
+        TreadmillEvent::Step(None) => "step ended".to_string(),
+        TreadmillEvent::DistanceReached(meters) => format!("distance_reached {}m", meters),
+        TreadmillEvent::TrainingTimeReached(secs) => format!("training_time_reached {}s", secs),
+    }
+}
+
 fn hex_encode(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("")
 }
 
 fn hex_decode(hex: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     let hex = hex.replace(' ', "");
-    if hex.len() % 2 != 0 {
+    if !hex.len().is_multiple_of(2) {
         return Err("hex string must have even length".into());
     }
     (0..hex.len())
@@ -255,13 +1022,69 @@ fn hex_decode(hex: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + S
 
 const HELP_TEXT: &str = "\
 commands:
-  state           show current treadmill state (human-readable)
+  state [json]    show current treadmill state (human-readable, or one
+                  JSON line for scripts and --check)
   td              read treadmill data characteristic (0x2ACD) as hex
   feat            read feature characteristic (0x2ACC) as hex
   sr              read supported speed range (0x2AD4) as hex
   ir              read supported incline range (0x2AD5) as hex
   cp <hex>        write to control point (0x2AD9), execute + show response
-  sub             subscribe to 1 Hz treadmill data stream
+                  and Machine Status (applied target)
+  gatt            registered GATT service and characteristics: handles
+                  BlueZ assigned, properties, active subscribers
+  clients         notifications sent/delivered/failed/skipped per
+                  characteristic and per subscriber, sessions dropped
+  history [n]     last n control commands (default 20) with time, sender
+                  (BLE address or debug peer) and result
+  sub [hz]        subscribe to treadmill data stream + events (1, 2 or 4 Hz)
+  sub <topic>[:hz]...
+                  only the topics named, each at its own rate (default 1 Hz):
+                  state (state json lines), telemetry (treadmill data hex),
+                  control-events (commands from any client, failed/lost
+                  targets), session-events (pauses, resumes, program
+                  steps, targeted distance/time reached); e.g.
+                  'sub state:2 session-events'
+  replay <file> [speed]
+                  play a telemetry log into the state at [speed]x (default 1)
+  replay stop     end a running replay and restore live state
+  sync            re-push workout files whose archive upload failed
+  latency         control point → treadmill_io send / status confirmation
+                  latency (p50/p90/p99/max)
+  metrics         latency histograms, actual/target speed and incline and
+                  notification counters in Prometheus text format
+  stats           uptime, last activity per task, notification/command/
+                  reconnect/error/duplicate-target counters
+  load            TRIMP training load over 7 and 28 days, last 5 workouts
+                  (needs history_file)
+  label [text]    show or set the label of the workout under way (or the
+                  next one); 'label -' clears it
+  step [text]     show or set the interval program's step line, sent to BLE
+                  apps as the Training Status string; 'step -' clears it
+  calibrate       speed correction in effect (actual belt speed over
+                  treadmill_io's)
+  calibrate <mph> with the belt running steadily, the speed it really does
+                  (e.g. timed belt laps); saved to calibration_file
+  calibrate -     forget the measured correction, back to speed_correction
+  pause [secs]    stop the belt but keep the workout (elapsed time frozen);
+                  with secs, resume by itself after a 3-2-1 countdown
+  resume          ramp back up to the speed paused at
+  relabel <n> [text]
+                  relabel workout #n from 'load' in the history; no text
+                  clears it (already exported files keep the old label)
+  profile         active speed-limit profile and its caps
+  profile <name> <pin>
+                  switch profiles (needs profile_pin in the config)
+  quiet           quiet hours windows and whether one is in effect now
+  totals [week|month] [n] [json]
+                  sessions, time (h:mm), distance, climb and active kcal for
+                  the last n weeks (default 4) or months (default 6)
+  records [json]  personal records from the history: fastest 1k and mile
+                  (best splits within a workout) and longest run, with the
+                  workout that set each
+  mode [plain|raw|edit]
+                  plain: prompt after each response (default); raw: no
+                  prompt, a blank line ends each response (rlwrap, scripts);
+                  edit: line editing + Up/Down history (telnet clients)
   help            this message
   quit            disconnect
 
@@ -274,5 +1097,34 @@ control point examples:
   cp 07           Start or Resume
   cp 08 01        Stop
   cp 08 02        Pause
+  cp 0c 881300    Set Targeted Distance 5000 m (0x001388 LE), stop there
+  cp 0d 0807      Set Targeted Training Time 30:00 (1800 = 0x0708 LE)
 
 all values are little-endian hex, matching raw BLE GATT writes.";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscription() {
+        let half = Some(Duration::from_millis(500));
+        assert_eq!(Subscription::parse("2"), Ok(Subscription::everything(Duration::from_millis(500))));
+        assert_eq!(
+            Subscription::parse("state:2 session-events"),
+            Ok(Subscription { state: half, session_events: true, ..Default::default() })
+        );
+        assert_eq!(
+            Subscription::parse("telemetry control-events"),
+            Ok(Subscription { telemetry: Some(ONE_HZ), control_events: true, ..Default::default() })
+        );
+        assert!(Subscription::parse("state:3").unwrap_err().starts_with("bad rate '3'"));
+        assert!(Subscription::parse("session-events:1").unwrap_err().contains("no rate"));
+        assert!(Subscription::parse("3").unwrap_err().starts_with("unknown topic '3'"));
+        assert_eq!(Subscription::parse("state:4 telemetry").unwrap().describe(), "state at 4 Hz, telemetry at 1 Hz");
+
+        let sessions = Subscription::parse("session-events").unwrap();
+        assert!(sessions.wants(&TreadmillEvent::Paused));
+        assert!(!sessions.wants(&TreadmillEvent::TargetsLost));
+    }
+}
@@ -0,0 +1,311 @@
+//! Workout file export.
+//!
+//! When a workout ends the treadmill task hands it here. With `export_dir`
+//! set, it's written there in each of `export_formats` (point `export_dir`
+//! at a folder Garmin Express or other import tooling watches), and the
+//! written files are handed to the archiver for copying to network storage.
+//! File names and the activity name, sport and device embedded in the
+//! files come from the `export_*` templates in the config.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::Archiver;
+use crate::clock;
+use crate::config::FtmsConfig;
+use crate::workout::Workout;
+use crate::{apple_health, csv, fit, health_connect};
+
+/// Workouts shorter than this (a stray belt bump) aren't exported.
+pub const MIN_EXPORT_SECS: u32 = 60;
+
+/// A workout file format.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// FIT activity (Garmin Connect, Strava, ...).
+    Fit,
+    /// Health Connect record bundle for Android bridge apps.
+    HealthConnect,
+    /// Apple Health `export.xml` zip for import apps.
+    AppleHealth,
+    /// Per-second samples for spreadsheets.
+    Csv,
+}
+
+impl ExportFormat {
+    /// Appended to the file stem.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            ExportFormat::Fit => ".fit",
+            ExportFormat::HealthConnect => ".healthconnect.json",
+            ExportFormat::AppleHealth => ".apple-health.zip",
+            ExportFormat::Csv => ".csv",
+        }
+    }
+
+    pub fn encode(self, workout: &Workout, meta: &Metadata, config: &FtmsConfig) -> Vec<u8> {
+        match self {
+            ExportFormat::Fit => fit::encode_activity(workout, meta, &config.fit_device),
+            ExportFormat::HealthConnect => health_connect::encode(workout, meta, config.body_weight_kg),
+            ExportFormat::AppleHealth => apple_health::encode(workout, meta, config.body_weight_kg),
+            ExportFormat::Csv => csv::encode(workout, config.body_weight_kg),
+        }
+    }
+}
+
+/// Sport written into exported files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sport {
+    #[default]
+    Running,
+    Walking,
+}
+
+/// What the exported files call the workout and its source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// Activity name. None leaves each format's default.
+    pub title: Option<String>,
+    pub sport: Sport,
+    /// Recording device name. None leaves each format's default.
+    pub device: Option<String>,
+}
+
+impl Metadata {
+    /// Fill in the config's templates for `workout`.
+    pub fn new(workout: &Workout, config: &FtmsConfig) -> Self {
+        let text = |template: &str| Some(tidy_text(&expand(template, workout, config, str::to_string))).filter(|t| !t.is_empty());
+        Self {
+            title: text(&config.export_title),
+            sport: config.export_sport,
+            device: config.export_device.as_deref().and_then(text),
+        }
+    }
+}
+
+/// Write the finished `workout` to `export_dir` and archive the files.
+pub async fn export(workout: Workout, config: Arc<FtmsConfig>, archiver: Archiver) {
+    let Some(dir) = config.export_dir.as_deref() else {
+        return;
+    };
+    if workout.elapsed_secs() < MIN_EXPORT_SECS {
+        info!("Workout too short to export ({}s)", workout.elapsed_secs());
+        return;
+    }
+
+    let stem = file_stem(&workout, &config);
+    let meta = Metadata::new(&workout, &config);
+    let mut written = Vec::new();
+    for &format in &config.export_formats {
+        let name = format!("{}{}", stem, format.suffix());
+        let data = format.encode(&workout, &meta, &config);
+        let path = Path::new(dir).join(&name);
+        if let Err(e) = write_file(&path, &data).await {
+            warn!("Failed to write {}: {}", path.display(), e);
+            continue;
+        }
+        info!("Exported workout to {}", path.display());
+        written.push(path);
+    }
+    archiver.archive(written);
+}
+
+async fn write_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, data).await
+}
+
+/// File name (without extension) for `workout` from `export_name`, e.g.
+/// `treadmill-20240315-071502`, relative to `export_dir`. Filled-in values
+/// are made file-safe, separators left doubled or dangling by an empty
+/// value are dropped, and the name can't climb out of `export_dir`.
+pub fn file_stem(workout: &Workout, config: &FtmsConfig) -> String {
+    let expanded = expand(&config.export_name, workout, config, |value| {
+        value
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+            .collect()
+    });
+    let parts: Vec<String> = expanded
+        .split('/')
+        .map(tidy_component)
+        .filter(|part| !part.is_empty() && part != "." && part != "..")
+        .collect();
+    if parts.is_empty() {
+        warn!("export_name {:?} gave an empty file name; using the default", config.export_name);
+        return file_stem(workout, &FtmsConfig::default());
+    }
+    parts.join("/")
+}
+
+/// Replace the `{placeholder}`s in `template` with `workout`'s values,
+/// passed through `escape`. Unknown placeholders are left as written.
+fn expand(template: &str, workout: &Workout, config: &FtmsConfig, escape: impl Fn(&str) -> String) -> String {
+    let (y, m, d, hh, mm, ss) = utc_parts(clock::local_ms(workout.start_wall_ms, workout.utc_offset_secs));
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        let value = match &rest[open + 1..close] {
+            "date" => format!("{:04}{:02}{:02}", y, m, d),
+            "time" => format!("{:02}{:02}{:02}", hh, mm, ss),
+            "year" => format!("{:04}", y),
+            "month" => format!("{:02}", m),
+            "day" => format!("{:02}", d),
+            "profile" => workout.profile.clone().unwrap_or_default(),
+            "label" => workout.label.clone().unwrap_or_default(),
+            "distance" => config.units.distance(workout.distance_m().round() as u32).replace(' ', ""),
+            _ => {
+                out.push_str(&rest[open..=close]);
+                rest = &rest[close + 1..];
+                continue;
+            }
+        };
+        out.push_str(&escape(&value));
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Collapse repeated `-`/`_` in a file name component and trim them from
+/// its ends.
+fn tidy_component(part: &str) -> String {
+    let mut out = String::new();
+    for c in part.chars() {
+        if matches!(c, '-' | '_') && out.ends_with(c) {
+            continue;
+        }
+        out.push(c);
+    }
+    out.trim_matches(|c| matches!(c, '-' | '_')).to_string()
+}
+
+/// Collapse whitespace in a title and trim separators an empty value left
+/// at its ends ("Run - " → "Run").
+fn tidy_text(text: &str) -> String {
+    let joined = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    joined.trim_matches(|c: char| c.is_whitespace() || matches!(c, '-' | ',' | ':' | '|')).to_string()
+}
+
+/// ISO 8601 UTC timestamp, e.g. `2024-03-15T07:15:02Z`.
+pub fn iso8601(wall_ms: u64) -> String {
+    let (y, m, d, hh, mm, ss) = utc_parts(wall_ms);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, m, d, hh, mm, ss)
+}
+
+/// ISO 8601 local timestamp with its offset, e.g. `2024-03-15T02:15:02-05:00`.
+pub fn iso8601_local(wall_ms: u64, utc_offset_secs: i32) -> String {
+    let (y, m, d, hh, mm, ss) = utc_parts(clock::local_ms(wall_ms, utc_offset_secs));
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        y,
+        m,
+        d,
+        hh,
+        mm,
+        ss,
+        clock::format_offset(utc_offset_secs)
+    )
+}
+
+/// Unix epoch ms to UTC (year, month, day, hour, minute, second).
+pub fn utc_parts(wall_ms: u64) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = wall_ms / 1000;
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    let tod = secs % 86_400;
+    (y, m, d, tod / 3600, tod % 3600 / 60, tod % 60)
+}
+
+/// Days since 1970-01-01 to a (year, month, day) civil date.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    #[test]
+    fn test_file_stem() {
+        let stem = |wall_ms, offset| file_stem(&Workout::new(wall_ms, offset), &FtmsConfig::default());
+        assert_eq!(stem(0, 0), "treadmill-19700101-000000");
+        assert_eq!(stem(1_710_486_902_000, 0), "treadmill-20240315-071502");
+        // Local time, across midnight
+        assert_eq!(stem(1_710_486_902_000, -8 * 3600), "treadmill-20240314-231502");
+        // Leap day
+        assert_eq!(stem(1_709_164_800_000, 0), "treadmill-20240229-000000");
+        assert_eq!(iso8601_local(1_710_486_902_000, -4 * 3600), "2024-03-15T03:15:02-04:00");
+        assert_eq!(iso8601(1_710_486_902_999), "2024-03-15T07:15:02Z");
+    }
+
+    #[test]
+    fn test_templates() {
+        let mut config = FtmsConfig {
+            export_name: "{year}/{month}/{profile}-{label}-{distance}-{date}_{nope}".to_string(),
+            export_title: "{label} - {distance}".to_string(),
+            export_device: Some("Precor 9.31 ({profile})".to_string()),
+            ..Default::default()
+        };
+        let mut w = sample_workout();
+        assert_eq!(file_stem(&w, &config), "2023/11/1.05mi-20231114_{nope}");
+        let meta = Metadata::new(&w, &config);
+        assert_eq!(meta.title.as_deref(), Some("1.05mi"));
+        assert_eq!(meta.device.as_deref(), Some("Precor 9.31 ()"));
+
+        w.label = Some("Hills / tempo".to_string());
+        w.profile = Some("guest".to_string());
+        config.units = crate::config::Units::Metric;
+        assert_eq!(file_stem(&w, &config), "2023/11/guest-Hills-tempo-1.69km-20231114_{nope}");
+        assert_eq!(Metadata::new(&w, &config).title.as_deref(), Some("Hills / tempo - 1.69km"));
+
+        // No climbing out of export_dir, and never an empty name
+        config.export_name = "/../{label}/..".to_string();
+        w.label = Some("../x".to_string());
+        assert_eq!(file_stem(&w, &config), "..-x");
+        config.export_name = "{label}".to_string();
+        w.label = None;
+        assert_eq!(file_stem(&w, &config), "treadmill-20231114-171320");
+        assert_eq!(Metadata::new(&w, &FtmsConfig::default()), Metadata::default());
+    }
+
+    #[tokio::test]
+    async fn test_export_writes_fit() {
+        let dir = "/tmp/ftms_test_export";
+        let _ = std::fs::remove_dir_all(dir);
+        let config = FtmsConfig {
+            export_dir: Some(dir.to_string()),
+            export_formats: vec![ExportFormat::Fit, ExportFormat::HealthConnect, ExportFormat::AppleHealth],
+            ..Default::default()
+        };
+        let workout = sample_workout();
+        let stem = file_stem(&workout, &config);
+        export(workout, Arc::new(config), Archiver::disabled()).await;
+
+        let fit = std::fs::read(Path::new(dir).join(format!("{}.fit", stem))).unwrap();
+        assert_eq!(&fit[8..12], b".FIT");
+        assert!(Path::new(dir).join(format!("{}.healthconnect.json", stem)).exists());
+        assert!(Path::new(dir).join(format!("{}.apple-health.zip", stem)).exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
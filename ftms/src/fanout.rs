@@ -0,0 +1,393 @@
+//! Notification fan-out to GATT subscribers.
+//!
+//! Each subscriber to a notifying characteristic gets its own writer task
+//! fed by a small bounded queue. Publishing never waits on a client: if a
+//! subscriber's queue is full (a slow link, or an indication the client
+//! never confirms) that subscriber skips the update while everyone else
+//! gets it, and a subscriber that stays full for `LAG_LIMIT` updates in a
+//! row is disconnected. A notify that errors `ERROR_LIMIT` times in a row,
+//! or hangs for `NOTIFY_TIMEOUT`, ends the session too: the app froze or
+//! the link died without BlueZ noticing.
+//!
+//! Every subscriber counts the notifications it was sent, how many went
+//! through, failed or were skipped, and the fanout keeps the same totals
+//! (plus sessions it tore down) across subscribers, for the debug
+//! `clients` and `metrics` commands.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, info, warn};
+use tokio::sync::mpsc;
+
+use crate::health::{Counter, Health};
+
+/// Consecutive skipped updates before a subscriber is dropped.
+pub const LAG_LIMIT: u32 = 16;
+/// Failed notifications in a row before a subscriber is dropped.
+pub const ERROR_LIMIT: u32 = 3;
+/// A single notify/indicate taking longer than this ends the session.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Notification counts, for one subscriber or all of a characteristic's.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Delivery {
+    /// Notifications handed to BlueZ.
+    pub attempted: u64,
+    /// Of those, the ones that went through.
+    pub delivered: u64,
+    /// Of those, the ones that errored or timed out.
+    pub failed: u64,
+    /// Updates never attempted because the subscriber's queue was full.
+    pub skipped: u64,
+}
+
+#[derive(Default)]
+struct Counts {
+    attempted: AtomicU64,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Counts {
+    fn snapshot(&self) -> Delivery {
+        Delivery {
+            attempted: self.attempted.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bump `field` in each of `counts`.
+fn count(counts: &[&Counts], field: fn(&Counts) -> &AtomicU64) {
+    for c in counts {
+        field(c).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// One current subscriber, for `clients`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Client {
+    pub id: u64,
+    pub connected_for: Duration,
+    pub delivery: Delivery,
+}
+
+/// The receiving end of one notification session.
+pub trait NotifySink: Send + 'static {
+    /// Resolves when the client unsubscribes.
+    fn stopped(&self) -> BoxFuture<'static, ()>;
+    fn notify(&mut self, data: Vec<u8>) -> BoxFuture<'_, Result<(), String>>;
+}
+
+impl NotifySink for bluer::gatt::local::CharacteristicNotifier {
+    fn stopped(&self) -> BoxFuture<'static, ()> {
+        bluer::gatt::local::CharacteristicNotifier::stopped(self).boxed()
+    }
+
+    fn notify(&mut self, data: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        async move { bluer::gatt::local::CharacteristicNotifier::notify(self, data).await.map_err(|e| e.to_string()) }
+            .boxed()
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    tx: mpsc::Sender<Vec<u8>>,
+    /// Updates skipped in a row because the queue was full.
+    lagging: u32,
+    counts: Arc<Counts>,
+    joined: Instant,
+}
+
+/// Subscribers of one characteristic.
+pub struct Fanout {
+    label: &'static str,
+    depth: usize,
+    subscribers: Mutex<Vec<Subscriber>>,
+    next_id: Mutex<u64>,
+    health: Health,
+    /// Every subscriber's counts since the fanout was created.
+    totals: Arc<Counts>,
+    /// Subscribers disconnected for lagging or failing.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Fanout {
+    /// `depth` updates can queue per subscriber before it starts skipping.
+    /// Deliveries and failures are counted in `health`.
+    pub fn new(label: &'static str, depth: usize, health: Health) -> Self {
+        Self {
+            label,
+            depth,
+            subscribers: Mutex::new(Vec::new()),
+            next_id: Mutex::new(0),
+            health,
+            totals: Arc::default(),
+            dropped: Arc::default(),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Current subscribers, oldest first. Ones whose session just ended
+    /// are left out even before the next publish clears them away.
+    pub fn clients(&self) -> Vec<Client> {
+        let now = Instant::now();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|sub| !sub.tx.is_closed())
+            .map(|sub| Client { id: sub.id, connected_for: now - sub.joined, delivery: sub.counts.snapshot() })
+            .collect()
+    }
+
+    /// Counts across every subscriber so far, gone ones included.
+    pub fn totals(&self) -> Delivery {
+        self.totals.snapshot()
+    }
+
+    /// Sessions torn down for lagging, failing or hanging.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Start a writer task for a new subscriber, sending `initial` first
+    /// (as much of it as the queue holds).
+    pub fn subscribe<S: NotifySink>(&self, sink: S, initial: Vec<Vec<u8>>) {
+        let (tx, rx) = mpsc::channel(self.depth);
+        for data in initial {
+            let _ = tx.try_send(data);
+        }
+        let id = {
+            let mut next = self.next_id.lock().unwrap();
+            *next += 1;
+            *next
+        };
+        let counts = Arc::new(Counts::default());
+        self.subscribers.lock().unwrap().push(Subscriber { id, tx, lagging: 0, counts: counts.clone(), joined: Instant::now() });
+        info!("{} subscriber {} joined", self.label, id);
+        let writer = Writer {
+            label: self.label,
+            id,
+            health: self.health.clone(),
+            counts,
+            totals: self.totals.clone(),
+            dropped: self.dropped.clone(),
+        };
+        tokio::spawn(writer.run(sink, rx));
+    }
+
+    /// Queue `data` for every subscriber. Never blocks.
+    pub fn publish(&self, data: &[u8]) {
+        let label = self.label;
+        let totals = &*self.totals;
+        let dropped = &self.dropped;
+        self.subscribers.lock().unwrap().retain_mut(|sub| match sub.tx.try_send(data.to_vec()) {
+            Ok(()) => {
+                if sub.lagging > 0 {
+                    info!("{} subscriber {} caught up after skipping {}", label, sub.id, sub.lagging);
+                    sub.lagging = 0;
+                }
+                true
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                count(&[&sub.counts, totals], |c| &c.skipped);
+                sub.lagging += 1;
+                if sub.lagging == 1 {
+                    warn!("{} subscriber {} is falling behind, skipping updates", label, sub.id);
+                }
+                if sub.lagging >= LAG_LIMIT {
+                    warn!("{} subscriber {} skipped {} updates, disconnecting", label, sub.id, sub.lagging);
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                true
+            }
+            // Writer task ended: the client unsubscribed or a send failed
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        });
+    }
+}
+
+/// One subscriber's writer task and what it reports to.
+struct Writer {
+    label: &'static str,
+    id: u64,
+    health: Health,
+    counts: Arc<Counts>,
+    totals: Arc<Counts>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Writer {
+    async fn run<S: NotifySink>(self, mut sink: S, mut rx: mpsc::Receiver<Vec<u8>>) {
+        let (label, id) = (self.label, self.id);
+        let counts = [&*self.counts, &*self.totals];
+        let mut stopped = sink.stopped();
+        let mut errors = 0;
+        loop {
+            let data = tokio::select! {
+                _ = &mut stopped => break,
+                data = rx.recv() => match data {
+                    Some(data) => data,
+                    None => break, // dropped for lagging
+                },
+            };
+            debug!("{} notify to subscriber {}: {} bytes", label, id, data.len());
+            count(&counts, |c| &c.attempted);
+            match tokio::time::timeout(NOTIFY_TIMEOUT, sink.notify(data)).await {
+                Ok(Ok(())) => {
+                    count(&counts, |c| &c.delivered);
+                    self.health.count(Counter::Notifications);
+                    errors = 0;
+                }
+                Ok(Err(e)) => {
+                    count(&counts, |c| &c.failed);
+                    self.health.count(Counter::Errors);
+                    errors += 1;
+                    warn!("{} notification error for subscriber {} ({} in a row): {}", label, id, errors, e);
+                    if errors >= ERROR_LIMIT {
+                        warn!("{} subscriber {} keeps failing, disconnecting", label, id);
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                }
+                Err(_) => {
+                    count(&counts, |c| &c.failed);
+                    self.health.count(Counter::Errors);
+                    warn!("{} notify to subscriber {} timed out, disconnecting", label, id);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+        let d = self.counts.snapshot();
+        info!(
+            "{} subscriber {} left: {}/{} delivered, {} failed, {} skipped",
+            label, id, d.delivered, d.attempted, d.failed, d.skipped
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Semaphore;
+
+    /// Records what it's sent; each notify waits for a permit.
+    struct TestSink {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+        permits: Arc<Semaphore>,
+    }
+
+    impl NotifySink for TestSink {
+        fn stopped(&self) -> BoxFuture<'static, ()> {
+            futures::future::pending().boxed()
+        }
+
+        fn notify(&mut self, data: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+            async move {
+                self.permits.acquire().await.unwrap().forget();
+                self.sent.lock().unwrap().push(data);
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    fn sink(permits: usize) -> (TestSink, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = TestSink { sent: sent.clone(), permits: Arc::new(Semaphore::new(permits)) };
+        (sink, sent)
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocked_subscriber_does_not_delay_others() {
+        let fanout = Fanout::new("Test", 2, Health::default());
+        let (fast, fast_sent) = sink(usize::MAX >> 4);
+        // Takes the initial value, then blocks
+        let (slow, slow_sent) = sink(1);
+        fanout.subscribe(fast, vec![vec![0]]);
+        fanout.subscribe(slow, vec![vec![0]]);
+
+        for i in 1..=5u8 {
+            fanout.publish(&[i]);
+            settle().await;
+        }
+        assert_eq!(fast_sent.lock().unwrap().len(), 6);
+        assert_eq!(*slow_sent.lock().unwrap(), vec![vec![0]]);
+        assert_eq!(fanout.subscriber_count(), 2);
+
+        // Skipping for LAG_LIMIT updates in a row drops the laggard
+        for _ in 0..LAG_LIMIT {
+            fanout.publish(&[9]);
+            settle().await;
+        }
+        assert_eq!(fanout.subscriber_count(), 1);
+        assert_eq!(fast_sent.lock().unwrap().len(), 6 + LAG_LIMIT as usize);
+        assert_eq!(fanout.health.get(Counter::Notifications), 6 + 1 + LAG_LIMIT as u64);
+
+        // The laggard's initial value went through, the next update is
+        // stuck in BlueZ with two queued behind it, and it skipped the
+        // LAG_LIMIT that got it dropped
+        let clients = fanout.clients();
+        assert_eq!(clients.len(), 1);
+        let fast = Delivery { attempted: 6 + LAG_LIMIT as u64, delivered: 6 + LAG_LIMIT as u64, failed: 0, skipped: 0 };
+        assert_eq!(clients[0].delivery, fast);
+        assert_eq!(
+            fanout.totals(),
+            Delivery { attempted: fast.attempted + 2, delivered: fast.delivered + 1, failed: 0, skipped: LAG_LIMIT as u64 }
+        );
+        assert_eq!(fanout.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_subscriber_is_removed() {
+        struct Failing;
+        impl NotifySink for Failing {
+            fn stopped(&self) -> BoxFuture<'static, ()> {
+                futures::future::pending().boxed()
+            }
+            fn notify(&mut self, _: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+                async { Err("gone".to_string()) }.boxed()
+            }
+        }
+        let fanout = Fanout::new("Test", 2, Health::default());
+        fanout.subscribe(Failing, vec![]);
+        // A failure or two is tolerated; ERROR_LIMIT in a row ends the session
+        for i in 1..ERROR_LIMIT as u8 {
+            fanout.publish(&[i]);
+            settle().await;
+        }
+        assert_eq!(fanout.clients().len(), 1);
+        fanout.publish(&[9]);
+        settle().await;
+        assert_eq!(fanout.clients(), vec![]);
+        fanout.publish(&[10]);
+        assert_eq!(fanout.subscriber_count(), 0);
+        assert_eq!(fanout.health.get(Counter::Errors), ERROR_LIMIT as u64);
+        let n = ERROR_LIMIT as u64;
+        assert_eq!(fanout.totals(), Delivery { attempted: n, delivered: 0, failed: n, skipped: 0 });
+        assert_eq!(fanout.dropped(), 1);
+    }
+}
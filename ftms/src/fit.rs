@@ -0,0 +1,392 @@
+//! FIT activity file encoding.
+//!
+//! Writes the subset of the Garmin FIT protocol Garmin Connect (and Strava,
+//! TrainingPeaks, ...) need to import a treadmill run: file_id and
+//! device_info with the configured device IDs, a sport profile (running on
+//! a treadmill or indoor walking) named after the workout, timer events, per-second records, and one lap/session/activity.
+//! All multi-byte fields are little-endian.
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::export::{Metadata, Sport};
+use crate::workout::Workout;
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31 00:00 UTC).
+const FIT_EPOCH_OFFSET: u64 = 631_065_600;
+/// FIT protocol 1.0: no developer fields, readable by everything.
+const PROTOCOL_VERSION: u8 = 0x10;
+/// Profile 21.32.
+const PROFILE_VERSION: u16 = 2132;
+
+// Global message numbers
+const MESG_FILE_ID: u16 = 0;
+const MESG_SPORT: u16 = 12;
+const MESG_SESSION: u16 = 18;
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_EVENT: u16 = 21;
+const MESG_DEVICE_INFO: u16 = 23;
+const MESG_ACTIVITY: u16 = 34;
+
+/// Common field number of `timestamp` in every message that has one.
+const FIELD_TIMESTAMP: u8 = 253;
+
+// Enum values
+const FILE_ACTIVITY: u8 = 4;
+const SPORT_RUNNING: u8 = 1;
+const SPORT_WALKING: u8 = 11;
+const SUB_SPORT_TREADMILL: u8 = 1;
+const SUB_SPORT_INDOOR_WALKING: u8 = 27;
+const EVENT_TIMER: u8 = 0;
+const EVENT_SESSION: u8 = 8;
+const EVENT_LAP: u8 = 9;
+const EVENT_ACTIVITY: u8 = 26;
+const EVENT_TYPE_START: u8 = 0;
+const EVENT_TYPE_STOP: u8 = 1;
+const EVENT_TYPE_STOP_ALL: u8 = 4;
+const ACTIVITY_MANUAL: u8 = 0;
+
+/// Device identity written to file_id/device_info. Garmin Connect only
+/// credits some features (training effect, device stats) to files that
+/// claim a Garmin device; the default is the FIT "development" manufacturer.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FitDevice {
+    /// FIT manufacturer ID (1 = Garmin, 255 = development).
+    pub manufacturer: u16,
+    pub product: u16,
+    /// 0 leaves the serial number unset.
+    pub serial_number: u32,
+    /// Software version × 100 (e.g. 100 = 1.00).
+    pub software_version: u16,
+}
+
+impl Default for FitDevice {
+    fn default() -> Self {
+        Self { manufacturer: 255, product: 0, serial_number: 0, software_version: 100 }
+    }
+}
+
+/// A typed field value. Sizes and base types follow the FIT profile.
+#[derive(Debug, Clone, Copy)]
+enum Field<'a> {
+    Enum(u8),
+    U8(u8),
+    U16(u16),
+    S16(i16),
+    U32(u32),
+    U32z(u32),
+    /// Null-terminated UTF-8; see `fit_string`.
+    Str(&'a str),
+}
+
+impl Field<'_> {
+    fn size(self) -> u8 {
+        match self {
+            Field::Enum(_) | Field::U8(_) => 1,
+            Field::U16(_) | Field::S16(_) => 2,
+            Field::U32(_) | Field::U32z(_) => 4,
+            Field::Str(s) => s.len() as u8 + 1,
+        }
+    }
+
+    fn base_type(self) -> u8 {
+        match self {
+            Field::Enum(_) => 0x00,
+            Field::Str(_) => 0x07,
+            Field::U8(_) => 0x02,
+            Field::S16(_) => 0x83,
+            Field::U16(_) => 0x84,
+            Field::U32(_) => 0x86,
+            Field::U32z(_) => 0x8C,
+        }
+    }
+
+    fn write(self, buf: &mut Vec<u8>) {
+        match self {
+            Field::Enum(v) | Field::U8(v) => buf.push(v),
+            Field::U16(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Field::S16(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Field::U32(v) | Field::U32z(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Field::Str(s) => {
+                buf.extend_from_slice(s.as_bytes());
+                buf.push(0);
+            }
+        }
+    }
+}
+
+/// Field layout of a definition: (field number, size, base type).
+type Layout = Vec<(u8, u8, u8)>;
+
+/// Builds the record section, emitting a definition message the first
+/// time each (global message, field layout) pair is used.
+#[derive(Default)]
+struct Encoder {
+    data: Vec<u8>,
+    locals: Vec<(u16, Layout)>,
+}
+
+impl Encoder {
+    fn message(&mut self, global: u16, fields: &[(u8, Field)]) {
+        let layout: Layout = fields.iter().map(|&(num, f)| (num, f.size(), f.base_type())).collect();
+        let local = match self.locals.iter().position(|(g, l)| *g == global && *l == layout) {
+            Some(i) => i as u8,
+            None => {
+                let local = self.locals.len() as u8;
+                assert!(local < 16, "out of FIT local message types");
+                self.data.extend_from_slice(&[0x40 | local, 0, 0]); // reserved, little-endian
+                self.data.extend_from_slice(&global.to_le_bytes());
+                self.data.push(layout.len() as u8);
+                for &(num, size, base) in &layout {
+                    self.data.extend_from_slice(&[num, size, base]);
+                }
+                self.locals.push((global, layout));
+                local
+            }
+        };
+        self.data.push(local);
+        for &(_, f) in fields {
+            f.write(&mut self.data);
+        }
+    }
+
+    /// Wrap the records in the file header and trailing CRC.
+    fn finish(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.data.len() + 16);
+        out.extend_from_slice(&[14, PROTOCOL_VERSION]);
+        out.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b".FIT");
+        let header_crc = crc16(&out);
+        out.extend_from_slice(&header_crc.to_le_bytes());
+        out.extend_from_slice(&self.data);
+        let crc = crc16(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+}
+
+/// FIT's CRC-16 (the ARC polynomial), computed a nibble at a time.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401,
+        0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    bytes.iter().fold(0u16, |crc, &b| {
+        let crc = (crc >> 4) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[(b & 0xF) as usize];
+        (crc >> 4) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[(b >> 4) as usize]
+    })
+}
+
+/// Unix epoch ms to a FIT timestamp (seconds since the FIT epoch).
+fn fit_time(wall_ms: u64) -> u32 {
+    (wall_ms / 1000).saturating_sub(FIT_EPOCH_OFFSET) as u32
+}
+
+/// Speed field value: mm/s.
+fn speed_field(mps: f64) -> Field<'static> {
+    Field::U16((mps * 1000.0).round().min(u16::MAX as f64 - 1.0) as u16)
+}
+
+/// `s` cut at a character boundary to fit a string field (255 bytes with
+/// the terminator).
+fn fit_string(s: &str) -> &str {
+    let mut end = s.len().min(254);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Heart rate field value, invalid (0xFF) when unknown.
+fn hr_field(hr: Option<u16>) -> Field<'static> {
+    Field::U8(hr.map(|b| b.min(254) as u8).unwrap_or(0xFF))
+}
+
+/// Encode `workout` as a FIT activity file.
+pub fn encode_activity(workout: &Workout, meta: &Metadata, device: &FitDevice) -> Vec<u8> {
+    let (sport, sub_sport) = match meta.sport {
+        Sport::Running => (Field::Enum(SPORT_RUNNING), Field::Enum(SUB_SPORT_TREADMILL)),
+        Sport::Walking => (Field::Enum(SPORT_WALKING), Field::Enum(SUB_SPORT_INDOOR_WALKING)),
+    };
+    let start = fit_time(workout.start_wall_ms);
+    let end = fit_time(workout.end_wall_ms());
+    let elapsed_ms = Field::U32(workout.elapsed_secs() * 1000);
+    let distance_cm = Field::U32((workout.distance_m() * 100.0).round() as u32);
+    let ascent_m = Field::U16(workout.elevation_gain_m().round().min(u16::MAX as f64) as u16);
+    let mut enc = Encoder::default();
+
+    enc.message(MESG_FILE_ID, &[
+        (0, Field::Enum(FILE_ACTIVITY)),
+        (1, Field::U16(device.manufacturer)),
+        (2, Field::U16(device.product)),
+        (3, Field::U32z(device.serial_number)),
+        (4, Field::U32(start)),
+    ]);
+    let mut device_info = vec![
+        (FIELD_TIMESTAMP, Field::U32(start)),
+        (0, Field::U8(0)), // device_index: creator
+        (2, Field::U16(device.manufacturer)),
+        (3, Field::U32z(device.serial_number)),
+        (4, Field::U16(device.product)),
+        (5, Field::U16(device.software_version)),
+    ];
+    if let Some(name) = &meta.device {
+        device_info.push((27, Field::Str(fit_string(name)))); // product_name
+    }
+    enc.message(MESG_DEVICE_INFO, &device_info);
+    let mut sport_profile = vec![(0, sport), (1, sub_sport)];
+    if let Some(title) = &meta.title {
+        sport_profile.push((3, Field::Str(fit_string(title)))); // name
+    }
+    enc.message(MESG_SPORT, &sport_profile);
+    enc.message(MESG_EVENT, &[
+        (FIELD_TIMESTAMP, Field::U32(start)),
+        (0, Field::Enum(EVENT_TIMER)),
+        (1, Field::Enum(EVENT_TYPE_START)),
+    ]);
+
+    for s in &workout.samples {
+        enc.message(MESG_RECORD, &[
+            (FIELD_TIMESTAMP, Field::U32(fit_time(s.wall_ms))),
+            (3, hr_field(s.heart_rate)),
+            (5, Field::U32((s.distance_m * 100.0).round() as u32)),
+            (6, speed_field(s.speed_mps())),
+            (9, Field::S16((s.grade_pct() * 100.0).round() as i16)),
+        ]);
+    }
+
+    enc.message(MESG_EVENT, &[
+        (FIELD_TIMESTAMP, Field::U32(end)),
+        (0, Field::Enum(EVENT_TIMER)),
+        (1, Field::Enum(EVENT_TYPE_STOP_ALL)),
+    ]);
+    enc.message(MESG_LAP, &[
+        (FIELD_TIMESTAMP, Field::U32(end)),
+        (0, Field::Enum(EVENT_LAP)),
+        (1, Field::Enum(EVENT_TYPE_STOP)),
+        (2, Field::U32(start)),
+        (7, elapsed_ms),
+        (8, elapsed_ms),
+        (9, distance_cm),
+        (13, speed_field(workout.avg_speed_mps())),
+        (14, speed_field(workout.max_speed_mps())),
+        (15, hr_field(workout.avg_heart_rate())),
+        (16, hr_field(workout.max_heart_rate())),
+        (21, ascent_m), // total_ascent
+        (25, sport),
+        (39, sub_sport),
+    ]);
+    enc.message(MESG_SESSION, &[
+        (FIELD_TIMESTAMP, Field::U32(end)),
+        (0, Field::Enum(EVENT_SESSION)),
+        (1, Field::Enum(EVENT_TYPE_STOP)),
+        (2, Field::U32(start)),
+        (5, sport),
+        (6, sub_sport),
+        (7, elapsed_ms),
+        (8, elapsed_ms),
+        (9, distance_cm),
+        (14, speed_field(workout.avg_speed_mps())),
+        (15, speed_field(workout.max_speed_mps())),
+        (16, hr_field(workout.avg_heart_rate())),
+        (17, hr_field(workout.max_heart_rate())),
+        (22, ascent_m), // total_ascent
+        (25, Field::U16(0)), // first_lap_index
+        (26, Field::U16(1)), // num_laps
+    ]);
+    enc.message(MESG_ACTIVITY, &[
+        (FIELD_TIMESTAMP, Field::U32(end)),
+        (0, elapsed_ms),
+        (1, Field::U16(1)), // num_sessions
+        (2, Field::Enum(ACTIVITY_MANUAL)),
+        (3, Field::Enum(EVENT_ACTIVITY)),
+        (4, Field::Enum(EVENT_TYPE_STOP)),
+        (5, Field::U32(fit_time(clock::local_ms(workout.end_wall_ms(), workout.utc_offset_secs)))), // local_timestamp
+    ]);
+
+    enc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    #[test]
+    fn test_crc16_check_value() {
+        // CRC-16/ARC check value
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn test_encode_activity_framing() {
+        let w = sample_workout();
+        let fit = encode_activity(&w, &Metadata::default(), &FitDevice::default());
+
+        assert_eq!(fit[0], 14);
+        assert_eq!(&fit[8..12], b".FIT");
+        assert_eq!(u16::from_le_bytes([fit[12], fit[13]]), crc16(&fit[..12]));
+        let data_size = u32::from_le_bytes(fit[4..8].try_into().unwrap()) as usize;
+        assert_eq!(fit.len(), 14 + data_size + 2);
+        // CRC over everything including the trailing CRC comes out zero
+        assert_eq!(crc16(&fit), 0);
+
+        let counts = count_messages(&fit[14..14 + data_size]);
+        assert_eq!(counts.get(&MESG_RECORD), Some(&660));
+        for global in [MESG_FILE_ID, MESG_DEVICE_INFO, MESG_SPORT, MESG_LAP, MESG_SESSION, MESG_ACTIVITY] {
+            assert_eq!(counts.get(&global), Some(&1), "message {}", global);
+        }
+        assert_eq!(counts.get(&MESG_EVENT), Some(&2));
+    }
+
+    #[test]
+    fn test_encode_metadata() {
+        let meta = Metadata {
+            title: Some("Hills".to_string()),
+            sport: Sport::Walking,
+            device: Some("Precor 9.31".to_string()),
+        };
+        let fit = encode_activity(&sample_workout(), &meta, &FitDevice::default());
+        assert_eq!(crc16(&fit), 0);
+        let find = |needle: &[u8]| fit.windows(needle.len()).any(|w| w == needle);
+        // sport message: walking, indoor_walking, name
+        assert!(find(&[SPORT_WALKING, SUB_SPORT_INDOOR_WALKING, b'H', b'i', b'l', b'l', b's', 0]));
+        assert!(find(b"Precor 9.31\0"));
+
+        let long = "\u{e9}".repeat(200);
+        assert_eq!(fit_string(&long).len(), 254);
+        assert_eq!(fit_string("short"), "short");
+    }
+
+    /// Walk the record section, counting data messages per global number.
+    fn count_messages(mut data: &[u8]) -> std::collections::HashMap<u16, usize> {
+        let mut locals = std::collections::HashMap::new();
+        let mut counts = std::collections::HashMap::new();
+        while let Some(&header) = data.first() {
+            let local = header & 0x0F;
+            if header & 0x40 != 0 {
+                let global = u16::from_le_bytes([data[3], data[4]]);
+                let n = data[5] as usize;
+                let size: usize = data[6..6 + n * 3].chunks(3).map(|f| f[1] as usize).sum();
+                locals.insert(local, (global, size));
+                data = &data[6 + n * 3..];
+            } else {
+                let (global, size) = locals[&local];
+                *counts.entry(global).or_insert(0) += 1;
+                data = &data[1 + size..];
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_fit_time() {
+        // 2020-01-01T00:00:00Z
+        assert_eq!(fit_time(1_577_836_800_000), 1_577_836_800 - 631_065_600);
+        assert_eq!(fit_time(0), 0);
+    }
+}
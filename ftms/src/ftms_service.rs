@@ -1,38 +1,196 @@
 //! BLE GATT server for the FTMS (Fitness Machine Service) treadmill profile.
 //!
-//! Advertises as "Precor 9.31" and exposes the standard FTMS treadmill service
+//! Advertises as "Precor 9.31" (configurable) and exposes the standard FTMS treadmill service
 //! (UUID 0x1826) so fitness apps like Zwift, QZ Fitness, and Apple Watch can
 //! read treadmill data and send control commands.
 
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bluer::{
-    adv::Advertisement,
     gatt::local::{
-        characteristic_control, Application, Characteristic, CharacteristicControlEvent,
-        CharacteristicNotify, CharacteristicNotifyMethod, CharacteristicRead,
-        CharacteristicWrite, CharacteristicWriteMethod, Service,
+        characteristic_control, service_control, Application, Characteristic,
+        CharacteristicControlEvent, CharacteristicNotify, CharacteristicNotifyMethod,
+        CharacteristicRead, CharacteristicWrite, CharacteristicWriteMethod, Service,
     },
+    AddressType,
 };
 use futures::{pin_mut, FutureExt, StreamExt};
 use log::{debug, error, info, warn};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 
+use crate::activity;
+use crate::archive::Archiver;
+use crate::audit::{Audit, Origin};
+use crate::advertising::{self, NamePlacement};
+use crate::calibration;
+use crate::config::FtmsConfig;
+use crate::fanout::Fanout;
+use crate::gatt::{self, GattTable, HandleSource, Subscribers};
+use crate::health::{Counter, Health};
+use crate::latency::{Latency, Stage};
 use crate::protocol::{
     self, CONTROL_POINT_UUID, FEATURE_UUID, FTMS_SERVICE_UUID, INCLINE_RANGE_UUID,
     MACHINE_STATUS_UUID, SPEED_RANGE_UUID, TRAINING_STATUS_UUID, TREADMILL_DATA_UUID,
 };
-use crate::treadmill::TreadmillState;
-
-/// Run the FTMS BLE GATT server. Advertises and notifies at 1 Hz.
-/// `socket_path` is passed through for control point commands that need to send
-/// speed/incline changes back to treadmill_io.
-pub async fn run(
-    state: Arc<Mutex<TreadmillState>>,
-    socket_path: String,
-) -> bluer::Result<()> {
+use crate::profile::Profiles;
+use crate::quiet;
+use crate::pause::{self, Pause};
+use crate::ramp::{self, Ramp, RampKind};
+use crate::status::CapsMsg;
+use crate::telemetry::Recorder;
+use crate::treadmill::{self, Commands, Target, TreadmillEvent, TreadmillState};
+
+/// How often to check that BlueZ still has our application registered.
+const REGISTRATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Serving this long before a loss resets the re-registration backoff.
+const STABLE_REGISTRATION: Duration = Duration::from_secs(60);
+
+/// Treadmill Data updates queued per subscriber before it skips.
+const TREADMILL_DATA_QUEUE: usize = 4;
+/// Status notifications queued per subscriber before it skips.
+const STATUS_QUEUE: usize = 8;
+
+/// Callback type for `CharacteristicNotifyMethod::Fun`.
+type NotifyFn = Box<
+    dyn Fn(bluer::gatt::local::CharacteristicNotifier) -> std::pin::Pin<Box<dyn futures::Future<Output = ()> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Everything the control point handler needs, shared by the BLE GATT
+/// server and the TCP debug server.
+#[derive(Clone)]
+pub struct ControlContext {
+    pub state: Arc<Mutex<TreadmillState>>,
+    pub socket_path: String,
+    /// Commands for treadmill_io, written over the connection `socket_path` names.
+    pub commands: Commands,
+    pub config: Arc<FtmsConfig>,
+    pub events: broadcast::Sender<TreadmillEvent>,
+    pub telemetry: Recorder,
+    pub audit: Audit,
+    pub archive: Archiver,
+    pub latency: Latency,
+    pub health: Health,
+    pub ramp: Ramp,
+    pub pause: Pause,
+    pub profiles: Profiles,
+    pub gatt: GattTable,
+}
+
+/// A context for unit tests: `config`, `events` and defaults. No
+/// treadmill_io is behind it; `commands` has no receiver, so any command
+/// that gets as far as sending fails with `CommandError::Closed`. Tests that
+/// need a connection set `socket_path` and `commands` themselves.
+#[cfg(test)]
+pub(crate) fn test_context(config: FtmsConfig, events: broadcast::Sender<TreadmillEvent>) -> ControlContext {
+    ControlContext {
+        state: Arc::new(Mutex::new(TreadmillState::default())),
+        socket_path: String::new(),
+        commands: Commands::channel().0,
+        config: Arc::new(config),
+        events,
+        telemetry: Recorder::disabled(),
+        audit: Audit::default(),
+        archive: Archiver::disabled(),
+        latency: Latency::default(),
+        health: Health::default(),
+        ramp: Ramp::default(),
+        pause: Pause::default(),
+        profiles: Profiles::default(),
+        gatt: GattTable::default(),
+    }
+}
+
+/// Run the FTMS BLE GATT server. Advertises and notifies Treadmill Data at
+/// the configured rate (1 Hz by default).
+/// Control point commands are dispatched through `ctx` back to treadmill_io.
+/// If BlueZ loses the application or advertisement (bluetoothd restarted,
+/// D-Bus hiccup, adapter reset) everything is registered again on a fresh
+/// D-Bus connection. Runs until cancelled.
+pub async fn run(ctx: ControlContext) -> bluer::Result<()> {
+    let mut backoff = Duration::from_secs(1);
+    let mut recovering = false;
+    loop {
+        let started = Instant::now();
+        match serve(ctx.clone(), recovering).await {
+            Ok(()) => warn!("FTMS GATT application lost"),
+            Err(e) => {
+                warn!("FTMS GATT server error: {}", e);
+                ctx.health.count(Counter::Errors);
+            }
+        }
+        // A registration that held for a while was a transient drop: retry fast
+        if started.elapsed() >= STABLE_REGISTRATION {
+            backoff = Duration::from_secs(1);
+        }
+        recovering = true;
+        info!("Re-registering FTMS GATT application in {:?}...", backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(30));
+    }
+}
+
+/// Why BlueZ no longer serves what we registered, if it doesn't.
+async fn registration_lost(adapter: &bluer::Adapter) -> Option<String> {
+    match adapter.is_powered().await {
+        Err(e) => return Some(format!("BlueZ unreachable: {}", e)),
+        Ok(false) => return Some("adapter powered off".to_string()),
+        Ok(true) => {}
+    }
+    // Counts registered advertisements, connected or not, so zero means a
+    // restarted bluetoothd that has forgotten ours (and the GATT app with it)
+    match adapter.active_advertising_instances().await {
+        Err(e) => Some(format!("BlueZ unreachable: {}", e)),
+        Ok(0) => Some("advertisement no longer registered".to_string()),
+        Ok(_) => None,
+    }
+}
+
+/// Whether the central at `address` is still connected, per BlueZ.
+async fn central_connected(adapter: &bluer::Adapter, address: &str) -> bool {
+    let Ok(address) = address.parse::<bluer::Address>() else {
+        return false;
+    };
+    match adapter.device(address) {
+        Ok(device) => device.is_connected().await.unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Keep the activity file current for hrm-daemon. An app subscribes to
+/// several characteristics, so the busiest one counts the apps.
+async fn publish_activity(path: String, fanouts: [Arc<Fanout>; 3]) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut last: Option<(usize, Instant)> = None;
+    loop {
+        tick.tick().await;
+        let centrals = fanouts.iter().map(|f| f.subscriber_count()).max().unwrap_or(0);
+        if let Some((n, at)) = last {
+            if n == centrals && at.elapsed() < activity::REFRESH_INTERVAL {
+                continue;
+            }
+        }
+        let record = activity::Activity { centrals, wall_ms: crate::telemetry::wall_ms() };
+        match activity::save(&path, &record).await {
+            Ok(()) => {
+                if last.map(|(n, _)| n) != Some(centrals) {
+                    debug!("Activity: {} app(s) subscribed", centrals);
+                }
+            }
+            Err(e) => warn!("Can't write activity file {}: {}", path, e),
+        }
+        last = Some((centrals, Instant::now()));
+    }
+}
+
+/// Register the application and advertisement, then serve until BlueZ
+/// loses them. Ok(()) means the registration went away.
+async fn serve(ctx: ControlContext, recovering: bool) -> bluer::Result<()> {
+    let state = ctx.state.clone();
     let session = bluer::Session::new().await?;
     let adapter = session.default_adapter().await?;
     adapter.set_powered(true).await?;
@@ -43,113 +201,130 @@ pub async fn run(
         adapter.address().await?
     );
 
+    // --- Privacy ---
+    // BlueZ owns RPA generation and rotation (main.conf `Privacy = device`);
+    // we only keep the adapter pairable so clients can bond and receive the
+    // IRK that lets them resolve us across rotations.
+    if ctx.config.privacy {
+        adapter.set_pairable(true).await?;
+        adapter.set_pairable_timeout(0).await?;
+        match adapter.address_type().await? {
+            AddressType::LeRandom => info!("Privacy: advertising with a resolvable private address"),
+            other => warn!(
+                "Privacy requested but adapter address is {} -- set `Privacy = device` in \
+                 /etc/bluetooth/main.conf (or `btmgmt privacy on` while powered off) and restart bluetoothd",
+                other
+            ),
+        }
+    }
+
     // --- Advertisement ---
-    // FTMS spec Section 3.1: Service Data must include Flags (available) + Machine Type (treadmill)
-    let ftms_service_data: Vec<u8> = vec![
-        0x01, // Flags: bit 0 = Fitness Machine Available
-        0x01, // Fitness Machine Type: bit 0 = Treadmill Supported
-    ];
-    let adv = Advertisement {
-        advertisement_type: bluer::adv::Type::Peripheral,
-        service_uuids: vec![FTMS_SERVICE_UUID].into_iter().collect(),
-        service_data: [(FTMS_SERVICE_UUID, ftms_service_data)].into_iter().collect(),
-        local_name: Some("Precor 9.31".to_string()),
-        discoverable: Some(true),
-        ..Default::default()
+    let name = ctx.config.advertised_name.as_str();
+    let secondary = if ctx.config.extended_advertising {
+        let supported: Vec<_> = adapter
+            .supported_advertising_secondary_channels()
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let channel = advertising::pick_secondary_channel(&supported);
+        if channel.is_none() {
+            warn!("Adapter has no 1M/2M secondary channel (supports {:?}), using legacy advertising", supported);
+        }
+        channel
+    } else {
+        None
     };
+    let (adv, placement) = advertising::build(name, ctx.config.name_placement, secondary);
+    if placement == NamePlacement::ScanResponse {
+        // BlueZ fills the included local name from the adapter alias
+        adapter.set_alias(name.to_string()).await?;
+    }
     let _adv_handle = adapter.advertise(adv).await?;
-    info!("Advertising as 'Precor 9.31' with FTMS service");
-
-    // --- Treadmill Data notify (1 Hz) ---
-    // Uses the Fun callback model: when a client subscribes, we spawn a task that
-    // pushes data at 1 Hz until the session is stopped.
-    let td_state = state.clone();
-    let treadmill_data_notify_fn: Box<
-        dyn Fn(bluer::gatt::local::CharacteristicNotifier) -> std::pin::Pin<Box<dyn futures::Future<Output = ()> + Send>>
-            + Send
-            + Sync,
-    > = Box::new(move |notifier| {
-        let state = td_state.clone();
-        async move {
-            tokio::spawn(async move {
-                info!(
-                    "Treadmill Data notification session started (confirming={})",
-                    notifier.confirming()
-                );
-                let mut notifier = notifier;
-                let mut interval = tokio::time::interval(Duration::from_secs(1));
-                loop {
-                    interval.tick().await;
-
-                    if notifier.is_stopped() {
-                        break;
-                    }
+    info!("Advertising as '{}' with FTMS service", name);
 
+    // --- Treadmill Data notify (1-4 Hz) ---
+    // One producer encodes the data every `treadmill_data_interval_ms` and
+    // fans it out; each subscriber gets its own bounded queue and writer
+    // task, so a slow client only skips its own updates.
+    let td_fanout = Arc::new(Fanout::new("Treadmill Data", TREADMILL_DATA_QUEUE, ctx.health.clone()));
+    let td_period = ctx.config.treadmill_data_interval();
+    let td_producer = {
+        let fanout = td_fanout.clone();
+        let state = state.clone();
+        let health = ctx.health.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(td_period);
+            loop {
+                interval.tick().await;
+                if fanout.subscriber_count() > 0 {
                     let data = state.lock().await.encode_ftms_data();
-
-                    debug!("Treadmill Data notify: {} bytes", data.len());
-                    if let Err(err) = notifier.notify(data).await {
-                        warn!("Treadmill Data notification error: {}", err);
-                        break;
-                    }
+                    fanout.publish(&data);
+                    health.touch("treadmill_data");
                 }
-                info!("Treadmill Data notification session ended");
-            });
-        }
-        .boxed()
+            }
+        })
+    };
+    let _td_producer = AbortOnDrop(td_producer);
+    let td_subscribers = td_fanout.clone();
+    let treadmill_data_notify_fn: NotifyFn = Box::new(move |notifier| {
+        info!(
+            "Treadmill Data notification session started (confirming={}, every {:?})",
+            notifier.confirming(),
+            td_period
+        );
+        td_subscribers.subscribe(notifier, vec![]);
+        async {}.boxed()
     });
 
     // --- Machine Status notify ---
-    // We need to send status updates when control commands are processed.
-    // The status notifier is shared with the control point write handler.
-    let status_notifier: Arc<Mutex<Option<bluer::gatt::local::CharacteristicNotifier>>> =
-        Arc::new(Mutex::new(None));
-
-    let sn_clone = status_notifier.clone();
-    let machine_status_notify_fn: Box<
-        dyn Fn(bluer::gatt::local::CharacteristicNotifier) -> std::pin::Pin<Box<dyn futures::Future<Output = ()> + Send>>
-            + Send
-            + Sync,
-    > = Box::new(move |notifier| {
-        let sn = sn_clone.clone();
+    // We need to send status updates when control commands are processed,
+    // so the subscribers are shared with the control point write handler.
+    let status_fanout = Arc::new(Fanout::new("Machine Status", STATUS_QUEUE, ctx.health.clone()));
+    let status_replay = Arc::new(std::sync::Mutex::new(StatusReplay::default()));
+    let ms_subscribers = status_fanout.clone();
+    let ms_replay = status_replay.clone();
+    let ms_state = state.clone();
+    let machine_status_notify_fn: NotifyFn = Box::new(move |notifier| {
+        let subscribers = ms_subscribers.clone();
+        let replay = ms_replay.clone();
+        let state = ms_state.clone();
         async move {
             info!(
                 "Machine Status notification session started (confirming={})",
                 notifier.confirming()
             );
-            // Send initial "Stopped by User" status on subscribe so client knows machine state
-            let mut notifier = notifier;
-            let _ = notifier.notify(vec![0x02, 0x01]).await;
-            // Store the notifier so control_point handler can send status updates
-            let mut sn_guard = sn.lock().await;
-            *sn_guard = Some(notifier);
+            // Send the machine state on subscribe, then the target changes an
+            // app may have sent before subscribing
+            let current = current_machine_status(&*state.lock().await);
+            let initial = replay.lock().unwrap().replay(current, Instant::now());
+            subscribers.subscribe(notifier, initial);
         }
         .boxed()
     });
 
     // --- Training Status notify ---
     // Mandatory when Control Point is exposed (FTMS spec).
-    // Notifies Idle (0x01) or Manual Mode (0x0D) on start/stop.
-    let training_notifier: Arc<Mutex<Option<bluer::gatt::local::CharacteristicNotifier>>> =
-        Arc::new(Mutex::new(None));
-
-    let tn_clone = training_notifier.clone();
-    let training_status_notify_fn: Box<
-        dyn Fn(bluer::gatt::local::CharacteristicNotifier) -> std::pin::Pin<Box<dyn futures::Future<Output = ()> + Send>>
-            + Send
-            + Sync,
-    > = Box::new(move |notifier| {
-        let tn = tn_clone.clone();
+    // Notifies Idle/Manual Mode on stop/start, and Pre-/Post-Workout while
+    // a warm-up or cool-down ramp runs.
+    let training_fanout = Arc::new(Fanout::new("Training Status", STATUS_QUEUE, ctx.health.clone()));
+    let ts_subscribers = training_fanout.clone();
+    let ts_state = state.clone();
+    let ts_ramp = ctx.ramp.clone();
+    let training_status_notify_fn: NotifyFn = Box::new(move |notifier| {
+        let subscribers = ts_subscribers.clone();
+        let state = ts_state.clone();
+        let ramp = ts_ramp.clone();
         async move {
             info!(
                 "Training Status notification session started (confirming={})",
                 notifier.confirming()
             );
-            // Send initial "Idle" status on subscribe so client knows training state
-            let mut notifier = notifier;
-            let _ = notifier.notify(vec![0x00, 0x01]).await;
-            let mut tn_guard = tn.lock().await;
-            *tn_guard = Some(notifier);
+            // Send the current status on subscribe so client knows training state
+            let status = current_training_status(&*state.lock().await, ramp.running());
+            subscribers.subscribe(notifier, vec![status]);
         }
         .boxed()
     });
@@ -158,12 +333,19 @@ pub async fn run(
     // Uses the Fun callback model: each write parses an FTMS control command,
     // dispatches it to treadmill_io, and returns an indication response.
     let (cp_control, cp_handle) = characteristic_control();
-    let cp_status_notifier = status_notifier.clone();
-    let cp_training_notifier = training_notifier.clone();
-    let cp_socket = socket_path.clone();
+    let cp_ctx = ctx.clone();
+    let ts_read_state = state.clone();
+    let ts_read_ramp = ctx.ramp.clone();
+    let ms_read_state = state.clone();
+    let speed_range_state = state.clone();
+    let incline_range_state = state.clone();
+
+    // Feature bits follow what's actually enabled
+    let capabilities = ctx.config.capabilities();
+    info!("FTMS features: machine=0x{:08x} target=0x{:08x}", capabilities.machine_features(), capabilities.target_features());
 
     // --- Build GATT Application ---
-    let app = Application {
+    let mut app = Application {
         services: vec![Service {
             uuid: FTMS_SERVICE_UUID,
             primary: true,
@@ -173,10 +355,10 @@ pub async fn run(
                     uuid: FEATURE_UUID,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(|_req| {
+                        fun: Box::new(move |_req| {
                             async move {
                                 debug!("Feature characteristic read");
-                                Ok(protocol::encode_feature().to_vec())
+                                Ok(protocol::encode_feature(&capabilities).to_vec())
                             }
                             .boxed()
                         }),
@@ -199,10 +381,11 @@ pub async fn run(
                     uuid: SPEED_RANGE_UUID,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(|_req| {
+                        fun: Box::new(move |_req| {
+                            let state = speed_range_state.clone();
                             async move {
                                 debug!("Speed range characteristic read");
-                                Ok(protocol::encode_speed_range().to_vec())
+                                Ok(state.lock().await.limits().speed_range().to_vec())
                             }
                             .boxed()
                         }),
@@ -215,10 +398,11 @@ pub async fn run(
                     uuid: INCLINE_RANGE_UUID,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(|_req| {
+                        fun: Box::new(move |_req| {
+                            let state = incline_range_state.clone();
                             async move {
                                 debug!("Incline range characteristic read");
-                                Ok(protocol::encode_incline_range().to_vec())
+                                Ok(state.lock().await.limits().incline_range().to_vec())
                             }
                             .boxed()
                         }),
@@ -232,11 +416,12 @@ pub async fn run(
                     uuid: TRAINING_STATUS_UUID,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(|_req| {
+                        fun: Box::new(move |_req| {
+                            let state = ts_read_state.clone();
+                            let ramp = ts_read_ramp.clone();
                             async move {
                                 debug!("Training Status read");
-                                // Flags=0x00 (no string), Status=0x01 (Idle)
-                                Ok(vec![0x00, 0x01])
+                                Ok(current_training_status(&*state.lock().await, ramp.running()))
                             }
                             .boxed()
                         }),
@@ -272,11 +457,11 @@ pub async fn run(
                     uuid: MACHINE_STATUS_UUID,
                     read: Some(CharacteristicRead {
                         read: true,
-                        fun: Box::new(|_req| {
+                        fun: Box::new(move |_req| {
+                            let state = ms_read_state.clone();
                             async move {
                                 debug!("Machine Status read");
-                                // Default: Stopped by User (0x02, param 0x01=stop)
-                                Ok(vec![0x02, 0x01])
+                                Ok(current_machine_status(&*state.lock().await))
                             }
                             .boxed()
                         }),
@@ -295,17 +480,72 @@ pub async fn run(
         ..Default::default()
     };
 
+    // Controls on every characteristic so the debug `gatt` command can show
+    // the handles BlueZ assigns; the Control Point's own control publishes
+    // its handle from the event loop below.
+    let (service_control, service_handle) = service_control();
+    let cp_handle_value = Arc::new(AtomicU16::new(0));
+    let cp_indicating = Arc::new(AtomicBool::new(false));
+    let service = &mut app.services[0];
+    service.control_handle = service_handle;
+    let entries = service
+        .characteristics
+        .iter_mut()
+        .map(|c| {
+            let handle = if c.uuid == CONTROL_POINT_UUID {
+                HandleSource::Shared(cp_handle_value.clone())
+            } else {
+                let (control, handle) = characteristic_control();
+                c.control_handle = handle;
+                HandleSource::Control(control)
+            };
+            let subscribers = match c.uuid {
+                TREADMILL_DATA_UUID => Subscribers::Fanout(td_fanout.clone()),
+                MACHINE_STATUS_UUID => Subscribers::Fanout(status_fanout.clone()),
+                TRAINING_STATUS_UUID => Subscribers::Fanout(training_fanout.clone()),
+                CONTROL_POINT_UUID => Subscribers::Indications(cp_indicating.clone()),
+                _ => Subscribers::None,
+            };
+            gatt::Entry { uuid: c.uuid, properties: gatt::properties(c), handle, subscribers }
+        })
+        .collect();
+
     let _app_handle = adapter.serve_gatt_application(app).await?;
-    info!("FTMS GATT service registered");
+    let _gatt_registration = ctx.gatt.register(service_control, entries);
+    let _activity = ctx.config.activity_file.clone().map(|path| {
+        let fanouts = [td_fanout.clone(), status_fanout.clone(), training_fanout.clone()];
+        AbortOnDrop(tokio::spawn(publish_activity(path, fanouts)))
+    });
+    if recovering {
+        info!("FTMS GATT service re-registered after BlueZ lost it");
+        ctx.health.count(Counter::Reconnects);
+    } else {
+        info!("FTMS GATT service registered");
+    }
 
     // --- Control Point event loop ---
     // Process write requests (commands) and notify events (indication subscribers)
     // from the IO-mode control point characteristic.
     let mut cp_reader: Option<bluer::gatt::CharacteristicReader> = None;
+    // Who the current write session belongs to, for the audit trail and
+    // control ownership
+    let mut cp_origin = Origin::Ble("unknown".to_string());
+    let mut cp_device: Option<String> = None;
+    let mut control = ControlOwner::default();
     let mut cp_writer: Option<bluer::gatt::CharacteristicWriter> = None;
     let mut read_buf = Vec::new();
+    let mut events = ctx.events.subscribe();
+    let mut ramps_finished = ctx.ramp.finished();
+    // Last Training Status published, so repeats aren't notified
+    let mut training_status = current_training_status(&*state.lock().await, ctx.ramp.running());
 
     pin_mut!(cp_control);
+    let mut registration_check = tokio::time::interval(REGISTRATION_CHECK_INTERVAL);
+    registration_check.tick().await;
+    let publish_cp_handle = |control: &bluer::gatt::local::CharacteristicControl| {
+        cp_handle_value.store(control.handle().map_or(0, |h| h.get()), Ordering::Relaxed)
+    };
+    publish_cp_handle(&cp_control);
 
     info!("FTMS service running");
 
@@ -320,6 +560,11 @@ pub async fn run(
                             req.device_address(), req.mtu()
                         );
                         read_buf = vec![0u8; req.mtu()];
+                        let device = req.device_address().to_string();
+                        // The previous session's reader is dropped below, but
+                        // its central keeps control while it stays connected
+                        cp_device = Some(device.clone());
+                        cp_origin = Origin::Ble(device);
                         match req.accept() {
                             Ok(reader) => cp_reader = Some(reader),
                             Err(e) => error!("Failed to accept CP write: {}", e),
@@ -331,6 +576,7 @@ pub async fn run(
                             notifier.device_address(), notifier.mtu()
                         );
                         cp_writer = Some(notifier);
+                        cp_indicating.store(true, Ordering::Relaxed);
                     }
                     None => {
                         info!("Control Point control stream ended");
@@ -339,6 +585,60 @@ pub async fn run(
                 }
             }
 
+            _ = registration_check.tick() => {
+                if let Some(reason) = registration_lost(&adapter).await {
+                    warn!("FTMS GATT registration lost: {}", reason);
+                    return Ok(());
+                }
+                ctx.health.touch("gatt");
+                publish_cp_handle(&cp_control);
+                // An owner whose write session was replaced is only seen
+                // leaving through BlueZ
+                if let Some(owner) = control.owner().map(str::to_string) {
+                    if cp_device.as_deref() != Some(owner.as_str()) && !central_connected(&adapter, &owner).await {
+                        control.release(&owner);
+                    }
+                }
+            }
+
+            // Turn treadmill_io link events into Machine Status (and, for
+            // pauses, resumes and program steps, Training Status) notifications
+            event = events.recv() => {
+                match event {
+                    Ok(event) => {
+                        if let Some(status_data) = encode_event_status(&event) {
+                            publish_machine_status(&status_fanout, &status_replay, &status_data);
+                        }
+                        if let Some(ts_data) = encode_event_training_status(&event) {
+                            publish_training_status(&training_fanout, &mut training_status, ts_data);
+                        }
+                        if let TreadmillEvent::Step(_) = event {
+                            let ts_data = current_training_status(&*state.lock().await, ctx.ramp.running());
+                            publish_training_status(&training_fanout, &mut training_status, ts_data);
+                        }
+                        // Reaching a target is a Stop: Post-Workout while cooling down
+                        if let TreadmillEvent::DistanceReached(_) | TreadmillEvent::TrainingTimeReached(_) = event {
+                            let stop = protocol::ControlCommand::StopOrPause(0x01);
+                            if let Some(ts_data) = encode_training_status(&stop, ctx.ramp.running()) {
+                                publish_training_status(&training_fanout, &mut training_status, ts_data);
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("FTMS service missed {} treadmill events", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {}
+                }
+            }
+
+            // A warm-up reaching its target is Manual Mode; a finished
+            // cool-down has stopped the belt
+            finished = ramps_finished.recv() => {
+                if let Ok(kind) = finished {
+                    publish_training_status(&training_fanout, &mut training_status, ramp_finished_training_status(kind));
+                }
+            }
+
             // Read incoming control point writes
             read_res = async {
                 match &mut cp_reader {
@@ -350,44 +650,49 @@ pub async fn run(
                     Ok(0) => {
                         info!("Control Point write stream ended");
                         cp_reader = None;
+                        if let Some(device) = cp_device.take() {
+                            control.release(&device);
+                        }
                     }
                     Ok(n) => {
                         let bytes = &read_buf[..n];
                         debug!("Control Point write: {} bytes {:02x?}", n, bytes);
+                        ctx.health.touch("gatt");
 
                         // Parse and handle the FTMS control command
                         let (opcode, result) = match protocol::parse_control_point(bytes) {
                             Some(cmd) => {
-                                // Send Machine Status notification for this command
-                                if let Some(status_data) = encode_status_notification(&cmd) {
-                                    let mut sn = cp_status_notifier.lock().await;
-                                    if let Some(notifier) = sn.as_mut() {
-                                        if notifier.is_stopped() {
-                                            *sn = None;
-                                        } else if let Err(e) = notifier.notify(status_data).await {
-                                            warn!("Status notification error: {}", e);
-                                            *sn = None;
-                                        }
-                                    }
+                                let device = cp_device.as_deref().unwrap_or_default();
+                                let (opcode, result) = if control.permits(&cmd, device) {
+                                    handle_control_command(&cmd, &cp_ctx, &cp_origin).await
+                                } else {
+                                    warn!("FTMS: {:?} from {} refused, it doesn't hold control", cmd, device);
+                                    refuse_control_command(&cmd, &cp_ctx, &cp_origin)
+                                };
+                                if result == protocol::RESULT_SUCCESS && cmd == protocol::ControlCommand::RequestControl {
+                                    control.grant(device);
                                 }
 
-                                // Send Training Status notification on start/stop
-                                if let Some(ts_data) = encode_training_status(&cmd) {
-                                    let mut tn = cp_training_notifier.lock().await;
-                                    if let Some(notifier) = tn.as_mut() {
-                                        if notifier.is_stopped() {
-                                            *tn = None;
-                                        } else if let Err(e) = notifier.notify(ts_data).await {
-                                            warn!("Training Status notification error: {}", e);
-                                            *tn = None;
-                                        }
+                                if result == protocol::RESULT_SUCCESS {
+                                    // Machine Status carries the target actually applied
+                                    // (rounded to the treadmill's resolution), not the raw request
+                                    let limits = cp_ctx.state.lock().await.limits();
+                                    if let Some(status_data) = encode_status_notification(&applied_command(&cmd, limits)) {
+                                        publish_machine_status(&status_fanout, &status_replay, &status_data);
+                                    }
+
+                                    // Send Training Status notification on start/stop,
+                                    // and as ramps begin
+                                    if let Some(ts_data) = encode_training_status(&cmd, cp_ctx.ramp.running()) {
+                                        publish_training_status(&training_fanout, &mut training_status, ts_data);
                                     }
                                 }
 
-                                handle_control_command(&cmd, &cp_socket).await
+                                (opcode, result)
                             }
                             None => {
                                 warn!("Unknown control point opcode: 0x{:02x}", bytes[0]);
+                                cp_ctx.audit.unsupported(&cp_origin, bytes);
                                 (bytes[0], protocol::RESULT_NOT_SUPPORTED)
                             }
                         };
@@ -397,15 +702,23 @@ pub async fn run(
                         // complete 3-byte response as one BLE indication.
                         let response = protocol::encode_control_response(opcode, result);
                         if let Some(writer) = cp_writer.as_mut() {
-                            if let Err(e) = writer.write(&response).await {
-                                warn!("Control Point indication error: {}", e);
-                                cp_writer = None;
+                            match writer.write(&response).await {
+                                Ok(_) => ctx.health.count(Counter::Notifications),
+                                Err(e) => {
+                                    warn!("Control Point indication error: {}", e);
+                                    ctx.health.count(Counter::Errors);
+                                    cp_writer = None;
+                                    cp_indicating.store(false, Ordering::Relaxed);
+                                }
                             }
                         }
                     }
                     Err(e) => {
                         warn!("Control Point read error: {}", e);
                         cp_reader = None;
+                        if let Some(device) = cp_device.take() {
+                            control.release(&device);
+                        }
                     }
                 }
             }
@@ -423,23 +736,164 @@ pub async fn run(
 /// same code path regardless of transport.
 pub async fn handle_control_command(
     cmd: &protocol::ControlCommand,
-    socket_path: &str,
+    ctx: &ControlContext,
+    origin: &Origin,
 ) -> (u8, u8) {
+    // A Pause or Start goes through the pause itself; a Stop or a new
+    // speed means an app took over
+    if matches!(cmd, protocol::ControlCommand::SetTargetSpeed(_))
+        || matches!(cmd, protocol::ControlCommand::StopOrPause(param) if *param != 0x02)
+    {
+        pause::lift(ctx).await;
+    }
+    let (opcode, result) = dispatch_control_command(cmd, ctx, Instant::now()).await;
+    record_control_command(cmd, ctx, origin, opcode, result);
+    (opcode, result)
+}
+
+/// Answer a BLE command from a client that doesn't hold control with
+/// Control Not Permitted, recorded like any other command.
+fn refuse_control_command(cmd: &protocol::ControlCommand, ctx: &ControlContext, origin: &Origin) -> (u8, u8) {
+    let (opcode, result) = (cmd.opcode(), protocol::RESULT_CONTROL_NOT_PERMITTED);
+    record_control_command(cmd, ctx, origin, opcode, result);
+    (opcode, result)
+}
+
+fn record_control_command(cmd: &protocol::ControlCommand, ctx: &ControlContext, origin: &Origin, opcode: u8, result: u8) {
+    ctx.telemetry.control(cmd, result);
+    ctx.audit.control(origin, opcode, cmd, result);
+    ctx.health.count(Counter::Commands);
+    if result != protocol::RESULT_SUCCESS {
+        ctx.health.count(Counter::Errors);
+    }
+}
+
+/// Which BLE central holds FTMS control. Per the FTMS spec a client has to
+/// send Request Control before any other Control Point command; a Request
+/// Control while another central holds control is refused. Control is
+/// released when the owner's Control Point write session ends or, if
+/// another central's session has replaced it, once BlueZ no longer has
+/// the owner connected. The debug port and gRPC aren't BLE clients and
+/// don't need control.
+#[derive(Debug, Default)]
+struct ControlOwner(Option<String>);
+
+impl ControlOwner {
+    /// Whether `device` may send `cmd`.
+    fn permits(&self, cmd: &protocol::ControlCommand, device: &str) -> bool {
+        match &self.0 {
+            Some(owner) => owner == device,
+            None => *cmd == protocol::ControlCommand::RequestControl,
+        }
+    }
+
+    fn grant(&mut self, device: &str) {
+        if self.0.as_deref() != Some(device) {
+            info!("FTMS: {} has control", device);
+            self.0 = Some(device.to_string());
+        }
+    }
+
+    fn owner(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Release control if `device` holds it.
+    fn release(&mut self, device: &str) {
+        if self.0.as_deref() == Some(device) {
+            info!("FTMS: {} released control", device);
+            self.0 = None;
+        }
+    }
+}
+
+async fn dispatch_control_command(
+    cmd: &protocol::ControlCommand,
+    ctx: &ControlContext,
+    received: Instant,
+) -> (u8, u8) {
+    let commands = &ctx.commands;
     match cmd {
         protocol::ControlCommand::RequestControl => {
             info!("FTMS: client requested control");
             (0x00, protocol::RESULT_SUCCESS)
         }
         protocol::ControlCommand::SetTargetSpeed(kmh_hundredths) => {
-            let mph_tenths = protocol::kmh_hundredths_to_mph_tenths(*kmh_hundredths);
-            let mph = (mph_tenths as f64 / 10.0).clamp(0.0, 12.0); // Safety clamp: max 12.0 mph
-            info!(
-                "FTMS: set speed to {:.1} mph ({} km/h*100)",
-                mph, kmh_hundredths
-            );
+            let limits = ctx.state.lock().await.limits();
+            let mph_tenths = limits.speed_target_tenths(*kmh_hundredths);
+            let mph = mph_tenths as f64 / 10.0;
+            let requested = protocol::kmh_hundredths_to_mph_tenths(*kmh_hundredths);
+            if limits.speed_correction.is_some() {
+                info!(
+                    "FTMS: set speed to {} ({} km/h*100), {} at treadmill_io",
+                    ctx.config.units.speed_tenths(requested),
+                    kmh_hundredths,
+                    ctx.config.units.speed_tenths(mph_tenths)
+                );
+            } else {
+                info!(
+                    "FTMS: set speed to {} ({} km/h*100)",
+                    ctx.config.units.speed_tenths(mph_tenths),
+                    kmh_hundredths
+                );
+            }
+            if !limits.speed_in_range(*kmh_hundredths) {
+                warn!(
+                    "FTMS: speed {} km/h*100 refused, outside the supported range (max {})",
+                    kmh_hundredths,
+                    ctx.config.units.speed_tenths(limits.max_speed_tenths_mph)
+                );
+                return (0x02, protocol::RESULT_INVALID_PARAM);
+            }
+            if let Some((name, caps)) = ctx.profiles.active(&ctx.config) {
+                if !caps.allows_speed(requested) {
+                    warn!(
+                        "FTMS: speed {} refused, profile {:?} allows {}",
+                        ctx.config.units.speed_tenths(requested),
+                        name,
+                        caps.describe(&ctx.config)
+                    );
+                    return (0x02, protocol::RESULT_INVALID_PARAM);
+                }
+            }
+            if let Some(quiet) = quiet::now(&ctx.config) {
+                if !quiet.allows_speed(requested) {
+                    warn!(
+                        "FTMS: speed {} refused, {}",
+                        ctx.config.units.speed_tenths(requested),
+                        quiet.describe(&ctx.config)
+                    );
+                    return (0x02, protocol::RESULT_INVALID_PARAM);
+                }
+            }
+
+            if already_at(ctx, Target::Speed(mph_tenths)).await {
+                return (0x02, protocol::RESULT_SUCCESS);
+            }
 
-            match crate::treadmill::send_speed(socket_path, mph).await {
-                Ok(()) => (0x02, protocol::RESULT_SUCCESS),
+            let warm_up = ctx.ramp.take_warmup() && ctx.config.warmup_secs > 0;
+            ctx.ramp.cancel();
+            let current = ctx.state.lock().await.speed_tenths_mph;
+            if warm_up && mph_tenths > current {
+                // Supersede any pending verifier so it can't re-send an old
+                // speed under the ramp.
+                let gen = ctx.state.lock().await.begin_command(Target::Speed(mph_tenths));
+                info!("FTMS: warming up to {} over {}s", ctx.config.units.speed_tenths(mph_tenths), ctx.config.warmup_secs);
+                let steps = ramp::steps(current, mph_tenths, ctx.config.warmup_secs);
+                let verify = verify(ctx, Target::Speed(mph_tenths), gen);
+                ctx.ramp.start(RampKind::WarmUp, commands.clone(), steps, async move {
+                    verify.await;
+                });
+                return (0x02, protocol::RESULT_SUCCESS);
+            }
+
+            let gen = ctx.state.lock().await.begin_command(Target::Speed(mph_tenths));
+            match treadmill::send_speed(commands, mph).await {
+                Ok(()) => {
+                    ctx.latency.record(Stage::Send, received.elapsed());
+                    spawn_verifier(ctx, Target::Speed(mph_tenths), gen, received);
+                    (0x02, protocol::RESULT_SUCCESS)
+                }
                 Err(e) => {
                     error!("FTMS: failed to send speed command: {}", e);
                     (0x02, protocol::RESULT_FAILED)
@@ -447,18 +901,48 @@ pub async fn handle_control_command(
             }
         }
         protocol::ControlCommand::SetTargetInclination(incline_tenths) => {
-            // FTMS sends tenths of percent (e.g. 50 = 5.0%). Convert to float percent
-            // and round to nearest 0.5 for the treadmill's half-percent resolution.
-            let pct = (*incline_tenths as f64 / 10.0).clamp(0.0, 15.0);
-            // Round to nearest 0.5
-            let incline = (pct * 2.0).round() / 2.0;
+            let limits = ctx.state.lock().await.limits();
+            let half_pct = limits.incline_target_half_pct(*incline_tenths);
+            let incline = half_pct as f64 / 2.0;
             info!(
                 "FTMS: set incline to {:.1}% ({} tenths)",
                 incline, incline_tenths
             );
+            if !limits.incline_in_range(*incline_tenths) {
+                warn!(
+                    "FTMS: incline {:.1}% refused, outside the supported range ({:.1}-{:.1}%)",
+                    *incline_tenths as f64 / 10.0,
+                    limits.min_incline_half_pct as f64 / 2.0,
+                    limits.max_incline_half_pct as f64 / 2.0
+                );
+                return (0x03, protocol::RESULT_INVALID_PARAM);
+            }
+            if let Some((name, caps)) = ctx.profiles.active(&ctx.config) {
+                if !caps.allows_incline(*incline_tenths) {
+                    warn!(
+                        "FTMS: incline {:.1}% refused, profile {:?} allows {}",
+                        *incline_tenths as f64 / 10.0,
+                        name,
+                        caps.describe(&ctx.config)
+                    );
+                    return (0x03, protocol::RESULT_INVALID_PARAM);
+                }
+            }
+
+            if queue_incline(ctx, half_pct).await {
+                return (0x03, protocol::RESULT_SUCCESS);
+            }
+            if already_at(ctx, Target::Incline(half_pct)).await {
+                return (0x03, protocol::RESULT_SUCCESS);
+            }
 
-            match crate::treadmill::send_incline(socket_path, incline).await {
-                Ok(()) => (0x03, protocol::RESULT_SUCCESS),
+            let gen = ctx.state.lock().await.begin_command(Target::Incline(half_pct));
+            match treadmill::send_incline(commands, incline).await {
+                Ok(()) => {
+                    ctx.latency.record(Stage::Send, received.elapsed());
+                    spawn_verifier(ctx, Target::Incline(half_pct), gen, received);
+                    (0x03, protocol::RESULT_SUCCESS)
+                }
                 Err(e) => {
                     error!("FTMS: failed to send incline command: {}", e);
                     (0x03, protocol::RESULT_FAILED)
@@ -467,8 +951,27 @@ pub async fn handle_control_command(
         }
         protocol::ControlCommand::StartOrResume => {
             info!("FTMS: start/resume");
-            match crate::treadmill::send_start(socket_path).await {
-                Ok(()) => (0x07, protocol::RESULT_SUCCESS),
+            if let Some(quiet) = quiet::now(&ctx.config).filter(|q| !q.allows_start()) {
+                warn!("FTMS: start refused, {}", quiet.describe(&ctx.config));
+                return (0x07, protocol::RESULT_CONTROL_NOT_PERMITTED);
+            }
+            if ctx.state.lock().await.app_paused.is_some() {
+                return match pause::resume(ctx).await {
+                    Ok(_) => (0x07, protocol::RESULT_SUCCESS),
+                    Err(e) => {
+                        error!("FTMS: failed to resume: {}", e);
+                        (0x07, protocol::RESULT_FAILED)
+                    }
+                };
+            }
+            match treadmill::send_start(commands).await {
+                Ok(()) => {
+                    ctx.latency.record(Stage::Send, received.elapsed());
+                    if ctx.config.warmup_secs > 0 {
+                        ctx.ramp.arm_warmup();
+                    }
+                    (0x07, protocol::RESULT_SUCCESS)
+                }
                 Err(e) => {
                     error!("FTMS: failed to send start command: {}", e);
                     (0x07, protocol::RESULT_FAILED)
@@ -477,34 +980,459 @@ pub async fn handle_control_command(
         }
         protocol::ControlCommand::StopOrPause(param) => {
             info!("FTMS: stop/pause (param={})", param);
-            match crate::treadmill::send_stop(socket_path).await {
-                Ok(()) => (0x08, protocol::RESULT_SUCCESS),
+            if *param == 0x02 {
+                // Keep the session, frozen, to resume at the same speed. A
+                // belt that isn't moving has nothing to pause: stop it.
+                let s = ctx.state.lock().await;
+                let (paused, moving) = (s.app_paused.is_some(), s.speed_tenths_mph > 0);
+                drop(s);
+                if paused {
+                    return (0x08, protocol::RESULT_SUCCESS);
+                }
+                if moving {
+                    return match pause::pause(ctx, None).await {
+                        Ok(_) => (0x08, protocol::RESULT_SUCCESS),
+                        Err(e) => {
+                            error!("FTMS: failed to pause: {}", e);
+                            (0x08, protocol::RESULT_FAILED)
+                        }
+                    };
+                }
+            }
+            let was_cooling = ctx.ramp.cancel() == Some(RampKind::CoolDown);
+            let current = ctx.state.lock().await.speed_tenths_mph;
+            if *param == 0x01 && !was_cooling && ctx.config.cooldown_secs > 0 && current > 0 {
+                info!("FTMS: cooling down over {}s", ctx.config.cooldown_secs);
+                let gens = stop_gens(ctx).await;
+                let steps = ramp::steps(current, 0, ctx.config.cooldown_secs);
+                let stop_speed = verify(ctx, Target::Speed(0), gens.0);
+                let stop_incline = verify(ctx, Target::Incline(0), gens.1);
+                let stop = commands.clone();
+                ctx.ramp.start(RampKind::CoolDown, commands.clone(), steps, async move {
+                    if let Err(e) = treadmill::send_stop(&stop).await {
+                        error!("FTMS: failed to stop after cool-down: {}", e);
+                    }
+                    tokio::join!(stop_speed, stop_incline);
+                });
+                return (0x08, protocol::RESULT_SUCCESS);
+            }
+            if was_cooling {
+                warn!("FTMS: stop during cool-down, stopping the belt now");
+            }
+            // Zero targets supersede any pending verifier, so nothing
+            // re-sends a speed after a stop.
+            let gens = stop_gens(ctx).await;
+            match treadmill::send_stop(commands).await {
+                Ok(()) => {
+                    ctx.latency.record(Stage::Send, received.elapsed());
+                    spawn_verifier(ctx, Target::Speed(0), gens.0, received);
+                    spawn_verifier(ctx, Target::Incline(0), gens.1, received);
+                    (0x08, protocol::RESULT_SUCCESS)
+                }
                 Err(e) => {
                     error!("FTMS: failed to send stop command: {}", e);
                     (0x08, protocol::RESULT_FAILED)
                 }
             }
         }
+        protocol::ControlCommand::SetTargetedDistance(meters) => {
+            let mut s = ctx.state.lock().await;
+            if *meters == 0 {
+                info!("FTMS: targeted distance cleared");
+                s.distance_target = None;
+                return (0x0C, protocol::RESULT_SUCCESS);
+            }
+            // Already covered: the belt would stop on the next status
+            if *meters <= s.distance_meters {
+                warn!(
+                    "FTMS: targeted distance {} refused, already at {}",
+                    ctx.config.units.distance(*meters),
+                    ctx.config.units.distance(s.distance_meters)
+                );
+                return (0x0C, protocol::RESULT_INVALID_PARAM);
+            }
+            info!("FTMS: targeted distance {} ({} m)", ctx.config.units.distance(*meters), meters);
+            s.distance_target = Some(*meters);
+            (0x0C, protocol::RESULT_SUCCESS)
+        }
+        protocol::ControlCommand::SetTargetedTrainingTime(secs) => {
+            let mut s = ctx.state.lock().await;
+            if *secs == 0 {
+                info!("FTMS: targeted training time cleared");
+                s.training_time_target = None;
+                return (0x0D, protocol::RESULT_SUCCESS);
+            }
+            // Already run: the belt would stop on the next tick
+            if *secs <= s.elapsed_secs {
+                warn!("FTMS: targeted training time {}s refused, already at {}s", secs, s.elapsed_secs);
+                return (0x0D, protocol::RESULT_INVALID_PARAM);
+            }
+            info!("FTMS: targeted training time {}:{:02}", secs / 60, secs % 60);
+            s.training_time_target = Some(*secs);
+            (0x0D, protocol::RESULT_SUCCESS)
+        }
+    }
+}
+
+/// Safety clamp: max 12.0 mph.
+const MAX_SPEED_TENTHS_MPH: u16 = 120;
+/// Max 15.0% incline, in the treadmill's half-percent units.
+const MAX_INCLINE_HALF_PCT: u16 = 30;
+
+/// Bounds for FTMS targets and the Supported Speed/Inclination Range
+/// characteristics: the safety max above, lowered to whatever
+/// treadmill_io reports through `caps` when that's less. The incline floor
+/// is 0% unless `caps` reports decline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_speed_tenths_mph: u16,
+    /// Half-percent units; negative with decline.
+    pub min_incline_half_pct: i16,
+    pub max_incline_half_pct: u16,
+    /// Actual belt speed over treadmill_io's. FTMS speeds are actual ones;
+    /// `max_speed_tenths_mph` and commanded speeds are treadmill_io's.
+    pub speed_correction: Option<f64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_speed_tenths_mph: MAX_SPEED_TENTHS_MPH,
+            min_incline_half_pct: 0,
+            max_incline_half_pct: MAX_INCLINE_HALF_PCT,
+            speed_correction: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn from_caps(caps: Option<&CapsMsg>) -> Self {
+        let limits = Self::default();
+        match caps {
+            Some(caps) => Self {
+                max_speed_tenths_mph: limits.max_speed_tenths_mph.min(caps.max_speed),
+                min_incline_half_pct: if caps.decline { caps.min_incline.min(0) } else { 0 },
+                max_incline_half_pct: limits.max_incline_half_pct.min(caps.max_incline),
+                ..limits
+            },
+            None => limits,
+        }
+    }
+
+    /// The speed actually commanded for an FTMS target: converted to the
+    /// treadmill's 0.1 mph resolution, scaled back by the speed correction
+    /// and clamped to the max (targets over the range are refused before
+    /// this; the clamp only catches rounding at the top).
+    pub fn speed_target_tenths(&self, kmh_hundredths: u16) -> u16 {
+        let mph_tenths = protocol::kmh_hundredths_to_mph_tenths(kmh_hundredths);
+        calibration::uncorrect_tenths(mph_tenths, self.speed_correction).min(self.max_speed_tenths_mph)
+    }
+
+    /// A commanded speed (tenths of mph) as FTMS reports it: corrected, in
+    /// km/h * 100.
+    pub fn reported_kmh_hundredths(&self, mph_tenths: u16) -> u16 {
+        protocol::mph_hundredths_to_kmh_hundredths(calibration::correct(mph_tenths as u32 * 10, self.speed_correction))
+    }
+
+    /// The incline actually commanded for an FTMS target (tenths of percent,
+    /// e.g. 50 = 5.0%): rounded to the treadmill's half-percent resolution.
+    /// Targets outside the range are refused before this.
+    pub fn incline_target_half_pct(&self, incline_tenths: i16) -> i16 {
+        (incline_tenths as f64 / 5.0).round() as i16
+    }
+
+    /// Whether an FTMS speed target is within the Supported Speed Range.
+    /// 0 is accepted too: it's how apps stop the belt.
+    pub fn speed_in_range(&self, kmh_hundredths: u16) -> bool {
+        kmh_hundredths == 0
+            || (protocol::SPEED_RANGE_MIN..=self.reported_kmh_hundredths(self.max_speed_tenths_mph)).contains(&kmh_hundredths)
+    }
+
+    /// Whether an FTMS incline target is within the Supported Inclination
+    /// Range.
+    pub fn incline_in_range(&self, incline_tenths: i16) -> bool {
+        (self.min_incline_half_pct * 5..=self.max_incline_half_pct as i16 * 5).contains(&incline_tenths)
+    }
+
+    pub fn speed_range(&self) -> [u8; 6] {
+        protocol::encode_speed_range(self.reported_kmh_hundredths(self.max_speed_tenths_mph))
+    }
+
+    pub fn incline_range(&self) -> [u8; 6] {
+        protocol::encode_incline_range(self.min_incline_half_pct * 5, self.max_incline_half_pct as i16 * 5)
+    }
+}
+
+/// `cmd` with its target quantized to what's actually commanded (0.1 mph,
+/// 0.5%), back in FTMS units, so status notifications match what the
+/// treadmill does. Only in-range targets get this far.
+pub fn applied_command(cmd: &protocol::ControlCommand, limits: Limits) -> protocol::ControlCommand {
+    use protocol::ControlCommand::*;
+    match *cmd {
+        SetTargetSpeed(kmh_hundredths) => {
+            SetTargetSpeed(limits.reported_kmh_hundredths(limits.speed_target_tenths(kmh_hundredths)))
+        }
+        SetTargetInclination(incline_tenths) => {
+            SetTargetInclination(limits.incline_target_half_pct(incline_tenths) * 5)
+        }
+        RequestControl => RequestControl,
+        StartOrResume => StartOrResume,
+        StopOrPause(param) => StopOrPause(param),
+        SetTargetedDistance(meters) => SetTargetedDistance(meters),
+        SetTargetedTrainingTime(secs) => SetTargetedTrainingTime(secs),
+    }
+}
+
+/// Aborts a background task when the GATT server it serves goes away.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Target verification for `target` per config, to await later. `gen` is
+/// the generation `begin_command` gave it before the command went out.
+fn verify(ctx: &ControlContext, target: Target, gen: u32) -> impl std::future::Future<Output = bool> + Send + 'static {
+    treadmill::verify_target(
+        ctx.state.clone(),
+        ctx.commands.clone(),
+        ctx.events.clone(),
+        target,
+        gen,
+        Duration::from_millis(ctx.config.target_verify_timeout_ms),
+        ctx.config.target_retries,
+    )
+}
+
+/// Whether treadmill_io already reports `target` (and, for speed, no ramp
+/// is about to move the belt off it), so sending it again would only add
+/// serial-bus traffic (Zwift re-sends its targets constantly). The target
+/// still becomes the latest command, so any verifier chasing an older one
+/// stands down.
+async fn already_at(ctx: &ControlContext, target: Target) -> bool {
+    if matches!(target, Target::Speed(_)) && ctx.ramp.running().is_some() {
+        return false;
+    }
+    let mut s = ctx.state.lock().await;
+    if !s.connected || !s.reached(target) {
+        return false;
+    }
+    s.begin_command(target);
+    drop(s);
+    debug!("FTMS: already at {}, not re-sending", target);
+    ctx.health.count(Counter::Duplicates);
+    true
+}
+
+/// Hold an incline target back while the incline motor is still moving
+/// toward an earlier one, so successive targets don't fight it. The latest
+/// one queued wins and goes out once the motor settles (`treadmill.rs`).
+/// It becomes the latest command straight away, so the earlier target's
+/// verifier stands down.
+async fn queue_incline(ctx: &ControlContext, half_pct: i16) -> bool {
+    let mut s = ctx.state.lock().await;
+    if !s.connected || !s.incline_moving() {
+        return false;
+    }
+    s.begin_command(Target::Incline(half_pct));
+    if let Some(replaced) = s.queued_incline.replace(half_pct) {
+        debug!("FTMS: queued incline {:.1}% replaced", replaced as f64 / 2.0);
+    }
+    info!("FTMS: incline motor moving, {:.1}% queued until it settles", half_pct as f64 / 2.0);
+    true
+}
+
+/// Start the speed and incline generations for a stop, before it's sent.
+async fn stop_gens(ctx: &ControlContext) -> (u32, u32) {
+    let mut s = ctx.state.lock().await;
+    (s.begin_command(Target::Speed(0)), s.begin_command(Target::Incline(0)))
+}
+
+/// Watch for `target` to show up in treadmill_io status in the background,
+/// retrying per config. The control point response doesn't wait for it.
+/// Confirmation time since the command was `received` goes into the
+/// latency histograms.
+fn spawn_verifier(ctx: &ControlContext, target: Target, gen: u32, received: Instant) {
+    let verify = verify(ctx, target, gen);
+    let latency = ctx.latency.clone();
+    tokio::spawn(async move {
+        if verify.await {
+            latency.record(Stage::applied(target), received.elapsed());
+        }
+    });
+}
+
+/// Encode a Fitness Machine Status notification for a treadmill link event.
+///
+///   0x01 = Reset — after a lost-targets reconnect the machine is back at
+///          defaults, and apps respond by re-requesting control and targets.
+///   0x02 0x02 = Paused by User — the console or the debug port stopped
+///               the belt mid-workout.
+///   0x04 = Started or Resumed by User — it's moving again.
+///   0x02 0x01 = Stopped by User — a targeted distance or training time was
+///               reached; FTMS
+///               has no "target reached" status, and apps treat this like
+///               their own Stop.
+fn encode_event_status(event: &TreadmillEvent) -> Option<Vec<u8>> {
+    match event {
+        TreadmillEvent::TargetsLost => Some(vec![0x01]),
+        TreadmillEvent::TargetFailed { .. } | TreadmillEvent::ResumeCountdown(_) | TreadmillEvent::Step(_) => None,
+        TreadmillEvent::DistanceReached(_) | TreadmillEvent::TrainingTimeReached(_) => Some(vec![0x02, 0x01]),
+        TreadmillEvent::ConsolePaused | TreadmillEvent::Paused => Some(vec![0x02, 0x02]),
+        TreadmillEvent::ConsoleResumed | TreadmillEvent::Resumed => Some(vec![0x04]),
+    }
+}
+
+/// How long a target change stays worth replaying to a new Machine Status
+/// subscriber.
+const STATUS_REPLAY_WINDOW: Duration = Duration::from_secs(60);
+
+/// The latest Machine Status target changes, for apps that subscribe only
+/// after sending their commands. A stop or reset clears them: the targets
+/// they announced no longer apply.
+#[derive(Default)]
+struct StatusReplay {
+    speed: Option<(Instant, Vec<u8>)>,
+    incline: Option<(Instant, Vec<u8>)>,
+    distance: Option<(Instant, Vec<u8>)>,
+    training_time: Option<(Instant, Vec<u8>)>,
+}
+
+impl StatusReplay {
+    fn record(&mut self, data: &[u8], now: Instant) {
+        match data {
+            [0x05, ..] => self.speed = Some((now, data.to_vec())),
+            [0x06, ..] => self.incline = Some((now, data.to_vec())),
+            [0x0D, ..] => self.distance = Some((now, data.to_vec())),
+            [0x0E, ..] => self.training_time = Some((now, data.to_vec())),
+            [0x01] | [0x02, 0x01] => *self = Self::default(),
+            _ => {}
+        }
+    }
+
+    /// `current` machine state, then the target changes from the last
+    /// `STATUS_REPLAY_WINDOW`.
+    fn replay(&self, current: Vec<u8>, now: Instant) -> Vec<Vec<u8>> {
+        let recent = [&self.speed, &self.incline, &self.distance, &self.training_time]
+            .into_iter()
+            .flatten()
+            .filter(|(at, _)| now.duration_since(*at) < STATUS_REPLAY_WINDOW)
+            .map(|(_, data)| data.clone());
+        std::iter::once(current).chain(recent).collect()
+    }
+}
+
+/// Publish a Machine Status notification, keeping it for late subscribers.
+fn publish_machine_status(fanout: &Fanout, replay: &std::sync::Mutex<StatusReplay>, data: &[u8]) {
+    replay.lock().unwrap().record(data, Instant::now());
+    fanout.publish(data);
+}
+
+/// Machine Status for the current state: Paused by User while paused at
+/// the console, from the debug port or by an app, Started while the belt
+/// moves, else Stopped by User.
+fn current_machine_status(s: &TreadmillState) -> Vec<u8> {
+    if s.is_paused() {
+        vec![0x02, 0x02]
+    } else if s.speed_tenths_mph > 0 {
+        vec![0x04]
+    } else {
+        vec![0x02, 0x01]
+    }
+}
+
+/// Publish `status` unless it's the one last published.
+fn publish_training_status(fanout: &Fanout, last: &mut Vec<u8>, status: Vec<u8>) {
+    if *last != status {
+        fanout.publish(&status);
+        *last = status;
+    }
+}
+
+/// Training Status for a treadmill link event: a pause/resume moves between
+/// Idle and Manual Mode like an app's Stop/Start would, and a resume
+/// countdown is Idle with the seconds left in the status string. A step
+/// change depends on the state, so it goes through `current_training_status`.
+fn encode_event_training_status(event: &TreadmillEvent) -> Option<Vec<u8>> {
+    match event {
+        TreadmillEvent::ConsolePaused | TreadmillEvent::Paused => Some(vec![0x00, protocol::TRAINING_IDLE]),
+        TreadmillEvent::ConsoleResumed | TreadmillEvent::Resumed => Some(vec![0x00, protocol::TRAINING_MANUAL]),
+        TreadmillEvent::ResumeCountdown(left) => {
+            let mut data = vec![0x01, protocol::TRAINING_IDLE];
+            data.extend_from_slice(format!("Resuming in {}", left).as_bytes());
+            Some(data)
+        }
+        TreadmillEvent::TargetsLost
+        | TreadmillEvent::TargetFailed { .. }
+        | TreadmillEvent::Step(_)
+        | TreadmillEvent::DistanceReached(_)
+        | TreadmillEvent::TrainingTimeReached(_) => None,
     }
 }
 
-/// Encode a Training Status notification for start/stop state changes.
+/// Longest Training Status string sent, in bytes; a step line past it is cut
+/// at a character boundary.
+const TRAINING_STRING_MAX: usize = 40;
+
+/// Training Status for the current state: Pre-Workout during a warm-up,
+/// Post-Workout during a cool-down, Idle while paused,
+/// otherwise Manual Mode while the belt is moving (including a workout
+/// resumed from a session checkpoint), else Idle. Manual Mode carries the
+/// interval program's step, if one is running, as the status string.
+fn current_training_status(s: &TreadmillState, ramp: Option<RampKind>) -> Vec<u8> {
+    let status = match ramp {
+        Some(RampKind::WarmUp) => protocol::TRAINING_PRE_WORKOUT,
+        Some(RampKind::CoolDown) => protocol::TRAINING_POST_WORKOUT,
+        Some(RampKind::Resume) => protocol::TRAINING_MANUAL,
+        None if s.is_paused() => protocol::TRAINING_IDLE,
+        None if s.speed_tenths_mph > 0 => protocol::TRAINING_MANUAL,
+        None => protocol::TRAINING_IDLE,
+    };
+    match &s.workout_step {
+        Some(step) if status == protocol::TRAINING_MANUAL => {
+            let mut end = step.len().min(TRAINING_STRING_MAX);
+            while !step.is_char_boundary(end) {
+                end -= 1;
+            }
+            let mut data = vec![0x01, status];
+            data.extend_from_slice(&step.as_bytes()[..end]);
+            data
+        }
+        _ => vec![0x00, status],
+    }
+}
+
+/// Training Status once a ramp runs to the end.
+fn ramp_finished_training_status(kind: RampKind) -> Vec<u8> {
+    match kind {
+        RampKind::WarmUp | RampKind::Resume => vec![0x00, protocol::TRAINING_MANUAL],
+        RampKind::CoolDown => vec![0x00, protocol::TRAINING_IDLE],
+    }
+}
+
+/// Encode a Training Status notification for a successful command, given
+/// the ramp it left running.
 ///
 /// Training Status format: [flags(1), status(1)]
 ///   Flags: 0x00 (no string present)
 ///   Status values (FTMS spec Table 4.25):
 ///     0x01 = Idle
 ///     0x0D = Manual Mode (Quick Start)
-fn encode_training_status(cmd: &protocol::ControlCommand) -> Option<Vec<u8>> {
-    match cmd {
-        protocol::ControlCommand::StartOrResume => {
-            Some(vec![0x00, 0x0D]) // Manual Mode (Quick Start)
-        }
-        protocol::ControlCommand::StopOrPause(_) => {
-            Some(vec![0x00, 0x01]) // Idle
-        }
-        _ => None,
-    }
+///     0x0E = Pre-Workout (warm-up ramp)
+///     0x0F = Post-Workout (cool-down ramp)
+fn encode_training_status(cmd: &protocol::ControlCommand, ramp: Option<RampKind>) -> Option<Vec<u8>> {
+    let status = match (cmd, ramp) {
+        (protocol::ControlCommand::StartOrResume, _) => protocol::TRAINING_MANUAL,
+        (protocol::ControlCommand::StopOrPause(_), Some(RampKind::CoolDown)) => protocol::TRAINING_POST_WORKOUT,
+        (protocol::ControlCommand::StopOrPause(_), _) => protocol::TRAINING_IDLE,
+        (protocol::ControlCommand::SetTargetSpeed(_), Some(RampKind::WarmUp)) => protocol::TRAINING_PRE_WORKOUT,
+        // A new target ends any ramp: the belt is under manual control
+        (protocol::ControlCommand::SetTargetSpeed(kmh_hundredths), _) if *kmh_hundredths > 0 => protocol::TRAINING_MANUAL,
+        _ => return None,
+    };
+    Some(vec![0x00, status])
 }
 
 /// Encode a Fitness Machine Status notification for a state/target change.
@@ -514,7 +1442,9 @@ fn encode_training_status(cmd: &protocol::ControlCommand) -> Option<Vec<u8>> {
 ///   0x04 = Fitness Machine Started or Resumed by the User
 ///   0x05 = Target Speed Changed (uint16 LE param: km/h * 100)
 ///   0x06 = Target Incline Changed (int16 LE param: % * 10)
-fn encode_status_notification(cmd: &protocol::ControlCommand) -> Option<Vec<u8>> {
+///   0x0D = Targeted Distance Changed (uint24 LE param: meters)
+///   0x0E = Targeted Training Time Changed (uint16 LE param: seconds)
+pub fn encode_status_notification(cmd: &protocol::ControlCommand) -> Option<Vec<u8>> {
     match cmd {
         protocol::ControlCommand::SetTargetSpeed(kmh_hundredths) => {
             let mut buf = vec![0x05]; // Target Speed Changed
@@ -532,6 +1462,298 @@ fn encode_status_notification(cmd: &protocol::ControlCommand) -> Option<Vec<u8>>
         protocol::ControlCommand::StopOrPause(param) => {
             Some(vec![0x02, *param]) // Stopped or Paused
         }
+        protocol::ControlCommand::SetTargetedDistance(meters) => {
+            let mut buf = vec![0x0D]; // Targeted Distance Changed
+            buf.extend_from_slice(&meters.to_le_bytes()[..3]);
+            Some(buf)
+        }
+        protocol::ControlCommand::SetTargetedTrainingTime(secs) => {
+            let mut buf = vec![0x0E]; // Targeted Training Time Changed
+            buf.extend_from_slice(&secs.to_le_bytes());
+            Some(buf)
+        }
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::ControlCommand::*;
+    use crate::treadmill::CommandError;
+
+    #[test]
+    fn test_applied_speed_is_quantized() {
+        let limits = Limits::default();
+        // 5.00 km/h → 3.1 mph → 4.99 km/h
+        assert_eq!(applied_command(&SetTargetSpeed(500), limits), SetTargetSpeed(499));
+        assert_eq!(limits.speed_target_tenths(500), 31);
+        // 19.31 km/h, the top of the range, is 12.0 mph exactly
+        assert_eq!(applied_command(&SetTargetSpeed(1931), limits), SetTargetSpeed(1931));
+        assert_eq!(encode_status_notification(&applied_command(&SetTargetSpeed(1931), limits)), Some(vec![0x05, 0x8b, 0x07]));
+    }
+
+    #[test]
+    fn test_applied_incline_is_rounded() {
+        let limits = Limits::default();
+        assert_eq!(applied_command(&SetTargetInclination(150), limits), SetTargetInclination(150));
+        // 2.3% → 2.5%, 2.2% → 2.0%
+        assert_eq!(applied_command(&SetTargetInclination(23), limits), SetTargetInclination(25));
+        assert_eq!(applied_command(&SetTargetInclination(22), limits), SetTargetInclination(20));
+        assert_eq!(limits.incline_target_half_pct(23), 5);
+        assert_eq!(applied_command(&StopOrPause(2), limits), StopOrPause(2));
+    }
+
+    #[test]
+    fn test_status_replay() {
+        let t0 = Instant::now();
+        let mut replay = StatusReplay::default();
+        assert_eq!(replay.replay(vec![0x02, 0x01], t0), vec![vec![0x02, 0x01]]);
+
+        replay.record(&[0x04], t0);
+        replay.record(&[0x05, 0x20, 0x03], t0);
+        replay.record(&[0x06, 0x14, 0x00], t0);
+        replay.record(&[0x05, 0x84, 0x03], t0 + Duration::from_secs(10));
+        // Latest of each target, after the current state
+        assert_eq!(
+            replay.replay(vec![0x04], t0 + Duration::from_secs(20)),
+            vec![vec![0x04], vec![0x05, 0x84, 0x03], vec![0x06, 0x14, 0x00]]
+        );
+        // Old ones age out
+        assert_eq!(replay.replay(vec![0x04], t0 + Duration::from_secs(65)), vec![vec![0x04], vec![0x05, 0x84, 0x03]]);
+        // A pause keeps them, a stop clears them
+        replay.record(&[0x02, 0x02], t0 + Duration::from_secs(20));
+        assert_eq!(replay.replay(vec![0x02, 0x02], t0 + Duration::from_secs(20)).len(), 3);
+        replay.record(&[0x02, 0x01], t0 + Duration::from_secs(20));
+        assert_eq!(replay.replay(vec![0x02, 0x01], t0 + Duration::from_secs(20)), vec![vec![0x02, 0x01]]);
+
+        let paused = TreadmillState { speed_tenths_mph: 0, console_paused: true, ..Default::default() };
+        assert_eq!(current_machine_status(&paused), vec![0x02, 0x02]);
+        assert_eq!(current_machine_status(&TreadmillState { speed_tenths_mph: 30, ..Default::default() }), vec![0x04]);
+        assert_eq!(current_machine_status(&TreadmillState::default()), vec![0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_training_status_follows_ramps() {
+        let moving = TreadmillState { speed_tenths_mph: 30, ..Default::default() };
+        assert_eq!(current_training_status(&TreadmillState::default(), None), vec![0x00, 0x01]);
+        assert_eq!(current_training_status(&moving, None), vec![0x00, 0x0D]);
+        assert_eq!(current_training_status(&moving, Some(RampKind::WarmUp)), vec![0x00, 0x0E]);
+        assert_eq!(current_training_status(&moving, Some(RampKind::CoolDown)), vec![0x00, 0x0F]);
+        let paused = TreadmillState { app_paused: Some(50), ..moving.clone() };
+        assert_eq!(current_training_status(&paused, None), vec![0x00, 0x01]);
+        assert_eq!(current_training_status(&paused, Some(RampKind::Resume)), vec![0x00, 0x0D]);
+        assert_eq!(encode_event_training_status(&TreadmillEvent::ResumeCountdown(3)), Some(b"\x01\x01Resuming in 3".to_vec()));
+
+        // An interval program's step rides along with Manual Mode only
+        let step = TreadmillState { workout_step: Some("Step 4/10: 2:00 @ 9.0 mph, 3%".into()), ..moving.clone() };
+        assert_eq!(current_training_status(&step, None), b"\x01\x0DStep 4/10: 2:00 @ 9.0 mph, 3%".to_vec());
+        assert_eq!(current_training_status(&step, Some(RampKind::CoolDown)), vec![0x00, 0x0F]);
+        let long = TreadmillState { workout_step: Some("é".repeat(30)), ..moving.clone() };
+        assert_eq!(current_training_status(&long, None).len(), 2 + TRAINING_STRING_MAX);
+
+        assert_eq!(encode_training_status(&StartOrResume, None), Some(vec![0x00, 0x0D]));
+        assert_eq!(encode_training_status(&SetTargetSpeed(800), Some(RampKind::WarmUp)), Some(vec![0x00, 0x0E]));
+        assert_eq!(encode_training_status(&SetTargetSpeed(800), None), Some(vec![0x00, 0x0D]));
+        assert_eq!(encode_training_status(&SetTargetSpeed(0), None), None);
+        assert_eq!(encode_training_status(&StopOrPause(1), Some(RampKind::CoolDown)), Some(vec![0x00, 0x0F]));
+        assert_eq!(encode_training_status(&StopOrPause(2), None), Some(vec![0x00, 0x01]));
+        assert_eq!(encode_training_status(&SetTargetInclination(20), Some(RampKind::WarmUp)), None);
+
+        assert_eq!(ramp_finished_training_status(RampKind::WarmUp), vec![0x00, 0x0D]);
+        assert_eq!(ramp_finished_training_status(RampKind::CoolDown), vec![0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_limits_follow_caps() {
+        let caps = |max_speed, max_incline| CapsMsg {
+            commands: vec![],
+            max_speed,
+            min_incline: 0,
+            max_incline,
+            decline: false,
+        };
+        // treadmill_io's wider clamps leave the safety max in place
+        assert_eq!(Limits::from_caps(Some(&caps(120, 198))), Limits::default());
+        assert_eq!(Limits::from_caps(None).speed_range(), protocol::encode_speed_range(1931));
+
+        let narrow = Limits::from_caps(Some(&caps(80, 20)));
+        assert_eq!(narrow.speed_range(), protocol::encode_speed_range(1287));
+        assert_eq!(narrow.incline_range(), protocol::encode_incline_range(0, 100));
+        assert!(narrow.speed_in_range(1287) && narrow.speed_in_range(80) && narrow.speed_in_range(0));
+        assert!(!narrow.speed_in_range(1288) && !narrow.speed_in_range(79));
+        assert!(narrow.incline_in_range(0) && narrow.incline_in_range(100));
+        assert!(!narrow.incline_in_range(101) && !narrow.incline_in_range(-5));
+    }
+
+    #[test]
+    fn test_limits_follow_decline() {
+        let caps = |line| match crate::status::parse_line(line).unwrap() {
+            crate::status::Message::Caps(caps) => caps,
+            other => panic!("expected caps, got {:?}", other),
+        };
+        let decline = Limits::from_caps(Some(&caps(
+            r#"{"type":"caps","max_speed":120,"min_incline":-6,"max_incline":30,"decline":true}"#,
+        )));
+        assert_eq!(decline.incline_range(), protocol::encode_incline_range(-30, 150));
+        assert!(decline.incline_in_range(-30) && !decline.incline_in_range(-35));
+        assert_eq!(decline.incline_target_half_pct(-25), -5);
+        assert_eq!(applied_command(&SetTargetInclination(-27), decline), SetTargetInclination(-25));
+        // Without decline a negative floor isn't one
+        let flat = Limits::from_caps(Some(&caps(
+            r#"{"type":"caps","max_speed":120,"min_incline":-6,"max_incline":30,"decline":false}"#,
+        )));
+        assert_eq!(flat, Limits::default());
+        assert!(!flat.incline_in_range(-5));
+    }
+
+    #[test]
+    fn test_limits_apply_speed_correction() {
+        // A belt 3% fast: 9.0 mph is 8.7 at treadmill_io, which really runs 8.96
+        let fast = Limits { speed_correction: Some(1.03), ..Limits::default() };
+        assert_eq!(fast.speed_target_tenths(1448), 87);
+        assert_eq!(applied_command(&SetTargetSpeed(1448), fast), SetTargetSpeed(1442));
+        assert_eq!(fast.speed_range(), protocol::encode_speed_range(1989));
+        assert_eq!(Limits::default().speed_target_tenths(1448), 90);
+    }
+
+    #[tokio::test]
+    async fn test_profile_caps_refuse_targets() {
+        let config: FtmsConfig = serde_json::from_str(
+            r#"{"profiles": {"guest": {"max_speed_mph": 6.0, "max_incline_pct": 5.0}}, "profile": "guest"}"#,
+        )
+        .unwrap();
+        let ctx = ControlContext { profiles: Profiles::new(&config), ..test_context(config, broadcast::channel(4).0) };
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        // 10.00 km/h is 6.2 mph; 9.66 km/h is 6.0 mph and gets as far as
+        // sending, which fails with no treadmill_io behind the context
+        assert_eq!(handle_control_command(&SetTargetSpeed(1000), &ctx, &origin).await, (0x02, protocol::RESULT_INVALID_PARAM));
+        assert_eq!(handle_control_command(&SetTargetSpeed(966), &ctx, &origin).await, (0x02, protocol::RESULT_FAILED));
+        assert_eq!(handle_control_command(&SetTargetInclination(60), &ctx, &origin).await, (0x03, protocol::RESULT_INVALID_PARAM));
+        assert_eq!(handle_control_command(&SetTargetInclination(50), &ctx, &origin).await, (0x03, protocol::RESULT_FAILED));
+    }
+
+    #[tokio::test]
+    async fn test_refused_command_fails() {
+        // A treadmill_io that acks speeds and refuses inclines by id
+        let (commands, mut queue) = Commands::channel();
+        tokio::spawn(async move {
+            while let Some(command) = queue.recv().await {
+                let answer = if command.line.contains("\"speed\"") {
+                    Ok(())
+                } else {
+                    Err(CommandError::Refused("incline out of range".into()))
+                };
+                command.complete(answer);
+            }
+        });
+        let ctx = ControlContext { commands, ..test_context(FtmsConfig::default(), broadcast::channel(4).0) };
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        assert_eq!(handle_control_command(&SetTargetSpeed(805), &ctx, &origin).await, (0x02, protocol::RESULT_SUCCESS));
+        assert_eq!(handle_control_command(&SetTargetInclination(50), &ctx, &origin).await, (0x03, protocol::RESULT_FAILED));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_targets_are_not_sent() {
+        let state = TreadmillState { speed_tenths_mph: 50, incline_half_pct: 4, connected: true, ..Default::default() };
+        let ctx = ControlContext { state: Arc::new(Mutex::new(state)), ..test_context(FtmsConfig::default(), broadcast::channel(4).0) };
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        // 8.05 km/h is 5.0 mph and 2.0% is 4 half-percent: what the belt is
+        // doing, so nothing is sent
+        assert_eq!(handle_control_command(&SetTargetSpeed(805), &ctx, &origin).await, (0x02, protocol::RESULT_SUCCESS));
+        assert_eq!(handle_control_command(&SetTargetInclination(20), &ctx, &origin).await, (0x03, protocol::RESULT_SUCCESS));
+        assert_eq!(ctx.health.get(Counter::Duplicates), 2);
+        let s = ctx.state.lock().await;
+        assert_eq!((s.last_speed_target, s.last_incline_target), (Some(50), Some(4)));
+        drop(s);
+
+        // A different target, or the same speed while a ramp runs, is sent
+        assert_eq!(handle_control_command(&SetTargetSpeed(966), &ctx, &origin).await, (0x02, protocol::RESULT_FAILED));
+        ctx.ramp.start(RampKind::CoolDown, Commands::channel().0, vec![40, 30], async {});
+        assert_eq!(handle_control_command(&SetTargetSpeed(805), &ctx, &origin).await, (0x02, protocol::RESULT_FAILED));
+        assert_eq!(ctx.health.get(Counter::Duplicates), 2);
+    }
+
+    #[tokio::test]
+    async fn test_incline_queued_while_motor_moves() {
+        let state = TreadmillState {
+            incline_half_pct: 4,
+            connected: true,
+            last_incline_target: Some(4),
+            incline_moved_at: Some(Instant::now()),
+            ..Default::default()
+        };
+        let ctx = ControlContext { state: Arc::new(Mutex::new(state)), ..test_context(FtmsConfig::default(), broadcast::channel(4).0) };
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        // Nothing is sent while the motor moves; the latest target wins
+        let gen = ctx.state.lock().await.incline_cmd_gen;
+        assert_eq!(handle_control_command(&SetTargetInclination(30), &ctx, &origin).await, (0x03, protocol::RESULT_SUCCESS));
+        assert_eq!(handle_control_command(&SetTargetInclination(50), &ctx, &origin).await, (0x03, protocol::RESULT_SUCCESS));
+        let mut s = ctx.state.lock().await;
+        assert_eq!((s.queued_incline, s.last_incline_target), (Some(10), Some(10)));
+        assert_eq!(s.incline_cmd_gen, gen + 2);
+
+        // Once the motor settles the target goes out as usual
+        assert_eq!(s.settle_incline(Instant::now() + Duration::from_secs(3), Duration::from_secs(2)), Some(10));
+        drop(s);
+        assert_eq!(handle_control_command(&SetTargetInclination(50), &ctx, &origin).await, (0x03, protocol::RESULT_FAILED));
+    }
+
+    #[tokio::test]
+    async fn test_workout_targets() {
+        let state = TreadmillState { distance_meters: 1200, ..Default::default() };
+        let ctx = ControlContext { state: Arc::new(Mutex::new(state)), ..test_context(FtmsConfig::default(), broadcast::channel(4).0) };
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        assert_eq!(handle_control_command(&SetTargetedDistance(1000), &ctx, &origin).await, (0x0C, protocol::RESULT_INVALID_PARAM));
+        assert_eq!(handle_control_command(&SetTargetedDistance(5000), &ctx, &origin).await, (0x0C, protocol::RESULT_SUCCESS));
+        assert_eq!(ctx.state.lock().await.distance_target, Some(5000));
+        assert_eq!(encode_status_notification(&SetTargetedDistance(5000)), Some(vec![0x0D, 0x88, 0x13, 0x00]));
+        assert_eq!(handle_control_command(&SetTargetedDistance(0), &ctx, &origin).await, (0x0C, protocol::RESULT_SUCCESS));
+        assert_eq!(ctx.state.lock().await.distance_target, None);
+
+        assert_eq!(encode_event_status(&TreadmillEvent::DistanceReached(5000)), Some(vec![0x02, 0x01]));
+
+        ctx.state.lock().await.elapsed_secs = 600;
+        assert_eq!(handle_control_command(&SetTargetedTrainingTime(600), &ctx, &origin).await, (0x0D, protocol::RESULT_INVALID_PARAM));
+        assert_eq!(handle_control_command(&SetTargetedTrainingTime(1800), &ctx, &origin).await, (0x0D, protocol::RESULT_SUCCESS));
+        assert_eq!(ctx.state.lock().await.remaining_secs(), Some(1200));
+        assert_eq!(encode_status_notification(&SetTargetedTrainingTime(1800)), Some(vec![0x0E, 0x08, 0x07]));
+    }
+
+    #[test]
+    fn test_control_ownership() {
+        use protocol::ControlCommand::*;
+        let (app, other) = ("AA:BB:CC:DD:EE:01", "AA:BB:CC:DD:EE:02");
+        let mut control = ControlOwner::default();
+        assert!(!control.permits(&SetTargetSpeed(500), app), "needs Request Control first");
+        assert!(!control.permits(&StartOrResume, app));
+        assert!(control.permits(&RequestControl, app));
+        control.grant(app);
+        assert!(control.permits(&SetTargetSpeed(500), app));
+        assert!(control.permits(&RequestControl, app), "asking again is fine");
+        assert!(!control.permits(&RequestControl, other));
+        assert!(!control.permits(&StopOrPause(1), other));
+
+        control.release(other);
+        assert!(control.permits(&StopOrPause(1), app), "only the owner releases");
+        assert_eq!(control.owner(), Some(app));
+        control.release(app);
+        assert!(!control.permits(&StopOrPause(1), app));
+        assert!(control.permits(&RequestControl, other));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_targets_refused() {
+        let ctx = test_context(FtmsConfig::default(), broadcast::channel(4).0);
+        let origin = Origin::Debug("127.0.0.1:5000".into());
+        for cmd in [SetTargetSpeed(2500), SetTargetSpeed(1932), SetTargetSpeed(50)] {
+            assert_eq!(handle_control_command(&cmd, &ctx, &origin).await, (0x02, protocol::RESULT_INVALID_PARAM), "{:?}", cmd);
+        }
+        for cmd in [SetTargetInclination(151), SetTargetInclination(-5)] {
+            assert_eq!(handle_control_command(&cmd, &ctx, &origin).await, (0x03, protocol::RESULT_INVALID_PARAM), "{:?}", cmd);
+        }
+        // In range: fails only because no treadmill_io takes the command
+        assert_eq!(handle_control_command(&SetTargetSpeed(1931), &ctx, &origin).await, (0x02, protocol::RESULT_FAILED));
+        assert_eq!(handle_control_command(&SetTargetInclination(150), &ctx, &origin).await, (0x03, protocol::RESULT_FAILED));
+    }
+}
@@ -0,0 +1,326 @@
+//! What the GATT server has registered with BlueZ, for the debug `gatt`
+//! command.
+//!
+//! Each registration records the service and its characteristics: the
+//! handles BlueZ assigned, the properties we declared, and who is
+//! subscribed right now (Treadmill Data / status notifications through
+//! their fanouts, Control Point indications through the one indication
+//! session). Useful when an app claims a characteristic is missing: if
+//! it's listed here, BlueZ has it and the app's cache is the suspect.
+//!
+//! The fanouts' delivery counts back the debug `clients` command and the
+//! notification counters in `metrics`, for when an app freezes mid-run
+//! without disconnecting.
+
+use std::fmt::Write;
+use std::num::NonZeroU16;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bluer::gatt::local::{Characteristic, CharacteristicControl, ServiceControl};
+use uuid::Uuid;
+
+use crate::fanout::Fanout;
+use crate::protocol::{
+    CONTROL_POINT_UUID, FEATURE_UUID, FTMS_SERVICE_UUID, INCLINE_RANGE_UUID, MACHINE_STATUS_UUID,
+    SPEED_RANGE_UUID, TRAINING_STATUS_UUID, TREADMILL_DATA_UUID,
+};
+
+/// Where a characteristic's assigned handle can be read.
+pub enum HandleSource {
+    /// A control kept only for its handle.
+    Control(CharacteristicControl),
+    /// Published by whoever owns the control (0 = not assigned yet).
+    Shared(Arc<AtomicU16>),
+}
+
+impl HandleSource {
+    fn get(&self) -> Option<u16> {
+        match self {
+            HandleSource::Control(control) => control.handle().ok().map(NonZeroU16::get),
+            HandleSource::Shared(handle) => Some(handle.load(Ordering::Relaxed)).filter(|&h| h != 0),
+        }
+    }
+}
+
+/// Who is listening to a characteristic.
+pub enum Subscribers {
+    /// Read/write only.
+    None,
+    Fanout(Arc<Fanout>),
+    /// Whether the single indication session is open.
+    Indications(Arc<AtomicBool>),
+}
+
+/// One registered characteristic.
+pub struct Entry {
+    pub uuid: Uuid,
+    pub properties: Vec<&'static str>,
+    pub handle: HandleSource,
+    pub subscribers: Subscribers,
+}
+
+struct Registered {
+    service: ServiceControl,
+    entries: Vec<Entry>,
+}
+
+/// Cheap, cloneable handle to the current registration, if any.
+#[derive(Clone, Default)]
+pub struct GattTable {
+    current: Arc<Mutex<Option<Registered>>>,
+}
+
+/// Clears the table when the registration it describes goes away.
+pub struct Registration(GattTable);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        *self.0.current.lock().unwrap() = None;
+    }
+}
+
+impl GattTable {
+    /// Record what was just registered, until the returned guard drops.
+    pub fn register(&self, service: ServiceControl, entries: Vec<Entry>) -> Registration {
+        *self.current.lock().unwrap() = Some(Registered { service, entries });
+        Registration(self.clone())
+    }
+
+    pub fn report(&self) -> String {
+        let current = self.current.lock().unwrap();
+        let Some(reg) = current.as_ref() else {
+            return "GATT application not registered (BlueZ or the adapter is restarting)".to_string();
+        };
+        let mut out = format!(
+            "service {} {} (handle {}, primary)",
+            short_uuid(FTMS_SERVICE_UUID),
+            name(FTMS_SERVICE_UUID),
+            format_handle(reg.service.handle().ok().map(NonZeroU16::get))
+        );
+        for e in &reg.entries {
+            let subscribers = match &e.subscribers {
+                Subscribers::None => String::new(),
+                Subscribers::Fanout(fanout) => match fanout.subscriber_count() {
+                    1 => "1 subscriber".to_string(),
+                    n => format!("{} subscribers", n),
+                },
+                Subscribers::Indications(open) if open.load(Ordering::Relaxed) => "indication session open".to_string(),
+                Subscribers::Indications(_) => "no indication session".to_string(),
+            };
+            let line = format!(
+                "{:>6} {} {:<30} {:<16} {}",
+                format_handle(e.handle.get()),
+                short_uuid(e.uuid),
+                name(e.uuid),
+                e.properties.join(","),
+                subscribers
+            );
+            out.push_str("\n  ");
+            out.push_str(line.trim_end());
+        }
+        out
+    }
+
+    /// Every notifying characteristic's delivery totals and current
+    /// subscribers, for the debug `clients` command.
+    pub fn clients_report(&self) -> String {
+        let current = self.current.lock().unwrap();
+        let Some(reg) = current.as_ref() else {
+            return "GATT application not registered (BlueZ or the adapter is restarting)".to_string();
+        };
+        let mut out = String::new();
+        for fanout in reg.fanouts() {
+            let t = fanout.totals();
+            let _ = writeln!(
+                out,
+                "{}: {}/{} delivered, {} failed, {} skipped, {} dropped",
+                fanout.label(),
+                t.delivered,
+                t.attempted,
+                t.failed,
+                t.skipped,
+                fanout.dropped()
+            );
+            let clients = fanout.clients();
+            if clients.is_empty() {
+                out.push_str("  no subscribers\n");
+            }
+            for c in clients {
+                let (secs, d) = (c.connected_for.as_secs(), c.delivery);
+                let _ = writeln!(
+                    out,
+                    "  #{} for {}:{:02}: {}/{} delivered, {} failed, {} skipped",
+                    c.id,
+                    secs / 60,
+                    secs % 60,
+                    d.delivered,
+                    d.attempted,
+                    d.failed,
+                    d.skipped
+                );
+            }
+        }
+        out.pop();
+        out
+    }
+
+    /// Notification counters and subscriber gauges per characteristic in
+    /// Prometheus text format. Counters start over with each registration;
+    /// empty while there is none.
+    pub fn prometheus(&self) -> String {
+        let current = self.current.lock().unwrap();
+        let Some(reg) = current.as_ref() else {
+            return String::new();
+        };
+        let metrics: [NotifyMetric; 6] = [
+            ("ftms_subscribers", "gauge", "Current subscribers.", |f| f.clients().len() as u64),
+            ("ftms_notifications_attempted_total", "counter", "Notifications handed to BlueZ.", |f| f.totals().attempted),
+            ("ftms_notifications_delivered_total", "counter", "Notifications that went through.", |f| f.totals().delivered),
+            ("ftms_notifications_failed_total", "counter", "Notifications that errored or timed out.", |f| f.totals().failed),
+            ("ftms_notifications_skipped_total", "counter", "Updates skipped for a full subscriber queue.", |f| f.totals().skipped),
+            ("ftms_subscribers_dropped_total", "counter", "Subscriptions torn down for lagging or failing.", Fanout::dropped),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for fanout in reg.fanouts() {
+                let label = fanout.label().to_lowercase().replace(' ', "_");
+                let _ = writeln!(out, "{}{{characteristic=\"{}\"}} {}", name, label, value(fanout));
+            }
+        }
+        out.pop();
+        out
+    }
+}
+
+/// Prometheus name, type, help text and how to read it off a fanout.
+type NotifyMetric = (&'static str, &'static str, &'static str, fn(&Fanout) -> u64);
+
+impl Registered {
+    fn fanouts(&self) -> impl Iterator<Item = &Arc<Fanout>> {
+        self.entries.iter().filter_map(|e| match &e.subscribers {
+            Subscribers::Fanout(fanout) => Some(fanout),
+            _ => None,
+        })
+    }
+}
+
+/// The properties `c` declares, in GATT order.
+pub fn properties(c: &Characteristic) -> Vec<&'static str> {
+    let mut props = Vec::new();
+    if c.read.as_ref().is_some_and(|r| r.read) {
+        props.push("read");
+    }
+    if c.write.as_ref().is_some_and(|w| w.write_without_response) {
+        props.push("write-no-resp");
+    }
+    if c.write.as_ref().is_some_and(|w| w.write) {
+        props.push("write");
+    }
+    if c.notify.as_ref().is_some_and(|n| n.notify) {
+        props.push("notify");
+    }
+    if c.notify.as_ref().is_some_and(|n| n.indicate) {
+        props.push("indicate");
+    }
+    props
+}
+
+fn format_handle(handle: Option<u16>) -> String {
+    handle.map_or_else(|| "?".to_string(), |h| format!("0x{:04x}", h))
+}
+
+/// `2acd` for a Bluetooth base UUID, else the full UUID.
+fn short_uuid(uuid: Uuid) -> String {
+    let full = uuid.to_string();
+    match full.strip_prefix("0000").and_then(|rest| rest.strip_suffix("-0000-1000-8000-00805f9b34fb")) {
+        Some(short) => short.to_string(),
+        None => full,
+    }
+}
+
+fn name(uuid: Uuid) -> &'static str {
+    match uuid {
+        FTMS_SERVICE_UUID => "Fitness Machine",
+        FEATURE_UUID => "Fitness Machine Feature",
+        TREADMILL_DATA_UUID => "Treadmill Data",
+        SPEED_RANGE_UUID => "Supported Speed Range",
+        INCLINE_RANGE_UUID => "Supported Inclination Range",
+        TRAINING_STATUS_UUID => "Training Status",
+        CONTROL_POINT_UUID => "Fitness Machine Control Point",
+        MACHINE_STATUS_UUID => "Fitness Machine Status",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bluer::gatt::local::{service_control, CharacteristicNotify, CharacteristicRead, CharacteristicWrite};
+    use crate::health::Health;
+
+    #[test]
+    fn test_report() {
+        let table = GattTable::default();
+        assert!(table.report().contains("not registered"));
+
+        let cp = Characteristic {
+            uuid: CONTROL_POINT_UUID,
+            write: Some(CharacteristicWrite { write: true, ..Default::default() }),
+            notify: Some(CharacteristicNotify { indicate: true, ..Default::default() }),
+            ..Default::default()
+        };
+        let feature = Characteristic {
+            uuid: FEATURE_UUID,
+            read: Some(CharacteristicRead { read: true, ..Default::default() }),
+            ..Default::default()
+        };
+        let td = Characteristic {
+            uuid: TREADMILL_DATA_UUID,
+            notify: Some(CharacteristicNotify { notify: true, ..Default::default() }),
+            ..Default::default()
+        };
+        let cp_handle = Arc::new(AtomicU16::new(0x16));
+        let indicating = Arc::new(AtomicBool::new(true));
+        let registration = table.register(
+            service_control().0,
+            vec![
+                Entry {
+                    uuid: feature.uuid,
+                    properties: properties(&feature),
+                    handle: HandleSource::Shared(Arc::new(AtomicU16::new(0))),
+                    subscribers: Subscribers::None,
+                },
+                Entry {
+                    uuid: cp.uuid,
+                    properties: properties(&cp),
+                    handle: HandleSource::Shared(cp_handle),
+                    subscribers: Subscribers::Indications(indicating),
+                },
+                Entry {
+                    uuid: td.uuid,
+                    properties: properties(&td),
+                    handle: HandleSource::Shared(Arc::new(AtomicU16::new(0x1a))),
+                    subscribers: Subscribers::Fanout(Arc::new(Fanout::new("Treadmill Data", 2, Health::default()))),
+                },
+            ],
+        );
+        let report = table.report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "service 1826 Fitness Machine (handle ?, primary)");
+        assert!(lines[1].contains("     ? 2acc Fitness Machine Feature") && lines[1].ends_with("read"), "{}", lines[1]);
+        assert!(lines[2].contains("0x0016 2ad9 Fitness Machine Control Point  write,indicate"), "{}", lines[2]);
+        assert!(lines[2].ends_with("indication session open"));
+        assert!(lines[3].ends_with("notify           0 subscribers"), "{}", lines[3]);
+
+        assert_eq!(table.clients_report(), "Treadmill Data: 0/0 delivered, 0 failed, 0 skipped, 0 dropped\n  no subscribers");
+        let metrics = table.prometheus();
+        assert!(metrics.contains("# TYPE ftms_notifications_failed_total counter\nftms_notifications_failed_total{characteristic=\"treadmill_data\"} 0"));
+        assert_eq!(metrics.lines().count(), 6 * 3);
+
+        drop(registration);
+        assert!(table.report().contains("not registered"), "cleared with the registration");
+        assert_eq!(table.prometheus(), "");
+    }
+}
@@ -0,0 +1,365 @@
+//! Optional gRPC API, for companion apps that would rather have typed
+//! clients than JSON or the debug console's text.
+//!
+//! With `grpc_addr` set, serves the `Treadmill` service from
+//! `proto/precor.proto`: state snapshots, a state stream, and speed,
+//! incline, start and stop. Control goes through the same handler as BLE
+//! Control Point writes, so profiles, quiet hours and the safety max apply
+//! and the audit trail records the caller as `grpc <ip:port>`. Like `cp`
+//! on the debug port, it doesn't send BLE apps a Machine Status.
+//!
+//! `build.rs` generates the service plumbing; the messages below mirror the
+//! .proto by hand (no protoc needed), and `test_messages_match_proto` keeps
+//! the two from drifting apart.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+use log::{info, warn};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::{Request, Response, Status};
+
+use crate::audit::Origin;
+use crate::throttle::{Limits, Slot};
+use crate::ftms_service::{self, ControlContext};
+use crate::protocol::{self, ControlCommand};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/precor.v1.Treadmill.rs"));
+}
+
+use generated::treadmill_server::{Treadmill, TreadmillServer};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {
+    #[prost(uint32, tag = "1")]
+    pub interval_ms: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TreadmillState {
+    #[prost(double, tag = "1")]
+    pub speed_mph: f64,
+    #[prost(double, tag = "2")]
+    pub incline_pct: f64,
+    #[prost(uint32, tag = "3")]
+    pub elapsed_secs: u32,
+    #[prost(uint32, tag = "4")]
+    pub distance_meters: u32,
+    #[prost(bool, tag = "5")]
+    pub connected: bool,
+    #[prost(bool, tag = "6")]
+    pub console_paused: bool,
+    #[prost(double, optional, tag = "7")]
+    pub target_speed_mph: Option<f64>,
+    #[prost(double, optional, tag = "8")]
+    pub target_incline_pct: Option<f64>,
+    #[prost(double, tag = "9")]
+    pub elevation_gain_m: f64,
+    #[prost(string, optional, tag = "10")]
+    pub workout_step: Option<String>,
+    #[prost(uint32, optional, tag = "11")]
+    pub target_distance_meters: Option<u32>,
+    #[prost(uint32, optional, tag = "12")]
+    pub remaining_secs: Option<u32>,
+    #[prost(bool, tag = "13")]
+    pub incline_moving: bool,
+    #[prost(double, optional, tag = "14")]
+    pub queued_incline_pct: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetSpeedRequest {
+    #[prost(double, tag = "1")]
+    pub mph: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetInclineRequest {
+    #[prost(double, tag = "1")]
+    pub percent: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StopRequest {
+    #[prost(bool, tag = "1")]
+    pub pause: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ControlReply {
+    #[prost(uint32, tag = "1")]
+    pub result_code: u32,
+    #[prost(string, tag = "2")]
+    pub result: String,
+}
+
+/// Bind `addr` (e.g. `0.0.0.0:8836`). Done before `run` so privileges can
+/// be dropped in between.
+pub async fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("gRPC server listening on {}", addr);
+    Ok(listener)
+}
+
+/// Serve the Treadmill service until the listener fails, to at most
+/// `grpc_max_connections` clients at once.
+pub async fn run(ctx: ControlContext, listener: TcpListener) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let limits = Limits::new(ctx.config.grpc_max_connections, 0, Duration::ZERO);
+    tonic::transport::Server::builder()
+        .add_service(TreadmillServer::new(Service { ctx }))
+        .serve_with_incoming(incoming(listener, limits))
+        .await?;
+    Ok(())
+}
+
+/// An accepted connection, holding its slot until tonic drops it.
+struct Pooled {
+    stream: TcpStream,
+    _slot: Slot,
+}
+
+impl AsyncRead for Pooled {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Pooled {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected for Pooled {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> TcpConnectInfo {
+        self.stream.connect_info()
+    }
+}
+
+/// Connections from `listener`, up to `limits`' cap. Ones past it are
+/// closed straight away, which clients see as the server being unavailable.
+fn incoming(listener: TcpListener, limits: Limits) -> impl Stream<Item = std::io::Result<Pooled>> {
+    futures::stream::unfold((listener, limits), |(listener, limits)| async move {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => return Some((Err(e), (listener, limits))),
+            };
+            let Some(slot) = limits.open() else {
+                warn!("gRPC client {} refused: {} connections already open", addr, limits.max_connections());
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            return Some((Ok(Pooled { stream, _slot: slot }), (listener, limits)));
+        }
+    })
+}
+
+/// `WatchState` period: 0 is 1 s, and no faster than Treadmill Data goes.
+fn watch_period(interval_ms: u32) -> Duration {
+    match interval_ms {
+        0 => Duration::from_secs(1),
+        ms => Duration::from_millis((ms as u64).max(crate::config::MIN_NOTIFY_INTERVAL_MS)),
+    }
+}
+
+async fn snapshot(ctx: &ControlContext) -> TreadmillState {
+    let s = ctx.state.lock().await;
+    TreadmillState {
+        speed_mph: s.displayed_speed_at(Instant::now()) as f64 / 100.0,
+        incline_pct: s.incline_half_pct as f64 / 2.0,
+        elapsed_secs: s.elapsed_secs as u32,
+        distance_meters: s.distance_meters,
+        connected: s.connected,
+        console_paused: s.console_paused,
+        target_speed_mph: s.last_speed_target.map(|t| s.corrected_speed(t as u32 * 10) as f64 / 100.0),
+        target_incline_pct: s.last_incline_target.map(|h| h as f64 / 2.0),
+        elevation_gain_m: s.elevation_gain_m,
+        workout_step: s.workout_step.clone(),
+        target_distance_meters: s.distance_target,
+        remaining_secs: s.remaining_secs().map(u32::from),
+        incline_moving: s.incline_moving(),
+        queued_incline_pct: s.queued_incline.map(|h| h as f64 / 2.0),
+    }
+}
+
+struct Service {
+    ctx: ControlContext,
+}
+
+impl Service {
+    async fn control<T>(&self, request: &Request<T>, cmd: ControlCommand) -> Result<Response<ControlReply>, Status> {
+        let peer = request.remote_addr().map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+        let (_, result) = ftms_service::handle_control_command(&cmd, &self.ctx, &Origin::Grpc(peer)).await;
+        Ok(Response::new(ControlReply { result_code: result as u32, result: protocol::result_name(result).to_string() }))
+    }
+}
+
+#[tonic::async_trait]
+impl Treadmill for Service {
+    type WatchStateStream = Pin<Box<dyn Stream<Item = Result<TreadmillState, Status>> + Send>>;
+
+    async fn get_state(&self, _request: Request<Empty>) -> Result<Response<TreadmillState>, Status> {
+        Ok(Response::new(snapshot(&self.ctx).await))
+    }
+
+    async fn watch_state(&self, request: Request<WatchRequest>) -> Result<Response<Self::WatchStateStream>, Status> {
+        let tick = tokio::time::interval(watch_period(request.get_ref().interval_ms));
+        let states = futures::stream::unfold((self.ctx.clone(), tick), |(ctx, mut tick)| async move {
+            tick.tick().await;
+            let state = snapshot(&ctx).await;
+            Some((Ok(state), (ctx, tick)))
+        });
+        Ok(Response::new(Box::pin(states)))
+    }
+
+    async fn set_speed(&self, request: Request<SetSpeedRequest>) -> Result<Response<ControlReply>, Status> {
+        let mph = request.get_ref().mph;
+        if !mph.is_finite() || mph < 0.0 {
+            return Err(Status::invalid_argument(format!("bad speed {} mph", mph)));
+        }
+        // Float to int casts saturate, and the handler refuses speeds over
+        // the limits
+        let kmh_hundredths = protocol::mph_hundredths_to_kmh_hundredths((mph * 100.0).round() as u32);
+        self.control(&request, ControlCommand::SetTargetSpeed(kmh_hundredths)).await
+    }
+
+    async fn set_incline(&self, request: Request<SetInclineRequest>) -> Result<Response<ControlReply>, Status> {
+        let percent = request.get_ref().percent;
+        if !percent.is_finite() {
+            return Err(Status::invalid_argument(format!("bad incline {}%", percent)));
+        }
+        self.control(&request, ControlCommand::SetTargetInclination((percent * 10.0).round() as i16)).await
+    }
+
+    async fn start(&self, request: Request<Empty>) -> Result<Response<ControlReply>, Status> {
+        self.control(&request, ControlCommand::StartOrResume).await
+    }
+
+    async fn stop(&self, request: Request<StopRequest>) -> Result<Response<ControlReply>, Status> {
+        let param = if request.get_ref().pause { 2 } else { 1 };
+        self.control(&request, ControlCommand::StopOrPause(param)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generated::treadmill_client::TreadmillClient;
+
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, Mutex};
+
+    use crate::audit::Audit;
+    use crate::config::FtmsConfig;
+    use crate::ftms_service::test_context;
+    use crate::treadmill::TreadmillState as State;
+
+    #[test]
+    fn test_messages_match_proto() {
+        let proto = include_str!("../../proto/precor.proto");
+        for field in [
+            "uint32 interval_ms = 1;",
+            "double speed_mph = 1;",
+            "double incline_pct = 2;",
+            "uint32 elapsed_secs = 3;",
+            "uint32 distance_meters = 4;",
+            "bool connected = 5;",
+            "bool console_paused = 6;",
+            "optional double target_speed_mph = 7;",
+            "optional double target_incline_pct = 8;",
+            "double elevation_gain_m = 9;",
+            "optional string workout_step = 10;",
+            "optional uint32 target_distance_meters = 11;",
+            "optional uint32 remaining_secs = 12;",
+            "bool incline_moving = 13;",
+            "optional double queued_incline_pct = 14;",
+            "double mph = 1;",
+            "double percent = 1;",
+            "bool pause = 1;",
+            "uint32 result_code = 1;",
+            "string result = 2;",
+            "rpc WatchState(WatchRequest) returns (stream TreadmillState);",
+            "rpc SetSpeed(SetSpeedRequest) returns (ControlReply);",
+        ] {
+            assert!(proto.contains(field), "proto/precor.proto lacks {:?}", field);
+        }
+    }
+
+    #[test]
+    fn test_watch_period() {
+        assert_eq!(watch_period(0), Duration::from_secs(1));
+        assert_eq!(watch_period(100), Duration::from_millis(250));
+        assert_eq!(watch_period(500), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_state_and_control_over_grpc() {
+        let audit = Audit::default();
+        let ctx = ControlContext {
+            state: Arc::new(Mutex::new(State {
+                speed_tenths_mph: 35,
+                incline_half_pct: 4,
+                distance_meters: 120,
+                connected: true,
+                last_speed_target: Some(50),
+                ..Default::default()
+            })),
+            audit: audit.clone(),
+            ..test_context(FtmsConfig { speed_smoothing_mph_per_s: 0.0, ..Default::default() }, broadcast::channel(4).0)
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run(ctx, listener));
+
+        let mut client = TreadmillClient::connect(format!("http://{}", addr)).await.unwrap();
+        let state = client.get_state(Empty {}).await.unwrap().into_inner();
+        assert_eq!((state.speed_mph, state.incline_pct, state.distance_meters), (3.5, 2.0, 120));
+        assert_eq!((state.target_speed_mph, state.target_incline_pct), (Some(5.0), None));
+
+        let mut states = client.watch_state(WatchRequest { interval_ms: 250 }).await.unwrap().into_inner();
+        assert!(states.message().await.unwrap().unwrap().connected);
+
+        // No treadmill_io behind the context, so the command gets as far
+        // as the socket and fails there
+        let reply = client.set_speed(SetSpeedRequest { mph: 4.0 }).await.unwrap().into_inner();
+        assert_eq!((reply.result_code, reply.result.as_str()), (4, "Operation Failed"));
+        let status = client.set_speed(SetSpeedRequest { mph: f64::NAN }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let recent = audit.recent(10);
+        assert_eq!(recent.len(), 1, "rejected requests never reach the handler");
+        assert!(matches!(&recent[0].origin, Origin::Grpc(peer) if peer.starts_with("127.0.0.1:")));
+        assert_eq!(recent[0].command, "SetTargetSpeed(644)");
+    }
+}
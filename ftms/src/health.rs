@@ -0,0 +1,138 @@
+//! Daemon health for the debug `stats` command.
+//!
+//! Long-running tasks stamp their last activity here and bump a few shared
+//! counters, so `stats` shows at a glance which part of the daemon has gone
+//! quiet or is failing: a treadmill_io link that stopped talking, a GATT
+//! server that no longer notifies, a climbing error count.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tasks reported by `stats`, listed even before their first activity.
+pub const TASKS: [&str; 4] = ["treadmill_io", "gatt", "treadmill_data", "debug"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    /// GATT notifications and indications delivered.
+    Notifications,
+    /// Control point commands handled (BLE and debug `cp`).
+    Commands,
+    /// treadmill_io reconnects and GATT re-registrations.
+    Reconnects,
+    /// Failed commands, dropped links, notify errors.
+    Errors,
+    /// Speed/incline targets the belt was already at, not sent to treadmill_io.
+    Duplicates,
+}
+
+impl Counter {
+    const ALL: [Counter; 5] =
+        [Counter::Notifications, Counter::Commands, Counter::Reconnects, Counter::Errors, Counter::Duplicates];
+
+    fn name(self) -> &'static str {
+        match self {
+            Counter::Notifications => "notifications",
+            Counter::Commands => "commands",
+            Counter::Reconnects => "reconnects",
+            Counter::Errors => "errors",
+            Counter::Duplicates => "duplicates",
+        }
+    }
+}
+
+struct Inner {
+    started: Instant,
+    counters: [AtomicU64; 5],
+    tasks: Mutex<BTreeMap<&'static str, Instant>>,
+}
+
+/// Cheap, cloneable handle to the daemon's health counters.
+#[derive(Clone)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                started: Instant::now(),
+                counters: Default::default(),
+                tasks: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+}
+
+impl Health {
+    /// Note that `task` just did something useful.
+    pub fn touch(&self, task: &'static str) {
+        self.inner.tasks.lock().unwrap().insert(task, Instant::now());
+    }
+
+    pub fn count(&self, counter: Counter) {
+        self.inner.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.inner.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Human-readable report for the debug console.
+    pub fn report(&self) -> String {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> String {
+        let mut out = format!("uptime {}\nlast activity:", format_duration(now - self.inner.started));
+        let tasks = self.inner.tasks.lock().unwrap();
+        let extra = tasks.keys().filter(|t| !TASKS.contains(t)).copied();
+        for task in TASKS.iter().copied().chain(extra) {
+            let seen = match tasks.get(task) {
+                Some(&at) => format!("{} ago", format_duration(now.saturating_duration_since(at))),
+                None => "never".to_string(),
+            };
+            let _ = write!(out, "\n  {:<16}{}", task, seen);
+        }
+        out.push_str("\ncounters:");
+        for counter in Counter::ALL {
+            let _ = write!(out, "\n  {:<16}{}", counter.name(), self.get(counter));
+        }
+        out
+    }
+}
+
+/// e.g. "42s", "12m05s", "3h02m".
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let health = Health::default();
+        health.touch("treadmill_io");
+        health.count(Counter::Commands);
+        health.count(Counter::Commands);
+        health.count(Counter::Errors);
+        let later = Instant::now() + Duration::from_secs(3725);
+        let report = health.report_at(later);
+        assert!(report.starts_with("uptime 1h02m\n"), "{}", report);
+        assert!(report.contains("\n  treadmill_io    1h02m ago"), "{}", report);
+        assert!(report.contains("\n  gatt            never"));
+        assert!(report.contains("\n  commands        2"));
+        assert!(report.contains("\n  errors          1"));
+        assert_eq!(format_duration(Duration::from_secs(725)), "12m05s");
+    }
+}
@@ -0,0 +1,139 @@
+//! Health Connect JSON export.
+//!
+//! Android has no file import for Health Connect, so bridge apps (Tasker
+//! flows, Health Sync and friends) read records from JSON. This writes one
+//! document per workout holding the records a treadmill run maps to, named
+//! after the Health Connect record classes with their unit-suffixed fields.
+//! Times are UTC with the local zone offset alongside, as the records
+//! store them.
+
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::export::{iso8601, Metadata, Sport};
+use crate::workout::Workout;
+
+/// `ExerciseSessionRecord.EXERCISE_TYPE_RUNNING_TREADMILL`.
+const EXERCISE_TYPE_RUNNING_TREADMILL: u32 = 57;
+/// `ExerciseSessionRecord.EXERCISE_TYPE_WALKING`.
+const EXERCISE_TYPE_WALKING: u32 = 79;
+
+/// Encode `workout` as a Health Connect record bundle. Calories are only
+/// included when the user's weight is known.
+pub fn encode(workout: &Workout, meta: &Metadata, weight_kg: Option<f64>) -> Vec<u8> {
+    let (exercise_type, default_title) = match meta.sport {
+        Sport::Running => (EXERCISE_TYPE_RUNNING_TREADMILL, "Treadmill run"),
+        Sport::Walking => (EXERCISE_TYPE_WALKING, "Treadmill walk"),
+    };
+    let start = iso8601(workout.start_wall_ms);
+    let end = iso8601(workout.end_wall_ms());
+    let zone_offset = clock::format_offset(workout.utc_offset_secs);
+    let span = |mut record: Value| {
+        record["startTime"] = json!(start);
+        record["startZoneOffset"] = json!(zone_offset);
+        record["endTime"] = json!(end);
+        record["endZoneOffset"] = json!(zone_offset);
+        record
+    };
+
+    let mut records = vec![
+        span(json!({
+            "recordType": "ExerciseSessionRecord",
+            "exerciseType": exercise_type,
+            "title": meta.title.as_deref().unwrap_or(default_title),
+        })),
+        span(json!({
+            "recordType": "DistanceRecord",
+            "distance": { "inMeters": round2(workout.distance_m()) },
+        })),
+        span(json!({
+            "recordType": "SpeedRecord",
+            "samples": workout.samples.iter().map(|s| json!({
+                "time": iso8601(s.wall_ms),
+                "speed": { "inMetersPerSecond": round2(s.speed_mps()) },
+            })).collect::<Vec<_>>(),
+        })),
+    ];
+    let climb_m = round2(workout.elevation_gain_m());
+    if climb_m > 0.0 {
+        records.push(span(json!({
+            "recordType": "ElevationGainedRecord",
+            "elevation": { "inMeters": climb_m },
+        })));
+    }
+    if let Some(kg) = weight_kg {
+        records.push(span(json!({
+            "recordType": "ActiveCaloriesBurnedRecord",
+            "energy": { "inKilocalories": round2(workout.active_kcal(kg)) },
+        })));
+    }
+    let hr_samples: Vec<Value> = workout
+        .samples
+        .iter()
+        .filter_map(|s| s.heart_rate.map(|bpm| json!({ "time": iso8601(s.wall_ms), "beatsPerMinute": bpm })))
+        .collect();
+    if !hr_samples.is_empty() {
+        records.push(span(json!({ "recordType": "HeartRateRecord", "samples": hr_samples })));
+    }
+
+    let doc = json!({
+        "source": meta.device.as_deref().unwrap_or("precor-ftms"),
+        "durationSeconds": workout.elapsed_secs(),
+        "records": records,
+    });
+    serde_json::to_vec_pretty(&doc).unwrap_or_default()
+}
+
+fn round2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    fn record<'a>(doc: &'a Value, kind: &str) -> Option<&'a Value> {
+        doc["records"].as_array().unwrap().iter().find(|r| r["recordType"] == kind)
+    }
+
+    #[test]
+    fn test_encode_records() {
+        let w = sample_workout();
+        let doc: Value = serde_json::from_slice(&encode(&w, &Metadata::default(), Some(70.0))).unwrap();
+
+        assert_eq!(doc["durationSeconds"], 660);
+        let session = record(&doc, "ExerciseSessionRecord").unwrap();
+        assert_eq!(session["exerciseType"], 57);
+        assert_eq!(session["title"], "Treadmill run");
+        assert_eq!(doc["source"], "precor-ftms");
+        assert_eq!(session["startTime"], "2023-11-14T22:13:20Z");
+        assert_eq!(session["endTime"], "2023-11-14T22:24:20Z");
+        assert_eq!(session["startZoneOffset"], "-05:00");
+
+        let distance = record(&doc, "DistanceRecord").unwrap()["distance"]["inMeters"].as_f64().unwrap();
+        assert!((distance - 1689.81).abs() < 0.02);
+        assert_eq!(record(&doc, "SpeedRecord").unwrap()["samples"].as_array().unwrap().len(), 660);
+        // HR only for the first 600 samples
+        let hr = record(&doc, "HeartRateRecord").unwrap()["samples"].as_array().unwrap();
+        assert_eq!(hr.len(), 600);
+        assert_eq!(hr[0]["beatsPerMinute"], 120);
+        assert!(record(&doc, "ActiveCaloriesBurnedRecord").is_some());
+        let climb = record(&doc, "ElevationGainedRecord").unwrap()["elevation"]["inMeters"].as_f64().unwrap();
+        assert!((climb - 33.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_encode_without_weight_or_hr_with_metadata() {
+        let mut w = sample_workout();
+        w.samples.iter_mut().for_each(|s| s.heart_rate = None);
+        let meta = Metadata { title: Some("Hills".to_string()), sport: Sport::Walking, device: Some("Precor 9.31".to_string()) };
+        let doc: Value = serde_json::from_slice(&encode(&w, &meta, None)).unwrap();
+        assert!(record(&doc, "ActiveCaloriesBurnedRecord").is_none());
+        assert!(record(&doc, "HeartRateRecord").is_none());
+        let session = record(&doc, "ExerciseSessionRecord").unwrap();
+        assert_eq!(session["exerciseType"], 79);
+        assert_eq!(session["title"], "Hills");
+        assert_eq!(doc["source"], "Precor 9.31");
+    }
+}
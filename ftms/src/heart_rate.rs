@@ -0,0 +1,102 @@
+//! Heart rate bridged in from hrm-daemon.
+//!
+//! With `hrm_socket` set (the default, `/tmp/hrm.sock`), a task follows
+//! hrm-daemon's 1 Hz `{"type":"hr",...}` broadcast and keeps
+//! `TreadmillState::heart_rate` current, so Treadmill Data carries the
+//! strap's BPM and apps like Zwift need to pair only the treadmill. The
+//! Feature characteristic then advertises Heart Rate; notifications only
+//! set the field while there's a reading, i.e. a strap is connected and
+//! notifying and hrm-daemon is answering. The same value goes into the
+//! workout samples.
+//!
+//! hrm-daemon not running is normal; the task retries quietly with backoff.
+
+use std::time::Duration;
+
+use log::{debug, info};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+
+use crate::ftms_service::ControlContext;
+
+/// A broadcast this late means hrm-daemon stopped sending; the BPM is
+/// dropped rather than repeated.
+const SILENT_AFTER: Duration = Duration::from_secs(5);
+
+/// Longest wait between connection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The fields of hrm-daemon's broadcast that matter here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HrMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    bpm: u16,
+    connected: bool,
+    stale: bool,
+}
+
+/// The BPM an hrm-daemon line carries: `None` for lines that aren't the HR
+/// broadcast, `Some(None)` when there's no reading to trust.
+fn reading(line: &str) -> Option<Option<u8>> {
+    let msg: HrMessage = serde_json::from_str(line).ok()?;
+    if msg.kind != "hr" {
+        return None;
+    }
+    let usable = msg.connected && !msg.stale && msg.bpm > 0;
+    Some(usable.then(|| msg.bpm.min(u8::MAX as u16) as u8))
+}
+
+/// Follow hrm-daemon at `path` forever, reconnecting with backoff.
+pub async fn run(ctx: ControlContext, path: String) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match follow(&ctx, &path, &mut backoff).await {
+            Ok(()) => info!("hrm-daemon closed {}", path),
+            Err(e) => debug!("hrm-daemon at {}: {}", path, e),
+        }
+        ctx.state.lock().await.heart_rate = None;
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn follow(ctx: &ControlContext, path: &str, backoff: &mut Duration) -> std::io::Result<()> {
+    let stream = UnixStream::connect(path).await?;
+    info!("Following heart rate from hrm-daemon at {}", path);
+    *backoff = Duration::from_secs(1);
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        let line = match tokio::time::timeout(SILENT_AFTER, lines.next_line()).await {
+            Ok(Ok(Some(line))) => line,
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                ctx.state.lock().await.heart_rate = None;
+                continue;
+            }
+        };
+        if let Some(bpm) = reading(&line) {
+            ctx.health.touch("hrm");
+            ctx.state.lock().await.heart_rate = bpm;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reading() {
+        let hr = r#"{"type":"hr","bpm":142,"connected":true,"stale":false,"last_sample_age_ms":480}"#;
+        assert_eq!(reading(hr), Some(Some(142)));
+        assert_eq!(reading(r#"{"type":"hr","bpm":142,"connected":true,"stale":true}"#), Some(None));
+        assert_eq!(reading(r#"{"type":"hr","bpm":0,"connected":false,"stale":false}"#), Some(None));
+        assert_eq!(reading(r#"{"type":"hr","bpm":300,"connected":true,"stale":false}"#), Some(Some(255)));
+        assert_eq!(reading(r#"{"type":"status","bpm":142,"connected":true}"#), None);
+        assert_eq!(reading("not json"), None);
+    }
+}
@@ -0,0 +1,513 @@
+//! Workout history.
+//!
+//! Every finished workout long enough to export is summarized as one JSON
+//! line appended to `history_file`: label, start time, duration, distance,
+//! elevation gain, average HR, active calories (with `body_weight_kg` set)
+//! and, with `max_heart_rate` set, its TRIMP (see [`Workout::trimp`]).
+//! Labels of past workouts can be changed with [`relabel`]. The
+//! file is the source for the rolling training load shown by the debug
+//! `load` command and in workout notifications, and for the weekly and
+//! monthly totals of `totals`.
+//!
+//! Each record also keeps the workout's best-effort splits (fastest 1 km
+//! and mile anywhere in it, see [`Workout::best_effort_secs`]). The
+//! personal records of `records` are the best of those, plus the longest
+//! run, across the file, so they move as soon as a workout that beats one
+//! is added. Workouts recorded before the splits were kept count for the
+//! longest run only.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::{FtmsConfig, Units};
+use crate::export::{self, MIN_EXPORT_SECS};
+use crate::protocol::METERS_PER_MILE;
+use crate::workout::Workout;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// One finished workout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkoutRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Unix epoch ms when the belt started.
+    pub start_wall_ms: u64,
+    pub utc_offset_secs: i32,
+    pub elapsed_secs: u32,
+    pub distance_m: f64,
+    #[serde(default)]
+    pub elevation_gain_m: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_heart_rate: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kcal: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trimp: Option<f64>,
+    /// Fastest 1 km within the workout, seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_1k_secs: Option<u32>,
+    /// Fastest mile within the workout, seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_mile_secs: Option<u32>,
+}
+
+impl WorkoutRecord {
+    pub fn new(workout: &Workout, config: &FtmsConfig) -> Self {
+        Self {
+            label: workout.label.clone(),
+            start_wall_ms: workout.start_wall_ms,
+            utc_offset_secs: workout.utc_offset_secs,
+            elapsed_secs: workout.elapsed_secs(),
+            distance_m: (workout.distance_m() * 10.0).round() / 10.0,
+            elevation_gain_m: (workout.elevation_gain_m() * 10.0).round() / 10.0,
+            avg_heart_rate: workout.avg_heart_rate(),
+            kcal: config.body_weight_kg.map(|kg| workout.active_kcal(kg).round()),
+            trimp: config.max_heart_rate.and_then(|max| workout.trimp(max)).map(|t| (t * 10.0).round() / 10.0),
+            best_1k_secs: workout.best_effort_secs(1000.0),
+            best_mile_secs: workout.best_effort_secs(METERS_PER_MILE),
+        }
+    }
+}
+
+/// Append `workout` to the history file, if one is configured and the
+/// workout is long enough to count.
+pub async fn record(workout: &Workout, config: &FtmsConfig) {
+    let Some(path) = config.history_file.as_deref() else {
+        return;
+    };
+    if workout.elapsed_secs() < MIN_EXPORT_SECS {
+        return;
+    }
+    let record = WorkoutRecord::new(workout, config);
+    match append(path, &record).await {
+        Ok(()) => info!("Added workout to history {}", path),
+        Err(e) => {
+            warn!("Failed to add workout to history {}: {}", path, e);
+            return;
+        }
+    }
+    for best in new_records(&load(path).await) {
+        info!("New personal record: {}", best.describe(config.units));
+    }
+}
+
+async fn append(path: &str, record: &WorkoutRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(line.as_bytes()).await
+}
+
+/// Every workout in the history file, oldest first. Unreadable lines are
+/// skipped; a missing file is an empty history.
+pub async fn load(path: &str) -> Vec<WorkoutRecord> {
+    let Ok(data) = tokio::fs::read_to_string(path).await else {
+        return Vec::new();
+    };
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| match serde_json::from_str(l) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                warn!("Skipping bad history line in {}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Set (or with `None`, clear) the label of the `n`th most recent workout
+/// (1 = the latest), rewriting the file atomically. Returns the updated
+/// record, or None when there aren't `n` workouts. Files already exported
+/// keep their old label.
+pub async fn relabel(path: &str, n: usize, label: Option<String>) -> std::io::Result<Option<WorkoutRecord>> {
+    let mut records = load(path).await;
+    let Some(i) = records.len().checked_sub(n).filter(|_| n > 0) else {
+        return Ok(None);
+    };
+    records[i].label = label;
+    let mut data = String::new();
+    for r in &records {
+        data.push_str(&serde_json::to_string(r)?);
+        data.push('\n');
+    }
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(Some(records.swap_remove(i)))
+}
+
+/// Rolling TRIMP totals at `now_ms`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainingLoad {
+    /// Last 7 days.
+    pub acute: f64,
+    /// Last 28 days.
+    pub chronic: f64,
+}
+
+impl TrainingLoad {
+    pub fn at(records: &[WorkoutRecord], now_ms: u64) -> Self {
+        let since = |days: u64| -> f64 {
+            let cutoff = now_ms.saturating_sub(days * DAY_MS);
+            records.iter().filter(|r| r.start_wall_ms >= cutoff).filter_map(|r| r.trimp).sum()
+        };
+        Self { acute: since(7), chronic: since(28) }
+    }
+
+    /// This week's load against the 28-day weekly average; above ~1.5 is
+    /// a sharp jump in training stress.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.chronic > 0.0).then(|| self.acute / (self.chronic / 4.0))
+    }
+
+    /// Human-readable report for the debug console.
+    pub fn report(&self) -> String {
+        let ratio = self.ratio().map(|r| format!("{:.2}", r)).unwrap_or_else(|| "-".to_string());
+        format!(
+            "training load (TRIMP)\n  7-day   {:.0}\n  28-day  {:.0} ({:.0}/week)\n  acute:chronic {}",
+            self.acute,
+            self.chronic,
+            self.chronic / 4.0,
+            ratio
+        )
+    }
+}
+
+/// Calendar period for [`totals`], in local time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    /// Monday to Sunday.
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "week" | "weekly" => Some(Period::Week),
+            "month" | "monthly" => Some(Period::Month),
+            _ => None,
+        }
+    }
+
+    /// Index of the period holding local day `day` (days since
+    /// 1970-01-01): weeks since the Monday before the epoch, or months
+    /// since year 0.
+    fn index(self, day: i64) -> i64 {
+        match self {
+            // 1970-01-01 was a Thursday
+            Period::Week => (day + 3).div_euclid(7),
+            Period::Month => {
+                let (y, m, _) = export::civil_from_days(day);
+                y * 12 + m as i64 - 1
+            }
+        }
+    }
+
+    /// `2024-03-11` (the Monday) or `2024-03`.
+    fn label(self, index: i64) -> String {
+        match self {
+            Period::Week => {
+                let (y, m, d) = export::civil_from_days(index * 7 - 3);
+                format!("{:04}-{:02}-{:02}", y, m, d)
+            }
+            Period::Month => format!("{:04}-{:02}", index.div_euclid(12), index.rem_euclid(12) + 1),
+        }
+    }
+}
+
+/// Summed workouts for one period.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    /// First day (`YYYY-MM-DD`) of the week or the month (`YYYY-MM`).
+    pub period: String,
+    pub sessions: u32,
+    pub elapsed_secs: u64,
+    pub distance_m: f64,
+    pub elevation_gain_m: f64,
+    /// Active calories of the workouts that have them.
+    pub kcal: f64,
+}
+
+/// Totals for the `count` most recent periods up to the one holding
+/// `now_ms` (local at `utc_offset_secs`), oldest first. Periods without
+/// workouts are included as zeros.
+pub fn totals(records: &[WorkoutRecord], period: Period, count: usize, now_ms: u64, utc_offset_secs: i32) -> Vec<Totals> {
+    let day = |wall_ms: u64, offset: i32| (wall_ms as i64 + offset as i64 * 1000).div_euclid(DAY_MS as i64);
+    let last = period.index(day(now_ms, utc_offset_secs));
+    let first = last - count as i64 + 1;
+    let mut out: Vec<Totals> =
+        (first..=last).map(|i| Totals { period: period.label(i), ..Default::default() }).collect();
+    for r in records {
+        let i = period.index(day(r.start_wall_ms, r.utc_offset_secs));
+        if i < first || i > last {
+            continue;
+        }
+        let t = &mut out[(i - first) as usize];
+        t.sessions += 1;
+        t.elapsed_secs += r.elapsed_secs as u64;
+        t.distance_m += r.distance_m;
+        t.elevation_gain_m += r.elevation_gain_m;
+        t.kcal += r.kcal.unwrap_or(0.0);
+    }
+    out
+}
+
+/// A personal record kept across the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Effort {
+    #[serde(rename = "fastest_1k")]
+    Fastest1k,
+    #[serde(rename = "fastest_mile")]
+    FastestMile,
+    #[serde(rename = "longest_run")]
+    LongestRun,
+}
+
+impl Effort {
+    pub const ALL: [Effort; 3] = [Effort::Fastest1k, Effort::FastestMile, Effort::LongestRun];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Effort::Fastest1k => "fastest 1k",
+            Effort::FastestMile => "fastest mile",
+            Effort::LongestRun => "longest run",
+        }
+    }
+
+    /// What `record` did at this effort: seconds for the splits, meters
+    /// for the longest run.
+    fn value(self, record: &WorkoutRecord) -> Option<f64> {
+        match self {
+            Effort::Fastest1k => record.best_1k_secs.map(f64::from),
+            Effort::FastestMile => record.best_mile_secs.map(f64::from),
+            Effort::LongestRun => (record.distance_m > 0.0).then_some(record.distance_m),
+        }
+    }
+
+    /// Whether `value` beats `best`.
+    fn beats(self, value: f64, best: f64) -> bool {
+        match self {
+            Effort::LongestRun => value > best,
+            _ => value < best,
+        }
+    }
+}
+
+/// The best at one effort and the workout that set it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Best {
+    pub effort: Effort,
+    /// Seconds for the fastest splits, meters for the longest run.
+    pub value: f64,
+    pub start_wall_ms: u64,
+    pub utc_offset_secs: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl Best {
+    fn of(effort: Effort, value: f64, record: &WorkoutRecord) -> Self {
+        Self {
+            effort,
+            value,
+            start_wall_ms: record.start_wall_ms,
+            utc_offset_secs: record.utc_offset_secs,
+            label: record.label.clone(),
+        }
+    }
+
+    /// `fastest mile 7:32` or `longest run 6.21 mi`.
+    pub fn describe(&self, units: Units) -> String {
+        let value = match self.effort {
+            Effort::LongestRun => units.distance(self.value.round() as u32),
+            _ => crate::notify::format_duration(self.value as u32),
+        };
+        format!("{} {}", self.effort.name(), value)
+    }
+}
+
+/// The best of each effort across `records`, in [`Effort::ALL`] order,
+/// leaving out efforts no workout has. A tie stays with the earlier
+/// workout.
+pub fn personal_records(records: &[WorkoutRecord]) -> Vec<Best> {
+    Effort::ALL
+        .iter()
+        .filter_map(|&effort| {
+            let mut best: Option<Best> = None;
+            for r in records {
+                let Some(value) = effort.value(r) else { continue };
+                if best.as_ref().is_none_or(|b| effort.beats(value, b.value)) {
+                    best = Some(Best::of(effort, value, r));
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// The records the latest workout in `records` just set, beating an
+/// earlier workout's. The very first workout at an effort doesn't count.
+pub fn new_records(records: &[WorkoutRecord]) -> Vec<Best> {
+    let Some((latest, earlier)) = records.split_last() else {
+        return Vec::new();
+    };
+    let before = personal_records(earlier);
+    Effort::ALL
+        .iter()
+        .filter_map(|&effort| {
+            let value = effort.value(latest)?;
+            let previous = before.iter().find(|b| b.effort == effort)?;
+            effort.beats(value, previous.value).then(|| Best::of(effort, value, latest))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    #[tokio::test]
+    async fn test_record_and_load() {
+        let path = "/tmp/ftms_test_history.jsonl";
+        let _ = std::fs::remove_file(path);
+        let config = FtmsConfig {
+            history_file: Some(path.to_string()),
+            max_heart_rate: Some(200),
+            ..Default::default()
+        };
+        let w = sample_workout();
+        record(&w, &config).await;
+        record(&Workout::new(0, 0), &config).await; // too short
+        std::fs::write(path, std::fs::read_to_string(path).unwrap() + "garbage\n").unwrap();
+        record(&w, &config).await;
+
+        let records = load(path).await;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].label, None);
+
+        let updated = relabel(path, 2, Some("Zone 2".to_string())).await.unwrap().unwrap();
+        assert_eq!(updated.label.as_deref(), Some("Zone 2"));
+        assert_eq!(relabel(path, 3, None).await.unwrap(), None);
+        assert_eq!(relabel(path, 0, None).await.unwrap(), None);
+        let records = load(path).await;
+        assert_eq!(records.len(), 2, "bad line dropped, nothing else");
+        assert_eq!((records[0].label.as_deref(), records[1].label.as_deref()), (Some("Zone 2"), None));
+        assert!((records[0].trimp.unwrap() - 30.05).abs() < 0.06);
+        assert_eq!(records[0].avg_heart_rate, Some(149));
+        assert_eq!(records[0].elapsed_secs, 660);
+        assert_eq!(records[0].best_1k_secs, Some(373));
+        assert!(records[0].best_mile_secs.is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_totals() {
+        // Friday 2024-03-15 07:15 UTC
+        let now = 1_710_486_902_000;
+        let at = |wall_ms: u64, distance_m: f64| WorkoutRecord {
+            label: None,
+            start_wall_ms: wall_ms,
+            utc_offset_secs: -8 * 3600,
+            elapsed_secs: 600,
+            distance_m,
+            elevation_gain_m: 10.0,
+            avg_heart_rate: None,
+            kcal: Some(100.0),
+            trimp: None,
+            best_1k_secs: None,
+            best_mile_secs: None,
+        };
+        let records = [
+            at(now - 40 * DAY_MS, 1000.0), // February
+            at(now - 3 * DAY_MS, 2000.0),  // Monday 03-11, local time
+            at(now - 4 * DAY_MS, 3000.0),  // Sunday 03-10
+            at(now, 4000.0),               // Thursday 03-14
+        ];
+        let weeks = totals(&records, Period::Week, 3, now, -8 * 3600);
+        assert_eq!(weeks.iter().map(|t| t.period.as_str()).collect::<Vec<_>>(), ["2024-02-26", "2024-03-04", "2024-03-11"]);
+        assert_eq!(weeks[0].sessions, 0);
+        assert_eq!((weeks[1].sessions, weeks[1].distance_m), (1, 3000.0));
+        assert_eq!(weeks[2], Totals {
+            period: "2024-03-11".to_string(),
+            sessions: 2,
+            elapsed_secs: 1200,
+            distance_m: 6000.0,
+            elevation_gain_m: 20.0,
+            kcal: 200.0,
+        });
+
+        let months = totals(&records, Period::Month, 2, now, -8 * 3600);
+        assert_eq!(months[0].period, "2024-02");
+        assert_eq!((months[0].sessions, months[1].sessions), (1, 3));
+        assert_eq!(Period::parse("monthly"), Some(Period::Month));
+    }
+
+    #[test]
+    fn test_training_load() {
+        let now = 1_700_000_000_000;
+        let at = |days_ago: u64, trimp: Option<f64>| WorkoutRecord {
+            label: None,
+            start_wall_ms: now - days_ago * DAY_MS,
+            utc_offset_secs: 0,
+            elapsed_secs: 1800,
+            distance_m: 5000.0,
+            elevation_gain_m: 0.0,
+            avg_heart_rate: None,
+            kcal: None,
+            trimp,
+            best_1k_secs: None,
+            best_mile_secs: None,
+        };
+        let records = [at(40, Some(500.0)), at(20, Some(100.0)), at(10, Some(60.0)), at(3, Some(80.0)), at(1, None)];
+        let load = TrainingLoad::at(&records, now);
+        assert_eq!(load, TrainingLoad { acute: 80.0, chronic: 240.0 });
+        assert_eq!(load.ratio(), Some(80.0 / 60.0));
+        assert!(load.report().contains("28-day  240 (60/week)"), "{}", load.report());
+        assert_eq!(TrainingLoad::at(&[], now).ratio(), None);
+    }
+
+    #[test]
+    fn test_personal_records() {
+        let at = |day: u64, distance_m: f64, best_1k_secs: Option<u32>, best_mile_secs: Option<u32>| WorkoutRecord {
+            label: Some(format!("day {}", day)),
+            start_wall_ms: day * DAY_MS,
+            utc_offset_secs: 0,
+            elapsed_secs: 1800,
+            distance_m,
+            elevation_gain_m: 0.0,
+            avg_heart_rate: None,
+            kcal: None,
+            trimp: None,
+            best_1k_secs,
+            best_mile_secs,
+        };
+        // An old record without splits still counts for distance
+        let mut records = vec![at(1, 8000.0, None, None), at(2, 5000.0, Some(300), Some(500)), at(3, 5000.0, Some(300), None)];
+        let bests = personal_records(&records);
+        assert_eq!(bests.iter().map(|b| (b.effort, b.value, b.start_wall_ms / DAY_MS)).collect::<Vec<_>>(), [
+            (Effort::Fastest1k, 300.0, 2), // tie stays with the earlier workout
+            (Effort::FastestMile, 500.0, 2),
+            (Effort::LongestRun, 8000.0, 1),
+        ]);
+        assert_eq!(bests[1].describe(Units::Imperial), "fastest mile 8:20");
+        assert_eq!(bests[2].describe(Units::Metric), "longest run 8.00 km");
+        assert!(new_records(&records).is_empty(), "a tie isn't a new record");
+
+        records.push(at(4, 9000.0, Some(290), Some(510)));
+        let new = new_records(&records);
+        assert_eq!(new.iter().map(|b| b.effort).collect::<Vec<_>>(), [Effort::Fastest1k, Effort::LongestRun]);
+        assert_eq!(new[0].label.as_deref(), Some("day 4"));
+        assert_eq!(personal_records(&records)[0].value, 290.0);
+        assert!(new_records(&records[..1]).is_empty(), "nothing to beat yet");
+        assert!(personal_records(&[]).is_empty());
+        assert_eq!(
+            serde_json::to_string(&bests[0]).unwrap(),
+            r#"{"effort":"fastest_1k","value":300.0,"start_wall_ms":172800000,"utc_offset_secs":0,"label":"day 2"}"#
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Command-to-hardware latency histograms.
+//!
+//! Every control point command is timed from the moment it's handled:
+//! `send` until the command has been written to treadmill_io, and `speed` /
+//! `incline` until treadmill_io status first shows the target reached (as
+//! seen by the target verifier, so to within its 100 ms poll). Retries are
+//! included; targets that never converge or are superseded aren't recorded.
+//! Shown by the debug `latency` command and, in Prometheus text format, by
+//! `metrics`.
+
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::treadmill::Target;
+
+/// Histogram bucket upper bounds, in milliseconds.
+const BUCKETS_MS: [u64; 11] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10_000, 30_000];
+
+/// One measured leg of the command path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Control point write → command written to treadmill_io.
+    Send,
+    /// Control point write → speed target seen in status.
+    Speed,
+    /// Control point write → incline target seen in status.
+    Incline,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Send, Stage::Speed, Stage::Incline];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Send => "send",
+            Stage::Speed => "speed",
+            Stage::Incline => "incline",
+        }
+    }
+
+    /// The stage that confirms `target`.
+    pub fn applied(target: Target) -> Stage {
+        match target {
+            Target::Speed(_) => Stage::Speed,
+            Target::Incline(_) => Stage::Incline,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket; the last slot is overflow.
+    buckets: [u64; BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: f64,
+    max_ms: f64,
+}
+
+impl Histogram {
+    fn record(&mut self, d: Duration) {
+        let ms = d.as_secs_f64() * 1000.0;
+        let slot = BUCKETS_MS.iter().position(|&b| ms <= b as f64).unwrap_or(BUCKETS_MS.len());
+        self.buckets[slot] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, in ms. Overflow
+    /// reports the max seen.
+    fn quantile_ms(&self, q: f64) -> f64 {
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return BUCKETS_MS.get(i).map_or(self.max_ms, |&b| b as f64).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+}
+
+/// Cheap, cloneable handle to the latency histograms.
+#[derive(Clone, Default)]
+pub struct Latency {
+    stages: Arc<Mutex<[Histogram; 3]>>,
+}
+
+impl Latency {
+    pub fn record(&self, stage: Stage, d: Duration) {
+        self.stages.lock().unwrap()[stage as usize].record(d);
+    }
+
+    /// Human-readable summary for the debug console.
+    pub fn report(&self) -> String {
+        let stages = self.stages.lock().unwrap();
+        let mut out = String::from("stage     count     p50     p90     p99     max");
+        for stage in Stage::ALL {
+            let h = &stages[stage as usize];
+            if h.count == 0 {
+                let _ = write!(out, "\n{:<8} {:>6}       -       -       -       -", stage.name(), 0);
+                continue;
+            }
+            let _ = write!(
+                out,
+                "\n{:<8} {:>6} {:>7} {:>7} {:>7} {:>7}",
+                stage.name(),
+                h.count,
+                fmt_ms(h.quantile_ms(0.5)),
+                fmt_ms(h.quantile_ms(0.9)),
+                fmt_ms(h.quantile_ms(0.99)),
+                fmt_ms(h.max_ms)
+            );
+        }
+        out.push_str("\n(percentiles are bucket upper bounds)");
+        out
+    }
+
+    /// Prometheus text exposition of the histograms.
+    pub fn prometheus(&self) -> String {
+        let stages = self.stages.lock().unwrap();
+        let mut out = String::from(
+            "# HELP ftms_command_latency_seconds Control point command to treadmill_io send / status confirmation.\n\
+             # TYPE ftms_command_latency_seconds histogram\n",
+        );
+        for stage in Stage::ALL {
+            let h = &stages[stage as usize];
+            let mut cumulative = 0;
+            for (i, bound) in BUCKETS_MS.iter().enumerate() {
+                cumulative += h.buckets[i];
+                let _ = writeln!(
+                    out,
+                    "ftms_command_latency_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                    stage.name(),
+                    *bound as f64 / 1000.0,
+                    cumulative
+                );
+            }
+            let _ = writeln!(out, "ftms_command_latency_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}", stage.name(), h.count);
+            let _ = writeln!(out, "ftms_command_latency_seconds_sum{{stage=\"{}\"}} {}", stage.name(), h.sum_ms / 1000.0);
+            let _ = writeln!(out, "ftms_command_latency_seconds_count{{stage=\"{}\"}} {}", stage.name(), h.count);
+        }
+        out.pop();
+        out
+    }
+}
+
+fn fmt_ms(ms: f64) -> String {
+    if ms >= 1000.0 {
+        format!("{:.1}s", ms / 1000.0)
+    } else {
+        format!("{:.0}ms", ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles() {
+        let latency = Latency::default();
+        for ms in [5, 20, 20, 40, 80, 300, 300, 700, 1500, 45_000] {
+            latency.record(Stage::Speed, Duration::from_millis(ms));
+        }
+        let h = latency.stages.lock().unwrap()[Stage::Speed as usize].clone();
+        assert_eq!(h.count, 10);
+        assert_eq!(h.quantile_ms(0.5), 100.0);
+        assert_eq!(h.quantile_ms(0.9), 2500.0);
+        assert_eq!(h.quantile_ms(0.99), 45_000.0, "overflow reports the max");
+
+        let report = latency.report();
+        assert!(report.contains("speed        10   100ms    2.5s   45.0s   45.0s"), "{}", report);
+        assert!(report.contains("send          0       -"));
+    }
+
+    #[test]
+    fn test_prometheus_buckets_are_cumulative() {
+        let latency = Latency::default();
+        latency.record(Stage::Send, Duration::from_millis(3));
+        latency.record(Stage::Send, Duration::from_millis(60));
+        let text = latency.prometheus();
+        assert!(text.contains("ftms_command_latency_seconds_bucket{stage=\"send\",le=\"0.01\"} 1\n"));
+        assert!(text.contains("ftms_command_latency_seconds_bucket{stage=\"send\",le=\"0.1\"} 2\n"));
+        assert!(text.contains("ftms_command_latency_seconds_bucket{stage=\"send\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("ftms_command_latency_seconds_count{stage=\"incline\"} 0"));
+    }
+}
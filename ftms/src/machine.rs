@@ -0,0 +1,407 @@
+//! Simulated treadmill_io and the treadmill behind it, shared by the
+//! daemon's `--mock-treadmill` (`mock.rs`) and the standalone
+//! `treadmill-sim` binary, which pulls this file in by path.
+//!
+//! The line protocol follows treadmill_io: `hello`, `caps`, `status`,
+//! `heartbeat`, `speed`, `incline`, `emulate`, `proxy` and `quit`, with its
+//! clamps (12.0 mph, 99%), its emulate rules (speed and incline switch it
+//! on, and it always starts at 0) and its heartbeat watchdog (4 s without a
+//! command while emulating drops back to proxy at 0). A command carrying
+//! an `id` is checked strictly instead and answered to its sender alone,
+//! with an `ack` or an `error` naming the id.
+//!
+//! `emu_speed`/`emu_incline` are the commanded values straight away, as in
+//! emulate mode; the bus fields follow the belt, which speeds up and slows
+//! down at `Physics` rates, and the incline motor. Unlike treadmill_io,
+//! `tick` returns a status line whenever the belt or motor moves, so
+//! clients see them without polling.
+
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+/// treadmill_io's IPC protocol version (`IPC_PROTOCOL_VERSION`).
+pub const PROTOCOL_VERSION: u32 = 3;
+/// treadmill_io's clamps: 12.0 mph, and 99% in half-percent units.
+pub const MAX_SPEED_TENTHS: u16 = 120;
+pub const MAX_INCLINE_HALF_PCT: u16 = 198;
+/// Emulate without a command for this long and the watchdog drops it.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(4);
+/// How often the belt and motor are advanced.
+pub const TICK: Duration = Duration::from_millis(100);
+/// What `caps` lists, as treadmill_io does.
+const COMMANDS: [&str; 9] = ["speed", "incline", "emulate", "proxy", "status", "heartbeat", "quit", "hello", "caps"];
+
+/// How fast the belt and incline motor move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Physics {
+    pub accel_mph_per_s: f64,
+    pub decel_mph_per_s: f64,
+    pub incline_pct_per_s: f64,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self { accel_mph_per_s: 1.0, decel_mph_per_s: 1.5, incline_pct_per_s: 1.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Idle,
+    Proxy,
+    Emulating,
+}
+
+/// What a command line produces: lines for every client, and for a
+/// command with an id, the `ack` or `error` for the client that sent it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reply {
+    pub sender: Option<String>,
+    pub lines: Vec<String>,
+}
+
+/// The simulated treadmill_io and the machine behind it.
+#[derive(Debug)]
+pub struct Machine {
+    pub mode: Mode,
+    /// Commanded in emulate mode, tenths of mph and half-percent units.
+    pub speed_tenths: u16,
+    pub incline_half_pct: u16,
+    /// Set at the console; the belt follows these outside emulate.
+    console_speed_tenths: u16,
+    console_incline_half_pct: u16,
+    /// Where the belt (tenths of mph) and incline motor (half-percent) are.
+    belt: f64,
+    motor: f64,
+    physics: Physics,
+    last_command: Instant,
+}
+
+impl Machine {
+    /// A stopped machine in proxy mode, as treadmill_io starts.
+    pub fn new(physics: Physics, now: Instant) -> Self {
+        Self {
+            mode: Mode::Proxy,
+            speed_tenths: 0,
+            incline_half_pct: 0,
+            console_speed_tenths: 0,
+            console_incline_half_pct: 0,
+            belt: 0.0,
+            motor: 0.0,
+            physics,
+            last_command: now,
+        }
+    }
+
+    /// Apply one command line, returning what to send back, or None for
+    /// `quit`. Malformed and unknown commands are ignored and don't count
+    /// as a heartbeat, as in treadmill_io (one with an id is told so).
+    pub fn command(&mut self, line: &str, now: Instant) -> Option<Reply> {
+        let Ok(cmd) = serde_json::from_str::<serde_json::Value>(line) else {
+            debug!("Ignoring malformed command: {}", line);
+            return Some(Reply::default());
+        };
+        let id = cmd["id"].as_u64();
+        let name = cmd["cmd"].as_str().unwrap_or("");
+        if let Some(id) = id {
+            if !COMMANDS.contains(&name) {
+                debug!("Refusing unknown command {:?}", name);
+                return Some(Reply { sender: Some(error(id, "unrecognised command")), lines: Vec::new() });
+            }
+            if let Some(reason) = refusal(name, &cmd) {
+                self.last_command = now;
+                return Some(Reply { sender: Some(error(id, reason)), lines: Vec::new() });
+            }
+        }
+        let value = cmd["value"].as_f64().unwrap_or(0.0);
+        let enabled = cmd["enabled"].as_bool().unwrap_or(false);
+        let lines = match name {
+            "speed" => {
+                self.enter_emulate();
+                self.speed_tenths = ((value * 10.0 + 0.5).max(0.0) as u16).min(MAX_SPEED_TENTHS);
+                vec![self.status()]
+            }
+            "incline" => {
+                self.enter_emulate();
+                self.incline_half_pct = ((value * 2.0).round().max(0.0) as u16).min(MAX_INCLINE_HALF_PCT);
+                vec![self.status()]
+            }
+            "emulate" => {
+                if enabled {
+                    self.enter_emulate();
+                } else if self.mode == Mode::Emulating {
+                    self.mode = Mode::Idle;
+                }
+                vec![self.status()]
+            }
+            "proxy" => {
+                if enabled {
+                    self.mode = Mode::Proxy;
+                } else if self.mode == Mode::Proxy {
+                    self.mode = Mode::Idle;
+                }
+                vec![self.status()]
+            }
+            "status" => vec![self.status()],
+            "heartbeat" => Vec::new(),
+            "quit" => return None,
+            "hello" => {
+                let version = cmd["version"].as_u64().unwrap_or(0);
+                if version != PROTOCOL_VERSION as u64 {
+                    warn!("Client speaks protocol v{}, we speak v{}", version, PROTOCOL_VERSION);
+                }
+                vec![serde_json::json!({"type": "hello", "version": PROTOCOL_VERSION}).to_string()]
+            }
+            "caps" => vec![serde_json::json!({
+                "type": "caps",
+                "commands": COMMANDS,
+                "max_speed": MAX_SPEED_TENTHS,
+                "min_incline": 0,
+                "max_incline": MAX_INCLINE_HALF_PCT,
+                "decline": false,
+            })
+            .to_string()],
+            other => {
+                debug!("Ignoring unknown command {:?}", other);
+                return Some(Reply::default());
+            }
+        };
+        self.last_command = now;
+        let sender = id.map(|id| serde_json::json!({"type": "ack", "id": id}).to_string());
+        Some(Reply { sender, lines })
+    }
+
+    /// Emulate always starts at 0 speed and incline.
+    fn enter_emulate(&mut self) {
+        if self.mode != Mode::Emulating {
+            self.mode = Mode::Emulating;
+            self.speed_tenths = 0;
+            self.incline_half_pct = 0;
+        }
+    }
+
+    /// Zero the targets and go back to proxy, as either of treadmill_io's
+    /// watchdogs does (no command for a while, or the last client gone).
+    pub fn watchdog_reset(&mut self) -> Vec<String> {
+        self.mode = Mode::Proxy;
+        self.speed_tenths = 0;
+        self.incline_half_pct = 0;
+        vec![self.status()]
+    }
+
+    /// Someone at the console. A change while emulating hands the machine
+    /// back to the console (proxy), as treadmill_io does.
+    #[allow(dead_code)] // only treadmill-sim's scripts use the console
+    pub fn console(&mut self, speed_tenths: Option<u16>, incline_half_pct: Option<u16>) -> Vec<String> {
+        if let Some(t) = speed_tenths {
+            self.console_speed_tenths = t.min(MAX_SPEED_TENTHS);
+        }
+        if let Some(h) = incline_half_pct {
+            self.console_incline_half_pct = h.min(MAX_INCLINE_HALF_PCT);
+        }
+        if self.mode == Mode::Emulating {
+            info!("Console input, leaving emulate");
+            self.mode = Mode::Proxy;
+        }
+        vec![self.status()]
+    }
+
+    /// What the belt and motor are heading for: the commanded values in
+    /// emulate, the console's otherwise.
+    fn targets(&self) -> (u16, u16) {
+        match self.mode {
+            Mode::Emulating => (self.speed_tenths, self.incline_half_pct),
+            Mode::Idle | Mode::Proxy => (self.console_speed_tenths, self.console_incline_half_pct),
+        }
+    }
+
+    /// Advance `dt`: the heartbeat watchdog, then the belt and incline
+    /// motor. Returns a status line if anything visible changed.
+    pub fn tick(&mut self, now: Instant, dt: Duration) -> Vec<String> {
+        if self.mode == Mode::Emulating && now.saturating_duration_since(self.last_command) > HEARTBEAT_TIMEOUT {
+            warn!("No command for {:?}, leaving emulate", HEARTBEAT_TIMEOUT);
+            return self.watchdog_reset();
+        }
+        let before = self.bus();
+        let (speed, incline) = self.targets();
+        let secs = dt.as_secs_f64();
+        let rate = if (speed as f64) < self.belt { self.physics.decel_mph_per_s } else { self.physics.accel_mph_per_s };
+        self.belt = approach(self.belt, speed as f64, rate * 10.0 * secs);
+        self.motor = approach(self.motor, incline as f64, self.physics.incline_pct_per_s * 2.0 * secs);
+        if self.bus() != before {
+            vec![self.status()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Belt and incline motor as the bus reports them.
+    fn bus(&self) -> (u16, u16) {
+        (self.belt.round() as u16, self.motor.round() as u16)
+    }
+
+    pub fn status(&self) -> String {
+        let (bus_speed, bus_incline) = self.bus();
+        serde_json::json!({
+            "type": "status",
+            "proxy": self.mode == Mode::Proxy,
+            "emulate": self.mode == Mode::Emulating,
+            "emu_speed": self.speed_tenths,
+            "emu_incline": self.incline_half_pct,
+            "bus_speed": bus_speed,
+            "bus_incline": bus_incline,
+            "console_bytes": 0,
+            "motor_bytes": 0,
+        })
+        .to_string()
+    }
+}
+
+/// Why treadmill_io refuses a command with an id, as its `refusal`.
+fn refusal(name: &str, cmd: &serde_json::Value) -> Option<&'static str> {
+    match name {
+        "speed" => match cmd["value"].as_f64() {
+            None => Some("speed needs a value"),
+            Some(mph) if mph < 0.0 || mph * 10.0 > MAX_SPEED_TENTHS as f64 + 0.5 => Some("speed out of range"),
+            Some(_) => None,
+        },
+        "incline" => match cmd["value"].as_f64() {
+            None => Some("incline needs a value"),
+            Some(pct) if pct < 0.0 || (pct * 2.0).round() > MAX_INCLINE_HALF_PCT as f64 => Some("incline out of range"),
+            Some(_) => None,
+        },
+        "emulate" | "proxy" if !cmd["enabled"].is_boolean() => Some("enabled must be true or false"),
+        _ => None,
+    }
+}
+
+fn error(id: u64, msg: &str) -> String {
+    serde_json::json!({"type": "error", "msg": msg, "id": id}).to_string()
+}
+
+fn approach(current: f64, target: f64, max_step: f64) -> f64 {
+    if current < target {
+        (current + max_step).min(target)
+    } else {
+        (current - max_step).max(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(line: &str) -> serde_json::Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    /// (emulate, emu_speed, emu_incline, bus_speed, bus_incline)
+    fn status(m: &Machine) -> (bool, u64, u64, u64, u64) {
+        let s = json(&m.status());
+        let n = |k: &str| s[k].as_u64().unwrap();
+        (s["emulate"].as_bool().unwrap(), n("emu_speed"), n("emu_incline"), n("bus_speed"), n("bus_incline"))
+    }
+
+    #[test]
+    fn test_commands() {
+        let t0 = Instant::now();
+        let mut m = Machine::new(Physics::default(), t0);
+        assert_eq!(m.command("junk", t0), Some(Reply::default()));
+        assert_eq!(m.command(r#"{"cmd":"nope"}"#, t0), Some(Reply::default()));
+        assert_eq!(json(&m.command(r#"{"cmd":"hello","version":3}"#, t0).unwrap().lines[0])["version"], 3);
+        let caps = json(&m.command(r#"{"cmd":"caps"}"#, t0).unwrap().lines[0]);
+        assert_eq!((caps["max_speed"].as_u64(), caps["max_incline"].as_u64()), (Some(120), Some(198)));
+        assert_eq!(m.command(r#"{"cmd":"quit"}"#, t0), None);
+
+        // Speed switches emulate on; commanded values show at once, clamped
+        m.command(r#"{"cmd":"speed","value":3.14}"#, t0);
+        m.command(r#"{"cmd":"incline","value":150}"#, t0);
+        assert_eq!(status(&m), (true, 31, 198, 0, 0));
+        m.command(r#"{"cmd":"speed","value":-2}"#, t0);
+        assert_eq!(status(&m).1, 0);
+
+        // Leaving and re-entering emulate starts again from 0
+        m.command(r#"{"cmd":"incline","value":2.5}"#, t0);
+        m.command(r#"{"cmd":"emulate","enabled":false}"#, t0);
+        assert_eq!(m.mode, Mode::Idle);
+        m.command(r#"{"cmd":"emulate","enabled":true}"#, t0);
+        assert_eq!(status(&m), (true, 0, 0, 0, 0));
+        m.command(r#"{"cmd":"proxy","enabled":true}"#, t0);
+        assert_eq!(m.mode, Mode::Proxy);
+    }
+
+    #[test]
+    fn test_command_ids() {
+        let t0 = Instant::now();
+        let mut m = Machine::new(Physics::default(), t0);
+        let answer = |m: &mut Machine, line: &str| json(&m.command(line, t0).unwrap().sender.unwrap());
+
+        // Acked ahead of the status it causes, which still goes to everyone
+        let reply = m.command(r#"{"cmd":"speed","value":3.0,"id":1}"#, t0).unwrap();
+        assert_eq!(json(&reply.sender.unwrap()), serde_json::json!({"type": "ack", "id": 1}));
+        assert_eq!(reply.lines.len(), 1);
+
+        // Refused rather than clamped, and nothing changes
+        let refused = answer(&mut m, r#"{"cmd":"incline","value":150,"id":2}"#);
+        assert_eq!((refused["type"].as_str(), refused["id"].as_u64()), (Some("error"), Some(2)));
+        assert_eq!(refused["msg"], "incline out of range");
+        assert_eq!(answer(&mut m, r#"{"cmd":"speed","id":3}"#)["msg"], "speed needs a value");
+        assert_eq!(answer(&mut m, r#"{"cmd":"emulate","id":4}"#)["msg"], "enabled must be true or false");
+        assert_eq!(answer(&mut m, r#"{"cmd":"nope","id":5}"#)["msg"], "unrecognised command");
+        assert_eq!(status(&m), (true, 30, 0, 0, 0));
+
+        // Without an id nothing is answered
+        assert_eq!(m.command(r#"{"cmd":"incline","value":150}"#, t0).unwrap().sender, None);
+    }
+
+    #[test]
+    fn test_physics() {
+        let t0 = Instant::now();
+        let mut m = Machine::new(Physics::default(), t0);
+        m.command(r#"{"cmd":"speed","value":3.0}"#, t0);
+        m.command(r#"{"cmd":"incline","value":1.0}"#, t0);
+
+        // 1 mph/s up and 1%/s: a second gets the belt to 1.0 mph and the
+        // motor to its 1%, each 100ms tick showing as a status line
+        let mut now = t0;
+        let mut statuses = 0;
+        for _ in 0..10 {
+            now += TICK;
+            m.command(r#"{"cmd":"heartbeat"}"#, now);
+            statuses += m.tick(now, TICK).len();
+        }
+        assert_eq!(status(&m), (true, 30, 2, 10, 2));
+        assert_eq!(statuses, 10);
+
+        // 1.5 mph/s down
+        m.command(r#"{"cmd":"speed","value":0.0}"#, now);
+        m.tick(now + Duration::from_millis(400), Duration::from_millis(400));
+        assert_eq!(status(&m).3, 4);
+        assert!(m.tick(now + Duration::from_secs(1), Duration::from_millis(600)).len() == 1);
+        assert!(m.tick(now + Duration::from_secs(2), Duration::from_secs(1)).is_empty());
+        assert_eq!(status(&m).3, 0);
+    }
+
+    #[test]
+    fn test_watchdog_and_console() {
+        let t0 = Instant::now();
+        let mut m = Machine::new(Physics::default(), t0);
+        m.command(r#"{"cmd":"speed","value":3.0}"#, t0);
+
+        // Heartbeats keep emulate; 4s without a command drops it
+        m.tick(t0 + Duration::from_secs(3), TICK);
+        m.command(r#"{"cmd":"heartbeat"}"#, t0 + Duration::from_secs(3));
+        m.tick(t0 + Duration::from_secs(6), TICK);
+        assert_eq!(m.mode, Mode::Emulating);
+        assert_eq!(m.tick(t0 + Duration::from_secs(8), TICK).len(), 1);
+        assert_eq!((m.mode, m.speed_tenths), (Mode::Proxy, 0));
+
+        // The console takes over from emulate and the belt follows it
+        let t1 = t0 + Duration::from_secs(10);
+        m.command(r#"{"cmd":"speed","value":3.0}"#, t1);
+        m.console(Some(20), Some(3));
+        assert_eq!(m.mode, Mode::Proxy);
+        assert_eq!(m.targets(), (20, 3));
+    }
+}
@@ -1,40 +1,176 @@
+mod activity;
+mod advertising;
+mod apple_health;
+mod archive;
+mod audit;
+mod calibration;
+mod check;
+mod clock;
+mod config;
+mod csv;
 mod debug_server;
+mod export;
+mod fanout;
+mod fit;
 mod ftms_service;
+mod gatt;
+mod grpc;
+mod health;
+mod health_connect;
+mod heart_rate;
+mod history;
+mod latency;
+mod machine;
+mod mock;
+mod notify;
+mod pause;
 mod protocol;
+mod profile;
+mod quiet;
+mod ramp;
+mod replay;
+mod restart;
+mod session;
+mod status;
+mod telemetry;
 mod treadmill;
+mod workout;
 
+use std::os::fd::AsRawFd;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 
+use daemon_common::{console, privileges, throttle, tls};
+use ftms_service::ControlContext;
 use treadmill::TreadmillState;
 
 const DEFAULT_SOCKET: &str = "/tmp/treadmill_io.sock";
+const DEFAULT_CONFIG: &str = "ftms_config.json";
 const DEFAULT_DEBUG_PORT: u16 = 8826;
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let (socket_path, debug_port) = parse_args();
-    log::info!("FTMS daemon starting, socket: {}, debug port: {}", socket_path, debug_port);
+    let Args { socket_path, config_path, debug_port, user, group, check } = parse_args();
+    if check {
+        std::process::exit(check::run(&config::load(&config_path), debug_port).await);
+    }
+    log::info!(
+        "FTMS daemon starting, socket: {}, config: {}, debug port: {}",
+        socket_path,
+        config_path,
+        debug_port
+    );
+
+    let config = Arc::new(config::load(&config_path));
+    quiet::check(&config.quiet_hours);
 
-    let state = Arc::new(Mutex::new(TreadmillState::default()));
+    let account = match privileges::resolve(user.as_deref(), group.as_deref()) {
+        Ok(account) => account,
+        Err(e) => {
+            log::error!("Can't drop privileges: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut inherited = restart::Inherited::from_env();
+    let debug_listener = match inherited.tcp("debug", debug_port) {
+        Some(listener) => Ok(listener),
+        None => debug_server::bind(debug_port).await,
+    };
+    let debug_listener = match debug_listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Debug server can't listen on port {}: {}", debug_port, e);
+            std::process::exit(1);
+        }
+    };
+    let tls = match config.debug_tls.as_ref().map(tls::acceptor).transpose() {
+        Ok(tls) => tls,
+        Err(e) => {
+            log::error!("Debug server TLS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let grpc_listener = match &config.grpc_addr {
+        Some(addr) => match grpc_port(addr).and_then(|port| inherited.tcp("grpc", port)) {
+            Some(listener) => Some(listener),
+            None => match grpc::bind(addr).await {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    log::error!("gRPC server can't listen on {}: {}", addr, e);
+                    std::process::exit(1);
+                }
+            },
+        },
+        None => None,
+    };
+    drop(inherited);
+    let mut handover = vec![("debug", debug_listener.as_raw_fd())];
+    if let Some(listener) = &grpc_listener {
+        handover.push(("grpc", listener.as_raw_fd()));
+    }
+    tokio::spawn(restart::on_signal(handover));
+    if let Some(account) = &account {
+        if let Err(e) = privileges::drop_to(account) {
+            log::error!("Can't drop privileges: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let state = Arc::new(Mutex::new(TreadmillState {
+        speed_slew_per_s: config.speed_slew_per_s(),
+        speed_correction: calibration::initial(&config),
+        ..Default::default()
+    }));
+    let (events, _) = broadcast::channel(32);
+    // Commands go out over the status connection treadmill::run keeps open
+    let (commands, command_queue) = treadmill::Commands::channel();
+    let ctx = ControlContext {
+        state: state.clone(),
+        socket_path: socket_path.clone(),
+        commands,
+        telemetry: telemetry::start(config.telemetry_log.as_deref()),
+        audit: audit::start(config.audit_log.as_deref()),
+        archive: archive::start(&config),
+        latency: latency::Latency::default(),
+        health: health::Health::default(),
+        ramp: ramp::Ramp::default(),
+        pause: pause::Pause::default(),
+        profiles: profile::Profiles::new(&config),
+        gatt: gatt::GattTable::default(),
+        config,
+        events,
+    };
+
+    if let Some(listener) = grpc_listener {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::run(ctx, listener).await {
+                log::error!("gRPC server exited with error: {}", e);
+            }
+        });
+    }
+
+    if let Some(path) = ctx.config.hrm_socket.clone() {
+        tokio::spawn(heart_rate::run(ctx.clone(), path));
+    }
 
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             log::info!("Received shutdown signal");
         }
-        result = treadmill::run(state.clone(), &socket_path) => {
+        result = treadmill::run(ctx.clone(), command_queue) => {
             if let Err(e) = result {
                 log::error!("Treadmill task exited with error: {}", e);
             }
         }
-        result = ftms_service::run(state.clone(), socket_path.clone()) => {
+        result = ftms_service::run(ctx.clone()) => {
             if let Err(e) = result {
                 log::error!("FTMS service task exited with error: {}", e);
             }
         }
-        result = debug_server::run(state.clone(), socket_path.clone(), debug_port) => {
+        result = debug_server::run(ctx, debug_listener, tls) => {
             if let Err(e) = result {
                 log::error!("Debug server exited with error: {}", e);
             }
@@ -44,10 +180,30 @@ async fn main() {
     log::info!("FTMS daemon shutting down");
 }
 
-fn parse_args() -> (String, u16) {
+/// Port of a `host:port` listen address.
+fn grpc_port(addr: &str) -> Option<u16> {
+    addr.rsplit_once(':')?.1.parse().ok()
+}
+
+struct Args {
+    socket_path: String,
+    config_path: String,
+    debug_port: u16,
+    /// Account to switch to once the debug port is bound
+    user: Option<String>,
+    group: Option<String>,
+    /// Probe the running daemon and exit (`--check`)
+    check: bool,
+}
+
+fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
     let mut socket_path = DEFAULT_SOCKET.to_string();
+    let mut config_path = DEFAULT_CONFIG.to_string();
     let mut debug_port = DEFAULT_DEBUG_PORT;
+    let mut user = None;
+    let mut group = None;
+    let mut check = false;
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
@@ -57,15 +213,36 @@ fn parse_args() -> (String, u16) {
                     i += 1;
                 }
             }
+            "--config" => {
+                if let Some(path) = args.get(i + 1) {
+                    config_path = path.clone();
+                    i += 1;
+                }
+            }
             "--debug-port" => {
                 if let Some(port) = args.get(i + 1) {
                     debug_port = port.parse().unwrap_or(DEFAULT_DEBUG_PORT);
                     i += 1;
                 }
             }
+            "--user" => {
+                if let Some(name) = args.get(i + 1) {
+                    user = Some(name.clone());
+                    i += 1;
+                }
+            }
+            "--group" => {
+                if let Some(name) = args.get(i + 1) {
+                    group = Some(name.clone());
+                    i += 1;
+                }
+            }
+            "--check" => check = true,
+            // Simulated treadmill instead of treadmill_io (development without hardware)
+            "--mock-treadmill" => socket_path = mock::ADDR.to_string(),
             _ => {}
         }
         i += 1;
     }
-    (socket_path, debug_port)
+    Args { socket_path, config_path, debug_port, user, group, check }
 }
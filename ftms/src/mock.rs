@@ -0,0 +1,125 @@
+//! Simulated treadmill for running without hardware (`--mock-treadmill`).
+//!
+//! Instead of connecting to the treadmill_io socket the treadmill task gets
+//! one end of an in-process pipe, and the simulated treadmill_io in
+//! `machine.rs` (the one `treadmill-sim` serves on a real socket) speaks
+//! the line protocol on the other. The belt and incline motor move at the
+//! default `Physics` rates with a status line on every change, so ramps,
+//! target verification, distance and elapsed time, workouts and exports
+//! all run as on the real machine. Everything above the socket — the BLE
+//! server, debug port and gRPC — is the real daemon.
+//!
+//! Each connection starts a stopped treadmill, like treadmill_io's
+//! watchdog dropping out of emulate when its last client goes.
+
+use std::time::Instant;
+
+use log::{debug, info};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::machine::{Machine, Physics, TICK};
+
+/// `--socket` value that selects the simulator.
+pub const ADDR: &str = "mock:";
+
+/// Start a simulated treadmill_io and return the daemon's end of the pipe.
+pub fn connect() -> DuplexStream {
+    let (daemon, machine) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(serve(machine));
+    info!("Connected to the simulated treadmill");
+    daemon
+}
+
+/// Run the simulated treadmill_io on its end of the pipe until the daemon
+/// closes it or sends `quit`.
+async fn serve(stream: DuplexStream) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut last = Instant::now();
+    let mut machine = Machine::new(Physics::default(), last);
+    let mut ticks = interval(TICK);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        let out = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => match machine.command(&line, Instant::now()) {
+                    // One client, so its answer and the broadcast lines go together
+                    Some(reply) => reply.sender.into_iter().chain(reply.lines).collect(),
+                    None => break,
+                },
+                _ => break,
+            },
+            _ = ticks.tick() => {
+                let now = Instant::now();
+                let out = machine.tick(now, now - last);
+                last = now;
+                out
+            }
+        };
+        for line in out {
+            if writer.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!("Simulated treadmill connection closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::PROTOCOL_VERSION;
+    use crate::status::{self, parse_line, AckMsg, ErrorMsg, Message};
+
+    fn reading(line: &str) -> (u16, i16, bool, Option<i16>) {
+        match parse_line(line).unwrap() {
+            Message::Status(msg) => {
+                let r = msg.reading();
+                (r.speed_tenths_mph, r.incline_half_pct, r.emulating, msg.motor_incline())
+            }
+            other => panic!("expected status, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_speaks_the_daemons_protocol() {
+        assert_eq!(PROTOCOL_VERSION, status::PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let (reader, mut writer) = tokio::io::split(connect());
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"{\"cmd\":\"caps\"}\n").await.unwrap();
+        match parse_line(&lines.next_line().await.unwrap().unwrap()).unwrap() {
+            Message::Caps(caps) => assert_eq!((caps.max_speed, caps.max_incline), (120, 198)),
+            other => panic!("expected caps, got {:?}", other),
+        }
+
+        // The commanded incline shows at once; the motor gets there a
+        // status line at a time
+        writer.write_all(b"{\"cmd\":\"incline\",\"value\":1.0}\n").await.unwrap();
+        let mut seen = Vec::new();
+        while seen.last().map(|r: &(u16, i16, bool, Option<i16>)| r.3) != Some(Some(2)) {
+            seen.push(reading(&lines.next_line().await.unwrap().unwrap()));
+        }
+        assert_eq!(seen, [(0, 2, true, Some(0)), (0, 2, true, Some(1)), (0, 2, true, Some(2))]);
+
+        // A command with an id is acked ahead of its status, or refused
+        writer.write_all(b"{\"cmd\":\"speed\",\"value\":2.0,\"id\":1}\n").await.unwrap();
+        assert_eq!(parse_line(&lines.next_line().await.unwrap().unwrap()).unwrap(), Message::Ack(AckMsg { id: 1 }));
+        writer.write_all(b"{\"cmd\":\"speed\",\"value\":20,\"id\":2}\n").await.unwrap();
+        let refusal = loop {
+            match parse_line(&lines.next_line().await.unwrap().unwrap()).unwrap() {
+                Message::Error(err) => break err,
+                Message::Status(_) => continue,
+                other => panic!("expected error, got {:?}", other),
+            }
+        };
+        assert_eq!(refusal, ErrorMsg { msg: "speed out of range".into(), id: Some(2) });
+
+        writer.write_all(b"{\"cmd\":\"quit\"}\n").await.unwrap();
+        assert!(lines.next_line().await.unwrap().is_none(), "quit closes the connection");
+    }
+}
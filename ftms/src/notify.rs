@@ -0,0 +1,305 @@
+//! Push notifications when a workout ends.
+//!
+//! Each entry of `notify` gets a one-line summary (local start and end
+//! times, distance, duration, climb, average HR, TRIMP and 7-day training
+//! load) of every finished workout long enough to export. Requests go out
+//! through the `curl` binary, since the providers are HTTPS-only.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::config::{FtmsConfig, Units};
+use crate::clock;
+use crate::export::{utc_parts, MIN_EXPORT_SECS};
+use crate::history::{self, Best, TrainingLoad};
+use crate::workout::Workout;
+
+const REQUEST_TIMEOUT_SECS: &str = "30";
+
+/// A notification provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifyTarget {
+    /// Pushover application `token` and recipient `user` key.
+    Pushover {
+        token: String,
+        user: String,
+        #[serde(default = "default_pushover_url")]
+        url: String,
+    },
+    /// Telegram bot `token` posting to `chat_id`.
+    Telegram {
+        token: String,
+        chat_id: String,
+        #[serde(default = "default_telegram_url")]
+        url: String,
+    },
+    /// ntfy topic URL (e.g. `https://ntfy.sh/my-treadmill`), with an
+    /// optional access `token`.
+    Ntfy {
+        url: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+}
+
+fn default_pushover_url() -> String {
+    "https://api.pushover.net/1/messages.json".to_string()
+}
+
+fn default_telegram_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// An HTTP POST ready to hand to curl.
+#[derive(Debug, PartialEq)]
+struct Request {
+    url: String,
+    headers: Vec<String>,
+    body: String,
+}
+
+impl Request {
+    /// The request as a curl config. The body goes as `data-raw`: with
+    /// `data-binary` curl would read a body starting with `@` (a workout
+    /// label can) as a file to upload.
+    fn curl_config(&self) -> String {
+        let mut config = format!("url = \"{}\"\n", curl_quote(&self.url));
+        for header in &self.headers {
+            config.push_str(&format!("header = \"{}\"\n", curl_quote(header)));
+        }
+        config.push_str(&format!("data-raw = \"{}\"\n", curl_quote(&self.body)));
+        config
+    }
+}
+
+impl NotifyTarget {
+    /// Provider name and URL, without credentials.
+    pub fn describe(&self) -> String {
+        match self {
+            NotifyTarget::Pushover { url, .. } => format!("pushover {}", url),
+            NotifyTarget::Telegram { url, .. } => format!("telegram {}", url),
+            NotifyTarget::Ntfy { url, .. } => format!("ntfy {}", url),
+        }
+    }
+
+    /// This target with tokens masked, for logging the config.
+    pub fn redacted(&self) -> Self {
+        let mut t = self.clone();
+        match &mut t {
+            NotifyTarget::Pushover { token, .. } | NotifyTarget::Telegram { token, .. } => {
+                *token = "<redacted>".to_string()
+            }
+            NotifyTarget::Ntfy { token: Some(token), .. } => *token = "<redacted>".to_string(),
+            NotifyTarget::Ntfy { .. } => {}
+        }
+        t
+    }
+
+    fn request(&self, title: &str, message: &str) -> Request {
+        const FORM: &str = "Content-Type: application/x-www-form-urlencoded";
+        match self {
+            NotifyTarget::Pushover { token, user, url } => Request {
+                url: url.clone(),
+                headers: vec![FORM.to_string()],
+                body: form(&[("token", token), ("user", user), ("title", title), ("message", message)]),
+            },
+            NotifyTarget::Telegram { token, chat_id, url } => Request {
+                url: format!("{}/bot{}/sendMessage", url.trim_end_matches('/'), token),
+                headers: vec![FORM.to_string()],
+                body: form(&[("chat_id", chat_id), ("text", &format!("{}\n{}", title, message))]),
+            },
+            NotifyTarget::Ntfy { url, token } => {
+                let mut headers = vec![format!("Title: {}", title)];
+                if let Some(token) = token {
+                    headers.push(format!("Authorization: Bearer {}", token));
+                }
+                Request { url: url.clone(), headers, body: message.to_string() }
+            }
+        }
+    }
+
+    /// POST through curl. Everything goes in a curl config on stdin so
+    /// tokens (Telegram's is part of the URL) stay out of the process list.
+    async fn send(&self, title: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.request(title, message).curl_config();
+        let mut cmd = Command::new("curl");
+        cmd.args(["-sS", "--fail", "--max-time", REQUEST_TIMEOUT_SECS, "--config", "-"]);
+        crate::archive::run(&mut cmd, Some(config)).await
+    }
+}
+
+/// `application/x-www-form-urlencoded` body.
+fn form(fields: &[(&str, &str)]) -> String {
+    fields
+        .iter()
+        .map(|(k, v)| format!("{}={}", url_encode(k), url_encode(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Escape a value for a double-quoted curl config string.
+fn curl_quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// `h:mm:ss`, or `m:ss` under an hour.
+pub fn format_duration(secs: u32) -> String {
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        h => format!("{}:{:02}:{:02}", h, secs % 3600 / 60, secs % 60),
+    }
+}
+
+/// Local `HH:MM` of `wall_ms`.
+fn clock_time(wall_ms: u64, utc_offset_secs: i32) -> String {
+    let (_, _, _, hh, mm, _) = utc_parts(clock::local_ms(wall_ms, utc_offset_secs));
+    format!("{:02}:{:02}", hh, mm)
+}
+
+/// One-line summary with local start and end times, e.g.
+/// `07:13–07:24: 1.05 mi in 11:00, 111 ft climb, avg HR 149`, after the
+/// label if any. A flat run leaves the climb out.
+pub fn summary(workout: &Workout, units: Units) -> String {
+    let mut text = workout.label.as_ref().map(|l| format!("{}, ", l)).unwrap_or_default();
+    let _ = write!(
+        text,
+        "{}–{}: {} in {}",
+        clock_time(workout.start_wall_ms, workout.utc_offset_secs),
+        clock_time(workout.end_wall_ms(), workout.utc_offset_secs),
+        units.distance(workout.distance_m().round() as u32),
+        format_duration(workout.elapsed_secs())
+    );
+    let climb = workout.elevation_gain_m();
+    if climb >= 0.5 {
+        text.push_str(&format!(", {} climb", units.climb(climb)));
+    }
+    if let Some(hr) = workout.avg_heart_rate() {
+        text.push_str(&format!(", avg HR {}", hr));
+    }
+    text
+}
+
+/// `, TRIMP 30 (7-day load 240)` for the summary line; the load needs the
+/// workout history.
+fn training_summary(trimp: Option<f64>, load: Option<TrainingLoad>) -> String {
+    match (trimp, load) {
+        (Some(trimp), Some(load)) => format!(", TRIMP {:.0} (7-day load {:.0})", trimp, load.acute),
+        (Some(trimp), None) => format!(", TRIMP {:.0}", trimp),
+        (None, _) => String::new(),
+    }
+}
+
+/// `. New record: fastest mile 7:32, longest run 6.21 mi` for the records
+/// the workout just set, if any.
+fn records_summary(bests: &[Best], units: Units) -> String {
+    if bests.is_empty() {
+        return String::new();
+    }
+    let described: Vec<String> = bests.iter().map(|b| b.describe(units)).collect();
+    format!(". New record: {}", described.join(", "))
+}
+
+/// Send the summary of a finished `workout` to every configured target.
+/// Expects the workout to be in the history already.
+pub async fn workout_finished(workout: &Workout, config: Arc<FtmsConfig>) {
+    if config.notify.is_empty() || workout.elapsed_secs() < MIN_EXPORT_SECS {
+        return;
+    }
+    let mut message = summary(workout, config.units);
+    let trimp = config.max_heart_rate.and_then(|max| workout.trimp(max));
+    let history = match config.history_file.as_deref() {
+        Some(path) => history::load(path).await,
+        None => Vec::new(),
+    };
+    let load = (trimp.is_some() && config.history_file.is_some()).then(|| TrainingLoad::at(&history, workout.end_wall_ms()));
+    message.push_str(&training_summary(trimp, load));
+    message.push_str(&records_summary(&history::new_records(&history), config.units));
+    for target in &config.notify {
+        match target.send("Treadmill workout complete", &message).await {
+            Ok(()) => info!("Sent workout notification via {}", target.describe()),
+            Err(e) => warn!("Workout notification via {} failed: {}", target.describe(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::tests::sample_workout;
+
+    #[test]
+    fn test_summary() {
+        let w = sample_workout();
+        assert_eq!(summary(&w, Units::Imperial), "17:13–17:24: 1.05 mi in 11:00, 111 ft climb, avg HR 149");
+        assert_eq!(summary(&w, Units::Metric), "17:13–17:24: 1.69 km in 11:00, 34 m climb, avg HR 149");
+        assert_eq!(format_duration(3725), "1:02:05");
+        let mut no_hr = Workout::new(0, 0);
+        no_hr.samples = w.samples[600..].to_vec();
+        assert!(!summary(&no_hr, Units::Imperial).contains("HR"));
+        let labelled = Workout { label: Some("Tempo run".to_string()), ..w.clone() };
+        assert_eq!(summary(&labelled, Units::Imperial), "Tempo run, 17:13–17:24: 1.05 mi in 11:00, 111 ft climb, avg HR 149");
+        let mut flat = w.clone();
+        flat.samples.iter_mut().for_each(|s| s.incline_half_pct = 0);
+        assert!(!summary(&flat, Units::Imperial).contains("climb"));
+
+        let load = TrainingLoad { acute: 240.4, chronic: 900.0 };
+        assert_eq!(training_summary(Some(30.05), Some(load)), ", TRIMP 30 (7-day load 240)");
+        assert_eq!(training_summary(Some(30.05), None), ", TRIMP 30");
+        assert_eq!(training_summary(None, Some(load)), "");
+
+        let best = |effort, value| Best { effort, value, start_wall_ms: 0, utc_offset_secs: 0, label: None };
+        let bests = [best(history::Effort::FastestMile, 452.0), best(history::Effort::LongestRun, 10_000.0)];
+        assert_eq!(records_summary(&bests, Units::Imperial), ". New record: fastest mile 7:32, longest run 6.21 mi");
+        assert_eq!(records_summary(&[], Units::Imperial), "");
+    }
+
+    #[test]
+    fn test_provider_requests() {
+        let targets: Vec<NotifyTarget> = serde_json::from_str(
+            r#"[{"type": "pushover", "token": "app", "user": "me"},
+                {"type": "telegram", "token": "123:abc", "chat_id": "42"},
+                {"type": "ntfy", "url": "https://ntfy.sh/run", "token": "tk"}]"#,
+        )
+        .unwrap();
+
+        let req = targets[0].request("Done", "1.05 mi & more");
+        assert_eq!(req.url, "https://api.pushover.net/1/messages.json");
+        assert_eq!(req.body, "token=app&user=me&title=Done&message=1.05+mi+%26+more");
+
+        let req = targets[1].request("Done", "1.05 mi");
+        assert_eq!(req.url, "https://api.telegram.org/bot123:abc/sendMessage");
+        assert_eq!(req.body, "chat_id=42&text=Done%0A1.05+mi");
+
+        let req = targets[2].request("Done", "1.05 mi");
+        assert_eq!(req.headers, vec!["Title: Done", "Authorization: Bearer tk"]);
+        assert_eq!(req.body, "1.05 mi");
+
+        let shown = format!("{:?}", targets.iter().map(NotifyTarget::redacted).collect::<Vec<_>>());
+        assert!(!shown.contains("123:abc") && !shown.contains("\"tk\"") && !shown.contains("\"app\""), "{}", shown);
+        assert_eq!(curl_quote("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_label_is_never_a_file() {
+        let target = NotifyTarget::Ntfy { url: "https://ntfy.sh/run".to_string(), token: None };
+        let w = Workout { label: Some("@/etc/passwd".to_string()), ..sample_workout() };
+        let config = target.request("Done", &summary(&w, Units::Imperial)).curl_config();
+        assert!(config.contains("\ndata-raw = \"@/etc/passwd, 17:13"), "{}", config);
+        assert!(!config.contains("data-binary"), "{}", config);
+    }
+}
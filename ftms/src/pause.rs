@@ -0,0 +1,209 @@
+//! Pausing a workout from the debug port or a control point Pause.
+//!
+//! `pause` stops the belt but keeps the session: elapsed time is frozen,
+//! distance kept, and the workout isn't ended for standing idle however
+//! long the pause lasts. `resume` ramps the belt back to the speed it was
+//! paused at over `RESUME_SECS`. Given a delay, a pause resumes by itself,
+//! counting down its last `COUNTDOWN_SECS` seconds (3, 2, 1). Each step is
+//! a `TreadmillEvent`, so BLE apps see it in Machine Status and Training
+//! Status and `sub` clients in the event stream. A control point Pause
+//! pauses the same way (without the countdown) and Start resumes; a Stop
+//! or speed target ends the pause where it is: an app took over.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::ftms_service::ControlContext;
+use crate::ramp::{self, RampKind};
+use crate::treadmill::{self, Target, TreadmillEvent};
+
+/// Seconds counted down before a pause resumes by itself.
+pub const COUNTDOWN_SECS: u64 = 3;
+
+/// Seconds the belt takes to get back to speed after a pause.
+pub const RESUME_SECS: u64 = 3;
+
+/// Cheap, cloneable handle to the pending automatic resume, if any.
+#[derive(Clone, Default)]
+pub struct Pause {
+    countdown: Arc<Mutex<Option<tokio::task::AbortHandle>>>,
+}
+
+impl Pause {
+    /// Call off the automatic resume, if one is pending.
+    fn cancel_countdown(&self) {
+        if let Some(handle) = self.countdown.lock().unwrap().take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Stop the belt, remembering its speed, and with `resume_after` resume by
+/// itself once that has passed. Returns the speed (tenths of mph) it will
+/// come back to.
+pub async fn pause(ctx: &ControlContext, resume_after: Option<Duration>) -> Result<u16, String> {
+    let (speed, previous) = {
+        let mut s = ctx.state.lock().await;
+        if s.app_paused.is_some() {
+            return Err("already paused".into());
+        }
+        if !s.connected {
+            return Err("treadmill_io not connected".into());
+        }
+        if s.speed_tenths_mph == 0 {
+            return Err("belt isn't moving".into());
+        }
+        // Where the belt was headed, if a ramp or verifier hadn't got it there yet
+        let speed = s.last_speed_target.filter(|&t| t > 0).unwrap_or(s.speed_tenths_mph);
+        let previous = s.last_speed_target;
+        ctx.ramp.cancel();
+        s.begin_command(Target::Speed(0));
+        s.app_paused = Some(speed);
+        (speed, previous)
+    };
+    if let Err(e) = treadmill::send_speed(&ctx.commands, 0.0).await {
+        let mut s = ctx.state.lock().await;
+        s.app_paused = None;
+        s.last_speed_target = previous;
+        return Err(format!("stopping the belt failed: {}", e));
+    }
+    info!("Paused at {}", ctx.config.units.speed_tenths(speed));
+    let _ = ctx.events.send(TreadmillEvent::Paused);
+
+    if let Some(after) = resume_after {
+        ctx.pause.cancel_countdown();
+        let task = tokio::spawn(countdown(ctx.clone(), after));
+        *ctx.pause.countdown.lock().unwrap() = Some(task.abort_handle());
+    }
+    Ok(speed)
+}
+
+/// Ramp the belt back to the speed it was paused at, returning that speed.
+pub async fn resume(ctx: &ControlContext) -> Result<u16, String> {
+    ctx.pause.cancel_countdown();
+    let speed = {
+        let mut s = ctx.state.lock().await;
+        let speed = s.app_paused.take().ok_or("not paused")?;
+        s.begin_command(Target::Speed(speed));
+        speed
+    };
+    info!("Resuming at {}", ctx.config.units.speed_tenths(speed));
+    let _ = ctx.events.send(TreadmillEvent::Resumed);
+    ctx.ramp.start(RampKind::Resume, ctx.commands.clone(), ramp::steps(0, speed, RESUME_SECS), async {});
+    Ok(speed)
+}
+
+/// End a pause without touching the belt, because a control point command
+/// is about to set it.
+pub async fn lift(ctx: &ControlContext) {
+    ctx.pause.cancel_countdown();
+    if ctx.state.lock().await.app_paused.take().is_some() {
+        info!("Pause ended by a control point command");
+    }
+}
+
+/// Wait out `after`, announcing the last `COUNTDOWN_SECS` of it, and resume.
+async fn countdown(ctx: ControlContext, after: Duration) {
+    let lead = Duration::from_secs(COUNTDOWN_SECS).min(after);
+    tokio::time::sleep(after - lead).await;
+    for left in (1..=lead.as_secs()).rev() {
+        let _ = ctx.events.send(TreadmillEvent::ResumeCountdown(left as u8));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    // Resuming cancels the countdown, which mustn't be this task any more
+    ctx.pause.countdown.lock().unwrap().take();
+    if let Err(e) = resume(&ctx).await {
+        warn!("Automatic resume failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::{broadcast, mpsc};
+
+    use crate::config::FtmsConfig;
+    use crate::ftms_service::test_context;
+    use crate::treadmill::{CommandQueue, Commands, TreadmillState};
+
+    /// A treadmill_io link that takes every command and passes it on.
+    fn fake_treadmill_io(mut queue: CommandQueue) -> mpsc::UnboundedReceiver<String> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(command) = queue.recv().await {
+                let _ = tx.send(command.line.trim().to_string());
+                command.complete(Ok(()));
+            }
+        });
+        rx
+    }
+
+    fn context(state: TreadmillState) -> (ControlContext, mpsc::UnboundedReceiver<String>, broadcast::Receiver<TreadmillEvent>) {
+        let (events, rx) = broadcast::channel(8);
+        let (commands, queue) = Commands::channel();
+        let ctx = ControlContext {
+            state: Arc::new(tokio::sync::Mutex::new(state)),
+            commands,
+            ..test_context(FtmsConfig::default(), events)
+        };
+        (ctx, fake_treadmill_io(queue), rx)
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_auto_resume() {
+        let state = TreadmillState { speed_tenths_mph: 48, connected: true, last_speed_target: Some(50), ..Default::default() };
+        let (ctx, mut sent, mut rx) = context(state);
+
+        // Stops the belt, keeping the target it was headed for
+        assert_eq!(pause(&ctx, None).await, Ok(50));
+        assert_eq!(sent.recv().await.unwrap(), r#"{"cmd":"speed","value":0.0}"#);
+        assert_eq!(rx.recv().await.unwrap(), TreadmillEvent::Paused);
+        assert_eq!(pause(&ctx, None).await, Err("already paused".to_string()));
+        assert_eq!(ctx.state.lock().await.last_speed_target, Some(0));
+
+        // A control point command takes over
+        lift(&ctx).await;
+        assert_eq!(resume(&ctx).await, Err("not paused".to_string()));
+
+        // Resuming by itself ramps back up from the first step
+        ctx.state.lock().await.speed_tenths_mph = 50;
+        assert_eq!(pause(&ctx, Some(Duration::ZERO)).await, Ok(50));
+        assert_eq!(sent.recv().await.unwrap(), r#"{"cmd":"speed","value":0.0}"#);
+        assert_eq!(rx.recv().await.unwrap(), TreadmillEvent::Paused);
+        assert_eq!(rx.recv().await.unwrap(), TreadmillEvent::Resumed);
+        assert_eq!(sent.recv().await.unwrap(), r#"{"cmd":"speed","value":1.7}"#);
+        assert_eq!(ctx.ramp.running(), Some(RampKind::Resume));
+        let s = ctx.state.lock().await;
+        assert_eq!((s.app_paused, s.last_speed_target), (None, Some(50)));
+        drop(s);
+        ctx.ramp.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_control_point_pause_and_resume() {
+        use crate::audit::Origin;
+        use crate::ftms_service::handle_control_command;
+        use crate::protocol::{ControlCommand, RESULT_SUCCESS};
+
+        let state = TreadmillState { speed_tenths_mph: 40, connected: true, ..Default::default() };
+        let (ctx, mut sent, mut rx) = context(state);
+        let origin = Origin::Ble("AA:BB:CC:DD:EE:FF".into());
+
+        // Pause only stops the belt, incline kept, and holds the session
+        assert_eq!(handle_control_command(&ControlCommand::StopOrPause(2), &ctx, &origin).await, (0x08, RESULT_SUCCESS));
+        assert_eq!(sent.recv().await.unwrap(), r#"{"cmd":"speed","value":0.0}"#);
+        assert_eq!(rx.recv().await.unwrap(), TreadmillEvent::Paused);
+        assert_eq!(ctx.state.lock().await.app_paused, Some(40));
+        assert_eq!(handle_control_command(&ControlCommand::StopOrPause(2), &ctx, &origin).await, (0x08, RESULT_SUCCESS));
+
+        // Start resumes at the paused speed rather than starting afresh
+        assert_eq!(handle_control_command(&ControlCommand::StartOrResume, &ctx, &origin).await, (0x07, RESULT_SUCCESS));
+        assert_eq!(rx.recv().await.unwrap(), TreadmillEvent::Resumed);
+        assert_eq!(sent.recv().await.unwrap(), r#"{"cmd":"speed","value":1.3}"#);
+        assert_eq!(ctx.ramp.running(), Some(RampKind::Resume));
+        assert_eq!(ctx.state.lock().await.app_paused, None);
+        ctx.ramp.cancel();
+    }
+}
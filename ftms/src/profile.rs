@@ -0,0 +1,130 @@
+//! Per-profile speed and incline limits (kid/guest mode).
+//!
+//! `profiles` in the config names sets of caps, e.g.
+//! `{"guest": {"max_speed_mph": 6.0, "max_incline_pct": 5.0}}`, and
+//! `profile` picks the one active at startup. While a capped profile is
+//! active, a Set Target Speed or Set Target Inclination above its cap is
+//! refused with Invalid Parameter instead of being clamped, whichever
+//! transport it came from. Switching profiles at runtime goes through the
+//! debug `profile` command and needs `profile_pin`; with no pin
+//! configured only the config file can change the profile. A restart
+//! always comes back up in the configured profile.
+
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::FtmsConfig;
+
+/// Caps for one profile. Unset fields leave only the built-in safety max.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedProfile {
+    pub max_speed_mph: Option<f64>,
+    pub max_incline_pct: Option<f64>,
+}
+
+impl SpeedProfile {
+    /// Whether a speed of `mph_tenths` is within the cap.
+    pub fn allows_speed(&self, mph_tenths: u16) -> bool {
+        self.max_speed_mph.is_none_or(|max| mph_tenths as f64 <= (max * 10.0).round())
+    }
+
+    /// Whether an incline of `incline_tenths` (tenths of a percent) is
+    /// within the cap.
+    pub fn allows_incline(&self, incline_tenths: i16) -> bool {
+        self.max_incline_pct.is_none_or(|max| incline_tenths as f64 <= (max * 10.0).round())
+    }
+
+    /// e.g. "max 6.0 mph, 5.0%".
+    pub fn describe(&self, config: &FtmsConfig) -> String {
+        let speed = self
+            .max_speed_mph
+            .map(|mph| config.units.speed((mph * 100.0).round() as u32))
+            .unwrap_or_else(|| "no speed cap".to_string());
+        let incline = self
+            .max_incline_pct
+            .map(|pct| format!("{:.1}%", pct))
+            .unwrap_or_else(|| "no incline cap".to_string());
+        format!("max {}, {}", speed, incline)
+    }
+}
+
+/// Cheap, cloneable handle to the active profile's name.
+#[derive(Clone, Default)]
+pub struct Profiles {
+    active: Arc<Mutex<Option<String>>>,
+}
+
+impl Profiles {
+    /// Start in the configured `profile`. An unknown name is a config
+    /// mistake; it is logged and leaves no profile active.
+    pub fn new(config: &FtmsConfig) -> Self {
+        let active = config.profile.clone().filter(|name| {
+            let known = config.profiles.contains_key(name);
+            if !known {
+                warn!("Profile {:?} is not in profiles; no speed limits apply", name);
+            }
+            known
+        });
+        Self { active: Arc::new(Mutex::new(active)) }
+    }
+
+    /// The active profile's name and caps.
+    pub fn active(&self, config: &FtmsConfig) -> Option<(String, SpeedProfile)> {
+        let name = self.active.lock().unwrap().clone()?;
+        let caps = *config.profiles.get(&name)?;
+        Some((name, caps))
+    }
+
+    /// Switch to profile `name`, if `pin` matches `profile_pin`.
+    pub fn switch(&self, config: &FtmsConfig, name: &str, pin: &str) -> Result<(), String> {
+        match config.profile_pin.as_deref() {
+            None => return Err("profile switching is disabled (no profile_pin configured)".to_string()),
+            Some(expected) if expected != pin => {
+                warn!("Profile switch to {:?} refused: wrong PIN", name);
+                return Err("wrong PIN".to_string());
+            }
+            Some(_) => {}
+        }
+        if !config.profiles.contains_key(name) {
+            return Err(format!("no profile {:?}", name));
+        }
+        info!("Switched to profile {:?}", name);
+        *self.active.lock().unwrap() = Some(name.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caps_and_switching() {
+        let mut config: FtmsConfig = serde_json::from_str(
+            r#"{"profiles": {"guest": {"max_speed_mph": 6.0, "max_incline_pct": 5.0}, "adult": {}},
+                "profile": "guest"}"#,
+        )
+        .unwrap();
+        let profiles = Profiles::new(&config);
+        let (name, guest) = profiles.active(&config).unwrap();
+        assert_eq!(name, "guest");
+        assert!(guest.allows_speed(60) && !guest.allows_speed(61));
+        assert!(guest.allows_incline(50) && !guest.allows_incline(55));
+        assert_eq!(guest.describe(&config), "max 6.0 mph, 5.0%");
+
+        assert!(profiles.switch(&config, "adult", "1234").unwrap_err().contains("disabled"));
+        config.profile_pin = Some("1234".to_string());
+        assert_eq!(profiles.switch(&config, "adult", "0000"), Err("wrong PIN".to_string()));
+        assert!(profiles.switch(&config, "nobody", "1234").is_err());
+        assert_eq!(profiles.active(&config).unwrap().0, "guest", "refused switches change nothing");
+        profiles.switch(&config, "adult", "1234").unwrap();
+        let (_, adult) = profiles.active(&config).unwrap();
+        assert!(adult.allows_speed(120) && adult.allows_incline(150));
+
+        config.profile = Some("typo".to_string());
+        assert!(Profiles::new(&config).active(&config).is_none());
+    }
+}
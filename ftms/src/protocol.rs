@@ -29,6 +29,23 @@ pub enum ControlCommand {
     SetTargetInclination(i16), // percent * 10
     StartOrResume,
     StopOrPause(u8),           // 1=stop, 2=pause
+    SetTargetedDistance(u32),  // meters (uint24)
+    SetTargetedTrainingTime(u16), // seconds
+}
+
+impl ControlCommand {
+    /// The Control Point opcode this command is written with.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            ControlCommand::RequestControl => 0x00,
+            ControlCommand::SetTargetSpeed(_) => 0x02,
+            ControlCommand::SetTargetInclination(_) => 0x03,
+            ControlCommand::StartOrResume => 0x07,
+            ControlCommand::StopOrPause(_) => 0x08,
+            ControlCommand::SetTargetedDistance(_) => 0x0C,
+            ControlCommand::SetTargetedTrainingTime(_) => 0x0D,
+        }
+    }
 }
 
 // Control Point result codes (FTMS spec Table 4.24)
@@ -36,25 +53,43 @@ pub const RESULT_SUCCESS: u8 = 0x01;
 pub const RESULT_NOT_SUPPORTED: u8 = 0x02;
 pub const RESULT_INVALID_PARAM: u8 = 0x03;
 pub const RESULT_FAILED: u8 = 0x04;
+pub const RESULT_CONTROL_NOT_PERMITTED: u8 = 0x05;
 pub const RESPONSE_CODE: u8 = 0x80;
 
+// Training Status values (FTMS spec Table 4.25)
+pub const TRAINING_IDLE: u8 = 0x01;
+pub const TRAINING_MANUAL: u8 = 0x0D;
+pub const TRAINING_PRE_WORKOUT: u8 = 0x0E;
+pub const TRAINING_POST_WORKOUT: u8 = 0x0F;
+
 /// Encode FTMS Treadmill Data characteristic (0x2ACD).
 ///
 /// Flags 0x040C = bits 2,3,10 set:
 ///   - Bit 0 = 0: Instantaneous Speed present
 ///   - Bit 2 = 1: Total Distance present
 ///   - Bit 3 = 1: Inclination and Ramp Angle present
+///   - Bit 8 = 1 (0x050C) only with `heart_rate`: Heart Rate present
 ///   - Bit 10 = 1: Elapsed Time present
+///   - Bit 11 = 1 (0x0C0C) only with `remaining_secs`: Remaining Time present
 ///
-/// Layout: flags(2) + speed(2) + distance(3) + inclination(2) + ramp_angle(2) + elapsed(2) = 13 bytes
+/// Layout: flags(2) + speed(2) + distance(3) + inclination(2) + ramp_angle(2)
+/// [+ heart_rate(1)] + elapsed(2) [+ remaining(2)] = 13 bytes, up to 16
 pub fn encode_treadmill_data(
     speed_kmh_hundredths: u16,
     incline_tenths: i16,
     distance_meters: u32,
+    heart_rate: Option<u8>,
     elapsed_secs: u16,
+    remaining_secs: Option<u16>,
 ) -> Vec<u8> {
-    let flags: u16 = 0x040C;
-    let mut buf = Vec::with_capacity(13);
+    let mut flags: u16 = 0x040C;
+    if heart_rate.is_some() {
+        flags |= 0x0100;
+    }
+    if remaining_secs.is_some() {
+        flags |= 0x0800;
+    }
+    let mut buf = Vec::with_capacity(16);
 
     // Flags (uint16 LE)
     buf.extend_from_slice(&flags.to_le_bytes());
@@ -74,45 +109,91 @@ pub fn encode_treadmill_data(
     // Ramp Angle Setting (sint16 LE, degree with 0.1 resolution) — always 0
     buf.extend_from_slice(&0i16.to_le_bytes());
 
+    // Heart Rate (uint8, beats per minute)
+    if let Some(bpm) = heart_rate {
+        buf.push(bpm);
+    }
+
     // Elapsed Time (uint16 LE, seconds)
     buf.extend_from_slice(&elapsed_secs.to_le_bytes());
 
+    // Remaining Time (uint16 LE, seconds)
+    if let Some(secs) = remaining_secs {
+        buf.extend_from_slice(&secs.to_le_bytes());
+    }
+
     buf
 }
 
-/// Encode FTMS Feature characteristic (0x2ACC).
-///
-/// Fitness Machine Features (uint32 LE):
-///   - Bit 2: Total Distance Supported
-///   - Bit 3: Inclination Supported
-///   - Bit 12: Elapsed Time Supported
-///   = 0x0000_100C
-///
-/// Target Setting Features (uint32 LE):
-///   - Bit 0: Speed Target Supported
-///   - Bit 1: Inclination Target Supported
-///   = 0x0000_0003
-pub fn encode_feature() -> [u8; 8] {
-    let machine_features: u32 = 0x0000_100C;
-    let target_features: u32 = 0x0000_0003;
+// Fitness Machine Features bits (FTMS spec Table 4.3)
+pub const FEATURE_TOTAL_DISTANCE: u32 = 1 << 2;
+pub const FEATURE_INCLINATION: u32 = 1 << 3;
+pub const FEATURE_HEART_RATE: u32 = 1 << 10;
+pub const FEATURE_ELAPSED_TIME: u32 = 1 << 12;
+pub const FEATURE_REMAINING_TIME: u32 = 1 << 13;
+
+// Target Setting Features bits (FTMS spec Table 4.4)
+pub const TARGET_SPEED: u32 = 1 << 0;
+pub const TARGET_INCLINATION: u32 = 1 << 1;
+pub const TARGET_DISTANCE: u32 = 1 << 8;
+pub const TARGET_TRAINING_TIME: u32 = 1 << 9;
+
+/// Optional capabilities the daemon can actually deliver. Each one turns on
+/// the matching Feature bit, so apps never see a feature that isn't backed
+/// by data or control. Decline has no feature bit; it would show as a
+/// negative Inclination Range minimum (the 9.31 has none).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    /// Heart Rate field in Treadmill Data (HR strap bridged in).
+    pub heart_rate: bool,
+}
+
+impl Capabilities {
+    /// Fitness Machine Features word. Distance, inclination, and elapsed
+    /// time are always in Treadmill Data, and remaining time is whenever a
+    /// training time is targeted.
+    pub fn machine_features(&self) -> u32 {
+        let mut bits = FEATURE_TOTAL_DISTANCE | FEATURE_INCLINATION | FEATURE_ELAPSED_TIME | FEATURE_REMAINING_TIME;
+        if self.heart_rate {
+            bits |= FEATURE_HEART_RATE;
+        }
+        bits
+    }
+
+    /// Target Setting Features word. Speed, incline, distance and training
+    /// time targets are always supported.
+    pub fn target_features(&self) -> u32 {
+        TARGET_SPEED | TARGET_INCLINATION | TARGET_DISTANCE | TARGET_TRAINING_TIME
+    }
+}
+
+/// Encode FTMS Feature characteristic (0x2ACC): Fitness Machine Features
+/// then Target Setting Features, both uint32 LE, derived from `caps`.
+/// With nothing optional enabled that's 0x0000_300C (distance, inclination,
+/// elapsed and remaining time) and 0x0000_0303 (speed, inclination,
+/// distance and training time targets).
+pub fn encode_feature(caps: &Capabilities) -> [u8; 8] {
+    let machine_features = caps.machine_features();
+    let target_features = caps.target_features();
     let mut buf = [0u8; 8];
     buf[0..4].copy_from_slice(&machine_features.to_le_bytes());
     buf[4..8].copy_from_slice(&target_features.to_le_bytes());
     buf
 }
 
+/// Supported Speed Range minimum, km/h * 100 (0.80 km/h ~ 0.5 mph).
+pub const SPEED_RANGE_MIN: u16 = 80;
+
 /// Encode Supported Speed Range characteristic (0x2AD4).
 ///
 /// 3x uint16 LE: minimum, maximum, step (all in km/h * 100).
-///   - Min: 80  (0.80 km/h ~ 0.5 mph)
-///   - Max: 1931 (19.31 km/h ~ 12.0 mph)
+///   - Min: `SPEED_RANGE_MIN`
+///   - Max: `max`, normally 1931 (19.31 km/h ~ 12.0 mph)
 ///   - Step: 16 (0.16 km/h ~ 0.1 mph)
-pub fn encode_speed_range() -> [u8; 6] {
-    let min: u16 = 80;
-    let max: u16 = 1931;
+pub fn encode_speed_range(max: u16) -> [u8; 6] {
     let step: u16 = 16;
     let mut buf = [0u8; 6];
-    buf[0..2].copy_from_slice(&min.to_le_bytes());
+    buf[0..2].copy_from_slice(&SPEED_RANGE_MIN.to_le_bytes());
     buf[2..4].copy_from_slice(&max.to_le_bytes());
     buf[4..6].copy_from_slice(&step.to_le_bytes());
     buf
@@ -121,12 +202,10 @@ pub fn encode_speed_range() -> [u8; 6] {
 /// Encode Supported Inclination Range characteristic (0x2AD5).
 ///
 /// 3x sint16 LE: minimum, maximum, step (all in percent * 10).
-///   - Min: 0   (0.0%)
-///   - Max: 150 (15.0%)
+///   - Min: `min`, 0 (0.0%) unless the treadmill declines
+///   - Max: `max`, normally 150 (15.0%)
 ///   - Step: 5  (0.5%)
-pub fn encode_incline_range() -> [u8; 6] {
-    let min: i16 = 0;
-    let max: i16 = 150;
+pub fn encode_incline_range(min: i16, max: i16) -> [u8; 6] {
     let step: i16 = 5;
     let mut buf = [0u8; 6];
     buf[0..2].copy_from_slice(&min.to_le_bytes());
@@ -166,6 +245,22 @@ pub fn parse_control_point(bytes: &[u8]) -> Option<ControlCommand> {
             }
             Some(ControlCommand::StopOrPause(bytes[1]))
         }
+        0x0C => {
+            // Set Targeted Distance: opcode(1) + uint24 LE meters
+            if bytes.len() < 4 {
+                return None;
+            }
+            let meters = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]);
+            Some(ControlCommand::SetTargetedDistance(meters))
+        }
+        0x0D => {
+            // Set Targeted Training Time: opcode(1) + uint16 LE seconds
+            if bytes.len() < 3 {
+                return None;
+            }
+            let secs = u16::from_le_bytes([bytes[1], bytes[2]]);
+            Some(ControlCommand::SetTargetedTrainingTime(secs))
+        }
         _ => None,
     }
 }
@@ -177,19 +272,58 @@ pub fn encode_control_response(request_opcode: u8, result: u8) -> Vec<u8> {
     vec![RESPONSE_CODE, request_opcode, result]
 }
 
-/// Convert treadmill-native speed (mph * 10) to FTMS speed (km/h * 100).
+/// Human-readable name of a Control Point result code (for logs/debug output).
+pub fn result_name(result: u8) -> &'static str {
+    match result {
+        RESULT_SUCCESS => "Success",
+        RESULT_NOT_SUPPORTED => "Op Code not supported",
+        RESULT_INVALID_PARAM => "Invalid Parameter",
+        RESULT_FAILED => "Operation Failed",
+        RESULT_CONTROL_NOT_PERMITTED => "Control Not Permitted",
+        _ => "Reserved",
+    }
+}
+
+/// Meters in a statute mile (exact by definition).
+pub const METERS_PER_MILE: f64 = 1609.344;
+
+/// Meters in an international foot (exact by definition).
+pub const METERS_PER_FOOT: f64 = 0.3048;
+
+/// 1 mph = 1.609344 km/h, as a fixed-point integer scaled by 10^6.
+const KMH_PER_MPH_MICRO: u64 = 1_609_344;
+
+/// Integer division rounding half up.
+const fn div_round(n: u64, d: u64) -> u64 {
+    (n + d / 2) / d
+}
+
+/// Clamp a converted value into u16 instead of wrapping.
+fn saturate_u16(v: u64) -> u16 {
+    v.min(u16::MAX as u64) as u16
+}
+
+/// Convert treadmill-native speed (mph * 10) to FTMS speed (km/h * 100),
+/// rounded to nearest.
 ///
-/// 1 mph = 1.60934 km/h
-/// mph_tenths * 0.1 mph * 1.60934 * 100 = mph_tenths * 16.0934
+/// 1 mph = 1.609344 km/h
+/// mph_tenths * 0.1 mph * 1.609344 * 100 = mph_tenths * 16.09344
 pub fn mph_tenths_to_kmh_hundredths(mph_tenths: u16) -> u16 {
-    ((mph_tenths as u32) * 1609 / 100) as u16
+    saturate_u16(div_round(mph_tenths as u64 * KMH_PER_MPH_MICRO, 100_000))
+}
+
+/// Convert mph * 100 to FTMS speed (km/h * 100), rounded to nearest. Used
+/// for interpolated speeds that fall between treadmill-native tenths.
+pub fn mph_hundredths_to_kmh_hundredths(mph_hundredths: u32) -> u16 {
+    saturate_u16(div_round(mph_hundredths as u64 * KMH_PER_MPH_MICRO, 1_000_000))
 }
 
-/// Convert FTMS speed (km/h * 100) to treadmill-native speed (mph * 10).
+/// Convert FTMS speed (km/h * 100) to treadmill-native speed (mph * 10),
+/// rounded to nearest.
 ///
-/// kmh_hundredths * 0.01 km/h / 1.60934 * 10 = kmh_hundredths / 16.0934
+/// kmh_hundredths * 0.01 km/h / 1.609344 * 10 = kmh_hundredths / 16.09344
 pub fn kmh_hundredths_to_mph_tenths(kmh_hundredths: u16) -> u16 {
-    ((kmh_hundredths as u32) * 100 / 1609) as u16
+    saturate_u16(div_round(kmh_hundredths as u64 * 100_000, KMH_PER_MPH_MICRO))
 }
 
 #[cfg(test)]
@@ -198,7 +332,7 @@ mod tests {
 
     #[test]
     fn test_encode_treadmill_data_zeros() {
-        let data = encode_treadmill_data(0, 0, 0, 0);
+        let data = encode_treadmill_data(0, 0, 0, None, 0, None);
         assert_eq!(data.len(), 13);
         // Flags: 0x040C LE
         assert_eq!(data[0], 0x0C);
@@ -224,7 +358,7 @@ mod tests {
     #[test]
     fn test_encode_treadmill_data_running() {
         // speed=500 (5.00 km/h), incline=30 (3.0%), distance=1234m, elapsed=300s
-        let data = encode_treadmill_data(500, 30, 1234, 300);
+        let data = encode_treadmill_data(500, 30, 1234, None, 300, None);
         assert_eq!(data.len(), 13);
 
         // Flags
@@ -250,17 +384,26 @@ mod tests {
 
     #[test]
     fn test_encode_feature() {
-        let feat = encode_feature();
+        let feat = encode_feature(&Capabilities::default());
         assert_eq!(feat.len(), 8);
         let machine = u32::from_le_bytes([feat[0], feat[1], feat[2], feat[3]]);
         let target = u32::from_le_bytes([feat[4], feat[5], feat[6], feat[7]]);
-        assert_eq!(machine, 0x0000_100C);
-        assert_eq!(target, 0x0000_0003);
+        assert_eq!(machine, 0x0000_300C);
+        assert_eq!(target, 0x0000_0303);
+    }
+
+    #[test]
+    fn test_encode_feature_follows_capabilities() {
+        let feat = encode_feature(&Capabilities { heart_rate: true });
+        let machine = u32::from_le_bytes([feat[0], feat[1], feat[2], feat[3]]);
+        let target = u32::from_le_bytes([feat[4], feat[5], feat[6], feat[7]]);
+        assert_eq!(machine, 0x0000_300C | FEATURE_HEART_RATE);
+        assert_eq!(target, 0x0000_0303, "HR data alone doesn't imply HR control");
     }
 
     #[test]
     fn test_encode_speed_range() {
-        let range = encode_speed_range();
+        let range = encode_speed_range(1931);
         let min = u16::from_le_bytes([range[0], range[1]]);
         let max = u16::from_le_bytes([range[2], range[3]]);
         let step = u16::from_le_bytes([range[4], range[5]]);
@@ -271,7 +414,7 @@ mod tests {
 
     #[test]
     fn test_encode_incline_range() {
-        let range = encode_incline_range();
+        let range = encode_incline_range(0, 150);
         let min = i16::from_le_bytes([range[0], range[1]]);
         let max = i16::from_le_bytes([range[2], range[3]]);
         let step = i16::from_le_bytes([range[4], range[5]]);
@@ -322,6 +465,13 @@ mod tests {
         assert_eq!(cmd, Some(ControlCommand::StopOrPause(2)));
     }
 
+    #[test]
+    fn test_parse_control_targeted_distance() {
+        // Opcode 0x0C, 5000 m (0x001388 LE = [0x88, 0x13, 0x00])
+        assert_eq!(parse_control_point(&[0x0C, 0x88, 0x13, 0x00]), Some(ControlCommand::SetTargetedDistance(5000)));
+        assert_eq!(parse_control_point(&[0x0C, 0x88, 0x13]), None, "uint24 needs three bytes");
+    }
+
     #[test]
     fn test_parse_control_unknown() {
         let cmd = parse_control_point(&[0xFF]);
@@ -356,15 +506,26 @@ mod tests {
         assert_eq!(resp, vec![0x80, 0x00, 0x02]);
     }
 
+    #[test]
+    fn test_result_name() {
+        assert_eq!(result_name(RESULT_SUCCESS), "Success");
+        assert_eq!(result_name(RESULT_INVALID_PARAM), "Invalid Parameter");
+        assert_eq!(result_name(0x7F), "Reserved");
+    }
+
     #[test]
     fn test_mph_to_kmh_conversion() {
-        // 1.0 mph = 10 tenths → ~161 hundredths km/h (1.609 km/h)
+        // 1.0 mph = 10 tenths → 161 hundredths km/h (160.93 rounded)
         let kmh = mph_tenths_to_kmh_hundredths(10);
-        assert_eq!(kmh, 160); // 1609 * 10 / 100 = 160 (truncated from 160.9)
+        assert_eq!(kmh, 161);
 
-        // 12.0 mph = 120 tenths → ~1931 hundredths km/h (19.31 km/h)
+        // 12.0 mph = 120 tenths → 1931 hundredths km/h (1931.21 rounded),
+        // matching the advertised speed range max
         let kmh = mph_tenths_to_kmh_hundredths(120);
-        assert_eq!(kmh, 1930); // 1609 * 120 / 100 = 1930 (truncated from 1930.8)
+        assert_eq!(kmh, 1931);
+
+        // 0.5 mph = 5 tenths → 80 (80.47 rounded), the range min
+        assert_eq!(mph_tenths_to_kmh_hundredths(5), 80);
 
         // 0 mph → 0
         assert_eq!(mph_tenths_to_kmh_hundredths(0), 0);
@@ -374,7 +535,13 @@ mod tests {
     fn test_kmh_to_mph_conversion() {
         // ~161 hundredths km/h → 10 tenths mph (1.0 mph)
         let mph = kmh_hundredths_to_mph_tenths(161);
-        assert_eq!(mph, 10); // 161 * 100 / 1609 = 10.006 → 10
+        assert_eq!(mph, 10); // 161 / 16.09344 = 10.004 → 10
+
+        // 5.00 km/h → 31 tenths (31.07), 1.50 km/h → 9 tenths (9.32)
+        assert_eq!(kmh_hundredths_to_mph_tenths(500), 31);
+        assert_eq!(kmh_hundredths_to_mph_tenths(150), 9);
+        // Rounds up where truncation would drop a tenth: 15.99 → 10
+        assert_eq!(kmh_hundredths_to_mph_tenths(159), 10);
 
         // 0 → 0
         assert_eq!(kmh_hundredths_to_mph_tenths(0), 0);
@@ -382,15 +549,36 @@ mod tests {
 
     #[test]
     fn test_conversion_roundtrip() {
-        // Convert mph → kmh → mph, should be within ±1 of original
-        for mph_tenths in [0u16, 5, 10, 25, 50, 75, 100, 120] {
+        // mph → kmh → mph is exact across the treadmill's range
+        for mph_tenths in 0u16..=250 {
             let kmh = mph_tenths_to_kmh_hundredths(mph_tenths);
             let back = kmh_hundredths_to_mph_tenths(kmh);
-            let diff = (back as i32 - mph_tenths as i32).unsigned_abs();
-            assert!(
-                diff <= 1,
-                "roundtrip failed for {mph_tenths} tenths mph: got {back} (diff {diff})"
-            );
+            assert_eq!(back, mph_tenths, "roundtrip failed for {mph_tenths} tenths mph via {kmh}");
+        }
+    }
+
+    #[test]
+    fn test_conversion_accuracy_bounds() {
+        // Every result is within half a unit of the exact value
+        for mph_tenths in 0u16..=250 {
+            let exact = mph_tenths as f64 * 16.09344;
+            let kmh = mph_tenths_to_kmh_hundredths(mph_tenths) as f64;
+            assert!((kmh - exact).abs() <= 0.5, "{mph_tenths} tenths: {kmh} vs {exact}");
+        }
+        for mph_hundredths in 0u32..=2500 {
+            let exact = mph_hundredths as f64 * 1.609344;
+            let kmh = mph_hundredths_to_kmh_hundredths(mph_hundredths) as f64;
+            assert!((kmh - exact).abs() <= 0.5, "{mph_hundredths} hundredths: {kmh} vs {exact}");
+        }
+        for kmh_hundredths in 0u16..=4000 {
+            let exact = kmh_hundredths as f64 / 16.09344;
+            let mph = kmh_hundredths_to_mph_tenths(kmh_hundredths) as f64;
+            assert!((mph - exact).abs() <= 0.5, "{kmh_hundredths} km/h*100: {mph} vs {exact}");
+        }
+        // kmh → mph → kmh lands within half a mph step (0.08 km/h)
+        for kmh_hundredths in 0u16..=4000 {
+            let back = mph_tenths_to_kmh_hundredths(kmh_hundredths_to_mph_tenths(kmh_hundredths));
+            assert!((back as i32 - kmh_hundredths as i32).abs() <= 9, "{kmh_hundredths} → {back}");
         }
     }
 
@@ -404,6 +592,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_opcode_matches_parse() {
+        for byte in 0u8..=255 {
+            if let Some(cmd) = parse_control_point(&[byte, 0, 0, 0]) {
+                assert_eq!(cmd.opcode(), byte, "{:?}", cmd);
+            }
+        }
+    }
+
     #[test]
     fn test_parse_all_opcodes_with_garbage_trailing() {
         // Valid opcodes followed by excessive trailing bytes — should still parse
@@ -469,7 +666,7 @@ mod tests {
 
     #[test]
     fn test_encode_treadmill_data_max_values() {
-        let data = encode_treadmill_data(u16::MAX, i16::MAX, u32::MAX, u16::MAX);
+        let data = encode_treadmill_data(u16::MAX, i16::MAX, u32::MAX, None, u16::MAX, None);
         assert_eq!(data.len(), 13, "always 13 bytes regardless of values");
 
         let speed = u16::from_le_bytes([data[2], data[3]]);
@@ -488,11 +685,40 @@ mod tests {
 
     #[test]
     fn test_encode_treadmill_data_negative_incline() {
-        let data = encode_treadmill_data(0, -150, 0, 0); // -15.0%
+        let data = encode_treadmill_data(0, -150, 0, None, 0, None); // -15.0%
         let incline = i16::from_le_bytes([data[7], data[8]]);
         assert_eq!(incline, -150);
     }
 
+    #[test]
+    fn test_encode_treadmill_data_heart_rate() {
+        let data = encode_treadmill_data(500, 30, 1234, Some(142), 300, None);
+        assert_eq!(data.len(), 14);
+        assert_eq!(u16::from_le_bytes([data[0], data[1]]), 0x050C);
+        // Heart rate sits between ramp angle and elapsed time
+        assert_eq!(data[11], 142);
+        assert_eq!(u16::from_le_bytes([data[12], data[13]]), 300);
+        assert_eq!(data[2..11], encode_treadmill_data(500, 30, 1234, None, 300, None)[2..11]);
+    }
+
+    #[test]
+    fn test_encode_treadmill_data_remaining_time() {
+        let data = encode_treadmill_data(500, 30, 1234, Some(142), 300, Some(1500));
+        assert_eq!(data.len(), 16);
+        assert_eq!(u16::from_le_bytes([data[0], data[1]]), 0x0D0C);
+        // Remaining time follows elapsed time
+        assert_eq!(u16::from_le_bytes([data[12], data[13]]), 300);
+        assert_eq!(u16::from_le_bytes([data[14], data[15]]), 1500);
+        assert_eq!(encode_treadmill_data(0, 0, 0, None, 0, Some(60))[..2], [0x0C, 0x0C]);
+    }
+
+    #[test]
+    fn test_parse_control_targeted_training_time() {
+        // Opcode 0x0D, 1800 s (0x0708 LE = [0x08, 0x07])
+        assert_eq!(parse_control_point(&[0x0D, 0x08, 0x07]), Some(ControlCommand::SetTargetedTrainingTime(1800)));
+        assert_eq!(parse_control_point(&[0x0D, 0x08]), None);
+    }
+
     #[test]
     fn test_conversion_extreme_values() {
         // u16::MAX speed — should not overflow (uses u32 intermediate)
@@ -502,16 +728,17 @@ mod tests {
         let mph = kmh_hundredths_to_mph_tenths(u16::MAX);
         assert!(mph > 0, "should produce a positive value");
 
-        // Verify no overflow: 65535 * 1609 = 105_446_415 fits in u32
-        assert_eq!(kmh, ((65535u32 * 1609) / 100) as u16);
-        assert_eq!(mph, ((65535u32 * 100) / 1609) as u16);
+        // Out-of-range results saturate instead of wrapping
+        assert_eq!(kmh, u16::MAX);
+        assert_eq!(mph, 4072); // 65535 / 16.09344 = 4072.1
+        assert_eq!(mph_hundredths_to_kmh_hundredths(u32::MAX), u16::MAX);
     }
 
     #[test]
     fn test_encode_control_response_all_combos() {
         // Every opcode + result combo should produce exactly 3 bytes
         for opcode in [0x00, 0x02, 0x03, 0x07, 0x08, 0xFF] {
-            for result in [RESULT_SUCCESS, RESULT_NOT_SUPPORTED, RESULT_INVALID_PARAM, RESULT_FAILED] {
+            for result in [RESULT_SUCCESS, RESULT_NOT_SUPPORTED, RESULT_INVALID_PARAM, RESULT_FAILED, RESULT_CONTROL_NOT_PERMITTED] {
                 let resp = encode_control_response(opcode, result);
                 assert_eq!(resp.len(), 3);
                 assert_eq!(resp[0], RESPONSE_CODE);
@@ -0,0 +1,123 @@
+//! Quiet hours: local time windows with tighter limits.
+//!
+//! Each entry of `quiet_hours` is a window such as `"22:00-07:00"` (local
+//! time in the configured `timezone`, wrapping past midnight when the end
+//! is earlier) with an optional `max_speed_mph`. Inside a window without a
+//! speed, Start/Resume is refused with Control Not Permitted; inside one
+//! with a speed, faster targets get Invalid Parameter. A belt already
+//! running when a window opens keeps going. Overlapping windows apply the
+//! strictest. Unparseable windows are logged and never match.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::FtmsConfig;
+
+/// One quiet window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// Local `"HH:MM-HH:MM"`.
+    pub window: String,
+    /// Speed cap inside the window. Unset refuses Start instead.
+    #[serde(default)]
+    pub max_speed_mph: Option<f64>,
+}
+
+impl QuietHours {
+    /// Whether Start/Resume may go ahead.
+    pub fn allows_start(&self) -> bool {
+        self.max_speed_mph.is_some()
+    }
+
+    /// Whether a speed of `mph_tenths` is within the window's cap.
+    pub fn allows_speed(&self, mph_tenths: u16) -> bool {
+        self.max_speed_mph.is_none_or(|max| mph_tenths as f64 <= (max * 10.0).round())
+    }
+
+    /// e.g. "quiet hours 22:00-07:00, no starts".
+    pub fn describe(&self, config: &FtmsConfig) -> String {
+        match self.max_speed_mph {
+            Some(mph) => format!(
+                "quiet hours {}, max {}",
+                self.window,
+                config.units.speed((mph * 100.0).round() as u32)
+            ),
+            None => format!("quiet hours {}, no starts", self.window),
+        }
+    }
+}
+
+/// The strictest window of `rules` containing `minute` of the local day.
+pub fn in_effect(rules: &[QuietHours], minute: u32) -> Option<&QuietHours> {
+    rules
+        .iter()
+        .filter(|q| match parse_window(&q.window) {
+            Some((start, end)) if start <= end => (start..end).contains(&minute),
+            Some((start, end)) => minute >= start || minute < end,
+            None => false,
+        })
+        .min_by(|a, b| {
+            let cap = |q: &QuietHours| q.max_speed_mph.unwrap_or(f64::NEG_INFINITY);
+            cap(a).total_cmp(&cap(b))
+        })
+}
+
+/// The quiet window in effect right now, if any.
+pub fn now(config: &FtmsConfig) -> Option<&QuietHours> {
+    if config.quiet_hours.is_empty() {
+        return None;
+    }
+    let wall_ms = crate::telemetry::wall_ms();
+    let offset = crate::clock::utc_offset_secs(config.timezone.as_deref(), wall_ms);
+    let minute = (crate::clock::local_ms(wall_ms, offset) / 60_000 % 1440) as u32;
+    in_effect(&config.quiet_hours, minute)
+}
+
+/// Log windows that will never match.
+pub fn check(rules: &[QuietHours]) {
+    for q in rules {
+        if parse_window(&q.window).is_none() {
+            warn!("Ignoring quiet_hours window {:?} (expected HH:MM-HH:MM)", q.window);
+        }
+    }
+}
+
+/// `"22:00-07:00"` to minutes of the day, `(1320, 420)`.
+fn parse_window(s: &str) -> Option<(u32, u32)> {
+    let (start, end) = s.split_once('-')?;
+    Some((parse_hhmm(start)?, parse_hhmm(end)?))
+}
+
+fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (m < 60 && h * 60 + m <= 24 * 60).then_some(h * 60 + m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let rules: Vec<QuietHours> = serde_json::from_str(
+            r#"[{"window": "22:00-07:00"},
+                {"window": "20:00-23:00", "max_speed_mph": 3.5},
+                {"window": "bogus", "max_speed_mph": 1.0}]"#,
+        )
+        .unwrap();
+        assert_eq!(in_effect(&rules, 12 * 60), None);
+        let evening = in_effect(&rules, 21 * 60).unwrap();
+        assert!(evening.allows_start() && evening.allows_speed(35) && !evening.allows_speed(36));
+        // Overlap: the no-start window wins
+        assert!(!in_effect(&rules, 22 * 60 + 30).unwrap().allows_start());
+        assert!(!in_effect(&rules, 6 * 60 + 59).unwrap().allows_start());
+        assert_eq!(in_effect(&rules, 7 * 60), None);
+        assert_eq!(parse_window("7:00-24:00"), Some((420, 1440)));
+        assert_eq!(parse_window("07:60-08:00"), None);
+
+        let config = FtmsConfig::default();
+        assert_eq!(rules[0].describe(&config), "quiet hours 22:00-07:00, no starts");
+        assert_eq!(rules[1].describe(&config), "quiet hours 20:00-23:00, max 3.5 mph");
+    }
+}
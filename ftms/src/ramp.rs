@@ -0,0 +1,180 @@
+//! Warm-up and cool-down speed ramps.
+//!
+//! With `warmup_secs` set, the first speed target after a Start is reached
+//! gradually: the belt is stepped toward it once a second over the warm-up
+//! period instead of jumping straight there. With `cooldown_secs` set, a
+//! Stop steps the belt down to zero over the cool-down period before
+//! stopping it. The steps are real speed commands, so Treadmill Data
+//! follows the ramp. A new speed target cancels a running ramp; a second
+//! Stop during a cool-down, or a Pause, stops the belt immediately.
+//! Ramps that run to the end are announced on `finished`, so the GATT
+//! service can move Training Status on from Pre-/Post-Workout.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::sync::broadcast;
+
+use crate::treadmill::{self, Commands};
+
+/// Time between ramp steps.
+pub const STEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RampKind {
+    WarmUp,
+    CoolDown,
+    /// Back up to speed after a pause from the debug port.
+    Resume,
+}
+
+#[derive(Default)]
+struct RampState {
+    /// A Start arrived and the next speed target should warm up.
+    warmup_armed: bool,
+    running: Option<(u64, RampKind, tokio::task::AbortHandle)>,
+    next_id: u64,
+}
+
+/// Cheap, cloneable handle to the ramp in progress, if any.
+#[derive(Clone)]
+pub struct Ramp {
+    state: Arc<Mutex<RampState>>,
+    finished: broadcast::Sender<RampKind>,
+}
+
+impl Default for Ramp {
+    fn default() -> Self {
+        Self { state: Arc::default(), finished: broadcast::channel(4).0 }
+    }
+}
+
+impl Ramp {
+    /// Warm up toward the next speed target.
+    pub fn arm_warmup(&self) {
+        self.state.lock().unwrap().warmup_armed = true;
+    }
+
+    /// Whether a warm-up was armed, disarming it.
+    pub fn take_warmup(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().warmup_armed)
+    }
+
+    /// The kind of ramp under way.
+    pub fn running(&self) -> Option<RampKind> {
+        self.state.lock().unwrap().running.as_ref().map(|(_, kind, _)| *kind)
+    }
+
+    /// Ramps that complete (after their `finish`), not cancelled ones.
+    pub fn finished(&self) -> broadcast::Receiver<RampKind> {
+        self.finished.subscribe()
+    }
+
+    /// Abort the ramp under way and disarm any warm-up. Returns what was
+    /// cancelled.
+    pub fn cancel(&self) -> Option<RampKind> {
+        let mut s = self.state.lock().unwrap();
+        s.warmup_armed = false;
+        let (_, kind, handle) = s.running.take()?;
+        handle.abort();
+        info!("{:?} ramp cancelled", kind);
+        Some(kind)
+    }
+
+    /// Step the belt through `steps` (tenths of mph) once a second, then
+    /// run `finish`. Replaces any ramp already under way.
+    pub fn start<F>(&self, kind: RampKind, commands: Commands, steps: Vec<u16>, finish: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.cancel();
+        let mut s = self.state.lock().unwrap();
+        s.next_id += 1;
+        let id = s.next_id;
+        let ramp = self.clone();
+        let task = tokio::spawn(async move {
+            for (i, tenths) in steps.iter().enumerate() {
+                if i > 0 {
+                    tokio::time::sleep(STEP_INTERVAL).await;
+                }
+                if let Err(e) = treadmill::send_speed(&commands, *tenths as f64 / 10.0).await {
+                    warn!("{:?} step to {:.1} mph failed: {}", kind, *tenths as f64 / 10.0, e);
+                }
+            }
+            {
+                let mut s = ramp.state.lock().unwrap();
+                if s.running.as_ref().is_some_and(|(running, _, _)| *running == id) {
+                    s.running = None;
+                }
+            }
+            finish.await;
+            // Unless a new ramp took over while `finish` ran
+            if ramp.running().is_none() {
+                let _ = ramp.finished.send(kind);
+            }
+        });
+        s.running = Some((id, kind, task.abort_handle()));
+    }
+}
+
+/// Speeds (tenths of mph) to command once a second going from `from` to
+/// `to` over `secs`, ending exactly at `to`. Repeats are dropped, so a
+/// short ramp can have fewer steps than seconds; an empty ramp is just
+/// `to`.
+pub fn steps(from: u16, to: u16, secs: u64) -> Vec<u16> {
+    let n = secs.max(1);
+    let mut out: Vec<u16> = Vec::new();
+    for i in 1..=n {
+        let v = from as f64 + (to as f64 - from as f64) * i as f64 / n as f64;
+        let v = v.round() as u16;
+        if out.last() != Some(&v) && v != from {
+            out.push(v);
+        }
+    }
+    if out.last() != Some(&to) {
+        out.push(to);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        assert_eq!(steps(0, 50, 5), vec![10, 20, 30, 40, 50]);
+        assert_eq!(steps(60, 0, 3), vec![40, 20, 0]);
+        // Fewer distinct speeds than seconds: no repeats
+        assert_eq!(steps(0, 3, 10), vec![1, 2, 3]);
+        assert_eq!(steps(30, 30, 10), vec![30]);
+        assert_eq!(steps(0, 50, 0), vec![50]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_ramp() {
+        let ramp = Ramp::default();
+        ramp.arm_warmup();
+        assert!(ramp.take_warmup());
+        assert!(!ramp.take_warmup(), "warm-up is used once");
+
+        ramp.start(RampKind::CoolDown, Commands::channel().0, vec![20, 10, 0], async {});
+        assert_eq!(ramp.running(), Some(RampKind::CoolDown));
+        assert_eq!(ramp.cancel(), Some(RampKind::CoolDown));
+        assert_eq!(ramp.running(), None);
+    }
+
+    #[tokio::test]
+    async fn test_finished_announces_completed_ramps() {
+        let ramp = Ramp::default();
+        let mut finished = ramp.finished();
+        ramp.start(RampKind::WarmUp, Commands::channel().0, vec![20, 30], async {});
+        ramp.cancel();
+        ramp.start(RampKind::CoolDown, Commands::channel().0, vec![0], async {});
+        // Only the cool-down ran to the end
+        assert_eq!(finished.recv().await.unwrap(), RampKind::CoolDown);
+        assert_eq!(ramp.running(), None);
+        assert!(finished.try_recv().is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Telemetry replay into the live state.
+//!
+//! Plays a JSONL log written by the telemetry recorder back into the shared
+//! `TreadmillState`, so BLE notifications and debug `sub` streams carry the
+//! recorded workout. Nothing is sent to treadmill_io; while a replay runs the
+//! socket reader leaves the state alone.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+use tokio::sync::Mutex;
+
+use crate::telemetry::{Entry, Record, StateSample};
+use crate::treadmill::TreadmillState;
+
+/// Read the state samples from a telemetry log, in file order, as
+/// `(mono_ms, sample)` pairs. Control records and unparseable lines are skipped.
+pub fn load(path: &str) -> Result<Vec<(u64, StateSample)>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = std::fs::read_to_string(path)?;
+    let samples: Vec<(u64, StateSample)> = data
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Record>(line).ok())
+        .filter_map(|record| match record.entry {
+            Entry::State(sample) => Some((record.mono_ms, sample)),
+            Entry::Control { .. } => None,
+        })
+        .collect();
+    if samples.is_empty() {
+        return Err(format!("no state samples in {}", path).into());
+    }
+    Ok(samples)
+}
+
+/// Claim the state for a replay. Fails if one is already running.
+pub async fn begin(state: &Arc<Mutex<TreadmillState>>) -> Result<StateSample, &'static str> {
+    let mut s = state.lock().await;
+    if s.replaying {
+        return Err("a replay is already running; 'replay stop' first");
+    }
+    s.replaying = true;
+    Ok(StateSample::from(&*s))
+}
+
+/// Play `samples` into `state`, pacing them by their recorded timestamps
+/// divided by `speed`. Stops early if `replaying` is cleared, and restores
+/// `restore` (the pre-replay snapshot) when done.
+pub async fn run(
+    state: Arc<Mutex<TreadmillState>>,
+    samples: Vec<(u64, StateSample)>,
+    speed: f64,
+    restore: StateSample,
+) {
+    info!("Replaying {} telemetry samples at {}x", samples.len(), speed);
+    let mut prev_ms = samples.first().map(|(ms, _)| *ms).unwrap_or(0);
+
+    for (mono_ms, sample) in samples {
+        let gap = Duration::from_millis(mono_ms.saturating_sub(prev_ms)).div_f64(speed);
+        prev_ms = mono_ms;
+        tokio::time::sleep(gap).await;
+
+        let mut s = state.lock().await;
+        if !s.replaying {
+            info!("Replay stopped");
+            break;
+        }
+        sample.apply_to(&mut s);
+    }
+
+    let mut s = state.lock().await;
+    restore.apply_to(&mut s);
+    s.replaying = false;
+    info!("Replay finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_skips_control_and_garbage() {
+        let path = "/tmp/ftms_replay_test.jsonl";
+        std::fs::write(
+            path,
+            concat!(
+                r#"{"mono_ms":0,"wall_ms":1,"kind":"state","speed_tenths_mph":0,"incline_half_pct":0,"elapsed_secs":0,"distance_meters":0,"connected":true}"#, "\n",
+                r#"{"mono_ms":10,"wall_ms":2,"kind":"control","command":"StartOrResume","result":1}"#, "\n",
+                "garbage\n",
+                r#"{"mono_ms":1000,"wall_ms":3,"kind":"state","speed_tenths_mph":30,"incline_half_pct":2,"elapsed_secs":1,"distance_meters":1,"connected":true}"#, "\n",
+            ),
+        )
+        .unwrap();
+        let samples = load(path).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].0, 1000);
+        assert_eq!(samples[1].1.speed_tenths_mph, 30);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(load("/tmp/ftms_replay_nonexistent.jsonl").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_and_restores() {
+        let state = Arc::new(Mutex::new(TreadmillState { speed_tenths_mph: 7, ..Default::default() }));
+        let restore = begin(&state).await.unwrap();
+        assert!(begin(&state).await.is_err(), "second replay must be refused");
+
+        let samples = vec![
+            (0, StateSample { speed_tenths_mph: 30, ..Default::default() }),
+            (50, StateSample { speed_tenths_mph: 40, ..Default::default() }),
+        ];
+        let task = tokio::spawn(run(state.clone(), samples, 1.0, restore));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(state.lock().await.speed_tenths_mph, 30);
+
+        task.await.unwrap();
+        let s = state.lock().await;
+        assert_eq!(s.speed_tenths_mph, 7, "pre-replay state restored");
+        assert!(!s.replaying);
+    }
+}
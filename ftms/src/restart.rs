@@ -0,0 +1,177 @@
+//! In-place restart that keeps the listening sockets.
+//!
+//! SIGUSR2 (`systemctl reload ftms`) re-executes the daemon — the freshly
+//! installed binary after an upgrade — with the same arguments and PID.
+//! The debug and gRPC listeners are handed across as inherited file
+//! descriptors named in `FTMS_LISTEN_FDS`, so clients connecting during
+//! the restart wait in the listen backlog instead of being refused; only
+//! connections already open are dropped, and the UIs reconnect as usual.
+//! Everything else starts over as on a normal start: BlueZ drops the old
+//! GATT application and advertisement with our D-Bus connection and they
+//! are registered again (BLE apps reconnect as after any restart), and a
+//! workout under way carries on from its session checkpoint. If the exec
+//! fails the daemon logs it and keeps running.
+
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use log::{error, info, warn};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Names and descriptor numbers of the handed-over listeners,
+/// e.g. `debug=3,grpc=7`.
+pub const LISTEN_FDS_ENV: &str = "FTMS_LISTEN_FDS";
+
+/// Listeners handed over by the process we were exec'd from. Any not
+/// taken are closed when this is dropped.
+#[derive(Default)]
+pub struct Inherited(Vec<(String, OwnedFd)>);
+
+impl Inherited {
+    /// Claim the listeners named in the environment, and clear it so
+    /// nothing we spawn sees them.
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var(LISTEN_FDS_ENV) else {
+            return Self::default();
+        };
+        std::env::remove_var(LISTEN_FDS_ENV);
+        let mut fds = Vec::new();
+        for (name, fd) in parse(&value) {
+            // SAFETY: F_GETFD only checks the descriptor is open.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+                warn!("Inherited {} listener fd {} is not open", name, fd);
+                continue;
+            }
+            // SAFETY: the previous process handed this descriptor to us
+            // alone, and it is open.
+            fds.push((name, unsafe { OwnedFd::from_raw_fd(fd) }));
+        }
+        Self(fds)
+    }
+
+    fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let i = self.0.iter().position(|(n, _)| n == name)?;
+        Some(self.0.remove(i).1)
+    }
+
+    /// The inherited TCP listener `name`, if it's still listening on
+    /// `port`. A listener on another port (the config changed) is closed.
+    pub fn tcp(&mut self, name: &str, port: u16) -> Option<TcpListener> {
+        let listener = std::net::TcpListener::from(self.take(name)?);
+        match listener.local_addr() {
+            Ok(addr) if addr.port() == port => {}
+            _ => {
+                info!("Not reusing the {} listener: no longer on port {}", name, port);
+                return None;
+            }
+        }
+        match listener.set_nonblocking(true).and_then(|_| TcpListener::from_std(listener)) {
+            Ok(listener) => {
+                info!("Reusing the {} listener on port {} across the restart", name, port);
+                Some(listener)
+            }
+            Err(e) => {
+                warn!("Can't reuse the {} listener: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// `name=fd` pairs from `LISTEN_FDS_ENV`. Descriptors 0-2 are never
+/// listeners.
+fn parse(value: &str) -> Vec<(String, RawFd)> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (name, fd) = pair.split_once('=')?;
+            let fd: RawFd = fd.trim().parse().ok()?;
+            (fd > 2).then(|| (name.trim().to_string(), fd))
+        })
+        .collect()
+}
+
+/// Re-execute the daemon on every SIGUSR2, handing over `listeners`.
+/// Never returns: a successful exec replaces the process, a failed one is
+/// logged and the next signal tries again.
+pub async fn on_signal(listeners: Vec<(&'static str, RawFd)>) {
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("In-place restart unavailable: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    while usr2.recv().await.is_some() {
+        let e = exec(&listeners);
+        error!("Restart failed, carrying on: {}", e);
+    }
+}
+
+/// Exec our own command line with `listeners` inherited. Only returns if
+/// the exec failed.
+fn exec(listeners: &[(&str, RawFd)]) -> io::Error {
+    if let Err(e) = listeners.iter().try_for_each(|&(_, fd)| set_cloexec(fd, false)) {
+        restore(listeners);
+        return e;
+    }
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| "/proc/self/exe".into());
+    let fds = listeners.iter().map(|(name, fd)| format!("{}={}", name, fd)).collect::<Vec<_>>().join(",");
+    info!("Restarting in place: {} ({})", program.to_string_lossy(), fds);
+    let e = Command::new(&program).args(args).env(LISTEN_FDS_ENV, fds).exec();
+    // Still here: don't leak the listeners into child processes
+    restore(listeners);
+    e
+}
+
+fn restore(listeners: &[(&str, RawFd)]) {
+    for &(_, fd) in listeners {
+        let _ = set_cloexec(fd, true);
+    }
+}
+
+fn set_cloexec(fd: RawFd, on: bool) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor the daemon keeps open.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        let flags = if on { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("debug=3,grpc=7"), vec![("debug".to_string(), 3), ("grpc".to_string(), 7)]);
+        assert_eq!(parse("debug=1,grpc=x,=5,junk"), vec![("".to_string(), 5)]);
+        assert!(parse("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_handover() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut inherited = Inherited(vec![
+            ("debug".to_string(), OwnedFd::from(listener)),
+            ("grpc".to_string(), OwnedFd::from(other)),
+        ]);
+
+        assert!(inherited.tcp("grpc", port).is_none(), "wrong port");
+        assert!(inherited.tcp("grpc", port).is_none(), "already taken");
+        let reused = inherited.tcp("debug", port).unwrap();
+        let client = tokio::net::TcpStream::connect(("127.0.0.1", port));
+        let (accepted, connected) = tokio::join!(reused.accept(), client);
+        assert!(accepted.is_ok() && connected.is_ok());
+    }
+}
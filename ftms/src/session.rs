@@ -0,0 +1,131 @@
+//! Crash-safe workout checkpoints.
+//!
+//! While a workout is under way the treadmill task writes its counters and
+//! last commanded targets to `session_checkpoint` every few seconds (to a
+//! temp file, then renamed over the old one, so a crash mid-write never
+//! leaves a torn file). On startup a recent checkpoint is restored, so a
+//! daemon restart mid-workout carries on from the saved elapsed time,
+//! distance and climb instead of dropping to zero.
+
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// How often the treadmill task checkpoints an active workout.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A snapshot of the workout counters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Unix epoch ms when this was written.
+    pub wall_ms: u64,
+    pub elapsed_secs: u64,
+    pub distance_m: f64,
+    /// Meters climbed; absent from checkpoints written before it was tracked.
+    #[serde(default)]
+    pub elevation_gain_m: f64,
+    /// Belt speed at checkpoint time, tenths of mph.
+    pub speed_tenths_mph: u16,
+    pub last_speed_target: Option<u16>,
+    pub last_incline_target: Option<i16>,
+    /// The workout's label, if one was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl Checkpoint {
+    /// Whether this checkpoint is recent enough to resume at `now_ms`.
+    /// Older ones belong to a finished workout, not one interrupted by a
+    /// restart.
+    pub fn resumable(&self, now_ms: u64, max_age: Duration) -> bool {
+        now_ms >= self.wall_ms && now_ms - self.wall_ms <= max_age.as_millis() as u64
+    }
+
+    /// Elapsed time to resume with at `now_ms`. treadmill_io keeps the belt
+    /// running while we're down, so if it was moving the gap counts too.
+    pub fn elapsed_at(&self, now_ms: u64) -> Duration {
+        let mut secs = self.elapsed_secs;
+        if self.speed_tenths_mph > 0 {
+            secs += now_ms.saturating_sub(self.wall_ms) / 1000;
+        }
+        Duration::from_secs(secs)
+    }
+}
+
+/// Write `cp` to `path` atomically.
+pub async fn save(path: &str, cp: &Checkpoint) -> std::io::Result<()> {
+    let json = serde_json::to_string(cp)?;
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+/// Read the checkpoint at `path`. A missing or unreadable file means there
+/// is nothing to resume.
+pub fn load(path: &str) -> Option<Checkpoint> {
+    let data = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&data) {
+        Ok(cp) => Some(cp),
+        Err(e) => {
+            warn!("Ignoring invalid session checkpoint {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Remove the checkpoint once its workout has ended.
+pub async fn clear(path: &str) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove session checkpoint {}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(speed: u16) -> Checkpoint {
+        Checkpoint {
+            wall_ms: 1_000_000,
+            elapsed_secs: 600,
+            distance_m: 1234.5,
+            elevation_gain_m: 24.7,
+            speed_tenths_mph: speed,
+            last_speed_target: Some(speed),
+            last_incline_target: Some(4),
+            label: None,
+        }
+    }
+
+    #[test]
+    fn test_resumable_window() {
+        let cp = checkpoint(30);
+        let max_age = Duration::from_secs(300);
+        assert!(cp.resumable(1_000_000 + 299_000, max_age));
+        assert!(!cp.resumable(1_000_000 + 301_000, max_age), "stale workout");
+        assert!(!cp.resumable(999_000, max_age), "clock went backwards");
+    }
+
+    #[test]
+    fn test_elapsed_includes_gap_only_while_moving() {
+        assert_eq!(checkpoint(30).elapsed_at(1_000_000 + 12_500), Duration::from_secs(612));
+        assert_eq!(checkpoint(0).elapsed_at(1_000_000 + 12_500), Duration::from_secs(600));
+    }
+
+    #[tokio::test]
+    async fn test_save_load_roundtrip() {
+        let path = "/tmp/ftms_test_session_checkpoint.json";
+        let cp = checkpoint(30);
+        save(path, &cp).await.unwrap();
+        assert_eq!(load(path), Some(cp));
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        std::fs::write(path, "not json").unwrap();
+        assert_eq!(load(path), None);
+        let _ = std::fs::remove_file(path);
+        assert_eq!(load(path), None);
+    }
+}
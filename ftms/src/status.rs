@@ -0,0 +1,277 @@
+//! treadmill_io message decoding.
+//!
+//! Every line treadmill_io sends is a JSON object tagged by `"type"`.
+//! Status lines carry both the emulated values (`emu_speed`/`emu_incline`)
+//! and the decoded motor bus values (`bus_speed`/`bus_incline`, -1 when not
+//! yet seen). Emulate mode reports the former, proxy mode the latter.
+//! Lines that aren't JSON, or whose fields have the wrong type, are
+//! malformed; an unrecognised `"type"` is not an error, so a newer
+//! treadmill_io can add messages without breaking the daemon.
+//!
+//! On connect the daemon sends `{"cmd":"hello","version":N}` and
+//! treadmill_io answers with its own version. Builds that predate the
+//! handshake ignore the command, so a status line arriving first means
+//! version 0. From version 3 a command may carry an `"id"`, which
+//! treadmill_io answers, to the sender only, with an `ack` or an `error`
+//! naming the same id.
+
+use serde::Deserialize;
+
+/// The treadmill_io protocol version this daemon speaks
+/// (`IPC_PROTOCOL_VERSION` in `src/ipc_protocol.h`).
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// First protocol version that answers `{"cmd":"caps"}`.
+pub const CAPS_VERSION: u32 = 2;
+
+/// First protocol version that answers a command's `"id"` with `ack`/`error`.
+pub const ACK_VERSION: u32 = 3;
+
+/// What the daemon goes without on a treadmill_io speaking `version`: each
+/// version-gated feature with the version it needs.
+const GATED: [(u32, &str); 2] = [(CAPS_VERSION, "caps query"), (ACK_VERSION, "command acks")];
+
+/// The version-gated features `version` lacks, for logging.
+pub fn lacking(version: u32) -> Vec<&'static str> {
+    GATED.iter().filter(|&&(v, _)| version < v).map(|&(_, name)| name).collect()
+}
+
+/// One line from treadmill_io.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Status(StatusMsg),
+    Kv(KvMsg),
+    Ack(AckMsg),
+    Error(ErrorMsg),
+    Hello(HelloMsg),
+    Caps(CapsMsg),
+    #[serde(other)]
+    Unknown,
+}
+
+/// Parse one line (without its newline).
+pub fn parse_line(line: &str) -> Result<Message, serde_json::Error> {
+    serde_json::from_str(line)
+}
+
+/// `"type":"status"`, sent on connect, on `{"cmd":"status"}`, and on every
+/// mode or target change.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct StatusMsg {
+    #[serde(default)]
+    pub proxy: bool,
+    #[serde(default)]
+    pub emulate: bool,
+    #[serde(default)]
+    pub emu_speed: u16,
+    /// Half-percent units; negative on a treadmill_io with decline.
+    #[serde(default)]
+    pub emu_incline: i16,
+    #[serde(default = "not_seen")]
+    pub bus_speed: i32,
+    #[serde(default = "not_seen")]
+    pub bus_incline: i32,
+    #[serde(default)]
+    pub console_bytes: u64,
+    #[serde(default)]
+    pub motor_bytes: u64,
+}
+
+fn not_seen() -> i32 {
+    -1
+}
+
+/// `"type":"kv"`: one key/value pair seen on the serial bus.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct KvMsg {
+    #[serde(default)]
+    pub ts: f64,
+    /// `"console"`, `"motor"`, or `"emulate"`.
+    pub source: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// `"type":"ack"`: the command tagged `id` was accepted and applied.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct AckMsg {
+    pub id: u32,
+}
+
+/// `"type":"error"`, e.g. `"too many clients"` just before the socket
+/// closes, or with `id` the reason the command tagged with it was refused.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorMsg {
+    pub msg: String,
+    #[serde(default)]
+    pub id: Option<u32>,
+}
+
+/// `"type":"hello"`: the answer to `{"cmd":"hello"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct HelloMsg {
+    pub version: u32,
+}
+
+/// `"type":"caps"`: the answer to `{"cmd":"caps"}`, in the same units as
+/// status lines.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CapsMsg {
+    /// Every command treadmill_io accepts.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// Speed ceiling, tenths of mph.
+    pub max_speed: u16,
+    /// Incline floor, half-percent units; negative with decline.
+    pub min_incline: i16,
+    /// Incline ceiling, half-percent units.
+    pub max_incline: u16,
+    #[serde(default)]
+    pub decline: bool,
+}
+
+/// The speed/incline a status message reports for the current mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusReading {
+    pub speed_tenths_mph: u16,
+    pub incline_half_pct: i16,
+    pub emulating: bool,
+}
+
+impl StatusMsg {
+    /// Effective values: emulate mode uses emu_*, proxy uses bus_*.
+    pub fn reading(&self) -> StatusReading {
+        let effective = |emu: i32, bus: i32| {
+            if self.emulate {
+                emu
+            } else {
+                bus.max(0)
+            }
+        };
+        StatusReading {
+            speed_tenths_mph: effective(self.emu_speed as i32, self.bus_speed) as u16,
+            incline_half_pct: effective(self.emu_incline as i32, self.bus_incline) as i16,
+            emulating: self.emulate,
+        }
+    }
+
+    /// Where the incline motor is, in half-percent units: the motor bus
+    /// value, once seen. In emulate mode `emu_incline` is the commanded
+    /// value straight away, so this is what shows the motor still moving.
+    pub fn motor_incline(&self) -> Option<i16> {
+        (self.bus_incline >= 0).then_some(self.bus_incline as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(line: &str) -> StatusReading {
+        match parse_line(line).unwrap() {
+            Message::Status(msg) => msg.reading(),
+            other => panic!("expected status, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lacking() {
+        assert_eq!(lacking(0), vec!["caps query", "command acks"]);
+        assert_eq!(lacking(2), vec!["command acks"]);
+        assert!(lacking(PROTOCOL_VERSION).is_empty());
+    }
+
+    #[test]
+    fn test_parse_status_modes() {
+        assert_eq!(
+            status(r#"{"type":"status","emulate":true,"emu_speed":35,"emu_incline":4,"bus_speed":10,"bus_incline":2}"#),
+            StatusReading { speed_tenths_mph: 35, incline_half_pct: 4, emulating: true }
+        );
+        assert_eq!(
+            status(r#"{"type":"status","emulate":false,"emu_speed":35,"bus_speed":10,"bus_incline":-1}"#),
+            StatusReading { speed_tenths_mph: 10, incline_half_pct: 0, emulating: false }
+        );
+        assert_eq!(
+            status(r#"{"type":"status","emulate":true,"emu_speed":20,"emu_incline":-4}"#),
+            StatusReading { speed_tenths_mph: 20, incline_half_pct: -4, emulating: true }
+        );
+        // Bus values default to "not seen yet"
+        assert_eq!(
+            status(r#"{"type":"status"}"#),
+            StatusReading { speed_tenths_mph: 0, incline_half_pct: 0, emulating: false }
+        );
+    }
+
+    #[test]
+    fn test_motor_incline() {
+        let motor = |line: &str| match parse_line(line).unwrap() {
+            Message::Status(msg) => msg.motor_incline(),
+            other => panic!("expected status, got {:?}", other),
+        };
+        // Emulate reports the commanded 4 while the motor is still at 2
+        assert_eq!(motor(r#"{"type":"status","emulate":true,"emu_incline":4,"bus_incline":2}"#), Some(2));
+        assert_eq!(motor(r#"{"type":"status","emulate":true,"emu_incline":4,"bus_incline":-1}"#), None);
+        assert_eq!(motor(r#"{"type":"status"}"#), None);
+    }
+
+    #[test]
+    fn test_parse_other_messages() {
+        assert_eq!(
+            parse_line(r#"{"type":"kv","ts":12.5,"source":"motor","key":"hmph","value":"23"}"#).unwrap(),
+            Message::Kv(KvMsg {
+                ts: 12.5,
+                source: "motor".to_string(),
+                key: "hmph".to_string(),
+                value: "23".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_line(r#"{"type":"error","msg":"too many clients"}"#).unwrap(),
+            Message::Error(ErrorMsg { msg: "too many clients".to_string(), id: None })
+        );
+        assert_eq!(
+            parse_line(r#"{"type":"error","msg":"speed out of range","id":4}"#).unwrap(),
+            Message::Error(ErrorMsg { msg: "speed out of range".to_string(), id: Some(4) })
+        );
+        assert_eq!(parse_line(r#"{"type":"ack","id":4}"#).unwrap(), Message::Ack(AckMsg { id: 4 }));
+        assert!(parse_line(r#"{"type":"ack"}"#).is_err(), "an ack names its command");
+        assert_eq!(parse_line(r#"{"type":"hello","version":2}"#).unwrap(), Message::Hello(HelloMsg { version: 2 }));
+        assert_eq!(parse_line(r#"{"type":"odometer","miles":1234}"#).unwrap(), Message::Unknown);
+        assert_eq!(
+            parse_line(
+                r#"{"type":"caps","commands":["speed","caps"],"max_speed":120,"min_incline":0,"max_incline":198,"decline":false}"#
+            )
+            .unwrap(),
+            Message::Caps(CapsMsg {
+                commands: vec!["speed".to_string(), "caps".to_string()],
+                max_speed: 120,
+                min_incline: 0,
+                max_incline: 198,
+                decline: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_lines() {
+        for line in [
+            "",
+            "not json",
+            r#"{"type":"status","emu_speed":35"#,
+            r#"{"emu_speed":35}"#,
+            r#"{"type":7}"#,
+            r#"{"type":"status","emulate":"yes"}"#,
+            r#"{"type":"status","emu_speed":-5}"#,
+            r#"{"type":"status","emu_speed":70000}"#,
+            r#"{"type":"kv","source":"motor","key":"hmph"}"#,
+            r#"{"type":"kv","source":"motor","key":"hmph","value":23}"#,
+            r#"{"type":"error"}"#,
+            r#"{"type":"hello"}"#,
+            r#"{"type":"hello","version":-1}"#,
+            r#"{"type":"caps","commands":["speed"],"max_speed":120}"#,
+        ] {
+            assert!(parse_line(line).is_err(), "{:?} should be malformed", line);
+        }
+    }
+}
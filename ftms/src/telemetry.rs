@@ -0,0 +1,213 @@
+//! Optional JSONL telemetry recorder.
+//!
+//! When `telemetry_log` is set in the config, every treadmill state change and
+//! every control command is appended to that file as one JSON object per line,
+//! stamped with both monotonic time since daemon start and wall-clock time.
+//! Producers never block: records go through a bounded channel to a writer
+//! task, and are dropped (with a warning) if the disk can't keep up.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::protocol::ControlCommand;
+use crate::treadmill::TreadmillState;
+
+/// The treadmill fields worth recording, in treadmill-native units.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateSample {
+    pub speed_tenths_mph: u16,
+    pub incline_half_pct: i16,
+    pub elapsed_secs: u16,
+    pub distance_meters: u32,
+    pub connected: bool,
+}
+
+impl From<&TreadmillState> for StateSample {
+    fn from(s: &TreadmillState) -> Self {
+        Self {
+            speed_tenths_mph: s.speed_tenths_mph,
+            incline_half_pct: s.incline_half_pct,
+            elapsed_secs: s.elapsed_secs,
+            distance_meters: s.distance_meters,
+            connected: s.connected,
+        }
+    }
+}
+
+impl StateSample {
+    /// Overwrite the recorded fields of `state` with this sample. Speed goes
+    /// through the same smoothing as live status.
+    pub fn apply_to(&self, state: &mut TreadmillState) {
+        state.set_speed(self.speed_tenths_mph, Instant::now());
+        state.incline_half_pct = self.incline_half_pct;
+        state.elapsed_secs = self.elapsed_secs;
+        state.distance_meters = self.distance_meters;
+        state.connected = self.connected;
+    }
+}
+
+/// One line of the telemetry log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the recorder started (monotonic)
+    pub mono_ms: u64,
+    /// Milliseconds since the Unix epoch (wall clock)
+    pub wall_ms: u64,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    State(StateSample),
+    Control { command: String, result: u8 },
+}
+
+/// Cheap, cloneable handle for recording telemetry. A disabled recorder
+/// accepts records and discards them.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: Option<mpsc::Sender<Record>>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn disabled() -> Self {
+        Self { tx: None, started: Instant::now() }
+    }
+
+    /// Record a state snapshot.
+    pub fn state(&self, state: &TreadmillState) {
+        self.record(Entry::State(StateSample::from(state)));
+    }
+
+    /// Record a control command and the result code it produced.
+    pub fn control(&self, cmd: &ControlCommand, result: u8) {
+        self.record(Entry::Control { command: format!("{:?}", cmd), result });
+    }
+
+    fn record(&self, entry: Entry) {
+        let Some(tx) = &self.tx else { return };
+        let record = Record {
+            mono_ms: self.started.elapsed().as_millis() as u64,
+            wall_ms: wall_ms(),
+            entry,
+        };
+        if tx.try_send(record).is_err() {
+            warn!("Telemetry recorder backlogged, dropping record");
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn wall_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Start recording to `path` (append mode). Returns a disabled recorder when
+/// `path` is `None`.
+pub fn start(path: Option<&str>) -> Recorder {
+    let Some(path) = path else {
+        return Recorder::disabled();
+    };
+    let (tx, rx) = mpsc::channel(256);
+    tokio::spawn(write_jsonl(path.to_string(), rx, "telemetry log"));
+    info!("Recording telemetry to {}", path);
+    Recorder { tx: Some(tx), started: Instant::now() }
+}
+
+/// Append everything from `rx` to `path`, one JSON object per line.
+/// `what` names the file in warnings.
+pub async fn write_jsonl<T: Serialize>(path: String, mut rx: mpsc::Receiver<T>, what: &str) {
+    let mut file = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+    {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to open {} {}: {}", what, path, e);
+            return;
+        }
+    };
+
+    while let Some(record) = rx.recv().await {
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize {} record: {}", what, e);
+                continue;
+            }
+        };
+        line.push('\n');
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            warn!("Failed to write {} {}: {}", what, path, e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_json_shape() {
+        let record = Record {
+            mono_ms: 1500,
+            wall_ms: 1_700_000_000_000,
+            entry: Entry::State(StateSample {
+                speed_tenths_mph: 35,
+                incline_half_pct: 4,
+                elapsed_secs: 60,
+                distance_meters: 95,
+                connected: true,
+            }),
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            json,
+            r#"{"mono_ms":1500,"wall_ms":1700000000000,"kind":"state","speed_tenths_mph":35,"incline_half_pct":4,"elapsed_secs":60,"distance_meters":95,"connected":true}"#
+        );
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+
+    #[test]
+    fn test_control_record_roundtrip() {
+        let record = Record {
+            mono_ms: 0,
+            wall_ms: 0,
+            entry: Entry::Control { command: "SetTargetSpeed(500)".into(), result: 1 },
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(r#""kind":"control""#));
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+
+    #[tokio::test]
+    async fn test_recorder_writes_jsonl() {
+        let path = "/tmp/ftms_telemetry_test.jsonl";
+        let _ = std::fs::remove_file(path);
+        let recorder = start(Some(path));
+        recorder.state(&TreadmillState { speed_tenths_mph: 30, ..Default::default() });
+        recorder.control(&ControlCommand::StartOrResume, 1);
+        drop(recorder);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let data = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<Record> = data.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(&lines[0].entry, Entry::State(s) if s.speed_tenths_mph == 30));
+        assert!(matches!(&lines[1].entry, Entry::Control { result: 1, .. }));
+        let _ = std::fs::remove_file(path);
+    }
+}
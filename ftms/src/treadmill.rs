@@ -3,72 +3,699 @@
 //! Connects to the Unix domain socket, sends JSON commands,
 //! and receives JSON event lines. Maintains shared state with
 //! current speed, incline, elapsed time, and distance.
+//!
+//! Commands share that one connection: `send_*` queue a line on
+//! [`Commands`] and the connection task writes it between status lines,
+//! so a command can't overtake or race the reader. From protocol v3 each
+//! line carries an id that treadmill_io answers with an `ack` or an `error`,
+//! so the caller hears whether it was applied or refused; older builds only
+//! tell it the line was written. While the link is down commands are
+//! refused straight away rather than waiting for the reconnect.
+//!
+//! `--socket tcp:HOST:PORT` reaches a treadmill_io socket forwarded over
+//! TCP instead (e.g. by socat on the Pi), for driving the treadmill from a
+//! development machine or a container. The protocol is the same.
+//! `--mock-treadmill` (`mock:`) talks to a simulated one instead; see
+//! `mock.rs`.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 
 use log::{debug, error, info, warn};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::UnixStream;
-use tokio::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 use tokio::time::{interval, Duration};
 
+use crate::calibration;
+use crate::config::DistanceSource;
+use crate::audit::Origin;
+use crate::ftms_service::{self, ControlContext, Limits};
+use crate::health::Counter;
+use crate::protocol::{self, ControlCommand};
+use crate::session::{self, Checkpoint};
+use crate::telemetry::StateSample;
+use crate::workout::{Sample, Workout};
+use crate::status::{self, CapsMsg, Message, StatusReading};
+
 /// Shared treadmill state, updated continuously by the socket reader.
 #[derive(Debug, Clone, Default)]
 pub struct TreadmillState {
     /// Belt speed in tenths of mph (e.g. 35 = 3.5 mph)
     pub speed_tenths_mph: u16,
-    /// Incline in half-percent units (e.g. 10 = 5.0%, 1 = 0.5%)
-    pub incline_half_pct: u16,
+    /// Incline in half-percent units (e.g. 10 = 5.0%, 1 = 0.5%; negative
+    /// is decline)
+    pub incline_half_pct: i16,
     /// Seconds elapsed since belt first started moving
     pub elapsed_secs: u16,
     /// Cumulative distance in meters
     pub distance_meters: u32,
+    /// Meters climbed this workout: distance covered times the grade
+    pub elevation_gain_m: f64,
+    /// Distance by the speed `distance_source` doesn't use, for comparison
+    pub compare_distance_m: f64,
     /// Whether we have an active connection to treadmill_io
     pub connected: bool,
+    /// Last speed commanded through the control point, in tenths of mph
+    pub last_speed_target: Option<u16>,
+    /// Last incline commanded through the control point, in half-percent units
+    pub last_incline_target: Option<i16>,
+    /// Set Targeted Distance from an app: the workout's distance in meters
+    /// at which the belt is stopped. Cleared once reached or the workout ends.
+    pub distance_target: Option<u32>,
+    /// Set Targeted Training Time from an app: the workout's elapsed
+    /// seconds at which the belt is stopped. Counts down in Treadmill Data
+    /// as Remaining Time; cleared once reached or the workout ends.
+    pub training_time_target: Option<u16>,
+    /// A telemetry replay owns the state; status from treadmill_io is ignored
+    pub replaying: bool,
+    /// Bumped on every speed command so stale verifiers stand down
+    pub speed_cmd_gen: u32,
+    /// When the latest speed command was issued
+    pub speed_cmd_at: Option<Instant>,
+    /// Bumped on every incline command so stale verifiers stand down
+    pub incline_cmd_gen: u32,
+    /// Displayed speed (hundredths of mph) when the latest speed change arrived
+    pub speed_ramp_from: u32,
+    /// When the latest speed change arrived; None until the first one
+    pub speed_changed_at: Option<Instant>,
+    /// How fast displayed speed may move toward a new status value, in
+    /// hundredths of mph per second. 0 shows status values as-is.
+    pub speed_slew_per_s: u32,
+    /// Actual belt speed over treadmill_io's, from calibration or config;
+    /// None reports its speeds as-is. See `calibration.rs`.
+    pub speed_correction: Option<f64>,
+    /// The console stopped the belt mid-workout; elapsed time is frozen
+    /// until it starts again
+    pub console_paused: bool,
+    /// Paused from the debug port or a control point Pause: the speed
+    /// (tenths of mph) to resume at. Elapsed time and distance are frozen
+    /// and the workout kept open until it ends.
+    pub app_paused: Option<u16>,
+    /// Name for the workout under way, or the next one if none is
+    /// ("tempo run"). Set from the debug `label` command.
+    pub workout_label: Option<String>,
+    /// Where the interval program under way is ("Step 4/10: 2:00 @ 9.0 mph,
+    /// 3%"), from the debug `step` command. Training Status carries it.
+    pub workout_step: Option<String>,
+    /// Protocol version treadmill_io reported for this connection (0 for
+    /// builds without the handshake); None until it answers
+    pub protocol_version: Option<u32>,
+    /// What treadmill_io reported to `caps` on this connection, if it
+    /// speaks a protocol version that has it
+    pub caps: Option<CapsMsg>,
+    /// Beats per minute from hrm-daemon while a strap is reporting
+    pub heart_rate: Option<u8>,
+    /// Incline motor position from the latest status, in half-percent
+    /// units; None until one arrives
+    pub motor_incline_half_pct: Option<i16>,
+    /// When the incline motor last moved, while it's in motion. Cleared
+    /// once its position has held for `incline_settle_ms`.
+    pub incline_moved_at: Option<Instant>,
+    /// Incline target (half-percent units) held back while the motor
+    /// moves, sent once it settles
+    pub queued_incline: Option<i16>,
+}
+
+/// A speed or incline value commanded to treadmill_io, in treadmill-native units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Tenths of mph
+    Speed(u16),
+    /// Half-percent units
+    Incline(i16),
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Speed(t) => write!(f, "speed {:.1} mph", *t as f64 / 10.0),
+            Target::Incline(h) => write!(f, "incline {:.1}%", *h as f64 / 2.0),
+        }
+    }
+}
+
+/// Notable things that happened on the treadmill_io link, broadcast to
+/// any task that wants to react (BLE status notifications, debug streams).
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreadmillEvent {
+    /// A commanded target never showed up in status after all retries.
+    TargetFailed { target: Target, attempts: u32 },
+    /// treadmill_io came back after a drop without our last targets applied,
+    /// and re-applying is disabled. Clients need to re-send them.
+    TargetsLost,
+    /// The belt stopped mid-workout from the console, not from an app.
+    ConsolePaused,
+    /// The belt started again after a console pause.
+    ConsoleResumed,
+    /// The belt was paused from the debug port.
+    Paused,
+    /// Seconds left before a pause from the debug port resumes by itself.
+    ResumeCountdown(u8),
+    /// A pause from the debug port ended and the belt is ramping back up.
+    Resumed,
+    /// The interval program moved to another step, or ended (`None`).
+    Step(Option<String>),
+    /// The workout covered its targeted distance (meters) and the belt
+    /// is being stopped.
+    DistanceReached(u32),
+    /// The workout ran its targeted training time (seconds) and the belt
+    /// is being stopped.
+    TrainingTimeReached(u16),
 }
 
 impl TreadmillState {
+    /// Ceilings for FTMS targets on this connection.
+    pub fn limits(&self) -> Limits {
+        Limits { speed_correction: self.speed_correction, ..Limits::from_caps(self.caps.as_ref()) }
+    }
+
+    /// A treadmill_io speed in hundredths of mph, corrected to what the
+    /// belt really does.
+    pub fn corrected_speed(&self, mph_hundredths: u32) -> u32 {
+        calibration::correct(mph_hundredths, self.speed_correction)
+    }
+
+    /// The latest status speed, corrected, at the treadmill's 0.1 mph
+    /// resolution (for workout samples).
+    pub fn corrected_tenths(&self) -> u16 {
+        (self.corrected_speed(self.speed_tenths_mph as u32 * 10) as f64 / 10.0).round() as u16
+    }
+
     /// Encode current state as FTMS Treadmill Data (0x2ACD) bytes.
     /// Handles mph→km/h and half-pct→tenths conversions in one place.
     pub fn encode_ftms_data(&self) -> Vec<u8> {
-        let speed_kmh = crate::protocol::mph_tenths_to_kmh_hundredths(self.speed_tenths_mph);
+        self.encode_ftms_data_at(Instant::now())
+    }
+
+    /// Encode Treadmill Data as of `now`, with speed interpolated. While
+    /// paused the belt reads as stopped even as it winds down, next to the
+    /// frozen elapsed time and distance.
+    pub fn encode_ftms_data_at(&self, now: Instant) -> Vec<u8> {
+        let speed = if self.is_paused() { 0 } else { self.displayed_speed_at(now) };
+        let speed_kmh = crate::protocol::mph_hundredths_to_kmh_hundredths(speed);
         // half-pct * 5 = tenths of percent (e.g. 10 half_pct = 5% = 50 tenths)
-        let incline_tenths = (self.incline_half_pct as i16) * 5;
-        crate::protocol::encode_treadmill_data(speed_kmh, incline_tenths, self.distance_meters, self.elapsed_secs)
+        let incline_tenths = self.incline_half_pct * 5;
+        crate::protocol::encode_treadmill_data(
+            speed_kmh,
+            incline_tenths,
+            self.distance_meters,
+            self.heart_rate,
+            self.elapsed_secs,
+            self.remaining_secs(),
+        )
+    }
+
+    /// Seconds left of a targeted training time. Elapsed time stands still
+    /// while paused, and so does this.
+    pub fn remaining_secs(&self) -> Option<u16> {
+        self.training_time_target.map(|t| t.saturating_sub(self.elapsed_secs))
+    }
+
+    /// Paused at the console, from the debug port or by an app: the
+    /// session's clock and distance stand still.
+    pub fn is_paused(&self) -> bool {
+        self.console_paused || self.app_paused.is_some()
+    }
+
+    /// Speed to show at `now`, in hundredths of mph. Ramps from
+    /// `speed_ramp_from` toward the latest status at `speed_slew_per_s`,
+    /// like the belt itself does, instead of jumping on each ~1 Hz status
+    /// update. Everything that reports speed (BLE, debug, distance) goes
+    /// through here so they agree, speed correction included.
+    pub fn displayed_speed_at(&self, now: Instant) -> u32 {
+        let target = self.corrected_speed(self.speed_tenths_mph as u32 * 10);
+        let Some(changed_at) = self.speed_changed_at else {
+            return target;
+        };
+        if self.speed_slew_per_s == 0 {
+            return target;
+        }
+        let elapsed = now.saturating_duration_since(changed_at).as_secs_f64();
+        let max_step = (self.speed_slew_per_s as f64 * elapsed).round() as u32;
+        let from = self.speed_ramp_from;
+        if target > from {
+            (from + max_step).min(target)
+        } else {
+            from.saturating_sub(max_step).max(target)
+        }
+    }
+
+    /// Apply a new speed (status or replay), starting a ramp from whatever
+    /// is currently displayed.
+    pub fn set_speed(&mut self, speed_tenths_mph: u16, now: Instant) {
+        if speed_tenths_mph == self.speed_tenths_mph {
+            return;
+        }
+        self.speed_ramp_from = self.displayed_speed_at(now);
+        self.speed_changed_at = Some(now);
+        self.speed_tenths_mph = speed_tenths_mph;
+    }
+
+    /// Whether the latest status already reflects `target`.
+    pub fn reached(&self, target: Target) -> bool {
+        match target {
+            Target::Speed(t) => self.speed_tenths_mph == t,
+            Target::Incline(h) => self.incline_half_pct == h,
+        }
+    }
+
+    /// Record `target` as the latest command of its kind and start a new
+    /// command generation for it, returning the generation.
+    pub fn begin_command(&mut self, target: Target) -> u32 {
+        let gen = match target {
+            Target::Speed(t) => {
+                self.last_speed_target = Some(t);
+                self.speed_cmd_at = Some(Instant::now());
+                &mut self.speed_cmd_gen
+            }
+            Target::Incline(h) => {
+                self.last_incline_target = Some(h);
+                &mut self.incline_cmd_gen
+            }
+        };
+        *gen = gen.wrapping_add(1);
+        *gen
+    }
+
+    /// Last commanded targets that the current status doesn't reflect.
+    /// A zero speed is never "missing" — a fresh treadmill_io is already stopped.
+    pub fn unapplied_targets(&self) -> Vec<Target> {
+        let speed = self.last_speed_target.filter(|&t| t > 0).map(Target::Speed);
+        let incline = self.last_incline_target.map(Target::Incline);
+        [speed, incline]
+            .into_iter()
+            .flatten()
+            .filter(|&t| !self.reached(t))
+            .collect()
+    }
+
+    /// Whether the incline motor is still moving toward an earlier target.
+    pub fn incline_moving(&self) -> bool {
+        self.incline_moved_at.is_some()
+    }
+
+    /// Note the incline motor at `position` (half-percent units). Any
+    /// change from the last status means it's in motion.
+    pub fn track_incline_motor(&mut self, position: i16, now: Instant) {
+        if self.motor_incline_half_pct.is_some_and(|p| p != position) {
+            self.incline_moved_at = Some(now);
+        }
+        self.motor_incline_half_pct = Some(position);
+    }
+
+    /// Mark the incline motor settled once it has held still for `settle`,
+    /// returning the incline queued meanwhile. One superseded since (a
+    /// stop, or a target it was already at) is dropped.
+    pub fn settle_incline(&mut self, now: Instant, settle: Duration) -> Option<i16> {
+        let moved_at = self.incline_moved_at?;
+        if now.saturating_duration_since(moved_at) < settle {
+            return None;
+        }
+        self.incline_moved_at = None;
+        self.queued_incline.take().filter(|&h| self.last_incline_target == Some(h))
+    }
+
+    fn current_gen(&self, target: Target) -> u32 {
+        match target {
+            Target::Speed(_) => self.speed_cmd_gen,
+            Target::Incline(_) => self.incline_cmd_gen,
+        }
+    }
+}
+
+/// Bookkeeping that persists across reconnects (not local to connect_and_run).
+struct LinkProgress {
+    accumulated_distance_m: f64,
+    accumulated_climb_m: f64,
+    compare_distance_m: f64,
+    workout_start: Option<Instant>,
+    last_update: Instant,
+    /// Successful connections so far; anything after the first is a reconnect.
+    connects: u32,
+    /// Targets came from a session checkpoint; check them on first connect
+    /// like after a reconnect.
+    resumed: bool,
+    last_checkpoint: Instant,
+    /// Samples of the workout under way.
+    workout: Option<Workout>,
+    /// When the belt last stopped during the workout.
+    stopped_since: Option<Instant>,
+    /// When the current console or debug port pause began.
+    paused_at: Option<Instant>,
+    /// Pauses earlier in this workout, left out of elapsed time.
+    paused_total: Duration,
+}
+
+impl LinkProgress {
+    fn new() -> Self {
+        Self {
+            accumulated_distance_m: 0.0,
+            accumulated_climb_m: 0.0,
+            compare_distance_m: 0.0,
+            workout_start: None,
+            last_update: Instant::now(),
+            connects: 0,
+            resumed: false,
+            last_checkpoint: Instant::now(),
+            workout: None,
+            stopped_since: None,
+            paused_at: None,
+            paused_total: Duration::ZERO,
+        }
+    }
+
+    /// Time on the belt at `now`: since the workout started, minus pauses.
+    fn active_elapsed(&self, now: Instant) -> Option<Duration> {
+        let start = self.workout_start?;
+        let paused = self.paused_total + self.paused_at.map_or(Duration::ZERO, |t| now.saturating_duration_since(t));
+        Some(now.saturating_duration_since(start).saturating_sub(paused))
+    }
+}
+
+/// Start a workout, whether or not an app is connected, once the belt goes
+/// faster than `threshold` (tenths of mph). True if one just started.
+fn maybe_start_workout(progress: &mut LinkProgress, speed_tenths_mph: u16, threshold: u16, now: Instant) -> bool {
+    if progress.workout_start.is_some() || speed_tenths_mph == 0 || speed_tenths_mph <= threshold {
+        return false;
+    }
+    progress.workout_start = Some(now);
+    true
+}
+
+/// The targeted distance or training time the workout has just reached,
+/// as the event to broadcast. Both targets are cleared so it fires once.
+fn target_reached(s: &mut TreadmillState) -> Option<TreadmillEvent> {
+    let event = if let Some(meters) = s.distance_target.filter(|&m| s.distance_meters >= m) {
+        TreadmillEvent::DistanceReached(meters)
+    } else if let Some(secs) = s.training_time_target.filter(|&t| s.elapsed_secs >= t) {
+        TreadmillEvent::TrainingTimeReached(secs)
+    } else {
+        return None;
+    };
+    s.distance_target = None;
+    s.training_time_target = None;
+    Some(event)
+}
+
+/// Stop the belt, as an app's Stop would (cool-down included), once a
+/// workout target is reached, then tell the apps.
+fn stop_at_target(ctx: &ControlContext, reached: TreadmillEvent) {
+    match &reached {
+        TreadmillEvent::DistanceReached(meters) => {
+            info!("Targeted distance {} reached, stopping", ctx.config.units.distance(*meters))
+        }
+        TreadmillEvent::TrainingTimeReached(secs) => {
+            info!("Targeted training time {}:{:02} reached, stopping", secs / 60, secs % 60)
+        }
+        _ => {}
+    }
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let stop = ControlCommand::StopOrPause(0x01);
+        let (_, result) = ftms_service::handle_control_command(&stop, &ctx, &Origin::Daemon).await;
+        if result != protocol::RESULT_SUCCESS {
+            error!("Failed to stop at the workout target: {}", protocol::result_name(result));
+        }
+        let _ = ctx.events.send(reached);
+    });
+}
+
+/// Send the incline queued while the motor was moving, now that it has
+/// settled, through the control point like any other target.
+fn apply_queued_incline(ctx: &ControlContext, half_pct: i16) {
+    info!("Incline motor settled, sending queued incline {:.1}%", half_pct as f64 / 2.0);
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let cmd = ControlCommand::SetTargetInclination(half_pct * 5);
+        let (_, result) = ftms_service::handle_control_command(&cmd, &ctx, &Origin::Daemon).await;
+        if result != protocol::RESULT_SUCCESS {
+            warn!("Queued incline {:.1}% failed: {}", half_pct as f64 / 2.0, protocol::result_name(result));
+        }
+    });
+}
+
+/// Pause or resume the session when the console stops or restarts the belt
+/// mid-workout, returning the event to broadcast. `was_moving` is the speed
+/// before the status just applied to `s`. A stop an app commanded within
+/// `app_window` (while its verifier may still be driving it) isn't a pause.
+fn console_transition(
+    s: &mut TreadmillState,
+    progress: &mut LinkProgress,
+    was_moving: bool,
+    emulating: bool,
+    now: Instant,
+    app_window: Duration,
+) -> Option<TreadmillEvent> {
+    if s.console_paused {
+        if s.speed_tenths_mph == 0 {
+            return None;
+        }
+        if let Some(at) = progress.paused_at.take() {
+            progress.paused_total += now.saturating_duration_since(at);
+        }
+        s.console_paused = false;
+        return Some(TreadmillEvent::ConsoleResumed);
+    }
+    // In emulate mode there is no console; a console press switches
+    // treadmill_io to proxy first
+    if emulating || !was_moving || s.speed_tenths_mph > 0 || progress.workout_start.is_none() || s.app_paused.is_some() {
+        return None;
+    }
+    let app_stopped = s.last_speed_target == Some(0)
+        && s.speed_cmd_at.is_some_and(|at| now.saturating_duration_since(at) < app_window);
+    if app_stopped {
+        return None;
+    }
+    progress.paused_at = Some(now);
+    s.console_paused = true;
+    Some(TreadmillEvent::ConsolePaused)
+}
+
+/// Meters covered over `dt_hours` at the smoothed and the reported speed
+/// (mph), as (`source`'s, the other's).
+fn split_distance(source: DistanceSource, smoothed_mph: f64, reported_mph: f64, dt_hours: f64) -> (f64, f64) {
+    let meters = |mph: f64| mph * dt_hours * crate::protocol::METERS_PER_MILE;
+    match source {
+        DistanceSource::Smoothed => (meters(smoothed_mph), meters(reported_mph)),
+        DistanceSource::Reported => (meters(reported_mph), meters(smoothed_mph)),
+    }
+}
+
+/// Freeze elapsed time while a pause from the debug port or an app holds
+/// the belt, and let it run again once the pause ends.
+fn pause_transition(s: &TreadmillState, progress: &mut LinkProgress, now: Instant) {
+    if s.console_paused || progress.workout_start.is_none() {
+        return;
+    }
+    match (s.app_paused.is_some(), progress.paused_at) {
+        (true, None) => progress.paused_at = Some(now),
+        (false, Some(at)) => {
+            progress.paused_total += now.saturating_duration_since(at);
+            progress.paused_at = None;
+        }
+        _ => {}
+    }
+}
+
+/// Restore a recent session checkpoint into `progress` and `state`.
+async fn resume_session(ctx: &ControlContext, progress: &mut LinkProgress) {
+    let Some(path) = ctx.config.session_checkpoint.as_deref() else {
+        return;
+    };
+    let Some(cp) = session::load(path) else {
+        return;
+    };
+    let now_ms = crate::telemetry::wall_ms();
+    let max_age = Duration::from_secs(ctx.config.session_resume_max_age_secs);
+    if !cp.resumable(now_ms, max_age) {
+        info!("Session checkpoint {} is too old to resume", path);
+        return;
+    }
+
+    let elapsed = cp.elapsed_at(now_ms);
+    progress.accumulated_distance_m = cp.distance_m;
+    progress.accumulated_climb_m = cp.elevation_gain_m;
+    progress.compare_distance_m = cp.distance_m;
+    progress.workout_start = Instant::now().checked_sub(elapsed);
+    progress.resumed = cp.last_speed_target.is_some() || cp.last_incline_target.is_some();
+
+    let mut s = ctx.state.lock().await;
+    s.elapsed_secs = elapsed.as_secs().min(u16::MAX as u64) as u16;
+    s.distance_meters = cp.distance_m as u32;
+    s.elevation_gain_m = cp.elevation_gain_m;
+    s.compare_distance_m = cp.distance_m;
+    s.last_speed_target = cp.last_speed_target;
+    s.last_incline_target = cp.last_incline_target;
+    s.workout_label = cp.label;
+    info!(
+        "Resumed session from {}: {}s elapsed, {}m, targets speed={:?} incline={:?}",
+        path, s.elapsed_secs, s.distance_meters, cp.last_speed_target, cp.last_incline_target
+    );
+}
+
+/// Record this second of the workout under way, advance elapsed time
+/// (which treadmill_io only pushes on changes), and end the workout once
+/// the belt has been stopped for `workout_end_idle_secs` (not counting a
+/// pause from the debug port or an app, however long).
+async fn record_workout(ctx: &ControlContext, progress: &mut LinkProgress) {
+    let Some(start) = progress.workout_start else {
+        return;
+    };
+    let now = Instant::now();
+    let now_wall_ms = crate::telemetry::wall_ms();
+    let elapsed = now.duration_since(start);
+    let elapsed_ms = elapsed.as_millis() as u64;
+    let (speed, paused) = {
+        let mut s = ctx.state.lock().await;
+        if s.replaying {
+            return;
+        }
+        pause_transition(&s, progress, now);
+        if let Some(active) = progress.active_elapsed(now) {
+            s.elapsed_secs = active.as_secs() as u16;
+        }
+        // Status only comes on changes; the countdown runs on this tick
+        if let Some(reached) = target_reached(&mut s) {
+            stop_at_target(ctx, reached);
+        }
+        let workout = progress.workout.get_or_insert_with(|| {
+            let start_wall_ms = now_wall_ms.saturating_sub(elapsed_ms);
+            let offset = crate::clock::utc_offset_secs(ctx.config.timezone.as_deref(), start_wall_ms);
+            Workout::new(start_wall_ms, offset)
+        });
+        if let Some(step) = workout.follow_clock(now_wall_ms, elapsed_ms) {
+            warn!("Wall clock stepped {:+}s mid-workout; shifting workout timestamps", step / 1000);
+        }
+        workout.samples.push(Sample {
+            wall_ms: workout.wall_ms_at(elapsed_ms),
+            elapsed_secs: elapsed.as_secs() as u32,
+            speed_tenths_mph: s.corrected_tenths(),
+            incline_half_pct: s.incline_half_pct,
+            distance_m: progress.accumulated_distance_m,
+            heart_rate: s.heart_rate.map(u16::from),
+        });
+        (s.speed_tenths_mph, s.app_paused.is_some())
+    };
+
+    if speed > 0 || paused {
+        progress.stopped_since = None;
+        return;
+    }
+    let stopped_since = *progress.stopped_since.get_or_insert(now);
+    let idle_limit = ctx.config.workout_end_idle_secs;
+    if idle_limit == 0 || now.duration_since(stopped_since) < Duration::from_secs(idle_limit) {
+        return;
+    }
+
+    info!("Belt stopped for {}s, ending workout", idle_limit);
+    let (distance, compare) = (progress.accumulated_distance_m, progress.compare_distance_m);
+    if distance > 0.0 {
+        info!(
+            "Workout distance {:.0} m by {} speed; {} speed gives {:.0} m ({:+.1}%)",
+            distance,
+            ctx.config.distance_source.name(),
+            ctx.config.distance_source.other().name(),
+            compare,
+            (compare - distance) / distance * 100.0
+        );
+    }
+    progress.workout_start = None;
+    progress.stopped_since = None;
+    progress.paused_at = None;
+    progress.paused_total = Duration::ZERO;
+    progress.accumulated_distance_m = 0.0;
+    progress.accumulated_climb_m = 0.0;
+    progress.compare_distance_m = 0.0;
+    let label = {
+        let mut s = ctx.state.lock().await;
+        s.console_paused = false;
+        s.elapsed_secs = 0;
+        s.distance_meters = 0;
+        s.elevation_gain_m = 0.0;
+        s.compare_distance_m = 0.0;
+        ctx.telemetry.state(&s);
+        s.workout_step = None;
+        s.distance_target = None;
+        s.training_time_target = None;
+        s.workout_label.take()
+    };
+    if let Some(path) = ctx.config.session_checkpoint.as_deref() {
+        session::clear(path).await;
+    }
+    if let Some(mut workout) = progress.workout.take() {
+        workout.label = label;
+        workout.profile = ctx.profiles.active(&ctx.config).map(|(name, _)| name);
+        let config = ctx.config.clone();
+        let summary = workout.clone();
+        tokio::spawn(async move {
+            crate::history::record(&summary, &config).await;
+            crate::notify::workout_finished(&summary, config).await
+        });
+        tokio::spawn(crate::export::export(workout, ctx.config.clone(), ctx.archive.clone()));
+    }
+}
+
+/// Write a checkpoint if a workout is under way and one is due.
+async fn maybe_checkpoint(ctx: &ControlContext, progress: &mut LinkProgress) {
+    let Some(path) = ctx.config.session_checkpoint.as_deref() else {
+        return;
+    };
+    if progress.workout_start.is_none() || progress.last_checkpoint.elapsed() < session::CHECKPOINT_INTERVAL {
+        return;
+    }
+    progress.last_checkpoint = Instant::now();
+    let cp = {
+        let s = ctx.state.lock().await;
+        Checkpoint {
+            wall_ms: crate::telemetry::wall_ms(),
+            elapsed_secs: progress.active_elapsed(Instant::now()).map_or(0, |d| d.as_secs()),
+            distance_m: progress.accumulated_distance_m,
+            elevation_gain_m: progress.accumulated_climb_m,
+            speed_tenths_mph: s.speed_tenths_mph,
+            last_speed_target: s.last_speed_target,
+            last_incline_target: s.last_incline_target,
+            label: s.workout_label.clone(),
+        }
+    };
+    if let Err(e) = session::save(path, &cp).await {
+        warn!("Failed to write session checkpoint {}: {}", path, e);
     }
 }
 
 /// Run the treadmill socket client. Connects, reads state, auto-reconnects.
 /// Updates shared state continuously. Runs until cancelled.
-pub async fn run(
-    state: Arc<Mutex<TreadmillState>>,
-    socket_path: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn run(ctx: ControlContext, mut queue: CommandQueue) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let state = &ctx.state;
     let mut backoff = Duration::from_secs(1);
 
-    // Persist distance/elapsed across reconnects (not local to connect_and_run)
-    let mut accumulated_distance_m: f64 = 0.0;
-    let mut workout_start: Option<Instant> = None;
-    let mut last_update = Instant::now();
+    let mut progress = LinkProgress::new();
+    resume_session(&ctx, &mut progress).await;
 
     loop {
-        let was_connected;
-        match connect_and_run(&state, socket_path, &mut accumulated_distance_m, &mut workout_start, &mut last_update).await {
-            Ok(()) => {
-                info!("Treadmill connection closed cleanly");
-                was_connected = state.lock().await.connected;
-            }
+        match connect_and_run(&ctx, &mut progress, &mut queue).await {
+            Ok(()) => info!("Treadmill connection closed cleanly"),
             Err(e) => {
                 warn!("Treadmill connection error: {}", e);
-                was_connected = state.lock().await.connected;
+                ctx.health.count(Counter::Errors);
             }
         }
+        let was_connected = state.lock().await.connected;
 
         // Mark disconnected
         {
             let mut s = state.lock().await;
-            s.connected = false;
+            s.protocol_version = None;
+            s.caps = None;
+            // A queued incline is still the last target; restore_targets
+            // deals with it after the reconnect like any other
+            s.incline_moved_at = None;
+            s.queued_incline = None;
+            if s.connected {
+                s.connected = false;
+                ctx.telemetry.state(&s);
+            }
         }
 
         // Reset backoff if we had a successful connection (fast retry on transient drops)
@@ -77,25 +704,28 @@ pub async fn run(
         }
 
         info!("Reconnecting to treadmill_io in {:?}...", backoff);
-        tokio::time::sleep(backoff).await;
+        refuse_commands(&mut queue, backoff).await;
         backoff = (backoff * 2).min(Duration::from_secs(10));
     }
 }
 
-/// Connect to the socket and run the read/heartbeat loop until disconnection.
+/// Connect to the socket and run the read/heartbeat loop, writing queued
+/// commands as they come, until disconnection.
 /// Distance/elapsed state is passed in from the caller so it persists across reconnects.
 async fn connect_and_run(
-    state: &Arc<Mutex<TreadmillState>>,
-    socket_path: &str,
-    accumulated_distance_m: &mut f64,
-    workout_start: &mut Option<Instant>,
-    last_update: &mut Instant,
+    ctx: &ControlContext,
+    progress: &mut LinkProgress,
+    queue: &mut CommandQueue,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let stream = UnixStream::connect(socket_path).await?;
-    let (reader, mut writer) = stream.into_split();
+    let state = &ctx.state;
+    let socket_path = ctx.socket_path.as_str();
+    let (reader, mut writer) = tokio::io::split(connect(socket_path).await?);
     let mut lines = BufReader::new(reader).lines();
 
-    // Request initial status dump
+    // Announce our protocol version, then request the initial status dump.
+    // treadmill_io answers in order, so hello comes back first if it's understood.
+    let hello = format!("{{\"cmd\":\"hello\",\"version\":{}}}\n", status::PROTOCOL_VERSION);
+    writer.write_all(hello.as_bytes()).await?;
     writer
         .write_all(b"{\"cmd\":\"status\"}\n")
         .await?;
@@ -106,96 +736,176 @@ async fn connect_and_run(
     {
         let mut s = state.lock().await;
         s.connected = true;
+        ctx.telemetry.state(&s);
+    }
+
+    if progress.connects > 0 {
+        ctx.health.count(Counter::Reconnects);
+    }
+    if progress.connects > 0 || std::mem::take(&mut progress.resumed) {
+        tokio::spawn(restore_targets(ctx.clone()));
     }
+    progress.connects += 1;
 
     // Reset last_update to now so reconnect gap doesn't inflate distance
-    *last_update = Instant::now();
+    progress.last_update = Instant::now();
 
     let mut heartbeat = interval(Duration::from_secs(1));
     // First tick fires immediately — skip it since we just sent status
     heartbeat.tick().await;
 
+    // treadmill_io only pushes status on changes, so liveness is judged on any
+    // line (kv traffic included). When the link goes quiet we probe with a
+    // status request; if even that gets no answer the socket is dead.
+    let silence_limit = Duration::from_millis(ctx.config.status_timeout_ms);
+    let mut last_message = Instant::now();
+    let incline_settle = Duration::from_millis(ctx.config.incline_settle_ms);
+    let mut pending = Pending::default();
+
     loop {
         tokio::select! {
             line_result = lines.next_line() => {
                 match line_result {
                     Ok(Some(line)) => {
                         let now = Instant::now();
-                        let dt_hours = now.duration_since(*last_update).as_secs_f64() / 3600.0;
-                        *last_update = now;
-
-                        if let Ok(msg) = serde_json::from_str::<serde_json::Value>(&line) {
-                            let msg_type = msg.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                            match msg_type {
-                                "status" => {
-                                    let emu_speed = msg.get("emu_speed")
-                                        .and_then(|v| v.as_u64())
-                                        .unwrap_or(0) as u16;
-                                    let emu_incline = msg.get("emu_incline")
-                                        .and_then(|v| v.as_u64())
-                                        .unwrap_or(0) as u16;
-                                    let bus_speed = msg.get("bus_speed")
-                                        .and_then(|v| v.as_i64())
-                                        .unwrap_or(-1);
-                                    let bus_incline = msg.get("bus_incline")
-                                        .and_then(|v| v.as_i64())
-                                        .unwrap_or(-1);
-                                    let is_emulating = msg.get("emulate")
-                                        .and_then(|v| v.as_bool())
-                                        .unwrap_or(false);
-
-                                    // Effective values: emulate mode uses emu_*, proxy uses bus_*
-                                    let effective_speed = if is_emulating {
-                                        emu_speed
-                                    } else if bus_speed >= 0 {
-                                        bus_speed as u16
-                                    } else {
-                                        0
-                                    };
-                                    let effective_incline = if is_emulating {
-                                        emu_incline
-                                    } else if bus_incline >= 0 {
-                                        bus_incline as u16
-                                    } else {
-                                        0
-                                    };
-
-                                    // Accumulate distance based on previous speed
-                                    let mut s = state.lock().await;
-                                    let prev_speed_mph = s.speed_tenths_mph as f64 / 10.0;
-                                    *accumulated_distance_m += prev_speed_mph * dt_hours * 1609.34;
-
-                                    // Track elapsed time
-                                    if effective_speed > 0 {
-                                        if workout_start.is_none() {
-                                            *workout_start = Some(now);
-                                        }
-                                    }
-
-                                    s.speed_tenths_mph = effective_speed;
-                                    s.incline_half_pct = effective_incline;
-                                    s.distance_meters = *accumulated_distance_m as u32;
-                                    if let Some(start) = *workout_start {
-                                        s.elapsed_secs = now.duration_since(start).as_secs() as u16;
-                                    }
-
-                                    debug!(
-                                        "Status: speed={:.1} mph, incline={:.1}%, emulating={}",
-                                        effective_speed as f64 / 10.0,
-                                        effective_incline as f64 / 2.0,
-                                        is_emulating
+                        last_message = now;
+                        ctx.health.touch("treadmill_io");
+                        let prev_update = progress.last_update;
+                        let dt_hours = now.duration_since(prev_update).as_secs_f64() / 3600.0;
+                        progress.last_update = now;
+
+                        match status::parse_line(&line) {
+                            Ok(Message::Status(msg)) => {
+                                let StatusReading {
+                                    speed_tenths_mph: effective_speed,
+                                    incline_half_pct: effective_incline,
+                                    emulating: is_emulating,
+                                } = msg.reading();
+                                let motor_incline = msg.motor_incline().unwrap_or(effective_incline);
+
+                                // Accumulate distance at the (ramped) speed shown since the last update
+                                let mut s = state.lock().await;
+                                if s.protocol_version.is_none() {
+                                    warn!(
+                                        "treadmill_io predates the version handshake (protocol v0, this daemon speaks v{}); without {}",
+                                        status::PROTOCOL_VERSION,
+                                        status::lacking(0).join(", ")
                                     );
+                                    s.protocol_version = Some(0);
                                 }
-                                "kv" => {
-                                    // KV messages from the serial bus — mostly informational.
-                                    // We could parse hmph as fallback speed, but emu_speed
-                                    // from status messages is authoritative.
-                                    debug!("KV: {:?}", msg);
+                                if s.replaying {
+                                    continue;
                                 }
-                                _ => {
-                                    debug!("Unknown message type: {}", msg_type);
+                                // Track elapsed time
+                                let threshold = ctx.config.workout_start_tenths_mph();
+                                if maybe_start_workout(progress, effective_speed, threshold, now) {
+                                    info!("Belt at {}, starting workout", ctx.config.units.speed_tenths(effective_speed));
+                                }
+
+                                // Distance only counts toward a workout under way,
+                                // and not while a pause winds the belt down
+                                if progress.workout_start.is_some() && !s.is_paused() {
+                                    let smoothed_mph = (s.displayed_speed_at(prev_update) + s.displayed_speed_at(now))
+                                        as f64 / 200.0;
+                                    let reported_mph = s.corrected_speed(s.speed_tenths_mph as u32 * 10) as f64 / 100.0;
+                                    let (covered_m, compare_m) =
+                                        split_distance(ctx.config.distance_source, smoothed_mph, reported_mph, dt_hours);
+                                    progress.accumulated_distance_m += covered_m;
+                                    progress.compare_distance_m += compare_m;
+                                    // At the grade the belt was at over that stretch
+                                    progress.accumulated_climb_m += covered_m * s.incline_half_pct.max(0) as f64 / 200.0;
                                 }
+
+                                let before = StateSample::from(&*s);
+                                let was_moving = s.speed_tenths_mph > 0;
+                                s.set_speed(effective_speed, now);
+                                s.incline_half_pct = effective_incline;
+                                if incline_settle > Duration::ZERO {
+                                    s.track_incline_motor(motor_incline, now);
+                                }
+                                if let Some(queued) = s.settle_incline(now, incline_settle) {
+                                    apply_queued_incline(ctx, queued);
+                                }
+                                s.distance_meters = progress.accumulated_distance_m as u32;
+                                s.elevation_gain_m = progress.accumulated_climb_m;
+                                s.compare_distance_m = progress.compare_distance_m;
+                                let app_window = Duration::from_millis(
+                                    ctx.config.target_verify_timeout_ms * (ctx.config.target_retries as u64 + 1),
+                                );
+                                if let Some(event) = console_transition(&mut s, progress, was_moving, is_emulating, now, app_window) {
+                                    info!("Console {} the belt", if s.console_paused { "paused" } else { "resumed" });
+                                    let _ = ctx.events.send(event);
+                                }
+                                pause_transition(&s, progress, now);
+                                if let Some(active) = progress.active_elapsed(now) {
+                                    s.elapsed_secs = active.as_secs() as u16;
+                                }
+                                if let Some(reached) = target_reached(&mut s) {
+                                    stop_at_target(ctx, reached);
+                                }
+                                if StateSample::from(&*s) != before {
+                                    ctx.telemetry.state(&s);
+                                }
+
+                                debug!(
+                                    "Status: speed={}, incline={:.1}%, emulating={}",
+                                    ctx.config.units.speed_tenths(effective_speed),
+                                    effective_incline as f64 / 2.0,
+                                    is_emulating
+                                );
+                            }
+                            Ok(Message::Kv(kv)) => {
+                                // KV messages from the serial bus — mostly informational.
+                                // We could parse hmph as fallback speed, but emu_speed
+                                // from status messages is authoritative.
+                                debug!("KV: {} {}={}", kv.source, kv.key, kv.value);
+                            }
+                            Ok(Message::Ack(ack)) => {
+                                pending.answer(ack.id, Ok(()));
+                            }
+                            Ok(Message::Error(err)) => match err.id {
+                                Some(id) => {
+                                    warn!("treadmill_io refused command {}: {}", id, err.msg);
+                                    pending.answer(id, Err(CommandError::Refused(err.msg)));
+                                }
+                                None => warn!("treadmill_io error: {}", err.msg),
+                            },
+                            Ok(Message::Hello(hello)) => {
+                                match hello.version.cmp(&status::PROTOCOL_VERSION) {
+                                    std::cmp::Ordering::Equal => info!("treadmill_io speaks protocol v{}", hello.version),
+                                    std::cmp::Ordering::Less => warn!(
+                                        "treadmill_io speaks protocol v{}, older than this daemon's v{}; without {}",
+                                        hello.version,
+                                        status::PROTOCOL_VERSION,
+                                        status::lacking(hello.version).join(", ")
+                                    ),
+                                    std::cmp::Ordering::Greater => warn!(
+                                        "treadmill_io speaks protocol v{}, newer than this daemon's v{}; its newer features go unused",
+                                        hello.version,
+                                        status::PROTOCOL_VERSION
+                                    ),
+                                }
+                                state.lock().await.protocol_version = Some(hello.version);
+                                if hello.version >= status::CAPS_VERSION {
+                                    writer.write_all(b"{\"cmd\":\"caps\"}\n").await?;
+                                }
+                            }
+                            Ok(Message::Caps(caps)) => {
+                                let mut s = state.lock().await;
+                                s.caps = Some(caps);
+                                let limits = s.limits();
+                                info!(
+                                    "treadmill_io limits: {}, incline {:.1}-{:.1}%",
+                                    ctx.config.units.speed_tenths(limits.max_speed_tenths_mph),
+                                    limits.min_incline_half_pct as f64 / 2.0,
+                                    limits.max_incline_half_pct as f64 / 2.0
+                                );
+                            }
+                            Ok(Message::Unknown) => {
+                                debug!("Unknown message type: {}", line);
+                            }
+                            Err(e) => {
+                                debug!("Malformed line from treadmill_io ({}): {}", e, line);
                             }
                         }
                     }
@@ -209,61 +919,764 @@ async fn connect_and_run(
                     }
                 }
             }
+            Some(command) = queue.recv() => {
+                let acked = state.lock().await.protocol_version.is_some_and(|v| v >= status::ACK_VERSION);
+                let id = acked.then(|| pending.next_id());
+                let line = match id {
+                    Some(id) => command.tagged(id),
+                    None => command.line.clone(),
+                };
+                if let Err(e) = writer.write_all(line.as_bytes()).await {
+                    command.complete(Err(CommandError::Write(e.to_string())));
+                    return Err(format!("command write failed: {}", e).into());
+                }
+                match id {
+                    Some(id) => pending.wait(id, command),
+                    None => command.complete(Ok(())),
+                }
+            }
             _ = heartbeat.tick() => {
-                if let Err(e) = writer.write_all(b"{\"cmd\":\"heartbeat\"}\n").await {
-                    return Err(e.into());
+                // treadmill_io goes quiet once the motor stops, so settling
+                // is noticed here as well as on status
+                if let Some(queued) = state.lock().await.settle_incline(Instant::now(), incline_settle) {
+                    apply_queued_incline(ctx, queued);
                 }
+                record_workout(ctx, progress).await;
+                maybe_checkpoint(ctx, progress).await;
+                pending.expire(COMMAND_TIMEOUT);
+                let silent_for = last_message.elapsed();
+                let probe = match liveness(silent_for, silence_limit) {
+                    Liveness::Dead => {
+                        return Err(format!("no messages from treadmill_io for {:?}", silent_for).into());
+                    }
+                    Liveness::Quiet => b"{\"cmd\":\"status\"}\n".as_slice(),
+                    Liveness::Alive => b"{\"cmd\":\"heartbeat\"}\n".as_slice(),
+                };
+                // Every command doubles as a heartbeat for treadmill_io's watchdog
+                writer.write_all(probe).await?;
+            }
+        }
+    }
+}
+
+/// How the treadmill_io link looks based on time since its last message.
+#[derive(Debug, PartialEq)]
+enum Liveness {
+    Alive,
+    /// Silent for over half the limit — ask for a status dump to provoke a reply.
+    Quiet,
+    /// Silent past the limit — give up on this connection.
+    Dead,
+}
+
+/// Classify link silence against `limit` (zero disables detection).
+fn liveness(silent_for: Duration, limit: Duration) -> Liveness {
+    if limit.is_zero() {
+        Liveness::Alive
+    } else if silent_for >= limit {
+        Liveness::Dead
+    } else if silent_for >= limit / 2 {
+        Liveness::Quiet
+    } else {
+        Liveness::Alive
+    }
+}
+
+/// How long a command may wait to be written and, from protocol v3, answered.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Queues command lines for the treadmill_io connection. Cheap to clone;
+/// every clone feeds the same connection.
+#[derive(Clone)]
+pub struct Commands(mpsc::Sender<Command>);
+
+/// The connection task's end of [`Commands`].
+pub type CommandQueue = mpsc::Receiver<Command>;
+
+/// One command line, and where to report whether it was sent.
+pub struct Command {
+    pub line: String,
+    done: oneshot::Sender<Result<(), CommandError>>,
+}
+
+impl Command {
+    /// Tell the sender how its command went.
+    pub fn complete(self, result: Result<(), CommandError>) {
+        let _ = self.done.send(result);
+    }
+
+    /// The line with `"id":N` added, for treadmill_io to answer by.
+    fn tagged(&self, id: u32) -> String {
+        let body = self.line.trim_end().trim_end_matches('}');
+        format!("{},\"id\":{}}}\n", body, id)
+    }
+}
+
+/// Commands written with an id, waiting for treadmill_io's `ack` or `error`.
+/// Ids count up per connection; whatever is unanswered when the connection
+/// ends is failed then.
+#[derive(Default)]
+struct Pending {
+    next: u32,
+    waiting: HashMap<u32, (Instant, Command)>,
+}
+
+impl Pending {
+    fn next_id(&mut self) -> u32 {
+        self.next = self.next.wrapping_add(1);
+        self.next
+    }
+
+    fn wait(&mut self, id: u32, command: Command) {
+        self.waiting.insert(id, (Instant::now(), command));
+    }
+
+    /// Hand treadmill_io's answer for `id` to whoever sent it.
+    fn answer(&mut self, id: u32, result: Result<(), CommandError>) {
+        match self.waiting.remove(&id) {
+            Some((_, command)) => command.complete(result),
+            None => debug!("Answer for command {} nobody is waiting on", id),
+        }
+    }
+
+    /// Forget commands older than `limit`; their senders have timed out.
+    fn expire(&mut self, limit: Duration) {
+        self.waiting.retain(|id, (sent, command)| {
+            let live = sent.elapsed() < limit;
+            if !live {
+                warn!("treadmill_io never answered command {}: {}", id, command.line.trim_end());
+            }
+            live
+        });
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        for (_, (_, command)) in self.waiting.drain() {
+            command.complete(Err(CommandError::Unanswered));
+        }
+    }
+}
+
+/// Why a command didn't reach treadmill_io.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// The link is down; the daemon is between reconnects.
+    NotConnected,
+    /// Writing it to the socket failed (the link is being dropped).
+    Write(String),
+    /// The connection task didn't get to it within `COMMAND_TIMEOUT`.
+    Timeout,
+    /// The connection task isn't running.
+    Closed,
+    /// treadmill_io answered with an `error` naming it.
+    Refused(String),
+    /// The link dropped before treadmill_io answered.
+    Unanswered,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::NotConnected => write!(f, "treadmill_io not connected"),
+            CommandError::Write(e) => write!(f, "write to treadmill_io failed: {}", e),
+            CommandError::Timeout => write!(f, "treadmill_io link didn't take the command within {:?}", COMMAND_TIMEOUT),
+            CommandError::Closed => write!(f, "treadmill_io link task not running"),
+            CommandError::Refused(msg) => write!(f, "treadmill_io refused it: {}", msg),
+            CommandError::Unanswered => write!(f, "treadmill_io link dropped before it answered"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Commands {
+    /// A command handle and the queue `run` drains into the connection.
+    pub fn channel() -> (Self, CommandQueue) {
+        let (tx, rx) = mpsc::channel(32);
+        (Self(tx), rx)
+    }
+
+    /// Queue `line` and wait for treadmill_io to take it. From protocol v3
+    /// `Ok` means treadmill_io acked the command and a refusal comes back
+    /// as [`CommandError::Refused`]; older builds don't answer commands, so
+    /// there `Ok` only means written. Whether the belt then reaches a target
+    /// is still left to status-based verification (`verify_target`).
+    async fn send(&self, line: &str) -> Result<(), CommandError> {
+        let (done, ack) = oneshot::channel();
+        let command = Command { line: line.to_string(), done };
+        tokio::time::timeout(COMMAND_TIMEOUT, async {
+            self.0.send(command).await.map_err(|_| CommandError::Closed)?;
+            ack.await.map_err(|_| CommandError::Closed)?
+        })
+        .await
+        .unwrap_or(Err(CommandError::Timeout))
+    }
+}
+
+/// Refuse whatever is queued for `wait` while there's no connection.
+async fn refuse_commands(queue: &mut CommandQueue, wait: Duration) {
+    let deadline = tokio::time::sleep(wait);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return,
+            Some(command) = queue.recv() => {
+                debug!("Refusing {} while disconnected", command.line.trim_end());
+                command.complete(Err(CommandError::NotConnected));
             }
         }
     }
 }
 
 /// Send a speed command to treadmill_io (mph float).
-/// Opens a short-lived connection, sends the command, and closes.
 pub async fn send_speed(
-    socket_path: &str,
+    commands: &Commands,
     mph: f64,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cmd = format!("{{\"cmd\":\"speed\",\"value\":{:.1}}}\n", mph);
-    send_oneshot(socket_path, &cmd).await
+    Ok(commands.send(&cmd).await?)
 }
 
 /// Send an incline command to treadmill_io (float percent, 0.5 resolution).
-/// Opens a short-lived connection, sends the command, and closes.
 pub async fn send_incline(
-    socket_path: &str,
+    commands: &Commands,
     incline: f64,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cmd = format!("{{\"cmd\":\"incline\",\"value\":{:.1}}}\n", incline);
-    send_oneshot(socket_path, &cmd).await
+    Ok(commands.send(&cmd).await?)
 }
 
 /// Send start (emulate mode) command.
 pub async fn send_start(
-    socket_path: &str,
+    commands: &Commands,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    send_oneshot(socket_path, "{\"cmd\":\"emulate\",\"enabled\":true}\n").await
+    Ok(commands.send("{\"cmd\":\"emulate\",\"enabled\":true}\n").await?)
 }
 
 /// Send stop command (speed 0, incline 0).
 pub async fn send_stop(
-    socket_path: &str,
+    commands: &Commands,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Set speed to 0 first, then incline
-    send_oneshot(socket_path, "{\"cmd\":\"speed\",\"value\":0.0}\n").await?;
-    send_oneshot(socket_path, "{\"cmd\":\"incline\",\"value\":0.0}\n").await
+    commands.send("{\"cmd\":\"speed\",\"value\":0.0}\n").await?;
+    Ok(commands.send("{\"cmd\":\"incline\",\"value\":0.0}\n").await?)
 }
 
-/// Open a short-lived connection, send one command line, then close.
-async fn send_oneshot(
-    socket_path: &str,
-    cmd: &str,
+/// Send the command for a single target.
+async fn send_target(
+    commands: &Commands,
+    target: Target,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut stream = UnixStream::connect(socket_path).await.map_err(|e| {
-        error!("Failed to connect to treadmill_io at {}: {}", socket_path, e);
-        e
-    })?;
-    stream.write_all(cmd.as_bytes()).await?;
-    stream.shutdown().await?;
-    Ok(())
+    match target {
+        Target::Speed(t) => send_speed(commands, t as f64 / 10.0).await,
+        Target::Incline(h) => send_incline(commands, h as f64 / 2.0).await,
+    }
+}
+
+/// Watch status updates until `target` is reflected, re-sending it up to
+/// `retries` times when it doesn't converge within `timeout`. An incline's
+/// wait starts over while the incline motor is still moving.
+///
+/// Call after the initial command has been sent, with the generation
+/// [`TreadmillState::begin_command`] gave it before sending. A newer command
+/// of the same kind supersedes this verifier, so it never re-sends a stale
+/// value. If the target still hasn't converged after the last retry, a
+/// [`TreadmillEvent::TargetFailed`] is broadcast. Returns whether the
+/// target was confirmed.
+pub async fn verify_target(
+    state: Arc<Mutex<TreadmillState>>,
+    commands: Commands,
+    events: broadcast::Sender<TreadmillEvent>,
+    target: Target,
+    gen: u32,
+    timeout: Duration,
+    retries: u32,
+) -> bool {
+    let poll = Duration::from_millis(100);
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            warn!("{} not applied after {:?}, re-sending (retry {}/{})", target, timeout, attempt, retries);
+            if let Err(e) = send_target(&commands, target).await {
+                warn!("Retry of {} failed to send: {}", target, e);
+            }
+        }
+
+        let mut deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            tokio::time::sleep(poll).await;
+            let s = state.lock().await;
+            if s.current_gen(target) != gen {
+                debug!("{} superseded by a newer command", target);
+                return false;
+            }
+            if s.reached(target) {
+                debug!("{} confirmed by treadmill_io", target);
+                return true;
+            }
+            // The incline motor takes seconds per percent; don't re-send
+            // while it's still getting there
+            if matches!(target, Target::Incline(_)) && s.incline_moving() {
+                deadline = Instant::now() + timeout;
+            }
+        }
+    }
+
+    error!("{} not applied after {} attempts, giving up", target, retries + 1);
+    let _ = events.send(TreadmillEvent::TargetFailed { target, attempts: retries + 1 });
+    false
+}
+
+/// After a reconnect, bring back whatever targets treadmill_io forgot.
+///
+/// Waits briefly for the first status dump so targets that survived the drop
+/// (treadmill_io kept running, only the socket blipped) are left alone. The
+/// rest are re-sent and verified when `reapply_targets_on_reconnect` is set;
+/// otherwise they're cleared and [`TreadmillEvent::TargetsLost`] is broadcast
+/// so the app re-sends them itself.
+async fn restore_targets(ctx: ControlContext) {
+    let ControlContext { state, commands, config, events, .. } = ctx;
+    tokio::time::sleep(Duration::from_millis(config.reconnect_settle_ms)).await;
+
+    let missing = {
+        let mut s = state.lock().await;
+        let missing = s.unapplied_targets();
+        if !missing.is_empty() && !config.reapply_targets_on_reconnect {
+            s.last_speed_target = None;
+            s.last_incline_target = None;
+        }
+        missing
+    };
+    if missing.is_empty() {
+        return;
+    }
+
+    if !config.reapply_targets_on_reconnect {
+        warn!("treadmill_io reconnected without our targets; notifying clients");
+        let _ = events.send(TreadmillEvent::TargetsLost);
+        return;
+    }
+
+    for target in missing {
+        info!("Re-applying {} after treadmill_io reconnect", target);
+        let gen = state.lock().await.begin_command(target);
+        if let Err(e) = send_target(&commands, target).await {
+            warn!("Failed to re-apply {}: {}", target, e);
+        }
+        tokio::spawn(verify_target(
+            state.clone(),
+            commands.clone(),
+            events.clone(),
+            target,
+            gen,
+            Duration::from_millis(config.target_verify_timeout_ms),
+            config.target_retries,
+        ));
+    }
+}
+
+/// A byte stream to treadmill_io, whichever transport carries it.
+trait Link: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Link for T {}
+
+/// Connect to treadmill_io at `addr`: a Unix socket path, `tcp:HOST:PORT`,
+/// or `mock:` for the built-in simulator.
+async fn connect(addr: &str) -> std::io::Result<Box<dyn Link>> {
+    if addr == crate::mock::ADDR {
+        return Ok(Box::new(crate::mock::connect()));
+    }
+    match addr.strip_prefix("tcp:") {
+        Some(host_port) => {
+            let stream = TcpStream::connect(host_port).await?;
+            // Commands are single short lines; don't hold them back
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        None => Ok(Box::new(UnixStream::connect(addr).await?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FtmsConfig;
+    use crate::ftms_service::test_context;
+
+    fn shared(state: TreadmillState) -> Arc<Mutex<TreadmillState>> {
+        Arc::new(Mutex::new(state))
+    }
+
+    #[test]
+    fn test_reached() {
+        let s = TreadmillState { speed_tenths_mph: 35, incline_half_pct: 4, ..Default::default() };
+        assert!(s.reached(Target::Speed(35)));
+        assert!(!s.reached(Target::Speed(36)));
+        assert!(s.reached(Target::Incline(4)));
+        assert!(!s.reached(Target::Incline(0)));
+    }
+
+    #[test]
+    fn test_unapplied_targets() {
+        let mut s = TreadmillState::default();
+        assert!(s.unapplied_targets().is_empty());
+
+        s.begin_command(Target::Speed(50));
+        s.begin_command(Target::Incline(6));
+        assert_eq!(s.unapplied_targets(), vec![Target::Speed(50), Target::Incline(6)]);
+
+        s.speed_tenths_mph = 50;
+        assert_eq!(s.unapplied_targets(), vec![Target::Incline(6)]);
+
+        // A stopped belt after a stop command is not a lost target
+        s.begin_command(Target::Speed(0));
+        s.speed_tenths_mph = 0;
+        s.incline_half_pct = 6;
+        assert!(s.unapplied_targets().is_empty());
+    }
+
+    #[test]
+    fn test_speed_smoothing() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let mut s = TreadmillState { speed_slew_per_s: 100, ..Default::default() }; // 1 mph/s
+        s.set_speed(30, t0);
+        assert_eq!(s.displayed_speed_at(t0), 0);
+        assert_eq!(s.displayed_speed_at(t0 + ms(1500)), 150);
+        assert_eq!(s.displayed_speed_at(t0 + ms(3000)), 300);
+        assert_eq!(s.displayed_speed_at(t0 + ms(9000)), 300, "never overshoots");
+
+        // A change mid-ramp starts from what's on screen, not the old status
+        let t1 = t0 + ms(1000);
+        s.set_speed(0, t1);
+        assert_eq!(s.displayed_speed_at(t1), 100);
+        assert_eq!(s.displayed_speed_at(t1 + ms(500)), 50);
+        assert_eq!(s.displayed_speed_at(t1 + ms(5000)), 0, "never undershoots");
+
+        // No change, no new ramp
+        let t2 = t1 + ms(5000);
+        s.set_speed(0, t2);
+        assert_eq!(s.displayed_speed_at(t2), 0);
+    }
+
+    #[test]
+    fn test_speed_smoothing_disabled() {
+        let t0 = Instant::now();
+        let mut s = TreadmillState::default();
+        s.set_speed(30, t0);
+        assert_eq!(s.displayed_speed_at(t0), 300);
+    }
+
+    #[test]
+    fn test_liveness() {
+        let limit = Duration::from_secs(5);
+        assert_eq!(liveness(Duration::from_secs(1), limit), Liveness::Alive);
+        assert_eq!(liveness(Duration::from_millis(2500), limit), Liveness::Quiet);
+        assert_eq!(liveness(Duration::from_secs(5), limit), Liveness::Dead);
+        assert_eq!(liveness(Duration::from_secs(60), Duration::ZERO), Liveness::Alive);
+    }
+
+    #[tokio::test]
+    async fn test_restore_targets_lost_event() {
+        let mut s = TreadmillState::default();
+        s.begin_command(Target::Speed(40));
+        let state = shared(s);
+        let (events, mut rx) = broadcast::channel(4);
+        let mut ctx = test_context(FtmsConfig { reconnect_settle_ms: 0, ..Default::default() }, events);
+        ctx.state = state.clone();
+        restore_targets(ctx).await;
+        assert_eq!(rx.try_recv().unwrap(), TreadmillEvent::TargetsLost);
+        assert_eq!(state.lock().await.last_speed_target, None);
+    }
+
+    #[test]
+    fn test_console_pause_and_resume() {
+        let window = Duration::from_secs(9);
+        let t0 = Instant::now();
+        let mut progress = LinkProgress::new();
+        progress.workout_start = Some(t0);
+        let mut s = TreadmillState::default();
+
+        // Console stops the belt 60s in: pause, elapsed freezes
+        let paused = t0 + Duration::from_secs(60);
+        assert_eq!(console_transition(&mut s, &mut progress, true, false, paused, window), Some(TreadmillEvent::ConsolePaused));
+        assert!(s.console_paused);
+        assert_eq!(progress.active_elapsed(paused + Duration::from_secs(30)), Some(Duration::from_secs(60)));
+        assert_eq!(console_transition(&mut s, &mut progress, false, false, paused + Duration::from_secs(1), window), None);
+
+        // Belt moving again 30s later: resume, the pause stays out of elapsed
+        s.speed_tenths_mph = 30;
+        let resumed = paused + Duration::from_secs(30);
+        assert_eq!(console_transition(&mut s, &mut progress, false, false, resumed, window), Some(TreadmillEvent::ConsoleResumed));
+        assert!(!s.console_paused);
+        assert_eq!(progress.active_elapsed(resumed + Duration::from_secs(10)), Some(Duration::from_secs(70)));
+    }
+
+    #[test]
+    fn test_incline_motor_settles() {
+        let settle = Duration::from_secs(2);
+        let t0 = Instant::now();
+        let mut s = TreadmillState::default();
+
+        // The first position is only a reference; a change is motion
+        s.track_incline_motor(0, t0);
+        assert!(!s.incline_moving());
+        s.track_incline_motor(1, t0 + Duration::from_secs(1));
+        assert!(s.incline_moving());
+
+        // A target queued meanwhile comes back once the motor holds still
+        s.begin_command(Target::Incline(8));
+        s.queued_incline = Some(8);
+        s.track_incline_motor(1, t0 + Duration::from_secs(2));
+        assert_eq!(s.settle_incline(t0 + Duration::from_millis(2500), settle), None);
+        assert!(s.incline_moving());
+        assert_eq!(s.settle_incline(t0 + Duration::from_secs(3), settle), Some(8));
+        assert!(!s.incline_moving() && s.queued_incline.is_none());
+        assert_eq!(s.settle_incline(t0 + Duration::from_secs(9), settle), None);
+
+        // One superseded by a later command (a stop) is dropped
+        s.track_incline_motor(2, t0 + Duration::from_secs(10));
+        s.queued_incline = Some(8);
+        s.begin_command(Target::Incline(0));
+        assert_eq!(s.settle_incline(t0 + Duration::from_secs(12), settle), None);
+        assert!(!s.incline_moving() && s.queued_incline.is_none());
+    }
+
+    #[test]
+    fn test_split_distance() {
+        // A second at 6.0 mph reported while the shown speed still ramps up at 5.0
+        let dt_hours = 1.0 / 3600.0;
+        let (smoothed, reported) = split_distance(DistanceSource::Smoothed, 5.0, 6.0, dt_hours);
+        assert!((smoothed - 2.2352).abs() < 1e-4 && (reported - 2.68224).abs() < 1e-4);
+        assert_eq!(split_distance(DistanceSource::Reported, 5.0, 6.0, dt_hours), (reported, smoothed));
+    }
+
+    #[test]
+    fn test_debug_pause_freezes_elapsed() {
+        let window = Duration::from_secs(9);
+        let t0 = Instant::now();
+        let mut progress = LinkProgress { workout_start: Some(t0), ..LinkProgress::new() };
+        let mut s = TreadmillState { app_paused: Some(50), ..Default::default() };
+
+        // Paused 60s in; the belt stopping long after isn't the console
+        let paused = t0 + Duration::from_secs(60);
+        pause_transition(&s, &mut progress, paused);
+        assert_eq!(console_transition(&mut s, &mut progress, true, false, paused + window, window), None);
+        assert_eq!(progress.active_elapsed(paused + Duration::from_secs(45)), Some(Duration::from_secs(60)));
+
+        // Apps see a stopped belt next to the frozen elapsed time and distance
+        let winding_down = TreadmillState { speed_tenths_mph: 30, elapsed_secs: 60, distance_meters: 250, ..s.clone() };
+        let stopped = TreadmillState { speed_tenths_mph: 0, app_paused: None, ..winding_down.clone() };
+        assert_eq!(winding_down.encode_ftms_data_at(paused), stopped.encode_ftms_data_at(paused));
+
+        // Resumed 45s later: the pause stays out of elapsed
+        s.app_paused = None;
+        pause_transition(&s, &mut progress, paused + Duration::from_secs(45));
+        assert_eq!(progress.active_elapsed(paused + Duration::from_secs(55)), Some(Duration::from_secs(70)));
+    }
+
+    #[test]
+    fn test_workout_start_threshold() {
+        let now = Instant::now();
+        let mut progress = LinkProgress::new();
+        assert!(!maybe_start_workout(&mut progress, 0, 0, now));
+        // A belt creeping along under the threshold isn't a workout
+        assert!(!maybe_start_workout(&mut progress, 8, 10, now));
+        assert!(!maybe_start_workout(&mut progress, 10, 10, now));
+        assert!(maybe_start_workout(&mut progress, 11, 10, now));
+        assert_eq!(progress.workout_start, Some(now));
+        assert!(!maybe_start_workout(&mut progress, 30, 10, now + Duration::from_secs(5)), "already under way");
+        // Any movement at all with no threshold
+        assert!(maybe_start_workout(&mut LinkProgress::new(), 1, 0, now));
+    }
+
+    #[test]
+    fn test_workout_targets_fire_once() {
+        let mut s = TreadmillState { distance_meters: 4999, distance_target: Some(5000), ..Default::default() };
+        assert_eq!(target_reached(&mut s), None);
+        s.distance_meters = 5001;
+        assert_eq!(target_reached(&mut s), Some(TreadmillEvent::DistanceReached(5000)));
+        assert_eq!(s.distance_target, None);
+        assert_eq!(target_reached(&mut s), None);
+
+        let mut s = TreadmillState { elapsed_secs: 1790, training_time_target: Some(1800), ..Default::default() };
+        assert_eq!(s.remaining_secs(), Some(10));
+        assert_eq!(u16::from_le_bytes([s.encode_ftms_data()[13], s.encode_ftms_data()[14]]), 10);
+        assert_eq!(target_reached(&mut s), None);
+        s.elapsed_secs = 1800;
+        assert_eq!(s.remaining_secs(), Some(0));
+        assert_eq!(target_reached(&mut s), Some(TreadmillEvent::TrainingTimeReached(1800)));
+        assert_eq!((s.training_time_target, s.remaining_secs()), (None, None));
+    }
+
+    #[test]
+    fn test_app_stop_is_not_a_console_pause() {
+        let window = Duration::from_secs(9);
+        let mut progress = LinkProgress::new();
+        progress.workout_start = Some(Instant::now());
+        let mut s = TreadmillState::default();
+        s.begin_command(Target::Speed(0));
+        let now = Instant::now();
+        assert_eq!(console_transition(&mut s, &mut progress, true, false, now, window), None);
+        // Long after the app's stop, a stop is the console again
+        assert_eq!(
+            console_transition(&mut s, &mut progress, true, false, now + window, window),
+            Some(TreadmillEvent::ConsolePaused)
+        );
+        // Emulate mode has no console
+        let mut s = TreadmillState::default();
+        let mut progress = LinkProgress { workout_start: Some(now), ..LinkProgress::new() };
+        assert_eq!(console_transition(&mut s, &mut progress, true, true, now, window), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_target_confirmed() {
+        let state = shared(TreadmillState { speed_tenths_mph: 50, ..Default::default() });
+        let gen = state.lock().await.begin_command(Target::Speed(50));
+        let (events, mut rx) = broadcast::channel(4);
+        assert!(verify_target(state, Commands::channel().0, events, Target::Speed(50), gen, Duration::from_millis(200), 0).await);
+        assert!(rx.try_recv().is_err(), "no failure event when target is reached");
+    }
+
+    #[tokio::test]
+    async fn test_verify_target_failure_event() {
+        let state = shared(TreadmillState::default());
+        let gen = state.lock().await.begin_command(Target::Incline(10));
+        let (events, mut rx) = broadcast::channel(4);
+        assert!(!verify_target(state, Commands::channel().0, events, Target::Incline(10), gen, Duration::from_millis(150), 0).await);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            TreadmillEvent::TargetFailed { target: Target::Incline(10), attempts: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_target_superseded() {
+        let state = shared(TreadmillState::default());
+        let (events, mut rx) = broadcast::channel(4);
+        // Two targets sent back to back: their verifiers run in whatever
+        // order, but the generations were taken as the commands went out
+        let older = state.lock().await.begin_command(Target::Speed(80));
+        let newer = state.lock().await.begin_command(Target::Speed(0));
+        let stale = tokio::spawn(verify_target(
+            state.clone(), Commands::channel().0, events.clone(), Target::Speed(80), older, Duration::from_millis(300), 0,
+        ));
+        let latest = tokio::spawn(verify_target(
+            state.clone(), Commands::channel().0, events, Target::Speed(0), newer, Duration::from_millis(300), 0,
+        ));
+        assert!(!stale.await.unwrap());
+        assert!(latest.await.unwrap(), "the stopped belt confirms the newer target");
+        assert!(rx.try_recv().is_err(), "superseded verifier must not report failure");
+    }
+
+    /// The next command line from treadmill_io's side, skipping the
+    /// connection's own handshake and keep-alive traffic.
+    async fn next_command(lines: &mut tokio::sync::mpsc::UnboundedReceiver<String>) -> String {
+        loop {
+            let line = lines.recv().await.unwrap();
+            if !["hello", "status", "heartbeat"].iter().any(|cmd| line.contains(&format!("\"cmd\":\"{}\"", cmd))) {
+                return line;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_share_the_connection() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (lines_tx, mut lines) = tokio::sync::mpsc::unbounded_channel();
+        let accepts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = accepts.clone();
+        let mut ctx = test_context(FtmsConfig::default(), broadcast::channel(4).0);
+        ctx.socket_path = format!("tcp:{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let lines_tx = lines_tx.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(stream).lines();
+                    while let Ok(Some(line)) = reader.next_line().await {
+                        let _ = lines_tx.send(line);
+                    }
+                });
+            }
+        });
+        let (commands, queue) = Commands::channel();
+        ctx.commands = commands.clone();
+        tokio::spawn(run(ctx, queue));
+
+        send_speed(&commands, 3.5).await.unwrap();
+        send_stop(&commands).await.unwrap();
+        assert_eq!(next_command(&mut lines).await, r#"{"cmd":"speed","value":3.5}"#);
+        assert_eq!(next_command(&mut lines).await, r#"{"cmd":"speed","value":0.0}"#);
+        assert_eq!(next_command(&mut lines).await, r#"{"cmd":"incline","value":0.0}"#);
+        assert_eq!(accepts.load(std::sync::atomic::Ordering::SeqCst), 1, "no connection per command");
+    }
+
+    #[tokio::test]
+    async fn test_commands_answered_by_id() {
+        // A v3 treadmill_io that acks speeds and refuses inclines
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut ctx = test_context(FtmsConfig::default(), broadcast::channel(4).0);
+        ctx.socket_path = format!("tcp:{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut reader = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = reader.next_line().await {
+                let msg: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let reply = match (msg["cmd"].as_str(), msg["id"].as_u64()) {
+                    (Some("hello"), _) => "{\"type\":\"hello\",\"version\":3}\n".to_string(),
+                    (Some("speed"), Some(id)) => format!("{{\"type\":\"ack\",\"id\":{}}}\n", id),
+                    (Some("incline"), Some(id)) => {
+                        format!("{{\"type\":\"error\",\"msg\":\"incline out of range\",\"id\":{}}}\n", id)
+                    }
+                    _ => continue,
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        let state = ctx.state.clone();
+        let (commands, queue) = Commands::channel();
+        tokio::spawn(run(ctx, queue));
+        while state.lock().await.protocol_version != Some(3) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        send_speed(&commands, 3.5).await.unwrap();
+        assert_eq!(
+            send_incline(&commands, 30.0).await.unwrap_err().to_string(),
+            CommandError::Refused("incline out of range".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_tagged_command() {
+        let (done, _) = oneshot::channel();
+        let command = Command { line: "{\"cmd\":\"speed\",\"value\":3.5}\n".into(), done };
+        assert_eq!(command.tagged(7), "{\"cmd\":\"speed\",\"value\":3.5,\"id\":7}\n");
+    }
+
+    #[tokio::test]
+    async fn test_command_errors() {
+        let (commands, queue) = Commands::channel();
+        drop(queue);
+        assert_eq!(commands.send("{}\n").await, Err(CommandError::Closed));
+
+        // Between reconnects: refused, not held for later
+        let mut ctx = test_context(FtmsConfig::default(), broadcast::channel(4).0);
+        ctx.socket_path = "tcp:127.0.0.1:1".into();
+        let (commands, queue) = Commands::channel();
+        tokio::spawn(run(ctx, queue));
+        assert_eq!(send_start(&commands).await.unwrap_err().to_string(), CommandError::NotConnected.to_string());
+
+        let (commands, mut queue) = Commands::channel();
+        let link = tokio::spawn(async move {
+            let command = queue.recv().await.unwrap();
+            command.complete(Err(CommandError::Write("broken pipe".into())));
+        });
+        assert_eq!(commands.send("{}\n").await, Err(CommandError::Write("broken pipe".into())));
+        link.await.unwrap();
+    }
 }
@@ -0,0 +1,280 @@
+//! Per-workout sample recording.
+//!
+//! The treadmill task records one sample per second from the moment the
+//! belt goes faster than `workout_start_mph` until it has been stopped for
+//! `workout_end_idle_secs`, with or without an app connected. The
+//! finished workout is handed to the exporters.
+//!
+//! Sample times are the start time plus monotonic elapsed time, so a wall
+//! clock step mid-workout (NTP syncing on a Pi without an RTC) can't leave
+//! them out of order: once the step is seen, the whole workout is moved
+//! onto the corrected clock.
+
+use crate::protocol::METERS_PER_MILE;
+
+/// One second of a workout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Unix epoch ms.
+    pub wall_ms: u64,
+    pub elapsed_secs: u32,
+    pub speed_tenths_mph: u16,
+    pub incline_half_pct: i16,
+    pub distance_m: f64,
+    /// BPM, when a strap's HR is available to the daemon.
+    pub heart_rate: Option<u16>,
+}
+
+impl Sample {
+    pub fn speed_mps(&self) -> f64 {
+        tenths_mph_to_mps(self.speed_tenths_mph)
+    }
+
+    /// Incline as a percent grade.
+    pub fn grade_pct(&self) -> f64 {
+        self.incline_half_pct as f64 / 2.0
+    }
+
+    /// Estimated running power in watts for a runner of `weight_kg`: about
+    /// 1.04 J/kg per meter on the flat (what footpod power meters report),
+    /// plus the work of climbing the grade.
+    pub fn power_watts(&self, weight_kg: f64) -> f64 {
+        let v = self.speed_mps();
+        weight_kg * v * (1.04 + 9.81 * self.grade_pct() / 100.0)
+    }
+}
+
+fn tenths_mph_to_mps(tenths: u16) -> f64 {
+    tenths as f64 / 10.0 * METERS_PER_MILE / 3600.0
+}
+
+/// Wall clock disagreeing with start + elapsed by more than this is a
+/// clock step, not drift.
+const CLOCK_STEP_MS: u64 = 5_000;
+
+/// A recorded workout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workout {
+    /// Unix epoch ms when the belt started (elapsed 0).
+    pub start_wall_ms: u64,
+    /// Local time zone's UTC offset at the start, seconds.
+    pub utc_offset_secs: i32,
+    pub samples: Vec<Sample>,
+    /// User-given name ("tempo run"), for summaries, history and exports.
+    pub label: Option<String>,
+    /// Profile active when it ended, for export templates.
+    pub profile: Option<String>,
+}
+
+impl Workout {
+    pub fn new(start_wall_ms: u64, utc_offset_secs: i32) -> Self {
+        Self { start_wall_ms, utc_offset_secs, samples: Vec::new(), label: None, profile: None }
+    }
+
+    /// Move the workout onto the wall clock if it has stepped since the
+    /// start: `now_wall_ms` should be `start_wall_ms + elapsed_ms`. Returns
+    /// the step in ms when one was applied.
+    pub fn follow_clock(&mut self, now_wall_ms: u64, elapsed_ms: u64) -> Option<i64> {
+        let expected = self.start_wall_ms + elapsed_ms;
+        if now_wall_ms.abs_diff(expected) <= CLOCK_STEP_MS {
+            return None;
+        }
+        let step = now_wall_ms as i64 - expected as i64;
+        self.start_wall_ms = self.start_wall_ms.saturating_add_signed(step);
+        for s in &mut self.samples {
+            s.wall_ms = s.wall_ms.saturating_add_signed(step);
+        }
+        Some(step)
+    }
+
+    /// Unix epoch ms at `elapsed_ms` into the workout.
+    pub fn wall_ms_at(&self, elapsed_ms: u64) -> u64 {
+        self.start_wall_ms + elapsed_ms
+    }
+
+    pub fn end_wall_ms(&self) -> u64 {
+        self.samples.last().map(|s| s.wall_ms).unwrap_or(self.start_wall_ms)
+    }
+
+    pub fn elapsed_secs(&self) -> u32 {
+        self.samples.last().map(|s| s.elapsed_secs).unwrap_or(0)
+    }
+
+    pub fn distance_m(&self) -> f64 {
+        self.samples.last().map(|s| s.distance_m).unwrap_or(0.0)
+    }
+
+    pub fn avg_speed_mps(&self) -> f64 {
+        match self.elapsed_secs() {
+            0 => 0.0,
+            secs => self.distance_m() / secs as f64,
+        }
+    }
+
+    pub fn max_speed_mps(&self) -> f64 {
+        tenths_mph_to_mps(self.samples.iter().map(|s| s.speed_tenths_mph).max().unwrap_or(0))
+    }
+
+    /// Fastest time in whole seconds to cover `distance_m` anywhere in the
+    /// workout (a best-effort split), or None if it never went that far.
+    pub fn best_effort_secs(&self, distance_m: f64) -> Option<u32> {
+        // Elapsed 0 at distance 0, then every sample
+        let points: Vec<(u32, f64)> =
+            std::iter::once((0, 0.0)).chain(self.samples.iter().map(|s| (s.elapsed_secs, s.distance_m))).collect();
+        let mut best: Option<u32> = None;
+        let mut from = 0;
+        for to in 0..points.len() {
+            // Latest start that still leaves `distance_m` to `to`
+            while from + 1 < to && points[to].1 - points[from + 1].1 >= distance_m {
+                from += 1;
+            }
+            if points[to].1 - points[from].1 >= distance_m {
+                let secs = points[to].0 - points[from].0;
+                best = Some(best.map_or(secs, |b| b.min(secs)));
+            }
+        }
+        best
+    }
+
+    /// Climb in meters: distance covered times grade, summed per sample.
+    /// Decline doesn't count against it.
+    pub fn elevation_gain_m(&self) -> f64 {
+        let mut last_distance = 0.0;
+        let mut gain = 0.0;
+        for s in &self.samples {
+            gain += (s.distance_m - last_distance).max(0.0) * s.grade_pct().max(0.0) / 100.0;
+            last_distance = s.distance_m;
+        }
+        gain
+    }
+
+    /// Mean of the samples that have HR.
+    pub fn avg_heart_rate(&self) -> Option<u16> {
+        let hrs: Vec<u32> = self.samples.iter().filter_map(|s| s.heart_rate).map(u32::from).collect();
+        (!hrs.is_empty()).then(|| (hrs.iter().sum::<u32>() / hrs.len() as u32) as u16)
+    }
+
+    pub fn max_heart_rate(&self) -> Option<u16> {
+        self.samples.iter().filter_map(|s| s.heart_rate).max()
+    }
+
+    /// Edwards TRIMP: minutes spent in each of five HR zones (50-60% ...
+    /// 90-100% of `max_hr`) weighted 1 to 5. Each sample with HR counts for
+    /// one second. None without any HR.
+    pub fn trimp(&self, max_hr: u16) -> Option<f64> {
+        let mut seen = false;
+        let mut weighted_secs = 0u32;
+        for hr in self.samples.iter().filter_map(|s| s.heart_rate) {
+            seen = true;
+            let pct = hr as u32 * 100 / max_hr.max(1) as u32;
+            weighted_secs += pct.clamp(40, 90).saturating_sub(40) / 10;
+        }
+        seen.then(|| weighted_secs as f64 / 60.0)
+    }
+
+    /// Estimated active (above resting) energy in kcal for a runner of
+    /// `weight_kg`, from the ACSM walking and running equations. Each
+    /// sample counts for one second.
+    pub fn active_kcal(&self, weight_kg: f64) -> f64 {
+        let ml_per_kg: f64 = self
+            .samples
+            .iter()
+            .map(|s| {
+                let m_per_min = s.speed_mps() * 60.0;
+                let grade = s.grade_pct() / 100.0;
+                let vo2 = if s.speed_tenths_mph == 0 {
+                    0.0
+                } else if s.speed_tenths_mph < WALK_RUN_TENTHS_MPH {
+                    0.1 * m_per_min + 1.8 * m_per_min * grade
+                } else {
+                    0.2 * m_per_min + 0.9 * m_per_min * grade
+                };
+                vo2 / 60.0
+            })
+            .sum();
+        // ~5 kcal per liter of O2
+        ml_per_kg * weight_kg / 1000.0 * 5.0
+    }
+}
+
+/// Below this (3.8 mph) the ACSM walking equation applies.
+const WALK_RUN_TENTHS_MPH: u16 = 38;
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Ten minutes at 6.0 mph and 2% with HR climbing 120 → 180, plus a
+    /// minute of cool-down at 3.0 mph without HR.
+    pub fn sample_workout() -> Workout {
+        let start = 1_700_000_000_000;
+        let mut w = Workout::new(start, -5 * 3600);
+        let mut distance = 0.0;
+        for i in 1..=660u32 {
+            let (speed, hr) = if i <= 600 { (60, Some(120 + (i / 10) as u16)) } else { (30, None) };
+            distance += tenths_mph_to_mps(speed);
+            w.samples.push(Sample {
+                wall_ms: start + i as u64 * 1000,
+                elapsed_secs: i,
+                speed_tenths_mph: speed,
+                incline_half_pct: 4,
+                distance_m: distance,
+                heart_rate: hr,
+            });
+        }
+        w
+    }
+
+    #[test]
+    fn test_summary() {
+        let w = sample_workout();
+        assert_eq!(w.elapsed_secs(), 660);
+        assert_eq!(w.end_wall_ms(), w.start_wall_ms + 660_000);
+        // 10 min at 6 mph + 1 min at 3 mph = 1.05 mi
+        assert!((w.distance_m() - 1.05 * METERS_PER_MILE).abs() < 0.01);
+        assert!((w.max_speed_mps() - 2.68224).abs() < 1e-6);
+        assert_eq!(w.max_heart_rate(), Some(180));
+        assert_eq!(w.avg_heart_rate(), Some(149));
+        assert_eq!(w.samples[0].grade_pct(), 2.0);
+        // 1.05 mi at 2%
+        assert!((w.elevation_gain_m() - 1.05 * METERS_PER_MILE * 0.02).abs() < 0.01);
+        // 70 kg at 6 mph up 2%
+        assert!((w.samples[0].power_watts(70.0) - 232.1).abs() < 0.1);
+
+        // HR 120 → 180 against a max of 200: 199s in zone 2, 200s each in
+        // zones 3 and 4, 1s at 90% (zone 5)
+        assert!((w.trimp(200).unwrap() - 1803.0 / 60.0).abs() < 1e-9);
+
+        // 10 min at 6 mph and 2%, then 1 min walking at 3 mph
+        let kcal = w.active_kcal(70.0);
+        assert!((kcal - 126.6).abs() < 0.5, "kcal {}", kcal);
+
+        // The 10 minutes at 6 mph hold the fastest stretches
+        assert_eq!(w.best_effort_secs(1000.0), Some(373));
+        assert!(matches!(w.best_effort_secs(METERS_PER_MILE), Some(600 | 601)));
+        assert_eq!(w.best_effort_secs(2.0 * METERS_PER_MILE), None);
+
+        let empty = Workout::new(5, 0);
+        assert_eq!(empty.best_effort_secs(1000.0), None);
+        assert_eq!(empty.avg_speed_mps(), 0.0);
+        assert_eq!(empty.avg_heart_rate(), None);
+        assert_eq!(empty.trimp(200), None);
+        assert_eq!(empty.end_wall_ms(), 5);
+    }
+
+    #[test]
+    fn test_follow_clock_step() {
+        let mut w = sample_workout();
+        let start = w.start_wall_ms;
+        // Drift within tolerance is left alone
+        assert_eq!(w.follow_clock(start + 661_000 + 4_000, 661_000), None);
+        // NTP steps the clock forward an hour
+        assert_eq!(w.follow_clock(start + 3_600_000 + 661_000, 661_000), Some(3_600_000));
+        assert_eq!(w.start_wall_ms, start + 3_600_000);
+        assert_eq!(w.samples[0].wall_ms, w.start_wall_ms + 1000);
+        assert_eq!(w.wall_ms_at(661_000), start + 3_600_000 + 661_000);
+        // and back
+        assert_eq!(w.follow_clock(start + 661_000, 661_000), Some(-3_600_000));
+        assert_eq!(w.start_wall_ms, start);
+    }
+}
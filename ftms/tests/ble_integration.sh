@@ -277,7 +277,7 @@ check_indication() {
     read -ra resp_bytes <<< "$resp"
     local actual="${resp_bytes[0]} ${resp_bytes[1]} ${resp_bytes[2]}"
     if [[ "$actual" == "$expected" ]]; then
-        pass "$label: response $expected (success)"
+        pass "$label: response $expected"
     else
         fail "$label: expected '$expected', got '$actual'"
     fi
@@ -372,6 +372,22 @@ test_control_point_speed_and_incline() {
     [[ "$CP_IND_COUNT" -ge 5 ]] && check_indication "Stop" "$CP_INDICATIONS" 5 "80 08 01"
 }
 
+test_control_point_without_control() {
+    echo "TEST: Control Point — Set Speed without Request Control"
+
+    local outfile
+    outfile=$(mktemp /tmp/gt_cp.XXXXXX)
+    gt_interactive "$outfile" 10 \
+        "char-write-req $CP_CCC 0200" \
+        0.5 \
+        "char-write-req $CP_HANDLE 02f401" \
+        1
+    CP_INDICATIONS=$(grep "Indication" "$outfile" || true)
+    rm -f "$outfile"
+
+    check_indication "Set Speed (not permitted)" "$CP_INDICATIONS" 1 "80 02 05"
+}
+
 # --- Runner ---
 
 run_test() {
@@ -384,11 +400,12 @@ run_test() {
         cp_speed)             test_control_point_speed ;;
         cp_incline)           test_control_point_incline ;;
         cp_speed_and_incline) test_control_point_speed_and_incline ;;
+        cp_without_control)   test_control_point_without_control ;;
         *) echo "Unknown test: $1"; exit 1 ;;
     esac
 }
 
-ALL_TESTS="discovery read_feature read_speed_range read_incline_range treadmill_data cp_speed cp_incline cp_speed_and_incline"
+ALL_TESTS="discovery read_feature read_speed_range read_incline_range treadmill_data cp_speed cp_incline cp_speed_and_incline cp_without_control"
 
 # Check prerequisites
 if ! hciconfig "$HCI" 2>/dev/null | grep -q "UP RUNNING"; then
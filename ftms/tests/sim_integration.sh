@@ -0,0 +1,72 @@
+#!/usr/bin/env bash
+# Debug port integration tests against treadmill-sim instead of the Pi.
+#
+# Builds ftms-daemon and treadmill-sim, starts the simulator on a private
+# socket and the daemon against it (BLE is left to fail without BlueZ; the
+# debug server doesn't need it), then runs tests/debug_integration.rs
+# against the local debug port. Needs no hardware, so it suits CI.
+#
+# Usage:
+#   ftms/tests/sim_integration.sh                      # all debug tests
+#   ftms/tests/sim_integration.sh test_07              # a filter, as cargo test takes
+#   make test-ftms-sim                                 # from the repo root
+#
+# Set FTMS_DEBUG_PORT to move the debug port off 18826.
+
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+PORT="${FTMS_DEBUG_PORT:-18826}"
+WORK="$(mktemp -d)"
+SOCKET="$WORK/treadmill_io.sock"
+PIDS=()
+
+cleanup() {
+    for pid in "${PIDS[@]}"; do
+        kill "$pid" 2>/dev/null || true
+    done
+    wait 2>/dev/null || true
+    rm -rf "$WORK"
+}
+trap cleanup EXIT
+
+cargo build --bins
+cargo test --no-run --test debug_integration
+
+# Defaults, except nothing written outside $WORK and no debug throttling
+# (the tests fire commands faster than the default limit)
+cat > "$WORK/ftms_config.json" <<EOF
+{
+  "activity_file": "$WORK/activity.json",
+  "debug_commands_per_sec": 0,
+  "debug_max_connections": 0
+}
+EOF
+
+target/debug/treadmill-sim --socket "$SOCKET" > "$WORK/sim.log" 2>&1 &
+PIDS+=($!)
+for _ in $(seq 50); do
+    [ -S "$SOCKET" ] && break
+    sleep 0.1
+done
+
+target/debug/ftms-daemon --socket "$SOCKET" --config "$WORK/ftms_config.json" --debug-port "$PORT" \
+    > "$WORK/daemon.log" 2>&1 &
+PIDS+=($!)
+for _ in $(seq 50); do
+    (exec 3<>"/dev/tcp/127.0.0.1/$PORT") 2>/dev/null && break
+    sleep 0.1
+done
+
+status=0
+FTMS_HOST=127.0.0.1 FTMS_DEBUG_PORT="$PORT" \
+    cargo test --test debug_integration -- --ignored --test-threads=1 "$@" || status=$?
+
+if [ "$status" -ne 0 ]; then
+    echo "--- treadmill-sim log ---"
+    tail -n 50 "$WORK/sim.log"
+    echo "--- ftms-daemon log ---"
+    tail -n 50 "$WORK/daemon.log"
+fi
+exit "$status"
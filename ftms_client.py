@@ -0,0 +1,57 @@
+#!/usr/bin/env python3
+"""
+Python client for the ftms-daemon debug port.
+
+The daemon's debug server takes one text command per line over TCP
+(default port 8826). Only `step` is used here: it sets the line describing
+where the interval program is (e.g. "Step 4/10: 2:00 @ 9.0 mph, 3%"), which
+the daemon sends to BLE apps as the Training Status string and shows in
+debug `state` and gRPC. `step -` clears it.
+
+Each call opens its own short connection, so a daemon that isn't running
+(or is restarting) just costs a refused connect and a debug log line. The
+debug port must be plain TCP; with `debug_tls` configured this can't reach it.
+
+Usage:
+    from ftms_client import send_step
+
+    await send_step("Step 1/5: 3:00 @ 3.0 mph, 1%")
+    await send_step(None)  # program ended
+"""
+
+import asyncio
+import logging
+
+FTMS_DEBUG_HOST = "127.0.0.1"
+FTMS_DEBUG_PORT = 8826
+TIMEOUT_SEC = 1.0
+
+log = logging.getLogger("ftms_client")
+
+
+def step_command(text):
+    """Debug port command line setting the step to `text` (None clears it)."""
+    text = " ".join(text.split()) if text else ""
+    return f"step {text or '-'}\n"
+
+
+async def send_step(text, host=FTMS_DEBUG_HOST, port=FTMS_DEBUG_PORT, timeout=TIMEOUT_SEC):
+    """Set the ftms-daemon's program step. Returns False if the daemon didn't take it."""
+    try:
+        return await asyncio.wait_for(_send(step_command(text), host, port), timeout)
+    except (OSError, asyncio.TimeoutError) as e:
+        log.debug(f"ftms-daemon step not sent: {e}")
+        return False
+
+
+async def _send(line, host, port):
+    reader, writer = await asyncio.open_connection(host, port)
+    try:
+        await reader.readline()  # banner
+        writer.write(line.encode())
+        await writer.drain()
+        # The reply may follow a prompt, depending on the console mode
+        reply = (await reader.readline()).decode()
+        return "step: " in reply or "no step" in reply
+    finally:
+        writer.close()
@@ -0,0 +1,31 @@
+//! Generates the gRPC service plumbing for the `HeartRate` service in
+//! `../proto/precor.proto`. The messages are hand-written in `src/grpc.rs`,
+//! so no protoc is needed, here or when cross-compiling.
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> tonic_build::manual::MethodBuilder {
+    Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::grpc::{}", input))
+        .output_type(format!("crate::grpc::{}", output))
+        .codec_path("tonic_prost::ProstCodec")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let heart_rate = Service::builder()
+        .name("HeartRate")
+        .package("precor.v1")
+        .method(method("get_state", "GetState", "Empty", "HeartRateState").build())
+        .method(method("watch_state", "WatchState", "WatchRequest", "HeartRateState").server_streaming().build())
+        .method(method("connect", "Connect", "ConnectRequest", "Empty").build())
+        .method(method("disconnect", "Disconnect", "Empty", "Empty").build())
+        .method(method("forget", "Forget", "Empty", "Empty").build())
+        .method(method("scan", "Scan", "Empty", "Empty").build())
+        .build();
+    // No transport helpers: the client's `connect(dst)` would clash with the
+    // `Connect` RPC. Tests build the client from a `Channel` instead.
+    Builder::new().build_transport(false).compile(&[heart_rate]);
+}
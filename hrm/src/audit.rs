@@ -0,0 +1,177 @@
+//! Audit trail of device management commands.
+//!
+//! `connect`, `disconnect`, `forget` and `scan`, from a socket client,
+//! the debug port or gRPC, are recorded with when and who asked: the
+//! socket client's uid and pid (from the kernel, so they can't be faked),
+//! or the debug or gRPC client's address and port. The most recent ones stay in memory
+//! for the debug `history` command. With `audit_log` set they're also
+//! appended to that file as JSON lines, and its tail seeds the in-memory
+//! list on startup, so in a shared household "who unpaired my strap?"
+//! still has an answer after a restart.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::config;
+
+/// Commands kept in memory for `history`.
+const KEEP: usize = 200;
+
+/// Who sent a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// A Unix socket client, by connection number and peer credentials.
+    Socket { client: u64, uid: Option<u32>, pid: Option<i32> },
+    /// A debug port client, by `ip:port`.
+    Debug(String),
+    /// A gRPC client, by `ip:port`.
+    Grpc(String),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Socket { client, uid, pid } => {
+                write!(f, "socket #{}", client)?;
+                if let Some(uid) = uid {
+                    write!(f, " uid {}", uid)?;
+                }
+                if let Some(pid) = pid {
+                    write!(f, " pid {}", pid)?;
+                }
+                Ok(())
+            }
+            Origin::Debug(peer) => write!(f, "debug {}", peer),
+            Origin::Grpc(peer) => write!(f, "grpc {}", peer),
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix time (seconds).
+    pub time: u64,
+    pub origin: Origin,
+    /// The command as given, e.g. `connect AA:BB:CC:DD:EE:FF`.
+    pub action: String,
+}
+
+/// Cheap, cloneable handle to the audit trail. The default keeps
+/// commands in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct Audit {
+    recent: Arc<Mutex<VecDeque<AuditRecord>>>,
+    tx: Option<mpsc::Sender<AuditRecord>>,
+}
+
+impl Audit {
+    pub fn record(&self, origin: &Origin, action: impl Into<String>) {
+        let record = AuditRecord { time: config::unix_now(), origin: origin.clone(), action: action.into() };
+        info!("Audit: {} by {}", record.action, record.origin);
+        if let Some(tx) = &self.tx {
+            if tx.try_send(record.clone()).is_err() {
+                warn!("Audit log backlogged, dropping record");
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == KEEP {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+    }
+
+    /// The last `n` commands, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<AuditRecord> {
+        let recent = self.recent.lock().unwrap();
+        recent.iter().skip(recent.len().saturating_sub(n)).cloned().collect()
+    }
+}
+
+/// Start auditing to `path` (append mode), picking up where its last
+/// lines left off. In memory only when `path` is `None`.
+pub fn start(path: Option<&str>) -> Audit {
+    let Some(path) = path else {
+        return Audit::default();
+    };
+    let recent = tail(path);
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(write_records(path.to_string(), rx));
+    info!("Auditing device commands to {} ({} earlier ones loaded)", path, recent.len());
+    Audit { recent: Arc::new(Mutex::new(recent)), tx: Some(tx) }
+}
+
+/// The last `KEEP` records in `path`, skipping lines that don't parse.
+fn tail(path: &str) -> VecDeque<AuditRecord> {
+    let Ok(data) = std::fs::read_to_string(path) else {
+        return VecDeque::new();
+    };
+    let mut recent: VecDeque<AuditRecord> = data.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    while recent.len() > KEEP {
+        recent.pop_front();
+    }
+    recent
+}
+
+async fn write_records(path: String, mut rx: mpsc::Receiver<AuditRecord>) {
+    let mut file = match tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to open audit log {}: {}", path, e);
+            return;
+        }
+    };
+    while let Some(record) = rx.recv().await {
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize audit record: {}", e);
+                continue;
+            }
+        };
+        line.push('\n');
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            warn!("Failed to write audit log {}: {}", path, e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_log_round_trip() {
+        let path = std::env::temp_dir().join(format!("hrm_audit_test_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let socket = Origin::Socket { client: 3, uid: Some(1000), pid: Some(4242) };
+        let debug = Origin::Debug("10.0.0.5:51234".into());
+
+        let audit = start(Some(path));
+        audit.record(&socket, "forget");
+        audit.record(&debug, "connect AA:BB:CC:DD:EE:FF");
+        let recent = audit.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].origin.to_string(), "socket #3 uid 1000 pid 4242");
+        assert_eq!(recent[1].action, "connect AA:BB:CC:DD:EE:FF");
+        assert_eq!(audit.recent(1), recent[1..]);
+
+        // The writer task appends in the background
+        drop(audit);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let line = std::fs::read_to_string(path).unwrap().lines().next().unwrap().to_string();
+        assert!(line.contains(r#""origin":{"socket":{"client":3,"uid":1000,"pid":4242}}"#), "{}", line);
+
+        std::fs::write(path, std::fs::read_to_string(path).unwrap() + "garbage\n").unwrap();
+        assert_eq!(start(Some(path)).recent(10), recent, "reloaded after a restart");
+        let _ = std::fs::remove_file(path);
+    }
+}
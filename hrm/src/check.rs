@@ -0,0 +1,185 @@
+//! `hrm-daemon --check`: one-shot health probe for cron, systemd and
+//! shell scripts.
+//!
+//! Connects to the running daemon's debug port on localhost (over TLS,
+//! trusting `debug_tls`'s certificate, when that's set), asks for
+//! `state json` and prints one line, e.g.
+//!
+//!   ok: adapter hci0, Polar H10 (AA:BB:CC:DD:EE:FF) at 142 bpm, saved: Polar H10 (AA:BB:CC:DD:EE:FF) +1 more
+//!
+//! Exit status: 0 when the adapter is up and the scanner is working, 1
+//! when the adapter is missing, the watchdog has asked for a bluetoothd
+//! restart, or neither the scanner nor a strap has done anything for
+//! `WEDGED_AFTER`, 2 when the daemon can't be reached or
+//! doesn't answer in time. No strap connected is fine: nobody may be
+//! wearing one.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::config::HrmConfig;
+use crate::tls;
+
+/// How long the whole exchange may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Scanner and strap silence that counts as wedged. A scan and the longest
+/// retry backoff take well under this.
+const WEDGED_AFTER: Duration = Duration::from_secs(120);
+
+/// The debug port's `state json` reply.
+#[derive(Debug, Deserialize)]
+struct State {
+    bpm: u16,
+    connected: bool,
+    stale: bool,
+    device: String,
+    address: String,
+    scanning: bool,
+    adapter: Option<String>,
+    scanner_idle_secs: u64,
+    saved: Option<Saved>,
+    saved_count: usize,
+    /// Absent from daemons that predate health alerts.
+    #[serde(default)]
+    alert: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Saved {
+    name: String,
+    address: String,
+}
+
+/// Probe the daemon on `port`, print the summary, and return the exit status.
+pub async fn run(config: &HrmConfig, port: u16) -> i32 {
+    match tokio::time::timeout(TIMEOUT, query(config, port)).await {
+        Ok(Ok(state)) => {
+            println!("{}", summary(&state));
+            if problem(&state).is_some() {
+                1
+            } else {
+                0
+            }
+        }
+        Ok(Err(e)) => {
+            println!("unreachable: debug port {}: {}", port, e);
+            2
+        }
+        Err(_) => {
+            println!("unreachable: debug port {}: no answer in {}s", port, TIMEOUT.as_secs());
+            2
+        }
+    }
+}
+
+async fn query(config: &HrmConfig, port: u16) -> Result<State, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    match &config.debug_tls {
+        Some(files) => {
+            let name = ServerName::try_from("localhost")?;
+            let stream = tls::connector(files)?.connect(name, stream).await?;
+            exchange(stream).await
+        }
+        None => exchange(stream).await,
+    }
+}
+
+/// Ask for `state json` and pick the JSON line out of the replies.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(stream: S) -> Result<State, Box<dyn std::error::Error + Send + Sync>> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(b"mode raw\nstate json\nquit\n").await?;
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.starts_with('{') {
+            return Ok(serde_json::from_str(&line)?);
+        }
+        if line.starts_with("too many debug connections") {
+            return Err(line.into());
+        }
+    }
+    Err("closed without a state".into())
+}
+
+/// Why the daemon isn't healthy, if it isn't.
+fn problem(s: &State) -> Option<String> {
+    if s.adapter.is_none() {
+        return Some("no BLE adapter".to_string());
+    }
+    if let Some(alert) = &s.alert {
+        return Some(alert.clone());
+    }
+    (s.scanner_idle_secs >= WEDGED_AFTER.as_secs())
+        .then(|| format!("scanner quiet for {}m{:02}s", s.scanner_idle_secs / 60, s.scanner_idle_secs % 60))
+}
+
+fn summary(s: &State) -> String {
+    let mut out = match (problem(s), &s.adapter) {
+        (Some(problem), _) => format!("degraded: {}", problem),
+        (None, Some(adapter)) => format!("ok: adapter {}", adapter),
+        (None, None) => unreachable!("a missing adapter is a problem"),
+    };
+    if s.connected {
+        out.push_str(&format!(", {} ({}) at {} bpm", s.device, s.address, s.bpm));
+        if s.stale {
+            out.push_str(" (stale)");
+        }
+    } else if s.scanning {
+        out.push_str(", scanning");
+    } else {
+        out.push_str(", not connected");
+    }
+    match &s.saved {
+        Some(saved) => out.push_str(&format!(", saved: {} ({})", saved.name, saved.address)),
+        None => out.push_str(", saved: none"),
+    }
+    let others = s.saved_count.saturating_sub(s.saved.is_some() as usize);
+    if others > 0 {
+        out.push_str(&format!(" +{} more", others));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exchange_and_summary() {
+        // What the debug port sends in raw mode, after the banner
+        let (client, mut server) = tokio::io::duplex(4096);
+        server
+            .write_all(
+                b"hrm-debug> connected. type 'help' for commands.\n\
+                  mode: raw\n\n\
+                  {\"bpm\":142,\"connected\":true,\"stale\":false,\"device\":\"Polar H10\",\
+                  \"address\":\"AA:BB:CC:DD:EE:FF\",\"scanning\":false,\"adapter\":\"hci0\",\"scanner_idle_secs\":3,\
+                  \"saved\":{\"name\":\"Polar H10\",\"address\":\"AA:BB:CC:DD:EE:FF\"},\"saved_count\":2}\n\n",
+            )
+            .await
+            .unwrap();
+        let state = exchange(client).await.unwrap();
+        assert_eq!(
+            summary(&state),
+            "ok: adapter hci0, Polar H10 (AA:BB:CC:DD:EE:FF) at 142 bpm, saved: Polar H10 (AA:BB:CC:DD:EE:FF) +1 more"
+        );
+        assert_eq!(problem(&state), None);
+
+        let wedged = State { connected: false, scanner_idle_secs: 305, saved: None, saved_count: 0, ..state };
+        assert_eq!(summary(&wedged), "degraded: scanner quiet for 5m05s, not connected, saved: none");
+        let gone = State { adapter: None, scanner_idle_secs: 0, ..wedged };
+        assert_eq!(problem(&gone).as_deref(), Some("no BLE adapter"));
+        let failing = State { adapter: Some("hci0".into()), alert: Some("restart bluetoothd".into()), ..gone };
+        assert_eq!(summary(&failing), "degraded: restart bluetoothd, not connected, saved: none");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // Port 1 on localhost: nothing listens there
+        assert_eq!(run(&HrmConfig::default(), 1).await, 2);
+    }
+}
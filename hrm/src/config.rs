@@ -1,17 +1,430 @@
 //! Persistent HRM device configuration.
 //!
 //! Reads and writes `hrm_config.json` to remember the preferred
-//! heart rate monitor between daemon restarts.
+//! heart rate monitor between daemon restarts, the history of devices
+//! we've connected to (with last-connected times, optionally pruned after
+//! `forget_after_days`), plus any hand-written per-device overrides for
+//! straps that report HR on nonstandard services.
+//!
+//! Straps that advertise from rotating resolvable private addresses are
+//! bonded on first connect and saved under their identity address. BlueZ
+//! keeps the IRK from bonding and reports later sightings under that
+//! identity, so the saved entry keeps matching across address rotations.
+//!
+//! Each saved device also carries connection stats (connects, failures,
+//! session lengths, last battery level) for spotting a strap that's dying.
+//! Daily resting heart rates (see `resting.rs`) are kept here too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::resting;
+use crate::tls::TlsFiles;
+
 /// Saved device configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HrmConfig {
+    /// Saved device address. Empty when no device is saved but the file is
+    /// kept around for its overrides.
+    #[serde(default)]
     pub address: String,
     #[serde(default)]
     pub name: String,
+    /// Every device we've connected to, most recent first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub saved: Vec<SavedDevice>,
+    /// Drop saved devices not connected for this many days. 0 keeps them forever.
+    #[serde(default)]
+    pub forget_after_days: u32,
+    /// Give up on `device.connect()` after this long. Flaky straps can
+    /// otherwise hang the scanner for a minute or more.
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Give up waiting for BlueZ to resolve GATT services after this long.
+    #[serde(default = "default_services_timeout_secs")]
+    pub services_timeout_secs: u64,
+    /// When a connection fails and the last scan found other HR devices,
+    /// try them in order of signal strength.
+    #[serde(default = "default_true")]
+    pub candidate_fallback: bool,
+    /// Reconnect to the saved device, or to the only HR device a scan
+    /// finds, without being asked. Off, the scanner still scans but only
+    /// connects on a `connect` command.
+    #[serde(default = "default_true")]
+    pub auto_connect: bool,
+    /// Messages queued for a socket client that isn't reading before the
+    /// `slow_client` policy kicks in.
+    #[serde(default = "default_client_queue_len")]
+    pub client_queue_len: usize,
+    #[serde(default)]
+    pub slow_client: SlowClientPolicy,
+    /// Socket clients allowed at once. 0 is unlimited. See `throttle.rs`.
+    #[serde(default = "default_max_clients")]
+    pub max_clients: usize,
+    /// Activity file ftms-daemon keeps up to date while apps are connected
+    /// to it over the same adapter.
+    #[serde(default = "default_ftms_activity_file")]
+    pub ftms_activity_file: String,
+    /// How discovery yields to ftms-daemon's connected apps.
+    #[serde(default)]
+    pub ftms_busy_scan: FtmsBusyScan,
+    /// Local times of day (`"05:30-08:00"`) when a steady HR counts as
+    /// resting. Empty means any time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rest_windows: Vec<String>,
+    /// IANA time zone for `rest_windows` and daily dates. Unset uses the
+    /// system zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Lowest resting HR per local day (`"YYYY-MM-DD"`), oldest first.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resting_hr: BTreeMap<String, u16>,
+    /// Vendor-specific HR characteristic locations, keyed by device address.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<String, HrOverride>,
+    /// Permission bits for the socket file, in octal. Unset is 0660.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_mode: Option<String>,
+    /// Group to own the socket file. Unset uses the group of the directory
+    /// holding this config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_group: Option<String>,
+    /// Append every connect/disconnect/forget/scan command, with who sent
+    /// it, to this JSONL file. Unset keeps them in memory only. See
+    /// `audit.rs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<String>,
+    /// Certificate and key for a TLS-only debug port. See `tls.rs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_tls: Option<TlsFiles>,
+    /// Debug connections allowed at once. 0 is unlimited. See `throttle.rs`.
+    #[serde(default = "default_debug_max_connections")]
+    pub debug_max_connections: usize,
+    /// Commands per second each debug connection may send before it's
+    /// slowed down. 0 is unlimited.
+    #[serde(default = "default_debug_commands_per_sec")]
+    pub debug_commands_per_sec: u32,
+    /// Close debug connections that send nothing for this many seconds,
+    /// unless subscribed. 0 keeps them open.
+    #[serde(default = "default_debug_idle_timeout_secs")]
+    pub debug_idle_timeout_secs: u64,
+    /// Serve the gRPC API on this address, e.g. `0.0.0.0:8837`. Unset (the
+    /// default) disables. See `grpc.rs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc_addr: Option<String>,
+    /// gRPC connections allowed at once. 0 is unlimited.
+    #[serde(default = "default_grpc_max_connections")]
+    pub grpc_max_connections: usize,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    15
+}
+
+fn default_services_timeout_secs() -> u64 {
+    10
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_client_queue_len() -> usize {
+    32
+}
+
+fn default_max_clients() -> usize {
+    32
+}
+
+fn default_grpc_max_connections() -> usize {
+    8
+}
+
+fn default_debug_max_connections() -> usize {
+    8
+}
+
+fn default_debug_commands_per_sec() -> u32 {
+    20
+}
+
+fn default_debug_idle_timeout_secs() -> u64 {
+    600
+}
+
+fn default_ftms_activity_file() -> String {
+    "/tmp/ftms_activity.json".to_string()
+}
+
+/// Discovery behaviour while ftms-daemon has apps connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FtmsBusyScan {
+    /// Short scans with long gaps between them.
+    #[default]
+    Throttle,
+    /// No discovery until the apps disconnect. Saved-device reconnects and
+    /// explicit `scan` commands still go ahead.
+    Pause,
+    /// Scan as if ftms-daemon weren't there.
+    Ignore,
+}
+
+/// What the socket server does when a client's outbound queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowClientPolicy {
+    /// Discard the oldest queued messages; the client sees a gap.
+    #[default]
+    DropOldest,
+    /// Close the connection; the client has to reconnect.
+    Disconnect,
+}
+
+impl Default for HrmConfig {
+    fn default() -> Self {
+        Self {
+            address: String::new(),
+            name: String::new(),
+            saved: Vec::new(),
+            forget_after_days: 0,
+            connect_timeout_secs: default_connect_timeout_secs(),
+            services_timeout_secs: default_services_timeout_secs(),
+            candidate_fallback: true,
+            auto_connect: true,
+            client_queue_len: default_client_queue_len(),
+            slow_client: SlowClientPolicy::default(),
+            max_clients: default_max_clients(),
+            ftms_activity_file: default_ftms_activity_file(),
+            ftms_busy_scan: FtmsBusyScan::default(),
+            rest_windows: Vec::new(),
+            timezone: None,
+            resting_hr: BTreeMap::new(),
+            overrides: HashMap::new(),
+            socket_mode: None,
+            socket_group: None,
+            audit_log: None,
+            debug_tls: None,
+            debug_max_connections: default_debug_max_connections(),
+            debug_commands_per_sec: default_debug_commands_per_sec(),
+            debug_idle_timeout_secs: default_debug_idle_timeout_secs(),
+            grpc_addr: None,
+            grpc_max_connections: default_grpc_max_connections(),
+        }
+    }
+}
+
+/// A device we've connected to before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedDevice {
+    pub address: String,
+    #[serde(default)]
+    pub name: String,
+    /// Unix time (seconds) of the most recent successful connection.
+    #[serde(default)]
+    pub last_connected: u64,
+    /// User-assigned friendly name ("Dad's Polar"), shown instead of the
+    /// advertised name where set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// `address` is an identity address resolved by bonding with a strap
+    /// that uses resolvable private addresses.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    #[serde(default, skip_serializing_if = "DeviceStats::is_empty")]
+    pub stats: DeviceStats,
+}
+
+/// Connection history for a saved device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceStats {
+    /// Successful connections.
+    pub connects: u32,
+    /// Connection attempts that failed (timeouts, no HR characteristic, ...).
+    pub failures: u32,
+    /// Finished sessions and their combined length, for the average.
+    pub sessions: u32,
+    pub session_secs: u64,
+    /// Battery level (%) last read from the strap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+}
+
+impl DeviceStats {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Mean length of a finished session.
+    pub fn average_session_secs(&self) -> Option<u64> {
+        (self.sessions > 0).then(|| self.session_secs / self.sessions as u64)
+    }
+}
+
+impl HrmConfig {
+    /// Nothing worth keeping on disk: no devices, overrides, or tunables
+    /// changed from their defaults.
+    fn is_empty(&self) -> bool {
+        let d = HrmConfig::default();
+        self.address.is_empty()
+            && self.saved.is_empty()
+            && self.overrides.is_empty()
+            && self.resting_hr.is_empty()
+            && self.rest_windows.is_empty()
+            && self.timezone.is_none()
+            && self.forget_after_days == d.forget_after_days
+            && self.connect_timeout_secs == d.connect_timeout_secs
+            && self.services_timeout_secs == d.services_timeout_secs
+            && self.candidate_fallback == d.candidate_fallback
+            && self.auto_connect == d.auto_connect
+            && self.client_queue_len == d.client_queue_len
+            && self.slow_client == d.slow_client
+            && self.ftms_activity_file == d.ftms_activity_file
+            && self.ftms_busy_scan == d.ftms_busy_scan
+    }
+
+    /// Record a connection to `address` at `now`: makes it the preferred
+    /// device and moves it to the front of the saved list. When `identity`
+    /// is the resolved identity of a private `address`, the device is saved
+    /// under the identity and anything saved under the private address
+    /// (nickname, override) moves over to it.
+    pub fn record_connection(&mut self, address: &str, identity: Option<&str>, name: &str, now: u64) {
+        let key = identity.unwrap_or(address);
+        let previous = self.saved_device(key).or_else(|| self.saved_device(address));
+        let nickname = previous.and_then(|d| d.nickname.clone());
+        let mut stats = previous.map(|d| d.stats.clone()).unwrap_or_default();
+        stats.connects += 1;
+        if identity.is_some() && self.override_for(key).is_none() {
+            if let Some(k) = self.overrides.keys().find(|k| k.eq_ignore_ascii_case(address)).cloned() {
+                let ovr = self.overrides.remove(&k).unwrap();
+                self.overrides.insert(key.to_string(), ovr);
+            }
+        }
+        self.address = key.to_string();
+        self.name = name.to_string();
+        self.saved
+            .retain(|d| !d.address.eq_ignore_ascii_case(key) && !d.address.eq_ignore_ascii_case(address));
+        self.saved.insert(0, SavedDevice {
+            address: key.to_string(),
+            name: name.to_string(),
+            last_connected: now,
+            nickname,
+            private: identity.is_some(),
+            stats,
+        });
+    }
+
+    /// Record a resting HR of `bpm` on `date` if it's the day's lowest,
+    /// trimming history to `resting::HISTORY_DAYS`. Returns the day's value.
+    pub fn record_resting_hr(&mut self, date: &str, bpm: u16) -> u16 {
+        let low = self.resting_hr.entry(date.to_string()).or_insert(bpm);
+        *low = (*low).min(bpm);
+        let low = *low;
+        while self.resting_hr.len() > resting::HISTORY_DAYS {
+            self.resting_hr.pop_first();
+        }
+        low
+    }
+
+    /// The most recently connected device.
+    pub fn last_device(&self) -> Option<&SavedDevice> {
+        self.saved.first()
+    }
+
+    /// Look up a saved device by address (case-insensitive).
+    pub fn saved_device(&self, address: &str) -> Option<&SavedDevice> {
+        self.saved.iter().find(|d| d.address.eq_ignore_ascii_case(address))
+    }
+
+    /// The nickname assigned to `address`, if any.
+    pub fn nickname_for(&self, address: &str) -> Option<&str> {
+        self.saved_device(address).and_then(|d| d.nickname.as_deref())
+    }
+
+    /// Set (or with `None`, clear) a saved device's nickname. Returns false
+    /// if the device isn't saved.
+    pub fn set_nickname(&mut self, address: &str, nickname: Option<String>) -> bool {
+        match self.saved.iter_mut().find(|d| d.address.eq_ignore_ascii_case(address)) {
+            Some(d) => {
+                d.nickname = nickname;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A saved device's stats, for updating. None if the device isn't saved.
+    pub fn stats_mut(&mut self, address: &str) -> Option<&mut DeviceStats> {
+        self.saved
+            .iter_mut()
+            .find(|d| d.address.eq_ignore_ascii_case(address))
+            .map(|d| &mut d.stats)
+    }
+
+    /// Remove saved devices last connected more than `forget_after_days`
+    /// before `now`, clearing the preferred device if it was among them.
+    /// Returns the removed entries.
+    pub fn prune(&mut self, now: u64) -> Vec<SavedDevice> {
+        if self.forget_after_days == 0 {
+            return Vec::new();
+        }
+        let cutoff = now.saturating_sub(self.forget_after_days as u64 * 86_400);
+        let (keep, removed): (Vec<_>, Vec<_>) =
+            self.saved.drain(..).partition(|d| d.last_connected >= cutoff);
+        self.saved = keep;
+        if removed.iter().any(|d| d.address.eq_ignore_ascii_case(&self.address)) {
+            self.address.clear();
+            self.name.clear();
+        }
+        removed
+    }
+    /// `socket_mode` as permission bits.
+    pub fn socket_mode(&self) -> Result<u32, String> {
+        let Some(mode) = &self.socket_mode else {
+            return Ok(0o660);
+        };
+        u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|&bits| bits <= 0o777)
+            .ok_or_else(|| format!("socket_mode {:?} isn't octal permission bits like \"0660\"", mode))
+    }
+
+    /// Look up the override for a device address (case-insensitive).
+    pub fn override_for(&self, address: &str) -> Option<&HrOverride> {
+        self.overrides
+            .iter()
+            .find(|(addr, _)| addr.eq_ignore_ascii_case(address))
+            .map(|(_, ovr)| ovr)
+    }
+}
+
+/// Where to find HR on a device that doesn't use the standard Heart Rate
+/// Service. UUIDs are either full 128-bit strings or 16-bit SIG short
+/// forms like `"fee0"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HrOverride {
+    pub service: String,
+    pub characteristic: String,
+    #[serde(default)]
+    pub parser: HrParser,
+}
+
+/// How to decode the overridden characteristic's notification value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HrParser {
+    /// Standard HR Measurement layout (flags byte, then uint8/uint16).
+    #[default]
+    Standard,
+    /// A single byte at `offset`.
+    Uint8 { offset: usize },
+    /// A little-endian uint16 at `offset`.
+    Uint16Le { offset: usize },
 }
 
 /// Load config from disk. Returns None if file missing or invalid.
@@ -19,7 +432,10 @@ pub fn load(path: &str) -> Option<HrmConfig> {
     let data = std::fs::read_to_string(path).ok()?;
     match serde_json::from_str::<HrmConfig>(&data) {
         Ok(cfg) => {
-            info!("Loaded config: address={}, name={}", cfg.address, cfg.name);
+            info!(
+                "Loaded config: address={}, name={}, overrides={}",
+                cfg.address, cfg.name, cfg.overrides.len()
+            );
             Some(cfg)
         }
         Err(e) => {
@@ -45,9 +461,103 @@ pub fn save(path: &str, config: &HrmConfig) {
     }
 }
 
-/// Delete config file. Used when user sends "forget" command.
+/// Seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Address a `connect` command means: `address` as given, or for `last`
+/// the most recently connected device.
+pub fn resolve_connect(path: &str, address: &str) -> Result<String, String> {
+    if !address.eq_ignore_ascii_case("last") {
+        return Ok(address.to_string());
+    }
+    load(path)
+        .and_then(|cfg| cfg.last_device().map(|d| d.address.clone()))
+        .ok_or_else(|| "no device connected before".to_string())
+}
+
+/// Remember `address`/`name` (under `identity` when the address was a
+/// resolved private one) as the saved device and stamp its last-connected
+/// time, keeping everything else already in the file.
+pub fn save_device(path: &str, address: &str, identity: Option<&str>, name: &str) {
+    let mut cfg = load(path).unwrap_or_default();
+    cfg.record_connection(address, identity, name, unix_now());
+    save(path, &cfg);
+}
+
+/// Update a saved device's stats on disk. Devices that were never
+/// connected aren't saved, so they have no stats to update.
+pub fn update_stats(path: &str, address: &str, update: impl FnOnce(&mut DeviceStats)) {
+    let Some(mut cfg) = load(path) else { return };
+    if let Some(stats) = cfg.stats_mut(address) {
+        update(stats);
+        save(path, &cfg);
+    }
+}
+
+/// Record a resting HR reading on disk, returning the day's lowest.
+pub fn record_resting_hr(path: &str, date: &str, bpm: u16) -> u16 {
+    let mut cfg = load(path).unwrap_or_default();
+    let low = cfg.record_resting_hr(date, bpm);
+    if low == bpm {
+        info!("Resting HR for {}: {} bpm", date, bpm);
+        save(path, &cfg);
+    }
+    low
+}
+
+/// Apply `forget_after_days` to the file on disk. Only rewrites it when
+/// something was actually pruned.
+pub fn prune_stale(path: &str) {
+    let Some(mut cfg) = load(path) else { return };
+    let removed = cfg.prune(unix_now());
+    if removed.is_empty() {
+        return;
+    }
+    for d in &removed {
+        info!("Auto-forgetting {} ({}), unused for over {} days", d.name, d.address, cfg.forget_after_days);
+    }
+    write_or_delete(path, &cfg);
+}
+
+/// Set or clear a saved device's nickname on disk. An empty nickname clears it.
+pub fn set_nickname(path: &str, address: &str, nickname: &str) -> Result<(), String> {
+    let mut cfg = load(path).unwrap_or_default();
+    let nickname = Some(nickname.trim().to_string()).filter(|n| !n.is_empty());
+    if !cfg.set_nickname(address, nickname) {
+        return Err(format!("{} is not a saved device (connect to it first)", address));
+    }
+    save(path, &cfg);
+    Ok(())
+}
+
+/// Forget the saved device. Used when user sends "forget" command. It is
+/// dropped from the saved list too; the file is deleted once nothing else
+/// (other saved devices, user-authored overrides) is left in it.
 pub fn forget(path: &str) {
-    if std::fs::remove_file(path).is_ok() {
+    let mut cfg = load(path).unwrap_or_default();
+    let address = std::mem::take(&mut cfg.address);
+    cfg.name.clear();
+    cfg.saved.retain(|d| !d.address.eq_ignore_ascii_case(&address));
+    write_or_delete(path, &cfg);
+}
+
+fn write_or_delete(path: &str, cfg: &HrmConfig) {
+    if !cfg.is_empty() {
+        save(path, cfg);
+    } else if std::fs::remove_file(path).is_ok() {
         info!("Deleted config file {}", path);
     }
 }
@@ -66,6 +576,7 @@ mod tests {
         let cfg = HrmConfig {
             address: "AA:BB:CC:DD:EE:FF".to_string(),
             name: "Polar H10".to_string(),
+            ..Default::default()
         };
         save(path_str, &cfg);
 
@@ -79,6 +590,219 @@ mod tests {
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_overrides_survive_save_device_and_forget() {
+        let path = "/tmp/hrm_overrides_config.json";
+        std::fs::write(
+            path,
+            r#"{"overrides": {"aa:bb:cc:dd:ee:ff": {"service": "fee0", "characteristic": "fee1",
+                "parser": {"type": "uint8", "offset": 3}}}}"#,
+        )
+        .unwrap();
+
+        let cfg = load(path).expect("overrides-only config loads");
+        assert!(cfg.address.is_empty());
+        let ovr = cfg.override_for("AA:BB:CC:DD:EE:FF").expect("case-insensitive lookup");
+        assert_eq!(ovr.parser, HrParser::Uint8 { offset: 3 });
+
+        save_device(path, "AA:BB:CC:DD:EE:FF", None, "Band");
+        let cfg = load(path).unwrap();
+        assert_eq!(cfg.address, "AA:BB:CC:DD:EE:FF");
+        assert_eq!(cfg.overrides.len(), 1);
+
+        forget(path);
+        let cfg = load(path).expect("file kept for overrides");
+        assert!(cfg.address.is_empty());
+        assert_eq!(cfg.overrides.len(), 1);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_record_connection_moves_to_front() {
+        let mut cfg = HrmConfig::default();
+        cfg.record_connection("AA:AA:AA:AA:AA:AA", None, "Polar", 100);
+        cfg.record_connection("BB:BB:BB:BB:BB:BB", None, "Garmin", 200);
+        cfg.record_connection("aa:aa:aa:aa:aa:aa", None, "Polar", 300);
+        assert_eq!(cfg.address, "aa:aa:aa:aa:aa:aa");
+        assert_eq!(cfg.saved.len(), 2);
+        assert_eq!(cfg.saved[0].last_connected, 300);
+        assert_eq!(cfg.saved[1].name, "Garmin");
+    }
+
+    #[test]
+    fn test_nickname_survives_reconnect() {
+        let mut cfg = HrmConfig::default();
+        assert!(!cfg.set_nickname("AA:AA:AA:AA:AA:AA", Some("Dad's Polar".into())));
+
+        cfg.record_connection("AA:AA:AA:AA:AA:AA", None, "Polar H10", 100);
+        assert!(cfg.set_nickname("aa:aa:aa:aa:aa:aa", Some("Dad's Polar".into())));
+        cfg.record_connection("AA:AA:AA:AA:AA:AA", None, "Polar H10", 200);
+        assert_eq!(cfg.nickname_for("AA:AA:AA:AA:AA:AA"), Some("Dad's Polar"));
+
+        assert!(cfg.set_nickname("AA:AA:AA:AA:AA:AA", None));
+        assert_eq!(cfg.nickname_for("AA:AA:AA:AA:AA:AA"), None);
+    }
+
+    #[test]
+    fn test_private_address_saved_under_identity() {
+        let mut cfg = HrmConfig::default();
+        // Saved before bonding, under a resolvable private address
+        cfg.record_connection("5A:11:22:33:44:55", None, "Polar H10", 100);
+        cfg.set_nickname("5A:11:22:33:44:55", Some("Dad's Polar".into()));
+        cfg.overrides.insert("5A:11:22:33:44:55".into(), HrOverride {
+            service: "fee0".into(),
+            characteristic: "fee1".into(),
+            parser: HrParser::Standard,
+        });
+
+        cfg.record_connection("5A:11:22:33:44:55", Some("C0:AA:BB:CC:DD:EE"), "Polar H10", 200);
+        assert_eq!(cfg.address, "C0:AA:BB:CC:DD:EE");
+        assert_eq!(cfg.saved.len(), 1, "private-address entry replaced");
+        assert!(cfg.saved[0].private);
+        assert_eq!(cfg.nickname_for("C0:AA:BB:CC:DD:EE"), Some("Dad's Polar"));
+        assert!(cfg.override_for("C0:AA:BB:CC:DD:EE").is_some());
+        assert!(cfg.override_for("5A:11:22:33:44:55").is_none());
+
+        // A later session from a fresh RPA resolves to the same identity
+        cfg.record_connection("4B:99:88:77:66:55", Some("C0:AA:BB:CC:DD:EE"), "Polar H10", 300);
+        assert_eq!(cfg.saved.len(), 1);
+        assert_eq!(cfg.saved[0].last_connected, 300);
+        assert_eq!(cfg.nickname_for("C0:AA:BB:CC:DD:EE"), Some("Dad's Polar"));
+    }
+
+    #[test]
+    fn test_stats_carry_across_connections() {
+        let path = "/tmp/hrm_stats_config.json";
+        let _ = std::fs::remove_file(path);
+        update_stats(path, "AA:AA:AA:AA:AA:AA", |s| s.failures += 1);
+        assert!(load(path).is_none(), "unsaved device has no stats");
+
+        save_device(path, "AA:AA:AA:AA:AA:AA", None, "Polar");
+        update_stats(path, "aa:aa:aa:aa:aa:aa", |s| {
+            s.sessions += 1;
+            s.session_secs += 600;
+            s.battery = Some(80);
+        });
+        update_stats(path, "AA:AA:AA:AA:AA:AA", |s| s.failures += 1);
+        save_device(path, "AA:AA:AA:AA:AA:AA", None, "Polar");
+        update_stats(path, "AA:AA:AA:AA:AA:AA", |s| {
+            s.sessions += 1;
+            s.session_secs += 300;
+        });
+
+        let stats = load(path).unwrap().saved[0].stats.clone();
+        assert_eq!(stats.connects, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.average_session_secs(), Some(450));
+        assert_eq!(stats.battery, Some(80));
+        assert_eq!(DeviceStats::default().average_session_secs(), None);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_prune() {
+        let day = 86_400;
+        let mut cfg = HrmConfig::default();
+        cfg.record_connection("AA:AA:AA:AA:AA:AA", None, "Old", 0);
+        cfg.record_connection("BB:BB:BB:BB:BB:BB", None, "New", 9 * day);
+        cfg.address = "AA:AA:AA:AA:AA:AA".to_string();
+
+        assert!(cfg.prune(10 * day).is_empty(), "0 days keeps everything");
+
+        cfg.forget_after_days = 5;
+        let removed = cfg.prune(10 * day);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name, "Old");
+        assert_eq!(cfg.saved.len(), 1);
+        assert!(cfg.address.is_empty(), "pruned preferred device is cleared");
+    }
+
+    #[test]
+    fn test_forget_keeps_other_saved_devices() {
+        let path = "/tmp/hrm_forget_saved_config.json";
+        let _ = std::fs::remove_file(path);
+        save_device(path, "AA:AA:AA:AA:AA:AA", None, "Polar");
+        save_device(path, "BB:BB:BB:BB:BB:BB", None, "Garmin");
+
+        forget(path);
+        let cfg = load(path).expect("file kept for remaining device");
+        assert!(cfg.address.is_empty());
+        assert_eq!(cfg.saved.len(), 1);
+        assert_eq!(cfg.saved[0].address, "AA:AA:AA:AA:AA:AA");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_timeouts_default_when_absent() {
+        let cfg: HrmConfig = serde_json::from_str(r#"{"address": "AA:BB:CC:DD:EE:FF"}"#).unwrap();
+        assert_eq!(cfg.connect_timeout_secs, 15);
+        assert_eq!(cfg.services_timeout_secs, 10);
+        assert!(cfg.candidate_fallback);
+        assert!(cfg.auto_connect);
+    }
+
+    #[test]
+    fn test_resolve_connect_last() {
+        let dir = std::env::temp_dir().join(format!("hrm_connect_last_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hrm_config.json");
+        let path = path.to_str().unwrap();
+        assert_eq!(resolve_connect(path, "AA:BB:CC:DD:EE:FF"), Ok("AA:BB:CC:DD:EE:FF".to_string()));
+        assert_eq!(resolve_connect(path, "last"), Err("no device connected before".to_string()));
+
+        let mut cfg = HrmConfig::default();
+        cfg.record_connection("AA:AA:AA:AA:AA:AA", None, "Polar H10", 100);
+        cfg.record_connection("BB:BB:BB:BB:BB:BB", None, "Wahoo TICKR", 200);
+        save(path, &cfg);
+        assert_eq!(resolve_connect(path, "LAST"), Ok("BB:BB:BB:BB:BB:BB".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_socket_mode() {
+        let mut cfg = HrmConfig::default();
+        assert_eq!(cfg.socket_mode(), Ok(0o660));
+        for (mode, bits) in [("0660", 0o660), ("600", 0o600), ("0777", 0o777)] {
+            cfg.socket_mode = Some(mode.to_string());
+            assert_eq!(cfg.socket_mode(), Ok(bits));
+        }
+        for bad in ["0o660", "0668", "1777", "rw-rw----", ""] {
+            cfg.socket_mode = Some(bad.to_string());
+            assert!(cfg.socket_mode().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_forget_keeps_file_with_custom_tunables() {
+        let path = "/tmp/hrm_forget_tunables_config.json";
+        std::fs::write(path, r#"{"address": "AA:BB:CC:DD:EE:FF", "connect_timeout_secs": 5}"#).unwrap();
+        forget(path);
+        let cfg = load(path).expect("file kept for tunables");
+        assert!(cfg.address.is_empty());
+        assert_eq!(cfg.connect_timeout_secs, 5);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_record_resting_hr_keeps_daily_low() {
+        let mut cfg = HrmConfig::default();
+        assert_eq!(cfg.record_resting_hr("2026-10-16", 60), 60);
+        assert_eq!(cfg.record_resting_hr("2026-10-16", 62), 60);
+        assert_eq!(cfg.record_resting_hr("2026-10-16", 57), 57);
+        for day in 0..resting::HISTORY_DAYS {
+            cfg.record_resting_hr(&format!("2027-{:03}", day), 55);
+        }
+        assert_eq!(cfg.resting_hr.len(), resting::HISTORY_DAYS);
+        assert!(!cfg.resting_hr.contains_key("2026-10-16"), "oldest day dropped");
+        assert!(!cfg.is_empty());
+    }
+
+    #[test]
+    fn test_parser_defaults_to_standard() {
+        let ovr: HrOverride = serde_json::from_str(r#"{"service": "180d", "characteristic": "2a37"}"#).unwrap();
+        assert_eq!(ovr.parser, HrParser::Standard);
+    }
+
     #[test]
     fn test_load_missing() {
         assert!(load("/tmp/hrm_nonexistent_config.json").is_none());
@@ -7,87 +7,187 @@
 //!   nc rpi 8827
 //!
 //! Commands:
-//!   state           show HR + device info
+//!   state [json]    show HR + device info, or one JSON line with the
+//!                   adapter and scanner activity too (for --check)
 //!   sub             subscribe to 1 Hz HR stream
 //!   scan            trigger BLE scan
-//!   connect <addr>  connect to a device by address
+//!   devices [watch] list HR devices from the last scan, or stream scan
+//!                   events (found / RSSI updates / lost) as they happen
+//!   connect <addr>  connect to a device by address (`last`: the most recent one)
+//!   gattdump <addr> print a device's full GATT service tree
 //!   disconnect      disconnect from current device
 //!   forget          forget saved device + disconnect
+//!   saved           list previously connected devices + last-connected time
+//!   history [n]     last n connect/disconnect/forget/scan commands and who
+//!                   sent them
+//!   stats           uptime, per-task last activity, counters, resting HR
+//!                   trend, then per-device connects, failures, avg
+//!                   session, battery
+//!   nickname <addr> [name]  set (or clear) a saved device's nickname
 //!   mock <bpm>      fake a connected HRM at given BPM (for testing without hardware)
 //!   mock off        stop mocking, revert to disconnected
+//!   mode [plain|raw|edit]  input mode: raw drops the prompt (rlwrap,
+//!                   scripts), edit does telnet line editing + history
 //!   help            list commands
 //!   quit            disconnect
 
+use std::io::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use log::info;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use log::{info, warn};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
 
+use crate::audit::Origin;
 use crate::config;
-use crate::scanner::{HrmCommand, HrmState};
+use crate::console::{Console, Mode};
+use crate::health::Counter;
+use crate::resting;
+use crate::scanner::{self, BleDevice, DeviceEvent, HrmCommand, HrmState};
+use crate::throttle::Limits;
 
-/// Run the TCP debug server.
+/// Bind the debug port. Done before `run` so privileges can be dropped
+/// in between.
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Debug server listening on port {}", port);
+    Ok(listener)
+}
+
+/// Run the TCP debug server, over TLS when `tls` is set.
 pub async fn run(
     state: Arc<Mutex<HrmState>>,
     config_path: String,
-    port: u16,
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    limits: Limits,
     cmd_tx: mpsc::Sender<HrmCommand>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
-    info!("Debug server listening on port {}", port);
-
     loop {
         let (stream, addr) = listener.accept().await?;
+        let Some(slot) = limits.open() else {
+            warn!("Debug client {} refused: {} connections already open", addr, limits.max_connections());
+            // A fresh socket's send buffer is empty, so this can't block
+            if let (None, Ok(mut stream)) = (&tls, stream.into_std()) {
+                let _ = stream.write_all(b"too many debug connections, try again later\n");
+            }
+            continue;
+        };
         info!("Debug client connected from {}", addr);
 
         let state = state.clone();
         let config_path = config_path.clone();
         let cmd_tx = cmd_tx.clone();
+        let tls = tls.clone();
+        let limits = limits.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, state, config_path, cmd_tx).await {
+            let _slot = slot;
+            let result = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => handle_client(stream, addr, state, config_path, cmd_tx, limits).await,
+                    Err(e) => Err(format!("TLS handshake failed: {}", e).into()),
+                },
+                None => handle_client(stream, addr, state, config_path, cmd_tx, limits).await,
+            };
+            if let Err(e) = result {
                 info!("Debug client {} disconnected: {}", addr, e);
             }
         });
     }
 }
 
-async fn handle_client(
-    stream: tokio::net::TcpStream,
+async fn handle_client<S: AsyncRead + AsyncWrite + Send>(
+    stream: S,
+    peer: SocketAddr,
     state: Arc<Mutex<HrmState>>,
     config_path: String,
     cmd_tx: mpsc::Sender<HrmCommand>,
+    limits: Limits,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (reader, mut writer) = stream.into_split();
-    let mut lines = BufReader::new(reader).lines();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut console = Console::new(reader, "hrm-debug> ");
+    let mut rate = limits.command_rate();
+    let origin = Origin::Debug(peer.to_string());
+    let audit = state.lock().await.audit.clone();
 
     writer
         .write_all(b"hrm-debug> connected. type 'help' for commands.\n")
         .await?;
 
     loop {
-        writer.write_all(b"hrm-debug> ").await?;
-
-        match lines.next_line().await? {
-            Some(line) => {
-                let line = line.trim().to_lowercase();
+        let Some(read) = limits.until_idle(console.read_line(&mut writer)).await else {
+            let _ = writer.write_all(b"\nidle too long, closing\n").await;
+            return Err("idle timeout".into());
+        };
+        match read? {
+            Some(raw) => {
+                let raw = raw.trim();
+                let line = raw.to_lowercase();
                 if line.is_empty() {
                     continue;
                 }
 
+                rate.wait().await;
+                {
+                    let s = state.lock().await;
+                    s.health.touch("debug");
+                    s.health.count(Counter::Commands);
+                }
                 let response = match line.split_once(' ') {
-                    Some(("connect", addr)) => handle_connect(addr.trim(), &cmd_tx).await,
+                    Some(("connect", addr)) => match config::resolve_connect(&config_path, addr.trim()) {
+                        Ok(addr) => {
+                            audit.record(&origin, format!("connect {}", addr));
+                            handle_connect(&addr, &cmd_tx).await
+                        }
+                        Err(e) => Ok(format!("error: {}", e)),
+                    },
+                    Some(("history", n)) => Ok(handle_history(n.trim(), &state, &config_path).await),
+                    Some(("state", "json")) => Ok(handle_state_json(&state, &config_path).await),
+                    Some(("mode", mode)) => match Mode::parse(mode.trim()) {
+                        Some(mode) => {
+                            console.set_mode(mode, &mut writer).await?;
+                            Ok(format!("mode: {}", mode.name()))
+                        }
+                        None => Ok("usage: mode [plain|raw|edit]".to_string()),
+                    },
+                    Some(("devices", "watch")) => {
+                        handle_devices_watch(&state, &config_path, &mut writer).await?;
+                        continue;
+                    }
                     Some(("mock", arg)) => handle_mock(arg.trim(), &state).await,
+                    Some(("gattdump", addr)) => scanner::gatt_dump(addr.trim(), &config_path).await,
+                    // Nicknames keep their case, so take args from the raw line
+                    Some(("nickname", _)) => {
+                        handle_nickname(raw["nickname".len()..].trim(), &state, &config_path).await
+                    }
                     _ => match line.as_str() {
                         "help" => Ok(HELP_TEXT.to_string()),
+                        "mode" => Ok(format!("mode: {}", console.mode().name())),
                         "state" => handle_state(&state, &config_path).await,
-                        "scan" => handle_scan(&cmd_tx).await,
-                        "disconnect" => handle_disconnect(&cmd_tx).await,
-                        "forget" => handle_forget(&cmd_tx).await,
+                        "scan" => {
+                            audit.record(&origin, "scan");
+                            handle_scan(&cmd_tx).await
+                        }
+                        "disconnect" => {
+                            audit.record(&origin, "disconnect");
+                            handle_disconnect(&cmd_tx).await
+                        }
+                        "forget" => {
+                            audit.record(&origin, "forget");
+                            handle_forget(&cmd_tx).await
+                        }
+                        "history" => Ok(handle_history("", &state, &config_path).await),
+                        "saved" => handle_saved(&config_path),
+                        "devices" => handle_devices(&state).await,
+                        "stats" => handle_stats(&state, &config_path).await,
                         "mock" => Ok("usage: mock <bpm> or mock off".to_string()),
+                        "gattdump" => Ok("usage: gattdump <address>".to_string()),
+                        "nickname" => Ok("usage: nickname <address> [name]".to_string()),
                         "sub" => {
                             handle_subscribe(&state, &mut writer).await?;
                             continue;
@@ -98,15 +198,8 @@ async fn handle_client(
                 };
 
                 match response {
-                    Ok(msg) => {
-                        writer.write_all(msg.as_bytes()).await?;
-                        writer.write_all(b"\n").await?;
-                    }
-                    Err(e) => {
-                        writer
-                            .write_all(format!("error: {}\n", e).as_bytes())
-                            .await?;
-                    }
+                    Ok(msg) => console.respond(&mut writer, &msg).await?,
+                    Err(e) => console.respond(&mut writer, &format!("error: {}", e)).await?,
                 }
             }
             None => return Ok(()),
@@ -114,6 +207,31 @@ async fn handle_client(
     }
 }
 
+/// `state json`: HR and device info as one JSON line, plus what `--check`
+/// needs to tell a healthy scanner from a wedged one.
+async fn handle_state_json(state: &Arc<Mutex<HrmState>>, config_path: &str) -> String {
+    let s = state.lock().await;
+    let cfg = config::load(config_path).unwrap_or_default();
+    let now = std::time::Instant::now();
+    let msg = serde_json::json!({
+        "bpm": s.heart_rate,
+        "connected": s.connected,
+        "stale": s.is_stale(now),
+        "device": s.device_name,
+        "address": s.device_address,
+        "scanning": s.scanning,
+        "adapter": s.adapter,
+        "scanner_idle_secs": s.health.idle(&["scanner", "strap"]).as_secs(),
+        "alert": s.health.alert(),
+        "saved": (!cfg.address.is_empty()).then(|| serde_json::json!({
+            "name": cfg.nickname_for(&cfg.address).unwrap_or(cfg.name.as_str()),
+            "address": cfg.address,
+        })),
+        "saved_count": cfg.saved.len(),
+    });
+    msg.to_string()
+}
+
 async fn handle_state(
     state: &Arc<Mutex<HrmState>>,
     config_path: &str,
@@ -121,35 +239,129 @@ async fn handle_state(
     let s = state.lock().await;
     let saved = config::load(config_path);
     let saved_info = match saved {
-        Some(cfg) => format!("{} ({})", cfg.name, cfg.address),
-        None => "none".to_string(),
+        Some(cfg) if !cfg.address.is_empty() => format!("{} ({})", cfg.name, cfg.address),
+        _ => "none".to_string(),
+    };
+
+    let now = std::time::Instant::now();
+    let last_hr = match s.sample_age(now) {
+        Some(age) if s.is_stale(now) => format!("{:.1}s ago (STALE)", age.as_secs_f64()),
+        Some(age) => format!("{:.1}s ago", age.as_secs_f64()),
+        None => "-".to_string(),
     };
 
     let mut out = format!(
         "heart_rate: {} bpm\n\
          connected:  {}\n\
+         last HR:    {}\n\
          device:     {}\n\
+         nickname:   {}\n\
          address:    {}\n\
          scanning:   {}\n\
          saved:      {}",
         s.heart_rate,
         s.connected,
+        last_hr,
         if s.device_name.is_empty() { "-" } else { &s.device_name },
+        if s.device_nickname.is_empty() { "-" } else { &s.device_nickname },
         if s.device_address.is_empty() { "-" } else { &s.device_address },
         s.scanning,
         saved_info,
     );
 
+    if let Some(alert) = s.health.alert() {
+        out.push_str(&format!("\nALERT:      {}", alert));
+    }
+
     if !s.available_devices.is_empty() {
         out.push_str("\navailable devices:");
         for d in &s.available_devices {
-            out.push_str(&format!("\n  {} - {} (RSSI: {})", d.address, d.name, d.rssi));
+            out.push_str(&format!("\n  {}", describe_device(d)));
         }
     }
 
     Ok(out)
 }
 
+/// `AA:BB:... - name (RSSI: -60) battery 90% [saved]`, then any
+/// manufacturer data on lines of their own.
+fn describe_device(d: &BleDevice) -> String {
+    let name = d.nickname.as_deref().unwrap_or(&d.name);
+    let mut out = format!("{} - {} (RSSI: {})", d.address, name, d.rssi);
+    if let Some(level) = d.battery {
+        out.push_str(&format!(" battery {}%", level));
+    }
+    if d.saved {
+        out.push_str(" [saved]");
+    }
+    for (company, data) in &d.manufacturer_data {
+        out.push_str(&format!("\n      mfr {}: {}", company, data));
+    }
+    out
+}
+
+/// `devices`: the HR devices from the latest scan.
+async fn handle_devices(state: &Arc<Mutex<HrmState>>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let s = state.lock().await;
+    if s.scanning {
+        return Ok("scan in progress ('devices watch' to follow it)".to_string());
+    }
+    if s.available_devices.is_empty() {
+        return Ok("no devices from the last scan".to_string());
+    }
+    Ok(s.available_devices.iter().map(describe_device).collect::<Vec<_>>().join("\n"))
+}
+
+/// `devices watch`: stream discovery events from every scan until the
+/// client disconnects.
+async fn handle_devices_watch(
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (mut events, scanning) = {
+        let s = state.lock().await;
+        (s.device_events.subscribe(), s.scanning)
+    };
+    writer
+        .write_all(
+            format!(
+                "watching device discovery ({}). ctrl-c to stop.\n",
+                if scanning { "scan in progress" } else { "waiting for the next scan; 'scan' starts one" }
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    // Nicknames and saved flags are applied after a scan; look them up here
+    let cfg = config::load(config_path);
+    let annotate = |mut d: BleDevice| {
+        if let Some(cfg) = &cfg {
+            d.nickname = cfg.nickname_for(&d.address).map(str::to_string);
+            d.saved = cfg.saved_device(&d.address).is_some();
+        }
+        d
+    };
+    loop {
+        let line = match events.recv().await {
+            Ok(DeviceEvent::ScanStarted) => "scan started".to_string(),
+            Ok(DeviceEvent::Found(d)) => format!("found   {}", describe_device(&annotate(d))),
+            Ok(DeviceEvent::Updated(d)) => {
+                let d = annotate(d);
+                format!("update  {} - {} (RSSI: {})", d.address, d.nickname.as_deref().unwrap_or(&d.name), d.rssi)
+            }
+            Ok(DeviceEvent::Lost(address)) => format!("lost    {}", address),
+            Ok(DeviceEvent::ScanFinished(n)) => format!("scan finished: {} HR device(s)", n),
+            Err(broadcast::error::RecvError::Lagged(n)) => format!("(missed {} events)", n),
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if writer.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
 async fn handle_scan(
     cmd_tx: &mpsc::Sender<HrmCommand>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
@@ -162,7 +374,7 @@ async fn handle_connect(
     cmd_tx: &mpsc::Sender<HrmCommand>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     if addr.is_empty() {
-        return Ok("usage: connect <address>".to_string());
+        return Ok("usage: connect <address|last>".to_string());
     }
     let _ = cmd_tx.send(HrmCommand::Connect(addr.to_string())).await;
     Ok(format!("connecting to {}...", addr))
@@ -184,7 +396,9 @@ async fn handle_mock(
         s.connected = false;
         s.heart_rate = 0;
         s.device_name.clear();
+        s.device_nickname.clear();
         s.device_address.clear();
+        s.last_sample = None;
         return Ok("mock off — state reset to disconnected".to_string());
     }
 
@@ -198,6 +412,7 @@ async fn handle_mock(
                 s.device_address = "00:00:00:00:00:00".to_string();
             }
             s.scanning = false;
+            s.last_sample = None; // mock has no strap behind it, never stale
             Ok(format!("mock: HR set to {} bpm (device: {})", bpm, s.device_name))
         }
         Err(_) => Ok("usage: mock <bpm> or mock off".to_string()),
@@ -211,23 +426,165 @@ async fn handle_forget(
     Ok("forget + disconnect requested".to_string())
 }
 
+async fn handle_nickname(
+    args: &str,
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let (addr, nickname) = args.split_once(' ').unwrap_or((args, ""));
+    if addr.is_empty() {
+        return Ok("usage: nickname <address> [name]".to_string());
+    }
+    scanner::set_nickname(state, config_path, addr, nickname).await?;
+    Ok(match nickname.trim() {
+        "" => format!("nickname cleared for {}", addr),
+        n => format!("{} is now \"{}\"", addr, n),
+    })
+}
+
+fn handle_saved(config_path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(cfg) = config::load(config_path).filter(|c| !c.saved.is_empty()) else {
+        return Ok("no saved devices".to_string());
+    };
+
+    let now = config::unix_now();
+    let mut out = match cfg.forget_after_days {
+        0 => "saved devices (auto-forget off):".to_string(),
+        n => format!("saved devices (auto-forget after {} days):", n),
+    };
+    for d in &cfg.saved {
+        let preferred = if d.address.eq_ignore_ascii_case(&cfg.address) { " *" } else { "" };
+        let nickname = d.nickname.as_ref().map(|n| format!(" \"{}\"", n)).unwrap_or_default();
+        out.push_str(&format!(
+            "\n  {}{} - {}{}  last connected {}{}",
+            d.address,
+            if d.private { " (identity)" } else { "" },
+            if d.name.is_empty() { "Unknown" } else { &d.name },
+            nickname,
+            format_age(now.saturating_sub(d.last_connected)),
+            preferred,
+        ));
+    }
+    Ok(out)
+}
+
+/// Recent daily resting HRs with 7- and 30-day averages.
+fn resting_hr_summary(cfg: &config::HrmConfig) -> String {
+    if cfg.resting_hr.is_empty() {
+        return "\nresting HR: none recorded yet".to_string();
+    }
+    let avg = |days| resting::average(&cfg.resting_hr, days).map(|a| format!("{:.1}", a)).unwrap_or_default();
+    let mut out = format!("\nresting HR: 7-day avg {}  30-day avg {}", avg(7), avg(30));
+    for (date, bpm) in cfg.resting_hr.iter().rev().take(7) {
+        out.push_str(&format!("\n  {}  {} bpm", date, bpm));
+    }
+    out
+}
+
+async fn handle_stats(
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut out = state.lock().await.health.report();
+    let cfg = config::load(config_path).unwrap_or_default();
+    out.push_str(&resting_hr_summary(&cfg));
+    if cfg.saved.is_empty() {
+        out.push_str("\nno saved devices");
+        return Ok(out);
+    }
+
+    out.push_str("\nconnection stats:");
+    for d in &cfg.saved {
+        let st = &d.stats;
+        let attempts = st.connects + st.failures;
+        out.push_str(&format!(
+            "\n  {} - {}  connects {}  failures {} ({}%)  avg session {}  battery {}",
+            d.address,
+            d.nickname.as_deref().unwrap_or(if d.name.is_empty() { "Unknown" } else { &d.name }),
+            st.connects,
+            st.failures,
+            (st.failures * 100).checked_div(attempts).unwrap_or(0),
+            st.average_session_secs().map(format_duration).unwrap_or_else(|| "--".to_string()),
+            st.battery.map(|b| format!("{}%", b)).unwrap_or_else(|| "--".to_string()),
+        ));
+    }
+    Ok(out)
+}
+
+/// Render a duration in seconds as e.g. "42s", "12m", "1h05m".
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Render an age in seconds as a short "N units ago" string.
+/// `history [n]`: the last n device commands (default 20), oldest first,
+/// with who sent them.
+async fn handle_history(args: &str, state: &Arc<Mutex<HrmState>>, config_path: &str) -> String {
+    let n = match args {
+        "" => 20,
+        n => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return "usage: history [n]".to_string(),
+        },
+    };
+    let records = state.lock().await.audit.recent(n);
+    if records.is_empty() {
+        return "no device commands yet".to_string();
+    }
+    let timezone = config::load(config_path).and_then(|c| c.timezone);
+    let lines: Vec<String> = records
+        .iter()
+        .map(|r| {
+            let (date, minute) = resting::local_date_minute(timezone.as_deref(), r.time);
+            format!(
+                "{} {:02}:{:02}:{:02}  {:<32}  {}",
+                date,
+                minute / 60,
+                minute % 60,
+                r.time % 60,
+                r.origin.to_string(),
+                r.action
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86_399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
 async fn handle_subscribe(
     state: &Arc<Mutex<HrmState>>,
-    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    writer: &mut (impl AsyncWrite + Unpin),
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut updates = state.lock().await.hr_updates.subscribe();
     writer
         .write_all(b"subscribed to HR data at 1 Hz. ctrl-c to stop.\n")
         .await?;
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
     loop {
-        interval.tick().await;
-
-        let s = state.lock().await;
+        let s = match updates.recv().await {
+            Ok(snapshot) => snapshot,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
         let line = if s.connected {
             format!(
-                "hr {} bpm | {} ({})\n",
-                s.heart_rate, s.device_name, s.device_address
+                "hr {} bpm{} | {} ({})\n",
+                s.bpm,
+                if s.stale { " (stale)" } else { "" },
+                if s.nickname.is_empty() { &s.device } else { &s.nickname },
+                s.address
             )
         } else {
             format!(
@@ -235,7 +592,6 @@ async fn handle_subscribe(
                 s.scanning
             )
         };
-        drop(s);
 
         if writer.write_all(line.as_bytes()).await.is_err() {
             break;
@@ -247,14 +603,32 @@ async fn handle_subscribe(
 
 const HELP_TEXT: &str = "\
 commands:
-  state           show current HR + device state
+  state [json]    show current HR + device state (json: one line, with
+                  the adapter and scanner activity, for --check)
   sub             subscribe to 1 Hz HR stream
   scan            trigger BLE scan for HR devices
-  connect <addr>  connect to device by BLE address
+  devices         HR devices found by the last scan
+  devices watch   stream scan events live: scan started/finished, found,
+                  RSSI or name updates, lost (out of range)
+  connect <addr>  connect to device by BLE address, or `last` for the
+                  most recently connected one
+  gattdump <addr> connect and list all services/characteristics/descriptors
   disconnect      disconnect from current device
   forget          forget saved device + disconnect
+  saved           list saved devices, last-connected time (* = preferred)
+  history [n]     last n connect/disconnect/forget/scan commands (default
+                  20) with time and sender (socket uid/pid or debug peer)
+  stats           uptime, last activity per task, daemon counters, recent
+                  resting HR, then connects, failures, average session,
+                  battery per saved device
+  nickname <addr> [name]
+                  set a saved device's nickname (omit name to clear)
   mock <bpm>      fake a connected HRM at given BPM (no hardware needed)
   mock off        stop mocking, revert to disconnected
+  mode [plain|raw|edit]
+                  plain: prompt after each response (default); raw: no
+                  prompt, a blank line ends each response (rlwrap, scripts);
+                  edit: line editing + Up/Down history (telnet clients)
   help            this message
   quit            disconnect
 
@@ -262,5 +636,6 @@ examples:
   mock 142         simulate 142 bpm heart rate
   mock off         stop simulating
   connect AA:BB:CC:DD:EE:FF
+  nickname AA:BB:CC:DD:EE:FF Dad's Polar
   scan
   state";
@@ -0,0 +1,436 @@
+//! Socket framing: JSON lines, or length-prefixed protobuf.
+//!
+//! Clients start out on newline-delimited JSON. Sending
+//! `{"cmd":"framing","mode":"protobuf"}` switches the connection, after the
+//! `{"type":"framing","mode":"protobuf"}` reply, to `SocketCommand` and
+//! `SocketMessage` from `proto/precor.proto`, each preceded by its length
+//! as a 4-byte big-endian integer. Non-Rust clients get generated types
+//! instead of guessing at JSON shapes.
+//!
+//! The server still builds every message as JSON; `encode` maps it onto
+//! the protobuf types by field name, and incoming protobuf commands are
+//! turned back into the JSON the command handler takes, so both framings
+//! go through the same code. The tests check the field names stay in step.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::grpc::{ConnectRequest, Empty};
+
+/// Longest protobuf frame accepted from a client. Commands are tiny.
+const MAX_FRAME: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    #[default]
+    JsonLines,
+    Protobuf,
+}
+
+impl Framing {
+    /// The `mode` of a `framing` command.
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "json" => Some(Framing::JsonLines),
+            "protobuf" => Some(Framing::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn mode(self) -> &'static str {
+        match self {
+            Framing::JsonLines => "json",
+            Framing::Protobuf => "protobuf",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SocketCommand {
+    #[prost(oneof = "Cmd", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
+    pub cmd: Option<Cmd>,
+}
+
+/// Serializes to the JSON command, e.g. `{"cmd":"connect","address":...}`.
+#[derive(Clone, PartialEq, prost::Oneof, Serialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Cmd {
+    #[prost(message, tag = "1")]
+    Connect(ConnectRequest),
+    #[prost(message, tag = "2")]
+    Disconnect(Empty),
+    #[prost(message, tag = "3")]
+    Forget(Empty),
+    #[prost(message, tag = "4")]
+    Scan(Empty),
+    #[prost(message, tag = "5")]
+    Nickname(NicknameRequest),
+    #[prost(message, tag = "6")]
+    Status(Empty),
+    #[prost(message, tag = "7")]
+    RestingHr(RestingHrRequest),
+    #[prost(message, tag = "8")]
+    Subscribe(Topics),
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+pub struct NicknameRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub nickname: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+pub struct RestingHrRequest {
+    /// 0 leaves `days` out of the JSON, so the handler's default applies.
+    #[prost(uint32, tag = "1")]
+    #[serde(skip_serializing_if = "is_zero")]
+    pub days: u32,
+}
+
+/// A `subscribe` command's topics, and the `subscribed` reply's.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Topics {
+    #[prost(string, repeated, tag = "1")]
+    pub topics: Vec<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SocketMessage {
+    #[prost(oneof = "Msg", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub msg: Option<Msg>,
+}
+
+/// Deserializes from the JSON message by its `type`.
+#[derive(Clone, PartialEq, prost::Oneof, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Msg {
+    #[prost(message, tag = "1")]
+    Hr(HrUpdate),
+    #[prost(message, tag = "2")]
+    Status(HrUpdate),
+    #[prost(message, tag = "3")]
+    RestingHr(RestingHr),
+    #[prost(message, tag = "4")]
+    Error(Error),
+    #[prost(message, tag = "5")]
+    Subscribed(Topics),
+    #[prost(message, tag = "6")]
+    Device(DeviceUpdate),
+    #[prost(message, tag = "7")]
+    Connection(ConnectionUpdate),
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct HrUpdate {
+    #[prost(uint32, tag = "1")]
+    pub bpm: u32,
+    #[prost(bool, tag = "2")]
+    pub connected: bool,
+    #[prost(bool, tag = "3")]
+    pub stale: bool,
+    #[prost(uint64, optional, tag = "4")]
+    pub last_sample_age_ms: Option<u64>,
+    #[prost(string, tag = "5")]
+    pub device: String,
+    #[prost(string, tag = "6")]
+    pub nickname: String,
+    #[prost(string, tag = "7")]
+    pub address: String,
+    #[prost(bool, tag = "8")]
+    pub scanning: bool,
+    #[prost(message, repeated, tag = "9")]
+    pub available_devices: Vec<BleDevice>,
+    #[prost(uint64, optional, tag = "10")]
+    pub sample_mono_ms: Option<u64>,
+    #[prost(string, optional, tag = "11")]
+    pub sample_time: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct BleDevice {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(sint32, tag = "3")]
+    pub rssi: i32,
+    #[prost(string, optional, tag = "4")]
+    pub nickname: Option<String>,
+    #[prost(bool, tag = "5")]
+    pub saved: bool,
+    #[prost(uint32, optional, tag = "6")]
+    pub battery: Option<u32>,
+    #[prost(btree_map = "string, string", tag = "7")]
+    pub manufacturer_data: BTreeMap<String, String>,
+    #[prost(btree_map = "string, string", tag = "8")]
+    pub service_data: BTreeMap<String, String>,
+}
+
+/// A scan event for the `devices` topic. Which fields are set depends on
+/// `event`: `found`/`updated` carry `device`, `lost` the `address`,
+/// `scan_finished` the `count`.
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct DeviceUpdate {
+    #[prost(string, tag = "1")]
+    pub event: String,
+    #[prost(message, optional, tag = "2")]
+    pub device: Option<BleDevice>,
+    #[prost(string, tag = "3")]
+    pub address: String,
+    #[prost(uint32, tag = "4")]
+    pub count: u32,
+}
+
+/// A strap connection event for the `connection` topic.
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct ConnectionUpdate {
+    #[prost(string, tag = "1")]
+    pub event: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub address: String,
+    #[prost(string, tag = "4")]
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct RestingHr {
+    #[prost(uint32, optional, tag = "1")]
+    pub today: Option<u32>,
+    #[prost(double, optional, tag = "2")]
+    pub avg_7d: Option<f64>,
+    #[prost(double, optional, tag = "3")]
+    pub avg_30d: Option<f64>,
+    #[prost(message, repeated, tag = "4")]
+    pub days: Vec<RestingDay>,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct RestingDay {
+    #[prost(string, tag = "1")]
+    pub date: String,
+    #[prost(uint32, tag = "2")]
+    pub bpm: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Deserialize)]
+#[serde(default)]
+pub struct Error {
+    #[prost(string, tag = "1")]
+    pub message: String,
+}
+
+/// `msg` as one frame: a JSON line, or a length-prefixed `SocketMessage`.
+pub fn encode(framing: Framing, msg: &serde_json::Value) -> Result<Vec<u8>, serde_json::Error> {
+    match framing {
+        Framing::JsonLines => {
+            let mut line = serde_json::to_vec(msg)?;
+            line.push(b'\n');
+            Ok(line)
+        }
+        Framing::Protobuf => {
+            let msg = SocketMessage { msg: Some(Msg::deserialize(msg)?) };
+            let len = prost::Message::encoded_len(&msg);
+            let mut frame = Vec::with_capacity(4 + len);
+            frame.extend_from_slice(&(len as u32).to_be_bytes());
+            prost::Message::encode(&msg, &mut frame).expect("Vec grows as needed");
+            Ok(frame)
+        }
+    }
+}
+
+/// Reads commands in the connection's current framing. `next` is cancel
+/// safe, so it can sit in a `select!` next to the broadcast timer.
+pub struct FrameReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    pub framing: Framing,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, buf: Vec::new(), framing: Framing::JsonLines }
+    }
+
+    /// The next command as JSON, `Err` with a message for the client if it
+    /// doesn't parse, or `None` at EOF. Blank JSON lines are skipped.
+    pub async fn next(&mut self) -> std::io::Result<Option<Result<serde_json::Value, String>>> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                match self.framing {
+                    Framing::JsonLines => {
+                        let line = String::from_utf8_lossy(&frame);
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        return Ok(Some(serde_json::from_str(line.trim()).map_err(|e| format!("invalid JSON: {}", e))));
+                    }
+                    Framing::Protobuf => return Ok(Some(decode_command(&frame))),
+                }
+            }
+            if self.reader.read_buf(&mut self.buf).await? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Split one complete frame off the front of the buffer, if there is one.
+    fn take_frame(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        match self.framing {
+            Framing::JsonLines => Ok(self.buf.iter().position(|&b| b == b'\n').map(|end| {
+                let mut line: Vec<u8> = self.buf.drain(..=end).collect();
+                line.pop();
+                line
+            })),
+            Framing::Protobuf => {
+                let Some(len) = self.buf.first_chunk::<4>().map(|len| u32::from_be_bytes(*len) as usize) else {
+                    return Ok(None);
+                };
+                if len > MAX_FRAME {
+                    let msg = format!("{} byte frame, limit is {}", len, MAX_FRAME);
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+                }
+                if self.buf.len() < 4 + len {
+                    return Ok(None);
+                }
+                Ok(Some(self.buf.drain(..4 + len).skip(4).collect()))
+            }
+        }
+    }
+}
+
+fn decode_command(frame: &[u8]) -> Result<serde_json::Value, String> {
+    let cmd = <SocketCommand as prost::Message>::decode(frame).map_err(|e| format!("invalid protobuf: {}", e))?;
+    let cmd = cmd.cmd.ok_or("empty command")?;
+    serde_json::to_value(cmd).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_messages_match_proto() {
+        let proto = include_str!("../../proto/precor.proto");
+        for field in [
+            "ConnectRequest connect = 1;",
+            "Empty disconnect = 2;",
+            "NicknameRequest nickname = 5;",
+            "RestingHrRequest resting_hr = 7;",
+            "Topics subscribe = 8;",
+            "repeated string topics = 1;",
+            "Topics subscribed = 5;",
+            "DeviceUpdate device = 6;",
+            "ConnectionUpdate connection = 7;",
+            "BleDevice device = 2;",
+            "uint32 count = 4;",
+            "string error = 4;",
+            "string nickname = 2;",
+            "uint32 days = 1;",
+            "HrUpdate hr = 1;",
+            "HrUpdate status = 2;",
+            "RestingHr resting_hr = 3;",
+            "Error error = 4;",
+            "optional uint64 last_sample_age_ms = 4;",
+            "string address = 7;",
+            "repeated BleDevice available_devices = 9;",
+            "optional uint64 sample_mono_ms = 10;",
+            "optional string sample_time = 11;",
+            "sint32 rssi = 3;",
+            "optional uint32 battery = 6;",
+            "map<string, string> service_data = 8;",
+            "optional double avg_30d = 3;",
+            "repeated RestingDay days = 4;",
+        ] {
+            assert!(proto.contains(field), "proto/precor.proto lacks {:?}", field);
+        }
+    }
+
+    #[test]
+    fn test_encode_status() {
+        let msg = serde_json::json!({
+            "type": "status",
+            "bpm": 142,
+            "connected": true,
+            "stale": false,
+            "last_sample_age_ms": null,
+            "device": "Polar H10",
+            "nickname": "",
+            "address": "AA:BB:CC:DD:EE:FF",
+            "scanning": false,
+            "available_devices": [{ "address": "AA:BB:CC:DD:EE:FF", "name": "Polar H10", "rssi": -60, "saved": true, "battery": 80 }],
+        });
+        assert_eq!(encode(Framing::JsonLines, &msg).unwrap(), [serde_json::to_vec(&msg).unwrap(), b"\n".to_vec()].concat());
+
+        let frame = encode(Framing::Protobuf, &msg).unwrap();
+        assert_eq!(u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize, frame.len() - 4);
+        let Some(Msg::Status(status)) = SocketMessage::decode(&frame[4..]).unwrap().msg else {
+            panic!("not a status message");
+        };
+        assert_eq!((status.bpm, status.device.as_str(), status.last_sample_age_ms), (142, "Polar H10", None));
+        assert_eq!((status.available_devices[0].rssi, status.available_devices[0].battery), (-60, Some(80)));
+        assert!(encode(Framing::Protobuf, &serde_json::json!({ "type": "nope" })).is_err());
+    }
+
+    #[test]
+    fn test_encode_topic_messages() {
+        let found = serde_json::json!({
+            "type": "device",
+            "event": "found",
+            "device": { "address": "AA:BB:CC:DD:EE:FF", "name": "Polar H10", "rssi": -60, "saved": false },
+        });
+        let frame = encode(Framing::Protobuf, &found).unwrap();
+        let Some(Msg::Device(update)) = SocketMessage::decode(&frame[4..]).unwrap().msg else {
+            panic!("not a device message");
+        };
+        assert_eq!((update.event.as_str(), update.device.unwrap().rssi), ("found", -60));
+
+        let failed = serde_json::json!({ "type": "connection", "event": "failed", "address": "AA:BB", "error": "Page Timeout" });
+        let frame = encode(Framing::Protobuf, &failed).unwrap();
+        let Some(Msg::Connection(update)) = SocketMessage::decode(&frame[4..]).unwrap().msg else {
+            panic!("not a connection message");
+        };
+        assert_eq!((update.event.as_str(), update.error.as_str(), update.name.as_str()), ("failed", "Page Timeout", ""));
+    }
+
+    #[tokio::test]
+    async fn test_frame_reader_switches_framing() {
+        let connect = SocketCommand { cmd: Some(Cmd::Connect(ConnectRequest { address: "AA:BB".into() })) };
+        let resting = SocketCommand { cmd: Some(Cmd::RestingHr(RestingHrRequest { days: 0 })) };
+        let mut input = b"\n{\"cmd\":\"framing\",\"mode\":\"protobuf\"}\n".to_vec();
+        for cmd in [connect, resting] {
+            input.extend_from_slice(&(cmd.encoded_len() as u32).to_be_bytes());
+            input.extend_from_slice(&cmd.encode_to_vec());
+        }
+        input.extend_from_slice(&[0, 0, 0, 2, 0xff, 0xff]);
+
+        let mut frames = FrameReader::new(&input[..]);
+        assert_eq!(frames.next().await.unwrap(), Some(Ok(serde_json::json!({ "cmd": "framing", "mode": "protobuf" }))));
+        frames.framing = Framing::Protobuf;
+        assert_eq!(frames.next().await.unwrap(), Some(Ok(serde_json::json!({ "cmd": "connect", "address": "AA:BB" }))));
+        assert_eq!(frames.next().await.unwrap(), Some(Ok(serde_json::json!({ "cmd": "resting_hr" }))));
+        assert!(frames.next().await.unwrap().unwrap().unwrap_err().starts_with("invalid protobuf"));
+        assert_eq!(frames.next().await.unwrap(), None);
+
+        let mut huge = FrameReader::new(&[0xff, 0, 0, 0][..]);
+        huge.framing = Framing::Protobuf;
+        assert!(huge.next().await.is_err());
+    }
+}
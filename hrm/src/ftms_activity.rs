@@ -0,0 +1,60 @@
+//! Adapter sharing with ftms-daemon.
+//!
+//! Both daemons normally use the same hci adapter, and discovery on it
+//! competes with the FTMS advertising and with apps connected to the
+//! treadmill. ftms-daemon keeps a small JSON file (`ftms_activity_file`,
+//! `{"centrals":1,"wall_ms":...}`) refreshed every few seconds while it
+//! runs; the scanner reads it before each scan and yields per
+//! `ftms_busy_scan`. A missing, unreadable or stale file counts as idle, so
+//! a crashed ftms-daemon never blocks discovery.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use serde::Deserialize;
+
+/// A file not rewritten for this long is left over from a dead daemon.
+const STALE_AFTER_MS: u64 = 15_000;
+
+#[derive(Deserialize)]
+struct Activity {
+    centrals: usize,
+    wall_ms: u64,
+}
+
+/// Apps connected to ftms-daemon right now, per its activity file.
+pub fn ftms_centrals(path: &str) -> usize {
+    let Ok(data) = std::fs::read_to_string(path) else {
+        return 0;
+    };
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    parse(&data, now_ms)
+}
+
+fn parse(data: &str, now_ms: u64) -> usize {
+    match serde_json::from_str::<Activity>(data) {
+        Ok(a) if now_ms.saturating_sub(a.wall_ms) <= STALE_AFTER_MS => a.centrals,
+        Ok(_) => 0,
+        Err(e) => {
+            debug!("Ignoring unreadable ftms activity file: {}", e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_activity() {
+        let data = r#"{"centrals":2,"wall_ms":1700000000000}"#;
+        assert_eq!(parse(data, 1_700_000_005_000), 2);
+        assert_eq!(parse(data, 1_700_000_000_000 + STALE_AFTER_MS + 1), 0, "stale");
+        assert_eq!(parse("{", 1_700_000_000_000), 0);
+        assert_eq!(ftms_centrals("/tmp/hrm_test_no_such_activity.json"), 0);
+    }
+}
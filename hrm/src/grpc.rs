@@ -0,0 +1,303 @@
+//! Optional gRPC API, for companion apps that would rather have typed
+//! clients than JSON lines over the socket.
+//!
+//! With `grpc_addr` set, serves the `HeartRate` service from
+//! `proto/precor.proto`: state snapshots, a state stream, and connect,
+//! disconnect, forget and scan. Commands go to the scanner like the
+//! socket's do, and the audit trail records the caller as
+//! `grpc <ip:port>`.
+//!
+//! `build.rs` generates the service plumbing; the messages below mirror the
+//! .proto by hand (no protoc needed), and `test_messages_match_proto` keeps
+//! the two from drifting apart.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+use log::{info, warn};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::{Request, Response, Status};
+
+use crate::audit::Origin;
+use crate::throttle::{Limits, Slot};
+use crate::scanner::{HrmCommand, HrmState};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/precor.v1.HeartRate.rs"));
+}
+
+use generated::heart_rate_server::{HeartRate, HeartRateServer};
+
+/// Fastest `WatchState` period; the strap itself notifies about 1 Hz.
+const MIN_WATCH_INTERVAL_MS: u64 = 250;
+
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {
+    #[prost(uint32, tag = "1")]
+    pub interval_ms: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HeartRateState {
+    #[prost(uint32, tag = "1")]
+    pub bpm: u32,
+    #[prost(bool, tag = "2")]
+    pub connected: bool,
+    #[prost(bool, tag = "3")]
+    pub stale: bool,
+    #[prost(string, tag = "4")]
+    pub device: String,
+    #[prost(string, tag = "5")]
+    pub nickname: String,
+    #[prost(string, tag = "6")]
+    pub address: String,
+    #[prost(bool, tag = "7")]
+    pub scanning: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+pub struct ConnectRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+}
+
+/// Bind `addr` (e.g. `0.0.0.0:8837`). Done before `run` so privileges can
+/// be dropped in between.
+pub async fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("gRPC server listening on {}", addr);
+    Ok(listener)
+}
+
+/// Serve the HeartRate service until the listener fails, to at most
+/// `limits`' connections at once.
+pub async fn run(
+    state: Arc<Mutex<HrmState>>,
+    listener: TcpListener,
+    limits: Limits,
+    cmd_tx: mpsc::Sender<HrmCommand>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    tonic::transport::Server::builder()
+        .add_service(HeartRateServer::new(Service { state, cmd_tx }))
+        .serve_with_incoming(incoming(listener, limits))
+        .await?;
+    Ok(())
+}
+
+/// An accepted connection, holding its slot until tonic drops it.
+struct Pooled {
+    stream: TcpStream,
+    _slot: Slot,
+}
+
+impl AsyncRead for Pooled {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Pooled {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected for Pooled {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> TcpConnectInfo {
+        self.stream.connect_info()
+    }
+}
+
+/// Connections from `listener`, up to `limits`' cap. Ones past it are
+/// closed straight away, which clients see as the server being unavailable.
+fn incoming(listener: TcpListener, limits: Limits) -> impl Stream<Item = std::io::Result<Pooled>> {
+    futures::stream::unfold((listener, limits), |(listener, limits)| async move {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => return Some((Err(e), (listener, limits))),
+            };
+            let Some(slot) = limits.open() else {
+                warn!("gRPC client {} refused: {} connections already open", addr, limits.max_connections());
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            return Some((Ok(Pooled { stream, _slot: slot }), (listener, limits)));
+        }
+    })
+}
+
+/// `WatchState` period: 0 is 1 s, and no faster than `MIN_WATCH_INTERVAL_MS`.
+fn watch_period(interval_ms: u32) -> Duration {
+    match interval_ms {
+        0 => Duration::from_secs(1),
+        ms => Duration::from_millis((ms as u64).max(MIN_WATCH_INTERVAL_MS)),
+    }
+}
+
+async fn snapshot(state: &Mutex<HrmState>) -> HeartRateState {
+    let s = state.lock().await;
+    HeartRateState {
+        bpm: s.heart_rate as u32,
+        connected: s.connected,
+        stale: s.is_stale(Instant::now()),
+        device: s.device_name.clone(),
+        nickname: s.device_nickname.clone(),
+        address: s.device_address.clone(),
+        scanning: s.scanning,
+    }
+}
+
+struct Service {
+    state: Arc<Mutex<HrmState>>,
+    cmd_tx: mpsc::Sender<HrmCommand>,
+}
+
+impl Service {
+    /// Audit `action` and hand `cmd` to the scanner.
+    async fn command<T>(&self, request: &Request<T>, action: String, cmd: HrmCommand) -> Result<Response<Empty>, Status> {
+        let peer = request.remote_addr().map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+        self.state.lock().await.audit.record(&Origin::Grpc(peer), action);
+        self.cmd_tx.send(cmd).await.map_err(|_| Status::unavailable("scanner not running"))?;
+        Ok(Response::new(Empty {}))
+    }
+}
+
+#[tonic::async_trait]
+impl HeartRate for Service {
+    type WatchStateStream = Pin<Box<dyn Stream<Item = Result<HeartRateState, Status>> + Send>>;
+
+    async fn get_state(&self, _request: Request<Empty>) -> Result<Response<HeartRateState>, Status> {
+        Ok(Response::new(snapshot(&self.state).await))
+    }
+
+    async fn watch_state(&self, request: Request<WatchRequest>) -> Result<Response<Self::WatchStateStream>, Status> {
+        let tick = tokio::time::interval(watch_period(request.get_ref().interval_ms));
+        let states = futures::stream::unfold((self.state.clone(), tick), |(state, mut tick)| async move {
+            tick.tick().await;
+            let snapshot = snapshot(&state).await;
+            Some((Ok(snapshot), (state, tick)))
+        });
+        Ok(Response::new(Box::pin(states)))
+    }
+
+    async fn connect(&self, request: Request<ConnectRequest>) -> Result<Response<Empty>, Status> {
+        let address = request.get_ref().address.clone();
+        if address.is_empty() {
+            return Err(Status::invalid_argument("missing address"));
+        }
+        self.command(&request, format!("connect {}", address), HrmCommand::Connect(address)).await
+    }
+
+    async fn disconnect(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        self.command(&request, "disconnect".into(), HrmCommand::Disconnect).await
+    }
+
+    async fn forget(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        self.command(&request, "forget".into(), HrmCommand::Forget).await
+    }
+
+    async fn scan(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        self.command(&request, "scan".into(), HrmCommand::Scan).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generated::heart_rate_client::HeartRateClient;
+
+    #[test]
+    fn test_messages_match_proto() {
+        let proto = include_str!("../../proto/precor.proto");
+        for field in [
+            "uint32 interval_ms = 1;",
+            "uint32 bpm = 1;",
+            "bool connected = 2;",
+            "bool stale = 3;",
+            "string device = 4;",
+            "string nickname = 5;",
+            "string address = 6;",
+            "bool scanning = 7;",
+            "string address = 1;",
+            "rpc WatchState(WatchRequest) returns (stream HeartRateState);",
+            "rpc Connect(ConnectRequest) returns (Empty);",
+        ] {
+            assert!(proto.contains(field), "proto/precor.proto lacks {:?}", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_and_commands_over_grpc() {
+        let state = Arc::new(Mutex::new(HrmState {
+            heart_rate: 142,
+            connected: true,
+            device_name: "Polar H10".into(),
+            ..Default::default()
+        }));
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(4);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run(state.clone(), listener, Limits::new(1, 0, Duration::ZERO), cmd_tx));
+
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        let mut client = HeartRateClient::new(channel);
+        let hr = client.get_state(Empty {}).await.unwrap().into_inner();
+        assert_eq!((hr.bpm, hr.connected, hr.stale, hr.device.as_str()), (142, true, false, "Polar H10"));
+
+        let mut states = client.watch_state(WatchRequest { interval_ms: 0 }).await.unwrap().into_inner();
+        assert_eq!(states.message().await.unwrap().unwrap().bpm, 142);
+
+        client.connect(ConnectRequest { address: "AA:BB:CC:DD:EE:FF".into() }).await.unwrap();
+        assert!(matches!(cmd_rx.recv().await, Some(HrmCommand::Connect(addr)) if addr == "AA:BB:CC:DD:EE:FF"));
+        let status = client.connect(ConnectRequest::default()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        client.forget(Empty {}).await.unwrap();
+        assert!(matches!(cmd_rx.recv().await, Some(HrmCommand::Forget)));
+
+        let recent = state.lock().await.audit.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].origin.to_string().starts_with("grpc 127.0.0.1:"));
+        assert_eq!((recent[0].action.as_str(), recent[1].action.as_str()), ("connect AA:BB:CC:DD:EE:FF", "forget"));
+
+        // One connection allowed, and the client above holds it
+        let second = async {
+            let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))?.connect().await?;
+            HeartRateClient::new(channel).get_state(Empty {}).await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        };
+        assert!(second.await.is_err());
+    }
+}
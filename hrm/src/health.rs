@@ -0,0 +1,203 @@
+//! Daemon health for the debug `stats` command.
+//!
+//! Long-running tasks stamp their last activity here and bump a few shared
+//! counters, so `stats` shows at a glance which part of the daemon has gone
+//! quiet or is failing: a strap that stopped notifying, a scanner stuck in a
+//! connect, a climbing error count.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tasks reported by `stats`, listed even before their first activity.
+pub const TASKS: [&str; 4] = ["scanner", "strap", "server", "debug"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    /// HR notifications received from straps.
+    HrSamples,
+    /// Messages written to socket clients.
+    Messages,
+    /// Socket and debug commands handled.
+    Commands,
+    /// Strap connections made.
+    Connects,
+    /// BlueZ sessions reopened after losing D-Bus.
+    Reconnects,
+    /// BLE adapter power cycles by the watchdog.
+    PowerCycles,
+    /// Failed connects, lost sessions, unparseable HR, client write errors.
+    Errors,
+}
+
+impl Counter {
+    const ALL: [Counter; 7] = [
+        Counter::HrSamples,
+        Counter::Messages,
+        Counter::Commands,
+        Counter::Connects,
+        Counter::Reconnects,
+        Counter::PowerCycles,
+        Counter::Errors,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Counter::HrSamples => "hr_samples",
+            Counter::Messages => "messages",
+            Counter::Connects => "connects",
+            Counter::Commands => "commands",
+            Counter::Reconnects => "reconnects",
+            Counter::PowerCycles => "power_cycles",
+            Counter::Errors => "errors",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    started: Instant,
+    counters: [AtomicU64; 7],
+    tasks: Mutex<BTreeMap<&'static str, Instant>>,
+    /// Something only an operator can fix, and since when.
+    alert: Mutex<Option<(String, Instant)>>,
+}
+
+/// Cheap, cloneable handle to the daemon's health counters.
+#[derive(Debug, Clone)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                started: Instant::now(),
+                counters: Default::default(),
+                tasks: Mutex::new(BTreeMap::new()),
+                alert: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl Health {
+    /// Note that `task` just did something useful.
+    pub fn touch(&self, task: &'static str) {
+        self.inner.tasks.lock().unwrap().insert(task, Instant::now());
+    }
+
+    pub fn count(&self, counter: Counter) {
+        self.inner.counters[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.inner.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Ask for an operator's help, e.g. restarting bluetoothd. Shown by
+    /// `stats`, `state` and `--check` until cleared.
+    pub fn raise(&self, alert: &str) {
+        let mut current = self.inner.alert.lock().unwrap();
+        if current.as_ref().map(|(a, _)| a.as_str()) != Some(alert) {
+            *current = Some((alert.to_string(), Instant::now()));
+        }
+    }
+
+    /// The problem behind the alert went away.
+    pub fn clear(&self) {
+        self.inner.alert.lock().unwrap().take();
+    }
+
+    pub fn alert(&self) -> Option<String> {
+        self.inner.alert.lock().unwrap().as_ref().map(|(a, _)| a.clone())
+    }
+
+    /// When the daemon started.
+    pub fn started(&self) -> Instant {
+        self.inner.started
+    }
+
+    /// Time since any of `tasks` last did something, or since startup if
+    /// none has yet.
+    pub fn idle(&self, tasks: &[&str]) -> Duration {
+        self.idle_at(tasks, Instant::now())
+    }
+
+    fn idle_at(&self, tasks: &[&str], now: Instant) -> Duration {
+        let last = self.inner.tasks.lock().unwrap().iter().filter(|(task, _)| tasks.contains(task)).map(|(_, &at)| at).max();
+        now.saturating_duration_since(last.unwrap_or(self.inner.started))
+    }
+
+    /// Human-readable report for the debug console.
+    pub fn report(&self) -> String {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> String {
+        let mut out = format!("uptime {}", format_duration(now - self.inner.started));
+        if let Some((alert, since)) = self.inner.alert.lock().unwrap().as_ref() {
+            let _ = write!(out, "\nALERT: {} (for {})", alert, format_duration(now.saturating_duration_since(*since)));
+        }
+        out.push_str("\nlast activity:");
+        let tasks = self.inner.tasks.lock().unwrap();
+        let extra = tasks.keys().filter(|t| !TASKS.contains(t)).copied();
+        for task in TASKS.iter().copied().chain(extra) {
+            let seen = match tasks.get(task) {
+                Some(&at) => format!("{} ago", format_duration(now.saturating_duration_since(at))),
+                None => "never".to_string(),
+            };
+            let _ = write!(out, "\n  {:<16}{}", task, seen);
+        }
+        out.push_str("\ncounters:");
+        for counter in Counter::ALL {
+            let _ = write!(out, "\n  {:<16}{}", counter.name(), self.get(counter));
+        }
+        out
+    }
+}
+
+/// e.g. "42s", "12m05s", "3h02m".
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let health = Health::default();
+        health.touch("strap");
+        health.count(Counter::Commands);
+        health.count(Counter::Commands);
+        health.count(Counter::Errors);
+        let later = Instant::now() + Duration::from_secs(3725);
+        let report = health.report_at(later);
+        assert!(report.starts_with("uptime 1h02m\n"), "{}", report);
+        assert!(report.contains("\n  strap           1h02m ago"), "{}", report);
+        assert!(report.contains("\n  scanner         never"));
+        assert!(report.contains("\n  commands        2"));
+        assert!(report.contains("\n  errors          1"));
+        assert!(!report.contains("ALERT"));
+        assert_eq!(format_duration(Duration::from_secs(725)), "12m05s");
+        assert_eq!(health.idle_at(&["scanner", "strap"], later).as_secs(), 3725);
+        assert_eq!(health.idle_at(&["scanner"], later).as_secs(), 3725, "since startup");
+
+        health.raise("restart bluetoothd");
+        health.raise("restart bluetoothd");
+        assert_eq!(health.alert().as_deref(), Some("restart bluetoothd"));
+        assert!(health.report_at(later).starts_with("uptime 1h02m\nALERT: restart bluetoothd (for 1h02m)\n"));
+        health.clear();
+        assert_eq!(health.alert(), None);
+    }
+}
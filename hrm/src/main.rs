@@ -1,11 +1,23 @@
+mod audit;
+mod check;
 mod config;
 mod debug_server;
+mod framing;
+mod ftms_activity;
+mod grpc;
+mod health;
+mod resting;
+mod restart;
 mod scanner;
 mod server;
+mod watchdog;
 
+use std::os::fd::AsRawFd;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use daemon_common::{console, privileges, throttle, tls};
+
 pub use scanner::{BleDevice, HrmState};
 
 const DEFAULT_SOCKET: &str = "/tmp/hrm.sock";
@@ -16,7 +28,11 @@ const DEFAULT_DEBUG_PORT: u16 = 8827;
 async fn main() {
     env_logger::init();
 
-    let (socket_path, config_path, debug_port) = parse_args();
+    let Args { socket_path, config_path, debug_port, user, group, check } = parse_args();
+    if check {
+        let cfg = config::load(&config_path).unwrap_or_default();
+        std::process::exit(check::run(&cfg, debug_port).await);
+    }
     log::info!(
         "HRM daemon starting, socket: {}, config: {}, debug port: {}",
         socket_path,
@@ -24,11 +40,91 @@ async fn main() {
         debug_port
     );
 
-    let state = Arc::new(Mutex::new(HrmState::default()));
+    let account = match privileges::resolve(user.as_deref(), group.as_deref()) {
+        Ok(account) => account,
+        Err(e) => {
+            log::error!("Can't drop privileges: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let cfg = config::load(&config_path).unwrap_or_default();
+    let mut inherited = restart::Inherited::from_env();
+    let listener = match server::listen(&socket_path, &cfg, &config_path, &mut inherited) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Server can't listen on {}: {}", socket_path, e);
+            std::process::exit(1);
+        }
+    };
+    let debug_listener = match inherited.tcp("debug", debug_port) {
+        Some(listener) => Ok(listener),
+        None => debug_server::bind(debug_port).await,
+    };
+    let debug_listener = match debug_listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Debug server can't listen on port {}: {}", debug_port, e);
+            std::process::exit(1);
+        }
+    };
+    let tls = match cfg.debug_tls.as_ref().map(tls::acceptor).transpose() {
+        Ok(tls) => tls,
+        Err(e) => {
+            log::error!("Debug server TLS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let limits = throttle::Limits::new(
+        cfg.debug_max_connections,
+        cfg.debug_commands_per_sec,
+        std::time::Duration::from_secs(cfg.debug_idle_timeout_secs),
+    );
+    let grpc_listener = match &cfg.grpc_addr {
+        Some(addr) => match grpc_port(addr).and_then(|port| inherited.tcp("grpc", port)) {
+            Some(listener) => Some(listener),
+            None => match grpc::bind(addr).await {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    log::error!("gRPC server can't listen on {}: {}", addr, e);
+                    std::process::exit(1);
+                }
+            },
+        },
+        None => None,
+    };
+    drop(inherited);
+    let mut handover = vec![("socket", listener.as_raw_fd()), ("debug", debug_listener.as_raw_fd())];
+    if let Some(listener) = &grpc_listener {
+        handover.push(("grpc", listener.as_raw_fd()));
+    }
+    tokio::spawn(restart::on_signal(handover));
+    if let Some(account) = &account {
+        if let Err(e) = privileges::drop_to(account) {
+            log::error!("Can't drop privileges: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let state = Arc::new(Mutex::new(HrmState {
+        audit: audit::start(cfg.audit_log.as_deref()),
+        ..Default::default()
+    }));
 
     // Command channel: server and debug_server send commands, scanner receives them.
     let (cmd_tx, cmd_rx) = tokio::sync::mpsc::channel(16);
 
+    if let Some(listener) = grpc_listener {
+        let (state, cmd_tx) = (state.clone(), cmd_tx.clone());
+        let limits = throttle::Limits::new(cfg.grpc_max_connections, 0, std::time::Duration::ZERO);
+        tokio::spawn(async move {
+            if let Err(e) = grpc::run(state, listener, limits, cmd_tx).await {
+                log::error!("gRPC server exited with error: {}", e);
+            }
+        });
+    }
+
+    tokio::spawn(server::publish_hr(state.clone()));
+
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             log::info!("Received shutdown signal");
@@ -38,12 +134,12 @@ async fn main() {
                 log::error!("Scanner task exited with error: {}", e);
             }
         }
-        result = server::run(state.clone(), &socket_path, cmd_tx.clone()) => {
+        result = server::run(state.clone(), listener, config_path.clone(), cmd_tx.clone()) => {
             if let Err(e) = result {
                 log::error!("Server task exited with error: {}", e);
             }
         }
-        result = debug_server::run(state.clone(), config_path, debug_port, cmd_tx) => {
+        result = debug_server::run(state.clone(), config_path, debug_listener, tls, limits, cmd_tx) => {
             if let Err(e) = result {
                 log::error!("Debug server exited with error: {}", e);
             }
@@ -53,11 +149,30 @@ async fn main() {
     log::info!("HRM daemon shutting down");
 }
 
-fn parse_args() -> (String, String, u16) {
+/// Port of a `host:port` listen address.
+fn grpc_port(addr: &str) -> Option<u16> {
+    addr.rsplit_once(':')?.1.parse().ok()
+}
+
+struct Args {
+    socket_path: String,
+    config_path: String,
+    debug_port: u16,
+    /// Account to switch to once the socket and debug port are bound
+    user: Option<String>,
+    group: Option<String>,
+    /// Probe the running daemon and exit (`--check`)
+    check: bool,
+}
+
+fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
     let mut socket_path = DEFAULT_SOCKET.to_string();
     let mut config_path = DEFAULT_CONFIG.to_string();
     let mut debug_port = DEFAULT_DEBUG_PORT;
+    let mut user = None;
+    let mut group = None;
+    let mut check = false;
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
@@ -79,9 +194,22 @@ fn parse_args() -> (String, String, u16) {
                     i += 1;
                 }
             }
+            "--user" => {
+                if let Some(name) = args.get(i + 1) {
+                    user = Some(name.clone());
+                    i += 1;
+                }
+            }
+            "--group" => {
+                if let Some(name) = args.get(i + 1) {
+                    group = Some(name.clone());
+                    i += 1;
+                }
+            }
+            "--check" => check = true,
             _ => {}
         }
         i += 1;
     }
-    (socket_path, config_path, debug_port)
+    Args { socket_path, config_path, debug_port, user, group, check }
 }
@@ -0,0 +1,310 @@
+//! In-place restart that keeps the listening sockets.
+//!
+//! SIGUSR2 (`systemctl reload hrm`) re-executes the daemon — the freshly
+//! installed binary after an upgrade — with the same arguments and PID.
+//! The HR socket, debug and gRPC listeners are handed across as inherited
+//! file descriptors named in `HRM_LISTEN_FDS`, so clients connecting
+//! during the restart wait in the listen backlog instead of being refused
+//! (and the socket file is never missing); only connections already open
+//! are dropped, and ftms-daemon and the UIs reconnect as usual. The BLE
+//! side starts over as on a normal start: the scanner reconnects to the
+//! saved strap. If the exec fails the daemon logs it and keeps running.
+//!
+//! Started by systemd socket activation (`hrm.socket`, `hrm-debug.socket`)
+//! the daemon gets its listeners the same way: `LISTEN_FDS` descriptors
+//! from 3 up, named by the units' `FileDescriptorName=` in
+//! `LISTEN_FDNAMES`. They're adopted under those names like handed-over
+//! ones, so systemd owns the socket path and permissions and the first
+//! client to connect starts the daemon.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::path::Path;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use log::{error, info, warn};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Names and descriptor numbers of the handed-over listeners,
+/// e.g. `socket=3,debug=4`.
+pub const LISTEN_FDS_ENV: &str = "HRM_LISTEN_FDS";
+
+/// First descriptor systemd passes (`SD_LISTEN_FDS_START`).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Listeners handed over by the process we were exec'd from, or by
+/// systemd. Any not taken are closed when this is dropped.
+#[derive(Default)]
+pub struct Inherited(Vec<(String, OwnedFd)>);
+
+impl Inherited {
+    /// Claim the listeners named in the environment, and clear it so
+    /// nothing we spawn sees them.
+    pub fn from_env() -> Self {
+        let mut named = systemd_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+            std::process::id(),
+        );
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+        if !named.is_empty() {
+            info!("Socket activated by systemd: {:?}", named);
+        }
+        if let Ok(value) = std::env::var(LISTEN_FDS_ENV) {
+            std::env::remove_var(LISTEN_FDS_ENV);
+            named.extend(parse(&value));
+        }
+        let mut fds = Vec::new();
+        for (name, fd) in named {
+            // SAFETY: F_GETFD only checks the descriptor is open.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+                warn!("Inherited {} listener fd {} is not open", name, fd);
+                continue;
+            }
+            // SAFETY: the previous process handed this descriptor to us
+            // alone, and it is open.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // systemd leaves them inheritable; only a restart hands them on
+            if let Err(e) = set_cloexec(fd.as_raw_fd(), true) {
+                warn!("Inherited {} listener: {}", name, e);
+            }
+            fds.push((name, fd));
+        }
+        Self(fds)
+    }
+
+    /// Listener `name`, if it was inherited and is listening.
+    fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let i = self.0.iter().position(|(n, _)| n == name)?;
+        let fd = self.0.remove(i).1;
+        if !is_listening(&fd) {
+            warn!("Not reusing the {} listener: not a listening socket", name);
+            return None;
+        }
+        Some(fd)
+    }
+
+    /// The inherited TCP listener `name`, if it's still listening on
+    /// `port`. A listener on another port (the config changed) is closed.
+    pub fn tcp(&mut self, name: &str, port: u16) -> Option<TcpListener> {
+        let listener = std::net::TcpListener::from(self.take(name)?);
+        match listener.local_addr() {
+            Ok(addr) if addr.port() == port => {}
+            _ => {
+                info!("Not reusing the {} listener: no longer on port {}", name, port);
+                return None;
+            }
+        }
+        match listener.set_nonblocking(true).and_then(|_| TcpListener::from_std(listener)) {
+            Ok(listener) => {
+                info!("Reusing the {} listener on port {} across the restart", name, port);
+                Some(listener)
+            }
+            Err(e) => {
+                warn!("Can't reuse the {} listener: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// The inherited Unix listener `name`, if it's still bound to
+    /// `socket_path` (`@name` for an abstract socket) and, for a path, the
+    /// socket file is still there.
+    pub fn unix(&mut self, name: &str, socket_path: &str) -> Option<UnixListener> {
+        let listener = std::os::unix::net::UnixListener::from(self.take(name)?);
+        let bound = listener.local_addr().is_ok_and(|addr| match socket_path.strip_prefix('@') {
+            Some(abstract_name) => addr.as_abstract_name() == Some(abstract_name.as_bytes()),
+            None => addr.as_pathname() == Some(Path::new(socket_path)) && Path::new(socket_path).exists(),
+        });
+        if !bound {
+            info!("Not reusing the {} listener: no longer on {}", name, socket_path);
+            return None;
+        }
+        match listener.set_nonblocking(true).and_then(|_| UnixListener::from_std(listener)) {
+            Ok(listener) => {
+                info!("Reusing the {} listener on {} across the restart", name, socket_path);
+                Some(listener)
+            }
+            Err(e) => {
+                warn!("Can't reuse the {} listener: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// `name=fd` pairs from `LISTEN_FDS_ENV`. Descriptors 0-2 are never
+/// listeners.
+fn parse(value: &str) -> Vec<(String, RawFd)> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (name, fd) = pair.split_once('=')?;
+            let fd: RawFd = fd.trim().parse().ok()?;
+            (fd > 2).then(|| (name.trim().to_string(), fd))
+        })
+        .collect()
+}
+
+/// `name=fd` pairs systemd passed, from `LISTEN_PID`, `LISTEN_FDS` and
+/// `LISTEN_FDNAMES`. None unless they were meant for `pid`; a descriptor
+/// without a name gets systemd's `unknown`.
+fn systemd_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, names: Option<&str>, pid: u32) -> Vec<(String, RawFd)> {
+    if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+        return Vec::new();
+    }
+    let count: RawFd = listen_fds.and_then(|n| n.parse().ok()).unwrap_or(0);
+    let mut names = names.unwrap_or("").split(':').filter(|n| !n.is_empty());
+    (0..count.max(0))
+        .map(|i| (names.next().unwrap_or("unknown").to_string(), SD_LISTEN_FDS_START + i))
+        .collect()
+}
+
+/// Whether `fd` is a socket in the listening state.
+fn is_listening(fd: &OwnedFd) -> bool {
+    let mut accepting: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: getsockopt writes at most `len` bytes into `accepting`.
+    let rc = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accepting as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    rc == 0 && accepting != 0
+}
+
+/// Re-execute the daemon on every SIGUSR2, handing over `listeners`.
+/// Never returns: a successful exec replaces the process, a failed one is
+/// logged and the next signal tries again.
+pub async fn on_signal(listeners: Vec<(&'static str, RawFd)>) {
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("In-place restart unavailable: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    while usr2.recv().await.is_some() {
+        let e = exec(&listeners);
+        error!("Restart failed, carrying on: {}", e);
+    }
+}
+
+/// Exec our own command line with `listeners` inherited. Only returns if
+/// the exec failed.
+fn exec(listeners: &[(&str, RawFd)]) -> io::Error {
+    if let Err(e) = listeners.iter().try_for_each(|&(_, fd)| set_cloexec(fd, false)) {
+        restore(listeners);
+        return e;
+    }
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| "/proc/self/exe".into());
+    let fds = listeners.iter().map(|(name, fd)| format!("{}={}", name, fd)).collect::<Vec<_>>().join(",");
+    info!("Restarting in place: {} ({})", program.to_string_lossy(), fds);
+    let e = Command::new(&program).args(args).env(LISTEN_FDS_ENV, fds).exec();
+    // Still here: don't leak the listeners into child processes
+    restore(listeners);
+    e
+}
+
+fn restore(listeners: &[(&str, RawFd)]) {
+    for &(_, fd) in listeners {
+        let _ = set_cloexec(fd, true);
+    }
+}
+
+fn set_cloexec(fd: RawFd, on: bool) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor the daemon keeps open.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        let flags = if on { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("debug=3,grpc=7"), vec![("debug".to_string(), 3), ("grpc".to_string(), 7)]);
+        assert_eq!(parse("debug=1,grpc=x,=5,junk"), vec![("".to_string(), 5)]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_systemd_fds() {
+        let fds = systemd_fds(Some("42"), Some("2"), Some("socket:debug"), 42);
+        assert_eq!(fds, vec![("socket".to_string(), 3), ("debug".to_string(), 4)]);
+        assert!(systemd_fds(Some("41"), Some("2"), Some("socket:debug"), 42).is_empty(), "another process's");
+        assert!(systemd_fds(None, Some("2"), None, 42).is_empty());
+        assert_eq!(systemd_fds(Some("42"), Some("1"), None, 42), vec![("unknown".to_string(), 3)]);
+        assert!(systemd_fds(Some("42"), Some("x"), None, 42).is_empty());
+    }
+
+    #[test]
+    fn test_only_listening_sockets_taken() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let mut inherited = Inherited(vec![
+            ("debug".to_string(), OwnedFd::from(client)),
+            ("grpc".to_string(), OwnedFd::from(file)),
+            ("socket".to_string(), OwnedFd::from(listener)),
+        ]);
+        assert!(inherited.take("debug").is_none(), "connected, not listening");
+        assert!(inherited.take("grpc").is_none(), "not a socket");
+        assert!(inherited.take("socket").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_handover() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut inherited = Inherited(vec![
+            ("debug".to_string(), OwnedFd::from(listener)),
+            ("grpc".to_string(), OwnedFd::from(other)),
+        ]);
+
+        assert!(inherited.tcp("grpc", port).is_none(), "wrong port");
+        assert!(inherited.tcp("grpc", port).is_none(), "already taken");
+        let reused = inherited.tcp("debug", port).unwrap();
+        let client = tokio::net::TcpStream::connect(("127.0.0.1", port));
+        let (accepted, connected) = tokio::join!(reused.accept(), client);
+        assert!(accepted.is_ok() && connected.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unix_handover() {
+        let path = "/tmp/hrm_test_restart.sock";
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path).unwrap();
+        let mut inherited = Inherited(vec![("socket".to_string(), OwnedFd::from(listener))]);
+
+        let reused = inherited.unix("socket", path).unwrap();
+        let (accepted, connected) = tokio::join!(reused.accept(), tokio::net::UnixStream::connect(path));
+        assert!(accepted.is_ok() && connected.is_ok());
+
+        let moved = std::os::unix::net::UnixListener::bind(path.replace(".sock", "2.sock")).unwrap();
+        let mut inherited = Inherited(vec![("socket".to_string(), OwnedFd::from(moved))]);
+        assert!(inherited.unix("socket", path).is_none(), "bound elsewhere");
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(path.replace(".sock", "2.sock"));
+    }
+}
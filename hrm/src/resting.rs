@@ -0,0 +1,235 @@
+//! Resting heart rate tracking.
+//!
+//! While a strap streams, every sample goes through a [`RestingTracker`].
+//! A minute of readings that stay within a few bpm of each other counts as
+//! stable, and its average is a resting-HR candidate when the minute falls
+//! inside one of the configured `rest_windows` (local times such as
+//! `"05:30-08:00"`; none configured means any time of day). The lowest
+//! candidate of each local day is kept in the config file under
+//! `resting_hr`, for the socket `resting_hr` command and the debug `stats`
+//! command. A year of days is kept.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tz::TimeZone;
+
+/// How long HR has to hold steady to count as resting.
+const STABLE_FOR: Duration = Duration::from_secs(60);
+/// Largest max - min spread, in bpm, still considered steady.
+const STABLE_SPREAD: u16 = 5;
+/// A gap between samples longer than this starts the minute over.
+const MAX_GAP: Duration = Duration::from_secs(5);
+/// Daily values kept on disk.
+pub const HISTORY_DAYS: usize = 365;
+
+/// Finds stretches of steady HR in the sample stream.
+#[derive(Debug, Clone, Default)]
+pub struct RestingTracker {
+    /// Samples from the last `STABLE_FOR`.
+    window: VecDeque<(Instant, u16)>,
+    /// Start of the current unbroken run of samples.
+    run_start: Option<Instant>,
+    /// The lowest value recorded so far for `(date, bpm)`, to avoid
+    /// rewriting the config for candidates that aren't a new low.
+    today: Option<(String, u16)>,
+}
+
+impl RestingTracker {
+    /// Add a sample. Returns the average bpm of the last minute when it
+    /// has been steady.
+    pub fn sample(&mut self, now: Instant, bpm: u16) -> Option<u16> {
+        let gap = self.window.back().is_some_and(|&(at, _)| now.saturating_duration_since(at) > MAX_GAP);
+        if gap || bpm == 0 {
+            // 0 is a strap that lost skin contact
+            self.reset();
+            if bpm == 0 {
+                return None;
+            }
+        }
+        self.window.push_back((now, bpm));
+        let run_start = *self.run_start.get_or_insert(now);
+        while self.window.front().is_some_and(|&(at, _)| now.saturating_duration_since(at) > STABLE_FOR) {
+            self.window.pop_front();
+        }
+        if now.saturating_duration_since(run_start) < STABLE_FOR {
+            return None;
+        }
+        let min = self.window.iter().map(|&(_, b)| b).min()?;
+        let max = self.window.iter().map(|&(_, b)| b).max()?;
+        if max - min > STABLE_SPREAD {
+            return None;
+        }
+        let sum: u32 = self.window.iter().map(|&(_, b)| b as u32).sum();
+        let n = self.window.len() as u32;
+        Some(((sum + n / 2) / n) as u16)
+    }
+
+    /// Forget the current run, e.g. when the strap disconnects.
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.run_start = None;
+    }
+
+    /// Whether `bpm` beats the lowest value already recorded for `date`.
+    pub fn is_new_low(&self, date: &str, bpm: u16) -> bool {
+        match &self.today {
+            Some((d, low)) if d == date => bpm < *low,
+            _ => true,
+        }
+    }
+
+    /// Remember `bpm` as `date`'s recorded low.
+    pub fn set_low(&mut self, date: &str, bpm: u16) {
+        self.today = Some((date.to_string(), bpm));
+    }
+}
+
+/// Whether `minute` (of the local day) falls in one of `windows`
+/// (`"HH:MM-HH:MM"`, wrapping past midnight when the end is earlier). No
+/// windows means any time; unparseable ones never match.
+pub fn in_rest_window(windows: &[String], minute: u32) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+    windows.iter().any(|w| match parse_window(w) {
+        Some((start, end)) if start <= end => (start..end).contains(&minute),
+        Some((start, end)) => minute >= start || minute < end,
+        None => false,
+    })
+}
+
+/// `"05:30-08:00"` to minutes of the day, `(330, 480)`.
+pub fn parse_window(s: &str) -> Option<(u32, u32)> {
+    let (start, end) = s.split_once('-')?;
+    Some((parse_hhmm(start)?, parse_hhmm(end)?))
+}
+
+fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h <= 24 && m < 60 && h * 60 + m <= 24 * 60).then_some(h * 60 + m)
+}
+
+/// Local date (`"YYYY-MM-DD"`) and minute of the day at `unix_secs` in
+/// `timezone` (an IANA name; None is the system zone, unknown zones UTC).
+pub fn local_date_minute(timezone: Option<&str>, unix_secs: u64) -> (String, u32) {
+    let zone = match timezone {
+        Some(name) => TimeZone::from_posix_tz(name),
+        None => TimeZone::local(),
+    };
+    let offset = zone
+        .map_err(|e| e.to_string())
+        .and_then(|zone| {
+            zone.find_local_time_type(unix_secs as i64)
+                .map(|t| t.ut_offset())
+                .map_err(|e| e.to_string())
+        })
+        .unwrap_or_else(|e| {
+            warn!("Can't resolve time zone {}: {}; using UTC", timezone.unwrap_or("(system)"), e);
+            0
+        });
+    let local = unix_secs as i64 + offset as i64;
+    let (y, m, d) = civil_from_days(local.div_euclid(86_400));
+    (format!("{:04}-{:02}-{:02}", y, m, d), (local.rem_euclid(86_400) / 60) as u32)
+}
+
+/// `unix_ms` as an ISO 8601 UTC timestamp, e.g. `"2023-11-14T22:13:20.042Z"`.
+pub fn iso_utc(unix_ms: u64) -> String {
+    let secs = (unix_ms / 1000) as i64;
+    let (y, m, d) = civil_from_days(secs.div_euclid(86_400));
+    let day_secs = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        y,
+        m,
+        d,
+        day_secs / 3600,
+        day_secs / 60 % 60,
+        day_secs % 60,
+        unix_ms % 1000
+    )
+}
+
+/// Days since 1970-01-01 to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// Mean of the `days` most recently recorded daily values.
+pub fn average(history: &BTreeMap<String, u16>, days: usize) -> Option<f64> {
+    let recent: Vec<u16> = history.values().rev().take(days).copied().collect();
+    (!recent.is_empty()).then(|| recent.iter().map(|&b| b as f64).sum::<f64>() / recent.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_needs_a_steady_minute() {
+        let t0 = Instant::now();
+        let at = |s: u64| t0 + Duration::from_secs(s);
+        let mut tracker = RestingTracker::default();
+        for s in 0..60 {
+            assert_eq!(tracker.sample(at(s), 60 + (s % 3) as u16), None, "not a full minute yet");
+        }
+        assert_eq!(tracker.sample(at(60), 61), Some(61));
+
+        // A spike breaks stability until it ages out of the window
+        assert_eq!(tracker.sample(at(61), 75), None);
+        for s in 62..122 {
+            assert_eq!(tracker.sample(at(s), 60), None);
+        }
+        assert_eq!(tracker.sample(at(122), 60), Some(60));
+
+        // A gap starts the minute over
+        for s in 130..190 {
+            assert_eq!(tracker.sample(at(s), 58), None);
+        }
+        assert_eq!(tracker.sample(at(190), 58), Some(58));
+        assert_eq!(tracker.sample(at(191), 0), None);
+        assert_eq!(tracker.sample(at(192), 58), None, "lost contact resets");
+
+        assert!(tracker.is_new_low("2026-10-16", 58));
+        tracker.set_low("2026-10-16", 58);
+        assert!(!tracker.is_new_low("2026-10-16", 58));
+        assert!(tracker.is_new_low("2026-10-17", 70), "new day");
+    }
+
+    #[test]
+    fn test_rest_windows_and_dates() {
+        assert!(in_rest_window(&[], 0));
+        let windows = vec!["05:30-08:00".to_string(), "22:00-01:00".to_string(), "bogus".to_string()];
+        assert!(in_rest_window(&windows, 330));
+        assert!(!in_rest_window(&windows, 480));
+        assert!(in_rest_window(&windows, 23 * 60));
+        assert!(in_rest_window(&windows, 30));
+        assert!(!in_rest_window(&windows, 12 * 60));
+        assert_eq!(parse_window("7:00-24:00"), Some((420, 1440)));
+        assert_eq!(parse_window("07:60-08:00"), None);
+
+        // 2023-11-14 22:13:20 UTC is 17:13 in New York
+        assert_eq!(local_date_minute(Some("UTC"), 1_700_000_000), ("2023-11-14".to_string(), 22 * 60 + 13));
+        assert_eq!(local_date_minute(Some("America/New_York"), 1_700_000_000), ("2023-11-14".to_string(), 17 * 60 + 13));
+        assert_eq!(local_date_minute(Some("Asia/Tokyo"), 1_700_000_000).0, "2023-11-15");
+        assert_eq!(iso_utc(1_700_000_000_042), "2023-11-14T22:13:20.042Z");
+        assert_eq!(iso_utc(0), "1970-01-01T00:00:00.000Z");
+
+        let history: BTreeMap<String, u16> =
+            [("2026-10-14", 60), ("2026-10-15", 58), ("2026-10-16", 56)].map(|(d, b)| (d.to_string(), b)).into();
+        assert_eq!(average(&history, 2), Some(57.0));
+        assert_eq!(average(&history, 30), Some(58.0));
+        assert_eq!(average(&BTreeMap::new(), 7), None);
+    }
+}
@@ -2,31 +2,42 @@
 //!
 //! Scans for BLE devices advertising the Heart Rate Service (0x180D),
 //! connects via GATT, subscribes to HR Measurement notifications (0x2A37),
-//! and updates shared state with heart rate readings.
+//! and updates shared state with heart rate readings. Devices listed under
+//! `overrides` in the config are read from their vendor-specific
+//! characteristic instead, decoded with the configured parser.
+//!
+//! Straps connected from a resolvable private address are bonded so BlueZ
+//! learns their IRK; they're saved under the identity address it reports.
 //!
 //! Commands are received via a `tokio::sync::mpsc` channel, allowing
 //! immediate responsiveness even during blocking operations like BLE
 //! notification streaming and scan timeouts.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bluer::gatt::remote::Characteristic;
-use bluer::{Adapter, AdapterEvent, Address, Device};
+use bluer::gatt::CharacteristicFlags;
+use bluer::{Adapter, AdapterEvent, Address, AddressType, Device};
 use futures::StreamExt;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use uuid::Uuid;
 
-use crate::config;
+use crate::audit::Audit;
+use crate::config::{self, FtmsBusyScan, HrOverride, HrParser, HrmConfig};
+use crate::ftms_activity;
+use crate::health::{Counter, Health};
+use crate::resting::{self, RestingTracker};
+use crate::watchdog::Watchdog;
 
 // Bluetooth SIG base UUID: 0000XXXX-0000-1000-8000-00805f9b34fb
 const fn ble_uuid(short: u16) -> Uuid {
     Uuid::from_u128(
-        ((short as u128) << 96) | 0x0000_0000_0000_1000_8000_00805f9b34fb_u128,
+        ((short as u128) << 96) | 0x0000_0000_0000_1000_8000_0080_5f9b_34fb_u128,
     )
 }
 
@@ -36,6 +47,35 @@ const HR_SERVICE_UUID: Uuid = ble_uuid(0x180D);
 /// Heart Rate Measurement Characteristic UUID.
 const HR_MEASUREMENT_UUID: Uuid = ble_uuid(0x2A37);
 
+/// Battery Service UUID. Some straps put their level in advertised service data.
+const BATTERY_SERVICE_UUID: Uuid = ble_uuid(0x180F);
+
+/// Battery Level Characteristic UUID.
+const BATTERY_LEVEL_UUID: Uuid = ble_uuid(0x2A19);
+
+/// Parse a UUID from config: either a full 128-bit string or a 16-bit SIG
+/// short form (`"180d"`, `"0x180D"`).
+pub fn parse_uuid(s: &str) -> Option<Uuid> {
+    let s = s.trim();
+    let short = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    if short.len() == 4 {
+        return u16::from_str_radix(short, 16).ok().map(ble_uuid);
+    }
+    Uuid::parse_str(s).ok()
+}
+
+/// A connected strap that hasn't notified for this long is reported stale.
+pub const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// Discovery window for a normal scan.
+const SCAN_TIME: Duration = Duration::from_secs(10);
+/// Discovery window while throttled for ftms-daemon's apps.
+const THROTTLED_SCAN_TIME: Duration = Duration::from_secs(3);
+/// Shortest gap between throttled scans.
+const THROTTLED_SCAN_GAP: Duration = Duration::from_secs(30);
+/// How often a paused scanner re-reads the ftms activity file.
+const PAUSED_RECHECK: Duration = Duration::from_secs(5);
+
 /// Shared HRM state, updated by the scanner and read by server/debug_server.
 #[derive(Debug, Clone, Default)]
 pub struct HrmState {
@@ -45,20 +85,220 @@ pub struct HrmState {
     pub connected: bool,
     /// Name of the connected device (empty when not connected).
     pub device_name: String,
+    /// User-assigned nickname of the connected device (empty if none).
+    pub device_nickname: String,
     /// BLE address of the connected device.
     pub device_address: String,
     /// Whether we are actively scanning.
     pub scanning: bool,
     /// Devices found during the most recent scan.
     pub available_devices: Vec<BleDevice>,
+    /// When the last HR notification arrived (or the connection was made,
+    /// before the first one). None when there's no real device behind the
+    /// state, e.g. a debug mock.
+    pub last_sample: Option<Instant>,
+    /// Daemon-wide activity and counters for the debug `stats` command.
+    pub health: Health,
+    /// Steady-HR detection for the daily resting heart rate.
+    pub resting: RestingTracker,
+    /// Discovery events as scans find devices, for `devices watch` and
+    /// socket clients subscribed to `devices`.
+    pub device_events: DeviceEvents,
+    /// Straps connecting and disconnecting, for socket clients subscribed
+    /// to `connection`.
+    pub connection_events: ConnectionEvents,
+    /// The 1 Hz HR snapshots socket and debug `sub` clients consume.
+    pub hr_updates: HrUpdates,
+    /// Who asked for which device commands, for the debug `history` command.
+    pub audit: Audit,
+    /// Name of the BLE adapter in use (`hci0`). None while BlueZ or the
+    /// adapter can't be opened.
+    pub adapter: Option<String>,
+}
+
+impl HrmState {
+    /// Time since the last HR sample, if known.
+    pub fn sample_age(&self, now: Instant) -> Option<Duration> {
+        self.last_sample.map(|t| now.saturating_duration_since(t))
+    }
+
+    /// Connected, but the strap has gone quiet for longer than `STALE_AFTER`.
+    pub fn is_stale(&self, now: Instant) -> bool {
+        self.connected && self.sample_age(now).is_some_and(|age| age > STALE_AFTER)
+    }
 }
 
 /// A BLE device found during scanning.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BleDevice {
     pub address: String,
     pub name: String,
+    /// Most recent RSSI seen during the scan.
     pub rssi: i16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Whether this device is in the saved-device list.
+    #[serde(default)]
+    pub saved: bool,
+    /// Battery level (%) from advertised Battery Service data, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+    /// Manufacturer-specific data: company ID (`"0x006b"`) → payload hex.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub manufacturer_data: BTreeMap<String, String>,
+    /// Advertised service data: service UUID → payload hex.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub service_data: BTreeMap<String, String>,
+}
+
+impl BleDevice {
+    /// Fold a later sighting of the same device into this one. The newest
+    /// RSSI wins; names and advertisement data fill in as they arrive, since
+    /// BlueZ often reports the scan response after the first advertisement.
+    /// Returns whether the RSSI or name changed.
+    fn merge(&mut self, newer: BleDevice) -> bool {
+        let before = (self.rssi, self.name.clone());
+        if newer.rssi != 0 {
+            self.rssi = newer.rssi;
+        }
+        if self.name == "Unknown" && newer.name != "Unknown" {
+            self.name = newer.name;
+        }
+        if newer.battery.is_some() {
+            self.battery = newer.battery;
+        }
+        self.manufacturer_data.extend(newer.manufacturer_data);
+        self.service_data.extend(newer.service_data);
+        (self.rssi, &self.name) != (before.0, &before.1)
+    }
+}
+
+/// What a scan saw, as it happens.
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    ScanStarted,
+    Found(BleDevice),
+    /// A device already found changed RSSI or name.
+    Updated(BleDevice),
+    /// BlueZ dropped the device from its cache: it went out of range.
+    Lost(String),
+    /// The scan ended with this many HR devices.
+    ScanFinished(usize),
+}
+
+/// Fan-out of [`DeviceEvent`]s to debug watchers. Events nobody is
+/// watching are dropped.
+#[derive(Debug, Clone)]
+pub struct DeviceEvents(broadcast::Sender<DeviceEvent>);
+
+impl Default for DeviceEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(64).0)
+    }
+}
+
+impl DeviceEvents {
+    pub fn send(&self, event: DeviceEvent) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.0.subscribe()
+    }
+}
+
+/// One HR reading as every subscriber sees it, captured under a single
+/// lock. Serializes to the HR fields of the socket's `hr`/`status` messages.
+#[derive(Debug, Clone, Serialize)]
+pub struct HrSnapshot {
+    pub bpm: u16,
+    pub connected: bool,
+    pub stale: bool,
+    pub last_sample_age_ms: Option<u64>,
+    /// When `bpm` was measured, in millis since daemon start.
+    pub sample_mono_ms: Option<u64>,
+    /// When `bpm` was measured, ISO 8601 UTC.
+    pub sample_time: Option<String>,
+    pub device: String,
+    pub nickname: String,
+    pub address: String,
+    /// For the debug `sub` line; the socket's `status` carries it separately.
+    #[serde(skip)]
+    pub scanning: bool,
+}
+
+impl HrSnapshot {
+    pub fn capture(s: &HrmState, now: Instant) -> Self {
+        let wall_ms = config::unix_now_ms();
+        let age = s.sample_age(now).map(|age| age.as_millis() as u64);
+        Self {
+            bpm: s.heart_rate,
+            connected: s.connected,
+            stale: s.is_stale(now),
+            last_sample_age_ms: age,
+            sample_mono_ms: s.last_sample.map(|at| at.saturating_duration_since(s.health.started()).as_millis() as u64),
+            sample_time: age.map(|age| resting::iso_utc(wall_ms.saturating_sub(age))),
+            device: s.device_name.clone(),
+            nickname: s.device_nickname.clone(),
+            address: s.device_address.clone(),
+            scanning: s.scanning,
+        }
+    }
+}
+
+/// A strap connection made, ended or failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    Connected { name: String, address: String },
+    Disconnected { address: String },
+    /// The attempt (or the session, once connected) ended in an error.
+    Failed { address: String, error: String },
+}
+
+/// Fan-out of [`ConnectionEvent`]s to socket subscribers. Events nobody is
+/// watching are dropped.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvents(broadcast::Sender<ConnectionEvent>);
+
+impl Default for ConnectionEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(16).0)
+    }
+}
+
+impl ConnectionEvents {
+    pub fn send(&self, event: ConnectionEvent) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.0.subscribe()
+    }
+}
+
+/// Fan-out of [`HrSnapshot`]s from `server::publish_hr` to every socket
+/// and debug subscriber, so they all see the same values.
+#[derive(Debug, Clone)]
+pub struct HrUpdates(broadcast::Sender<Arc<HrSnapshot>>);
+
+impl Default for HrUpdates {
+    fn default() -> Self {
+        Self(broadcast::channel(4).0)
+    }
+}
+
+impl HrUpdates {
+    pub fn send(&self, snapshot: HrSnapshot) {
+        let _ = self.0.send(Arc::new(snapshot));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<HrSnapshot>> {
+        self.0.subscribe()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.0.receiver_count() > 0
+    }
 }
 
 /// Commands that can be sent to the scanner from the server.
@@ -97,31 +337,118 @@ pub fn parse_hr_measurement(data: &[u8]) -> Option<u16> {
     }
 }
 
+/// Decode an HR notification with the given parser. `Standard` is the
+/// spec-defined HR Measurement layout; the others read a raw integer at a
+/// fixed offset, for vendor characteristics.
+pub fn parse_hr_value(parser: HrParser, data: &[u8]) -> Option<u16> {
+    match parser {
+        HrParser::Standard => parse_hr_measurement(data),
+        HrParser::Uint8 { offset } => data.get(offset).map(|&b| b as u16),
+        HrParser::Uint16Le { offset } => {
+            let bytes = data.get(offset..offset.checked_add(2)?)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+        }
+    }
+}
+
 /// Run the BLE scanner loop. Connects to a saved device or scans for new ones.
-/// Reconnects on disconnection with exponential backoff.
+/// Reconnects on disconnection with exponential backoff. A failed connection
+/// falls back to the other devices from the last scan, strongest first.
 ///
 /// Commands arrive via `cmd_rx` and are handled immediately, even during
 /// active BLE connections or scan timeouts.
+///
+/// If the D-Bus connection to BlueZ fails (bluetoothd restarted, adapter
+/// gone) the session and adapter are opened again with backoff instead of
+/// the scanner exiting and taking the daemon down with it. Connects and
+/// scans that keep failing in the adapter escalate via [`Watchdog`].
 pub async fn run(
     state: Arc<Mutex<HrmState>>,
     config_path: String,
     mut cmd_rx: mpsc::Receiver<HrmCommand>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut backoff = Duration::from_secs(1);
+    // Holds a command that was received during a wait and needs processing
+    // on the next iteration. Survives session re-creation.
+    let mut pending: Option<HrmCommand> = None;
+    let mut session_backoff = Duration::from_secs(1);
+    let health = state.lock().await.health.clone();
+    let mut opened = false;
+
+    loop {
+        match open_adapter().await {
+            Ok((_session, adapter)) => {
+                state.lock().await.adapter = Some(adapter.name().to_string());
+                session_backoff = Duration::from_secs(1);
+                if std::mem::replace(&mut opened, true) {
+                    health.count(Counter::Reconnects);
+                    // A new session may well be a restarted bluetoothd
+                    health.clear();
+                }
+                let e = scan_loop(&adapter, &state, &config_path, &mut cmd_rx, &mut backoff, &mut pending).await;
+                warn!("BLE session lost: {}", e);
+                health.count(Counter::Errors);
+                let mut s = state.lock().await;
+                s.scanning = false;
+                s.connected = false;
+                s.adapter = None;
+            }
+            Err(e) => {
+                warn!("Can't open BLE adapter: {}", e);
+                health.count(Counter::Errors);
+                state.lock().await.adapter = None;
+            }
+        }
+        info!("Reopening BLE session in {:?}...", session_backoff);
+        tokio::time::sleep(session_backoff).await;
+        session_backoff = (session_backoff * 2).min(Duration::from_secs(30));
+    }
+}
+
+/// Open a D-Bus session to BlueZ and power up the default adapter. The
+/// session must outlive the adapter's use.
+async fn open_adapter() -> bluer::Result<(bluer::Session, Adapter)> {
     let session = bluer::Session::new().await?;
     let adapter = session.default_adapter().await?;
     info!("Using BLE adapter: {}", adapter.name());
-
     adapter.set_powered(true).await?;
+    Ok((session, adapter))
+}
 
-    let mut backoff = Duration::from_secs(1);
-    // Holds a command that was received during a wait and needs processing
-    // on the next iteration.
-    let mut pending: Option<HrmCommand> = None;
+/// Check the adapter still answers over D-Bus, re-powering it if it was
+/// switched off. An error means the session is dead.
+async fn check_adapter(adapter: &Adapter) -> bluer::Result<()> {
+    if !adapter.is_powered().await? {
+        warn!("BLE adapter powered off, powering on");
+        adapter.set_powered(true).await?;
+    }
+    Ok(())
+}
 
+/// Connect/scan loop on one adapter. Only returns when BlueZ stops
+/// answering, with the error.
+async fn scan_loop(
+    adapter: &Adapter,
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    cmd_rx: &mut mpsc::Receiver<HrmCommand>,
+    backoff: &mut Duration,
+    pending: &mut Option<HrmCommand>,
+) -> bluer::Error {
+    // Whether discovery is currently yielding to ftms-daemon, for logging
+    let mut yielding = false;
+    let health = state.lock().await.health.clone();
+    let mut watchdog = Watchdog::default();
     loop {
+        if let Err(e) = check_adapter(adapter).await {
+            return e;
+        }
+        health.touch("scanner");
+
         // Use a command carried over from an interruptible wait, or drain
         // any new commands from the channel (last one wins).
-        let cmd = pending.take().or_else(|| drain_last(&mut cmd_rx));
+        let cmd = pending.take().or_else(|| drain_last(cmd_rx));
+        let user_scan = matches!(cmd, Some(HrmCommand::Scan));
 
         match cmd {
             Some(HrmCommand::Disconnect) => {
@@ -130,22 +457,15 @@ pub async fn run(
             }
             Some(HrmCommand::Forget) => {
                 info!("Forget command received");
-                config::forget(&config_path);
+                config::forget(config_path);
             }
             Some(HrmCommand::Connect(addr)) => {
                 info!("Connect command for {}", addr);
                 match addr.parse::<Address>() {
                     Ok(address) => {
-                        match connect_and_stream(&adapter, address, &state, &config_path, &mut cmd_rx).await {
-                            Ok(()) => {
-                                info!("Device disconnected cleanly");
-                            }
-                            Err(e) => {
-                                warn!("Connection error: {}", e);
-                            }
-                        }
-                        mark_disconnected(&state).await;
-                        backoff = Duration::from_secs(1);
+                        let candidates = state.lock().await.available_devices.clone();
+                        connect_with_fallback(adapter, address, &candidates, state, config_path, cmd_rx, &mut watchdog).await;
+                        *backoff = Duration::from_secs(1);
                         continue;
                     }
                     Err(e) => {
@@ -158,27 +478,51 @@ pub async fn run(
                 // Fall through to scan, bypassing saved-device reconnect
             }
             None => {
-                // No command -- try saved device first
-                if let Some(cfg) = config::load(&config_path) {
+                // No command -- try saved device first, unless reconnecting
+                // is left to the user
+                config::prune_stale(config_path);
+                if let Some(cfg) = config::load(config_path).filter(|cfg| cfg.auto_connect) {
                     if let Ok(address) = cfg.address.parse::<Address>() {
                         info!("Attempting to connect to saved device: {} ({})", cfg.name, cfg.address);
-                        match connect_and_stream(&adapter, address, &state, &config_path, &mut cmd_rx).await {
-                            Ok(()) => {
-                                info!("Saved device disconnected");
-                            }
-                            Err(e) => {
-                                warn!("Failed to connect to saved device: {}", e);
-                            }
-                        }
-                        mark_disconnected(&state).await;
-                        backoff = Duration::from_secs(1);
+                        let candidates = state.lock().await.available_devices.clone();
+                        connect_with_fallback(adapter, address, &candidates, state, config_path, cmd_rx, &mut watchdog).await;
+                        *backoff = Duration::from_secs(1);
                         continue;
                     }
                 }
             }
         }
 
+        // Yield the shared adapter to apps connected to ftms-daemon, unless
+        // the user asked for this scan
+        let cfg = config::load(config_path).unwrap_or_default();
+        let ftms_busy = !user_scan
+            && cfg.ftms_busy_scan != FtmsBusyScan::Ignore
+            && ftms_activity::ftms_centrals(&cfg.ftms_activity_file) > 0;
+        if ftms_busy != yielding {
+            yielding = ftms_busy;
+            if !ftms_busy {
+                info!("ftms-daemon apps disconnected, resuming normal HR discovery");
+            } else if cfg.ftms_busy_scan == FtmsBusyScan::Pause {
+                info!("ftms-daemon has apps connected, pausing HR discovery");
+            } else {
+                info!("ftms-daemon has apps connected, throttling HR discovery");
+            }
+        }
+        if ftms_busy && cfg.ftms_busy_scan == FtmsBusyScan::Pause {
+            tokio::select! {
+                _ = tokio::time::sleep(PAUSED_RECHECK) => {}
+                cmd = cmd_rx.recv() => {
+                    if let Some(cmd) = cmd {
+                        *pending = Some(cmd);
+                    }
+                }
+            }
+            continue;
+        }
+
         // Scan for HR devices
+        let scan_time = if ftms_busy { THROTTLED_SCAN_TIME } else { SCAN_TIME };
         info!("Scanning for HR devices...");
         {
             let mut s = state.lock().await;
@@ -186,7 +530,25 @@ pub async fn run(
             s.available_devices.clear();
         }
 
-        let (devices, interrupted_cmd) = scan_for_hr_devices(&adapter, Duration::from_secs(10), &mut cmd_rx).await;
+        // Devices with overrides may advertise a vendor service instead of 0x180D
+        let extra_services: Vec<Uuid> =
+            cfg.overrides.values().filter_map(|o| parse_uuid(&o.service)).collect();
+
+        let events = state.lock().await.device_events.clone();
+        let (mut devices, interrupted_cmd) =
+            match scan_for_hr_devices(adapter, scan_time, &extra_services, &events, cmd_rx).await {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    error!("Failed to start discovery: {}", e);
+                    health.count(Counter::Errors);
+                    watchdog.check(&e, adapter, &health).await;
+                    (Vec::new(), None)
+                }
+            };
+        for d in &mut devices {
+            d.nickname = cfg.nickname_for(&d.address).map(str::to_string);
+            d.saved = cfg.saved_device(&d.address).is_some();
+        }
 
         {
             let mut s = state.lock().await;
@@ -196,57 +558,52 @@ pub async fn run(
 
         // If a command interrupted the scan, process it next iteration
         if let Some(cmd) = interrupted_cmd {
-            pending = Some(cmd);
+            *pending = Some(cmd);
             continue;
         }
 
         match devices.len() {
             0 => {
-                info!("No HR devices found, retrying in {:?}", backoff);
+                let wait = if ftms_busy { (*backoff).max(THROTTLED_SCAN_GAP) } else { *backoff };
+                info!("No HR devices found, retrying in {:?}", wait);
                 // Interruptible sleep: respond to commands during backoff
                 tokio::select! {
-                    _ = tokio::time::sleep(backoff) => {}
+                    _ = tokio::time::sleep(wait) => {}
                     cmd = cmd_rx.recv() => {
                         if let Some(cmd) = cmd {
-                            pending = Some(cmd);
+                            *pending = Some(cmd);
                         }
                     }
                 }
-                backoff = (backoff * 2).min(Duration::from_secs(30));
+                *backoff = (*backoff * 2).min(Duration::from_secs(30));
             }
-            1 => {
+            1 if cfg.auto_connect => {
                 // Auto-connect to sole device
                 let dev = &devices[0];
                 info!("Found single HR device: {} ({}), auto-connecting", dev.name, dev.address);
                 if let Ok(address) = dev.address.parse::<Address>() {
-                    match connect_and_stream(&adapter, address, &state, &config_path, &mut cmd_rx).await {
-                        Ok(()) => {
-                            info!("Device disconnected");
-                        }
-                        Err(e) => {
-                            warn!("Connection error: {}", e);
-                        }
-                    }
-                    mark_disconnected(&state).await;
+                    connect_with_fallback(adapter, address, &devices, state, config_path, cmd_rx, &mut watchdog).await;
                 }
-                backoff = Duration::from_secs(1);
+                *backoff = Duration::from_secs(1);
             }
             n => {
-                // Multiple devices found -- wait for user to choose via connect command
+                // Multiple devices found (or auto-connect is off) -- wait for
+                // user to choose via connect command
                 info!("Found {} HR devices, waiting for connect command", n);
                 for d in &devices {
                     info!("  {} - {} (RSSI: {})", d.address, d.name, d.rssi);
                 }
                 // Interruptible wait for user input before rescanning
+                let wait = if ftms_busy { THROTTLED_SCAN_GAP } else { Duration::from_secs(5) };
                 tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                    _ = tokio::time::sleep(wait) => {}
                     cmd = cmd_rx.recv() => {
                         if let Some(cmd) = cmd {
-                            pending = Some(cmd);
+                            *pending = Some(cmd);
                         }
                     }
                 }
-                backoff = Duration::from_secs(1);
+                *backoff = Duration::from_secs(1);
             }
         }
     }
@@ -261,36 +618,46 @@ fn drain_last(rx: &mut mpsc::Receiver<HrmCommand>) -> Option<HrmCommand> {
     last
 }
 
-/// Scan for BLE devices advertising the Heart Rate Service.
-/// Aborts early if a command arrives on cmd_rx, returning the interrupting
-/// command so the caller can process it.
+/// Scan for BLE devices advertising the Heart Rate Service (or one of
+/// `extra_services`). Aborts early if a command arrives on cmd_rx, returning the interrupting
+/// command so the caller can process it. Fails only if discovery can't start.
 async fn scan_for_hr_devices(
     adapter: &Adapter,
     timeout: Duration,
+    extra_services: &[Uuid],
+    events: &DeviceEvents,
     cmd_rx: &mut mpsc::Receiver<HrmCommand>,
-) -> (Vec<BleDevice>, Option<HrmCommand>) {
+) -> bluer::Result<(Vec<BleDevice>, Option<HrmCommand>)> {
     let mut found: HashMap<Address, BleDevice> = HashMap::new();
     let mut interrupted_cmd = None;
 
-    let discover = match adapter.discover_devices().await {
-        Ok(stream) => stream,
-        Err(e) => {
-            error!("Failed to start discovery: {}", e);
-            return (Vec::new(), None);
-        }
-    };
-
+    let discover = adapter.discover_devices().await?;
     let mut discover = Box::pin(discover);
+    events.send(DeviceEvent::ScanStarted);
 
     let deadline = tokio::time::sleep(timeout);
     tokio::pin!(deadline);
 
+    // BlueZ only emits DeviceAdded once per device, but keeps its RSSI and
+    // advertisement properties current; re-read them periodically.
+    let mut refresh = tokio::time::interval(Duration::from_secs(2));
+    refresh.tick().await;
+
     loop {
         tokio::select! {
             _ = &mut deadline => {
                 debug!("Scan timeout reached");
                 break;
             }
+            _ = refresh.tick() => {
+                for (addr, known) in found.iter_mut() {
+                    if let Ok(device) = adapter.device(*addr) {
+                        if known.merge(read_advertisement(&device).await) {
+                            events.send(DeviceEvent::Updated(known.clone()));
+                        }
+                    }
+                }
+            }
             cmd = cmd_rx.recv() => {
                 if let Some(cmd) = cmd {
                     info!("Command received during scan, aborting scan early");
@@ -304,19 +671,29 @@ async fn scan_for_hr_devices(
                 match event {
                     Some(AdapterEvent::DeviceAdded(addr)) => {
                         if let Ok(device) = adapter.device(addr) {
-                            if is_hr_device(&device).await {
-                                let name = device.name().await.ok().flatten()
-                                    .unwrap_or_else(|| "Unknown".to_string());
-                                let rssi = device.rssi().await.ok().flatten().unwrap_or(0);
-                                info!("Found HR device: {} ({}) RSSI={}", name, addr, rssi);
-                                found.insert(addr, BleDevice {
-                                    address: addr.to_string(),
-                                    name,
-                                    rssi,
-                                });
+                            if is_hr_device(&device, extra_services).await {
+                                let sighting = read_advertisement(&device).await;
+                                match found.get_mut(&addr) {
+                                    Some(known) => {
+                                        if known.merge(sighting) {
+                                            events.send(DeviceEvent::Updated(known.clone()));
+                                        }
+                                    }
+                                    None => {
+                                        info!("Found HR device: {} ({}) RSSI={}", sighting.name, addr, sighting.rssi);
+                                        events.send(DeviceEvent::Found(sighting.clone()));
+                                        found.insert(addr, sighting);
+                                    }
+                                }
                             }
                         }
                     }
+                    Some(AdapterEvent::DeviceRemoved(addr)) => {
+                        if let Some(gone) = found.remove(&addr) {
+                            info!("Lost HR device: {} ({})", gone.name, addr);
+                            events.send(DeviceEvent::Lost(addr.to_string()));
+                        }
+                    }
                     Some(_) => {}
                     None => break,
                 }
@@ -327,18 +704,152 @@ async fn scan_for_hr_devices(
     // Discovery stream drop handles cleanup (no need for set_discovery_filter)
 
     let mut devices: Vec<BleDevice> = found.into_values().collect();
-    devices.sort_by(|a, b| b.rssi.cmp(&a.rssi)); // strongest signal first
-    (devices, interrupted_cmd)
+    devices.sort_by_key(|d| std::cmp::Reverse(d.rssi)); // strongest signal first
+    events.send(DeviceEvent::ScanFinished(devices.len()));
+    Ok((devices, interrupted_cmd))
+}
+
+/// Snapshot a device's current advertisement properties.
+async fn read_advertisement(device: &Device) -> BleDevice {
+    let name = device.name().await.ok().flatten()
+        .unwrap_or_else(|| "Unknown".to_string());
+    let rssi = device.rssi().await.ok().flatten().unwrap_or(0);
+    let manufacturer_data = device.manufacturer_data().await.ok().flatten().unwrap_or_default();
+    let service_data = device.service_data().await.ok().flatten().unwrap_or_default();
+
+    BleDevice {
+        address: device.address().to_string(),
+        name,
+        rssi,
+        battery: battery_from_service_data(&service_data),
+        manufacturer_data: manufacturer_data
+            .iter()
+            .map(|(id, data)| (format!("0x{:04x}", id), hex_encode(data)))
+            .collect(),
+        service_data: service_data
+            .iter()
+            .map(|(uuid, data)| (uuid.to_string(), hex_encode(data)))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Battery level from advertised Battery Service data (first byte, 0-100).
+fn battery_from_service_data(service_data: &HashMap<Uuid, Vec<u8>>) -> Option<u8> {
+    service_data
+        .get(&BATTERY_SERVICE_UUID)
+        .and_then(|data| data.first().copied())
+        .filter(|level| *level <= 100)
+}
+
+/// A resolvable private address: random, with the two most significant
+/// bits `01`. Static random (`11`) addresses are already stable.
+fn is_resolvable_private(address: Address) -> bool {
+    address.0[0] & 0xC0 == 0x40
 }
 
-/// Check if a device advertises the Heart Rate Service.
-async fn is_hr_device(device: &Device) -> bool {
+/// Bond with a strap that connected from a resolvable private address so
+/// BlueZ stores its IRK, and return the identity address it resolves to.
+/// None if bonding fails or the strap didn't distribute an identity.
+async fn resolve_identity(device: &Device, limit: Duration) -> Option<Address> {
+    if !device.is_paired().await.unwrap_or(false) {
+        info!("{} uses a private address, bonding to learn its identity", device.address());
+        match tokio::time::timeout(limit, device.pair()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("Bonding with {} failed: {}", device.address(), e);
+                return None;
+            }
+            Err(_) => {
+                warn!("Bonding with {} timed out after {:?}", device.address(), limit);
+                return None;
+            }
+        }
+    }
+    let _ = device.set_trusted(true).await;
+    // After bonding BlueZ reports the identity address here
+    let identity = device.remote_address().await.ok()?;
+    if identity == device.address() || is_resolvable_private(identity) {
+        return None;
+    }
+    info!("{} resolved to identity {}", device.address(), identity);
+    Some(identity)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Check if a device advertises the Heart Rate Service or an override service.
+async fn is_hr_device(device: &Device, extra_services: &[Uuid]) -> bool {
     if let Ok(Some(uuids)) = device.uuids().await {
-        return uuids.contains(&HR_SERVICE_UUID);
+        return uuids.contains(&HR_SERVICE_UUID)
+            || extra_services.iter().any(|u| uuids.contains(u));
     }
     false
 }
 
+/// Connection order for an attempt at `first`: `first`, then (if fallback is
+/// enabled) every other scan candidate by descending RSSI.
+fn fallback_order(first: Address, candidates: &[BleDevice], fallback: bool) -> Vec<Address> {
+    let mut order = vec![first];
+    if fallback {
+        let mut others: Vec<&BleDevice> = candidates.iter().collect();
+        others.sort_by_key(|d| std::cmp::Reverse(d.rssi));
+        for d in others {
+            if let Ok(addr) = d.address.parse::<Address>() {
+                if !order.contains(&addr) {
+                    order.push(addr);
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Connect to `first` and stream until it disconnects. If the connection
+/// can't be established, move on to the next-strongest scan candidate.
+/// A command arriving on `cmd_rx` stops the fallback chain so the caller
+/// can act on it.
+async fn connect_with_fallback(
+    adapter: &Adapter,
+    first: Address,
+    candidates: &[BleDevice],
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    cmd_rx: &mut mpsc::Receiver<HrmCommand>,
+    watchdog: &mut Watchdog,
+) {
+    let fallback = config::load(config_path).unwrap_or_default().candidate_fallback;
+    let health = state.lock().await.health.clone();
+    for (i, address) in fallback_order(first, candidates, fallback).into_iter().enumerate() {
+        if i > 0 {
+            info!("Falling back to next candidate {}", address);
+        }
+        let result = connect_and_stream(adapter, address, state, config_path, cmd_rx, watchdog).await;
+        mark_disconnected(state).await;
+        match result {
+            Ok(()) => {
+                info!("Device {} disconnected", address);
+                return;
+            }
+            Err(e) => {
+                warn!("Connection to {} failed: {}", address, e);
+                let failed = ConnectionEvent::Failed { address: address.to_string(), error: e.to_string() };
+                state.lock().await.connection_events.send(failed);
+                health.count(Counter::Errors);
+                config::update_stats(config_path, &address.to_string(), |s| s.failures += 1);
+                if let Some(e) = e.downcast_ref::<bluer::Error>() {
+                    watchdog.check(e, adapter, &health).await;
+                }
+            }
+        }
+        if !cmd_rx.is_empty() {
+            return;
+        }
+    }
+}
+
 /// Connect to a device, find the HR characteristic, and stream notifications.
 /// Uses `tokio::select!` to respond to commands immediately, even while
 /// waiting for BLE notifications.
@@ -348,40 +859,96 @@ async fn connect_and_stream(
     state: &Arc<Mutex<HrmState>>,
     config_path: &str,
     cmd_rx: &mut mpsc::Receiver<HrmCommand>,
+    watchdog: &mut Watchdog,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let device = adapter.device(address)?;
+    let cfg = config::load(config_path).unwrap_or_default();
 
     if !device.is_connected().await? {
         info!("Connecting to {}...", address);
-        device.connect().await?;
+        let limit = Duration::from_secs(cfg.connect_timeout_secs);
+        if tokio::time::timeout(limit, device.connect()).await.is_err() {
+            // Cancel the pending BlueZ connection attempt
+            let _ = device.disconnect().await;
+            return Err(format!("connect timed out after {:?}", limit).into());
+        }
     }
 
     let name = device.name().await.ok().flatten()
         .unwrap_or_else(|| "Unknown".to_string());
     info!("Connected to {} ({})", name, address);
 
-    // Save to config
-    config::save(config_path, &config::HrmConfig {
-        address: address.to_string(),
-        name: name.clone(),
-    });
+    let identity = match device.address_type().await {
+        Ok(AddressType::LeRandom) if is_resolvable_private(address) => {
+            resolve_identity(&device, Duration::from_secs(cfg.connect_timeout_secs)).await
+        }
+        _ => None,
+    };
+    let key = identity.unwrap_or(address).to_string();
+
+    // Save to config (overrides and nickname in the file are preserved)
+    config::save_device(config_path, &address.to_string(), identity.map(|a| a.to_string()).as_deref(), &name);
+    let cfg = config::load(config_path).unwrap_or(cfg);
+    let hr_override = cfg.override_for(&key).cloned();
+    let nickname = cfg.nickname_for(&key).unwrap_or_default().to_string();
 
     // Update state
     {
         let mut s = state.lock().await;
         s.connected = true;
         s.device_name = name.clone();
-        s.device_address = address.to_string();
+        s.device_nickname = nickname;
+        s.device_address = key.clone();
         s.scanning = false;
+        s.last_sample = Some(Instant::now());
+        s.health.count(Counter::Connects);
+        watchdog.recovered(&s.health);
+        s.connection_events.send(ConnectionEvent::Connected { name: name.clone(), address: key.clone() });
     }
 
+    let started = Instant::now();
+    let services_timeout = Duration::from_secs(cfg.services_timeout_secs);
+    let result = stream_hr(&device, address, &key, hr_override.as_ref(), services_timeout, state, config_path, cmd_rx).await;
+    let secs = started.elapsed().as_secs();
+    config::update_stats(config_path, &key, |s| {
+        s.sessions += 1;
+        s.session_secs += secs;
+    });
+    result
+}
+
+/// Subscribe to a connected device's HR characteristic and stream
+/// notifications into `state` until it disconnects or a command arrives.
+#[allow(clippy::too_many_arguments)]
+async fn stream_hr(
+    device: &Device,
+    address: Address,
+    key: &str,
+    hr_override: Option<&HrOverride>,
+    services_timeout: Duration,
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    cmd_rx: &mut mpsc::Receiver<HrmCommand>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Find HR Measurement characteristic
-    let hr_char = find_hr_characteristic(&device).await?;
-    info!("Found HR Measurement characteristic, subscribing to notifications");
+    let (hr_char, parser) = match find_hr_characteristic(device, hr_override, services_timeout).await {
+        Ok(found) => found,
+        Err(e) => {
+            let _ = device.disconnect().await;
+            return Err(e);
+        }
+    };
+    info!("Found HR characteristic, subscribing to notifications (parser: {:?})", parser);
+
+    if let Some(level) = read_battery_level(device).await {
+        info!("Battery level {}%", level);
+        config::update_stats(config_path, key, |s| s.battery = Some(level));
+    }
 
     let notify_stream = hr_char.notify().await?;
 
     let mut notify_stream = Box::pin(notify_stream);
+    let rest = config::load(config_path).unwrap_or_default();
 
     loop {
         tokio::select! {
@@ -415,12 +982,21 @@ async fn connect_and_stream(
             notification = notify_stream.next() => {
                 match notification {
                     Some(data) => {
-                        if let Some(hr) = parse_hr_measurement(&data) {
+                        if let Some(hr) = parse_hr_value(parser, &data) {
                             debug!("HR: {} bpm", hr);
                             let mut s = state.lock().await;
+                            let now = Instant::now();
                             s.heart_rate = hr;
+                            s.last_sample = Some(now);
+                            s.health.touch("strap");
+                            s.health.count(Counter::HrSamples);
+                            if let Some(bpm) = s.resting.sample(now, hr) {
+                                drop(s);
+                                offer_resting_hr(state, config_path, &rest, bpm).await;
+                            }
                         } else {
                             warn!("Failed to parse HR measurement: {:?}", data);
+                            state.lock().await.health.count(Counter::Errors);
                         }
                     }
                     None => {
@@ -436,46 +1012,221 @@ async fn connect_and_stream(
     Ok(())
 }
 
-/// Walk the GATT service tree to find the HR Measurement characteristic.
-async fn find_hr_characteristic(
+/// Wait up to `limit` for BlueZ to resolve a device's GATT services.
+async fn wait_services_resolved(
     device: &Device,
-) -> Result<Characteristic, Box<dyn std::error::Error + Send + Sync>> {
-    // Wait briefly for services to be resolved
-    for _ in 0..20 {
-        if device.is_services_resolved().await? {
-            break;
+    limit: Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let deadline = tokio::time::Instant::now() + limit;
+    while !device.is_services_resolved().await? {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(format!("GATT services not resolved after {:?}", limit).into());
         }
         tokio::time::sleep(Duration::from_millis(250)).await;
     }
+    Ok(())
+}
+
+/// Walk the GATT service tree to find the HR characteristic: the override's
+/// service/characteristic if one is configured for this device, otherwise
+/// the standard HR Measurement. Returns the parser to decode it with.
+async fn find_hr_characteristic(
+    device: &Device,
+    hr_override: Option<&HrOverride>,
+    services_timeout: Duration,
+) -> Result<(Characteristic, HrParser), Box<dyn std::error::Error + Send + Sync>> {
+    let (service_uuid, char_uuid, parser) = match hr_override {
+        Some(o) => {
+            let service = parse_uuid(&o.service)
+                .ok_or_else(|| format!("invalid override service UUID '{}'", o.service))?;
+            let chr = parse_uuid(&o.characteristic)
+                .ok_or_else(|| format!("invalid override characteristic UUID '{}'", o.characteristic))?;
+            info!("Using HR override: service {} characteristic {}", service, chr);
+            (service, chr, o.parser)
+        }
+        None => (HR_SERVICE_UUID, HR_MEASUREMENT_UUID, HrParser::Standard),
+    };
+
+    wait_services_resolved(device, services_timeout).await?;
 
     for service in device.services().await? {
         let uuid = service.uuid().await?;
-        if uuid == HR_SERVICE_UUID {
+        if uuid == service_uuid {
             for chr in service.characteristics().await? {
                 let chr_uuid = chr.uuid().await?;
-                if chr_uuid == HR_MEASUREMENT_UUID {
-                    return Ok(chr);
+                if chr_uuid == char_uuid {
+                    return Ok((chr, parser));
                 }
             }
         }
     }
 
-    Err("HR Measurement characteristic not found".into())
+    Err(format!("HR characteristic {} not found in service {}", char_uuid, service_uuid).into())
+}
+
+/// Read the standard Battery Level characteristic, if the device has one.
+/// Call after services are resolved.
+async fn read_battery_level(device: &Device) -> Option<u8> {
+    for service in device.services().await.ok()? {
+        if service.uuid().await.ok()? != BATTERY_SERVICE_UUID {
+            continue;
+        }
+        for chr in service.characteristics().await.ok()? {
+            if chr.uuid().await.ok()? == BATTERY_LEVEL_UUID {
+                return chr.read().await.ok()?.first().copied().filter(|&level| level <= 100);
+            }
+        }
+    }
+    None
+}
+
+/// Connect to any BLE device and render its full GATT tree (services,
+/// characteristics with properties, descriptors) for the `gattdump` debug
+/// command. Uses its own BlueZ session so the scanner loop is undisturbed,
+/// and disconnects afterwards unless the device was already connected.
+pub async fn gatt_dump(
+    addr: &str,
+    config_path: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let address: Address = addr.parse().map_err(|e| format!("invalid address '{}': {}", addr, e))?;
+    let cfg = config::load(config_path).unwrap_or_default();
+    let session = bluer::Session::new().await?;
+    let adapter = session.default_adapter().await?;
+    let device = adapter.device(address)?;
+
+    let was_connected = device.is_connected().await?;
+    if !was_connected {
+        tokio::time::timeout(Duration::from_secs(cfg.connect_timeout_secs), device.connect())
+            .await
+            .map_err(|_| "connect timed out")??;
+    }
+
+    let result = render_gatt_tree(&device, Duration::from_secs(cfg.services_timeout_secs)).await;
+
+    if !was_connected {
+        let _ = device.disconnect().await;
+    }
+    result
+}
+
+async fn render_gatt_tree(
+    device: &Device,
+    services_timeout: Duration,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    wait_services_resolved(device, services_timeout).await?;
+
+    let name = device.name().await.ok().flatten().unwrap_or_else(|| "Unknown".to_string());
+    let mut out = format!("device {} ({})", device.address(), name);
+
+    let mut services = device.services().await?;
+    services.sort_by_key(|s| s.id());
+    for service in services {
+        let kind = if service.primary().await? { "primary" } else { "secondary" };
+        out.push_str(&format!("\nservice 0x{:04x} {} ({})", service.id(), service.uuid().await?, kind));
+
+        let mut chars = service.characteristics().await?;
+        chars.sort_by_key(|c| c.id());
+        for chr in chars {
+            let flags = chr.flags().await?;
+            out.push_str(&format!(
+                "\n  char 0x{:04x} {} [{}]",
+                chr.id(),
+                chr.uuid().await?,
+                format_char_flags(&flags)
+            ));
+
+            let mut descs = chr.descriptors().await?;
+            descs.sort_by_key(|d| d.id());
+            for desc in descs {
+                out.push_str(&format!("\n    desc 0x{:04x} {}", desc.id(), desc.uuid().await?));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Render characteristic properties as a compact comma-separated list.
+fn format_char_flags(flags: &CharacteristicFlags) -> String {
+    let names = [
+        (flags.broadcast, "broadcast"),
+        (flags.read, "read"),
+        (flags.write_without_response, "write-without-response"),
+        (flags.write, "write"),
+        (flags.notify, "notify"),
+        (flags.indicate, "indicate"),
+        (flags.authenticated_signed_writes, "signed-write"),
+        (flags.reliable_write, "reliable-write"),
+        (flags.encrypt_read || flags.encrypt_authenticated_read, "encrypted-read"),
+        (flags.encrypt_write || flags.encrypt_authenticated_write, "encrypted-write"),
+    ];
+    names
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Set or clear (empty `nickname`) a saved device's nickname, and reflect
+/// it immediately in the live state and last scan results.
+pub async fn set_nickname(
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    address: &str,
+    nickname: &str,
+) -> Result<(), String> {
+    config::set_nickname(config_path, address, nickname)?;
+    let nickname = nickname.trim();
+
+    let mut s = state.lock().await;
+    if s.device_address.eq_ignore_ascii_case(address) {
+        s.device_nickname = nickname.to_string();
+    }
+    for d in s.available_devices.iter_mut().filter(|d| d.address.eq_ignore_ascii_case(address)) {
+        d.nickname = Some(nickname.to_string()).filter(|n| !n.is_empty());
+    }
+    Ok(())
+}
+
+/// Record a steady `bpm` as today's resting HR if it's in a rest window
+/// and a new low.
+async fn offer_resting_hr(state: &Arc<Mutex<HrmState>>, config_path: &str, rest: &HrmConfig, bpm: u16) {
+    let (date, minute) = resting::local_date_minute(rest.timezone.as_deref(), config::unix_now());
+    if !resting::in_rest_window(&rest.rest_windows, minute) || !state.lock().await.resting.is_new_low(&date, bpm) {
+        return;
+    }
+    let low = config::record_resting_hr(config_path, &date, bpm);
+    state.lock().await.resting.set_low(&date, low);
 }
 
 /// Mark state as disconnected and clear HR.
 async fn mark_disconnected(state: &Arc<Mutex<HrmState>>) {
     let mut s = state.lock().await;
+    if s.connected {
+        let address = s.device_address.clone();
+        s.connection_events.send(ConnectionEvent::Disconnected { address });
+    }
+    s.resting.reset();
     s.connected = false;
     s.heart_rate = 0;
     s.device_name.clear();
+    s.device_nickname.clear();
     s.device_address.clear();
+    s.last_sample = None;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_resolvable_private() {
+        assert!(is_resolvable_private("5A:11:22:33:44:55".parse().unwrap()));
+        assert!(is_resolvable_private("7F:FF:FF:FF:FF:FF".parse().unwrap()));
+        assert!(!is_resolvable_private("C0:AA:BB:CC:DD:EE".parse().unwrap()), "static random");
+        assert!(!is_resolvable_private("0A:11:22:33:44:55".parse().unwrap()), "non-resolvable");
+    }
+
     #[test]
     fn test_parse_hr_uint8() {
         // flags=0x00 (uint8 format), HR=72
@@ -548,6 +1299,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_uuid_short_and_full() {
+        assert_eq!(parse_uuid("180d"), Some(HR_SERVICE_UUID));
+        assert_eq!(parse_uuid("0x2A37"), Some(HR_MEASUREMENT_UUID));
+        assert_eq!(
+            parse_uuid("0000180d-0000-1000-8000-00805f9b34fb"),
+            Some(HR_SERVICE_UUID)
+        );
+        assert_eq!(parse_uuid("nope"), None);
+    }
+
+    #[test]
+    fn test_parse_hr_value_parsers() {
+        let data = [0xAA, 0x00, 0x8C, 0x01];
+        assert_eq!(parse_hr_value(HrParser::Standard, &[0x00, 72]), Some(72));
+        assert_eq!(parse_hr_value(HrParser::Uint8 { offset: 2 }, &data), Some(140));
+        assert_eq!(parse_hr_value(HrParser::Uint16Le { offset: 2 }, &data), Some(396));
+        assert_eq!(parse_hr_value(HrParser::Uint8 { offset: 4 }, &data), None);
+        assert_eq!(parse_hr_value(HrParser::Uint16Le { offset: 3 }, &data), None);
+        assert_eq!(parse_hr_value(HrParser::Uint16Le { offset: usize::MAX }, &data), None);
+    }
+
+    #[test]
+    fn test_merge_sighting_updates_rssi_and_fills_gaps() {
+        let mut known = BleDevice {
+            address: "AA:BB:CC:DD:EE:FF".into(),
+            name: "Unknown".into(),
+            rssi: -80,
+            ..Default::default()
+        };
+        let mut later = BleDevice { name: "Polar H10".into(), rssi: -55, battery: Some(90), ..Default::default() };
+        later.manufacturer_data.insert("0x006b".into(), "0102".into());
+        assert!(known.merge(later));
+        assert_eq!(known.rssi, -55);
+        assert_eq!(known.name, "Polar H10");
+        assert_eq!(known.battery, Some(90));
+        assert_eq!(known.manufacturer_data["0x006b"], "0102");
+
+        // A sighting without RSSI or name doesn't erase what we have
+        assert!(!known.merge(BleDevice { name: "Unknown".into(), ..Default::default() }), "nothing new to report");
+        assert_eq!(known.rssi, -55);
+        assert_eq!(known.name, "Polar H10");
+        assert_eq!(known.battery, Some(90));
+    }
+
+    #[test]
+    fn test_staleness() {
+        let now = Instant::now();
+        let mut s = HrmState { connected: true, ..Default::default() };
+        assert!(!s.is_stale(now), "no timing info (mock) is never stale");
+
+        s.last_sample = Some(now);
+        assert!(!s.is_stale(now + STALE_AFTER));
+        assert!(s.is_stale(now + STALE_AFTER + Duration::from_millis(1)));
+        assert_eq!(s.sample_age(now + Duration::from_secs(2)), Some(Duration::from_secs(2)));
+
+        s.connected = false;
+        assert!(!s.is_stale(now + Duration::from_secs(60)), "disconnected is not stale");
+    }
+
+    #[test]
+    fn test_fallback_order() {
+        let dev = |addr: &str, rssi| BleDevice { address: addr.into(), rssi, ..Default::default() };
+        let first: Address = "AA:AA:AA:AA:AA:AA".parse().unwrap();
+        let candidates = [
+            dev("BB:BB:BB:BB:BB:BB", -80),
+            dev("AA:AA:AA:AA:AA:AA", -50),
+            dev("CC:CC:CC:CC:CC:CC", -60),
+            dev("not an address", -10),
+        ];
+
+        let order: Vec<String> = fallback_order(first, &candidates, true).iter().map(|a| a.to_string()).collect();
+        assert_eq!(order, ["AA:AA:AA:AA:AA:AA", "CC:CC:CC:CC:CC:CC", "BB:BB:BB:BB:BB:BB"]);
+
+        assert_eq!(fallback_order(first, &candidates, false), vec![first]);
+        assert_eq!(fallback_order(first, &[], true), vec![first]);
+    }
+
+    #[test]
+    fn test_battery_from_service_data() {
+        let mut data = HashMap::new();
+        assert_eq!(battery_from_service_data(&data), None);
+        data.insert(BATTERY_SERVICE_UUID, vec![87]);
+        assert_eq!(battery_from_service_data(&data), Some(87));
+        data.insert(BATTERY_SERVICE_UUID, vec![200]);
+        assert_eq!(battery_from_service_data(&data), None);
+    }
+
+    #[test]
+    fn test_format_char_flags() {
+        let flags = CharacteristicFlags { notify: true, read: true, ..Default::default() };
+        assert_eq!(format_char_flags(&flags), "read,notify");
+        assert_eq!(format_char_flags(&CharacteristicFlags::default()), "");
+    }
+
     #[test]
     fn test_drain_last_empty() {
         let (_tx, mut rx) = mpsc::channel::<HrmCommand>(8);
@@ -1,73 +1,417 @@
 //! Unix socket server for the HRM daemon.
 //!
 //! Accepts multiple clients on a Unix domain socket. Broadcasts heart rate
-//! data at 1 Hz as newline-delimited JSON. Accepts commands for device
-//! management (connect, disconnect, forget, scan).
+//! data at 1 Hz as newline-delimited JSON: `publish_hr` captures one
+//! snapshot a second into `HrmState::hr_updates`, and every client (and
+//! debug `sub`) sends that same snapshot on. Accepts commands for device
+//! management (connect, disconnect, forget, scan, nickname) and for the
+//! daily resting heart rate history (`resting_hr`).
+//!
+//! Each client's replies and broadcasts go through its own bounded outbox,
+//! drained by a writer task, so a client that stops reading never stalls
+//! its command loop or holds the state lock. When the outbox fills up the
+//! `slow_client` policy either drops the oldest queued messages or
+//! disconnects the client.
+//!
+//! The socket file is 0660, owned by `socket_group` or, by default, the
+//! group of the directory holding `hrm_config.json`. Under the deploy
+//! layout that directory belongs to the deploy user, so server.py, running
+//! as that user, keeps access while other local accounts lose it.
+//!
+//! Migrating from the old world-accessible 0777 socket: a client running
+//! as another account either joins the socket's group (set `socket_group`
+//! to a shared group such as `hrm` and add both accounts to it) or, as a
+//! stopgap, `socket_mode` goes back to `"0777"`.
+//!
+//! A `--socket` starting with `@` is a Linux abstract-namespace socket
+//! instead: no file to go stale or to protect, so any local process in the
+//! same network namespace can connect.
+//!
+//! Under systemd socket activation (`deploy/hrm.socket.in`) the socket
+//! is the unit's: `listen` adopts the descriptor systemd passes, or the
+//! one handed over by an in-place restart, as long as it's listening on
+//! `--socket`, and only binds one itself otherwise. An adopted socket
+//! keeps the mode and group it was created with (the unit's `SocketMode`
+//! and `SocketGroup`), so `socket_mode`/`socket_group` don't apply to it.
+//!
+//! A client can switch its connection to length-prefixed protobuf with a
+//! `framing` command; see `framing.rs`.
+//!
+//! What a client is sent besides replies is up to its topics: `hr` (the
+//! 1 Hz broadcast), `devices` (scan events as they happen) and
+//! `connection` (straps connecting, disconnecting and failing to).
+//! Clients start on `hr` alone; `{"cmd":"subscribe","topics":[...]}`
+//! replaces the set, and a client that leaves out `hr` doesn't keep the
+//! broadcast running.
 
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use log::{debug, info, warn};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::AsyncWriteExt;
 use tokio::net::UnixListener;
-use tokio::sync::Mutex;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{Mutex, Notify};
 use tokio::time::{interval, Duration};
 
-use crate::scanner::{HrmCommand, HrmState};
+use crate::audit::Origin;
+use crate::config::{self, HrmConfig, SlowClientPolicy};
+use crate::framing::{self, FrameReader, Framing};
+use crate::health::{Counter, Health};
+use crate::restart::Inherited;
+use crate::resting;
+use crate::throttle::Limits;
 
-/// Run the Unix socket server. Listens for clients and broadcasts HR data.
-pub async fn run(
-    state: Arc<Mutex<HrmState>>,
+/// HR-related JSON fields shared by the 1 Hz broadcast and `status` replies.
+/// `stale` flips true when a connected strap stops notifying; clients should
+/// grey out the number rather than trust `bpm`. `sample_mono_ms` (since
+/// daemon start) and `sample_time` (ISO 8601 UTC) say when `bpm` was
+/// measured, for lining it up with treadmill data.
+fn hr_fields(snapshot: &HrSnapshot) -> serde_json::Value {
+    serde_json::to_value(snapshot).expect("HrSnapshot serializes")
+}
+
+/// Build a message of `msg_type` from `fields` plus extra keys.
+fn with_type(msg_type: &str, mut fields: serde_json::Value, extra: serde_json::Value) -> serde_json::Value {
+    let map = fields.as_object_mut().expect("hr_fields is an object");
+    map.insert("type".to_string(), msg_type.into());
+    if let serde_json::Value::Object(extra) = extra {
+        map.extend(extra);
+    }
+    fields
+}
+
+use crate::scanner::{self, ConnectionEvent, DeviceEvent, HrSnapshot, HrmCommand, HrmState};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+struct OutboxQueue {
+    /// Encoded frames, in the framing that was current when each was queued.
+    lines: VecDeque<Vec<u8>>,
+    framing: Framing,
+    /// Messages dropped since the client last caught up.
+    dropped: u64,
+    /// Set once the disconnect policy has given up on the client.
+    overflowed: bool,
+}
+
+/// Outbound messages for one client, applying the slow-client policy.
+struct Outbox {
+    id: u64,
+    capacity: usize,
+    policy: SlowClientPolicy,
+    queue: std::sync::Mutex<OutboxQueue>,
+    ready: Notify,
+    health: Health,
+}
+
+impl Outbox {
+    fn new(id: u64, capacity: usize, policy: SlowClientPolicy, health: Health) -> Self {
+        Self {
+            id,
+            capacity: capacity.max(1),
+            policy,
+            queue: std::sync::Mutex::new(OutboxQueue {
+                lines: VecDeque::new(),
+                framing: Framing::JsonLines,
+                dropped: 0,
+                overflowed: false,
+            }),
+            ready: Notify::new(),
+            health,
+        }
+    }
+
+    /// Queue `msg` in the client's framing. Never waits on the socket.
+    fn send(&self, msg: &serde_json::Value) -> Result<(), BoxError> {
+        let mut q = self.queue.lock().unwrap();
+        let line = framing::encode(q.framing, msg)?;
+        if q.overflowed {
+            return Err("client outbox overflowed".into());
+        }
+        if q.lines.len() >= self.capacity {
+            match self.policy {
+                SlowClientPolicy::DropOldest => {
+                    if q.dropped == 0 {
+                        warn!("Client {} is falling behind, dropping oldest messages", self.id);
+                    }
+                    q.lines.pop_front();
+                    q.dropped += 1;
+                }
+                SlowClientPolicy::Disconnect => {
+                    warn!("Client {} fell {} messages behind, disconnecting", self.id, q.lines.len());
+                    q.overflowed = true;
+                    return Err("client outbox overflowed".into());
+                }
+            }
+        }
+        q.lines.push_back(line);
+        drop(q);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Encode messages queued from now on in `framing`.
+    fn set_framing(&self, framing: Framing) {
+        self.queue.lock().unwrap().framing = framing;
+    }
+
+    fn overflowed(&self) -> bool {
+        self.queue.lock().unwrap().overflowed
+    }
+
+    /// Wait for the next queued frame.
+    async fn next(&self) -> Vec<u8> {
+        loop {
+            {
+                let mut q = self.queue.lock().unwrap();
+                if let Some(line) = q.lines.pop_front() {
+                    if q.lines.is_empty() && q.dropped > 0 {
+                        info!("Client {} caught up after {} dropped messages", self.id, q.dropped);
+                        q.dropped = 0;
+                    }
+                    return line;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// Drain `outbox` into the socket until a write fails.
+async fn write_loop(outbox: Arc<Outbox>, mut writer: tokio::net::unix::OwnedWriteHalf) {
+    loop {
+        let line = outbox.next().await;
+        if let Err(e) = writer.write_all(&line).await {
+            debug!("Client {} write failed: {}", outbox.id, e);
+            outbox.health.count(Counter::Errors);
+            return;
+        }
+        outbox.health.count(Counter::Messages);
+        outbox.health.touch("server");
+    }
+}
+
+/// The socket to serve on: the inherited listener for `socket_path` if
+/// systemd or the previous process passed one, else a freshly bound one.
+/// Done before `run` so privileges can be dropped in between.
+pub fn listen(
     socket_path: &str,
-    cmd_tx: mpsc::Sender<HrmCommand>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    cfg: &HrmConfig,
+    config_path: &str,
+    inherited: &mut Inherited,
+) -> Result<UnixListener, BoxError> {
+    match inherited.unix("socket", socket_path) {
+        Some(listener) => {
+            if cfg.socket_mode.is_some() || cfg.socket_group.is_some() {
+                info!("socket_mode/socket_group don't apply to the inherited socket {}", socket_path);
+            }
+            Ok(listener)
+        }
+        None => bind(socket_path, cfg, config_path),
+    }
+}
+
+/// Create the socket.
+fn bind(socket_path: &str, cfg: &HrmConfig, config_path: &str) -> Result<UnixListener, BoxError> {
+    if let Some(name) = socket_path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+        listener.set_nonblocking(true)?;
+        if cfg.socket_mode.is_some() || cfg.socket_group.is_some() {
+            warn!("socket_mode/socket_group don't apply to abstract socket {}", socket_path);
+        }
+        info!("HRM server listening on abstract socket {}", socket_path);
+        return Ok(UnixListener::from_std(listener)?);
+    }
+
+    let mode = cfg.socket_mode()?;
+    let gid = match cfg.socket_group.as_deref() {
+        Some(group) => crate::privileges::lookup_group(group)?,
+        None => config_dir_gid(config_path)?,
+    };
+
     // Remove stale socket file
     let _ = std::fs::remove_file(socket_path);
 
     let listener = UnixListener::bind(socket_path)?;
 
-    // Make socket world-accessible (server.py runs as non-root user)
     use std::os::unix::fs::PermissionsExt;
-    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o777))?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(mode))?;
+    std::os::unix::fs::chown(socket_path, None, Some(gid))?;
+
+    info!("HRM server listening on {} (mode {:o}, gid {})", socket_path, mode, gid);
+    Ok(listener)
+}
 
-    info!("HRM server listening on {}", socket_path);
+/// Group owning the directory `config_path` is in.
+fn config_dir_gid(config_path: &str) -> Result<u32, BoxError> {
+    use std::os::unix::fs::MetadataExt;
+    let dir = match std::path::Path::new(config_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    Ok(std::fs::metadata(dir)?.gid())
+}
 
+/// Capture an HR snapshot once a second for every socket and debug `sub`
+/// client. Doesn't take the lock while nobody's subscribed.
+pub async fn publish_hr(state: Arc<Mutex<HrmState>>) {
+    let updates = state.lock().await.hr_updates.clone();
+    let mut tick = interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        if updates.has_subscribers() {
+            let snapshot = HrSnapshot::capture(&*state.lock().await, std::time::Instant::now());
+            updates.send(snapshot);
+        }
+    }
+}
+
+pub async fn run(
+    state: Arc<Mutex<HrmState>>,
+    listener: UnixListener,
+    config_path: String,
+    cmd_tx: mpsc::Sender<HrmCommand>,
+) -> Result<(), BoxError> {
+    let cfg = config::load(&config_path).unwrap_or_default();
+    let health = state.lock().await.health.clone();
+    let limits = Limits::new(cfg.max_clients, 0, Duration::ZERO);
+    let next_id = AtomicU64::new(1);
     loop {
         let (stream, _addr) = listener.accept().await?;
-        info!("Client connected");
+        let Some(slot) = limits.open() else {
+            warn!("Client refused: {} clients already connected", limits.max_connections());
+            // A fresh socket's send buffer is empty, so this can't block
+            if let Ok(mut stream) = stream.into_std() {
+                let _ = stream.write_all(b"{\"type\":\"error\",\"message\":\"too many clients, try again later\"}\n");
+            }
+            continue;
+        };
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        let cred = stream.peer_cred().ok();
+        let origin = Origin::Socket { client: id, uid: cred.map(|c| c.uid()), pid: cred.and_then(|c| c.pid()) };
+        info!("Client {} connected ({})", id, origin);
 
+        let outbox = Arc::new(Outbox::new(id, cfg.client_queue_len, cfg.slow_client, health.clone()));
         let state = state.clone();
+        let config_path = config_path.clone();
         let cmd_tx = cmd_tx.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, state, cmd_tx).await {
-                debug!("Client disconnected: {}", e);
+            let _slot = slot;
+            let (reader, writer) = stream.into_split();
+            let mut writer_task = tokio::spawn(write_loop(outbox.clone(), writer));
+            let result = handle_client(reader, &outbox, &mut writer_task, &state, &config_path, &cmd_tx, &origin).await;
+            writer_task.abort();
+            match result {
+                Ok(()) => info!("Client {} disconnected", id),
+                Err(e) => info!("Client {} disconnected: {}", id, e),
             }
         });
     }
 }
 
-async fn handle_client(
-    stream: tokio::net::UnixStream,
-    state: Arc<Mutex<HrmState>>,
-    cmd_tx: mpsc::Sender<HrmCommand>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (reader, mut writer) = stream.into_split();
-    let mut lines = BufReader::new(reader).lines();
+/// Event classes a socket client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Topic {
+    Hr,
+    Devices,
+    Connection,
+}
+
+impl Topic {
+    const ALL: [Topic; 3] = [Topic::Hr, Topic::Devices, Topic::Connection];
+
+    fn name(self) -> &'static str {
+        match self {
+            Topic::Hr => "hr",
+            Topic::Devices => "devices",
+            Topic::Connection => "connection",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// One client's receivers, one per subscribed topic.
+#[derive(Default)]
+struct Subscriptions {
+    hr: Option<broadcast::Receiver<Arc<HrSnapshot>>>,
+    devices: Option<broadcast::Receiver<DeviceEvent>>,
+    connection: Option<broadcast::Receiver<ConnectionEvent>>,
+}
+
+impl Subscriptions {
+    /// Receive exactly `topics`, keeping the receivers of topics already
+    /// subscribed so nothing queued for them is lost.
+    fn set(&mut self, topics: &[Topic], s: &HrmState) {
+        let wants = |topic| topics.contains(&topic);
+        if !wants(Topic::Hr) {
+            self.hr = None;
+        } else if self.hr.is_none() {
+            self.hr = Some(s.hr_updates.subscribe());
+        }
+        if !wants(Topic::Devices) {
+            self.devices = None;
+        } else if self.devices.is_none() {
+            self.devices = Some(s.device_events.subscribe());
+        }
+        if !wants(Topic::Connection) {
+            self.connection = None;
+        } else if self.connection.is_none() {
+            self.connection = Some(s.connection_events.subscribe());
+        }
+    }
+
+    fn topics(&self) -> Vec<&'static str> {
+        let subscribed = [self.hr.is_some(), self.devices.is_some(), self.connection.is_some()];
+        Topic::ALL.into_iter().zip(subscribed).filter(|(_, on)| *on).map(|(t, _)| t.name()).collect()
+    }
+}
+
+/// The next event from `rx`, or never when the topic isn't subscribed.
+async fn next_event<T: Clone>(rx: &mut Option<broadcast::Receiver<T>>) -> Result<T, broadcast::error::RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
-    let mut broadcast_interval = interval(Duration::from_secs(1));
-    // Skip the first immediate tick
-    broadcast_interval.tick().await;
+async fn handle_client(
+    reader: tokio::net::unix::OwnedReadHalf,
+    outbox: &Outbox,
+    writer_task: &mut tokio::task::JoinHandle<()>,
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    cmd_tx: &mpsc::Sender<HrmCommand>,
+    origin: &Origin,
+) -> Result<(), BoxError> {
+    let mut frames = FrameReader::new(reader);
+    let mut subs = Subscriptions::default();
+    subs.set(&[Topic::Hr], &*state.lock().await);
 
     loop {
         tokio::select! {
-            line_result = lines.next_line() => {
-                match line_result {
-                    Ok(Some(line)) => {
-                        let line = line.trim().to_string();
-                        if line.is_empty() {
-                            continue;
-                        }
-                        if let Err(e) = handle_command(&line, &state, &cmd_tx, &mut writer).await {
+            frame = frames.next() => {
+                match frame {
+                    Ok(Some(parsed)) => {
+                        outbox.health.count(Counter::Commands);
+                        let result = match parsed {
+                            Ok(parsed) if parsed.get("cmd").and_then(|v| v.as_str()) == Some("framing") => {
+                                switch_framing(&parsed, &mut frames, outbox)
+                            }
+                            Ok(parsed) if parsed.get("cmd").and_then(|v| v.as_str()) == Some("subscribe") => {
+                                subscribe(&parsed, &mut subs, state, outbox).await
+                            }
+                            Ok(parsed) => handle_command(parsed, state, config_path, cmd_tx, outbox, origin).await,
+                            Err(message) => send_error(outbox, &message).await,
+                        };
+                        if let Err(e) = result {
                             warn!("Error handling command: {}", e);
                         }
                     }
@@ -75,80 +419,176 @@ async fn handle_client(
                     Err(e) => return Err(e.into()),
                 }
             }
-            _ = broadcast_interval.tick() => {
-                let msg = {
-                    let s = state.lock().await;
-                    serde_json::json!({
-                        "type": "hr",
-                        "bpm": s.heart_rate,
-                        "connected": s.connected,
-                        "device": s.device_name,
-                        "address": s.device_address,
-                    })
-                };
-                let mut line = serde_json::to_string(&msg)?;
-                line.push('\n');
-                if writer.write_all(line.as_bytes()).await.is_err() {
-                    return Ok(()); // Client gone
+            // A full outbox is the policy's problem; overflow is checked below
+            update = next_event(&mut subs.hr) => {
+                match update {
+                    Ok(snapshot) => {
+                        let _ = outbox.send(&with_type("hr", hr_fields(&snapshot), serde_json::Value::Null));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => debug!("Client {} missed {} HR updates", outbox.id, n),
+                    Err(broadcast::error::RecvError::Closed) => return Err("HR updates ended".into()),
                 }
             }
+            event = next_event(&mut subs.devices) => {
+                match event {
+                    Ok(event) => {
+                        let _ = outbox.send(&device_message(&event));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => debug!("Client {} missed {} device events", outbox.id, n),
+                    Err(broadcast::error::RecvError::Closed) => return Err("device events ended".into()),
+                }
+            }
+            event = next_event(&mut subs.connection) => {
+                match event {
+                    Ok(event) => {
+                        let _ = outbox.send(&connection_message(&event));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => debug!("Client {} missed {} connection events", outbox.id, n),
+                    Err(broadcast::error::RecvError::Closed) => return Err("connection events ended".into()),
+                }
+            }
+            _ = &mut *writer_task => return Ok(()), // Client gone
+        }
+        if outbox.overflowed() {
+            return Err("too slow, disconnected".into());
         }
     }
 }
 
-async fn handle_command(
-    line: &str,
+/// `{"cmd":"framing","mode":"protobuf"|"json"}`: acknowledge in the
+/// current framing, then switch both directions.
+fn switch_framing<R>(parsed: &serde_json::Value, frames: &mut FrameReader<R>, outbox: &Outbox) -> Result<(), BoxError> {
+    let mode = parsed.get("mode").and_then(|v| v.as_str()).unwrap_or("");
+    let Some(framing) = Framing::from_mode(mode) else {
+        return outbox.send(&serde_json::json!({ "type": "error", "message": format!("unknown framing: '{}'", mode) }));
+    };
+    info!("Client {} switching to {} framing", outbox.id, framing.mode());
+    outbox.send(&serde_json::json!({ "type": "framing", "mode": framing.mode() }))?;
+    outbox.set_framing(framing);
+    frames.framing = framing;
+    Ok(())
+}
+
+/// `{"cmd":"subscribe","topics":["hr","devices","connection"]}`: receive
+/// exactly these topics from now on. Unknown topics change nothing.
+async fn subscribe(
+    parsed: &serde_json::Value,
+    subs: &mut Subscriptions,
     state: &Arc<Mutex<HrmState>>,
-    cmd_tx: &mpsc::Sender<HrmCommand>,
-    writer: &mut tokio::net::unix::OwnedWriteHalf,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let parsed: serde_json::Value = match serde_json::from_str(line) {
-        Ok(v) => v,
-        Err(e) => {
-            let err_msg = serde_json::json!({
-                "type": "error",
-                "message": format!("invalid JSON: {}", e),
-            });
-            let mut out = serde_json::to_string(&err_msg)?;
-            out.push('\n');
-            writer.write_all(out.as_bytes()).await?;
-            return Ok(());
-        }
+    outbox: &Outbox,
+) -> Result<(), BoxError> {
+    let Some(names) = parsed.get("topics").and_then(|v| v.as_array()) else {
+        return send_error(outbox, "missing 'topics' list").await;
     };
+    let mut topics = Vec::new();
+    for name in names {
+        let name = name.as_str().unwrap_or_default();
+        match Topic::from_name(name) {
+            Some(topic) => topics.push(topic),
+            None => {
+                let known: Vec<_> = Topic::ALL.iter().map(|t| t.name()).collect();
+                return send_error(outbox, &format!("unknown topic: '{}' (topics: {})", name, known.join(", "))).await;
+            }
+        }
+    }
+    subs.set(&topics, &*state.lock().await);
+    info!("Client {} subscribed to {:?}", outbox.id, subs.topics());
+    outbox.send(&serde_json::json!({ "type": "subscribed", "topics": subs.topics() }))
+}
+
+/// A `devices` topic message.
+fn device_message(event: &DeviceEvent) -> serde_json::Value {
+    match event {
+        DeviceEvent::ScanStarted => serde_json::json!({ "type": "device", "event": "scan_started" }),
+        DeviceEvent::Found(device) => serde_json::json!({ "type": "device", "event": "found", "device": device }),
+        DeviceEvent::Updated(device) => serde_json::json!({ "type": "device", "event": "updated", "device": device }),
+        DeviceEvent::Lost(address) => serde_json::json!({ "type": "device", "event": "lost", "address": address }),
+        DeviceEvent::ScanFinished(count) => serde_json::json!({ "type": "device", "event": "scan_finished", "count": count }),
+    }
+}
+
+/// A `connection` topic message.
+fn connection_message(event: &ConnectionEvent) -> serde_json::Value {
+    match event {
+        ConnectionEvent::Connected { name, address } => {
+            serde_json::json!({ "type": "connection", "event": "connected", "name": name, "address": address })
+        }
+        ConnectionEvent::Disconnected { address } => {
+            serde_json::json!({ "type": "connection", "event": "disconnected", "address": address })
+        }
+        ConnectionEvent::Failed { address, error } => {
+            serde_json::json!({ "type": "connection", "event": "failed", "address": address, "error": error })
+        }
+    }
+}
 
+async fn handle_command(
+    parsed: serde_json::Value,
+    state: &Arc<Mutex<HrmState>>,
+    config_path: &str,
+    cmd_tx: &mpsc::Sender<HrmCommand>,
+    outbox: &Outbox,
+    origin: &Origin,
+) -> Result<(), BoxError> {
     let cmd = parsed.get("cmd").and_then(|v| v.as_str()).unwrap_or("");
 
     match cmd {
         "connect" => {
             let address = parsed.get("address").and_then(|v| v.as_str()).unwrap_or("");
             if address.is_empty() {
-                send_error(writer, "missing 'address' field").await?;
+                send_error(outbox, "missing 'address' field").await?;
                 return Ok(());
             }
+            let address = match config::resolve_connect(config_path, address) {
+                Ok(address) => address,
+                Err(e) => return send_error(outbox, &e).await,
+            };
             info!("Connect command for {}", address);
+            state.lock().await.audit.record(origin, format!("connect {}", address));
             let _ = cmd_tx.send(HrmCommand::Connect(address.to_string())).await;
-            send_status(state, writer).await?;
+            send_status(state, outbox).await?;
         }
         "disconnect" => {
             info!("Disconnect command");
+            state.lock().await.audit.record(origin, "disconnect");
             let _ = cmd_tx.send(HrmCommand::Disconnect).await;
-            send_status(state, writer).await?;
+            send_status(state, outbox).await?;
         }
         "forget" => {
             info!("Forget command");
+            state.lock().await.audit.record(origin, "forget");
             let _ = cmd_tx.send(HrmCommand::Forget).await;
-            send_status(state, writer).await?;
+            send_status(state, outbox).await?;
         }
         "scan" => {
             info!("Scan command");
+            state.lock().await.audit.record(origin, "scan");
             let _ = cmd_tx.send(HrmCommand::Scan).await;
-            send_status(state, writer).await?;
+            send_status(state, outbox).await?;
+        }
+        "nickname" => {
+            let address = parsed.get("address").and_then(|v| v.as_str()).unwrap_or("");
+            if address.is_empty() {
+                send_error(outbox, "missing 'address' field").await?;
+                return Ok(());
+            }
+            // Missing or empty nickname clears it
+            let nickname = parsed.get("nickname").and_then(|v| v.as_str()).unwrap_or("");
+            info!("Nickname command for {}: '{}'", address, nickname);
+            match scanner::set_nickname(state, config_path, address, nickname).await {
+                Ok(()) => send_status(state, outbox).await?,
+                Err(e) => send_error(outbox, &e).await?,
+            }
         }
         "status" => {
-            send_status(state, writer).await?;
+            send_status(state, outbox).await?;
+        }
+        "resting_hr" => {
+            let days = parsed.get("days").and_then(|v| v.as_u64()).unwrap_or(30) as usize;
+            outbox.send(&resting_hr_message(&config::load(config_path).unwrap_or_default(), days))?;
         }
         _ => {
-            send_error(writer, &format!("unknown command: '{}'", cmd)).await?;
+            send_error(outbox, &format!("unknown command: '{}'", cmd)).await?;
         }
     }
 
@@ -157,36 +597,253 @@ async fn handle_command(
 
 async fn send_status(
     state: &Arc<Mutex<HrmState>>,
-    writer: &mut tokio::net::unix::OwnedWriteHalf,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    outbox: &Outbox,
+) -> Result<(), BoxError> {
     let s = state.lock().await;
-    let msg = serde_json::json!({
-        "type": "status",
-        "scanning": s.scanning,
-        "connected": s.connected,
-        "bpm": s.heart_rate,
-        "device": s.device_name,
-        "address": s.device_address,
-        "available_devices": s.available_devices,
-    });
+    let msg = with_type(
+        "status",
+        hr_fields(&HrSnapshot::capture(&s, std::time::Instant::now())),
+        serde_json::json!({
+            "scanning": s.scanning,
+            "available_devices": s.available_devices,
+        }),
+    );
     drop(s);
+    outbox.send(&msg)
+}
 
-    let mut line = serde_json::to_string(&msg)?;
-    line.push('\n');
-    writer.write_all(line.as_bytes()).await?;
-    Ok(())
+/// Daily resting HRs for the last `days` recorded days, newest first, with
+/// today's value and 7/30-day averages as a trend.
+fn resting_hr_message(cfg: &config::HrmConfig, days: usize) -> serde_json::Value {
+    let (today, _) = resting::local_date_minute(cfg.timezone.as_deref(), config::unix_now());
+    let history: Vec<_> = cfg
+        .resting_hr
+        .iter()
+        .rev()
+        .take(days)
+        .map(|(date, bpm)| serde_json::json!({ "date": date, "bpm": bpm }))
+        .collect();
+    serde_json::json!({
+        "type": "resting_hr",
+        "today": cfg.resting_hr.get(&today),
+        "avg_7d": resting::average(&cfg.resting_hr, 7),
+        "avg_30d": resting::average(&cfg.resting_hr, 30),
+        "days": history,
+    })
 }
 
 async fn send_error(
-    writer: &mut tokio::net::unix::OwnedWriteHalf,
+    outbox: &Outbox,
     message: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), BoxError> {
     let msg = serde_json::json!({
         "type": "error",
         "message": message,
     });
-    let mut line = serde_json::to_string(&msg)?;
-    line.push('\n');
-    writer.write_all(line.as_bytes()).await?;
-    Ok(())
+    outbox.send(&msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(n: u32) -> serde_json::Value {
+        serde_json::json!({ "n": n })
+    }
+
+    #[test]
+    fn test_resting_hr_message() {
+        let mut cfg = config::HrmConfig { timezone: Some("UTC".to_string()), ..Default::default() };
+        cfg.record_resting_hr("2026-10-14", 60);
+        cfg.record_resting_hr("2026-10-15", 56);
+        let msg = resting_hr_message(&cfg, 1);
+        assert_eq!(msg["type"], "resting_hr");
+        assert_eq!(msg["days"], serde_json::json!([{ "date": "2026-10-15", "bpm": 56 }]));
+        assert_eq!(msg["avg_7d"], 58.0);
+    }
+
+    #[test]
+    fn test_hr_sample_timestamps() {
+        let mut s = HrmState::default();
+        let fields = hr_fields(&HrSnapshot::capture(&s, std::time::Instant::now()));
+        assert!(fields["sample_mono_ms"].is_null() && fields["sample_time"].is_null());
+
+        s.last_sample = Some(s.health.started() + Duration::from_millis(1500));
+        let fields = hr_fields(&HrSnapshot::capture(&s, std::time::Instant::now()));
+        assert_eq!(fields["sample_mono_ms"], 1500);
+        let time = fields["sample_time"].as_str().unwrap();
+        assert!(time.len() == 24 && time.ends_with('Z'), "{}", time);
+    }
+
+    #[tokio::test]
+    async fn test_bind_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = std::env::temp_dir().join(format!("hrm_bind_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("hrm.sock");
+        let config_path = dir.join("hrm_config.json");
+        let socket = socket.to_str().unwrap();
+
+        let _listener = bind(socket, &HrmConfig::default(), config_path.to_str().unwrap()).unwrap();
+        let meta = std::fs::metadata(socket).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+        assert_eq!(meta.gid(), std::fs::metadata(&dir).unwrap().gid());
+
+        let cfg = HrmConfig { socket_mode: Some("0600".to_string()), ..Default::default() };
+        let _listener = bind(socket, &cfg, config_path.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::metadata(socket).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let cfg = HrmConfig { socket_group: Some("no-such-group-hrm".to_string()), ..Default::default() };
+        assert!(bind(socket, &cfg, config_path.to_str().unwrap()).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_max_clients() {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+        let dir = std::env::temp_dir().join(format!("hrm_max_clients_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("hrm_config.json");
+        std::fs::write(&config_path, r#"{"max_clients": 1}"#).unwrap();
+        let socket = dir.join("hrm.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let (cmd_tx, _cmd_rx) = mpsc::channel(4);
+        let state = Arc::new(Mutex::new(HrmState::default()));
+        tokio::spawn(run(state, listener, config_path.to_str().unwrap().to_string(), cmd_tx));
+
+        let mut first = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        first.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+        let mut first = BufReader::new(first).lines();
+        assert!(first.next_line().await.unwrap().unwrap().contains("\"type\":\"status\""));
+
+        let mut second = BufReader::new(tokio::net::UnixStream::connect(&socket).await.unwrap()).lines();
+        assert!(second.next_line().await.unwrap().unwrap().contains("too many clients"));
+        assert_eq!(second.next_line().await.unwrap(), None, "closed");
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        third.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+        let mut third = BufReader::new(third).lines();
+        assert!(third.next_line().await.unwrap().unwrap().contains("\"type\":\"status\""), "slot freed");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_clients_share_hr_snapshots() {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+        let dir = std::env::temp_dir().join(format!("hrm_hr_updates_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("hrm.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let (cmd_tx, _cmd_rx) = mpsc::channel(4);
+        let state = Arc::new(Mutex::new(HrmState { heart_rate: 142, connected: true, ..Default::default() }));
+        let config_path = dir.join("hrm_config.json").to_str().unwrap().to_string();
+        tokio::spawn(run(state.clone(), listener, config_path, cmd_tx));
+
+        let mut first = BufReader::new(tokio::net::UnixStream::connect(&socket).await.unwrap()).lines();
+        let mut second = BufReader::new(tokio::net::UnixStream::connect(&socket).await.unwrap()).lines();
+        // Both subscribed before the first snapshot goes out
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        tokio::spawn(publish_hr(state.clone()));
+
+        let a = first.next_line().await.unwrap().unwrap();
+        let b = second.next_line().await.unwrap().unwrap();
+        assert!(a.contains("\"type\":\"hr\"") && a.contains("\"bpm\":142"), "{}", a);
+        assert_eq!(a, b, "same snapshot, same line");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_topics() {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+        let dir = std::env::temp_dir().join(format!("hrm_topics_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("hrm.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let (cmd_tx, _cmd_rx) = mpsc::channel(4);
+        let state = Arc::new(Mutex::new(HrmState::default()));
+        let config_path = dir.join("hrm_config.json").to_str().unwrap().to_string();
+        tokio::spawn(run(state.clone(), listener, config_path, cmd_tx));
+
+        let stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"{\"cmd\":\"subscribe\",\"topics\":[\"hr\",\"nope\"]}\n").await.unwrap();
+        assert!(lines.next_line().await.unwrap().unwrap().contains("unknown topic: 'nope' (topics: hr, devices, connection)"));
+        writer.write_all(b"{\"cmd\":\"subscribe\",\"topics\":[\"connection\",\"devices\"]}\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"{"topics":["devices","connection"],"type":"subscribed"}"#);
+        assert!(!state.lock().await.hr_updates.has_subscribers(), "the broadcast can rest");
+
+        {
+            let s = state.lock().await;
+            s.device_events.send(DeviceEvent::ScanFinished(2));
+            s.connection_events.send(ConnectionEvent::Disconnected { address: "AA:BB:CC:DD:EE:FF".into() });
+        }
+        let mut got = vec![lines.next_line().await.unwrap().unwrap(), lines.next_line().await.unwrap().unwrap()];
+        got.sort();
+        assert_eq!(
+            got,
+            [
+                r#"{"address":"AA:BB:CC:DD:EE:FF","event":"disconnected","type":"connection"}"#,
+                r#"{"count":2,"event":"scan_finished","type":"device"}"#,
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_drops_oldest() {
+        let outbox = Outbox::new(1, 3, SlowClientPolicy::DropOldest, Health::default());
+        for n in 0..5 {
+            outbox.send(&msg(n)).unwrap();
+        }
+        assert!(!outbox.overflowed());
+        assert_eq!(outbox.next().await, b"{\"n\":2}\n");
+        assert_eq!(outbox.next().await, b"{\"n\":3}\n");
+        assert_eq!(outbox.next().await, b"{\"n\":4}\n");
+        assert_eq!(outbox.queue.lock().unwrap().dropped, 0, "caught up");
+    }
+
+    #[tokio::test]
+    async fn test_outbox_switches_framing() {
+        let outbox = Outbox::new(1, 4, SlowClientPolicy::DropOldest, Health::default());
+        outbox.send(&serde_json::json!({ "type": "framing", "mode": "protobuf" })).unwrap();
+        outbox.set_framing(Framing::Protobuf);
+        outbox.send(&serde_json::json!({ "type": "error", "message": "hi" })).unwrap();
+        assert_eq!(outbox.next().await, b"{\"mode\":\"protobuf\",\"type\":\"framing\"}\n", "queued before the switch");
+        // SocketMessage { error (4): Error { message (1): "hi" } }
+        assert_eq!(outbox.next().await, [0, 0, 0, 6, 0x22, 4, 0x0a, 2, b'h', b'i']);
+        assert!(outbox.send(&msg(0)).is_err(), "no protobuf type for it");
+    }
+
+    #[tokio::test]
+    async fn test_outbox_disconnects_on_lag() {
+        let outbox = Outbox::new(1, 2, SlowClientPolicy::Disconnect, Health::default());
+        outbox.send(&msg(0)).unwrap();
+        outbox.send(&msg(1)).unwrap();
+        assert!(outbox.send(&msg(2)).is_err());
+        assert!(outbox.overflowed());
+        assert!(outbox.send(&msg(3)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_client_does_not_block_sends() {
+        // A client that never reads: sends keep returning immediately
+        let (client, server) = tokio::net::UnixStream::pair().unwrap();
+        let (_reader, writer) = server.into_split();
+        let outbox = Arc::new(Outbox::new(1, 4, SlowClientPolicy::DropOldest, Health::default()));
+        let task = tokio::spawn(write_loop(outbox.clone(), writer));
+        let big = serde_json::json!({ "pad": "x".repeat(64 * 1024) });
+        for _ in 0..64 {
+            outbox.send(&big).unwrap();
+            tokio::task::yield_now().await;
+        }
+        let q = outbox.queue.lock().unwrap();
+        assert_eq!(q.lines.len(), 4);
+        assert!(q.dropped > 0);
+        drop(q);
+        drop(client);
+        task.abort();
+    }
 }
@@ -0,0 +1,152 @@
+//! Escalation when the BLE adapter itself keeps failing.
+//!
+//! A strap that's out of range fails to connect too, and backing off is
+//! the right answer to that. But a controller that's wedged ("Operation
+//! already in progress", `le-connection-abort-by-local`) fails every
+//! attempt the same way however long the backoff. After
+//! `POWER_CYCLE_AFTER` adapter-level failures in a row the scanner powers
+//! the adapter off and on again, up to `MAX_POWER_CYCLES` times; when that
+//! doesn't help either it raises a health alert asking for bluetoothd to
+//! be restarted, which `stats`, `state` and `--check` show until a strap
+//! connects or the BlueZ session is reopened.
+
+use std::time::Duration;
+
+use bluer::{Adapter, ErrorKind};
+use log::{error, info, warn};
+
+use crate::health::{Counter, Health};
+
+/// Adapter-level failures in a row before the adapter is power-cycled.
+pub const POWER_CYCLE_AFTER: u32 = 3;
+
+/// Power cycles tried before asking for a bluetoothd restart.
+pub const MAX_POWER_CYCLES: u32 = 2;
+
+/// How long the adapter stays off during a power cycle.
+const POWERED_OFF: Duration = Duration::from_secs(2);
+
+/// The alert raised when power cycling hasn't helped.
+pub const RESTART_ALERT: &str = "BLE adapter keeps failing, restart bluetoothd";
+
+/// BlueZ messages for failures of the controller rather than the strap.
+const ADAPTER_FAULTS: [&str; 3] = ["le-connection-abort-by-local", "Software caused connection abort", "Input/output error"];
+
+/// What to do about a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Keep retrying with the usual backoff.
+    Retry,
+    PowerCycle,
+    RestartBluetoothd,
+}
+
+/// Counts adapter-level failures for one BlueZ session.
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    failures: u32,
+    power_cycles: u32,
+    alerted: bool,
+}
+
+impl Watchdog {
+    /// A strap connected: the adapter works. True if it had been escalated.
+    pub fn ok(&mut self) -> bool {
+        let escalated = self.power_cycles > 0 || self.alerted;
+        *self = Self::default();
+        escalated
+    }
+
+    /// An adapter-level failure happened; what to do about it.
+    pub fn failed(&mut self) -> Action {
+        self.failures += 1;
+        if self.failures < POWER_CYCLE_AFTER {
+            return Action::Retry;
+        }
+        self.failures = 0;
+        if self.power_cycles < MAX_POWER_CYCLES {
+            self.power_cycles += 1;
+            Action::PowerCycle
+        } else if !std::mem::replace(&mut self.alerted, true) {
+            Action::RestartBluetoothd
+        } else {
+            Action::Retry
+        }
+    }
+
+    /// Note `e` if it's the adapter's fault, escalating as needed.
+    pub async fn check(&mut self, e: &bluer::Error, adapter: &Adapter, health: &Health) {
+        if !is_adapter_fault(e) {
+            return;
+        }
+        match self.failed() {
+            Action::Retry => {}
+            Action::PowerCycle => {
+                warn!("BLE adapter failing ({}), power cycling {} ({}/{})", e, adapter.name(), self.power_cycles, MAX_POWER_CYCLES);
+                health.count(Counter::PowerCycles);
+                if let Err(e) = power_cycle(adapter).await {
+                    warn!("Power cycling {} failed: {}", adapter.name(), e);
+                }
+            }
+            Action::RestartBluetoothd => {
+                error!("BLE adapter still failing after {} power cycles ({}): restart bluetoothd", MAX_POWER_CYCLES, e);
+                health.raise(RESTART_ALERT);
+            }
+        }
+    }
+
+    /// A strap connected; clear any alert this raised.
+    pub fn recovered(&mut self, health: &Health) {
+        if self.ok() {
+            info!("BLE adapter recovered");
+            health.clear();
+        }
+    }
+}
+
+/// Whether `e` points at the adapter or BlueZ rather than the strap.
+pub fn is_adapter_fault(e: &bluer::Error) -> bool {
+    match e.kind {
+        ErrorKind::NotReady | ErrorKind::InProgress => true,
+        ErrorKind::Failed => ADAPTER_FAULTS.iter().any(|m| e.message.contains(m)),
+        _ => false,
+    }
+}
+
+async fn power_cycle(adapter: &Adapter) -> bluer::Result<()> {
+    adapter.set_powered(false).await?;
+    tokio::time::sleep(POWERED_OFF).await;
+    adapter.set_powered(true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escalation() {
+        let mut watchdog = Watchdog::default();
+        let mut actions = Vec::new();
+        for _ in 0..12 {
+            actions.push(watchdog.failed());
+        }
+        use Action::*;
+        assert_eq!(
+            actions,
+            [Retry, Retry, PowerCycle, Retry, Retry, PowerCycle, Retry, Retry, RestartBluetoothd, Retry, Retry, Retry]
+        );
+        assert!(watchdog.ok());
+        assert!(!watchdog.ok(), "nothing escalated since");
+        assert_eq!(watchdog.failed(), Retry);
+    }
+
+    #[test]
+    fn test_is_adapter_fault() {
+        let err = |kind, message: &str| bluer::Error { kind, message: message.to_string() };
+        assert!(is_adapter_fault(&err(ErrorKind::InProgress, "Operation already in progress")));
+        assert!(is_adapter_fault(&err(ErrorKind::Failed, "le-connection-abort-by-local")));
+        assert!(!is_adapter_fault(&err(ErrorKind::Failed, "Operation failed with ATT error: 0x0e")));
+        assert!(!is_adapter_fault(&err(ErrorKind::ConnectionAttemptFailed, "Page Timeout")));
+        assert!(!is_adapter_fault(&err(ErrorKind::DoesNotExist, "")));
+    }
+}
@@ -66,7 +66,11 @@ class HrmClient:
     def _do_connect(self):
         """Internal: establish socket connection and start reader."""
         sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
-        sock.connect(self.sock_path)
+        # "@name" is a Linux abstract-namespace socket, as in hrm-daemon --socket
+        if self.sock_path.startswith("@"):
+            sock.connect("\0" + self.sock_path[1:])
+        else:
+            sock.connect(self.sock_path)
         with self._lock:
             self._sock = sock
             self._connected = True
@@ -111,6 +115,10 @@ class HrmClient:
         """Forget the saved device so it won't auto-connect."""
         self._send({"cmd": "forget"})
 
+    def set_nickname(self, address, nickname):
+        """Give a saved device a friendly name. Empty nickname clears it."""
+        self._send({"cmd": "nickname", "address": address, "nickname": nickname})
+
     def scan(self):
         """Start scanning for BLE heart rate devices."""
         self._send({"cmd": "scan"})
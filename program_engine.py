@@ -135,6 +135,9 @@ class ProgramState:
         self._task = None
         self._on_change = None
         self._on_update = None
+        # async callback(step_label or None), called whenever the step changes
+        self.on_step = None
+        self._last_step = None
         self._encouragement_milestones = set()
         self._last_encouragement_interval = -3
         self._pending_encouragement = None
@@ -179,6 +182,17 @@ class ProgramState:
             d["encouragement"] = self._pending_encouragement
         return d
 
+    def step_label(self):
+        """Where a running program is, e.g. "Step 4/10: 2:00 @ 9.0 mph, 3%"; None otherwise."""
+        iv = self.current_iv
+        if not self.running or not iv:
+            return None
+        minutes, seconds = divmod(int(iv["duration"]), 60)
+        return (
+            f"Step {self.current_interval + 1}/{len(self.program['intervals'])}: "
+            f"{minutes}:{seconds:02d} @ {iv['speed']:.1f} mph, {iv['incline']:g}%"
+        )
+
     def drain_encouragement(self):
         """Clear pending encouragement after broadcast. Call after to_dict()."""
         self._pending_encouragement = None
@@ -397,6 +411,11 @@ class ProgramState:
         await self._broadcast()
 
     async def _broadcast(self):
+        step = self.step_label()
+        if step != self._last_step:
+            self._last_step = step
+            if self.on_step:
+                await self.on_step(step)
         if self._on_update:
             await self._on_update(self.to_dict())
             self.drain_encouragement()
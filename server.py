@@ -26,6 +26,7 @@ from fastapi.middleware.cors import CORSMiddleware
 from fastapi.responses import FileResponse, JSONResponse
 from fastapi.staticfiles import StaticFiles
 from google import genai
+from ftms_client import send_step
 from hrm_client import HrmClient
 from program_engine import (
     CHAT_SYSTEM_PROMPT,
@@ -65,6 +66,8 @@ async def lifespan(application):
     loop = asyncio.get_event_loop()
     msg_queue = asyncio.Queue(maxsize=500)
     sess = WorkoutSession()
+    # Tell ftms-daemon which interval we're on (optional — ignored if it isn't running)
+    sess.prog.on_step = send_step
 
     # Connect to treadmill_io C binary
     client = TreadmillClient()
@@ -155,7 +158,8 @@ async def lifespan(application):
             if msg_type == "hr":
                 state["heart_rate"] = msg.get("bpm", 0)
                 state["hrm_connected"] = msg.get("connected", False)
-                state["hrm_device"] = msg.get("device", "")
+                state["hrm_stale"] = msg.get("stale", False)
+                state["hrm_device"] = msg.get("nickname") or msg.get("device", "")
             elif msg_type == "scan_result":
                 state["hrm_devices"] = msg.get("devices", [])
             _enqueue(msg)
@@ -167,6 +171,7 @@ async def lifespan(application):
     def on_hrm_disconnect():
         def _apply():
             state["hrm_connected"] = False
+            state["hrm_stale"] = False
             state["heart_rate"] = 0
             state["hrm_device"] = ""
             state["hrm_devices"] = []
@@ -232,6 +237,7 @@ state = {
     "treadmill_connected": False,
     "heart_rate": 0,
     "hrm_connected": False,
+    "hrm_stale": False,
     "hrm_device": "",
     "hrm_devices": [],
     "bus_speed": None,  # from C++ status: motor speed in tenths mph, None if unknown
@@ -685,6 +691,7 @@ async def get_hrm():
     return {
         "heart_rate": state["heart_rate"],
         "connected": state["hrm_connected"],
+        "stale": state["hrm_stale"],
         "device": state["hrm_device"],
         "available_devices": state.get("hrm_devices", []),
     }
@@ -699,6 +706,19 @@ async def select_hrm(req: HrmSelectRequest):
     return {"ok": True}
 
 
+class HrmNicknameRequest(HrmSelectRequest):
+    nickname: str = Field("", max_length=40)
+
+
+@app.post("/api/hrm/nickname")
+async def nickname_hrm(req: HrmNicknameRequest):
+    try:
+        hrm.set_nickname(req.address, req.nickname)
+    except ConnectionError:
+        return JSONResponse({"error": "hrm-daemon not connected"}, status_code=503)
+    return {"ok": True}
+
+
 @app.post("/api/hrm/forget")
 async def forget_hrm():
     try:
@@ -1130,7 +1150,7 @@ def _build_chat_system(smartass=False):
         "incline_pct": state["emu_incline"] / 2.0,
         "mode": "emulate" if state["emulate"] else "proxy" if state["proxy"] else "off",
     }
-    if state["hrm_connected"]:
+    if state["hrm_connected"] and not state["hrm_stale"]:
         treadmill_state["heart_rate_bpm"] = state["heart_rate"]
     if sess.prog.program:
         treadmill_state["program"] = {
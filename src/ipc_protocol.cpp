@@ -29,11 +29,15 @@ std::optional<IpcCommand> parse_command(std::string_view json) {
     std::string_view cmd(cmd_it->value.GetString(), cmd_it->value.GetStringLength());
 
     IpcCommand out{};
+    auto id_it = doc.FindMember("id");
+    if (id_it != doc.MemberEnd() && id_it->value.IsUint())
+        out.id = id_it->value.GetUint();
 
     if (cmd == "speed") {
         out.type = CmdType::Speed;
         auto val_it = doc.FindMember("value");
         if (val_it != doc.MemberEnd()) {
+            out.has_value = val_it->value.IsNumber();
             if (val_it->value.IsDouble())
                 out.float_value = val_it->value.GetDouble();
             else if (val_it->value.IsInt())
@@ -48,6 +52,7 @@ std::optional<IpcCommand> parse_command(std::string_view json) {
         auto val_it = doc.FindMember("value");
         if (val_it != doc.MemberEnd()) {
             // Accept float percent, convert to half-pct units: half_pct = round(pct * 2)
+            out.has_value = val_it->value.IsNumber();
             double pct = 0.0;
             if (val_it->value.IsDouble())
                 pct = val_it->value.GetDouble();
@@ -62,15 +67,19 @@ std::optional<IpcCommand> parse_command(std::string_view json) {
     else if (cmd == "emulate") {
         out.type = CmdType::Emulate;
         auto val_it = doc.FindMember("enabled");
-        if (val_it != doc.MemberEnd() && val_it->value.IsBool())
+        if (val_it != doc.MemberEnd() && val_it->value.IsBool()) {
             out.bool_value = val_it->value.GetBool();
+            out.has_value = true;
+        }
         return out;
     }
     else if (cmd == "proxy") {
         out.type = CmdType::Proxy;
         auto val_it = doc.FindMember("enabled");
-        if (val_it != doc.MemberEnd() && val_it->value.IsBool())
+        if (val_it != doc.MemberEnd() && val_it->value.IsBool()) {
             out.bool_value = val_it->value.GetBool();
+            out.has_value = true;
+        }
         return out;
     }
     else if (cmd == "status") {
@@ -85,10 +94,35 @@ std::optional<IpcCommand> parse_command(std::string_view json) {
         out.type = CmdType::Quit;
         return out;
     }
+    else if (cmd == "caps") {
+        out.type = CmdType::Caps;
+        return out;
+    }
+    else if (cmd == "hello") {
+        // int_value = the client's protocol version, 0 if not given
+        out.type = CmdType::Hello;
+        auto val_it = doc.FindMember("version");
+        if (val_it != doc.MemberEnd() && val_it->value.IsInt())
+            out.int_value = val_it->value.GetInt();
+        return out;
+    }
 
     return std::nullopt;
 }
 
+std::optional<uint32_t> parse_command_id(std::string_view json) {
+    if (json.empty() || json.size() > MAX_IPC_COMMAND_LEN) return std::nullopt;
+
+    std::string buf(json);
+    rapidjson::Document doc;
+    doc.ParseInsitu(buf.data());
+    if (doc.HasParseError() || !doc.IsObject()) return std::nullopt;
+
+    auto id_it = doc.FindMember("id");
+    if (id_it == doc.MemberEnd() || !id_it->value.IsUint()) return std::nullopt;
+    return id_it->value.GetUint();
+}
+
 static std::string rj_to_string(rapidjson::StringBuffer& sb) {
     std::string result(sb.GetString(), sb.GetSize());
     result += '\n';
@@ -129,13 +163,62 @@ std::string build_status_event(const StatusEvent& ev) {
     return rj_to_string(sb);
 }
 
-std::string build_error_event(std::string_view msg) {
+std::string build_error_event(std::string_view msg, std::optional<uint32_t> id) {
     rapidjson::StringBuffer sb;
     rapidjson::Writer<rapidjson::StringBuffer> w(sb);
 
     w.StartObject();
     w.Key("type"); w.String("error");
     w.Key("msg"); w.String(msg.data(), static_cast<unsigned>(msg.size()));
+    if (id) { w.Key("id"); w.Uint(*id); }
+    w.EndObject();
+
+    return rj_to_string(sb);
+}
+
+std::string build_ack_event(uint32_t id) {
+    rapidjson::StringBuffer sb;
+    rapidjson::Writer<rapidjson::StringBuffer> w(sb);
+
+    w.StartObject();
+    w.Key("type"); w.String("ack");
+    w.Key("id"); w.Uint(id);
+    w.EndObject();
+
+    return rj_to_string(sb);
+}
+
+std::string build_hello_event(int version) {
+    rapidjson::StringBuffer sb;
+    rapidjson::Writer<rapidjson::StringBuffer> w(sb);
+
+    w.StartObject();
+    w.Key("type"); w.String("hello");
+    w.Key("version"); w.Int(version);
+    w.EndObject();
+
+    return rj_to_string(sb);
+}
+
+std::string build_caps_event(const CapsEvent& ev) {
+    // Keep in step with parse_command()
+    static constexpr const char* COMMANDS[] = {
+        "speed", "incline", "emulate", "proxy", "status", "heartbeat", "quit", "hello", "caps",
+    };
+
+    rapidjson::StringBuffer sb;
+    rapidjson::Writer<rapidjson::StringBuffer> w(sb);
+
+    w.StartObject();
+    w.Key("type"); w.String("caps");
+    w.Key("commands");
+    w.StartArray();
+    for (const char* c : COMMANDS) w.String(c);
+    w.EndArray();
+    w.Key("max_speed"); w.Int(ev.max_speed);
+    w.Key("min_incline"); w.Int(ev.min_incline);
+    w.Key("max_incline"); w.Int(ev.max_incline);
+    w.Key("decline"); w.Bool(ev.decline);
     w.EndObject();
 
     return rj_to_string(sb);
@@ -23,6 +23,8 @@ enum class CmdType : uint8_t {
     Status,
     Heartbeat,
     Quit,
+    Hello,
+    Caps,
     Unknown
 };
 
@@ -31,16 +33,32 @@ struct IpcCommand {
     double float_value = 0.0;   // speed in mph
     int int_value = 0;          // incline value
     bool bool_value = false;    // emulate/proxy enabled
+    bool has_value = false;     // value/enabled was given
+    std::optional<uint32_t> id; // client's tag; answered with ack or error
 };
 
 static constexpr size_t MAX_IPC_COMMAND_LEN = 1024;
 
+/*
+ * IPC protocol version, reported in reply to {"cmd":"hello"}.
+ * Bump when adding commands or event fields a client may want to
+ * depend on; clients treat a treadmill_io that never answers hello
+ * as version 0.
+ */
+static constexpr int IPC_PROTOCOL_VERSION = 3;  // 2: caps, 3: command ids
+
 /*
  * Parse a JSON command string into a typed IpcCommand.
  * Returns the parsed command, or std::nullopt on failure.
  */
 std::optional<IpcCommand> parse_command(std::string_view json);
 
+/*
+ * The "id" of a line parse_command() rejected, if it's a JSON object
+ * carrying one, so the refusal can still be answered.
+ */
+std::optional<uint32_t> parse_command_id(std::string_view json);
+
 // --- Outbound events (C++ -> Python) ---
 
 struct KvEvent {
@@ -66,4 +84,25 @@ struct StatusEvent {
  */
 std::string build_kv_event(const KvEvent& ev);
 std::string build_status_event(const StatusEvent& ev);
-std::string build_error_event(std::string_view msg);
+std::string build_error_event(std::string_view msg,
+                              std::optional<uint32_t> id = std::nullopt);
+std::string build_hello_event(int version);
+
+/*
+ * Reply to a command carrying an id: {"type":"ack","id":N} once it's
+ * applied. A refused one gets build_error_event(reason, id) instead.
+ */
+std::string build_ack_event(uint32_t id);
+
+struct CapsEvent {
+    int max_speed;      // tenths mph
+    int min_incline;    // half-pct units
+    int max_incline;    // half-pct units
+    bool decline;       // min_incline < 0
+};
+
+/*
+ * Reply to {"cmd":"caps"}: every command parse_command() accepts, plus
+ * the limits set_speed/set_incline clamp to.
+ */
+std::string build_caps_event(const CapsEvent& ev);
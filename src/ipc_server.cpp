@@ -127,9 +127,13 @@ void IpcServer::read_client(int idx) {
         processed = nl_pos + 1;
 
         if (!line.empty() && cmd_cb_) {
+            replying_fd_ = c.fd;
             if (auto cmd = parse_command(line)) {
                 cmd_cb_(*cmd);
+            } else if (auto id = parse_command_id(line)) {
+                reply(build_error_event("unrecognised command", id));
             }
+            replying_fd_ = -1;
         }
     }
 
@@ -225,6 +229,14 @@ void IpcServer::push_to_ring(std::string_view msg) {
     ring_.push(msg);
 }
 
+void IpcServer::reply(std::string_view msg) {
+    if (replying_fd_ < 0) return;
+    // Best effort, like the ring flush: a client too far behind to take a
+    // short line times the command out instead
+    ssize_t wr = write(replying_fd_, msg.data(), msg.size());
+    (void)wr;
+}
+
 void IpcServer::shutdown() {
     for (int i = 0; i < num_clients_; i++) {
         close(clients_.at(i).fd);
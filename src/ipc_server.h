@@ -46,6 +46,10 @@ public:
     // Push a message into the ring
     void push_to_ring(std::string_view msg);
 
+    // Send a message to the client whose command is being dispatched
+    // only (ack/error for a command id). No-op outside the callback.
+    void reply(std::string_view msg);
+
     // Cleanup
     void shutdown();
 
@@ -66,6 +70,7 @@ private:
     int server_fd_ = -1;
     std::array<Client, MAX_CLIENTS> clients_{};
     int num_clients_ = 0;
+    int replying_fd_ = -1;      // sender of the command being dispatched
     CommandCallback cmd_cb_;
     DisconnectCallback disconnect_cb_;
 };
@@ -113,6 +113,41 @@ TEST_CASE("IPC incline command triggers emulate and reports status") {
     ctrl.stop();
 }
 
+TEST_CASE("IPC command ids are acked, or refused without applying") {
+    MockGpioPort port;
+    port.initialise();
+    GpioConfig cfg{27, 22, 17};
+
+    TreadmillController<MockGpioPort> ctrl(port, cfg);
+    ctrl.start();
+    std::this_thread::sleep_for(std::chrono::milliseconds(50));
+
+    int fd = connect_ipc();
+    read_available(fd, 80);
+
+    send_json(fd, "{\"cmd\":\"speed\",\"value\":4.0,\"id\":1}");
+    std::string data = read_available(fd, 150);
+    CHECK(data.find("{\"type\":\"ack\",\"id\":1}") != std::string::npos);
+    CHECK(ctrl.mode().snapshot().speed_tenths == 40);
+
+    // With an id, out-of-range and missing values are refused, not clamped
+    send_json(fd, "{\"cmd\":\"speed\",\"value\":15.0,\"id\":2}");
+    send_json(fd, "{\"cmd\":\"incline\",\"id\":3}");
+    data = read_available(fd, 150);
+    CHECK(data.find("{\"type\":\"error\",\"msg\":\"speed out of range\",\"id\":2}") != std::string::npos);
+    CHECK(data.find("{\"type\":\"error\",\"msg\":\"incline needs a value\",\"id\":3}") != std::string::npos);
+    CHECK(ctrl.mode().snapshot().speed_tenths == 40);
+
+    // Without one, the old clamp still applies
+    send_json(fd, "{\"cmd\":\"speed\",\"value\":15.0}");
+    data = read_available(fd, 150);
+    CHECK(data.find("\"type\":\"ack\"") == std::string::npos);
+    CHECK(ctrl.mode().snapshot().speed_tenths == MAX_SPEED_TENTHS);
+
+    close(fd);
+    ctrl.stop();
+}
+
 TEST_CASE("IPC proxy command disables emulate") {
     MockGpioPort port;
     port.initialise();
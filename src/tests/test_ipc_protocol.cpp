@@ -91,6 +91,44 @@ TEST_CASE("parse quit command") {
     CHECK(cmd->type == CmdType::Quit);
 }
 
+TEST_CASE("parse hello command") {
+    auto cmd = parse_command("{\"cmd\":\"hello\",\"version\":3}");
+    CHECK(cmd.has_value());
+    CHECK(cmd->type == CmdType::Hello);
+    CHECK(cmd->int_value == 3);
+
+    auto bare = parse_command("{\"cmd\":\"hello\"}");
+    CHECK(bare.has_value());
+    CHECK(bare->int_value == 0);
+}
+
+TEST_CASE("parse caps command") {
+    auto cmd = parse_command("{\"cmd\":\"caps\"}");
+    CHECK(cmd.has_value());
+    CHECK(cmd->type == CmdType::Caps);
+}
+
+TEST_CASE("parse command id") {
+    auto cmd = parse_command("{\"cmd\":\"speed\",\"value\":3.5,\"id\":7}");
+    CHECK(cmd.has_value());
+    CHECK(cmd->id == std::optional<uint32_t>(7));
+    CHECK(cmd->has_value);
+
+    auto untagged = parse_command("{\"cmd\":\"incline\",\"value\":2}");
+    CHECK_FALSE(untagged->id.has_value());
+    CHECK(untagged->has_value);
+
+    // A negative or non-integer id isn't one
+    CHECK_FALSE(parse_command("{\"cmd\":\"status\",\"id\":-1}")->id.has_value());
+    CHECK_FALSE(parse_command("{\"cmd\":\"status\",\"id\":\"7\"}")->id.has_value());
+}
+
+TEST_CASE("parse command id of a rejected line") {
+    CHECK(parse_command_id("{\"cmd\":\"foobar\",\"id\":9}") == std::optional<uint32_t>(9));
+    CHECK_FALSE(parse_command_id("{\"cmd\":\"foobar\"}").has_value());
+    CHECK_FALSE(parse_command_id("not json").has_value());
+}
+
 TEST_CASE("parse unknown command") {
     CHECK_FALSE(parse_command("{\"cmd\":\"foobar\"}").has_value());
 }
@@ -116,6 +154,7 @@ TEST_CASE("parse speed without value field") {
     CHECK(cmd.has_value());
     CHECK(cmd->type == CmdType::Speed);
     CHECK(cmd->float_value == doctest::Approx(0.0));
+    CHECK_FALSE(cmd->has_value);
 }
 
 // ── Event building tests ────────────────────────────────────────────
@@ -157,5 +196,37 @@ TEST_CASE("build error event") {
     CHECK(!result.empty());
     CHECK(result.find("\"type\":\"error\"") != std::string::npos);
     CHECK(result.find("\"msg\":\"too many clients\"") != std::string::npos);
+    CHECK(result.find("\"id\"") == std::string::npos);
+    CHECK(result.back() == '\n');
+}
+
+TEST_CASE("build error event for a command id") {
+    auto result = build_error_event("speed out of range", 12);
+
+    CHECK(result == "{\"type\":\"error\",\"msg\":\"speed out of range\",\"id\":12}\n");
+}
+
+TEST_CASE("build ack event") {
+    CHECK(build_ack_event(12) == "{\"type\":\"ack\",\"id\":12}\n");
+}
+
+TEST_CASE("build hello event") {
+    auto result = build_hello_event(IPC_PROTOCOL_VERSION);
+
+    CHECK(result.find("\"type\":\"hello\"") != std::string::npos);
+    CHECK(result.find("\"version\":3") != std::string::npos);
+    CHECK(result.back() == '\n');
+}
+
+TEST_CASE("build caps event") {
+    auto result = build_caps_event({120, 0, 198, false});
+
+    CHECK(result.find("\"type\":\"caps\"") != std::string::npos);
+    CHECK(result.find("\"commands\":[\"speed\",\"incline\",") != std::string::npos);
+    CHECK(result.find("\"caps\"]") != std::string::npos);
+    CHECK(result.find("\"max_speed\":120") != std::string::npos);
+    CHECK(result.find("\"min_incline\":0") != std::string::npos);
+    CHECK(result.find("\"max_incline\":198") != std::string::npos);
+    CHECK(result.find("\"decline\":false") != std::string::npos);
     CHECK(result.back() == '\n');
 }
@@ -431,3 +431,34 @@ TEST_CASE("server handles empty lines") {
     close(fd);
     ipc.shutdown();
 }
+
+TEST_CASE("replies go to the sending client only") {
+    RingBuffer<> ring;
+    IpcServer ipc(ring);
+    CHECK(ipc.create());
+
+    ipc.on_command([&](const IpcCommand& cmd) {
+        if (cmd.id) ipc.reply(build_ack_event(*cmd.id));
+    });
+
+    int sender = connect_client();
+    int other = connect_client();
+    poll_for(ipc, 30);
+
+    send_cmd(sender, "{\"cmd\":\"speed\",\"value\":2.0,\"id\":5}");
+    send_cmd(sender, "{\"cmd\":\"foobar\",\"id\":6}");
+    poll_for(ipc, 50);
+
+    auto data = read_all(sender, 50);
+    CHECK(data.find("{\"type\":\"ack\",\"id\":5}") != std::string::npos);
+    CHECK(data.find("{\"type\":\"error\",\"msg\":\"unrecognised command\",\"id\":6}") != std::string::npos);
+    CHECK(read_all(other, 50).empty());
+
+    // Outside a dispatch there's nobody to reply to
+    ipc.reply(build_ack_event(7));
+    CHECK(read_all(sender, 50).empty());
+
+    close(sender);
+    close(other);
+    ipc.shutdown();
+}
@@ -214,10 +214,41 @@ private:
         ring_.push(build_status_event(ev));
     }
 
+    // Why a command carrying an id is refused, or nullptr. Commands
+    // without one keep the old lenient handling (missing values are 0,
+    // out-of-range ones are clamped).
+    static const char* refusal(const IpcCommand& cmd) {
+        switch (cmd.type) {
+            case CmdType::Speed:
+                if (!cmd.has_value) return "speed needs a value";
+                if (cmd.float_value < 0 || cmd.float_value * 10 > MAX_SPEED_TENTHS + 0.5)
+                    return "speed out of range";
+                return nullptr;
+            case CmdType::Incline:
+                if (!cmd.has_value) return "incline needs a value";
+                if (cmd.int_value < 0 || cmd.int_value > MAX_INCLINE) return "incline out of range";
+                return nullptr;
+            case CmdType::Emulate:
+            case CmdType::Proxy:
+                return cmd.has_value ? nullptr : "enabled must be true or false";
+            default:
+                return nullptr;
+        }
+    }
+
     void handle_command(const IpcCommand& cmd) {
         // Every command is an implicit heartbeat
         clock_gettime(CLOCK_MONOTONIC, &last_cmd_time_);
 
+        if (cmd.id) {
+            if (const char* reason = refusal(cmd)) {
+                ipc_.reply(build_error_event(reason, cmd.id));
+                return;
+            }
+            // Applied below; the ack goes ahead of the status it causes
+            ipc_.reply(build_ack_event(*cmd.id));
+        }
+
         switch (cmd.type) {
             case CmdType::Proxy:
                 mode_.request_proxy(cmd.bool_value);
@@ -244,6 +275,16 @@ private:
             case CmdType::Quit:
                 running_.store(false, std::memory_order_relaxed);
                 break;
+            case CmdType::Hello:
+                if (cmd.int_value != IPC_PROTOCOL_VERSION) {
+                    std::fprintf(stderr, "[ipc] client speaks protocol v%d, we speak v%d\n",
+                                 cmd.int_value, IPC_PROTOCOL_VERSION);
+                }
+                ring_.push(build_hello_event(IPC_PROTOCOL_VERSION));
+                break;
+            case CmdType::Caps:
+                ring_.push(build_caps_event({MAX_SPEED_TENTHS, 0, MAX_INCLINE, false}));
+                break;
             case CmdType::Unknown:
                 break;
         }
@@ -167,6 +167,23 @@ def test_send_forget(mock_daemon):
     assert any(c.get("cmd") == "forget" for c in cmds)
 
 
+def test_send_nickname(mock_daemon):
+    """Client sends nickname command."""
+    client = HrmClient(sock_path=mock_daemon.sock_path)
+    client.on_message = lambda msg: None
+    client.connect()
+    time.sleep(0.1)
+
+    client.set_nickname("11:22:33:44:55:66", "Dad's Polar")
+    time.sleep(0.2)
+    client.close()
+
+    cmds = mock_daemon.received_commands
+    assert any(
+        c.get("cmd") == "nickname" and c.get("nickname") == "Dad's Polar" for c in cmds
+    )
+
+
 def test_send_scan(mock_daemon):
     """Client sends scan command."""
     client = HrmClient(sock_path=mock_daemon.sock_path)
@@ -229,3 +246,18 @@ def test_reconnect(mock_daemon):
     bpms = [m.get("bpm") for m in messages if m.get("type") == "hr"]
     assert 72 in bpms, "should have seen original 72 bpm"
     assert 150 in bpms, "should have seen reconnected 150 bpm"
+
+
+def test_abstract_socket():
+    """"@name" connects to an abstract-namespace socket."""
+    name = f"hrm_test_{os.getpid()}"
+    server = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    server.bind("\0" + name)
+    server.listen(1)
+    client = HrmClient(sock_path="@" + name)
+    client.connect()
+    conn, _ = server.accept()
+    assert client.connected
+    client.close()
+    conn.close()
+    server.close()
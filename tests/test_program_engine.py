@@ -398,6 +398,42 @@ class TestPrev:
         assert loaded_prog.interval_elapsed == 0
 
 
+class TestStepReporting:
+    @pytest.mark.asyncio
+    async def test_step_sent_on_each_change(self, loaded_prog):
+        on_step = AsyncMock()
+        loaded_prog.on_step = on_step
+        loaded_prog.running = True
+        loaded_prog._on_change = AsyncMock()
+        await loaded_prog.skip()
+        await loaded_prog.extend_current(30)
+        await loaded_prog.prev()
+        await loaded_prog.stop()
+        assert [c.args[0] for c in on_step.call_args_list] == [
+            "Step 2/3: 2:00 @ 6.0 mph, 3%",
+            "Step 2/3: 2:30 @ 6.0 mph, 3%",
+            "Step 1/3: 1:00 @ 2.0 mph, 0%",
+            None,
+        ]
+
+    @pytest.mark.asyncio
+    async def test_step_not_resent_while_unchanged(self, loaded_prog):
+        on_step = AsyncMock()
+        loaded_prog.on_step = on_step
+        loaded_prog.running = True
+        await loaded_prog._broadcast()
+        await loaded_prog.toggle_pause()
+        await loaded_prog._broadcast()
+        on_step.assert_called_once_with("Step 1/3: 1:00 @ 2.0 mph, 0%")
+
+    def test_step_label_half_percent_incline(self, loaded_prog):
+        loaded_prog.program["intervals"][0]["incline"] = 2.5
+        loaded_prog.running = True
+        assert loaded_prog.step_label() == "Step 1/3: 1:00 @ 2.0 mph, 2.5%"
+        loaded_prog.running = False
+        assert loaded_prog.step_label() is None
+
+
 class TestSkipWhilePaused:
     @pytest.mark.asyncio
     async def test_skip_while_paused_preserves_timing(self):
@@ -30,14 +30,16 @@ export default function HeartRate(): React.ReactElement | null {
   if (!status.hrmConnected) return null;
 
   const bpm = status.heartRate;
-  const color = hrColor(bpm);
+  // Strap connected but silent: grey out and stop pulsing rather than show a frozen BPM
+  const stale = status.hrmStale;
+  const color = stale ? 'var(--text3)' : hrColor(bpm);
   // Scale animation duration inversely with BPM for a realistic pulse
   const pulseDuration = bpm > 0 ? Math.max(0.4, 60 / bpm) : 1;
 
   return (
     <div
       role="status"
-      aria-label={`Heart rate: ${bpm} beats per minute`}
+      aria-label={stale ? 'Heart rate: no recent reading' : `Heart rate: ${bpm} beats per minute`}
       style={{ display: 'flex', alignItems: 'baseline', gap: 4 }}
     >
       <span
@@ -46,7 +48,7 @@ export default function HeartRate(): React.ReactElement | null {
           color,
           fontSize: 14,
           lineHeight: 1,
-          animation: `hr-pulse ${pulseDuration}s ease-in-out infinite`,
+          animation: stale ? 'none' : `hr-pulse ${pulseDuration}s ease-in-out infinite`,
           willChange: 'transform',
         }}
         aria-hidden="true"
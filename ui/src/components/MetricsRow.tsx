@@ -19,15 +19,15 @@ export default function MetricsRow(): React.ReactElement {
     >
       <div style={{ display: 'flex', alignItems: 'baseline', gap: 4 }}>
         <span className="metric-value" style={{ fontSize: 15, fontWeight: 600, fontVariantNumeric: 'tabular-nums', color: 'var(--teal)' }}>{sess.pace}</span>
-        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>min/mi</span>
+        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>{sess.paceUnit}</span>
       </div>
       <div style={{ display: 'flex', alignItems: 'baseline', gap: 4 }}>
         <span className="metric-value" style={{ fontSize: 15, fontWeight: 600, fontVariantNumeric: 'tabular-nums' }}>{sess.distDisplay}</span>
-        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>miles</span>
+        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>{sess.distUnit}</span>
       </div>
       <div style={{ display: 'flex', alignItems: 'baseline', gap: 4 }}>
         <span className="metric-value" style={{ fontSize: 15, fontWeight: 600, fontVariantNumeric: 'tabular-nums', color: 'var(--orange)' }}>{sess.vertDisplay}</span>
-        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>vert ft</span>
+        <span className="metric-label" style={{ fontSize: 10, color: 'var(--text3)' }}>vert {sess.vertUnit}</span>
       </div>
       <HeartRate />
     </motion.div>
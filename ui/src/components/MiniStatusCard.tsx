@@ -20,7 +20,7 @@ export default function MiniStatusCard(): React.ReactElement | null {
             {pgm.running && pgm.currentIv ? pgm.currentIv.name : 'Running'}
           </div>
           <div style={{ fontSize: 11, color: 'var(--text3)', marginTop: 2 }}>
-            {sess.speedMph.toFixed(1)} mph &middot; {sess.pace} min/mi
+            {sess.speedDisplay} {sess.speedUnit} &middot; {sess.pace} {sess.paceUnit}
           </div>
         </div>
         <div style={{
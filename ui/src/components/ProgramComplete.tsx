@@ -35,8 +35,8 @@ export default function ProgramComplete({ onVoice }: ProgramCompleteProps): Reac
         <div style={{ fontSize: 20, fontWeight: 700, marginBottom: 6 }}>Workout Complete</div>
         <div style={{ display: 'flex', gap: 16, justifyContent: 'center', fontSize: 13, color: 'var(--text3)' }}>
           <span>{fmtDur(pgm.totalElapsed)}</span>
-          <span>{sess.distDisplay} mi</span>
-          <span>{sess.vertDisplay} ft</span>
+          <span>{sess.distDisplay} {sess.distUnit}</span>
+          <span>{sess.vertDisplay} {sess.vertUnit}</span>
         </div>
       </div>
 
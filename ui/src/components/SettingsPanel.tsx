@@ -4,6 +4,7 @@ import { useTreadmillState, useTreadmillActions, useToast } from '../state/Tread
 import * as api from '../state/api';
 import { haptic } from '../utils/haptics';
 import { hrColor } from '../utils/hrColor';
+import { useUnits, setUnits } from '../utils/units';
 
 interface SettingsPanelProps {
   open: boolean;
@@ -26,6 +27,7 @@ export default function SettingsPanel({ open, onClose }: SettingsPanelProps): Re
   const [smartass, setSmartass] = useState(() => {
     try { return localStorage.getItem('smartass_mode') === 'true'; } catch { return false; }
   });
+  const units = useUnits();
   const [hrmScanning, setHrmScanning] = useState(false);
   const fileInputRef = useRef<HTMLInputElement>(null);
   const debugTaps = useRef<number[]>([]);
@@ -122,6 +124,20 @@ export default function SettingsPanel({ open, onClose }: SettingsPanelProps): Re
           <span style={{ fontSize: 13, color: 'var(--text3)' }}>&#8250;</span>
         </div>
 
+        {/* Units toggle (display only; the treadmill works in mph) */}
+        <div
+          onClick={() => {
+            setUnits(units === 'metric' ? 'imperial' : 'metric');
+            haptic(25);
+          }}
+          style={rowStyle}
+        >
+          <span style={{ fontSize: 15, color: 'var(--text)' }}>Units</span>
+          <span style={{ fontSize: 13, color: 'var(--text3)' }}>
+            {units === 'metric' ? 'km/h · km' : 'mph · mi'}
+          </span>
+        </div>
+
         {/* Smart-ass mode toggle */}
         <div
           onClick={() => {
@@ -185,7 +201,7 @@ export default function SettingsPanel({ open, onClose }: SettingsPanelProps): Re
                   haptic(25);
                   try {
                     await api.selectHrmDevice(d.address);
-                    showToast(`Connecting to ${d.name || d.address}...`);
+                    showToast(`Connecting to ${d.nickname || d.name || d.address}...`);
                   } catch {
                     showToast('Failed to select device');
                   }
@@ -210,7 +226,7 @@ export default function SettingsPanel({ open, onClose }: SettingsPanelProps): Re
                   }}
                 >
                   <span style={{ fontSize: 14, color: 'var(--text)' }}>
-                    {d.name || d.address}
+                    {d.nickname || d.name || d.address}
                   </span>
                   <span style={{ fontSize: 12, color: 'var(--text3)', fontVariantNumeric: 'tabular-nums' }}>
                     {d.rssi} dBm
@@ -1,6 +1,7 @@
 import React, { useRef, useCallback, useState, useEffect } from 'react';
 import { useTreadmillState, useTreadmillActions } from '../state/TreadmillContext';
 import { haptic } from '../utils/haptics';
+import { useUnits, speedIn, speedUnit } from '../utils/units';
 
 // Chevron SVGs
 function ChevronUp({ sw = 2 }: { sw?: number }) {
@@ -66,6 +67,7 @@ function usePulse(value: number): PulseDir {
 
 export default function SpeedInclineControls(): React.ReactElement {
   const { status } = useTreadmillState();
+  const units = useUnits();
   const actions = useTreadmillActions();
   const repeatTimer = useRef<ReturnType<typeof setTimeout> | null>(null);
   const repeatCount = useRef(0);
@@ -150,9 +152,9 @@ export default function SpeedInclineControls(): React.ReactElement {
             fontSize: 26, fontWeight: 600, fontVariantNumeric: 'tabular-nums',
             lineHeight: 1.1, color: 'var(--green)',
           }}>
-            {(status.emuSpeed / 10).toFixed(1)}
+            {speedIn(status.emuSpeed / 10, units).toFixed(1)}
           </div>
-          <div className="ctrl-label" style={{ fontSize: 10, color: 'var(--text3)', marginTop: 1 }}>mph</div>
+          <div className="ctrl-label" style={{ fontSize: 10, color: 'var(--text3)', marginTop: 1 }}>{speedUnit(units)}</div>
         </div>
         <div style={{ display: 'flex', flexDirection: 'column', gap: 3 }}>
           <button key={`su10-${status.emuSpeed}`} className={`ctrl-btn ${pulseBtn(speedPulse, 'up', 'su10')}`} style={{ ...btn, color: 'var(--green)' }} {...ph('speed', 10, 'su10')}>
@@ -37,6 +37,7 @@ const initialStatus: TreadmillStatus = {
   treadmillConnected: false,
   heartRate: 0,
   hrmConnected: false,
+  hrmStale: false,
 };
 
 const initialSession: SessionState = {
@@ -194,6 +195,7 @@ function reducer(state: AppState, action: Action): AppState {
           ...state.status,
           heartRate: m.bpm,
           hrmConnected: m.connected,
+          hrmStale: m.stale ?? false,
         },
       };
     }
@@ -160,7 +160,7 @@ export async function getConfig(): Promise<AppConfig> {
 
 // --- HRM ---
 
-export async function getHrm(): Promise<{ heart_rate: number; connected: boolean; device: string; available_devices: Array<{ address: string; name: string; rssi: number }> }> {
+export async function getHrm(): Promise<{ heart_rate: number; connected: boolean; device: string; available_devices: Array<{ address: string; name: string; rssi: number; nickname?: string }> }> {
   return get('/api/hrm');
 }
 
@@ -168,6 +168,10 @@ export async function selectHrmDevice(address: string): Promise<{ ok: boolean }>
   return post('/api/hrm/select', { address });
 }
 
+export async function setHrmNickname(address: string, nickname: string): Promise<{ ok: boolean }> {
+  return post('/api/hrm/nickname', { address, nickname });
+}
+
 export async function forgetHrmDevice(): Promise<{ ok: boolean }> {
   return post('/api/hrm/forget', {});
 }
@@ -69,7 +69,10 @@ export interface HRMessage {
   type: 'hr';
   bpm: number;
   connected: boolean;
+  stale?: boolean;
+  last_sample_age_ms?: number | null;
   device: string;
+  nickname?: string;
   address: string;
 }
 
@@ -93,6 +96,7 @@ export interface TreadmillStatus {
   treadmillConnected: boolean;
   heartRate: number;
   hrmConnected: boolean;
+  hrmStale: boolean;      // connected but strap stopped notifying
 }
 
 export interface SessionState {
@@ -121,7 +125,7 @@ export interface AppState {
   session: SessionState;
   program: ProgramState;
   kvLog: KVEntry[];
-  hrmDevices: Array<{ address: string; name: string; rssi: number }>;
+  hrmDevices: Array<{ address: string; name: string; rssi: number; nickname?: string }>;
   _dirtySpeed: number;
   _dirtyIncline: number;
 }
@@ -1,9 +1,13 @@
 import { useMemo, useState, useEffect, useRef } from 'react';
 import { useTreadmillState } from './TreadmillContext';
 import { fmtDur, paceDisplay } from '../utils/formatters';
+import {
+  useUnits, speedIn, distIn, vertIn, speedUnit, distUnit, paceUnit, vertUnit,
+} from '../utils/units';
 
 export function useSession() {
   const { session, status, program } = useTreadmillState();
+  const units = useUnits();
 
   // --- Local clock interpolation ---
   // Server sends elapsed at 1Hz. We interpolate locally at ~10Hz
@@ -61,12 +65,17 @@ export function useSession() {
       elapsed: session.elapsed,
       elapsedDisplay: fmtDur(localElapsed),
       distance: session.distance,
-      distDisplay: session.distance.toFixed(2),
+      distDisplay: distIn(session.distance, units).toFixed(2),
+      distUnit: distUnit(units),
       vertFeet: session.vertFeet,
-      vertDisplay: Math.round(session.vertFeet).toLocaleString(),
-      pace: paceDisplay(speedMph),
+      vertDisplay: Math.round(vertIn(session.vertFeet, units)).toLocaleString(),
+      vertUnit: vertUnit(units),
+      pace: paceDisplay(speedIn(speedMph, units)),
+      paceUnit: paceUnit(units),
       speedMph,
+      speedDisplay: speedIn(speedMph, units).toFixed(1),
+      speedUnit: speedUnit(units),
       endReason: session.endReason,
     };
-  }, [session, status.emulate, status.emuSpeed, status.speed, localElapsed]);
+  }, [session, status.emulate, status.emuSpeed, status.speed, localElapsed, units]);
 }
@@ -10,11 +10,12 @@ export function fmtDur(secs: number | null | undefined): string {
   return `${m}:${String(s).padStart(2, '0')}`;
 }
 
-export function paceDisplay(speedMph: number): string {
-  if (speedMph <= 0) return '--:--';
-  const minPerMile = 60 / speedMph;
-  let m = Math.floor(minPerMile);
-  let s = Math.round((minPerMile - m) * 60);
+/** Pace for a speed in mph (min/mi) or km/h (min/km). */
+export function paceDisplay(speed: number): string {
+  if (speed <= 0) return '--:--';
+  const minPerUnit = 60 / speed;
+  let m = Math.floor(minPerUnit);
+  let s = Math.round((minPerUnit - m) * 60);
   if (s === 60) { m += 1; s = 0; }
   return `${m}:${String(s).padStart(2, '0')}`;
 }
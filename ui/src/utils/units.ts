@@ -0,0 +1,39 @@
+import { useEffect, useState } from 'react';
+
+// Display units. The treadmill, server, and programs all work in mph,
+// miles, and feet; metric is applied only when rendering.
+export type Units = 'imperial' | 'metric';
+
+const STORAGE_KEY = 'units';
+const CHANGE_EVENT = 'units-change';
+const KM_PER_MILE = 1.609344;
+const M_PER_FOOT = 0.3048;
+
+export function getUnits(): Units {
+  try { return localStorage.getItem(STORAGE_KEY) === 'metric' ? 'metric' : 'imperial'; } catch { return 'imperial'; }
+}
+
+export function setUnits(units: Units): void {
+  try { localStorage.setItem(STORAGE_KEY, units); } catch {}
+  window.dispatchEvent(new Event(CHANGE_EVENT));
+}
+
+/** Current display units; re-renders when changed in Settings. */
+export function useUnits(): Units {
+  const [units, setState] = useState(getUnits);
+  useEffect(() => {
+    const onChange = () => setState(getUnits());
+    window.addEventListener(CHANGE_EVENT, onChange);
+    return () => window.removeEventListener(CHANGE_EVENT, onChange);
+  }, []);
+  return units;
+}
+
+export const speedIn = (mph: number, u: Units): number => (u === 'metric' ? mph * KM_PER_MILE : mph);
+export const distIn = (miles: number, u: Units): number => (u === 'metric' ? miles * KM_PER_MILE : miles);
+export const vertIn = (feet: number, u: Units): number => (u === 'metric' ? feet * M_PER_FOOT : feet);
+
+export const speedUnit = (u: Units): string => (u === 'metric' ? 'km/h' : 'mph');
+export const distUnit = (u: Units): string => (u === 'metric' ? 'km' : 'mi');
+export const paceUnit = (u: Units): string => (u === 'metric' ? 'min/km' : 'min/mi');
+export const vertUnit = (u: Units): string => (u === 'metric' ? 'm' : 'ft');